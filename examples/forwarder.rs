@@ -11,13 +11,20 @@ const BATCH_SIZE: usize = 32;
 pub fn main() {
     simple_logger::init().unwrap();
 
-    let mut args = env::args();
-    args.next();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // --bind-vfio takes the devices over from their kernel drivers
+    let bind_vfio = args.iter().any(|arg| arg == "--bind-vfio");
+    args.retain(|arg| arg != "--bind-vfio");
+
+    let mut args = args.into_iter();
 
     let pci_addr_1 = match args.next() {
         Some(arg) => arg,
         None => {
-            eprintln!("Usage: cargo run --example forwarder <pci bus id1> <pci bus id2>");
+            eprintln!(
+                "Usage: cargo run --example forwarder [--bind-vfio] <pci bus id1> <pci bus id2>"
+            );
             process::exit(1);
         }
     };
@@ -25,11 +32,23 @@ pub fn main() {
     let pci_addr_2 = match args.next() {
         Some(arg) => arg,
         None => {
-            eprintln!("Usage: cargo run --example forwarder <pci bus id1> <pci bus id2>");
+            eprintln!(
+                "Usage: cargo run --example forwarder [--bind-vfio] <pci bus id1> <pci bus id2>"
+            );
             process::exit(1);
         }
     };
 
+    // the bindings have to outlive the devices, dropping them hands the devices back
+    let _bindings = if bind_vfio {
+        vec![
+            pci::bind_to_vfio(&pci_addr_1).unwrap(),
+            pci::bind_to_vfio(&pci_addr_2).unwrap(),
+        ]
+    } else {
+        Vec::new()
+    };
+
     let mut dev1 = ixy_init(&pci_addr_1, 1, 1).unwrap();
     let mut dev2 = ixy_init(&pci_addr_2, 1, 1).unwrap();
 
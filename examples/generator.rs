@@ -3,6 +3,7 @@ use std::env;
 use std::process;
 use std::time::Instant;
 
+use ixy::checksum::ipv4_header_checksum;
 use ixy::memory::{alloc_pkt_batch, Mempool, Packet};
 use ixy::*;
 
@@ -16,17 +17,29 @@ const PACKET_SIZE: usize = 60;
 pub fn main() {
     simple_logger::init().unwrap();
 
-    let mut args = env::args();
-    args.next();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // --bind-vfio takes the device over from its kernel driver
+    let bind_vfio = args.iter().any(|arg| arg == "--bind-vfio");
+    args.retain(|arg| arg != "--bind-vfio");
+
+    let mut args = args.into_iter();
 
     let pci_addr = match args.next() {
         Some(arg) => arg,
         None => {
-            eprintln!("Usage: cargo run --example generator <pci bus id>");
+            eprintln!("Usage: cargo run --example generator [--bind-vfio] <pci bus id>");
             process::exit(1);
         }
     };
 
+    // the binding has to outlive the device, dropping it hands the device back
+    let _binding = if bind_vfio {
+        Some(pci::bind_to_vfio(&pci_addr).unwrap())
+    } else {
+        None
+    };
+
     let mut dev = ixy_init(&pci_addr, 1, 1).unwrap();
 
     #[rustfmt::skip]
@@ -61,7 +74,7 @@ pub fn main() {
             for (i, data) in pkt_data.iter().enumerate() {
                 p[i] = *data;
             }
-            let checksum = calc_ip_checksum(p, 14, 20);
+            let checksum = ipv4_header_checksum(&p[14..34]);
             p[24] = (checksum >> 8) as u8;
             p[25] = (checksum & 0xff) as u8;
         }
@@ -87,7 +100,7 @@ pub fn main() {
         // update sequence number of all packets (and checksum if necessary)
         for p in buffer.iter_mut() {
             p[PACKET_SIZE - 4] = seq_num;
-            seq_num = (seq_num % std::u8::MAX) + 1;
+            seq_num = (seq_num % u8::MAX) + 1;
         }
 
         dev.tx_batch(0, &mut buffer);
@@ -109,15 +122,3 @@ pub fn main() {
         counter += 1;
     }
 }
-
-// calculate IP/TCP/UDP checksum
-fn calc_ip_checksum(packet: &mut Packet, offset: usize, len: usize) -> u16 {
-    let mut checksum = 0;
-    for i in 0..len / 2 {
-        checksum += ((u32::from(packet[i + offset])) << 8) + u32::from(packet[i + offset + 1]);
-        if checksum > 0xffff {
-            checksum = (checksum & 0xfff) + 1;
-        }
-    }
-    !(checksum as u16)
-}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+use ixy::memory::{alloc_pkt_batch, Mempool, Packet};
+use ixy::*;
+
+// number of packets sent simultaneously by default
+const DEFAULT_BATCH_SIZE: usize = 32;
+// number of seconds to send by default
+const DEFAULT_DURATION_SECS: u64 = 10;
+// number of packets in our mempool
+const NUM_PACKETS: usize = 4096;
+// minimum sized frames, 64 bytes on the wire with the crc
+const PACKET_SIZE: usize = 60;
+
+pub fn main() {
+    simple_logger::init().unwrap();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // --bind-vfio takes the device over from its kernel driver
+    let bind_vfio = args.iter().any(|arg| arg == "--bind-vfio");
+    args.retain(|arg| arg != "--bind-vfio");
+
+    let mut args = args.into_iter();
+
+    let pci_addr = match args.next() {
+        Some(arg) => arg,
+        None => {
+            eprintln!(
+                "Usage: cargo run --release --example txbench [--bind-vfio] <pci bus id> \
+                 [batch size] [seconds]"
+            );
+            process::exit(1);
+        }
+    };
+
+    let batch_size = args
+        .next()
+        .map(|arg| arg.parse().expect("batch size must be a number"))
+        .unwrap_or(DEFAULT_BATCH_SIZE);
+    let duration = Duration::from_secs(
+        args.next()
+            .map(|arg| arg.parse().expect("duration must be a number of seconds"))
+            .unwrap_or(DEFAULT_DURATION_SECS),
+    );
+
+    // the binding has to outlive the device, dropping it hands the device back
+    let _binding = if bind_vfio {
+        Some(pci::bind_to_vfio(&pci_addr).unwrap())
+    } else {
+        None
+    };
+
+    let mut dev = ixy_init(&pci_addr, 1, 1).unwrap();
+
+    #[rustfmt::skip]
+    let pkt_data = [
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06,         // dst MAC
+        0x11, 0x12, 0x13, 0x14, 0x15, 0x16,         // src MAC
+        0x08, 0x00,                                 // ether type: IPv4
+    ];
+
+    let pool = Mempool::allocate(NUM_PACKETS, 0).unwrap();
+
+    // pre-fill all packet buffer in the pool with data and return them to the packet pool
+    {
+        let mut buffer: VecDeque<Packet> = VecDeque::with_capacity(NUM_PACKETS);
+
+        alloc_pkt_batch(&pool, &mut buffer, NUM_PACKETS, PACKET_SIZE);
+
+        for p in buffer.iter_mut() {
+            p[..pkt_data.len()].copy_from_slice(&pkt_data);
+        }
+    }
+
+    let mut dev_stats = DeviceStats::default();
+    let mut dev_stats_old = DeviceStats::default();
+
+    dev.reset_stats();
+    dev.read_stats(&mut dev_stats_old);
+
+    println!(
+        "sending {} byte packets in batches of {} for {:?}",
+        PACKET_SIZE + 4,
+        batch_size,
+        duration
+    );
+
+    let mut buffer: VecDeque<Packet> = VecDeque::with_capacity(batch_size);
+    let mut calls: u64 = 0;
+    let mut sent: u64 = 0;
+    let mut sent_old: u64 = 0;
+    let start = Instant::now();
+    let mut time = start;
+
+    loop {
+        // packets the device didn't take are sent again with the next batch
+        let missing = batch_size - buffer.len();
+        if missing > 0 {
+            alloc_pkt_batch(&pool, &mut buffer, missing, PACKET_SIZE);
+        }
+
+        sent += dev.tx_batch(0, &mut buffer) as u64;
+        calls += 1;
+
+        // don't poll the time unnecessarily
+        if calls & 0xfff == 0 && time.elapsed() >= Duration::from_secs(1) {
+            let secs = time.elapsed().as_secs_f64();
+
+            dev.read_stats(&mut dev_stats);
+            println!(
+                "driver: {:.2} Mpps, device: {:.2} Mpps",
+                (sent - sent_old) as f64 / secs / 1_000_000.0,
+                (dev_stats.tx_pkts - dev_stats_old.tx_pkts) as f64 / secs / 1_000_000.0
+            );
+
+            dev_stats_old = dev_stats;
+            sent_old = sent;
+            time = Instant::now();
+
+            if start.elapsed() >= duration {
+                break;
+            }
+        }
+    }
+
+    let secs = start.elapsed().as_secs_f64();
+
+    println!(
+        "total: {:.2} Mpps, {:.1} packets per tx_batch call",
+        sent as f64 / secs / 1_000_000.0,
+        sent as f64 / calls as f64
+    );
+}
@@ -0,0 +1,1645 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+
+use crate::ixgbe::{
+    allocate_mempool, NUM_RX_QUEUE_ENTRIES, NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE,
+    TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{MappedBar, PciDevice, PcieLink};
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-af_xdp";
+
+/// Prefix of the names passed to `ixy_init` that select this backend, e.g. `af_xdp:eth0`.
+pub(crate) const AF_XDP_PREFIX: &str = "af_xdp:";
+
+// chunks of the umem are between 2 KiB and a page, see xdp_umem_reg() in net/xdp/xdp_umem.c
+const MIN_CHUNK_SIZE: usize = 2048;
+const MAX_CHUNK_SIZE: usize = 4096;
+
+// in unaligned mode the upper 16 bits of a descriptor's address are an offset into the chunk
+const XSK_UNALIGNED_BUF_OFFSET_SHIFT: u64 = 48;
+const XSK_UNALIGNED_BUF_ADDR_MASK: u64 = (1 << XSK_UNALIGNED_BUF_OFFSET_SHIFT) - 1;
+
+// the kernel releases the queue of a closed socket asynchronously
+const BIND_TIMEOUT: Duration = Duration::from_secs(1);
+
+// bpf syscall commands and types, see include/uapi/linux/bpf.h
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_MAP_UPDATE_ELEM: libc::c_int = 2;
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_LINK_CREATE: libc::c_int = 28;
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+
+// instruction classes and operands
+const BPF_LDX_MEM_W: u8 = 0x61;
+const BPF_LD_IMM_DW: u8 = 0x18;
+const BPF_ALU64_MOV_K: u8 = 0xb7;
+const BPF_JMP_CALL: u8 = 0x85;
+const BPF_JMP_EXIT: u8 = 0x95;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const XDP_PASS: i32 = 2;
+
+// offset of rx_queue_index in struct xdp_md
+const XDP_MD_RX_QUEUE_INDEX: i16 = 16;
+
+// interfaces that report no speed, e.g. veth pairs, are reported as gigabit NICs
+const DEFAULT_LINK_SPEED: u32 = 1000;
+
+/// Instruction of an eBPF program.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BpfInsn {
+    code: u8,
+    // destination register in the lower, source register in the upper nibble
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl BpfInsn {
+    const fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> BpfInsn {
+        BpfInsn {
+            code,
+            regs: dst | (src << 4),
+            off,
+            imm,
+        }
+    }
+}
+
+/// Attributes of `BPF_MAP_CREATE`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfMapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+/// Attributes of `BPF_MAP_UPDATE_ELEM`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfMapUpdateAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// Attributes of `BPF_PROG_LOAD`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+/// Attributes of `BPF_LINK_CREATE`.
+#[repr(C)]
+#[derive(Default)]
+struct BpfLinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+/// Backend for network interfaces of the kernel via AF_XDP sockets, e.g. for NICs ixy.rs has
+/// no driver for or to compare a native driver against the kernel's.
+///
+/// Every queue is an XDP socket bound to the queue of the same index of the interface, with a
+/// umem of its own that is the memory of a [`Mempool`]. A small XDP program redirects the
+/// frames of the rx queues to the sockets and passes the frames of all other queues to the
+/// kernel, so the traffic has to be steered to the first `num_rx_queues` queues, e.g. with
+/// `ethtool -L`. The interface stays owned by its kernel driver, which may or may not support
+/// zero-copy.
+///
+/// Packets are received into the umem and sent from it without copying if they come from the
+/// mempool of the same queue, packets of other mempools are copied. Interrupts are not used,
+/// the sockets are polled.
+pub struct AfXdpDevice {
+    // the name passed to ixy_init, i.e. including the prefix
+    name: String,
+    ifname: String,
+    ifindex: u32,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    // socket i has an rx ring if i < num_rx_queues and a tx ring if i < num_tx_queues
+    queues: Vec<XskQueue>,
+    program: XdpProgram,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    // the driver counts the packets, the sockets only count drops
+    totals: AfXdpCounters,
+    counters: Cell<AfXdpCounters>,
+    // set once the interface is gone, it is not accessed anymore
+    removed: Cell<bool>,
+}
+
+/// Values of the packet counters summed over all queues.
+#[derive(Clone, Copy, Default)]
+struct AfXdpCounters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+/// XDP program that redirects frames to the sockets in its XSKMAP by rx queue, kept attached
+/// to the interface by its link.
+struct XdpProgram {
+    map: OwnedFd,
+    _prog: OwnedFd,
+    // closing the link detaches the program
+    _link: OwnedFd,
+}
+
+/// Ring shared with the kernel, either one the driver produces entries for (fill and tx) or
+/// one it consumes entries from (completion and rx).
+struct XdpRing<T> {
+    map: *mut libc::c_void,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    flags: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+    // local copies of the indexes, the one of the other side is refreshed only when needed
+    cached_prod: u32,
+    cached_cons: u32,
+}
+
+/// XDP socket of a queue with its umem and rings.
+struct XskQueue {
+    // closed first, the kernel stops accessing the umem before it is freed
+    fd: OwnedFd,
+    fill: XdpRing<u64>,
+    completion: XdpRing<u64>,
+    rx: Option<XdpRing<libc::xdp_desc>>,
+    tx: Option<XdpRing<libc::xdp_desc>>,
+    pool: Arc<Mempool>,
+    base: usize,
+    stride: usize,
+    // packets the kernel has not finished sending by their entry in the umem
+    tx_bufs: Vec<Option<Packet>>,
+    num_tx_pending: usize,
+    // number of buffers in the fill ring or in the kernel
+    num_rx_bufs: usize,
+    num_posted: usize,
+    drained: bool,
+    watchdog_time: Instant,
+    // statistics of the socket at their last read
+    xdp_stats: Cell<libc::xdp_statistics>,
+}
+
+impl<T: Copy> XdpRing<T> {
+    /// Maps the ring of `size` entries at page offset `pgoff` of socket `fd`.
+    fn map(
+        fd: RawFd,
+        offsets: &libc::xdp_ring_offset,
+        pgoff: libc::off_t,
+        size: u32,
+    ) -> Result<XdpRing<T>, IxyError> {
+        let map_len = offsets.desc as usize + size as usize * mem::size_of::<T>();
+
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                pgoff,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(IxyError::Io(io::Error::last_os_error()));
+        }
+
+        let base = map as usize;
+        let producer = (base + offsets.producer as usize) as *const AtomicU32;
+        let consumer = (base + offsets.consumer as usize) as *const AtomicU32;
+
+        Ok(XdpRing {
+            map,
+            map_len,
+            producer,
+            consumer,
+            flags: (base + offsets.flags as usize) as *const AtomicU32,
+            descs: (base + offsets.desc as usize) as *mut T,
+            size,
+            cached_prod: unsafe { (*producer).load(Ordering::Relaxed) },
+            cached_cons: unsafe { (*consumer).load(Ordering::Relaxed) },
+        })
+    }
+
+    /// Returns the number of free entries of a ring the driver produces for, at least
+    /// `wanted` if there are that many.
+    fn num_free(&mut self, wanted: u32) -> u32 {
+        let free = self.size - self.cached_prod.wrapping_sub(self.cached_cons);
+        if free >= wanted {
+            return free;
+        }
+
+        self.cached_cons = unsafe { (*self.consumer).load(Ordering::Acquire) };
+        self.size - self.cached_prod.wrapping_sub(self.cached_cons)
+    }
+
+    /// Writes `desc` to the next entry, the kernel sees it once it is submitted.
+    fn push(&mut self, desc: T) {
+        unsafe {
+            ptr::write(
+                self.descs
+                    .add((self.cached_prod & (self.size - 1)) as usize),
+                desc,
+            );
+        }
+        self.cached_prod = self.cached_prod.wrapping_add(1);
+    }
+
+    /// Hands all pushed entries to the kernel.
+    fn submit(&self) {
+        unsafe { (*self.producer).store(self.cached_prod, Ordering::Release) }
+    }
+
+    /// Returns the number of entries of a ring the driver consumes from, at least `wanted` if
+    /// there are that many.
+    fn num_available(&mut self, wanted: u32) -> u32 {
+        let available = self.cached_prod.wrapping_sub(self.cached_cons);
+        if available >= wanted {
+            return available;
+        }
+
+        self.cached_prod = unsafe { (*self.producer).load(Ordering::Acquire) };
+        self.cached_prod.wrapping_sub(self.cached_cons)
+    }
+
+    /// Returns the `i`th available entry.
+    fn read(&self, i: u32) -> T {
+        unsafe {
+            ptr::read(
+                self.descs
+                    .add((self.cached_cons.wrapping_add(i) & (self.size - 1)) as usize),
+            )
+        }
+    }
+
+    /// Returns the next entry without consuming it, if any.
+    fn peek(&self) -> Option<T> {
+        if self.cached_cons == unsafe { (*self.producer).load(Ordering::Acquire) } {
+            return None;
+        }
+
+        Some(self.read(0))
+    }
+
+    /// Returns `n` consumed entries to the kernel.
+    fn release(&mut self, n: u32) {
+        self.cached_cons = self.cached_cons.wrapping_add(n);
+        unsafe { (*self.consumer).store(self.cached_cons, Ordering::Release) }
+    }
+
+    /// Returns whether the kernel only processes the ring after a syscall.
+    fn needs_wakeup(&self) -> bool {
+        (unsafe { (*self.flags).load(Ordering::Relaxed) } & libc::XDP_RING_NEED_WAKEUP) != 0
+    }
+
+    /// Returns the producer and consumer index as seen by the kernel.
+    fn indexes(&self) -> (u32, u32) {
+        unsafe {
+            (
+                (*self.producer).load(Ordering::Relaxed),
+                (*self.consumer).load(Ordering::Relaxed),
+            )
+        }
+    }
+}
+
+impl<T> Drop for XdpRing<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.map, self.map_len);
+        }
+    }
+}
+
+impl XskQueue {
+    /// Returns a socket bound to queue `queue_id` of interface `ifindex` whose umem is the
+    /// memory of `pool`, with an rx ring if `rx` and a tx ring if `tx`.
+    fn open(
+        ifindex: u32,
+        queue_id: u16,
+        rx: bool,
+        tx: bool,
+        pool: Arc<Mempool>,
+    ) -> Result<XskQueue, IxyError> {
+        let stride = pool.entry_stride();
+
+        if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&stride) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "mempool entries of {} bytes are not between {} and {} bytes",
+                stride, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+            )));
+        }
+
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(privilege_error(
+                "creating an AF_XDP socket needs CAP_NET_RAW",
+                io::Error::last_os_error(),
+            ));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let base = pool.entry_virt_addr(0) as usize;
+
+        // entries of a power of two bytes are the chunks, others need unaligned mode
+        let umem_reg = libc::xdp_umem_reg {
+            addr: base as u64,
+            len: (pool.num_entries() * stride) as u64,
+            chunk_size: stride as u32,
+            headroom: 0,
+            flags: if stride.is_power_of_two() {
+                0
+            } else {
+                libc::XDP_UMEM_UNALIGNED_CHUNK_FLAG
+            },
+            tx_metadata_len: 0,
+        };
+        set_sockopt(fd.as_raw_fd(), libc::XDP_UMEM_REG, &umem_reg).map_err(|e| {
+            // the pinned pages of the umem are accounted against RLIMIT_MEMLOCK
+            if e.raw_os_error() == Some(libc::ENOBUFS) {
+                IxyError::InsufficientPrivileges {
+                    detail: format!(
+                        "registering the umem exceeds the locked memory limit of {}",
+                        format_memlock_limit()
+                    ),
+                }
+            } else {
+                IxyError::Io(e)
+            }
+        })?;
+
+        let num_rx_entries = NUM_RX_QUEUE_ENTRIES as u32;
+        let num_tx_entries = NUM_TX_QUEUE_ENTRIES as u32;
+
+        // every umem needs a fill and a completion ring, even if it is only used in one
+        // direction
+        set_sockopt(fd.as_raw_fd(), libc::XDP_UMEM_FILL_RING, &num_rx_entries)?;
+        set_sockopt(
+            fd.as_raw_fd(),
+            libc::XDP_UMEM_COMPLETION_RING,
+            &num_tx_entries,
+        )?;
+        if rx {
+            set_sockopt(fd.as_raw_fd(), libc::XDP_RX_RING, &num_rx_entries)?;
+        }
+        if tx {
+            set_sockopt(fd.as_raw_fd(), libc::XDP_TX_RING, &num_tx_entries)?;
+        }
+
+        let mut offsets: libc::xdp_mmap_offsets = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::xdp_mmap_offsets>() as libc::socklen_t;
+        if unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_XDP,
+                libc::XDP_MMAP_OFFSETS,
+                &mut offsets as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        } == -1
+        {
+            return Err(IxyError::Io(io::Error::last_os_error()));
+        }
+
+        // kernels before 5.4 have no flags and thus no need_wakeup
+        if len as usize != mem::size_of::<libc::xdp_mmap_offsets>() {
+            return Err(IxyError::InvalidConfiguration(
+                "AF_XDP sockets of this kernel do not support need_wakeup".to_string(),
+            ));
+        }
+
+        let fill = XdpRing::map(
+            fd.as_raw_fd(),
+            &offsets.fr,
+            libc::XDP_UMEM_PGOFF_FILL_RING as libc::off_t,
+            num_rx_entries,
+        )?;
+        let completion = XdpRing::map(
+            fd.as_raw_fd(),
+            &offsets.cr,
+            libc::XDP_UMEM_PGOFF_COMPLETION_RING as libc::off_t,
+            num_tx_entries,
+        )?;
+        let rx_ring = if rx {
+            Some(XdpRing::map(
+                fd.as_raw_fd(),
+                &offsets.rx,
+                libc::XDP_PGOFF_RX_RING,
+                num_rx_entries,
+            )?)
+        } else {
+            None
+        };
+        let tx_ring = if tx {
+            Some(XdpRing::map(
+                fd.as_raw_fd(),
+                &offsets.tx,
+                libc::XDP_PGOFF_TX_RING,
+                num_tx_entries,
+            )?)
+        } else {
+            None
+        };
+
+        // zero-copy is used if the driver of the interface supports it, copy mode otherwise
+        let addr = libc::sockaddr_xdp {
+            sxdp_family: libc::AF_XDP as u16,
+            sxdp_flags: libc::XDP_USE_NEED_WAKEUP,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: u32::from(queue_id),
+            sxdp_shared_umem_fd: 0,
+        };
+
+        let start = Instant::now();
+        while unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_xdp>() as libc::socklen_t,
+            )
+        } == -1
+        {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EBUSY) || start.elapsed() > BIND_TIMEOUT {
+                return Err(IxyError::Io(err));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let num_entries = pool.num_entries();
+
+        let mut queue = XskQueue {
+            fd,
+            fill,
+            completion,
+            rx: rx_ring,
+            tx: tx_ring,
+            pool,
+            base,
+            stride,
+            tx_bufs: (0..num_entries).map(|_| None).collect(),
+            num_tx_pending: 0,
+            num_rx_bufs: 0,
+            num_posted: if rx { NUM_RX_QUEUE_ENTRIES } else { 0 },
+            drained: false,
+            watchdog_time: Instant::now(),
+            xdp_stats: Cell::new(unsafe { mem::zeroed() }),
+        };
+
+        queue.fill();
+
+        Ok(queue)
+    }
+
+    /// Posts free buffers to the fill ring until `num_posted` buffers are in the kernel.
+    /// Returns whether buffers were posted.
+    fn fill(&mut self) -> bool {
+        if self.drained {
+            return false;
+        }
+
+        let wanted = self.num_posted.saturating_sub(self.num_rx_bufs) as u32;
+        if wanted == 0 {
+            return false;
+        }
+
+        let num_free = self.fill.num_free(wanted).min(wanted);
+        let mut posted = 0;
+
+        for _ in 0..num_free {
+            let id = match self.pool.alloc_buf() {
+                Some(id) => id,
+                None => break,
+            };
+
+            // the kernel puts the frame behind XDP_PACKET_HEADROOM bytes into the chunk
+            self.fill.push((id * self.stride) as u64);
+            posted += 1;
+        }
+
+        if posted == 0 {
+            return false;
+        }
+
+        self.fill.submit();
+        self.num_rx_bufs += posted;
+
+        true
+    }
+
+    /// Pushes up to `num_packets` received packets onto `buffer` and posts new buffers.
+    /// Returns the number of packets and their bytes.
+    fn receive(&mut self, buffer: &mut VecDeque<Packet>, num_packets: usize) -> (usize, u64) {
+        let rx = self.rx.as_mut().unwrap();
+
+        let received = rx.num_available(num_packets as u32).min(num_packets as u32);
+        let mut bytes = 0;
+
+        for i in 0..received {
+            let desc = rx.read(i);
+            let offset = umem_offset(desc.addr);
+            let id = offset / self.stride;
+            let data = offset - id * self.stride;
+
+            let p = unsafe {
+                Packet::new(
+                    (self.base + offset) as *mut u8,
+                    self.pool.get_phys_addr(id) - self.pool.headroom() + data,
+                    desc.len as usize,
+                    self.pool.clone(),
+                    id,
+                )
+            };
+
+            bytes += u64::from(desc.len);
+            buffer.push_back(p);
+        }
+
+        if received > 0 {
+            rx.release(received);
+            self.num_rx_bufs -= received as usize;
+        }
+
+        self.fill();
+
+        // in copy mode or with an idle driver the kernel only fills the rx ring on a syscall
+        if self.fill.needs_wakeup() {
+            unsafe {
+                libc::recvfrom(
+                    self.fd.as_raw_fd(),
+                    ptr::null_mut(),
+                    0,
+                    libc::MSG_DONTWAIT,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                );
+            }
+        }
+
+        (received as usize, bytes)
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx ring.
+    fn peek(&self) -> Option<&[u8]> {
+        let desc = self.rx.as_ref()?.peek()?;
+
+        Some(unsafe {
+            slice::from_raw_parts(
+                (self.base + umem_offset(desc.addr)) as *const u8,
+                desc.len as usize,
+            )
+        })
+    }
+
+    /// Pops as many packets as possible from `packets` and puts them into the tx ring.
+    /// Returns the number of packets and their bytes.
+    fn send(&mut self, packets: &mut VecDeque<Packet>) -> (usize, u64) {
+        self.clean();
+
+        let tx = self.tx.as_mut().unwrap();
+        let num_free = tx.num_free(packets.len() as u32) as usize;
+        let max_frame_len = self.pool.entry_size() - self.pool.headroom();
+
+        let mut sent = 0;
+        let mut bytes = 0;
+
+        while sent < num_free {
+            let packet = match packets.pop_front() {
+                Some(p) => p,
+                None => break,
+            };
+
+            // packets of the umem are sent from where they are, all others are copied into it
+            let packet = if Arc::ptr_eq(&packet.pool, &self.pool) && packet.next.is_none() {
+                packet
+            } else if packet.total_len() > max_frame_len {
+                warn!(
+                    "dropping packet of {} bytes: umem chunks hold at most {} bytes",
+                    packet.total_len(),
+                    max_frame_len
+                );
+                continue;
+            } else {
+                let mut copy = match alloc_pkt(&self.pool, packet.total_len()) {
+                    Some(p) => p,
+                    None => {
+                        packets.push_front(packet);
+                        break;
+                    }
+                };
+
+                let mut offset = 0;
+                for segment in packet.segments() {
+                    copy[offset..offset + segment.len()].copy_from_slice(segment);
+                    offset += segment.len();
+                }
+
+                copy
+            };
+
+            tx.push(libc::xdp_desc {
+                addr: (packet.addr_virt as usize - self.base) as u64,
+                len: packet.len as u32,
+                options: 0,
+            });
+
+            bytes += packet.len as u64;
+            sent += 1;
+
+            let id = packet.pool_entry;
+            self.tx_bufs[id] = Some(packet);
+        }
+
+        if sent > 0 {
+            tx.submit();
+
+            if self.num_tx_pending == 0 {
+                self.watchdog_time = Instant::now();
+            }
+            self.num_tx_pending += sent;
+        }
+
+        self.kick_tx();
+
+        (sent, bytes)
+    }
+
+    /// Returns the packets the kernel finished sending to their mempool.
+    fn clean(&mut self) {
+        let completed = self.completion.num_available(u32::MAX);
+
+        for i in 0..completed {
+            let id = umem_offset(self.completion.read(i)) / self.stride;
+            self.tx_bufs[id] = None;
+        }
+
+        if completed > 0 {
+            self.completion.release(completed);
+            self.num_tx_pending -= completed as usize;
+            self.watchdog_time = Instant::now();
+        }
+    }
+
+    /// Wakes the kernel up to send the pending packets if it asks for it, e.g. in copy mode.
+    fn kick_tx(&self) {
+        if self.num_tx_pending == 0 || !self.tx.as_ref().is_some_and(XdpRing::needs_wakeup) {
+            return;
+        }
+
+        // EAGAIN and EBUSY only mean the kernel is still busy with earlier packets
+        unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            );
+        }
+    }
+
+    /// Returns whether no packets are pending or the kernel finished packets within `timeout`.
+    fn healthy(&mut self, timeout: Duration) -> bool {
+        self.clean();
+        self.kick_tx();
+
+        if self.num_tx_pending == 0 {
+            self.watchdog_time = Instant::now();
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Sets the number of buffers the kernel may receive into. Returns whether buffers were
+    /// posted.
+    fn set_posted(&mut self, num_posted: usize) -> Result<bool, IxyError> {
+        if num_posted > NUM_RX_QUEUE_ENTRIES {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} buffers: fill ring holds at most {}",
+                num_posted, NUM_RX_QUEUE_ENTRIES
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // posted buffers can't be taken back, lowering the limit takes effect as packets arrive
+        Ok(self.fill())
+    }
+
+    /// Returns the statistics of the socket, see `struct xdp_statistics`.
+    fn read_xdp_stats(&self) -> io::Result<libc::xdp_statistics> {
+        let mut stats: libc::xdp_statistics = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::xdp_statistics>() as libc::socklen_t;
+
+        if unsafe {
+            libc::getsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_XDP,
+                libc::XDP_STATISTICS,
+                &mut stats as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(stats)
+    }
+}
+
+impl XdpProgram {
+    /// Loads the program and attaches it to interface `ifindex`.
+    fn load(ifindex: u32, ifname: &str) -> Result<XdpProgram, IxyError> {
+        let mut map_attr = BpfMapCreateAttr {
+            map_type: BPF_MAP_TYPE_XSKMAP,
+            key_size: 4,
+            value_size: 4,
+            max_entries: u32::from(MAX_QUEUES),
+            ..Default::default()
+        };
+        let map = bpf_fd(BPF_MAP_CREATE, &mut map_attr)
+            .map_err(|e| privilege_error("creating an XSKMAP needs CAP_BPF", e))?;
+
+        // return bpf_redirect_map(&xsks, ctx->rx_queue_index, XDP_PASS);
+        let insns = [
+            BpfInsn::new(BPF_LDX_MEM_W, 2, 1, XDP_MD_RX_QUEUE_INDEX, 0),
+            BpfInsn::new(BPF_LD_IMM_DW, 1, BPF_PSEUDO_MAP_FD, 0, map.as_raw_fd()),
+            BpfInsn::new(0, 0, 0, 0, 0),
+            BpfInsn::new(BPF_ALU64_MOV_K, 3, 0, 0, XDP_PASS),
+            BpfInsn::new(BPF_JMP_CALL, 0, 0, 0, BPF_FUNC_REDIRECT_MAP),
+            BpfInsn::new(BPF_JMP_EXIT, 0, 0, 0, 0),
+        ];
+        let license = CString::new("GPL").unwrap();
+
+        let mut prog_name = [0; 16];
+        prog_name[..7].copy_from_slice(b"ixy_xsk");
+
+        let mut prog_attr = BpfProgLoadAttr {
+            prog_type: BPF_PROG_TYPE_XDP,
+            insn_cnt: insns.len() as u32,
+            insns: insns.as_ptr() as u64,
+            license: license.as_ptr() as u64,
+            prog_name,
+            expected_attach_type: BPF_XDP,
+            ..Default::default()
+        };
+        let prog = bpf_fd(BPF_PROG_LOAD, &mut prog_attr)
+            .map_err(|e| privilege_error("loading an XDP program needs CAP_BPF", e))?;
+
+        let mut link_attr = BpfLinkCreateAttr {
+            prog_fd: prog.as_raw_fd() as u32,
+            target_ifindex: ifindex,
+            attach_type: BPF_XDP,
+            ..Default::default()
+        };
+        let link = bpf_fd(BPF_LINK_CREATE, &mut link_attr).map_err(|e| {
+            if e.raw_os_error() == Some(libc::EBUSY) {
+                IxyError::InvalidConfiguration(format!(
+                    "interface {} already has an XDP program attached",
+                    ifname
+                ))
+            } else {
+                privilege_error("attaching an XDP program needs CAP_NET_ADMIN", e)
+            }
+        })?;
+
+        Ok(XdpProgram {
+            map,
+            _prog: prog,
+            _link: link,
+        })
+    }
+
+    /// Redirects the frames of rx queue `queue_id` to socket `fd`. Closing the socket removes
+    /// it from the map again.
+    fn add_socket(&self, queue_id: u16, fd: RawFd) -> Result<(), IxyError> {
+        let key = u32::from(queue_id);
+        let value = fd as u32;
+
+        let mut attr = BpfMapUpdateAttr {
+            map_fd: self.map.as_raw_fd() as u32,
+            key: &key as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            ..Default::default()
+        };
+
+        bpf(BPF_MAP_UPDATE_ELEM, &mut attr)?;
+
+        Ok(())
+    }
+}
+
+impl IxyDevice for AfXdpDevice {
+    /// Returns an initialized `AfXdpDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(name: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<AfXdpDevice, IxyError> {
+        AfXdpDevice::init_with_allocator(name, num_rx_queues, num_tx_queues, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns false, the kernel driver of the interface handles the IOMMU.
+    fn is_card_iommu_capable(&self) -> bool {
+        false
+    }
+
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Returns the name of this device, i.e. the interface name with the `af_xdp:` prefix.
+    fn get_pci_addr(&self) -> &str {
+        &self.name
+    }
+
+    /// The NIC is owned by its kernel driver, its BARs are not mapped.
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot map BAR{}: the interface is owned by the kernel",
+            DRIVER_NAME, bar
+        )))
+    }
+
+    /// Returns the mac address of the interface.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        let mut mac = [0; 6];
+
+        if let Ok(address) = fs::read_to_string(self.sysfs_path("address")) {
+            for (byte, hex) in mac.iter_mut().zip(address.trim().split(':')) {
+                *byte = u8::from_str_radix(hex, 16).unwrap_or(0);
+            }
+        }
+
+        mac
+    }
+
+    /// The mac address of the interface is set via the kernel, e.g. with `ip link set`.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        warn!(
+            "cannot set mac address {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} of {}: the \
+             interface is owned by the kernel",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5], self.name
+        );
+    }
+
+    /// Enables or disables promiscuous mode of the interface.
+    fn set_promisc(&self, enabled: bool) -> Result<(), IxyError> {
+        set_interface_flag(&self.ifname, libc::IFF_PROMISC, enabled)
+    }
+
+    /// Enables or disables the reception of all multicast packets on the interface.
+    fn set_allmulti(&self, enabled: bool) -> Result<(), IxyError> {
+        set_interface_flag(&self.ifname, libc::IFF_ALLMULTI, enabled)
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (received, bytes) = self.queues[queue_id as usize].receive(buffer, num_packets);
+
+        self.totals.rx_pkts += received as u64;
+        self.totals.rx_bytes += bytes;
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the interface was removed, e.g. a hot-unplugged NIC or a deleted veth.
+    fn is_removed(&self) -> bool {
+        if !self.removed.get() && !Path::new(&self.sysfs_path("")).exists() {
+            self.removed.set(true);
+        }
+
+        self.removed.get()
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx ring.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of buffers of rx queue `queue_id` the kernel may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.queues[queue_id as usize].set_posted(num_posted)?;
+
+        Ok(())
+    }
+
+    /// Returns the number of buffers of rx queue `queue_id` the kernel may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the socket's tx ring.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let (sent, bytes) = self.queues[queue_id as usize].send(packets);
+
+        self.totals.tx_pkts += sent as u64;
+        self.totals.tx_bytes += bytes;
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`, the packets are counted by the driver.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.totals;
+
+        stats.rx_pkts += current.rx_pkts - last.rx_pkts;
+        stats.tx_pkts += current.tx_pkts - last.tx_pkts;
+        stats.rx_bytes += current.rx_bytes - last.rx_bytes;
+        stats.tx_bytes += current.tx_bytes - last.tx_bytes;
+
+        self.counters.set(current);
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.counters.set(self.totals);
+
+        for queue in self.queues.iter() {
+            if let Ok(current) = queue.read_xdp_stats() {
+                queue.xdp_stats.set(current);
+            }
+        }
+    }
+
+    /// Reads the drops of the sockets, frames dropped because an rx ring was full count as
+    /// missed, those dropped because a fill ring was empty as having no buffer.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        for queue in self.queues.iter() {
+            let current = match queue.read_xdp_stats() {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!("failed to read statistics of {}: {}", self.name, e);
+                    continue;
+                }
+            };
+            let last = queue.xdp_stats.get();
+
+            stats.rx_missed +=
+                (current.rx_dropped - last.rx_dropped) + (current.rx_ring_full - last.rx_ring_full);
+            stats.rx_no_buffer += current.rx_fill_ring_empty_descs - last.rx_fill_ring_empty_descs;
+
+            queue.xdp_stats.set(current);
+        }
+    }
+
+    /// Returns the link speed the kernel reports, 0 if the interface is down or a gigabit if it
+    /// has no speed.
+    fn get_link_speed(&self) -> u32 {
+        let operstate = fs::read_to_string(self.sysfs_path("operstate")).unwrap_or_default();
+
+        // interfaces without link detection, e.g. some virtual ones, are always "unknown"
+        if !matches!(operstate.trim(), "up" | "unknown") {
+            return 0;
+        }
+
+        match fs::read_to_string(self.sysfs_path("speed"))
+            .ok()
+            .and_then(|speed| speed.trim().parse::<i64>().ok())
+        {
+            Some(speed) if speed > 0 => speed as u32,
+            _ => DEFAULT_LINK_SPEED,
+        }
+    }
+
+    /// Returns the PCIe link of the NIC behind the interface, or [`None`] for virtual
+    /// interfaces.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        let device = match fs::read_link(self.sysfs_path("device")) {
+            Ok(device) => device,
+            Err(_) => return Ok(None),
+        };
+
+        let pci_addr = match device.file_name().and_then(|name| name.to_str()) {
+            Some(pci_addr) if Path::new("/sys/bus/pci/devices").join(pci_addr).exists() => {
+                pci_addr.to_string()
+            }
+            _ => return Ok(None),
+        };
+
+        PciDevice::open(&pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device. Sockets that keep their rings
+    /// survive, all others are closed and drop their pending packets.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.name, num_rx_queues, num_tx_queues
+        );
+
+        let num_queues = num_rx_queues.max(num_tx_queues);
+        let old_rx_queues = self.num_rx_queues;
+        let old_tx_queues = self.num_tx_queues;
+
+        self.queues.truncate(usize::from(num_queues));
+        self.num_rx_queues = num_rx_queues;
+        self.num_tx_queues = num_tx_queues;
+
+        for queue_id in 0..self.queues.len() as u16 {
+            let rx = queue_id < num_rx_queues;
+            let tx = queue_id < num_tx_queues;
+
+            if rx != (queue_id < old_rx_queues) || tx != (queue_id < old_tx_queues) {
+                let entry_size = self.queues[usize::from(queue_id)].pool.entry_size();
+                self.reopen_queue(queue_id, entry_size)?;
+            }
+        }
+
+        for queue_id in self.queues.len() as u16..num_queues {
+            let queue = self.open_queue(queue_id, PKT_BUF_ENTRY_SIZE)?;
+            self.queues.push(queue);
+        }
+
+        Ok(())
+    }
+
+    /// The rx queues of this backend receive as long as their socket is open.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.queues[queue_id as usize].drained {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Single queues of the interface are not stopped, use `set_rx_posted` or
+    /// `drain_rx_queue` instead.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether rx queue `queue_id` has buffers.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues) && !self.queues[queue_id as usize].drained
+    }
+
+    /// The tx queues of this backend send as long as their socket is open.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Single queues of the interface are not stopped.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether tx queue `queue_id` is configured.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or the kernel finished packets within
+    /// `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Ok(self.queues[queue_id as usize].healthy(timeout))
+    }
+
+    /// Drops all pending packets of tx queue `queue_id` by closing its socket and opening a
+    /// new one. Packets the socket received but the application did not yet fetch are dropped
+    /// as well.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!("resetting tx queue {} of device {}", queue_id, self.name);
+
+        let entry_size = self.queues[queue_id as usize].pool.entry_size();
+        self.reopen_queue(queue_id as u16, entry_size)
+    }
+
+    /// Takes all buffers of rx queue `queue_id` back from the kernel by closing its socket and
+    /// opening a new one without buffers.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let entry_size = self.queues[queue_id as usize].pool.entry_size();
+        self.reopen_queue(queue_id as u16, entry_size)?;
+
+        let queue = &mut self.queues[queue_id as usize];
+        queue.drained = true;
+        queue.num_posted = 0;
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        let queue = &mut self.queues[queue_id as usize];
+        queue.clean();
+        while queue.num_tx_pending > 0 {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            queue.kick_tx();
+            thread::sleep(Duration::from_millis(1));
+            queue.clean();
+        }
+
+        Ok(())
+    }
+
+    /// The kernel drops frames of rx queues without buffers.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} always drops packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Flow control is configured via the kernel, e.g. with `ethtool -A`.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "ignoring flow control mode {:?} of {}: the interface is owned by the kernel",
+            mode, self.name
+        );
+    }
+
+    /// Returns [`FlowControl::None`], the backend does not know the pause frame settings.
+    fn get_flow_control(&self) -> FlowControl {
+        FlowControl::None
+    }
+
+    /// The kernel never passes the CRC to AF_XDP sockets.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot keep the crc on {}: the kernel strips it",
+                self.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, received frames never carry a CRC.
+    fn get_crc_strip(&self) -> bool {
+        true
+    }
+
+    /// Reopens the socket of rx queue `queue_id` with a new mempool of `buffer_size` bytes as
+    /// its umem. The kernel puts 256 bytes of headroom in front of every frame.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&buffer_size) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between {} and {} bytes",
+                buffer_size, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+            )));
+        }
+
+        self.reopen_queue(queue_id as u16, buffer_size)
+    }
+
+    /// The EEPROM of the NIC is owned by its kernel driver.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// The thermal sensor of the NIC is read via the kernel, e.g. its hwmon device.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    /// The rings of the socket live in kernel memory.
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            queue_id < u32::from(self.num_rx_queues),
+            "rx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// The rings of the socket live in kernel memory.
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            queue_id < u32::from(self.num_tx_queues),
+            "tx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// Reads the indexes of the rings of all sockets.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        for (i, queue) in self.queues.iter().enumerate() {
+            let rings = [
+                ("FILL", Some(queue.fill.indexes())),
+                ("COMPLETION", Some(queue.completion.indexes())),
+                ("RX", queue.rx.as_ref().map(XdpRing::indexes)),
+                ("TX", queue.tx.as_ref().map(XdpRing::indexes)),
+            ];
+
+            for (name, indexes) in rings {
+                if let Some((producer, consumer)) = indexes {
+                    dump.push(format!("{}_PRODUCER[{}]", name, i), producer);
+                    dump.push(format!("{}_CONSUMER[{}]", name, i), consumer);
+                }
+            }
+        }
+
+        dump
+    }
+
+    /// Closes all sockets and opens new ones with the default buffer size.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.name);
+
+        // the kernel allows only one socket per queue
+        self.queues.clear();
+
+        for queue_id in 0..self.num_rx_queues.max(self.num_tx_queues) {
+            let queue = self.open_queue(queue_id, PKT_BUF_ENTRY_SIZE)?;
+            self.queues.push(queue);
+        }
+
+        self.totals = AfXdpCounters::default();
+        self.counters.set(AfXdpCounters::default());
+
+        Ok(())
+    }
+}
+
+impl AfXdpDevice {
+    /// Returns an initialized `AfXdpDevice` for interface `name`, with or without the
+    /// `af_xdp:` prefix, whose umems are allocated by `allocator`, or in hugepages if [`None`].
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        name: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+    ) -> Result<AfXdpDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let ifname = name.strip_prefix(AF_XDP_PREFIX).unwrap_or(name);
+
+        let ifindex = CString::new(ifname)
+            .map(|ifname| unsafe { libc::if_nametoindex(ifname.as_ptr()) })
+            .unwrap_or(0);
+        if ifindex == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "no network interface {}",
+                ifname
+            )));
+        }
+
+        let program = XdpProgram::load(ifindex, ifname)?;
+
+        let mut dev = AfXdpDevice {
+            name: format!("{}{}", AF_XDP_PREFIX, ifname),
+            ifname: ifname.to_string(),
+            ifindex,
+            num_rx_queues,
+            num_tx_queues,
+            queues: Vec::with_capacity(usize::from(num_rx_queues.max(num_tx_queues))),
+            program,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            totals: AfXdpCounters::default(),
+            counters: Cell::new(AfXdpCounters::default()),
+            removed: Cell::new(false),
+        };
+
+        dev.reset()?;
+
+        // wait some time for the link to come up
+        dev.wait_for_link();
+
+        Ok(dev)
+    }
+
+    /// Returns a new socket for queue `queue_id` with a mempool of `entry_size` byte entries
+    /// as its umem.
+    fn open_queue(&self, queue_id: u16, entry_size: usize) -> Result<XskQueue, IxyError> {
+        let rx = queue_id < self.num_rx_queues;
+        let tx = queue_id < self.num_tx_queues;
+
+        let pool = allocate_mempool(self.allocator.as_ref(), None, entry_size)?;
+        let queue = XskQueue::open(self.ifindex, queue_id, rx, tx, pool)?;
+
+        if rx {
+            self.program.add_socket(queue_id, queue.fd.as_raw_fd())?;
+        }
+
+        Ok(queue)
+    }
+
+    /// Closes the socket of queue `queue_id` and opens a new one with a mempool of
+    /// `entry_size` byte entries. If that fails, the device has no queues until it is reset.
+    fn reopen_queue(&mut self, queue_id: u16, entry_size: usize) -> Result<(), IxyError> {
+        // the kernel allows only one socket per queue
+        self.queues.remove(usize::from(queue_id));
+
+        match self.open_queue(queue_id, entry_size) {
+            Ok(queue) => {
+                self.queues.insert(usize::from(queue_id), queue);
+                Ok(())
+            }
+            Err(e) => {
+                self.queues.clear();
+                self.num_rx_queues = 0;
+                self.num_tx_queues = 0;
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the path of attribute `name` of the interface in sysfs.
+    fn sysfs_path(&self, name: &str) -> String {
+        format!("/sys/class/net/{}/{}", self.ifname, name)
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets or clears `flag` of the kernel's network interface `ifname`, e.g. `IFF_PROMISC`.
+pub(crate) fn set_interface_flag(
+    ifname: &str,
+    flag: libc::c_int,
+    enabled: bool,
+) -> Result<(), IxyError> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        return Err(IxyError::Io(io::Error::last_os_error()));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut ifreq: libc::ifreq = unsafe { mem::zeroed() };
+    for (dst, &src) in ifreq.ifr_name.iter_mut().zip(ifname.as_bytes()) {
+        *dst = src as libc::c_char;
+    }
+
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut ifreq) } == -1 {
+        return Err(IxyError::Io(io::Error::last_os_error()));
+    }
+
+    unsafe {
+        if enabled {
+            ifreq.ifr_ifru.ifru_flags |= flag as libc::c_short;
+        } else {
+            ifreq.ifr_ifru.ifru_flags &= !(flag as libc::c_short);
+        }
+    }
+
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCSIFFLAGS as _, &ifreq) } == -1 {
+        return Err(privilege_error(
+            "changing interface flags needs CAP_NET_ADMIN",
+            io::Error::last_os_error(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the offset into the umem a descriptor's address refers to.
+fn umem_offset(addr: u64) -> usize {
+    ((addr & XSK_UNALIGNED_BUF_ADDR_MASK) + (addr >> XSK_UNALIGNED_BUF_OFFSET_SHIFT)) as usize
+}
+
+/// Sets socket option `opt` of AF_XDP socket `fd` to `value`.
+fn set_sockopt<T>(fd: RawFd, opt: libc::c_int, value: &T) -> io::Result<()> {
+    if unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_XDP,
+            opt,
+            value as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Runs bpf command `cmd` with attributes `attr`.
+fn bpf<T>(cmd: libc::c_int, attr: &mut T) -> io::Result<libc::c_long> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T,
+            mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret)
+}
+
+/// Runs bpf command `cmd` that returns a new file descriptor.
+fn bpf_fd<T>(cmd: libc::c_int, attr: &mut T) -> io::Result<OwnedFd> {
+    bpf(cmd, attr).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Returns [`IxyError::InsufficientPrivileges`] with `detail` if `e` is a permission error, or
+/// `e` otherwise.
+fn privilege_error(detail: &str, e: io::Error) -> IxyError {
+    match e.raw_os_error() {
+        Some(libc::EPERM) | Some(libc::EACCES) => IxyError::InsufficientPrivileges {
+            detail: format!("{}: {}", detail, e),
+        },
+        _ => IxyError::Io(e),
+    }
+}
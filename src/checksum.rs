@@ -0,0 +1,468 @@
+//! Internet checksums (RFC 1071) for verifying and filling in packet headers.
+//!
+//! The 16 bit words are summed with AVX2 or SSE2 if the cpu supports them at runtime, other
+//! architectures use a scalar fallback.
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64 as x86;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const IPV4_MIN_HEADER_LEN: usize = 20;
+pub(crate) const IPV4_CHECKSUM_OFFSET: usize = 10;
+const IPV6_HEADER_LEN: usize = 40;
+
+pub(crate) const IPPROTO_TCP: u8 = 6;
+pub(crate) const IPPROTO_UDP: u8 = 17;
+// offsets of the checksum fields in the TCP and UDP headers
+const TCP_CHECKSUM_OFFSET: usize = 16;
+const UDP_CHECKSUM_OFFSET: usize = 6;
+const TCP_DATA_OFFSET: usize = 12;
+const TCP_MIN_HEADER_LEN: usize = 20;
+
+// flush the 32 bit simd lanes before they can overflow, each lane grows by at most 0x1fffe per
+// simd block
+const SIMD_FLUSH_BYTES: usize = 32 * 1024;
+
+/// Returns the internet checksum (RFC 1071) of `data`.
+///
+/// A trailing odd byte is padded with zero. The checksum of data that already contains a valid
+/// checksum is zero.
+///
+/// # Examples
+///
+/// ```
+/// use ixy::checksum::internet_checksum;
+///
+/// // example from RFC 1071
+/// let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+/// assert_eq!(internet_checksum(&data), !0xddf2);
+/// ```
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    !fold(sum_words(data))
+}
+
+/// Returns the checksum of the IPv4 `header`, ignoring the current content of its checksum field.
+///
+/// The checksum covers the whole `header`, i.e. it has to end where the header ends.
+///
+/// # Panics
+///
+/// Panics if `header` is shorter than the 20 byte minimum IPv4 header.
+///
+/// # Examples
+///
+/// ```
+/// use ixy::checksum::ipv4_header_checksum;
+///
+/// let header = [
+///     0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00,
+///     0x01, 0xc0, 0xa8, 0x00, 0xc7,
+/// ];
+/// assert_eq!(ipv4_header_checksum(&header), 0xb861);
+/// ```
+pub fn ipv4_header_checksum(header: &[u8]) -> u16 {
+    assert!(header.len() >= IPV4_MIN_HEADER_LEN, "ipv4 header too short");
+
+    // the checksum field is at an even offset, so the words around it can be summed separately
+    let sum =
+        sum_words(&header[..IPV4_CHECKSUM_OFFSET]) + sum_words(&header[IPV4_CHECKSUM_OFFSET + 2..]);
+
+    !fold(sum)
+}
+
+/// Returns whether the ethernet frame `pkt` carries an IPv4 packet with a valid header checksum.
+///
+/// Returns `false` for frames that are not IPv4, VLAN tagged frames are not supported.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::checksum::verify_ipv4;
+/// use ixy::*;
+///
+/// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+/// let valid = dev.rx_iter(0, 32).filter(|p| verify_ipv4(p)).count();
+/// ```
+pub fn verify_ipv4(pkt: &[u8]) -> bool {
+    if pkt.len() < ETHERNET_HEADER_LEN + IPV4_MIN_HEADER_LEN {
+        return false;
+    }
+
+    if u16::from_be_bytes([pkt[12], pkt[13]]) != ETHERTYPE_IPV4 {
+        return false;
+    }
+
+    let ip = &pkt[ETHERNET_HEADER_LEN..];
+    let header_len = usize::from(ip[0] & 0x0f) * 4;
+
+    if ip[0] >> 4 != 4 || header_len < IPV4_MIN_HEADER_LEN || header_len > ip.len() {
+        return false;
+    }
+
+    internet_checksum(&ip[..header_len]) == 0
+}
+
+/// Returns the checksum of the pseudo header of the TCP or UDP packet following the IPv4 or
+/// IPv6 header `ip`, it is not inverted.
+///
+/// Network cards that fill in TCP and UDP checksums expect it in the checksum field, see
+/// [`TxOffloads`](crate::memory::TxOffloads). The upper-layer length is derived from the length
+/// fields of `ip`, IPv6 extension headers are not supported.
+///
+/// # Panics
+///
+/// Panics if `ip` is shorter than the minimum header of its version.
+///
+/// # Examples
+///
+/// ```
+/// use ixy::checksum::pseudo_header_checksum;
+///
+/// let header = [
+///     0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+///     0x01, 0xc0, 0xa8, 0x00, 0xc7,
+/// ];
+/// // 0xc0a8 + 0x0001 + 0xc0a8 + 0x00c7 + 0x0011 + 0x005f
+/// assert_eq!(pseudo_header_checksum(&header), 0x8289);
+/// ```
+pub fn pseudo_header_checksum(ip: &[u8]) -> u16 {
+    fold(pseudo_header_sum(ip, true))
+}
+
+/// Returns the checksum of the pseudo header following the IPv4 or IPv6 header `ip` without
+/// the upper-layer length, network cards that segment TCP packets add it per segment.
+pub(crate) fn pseudo_header_checksum_without_len(ip: &[u8]) -> u16 {
+    fold(pseudo_header_sum(ip, false))
+}
+
+/// Returns the sum of the words of the pseudo header following the IP header `ip`, the
+/// upper-layer length is only included if `with_len` is set.
+fn pseudo_header_sum(ip: &[u8], with_len: bool) -> u64 {
+    let (addrs, protocol, len) = if ip[0] >> 4 == 6 {
+        assert!(ip.len() >= IPV6_HEADER_LEN, "ipv6 header too short");
+
+        let len = u16::from_be_bytes([ip[4], ip[5]]);
+        (&ip[8..IPV6_HEADER_LEN], ip[6], len)
+    } else {
+        assert!(ip.len() >= IPV4_MIN_HEADER_LEN, "ipv4 header too short");
+
+        let header_len = u16::from(ip[0] & 0x0f) * 4;
+        let len = u16::from_be_bytes([ip[2], ip[3]]).wrapping_sub(header_len);
+        (&ip[12..IPV4_MIN_HEADER_LEN], ip[9], len)
+    };
+
+    let mut sum = sum_words(addrs) + sum_words(&[0, protocol]);
+    if with_len {
+        sum += sum_words(&len.to_be_bytes());
+    }
+
+    sum
+}
+
+/// Offsets and protocols of the headers of an ethernet frame carrying IPv4 or IPv6.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HeaderOffsets {
+    /// Offset of the IP header, i.e. the length of the ethernet header including a VLAN tag.
+    pub(crate) l3: usize,
+    /// Offset of the header following the IP header.
+    pub(crate) l4: usize,
+    /// Offset of the end of the IP packet, i.e. of the ethernet padding if there is any.
+    pub(crate) end: usize,
+    pub(crate) ipv4: bool,
+    /// Protocol of the header following the IP header.
+    pub(crate) protocol: u8,
+}
+
+impl HeaderOffsets {
+    /// Returns the offsets of the headers of `pkt`, or [`None`] if it is no IP packet.
+    ///
+    /// A single VLAN tag is skipped, IPv6 extension headers are not supported.
+    pub(crate) fn parse(pkt: &[u8]) -> Option<HeaderOffsets> {
+        let mut l3 = ETHERNET_HEADER_LEN;
+        let mut ethertype = u16::from_be_bytes([*pkt.get(12)?, *pkt.get(13)?]);
+
+        if ethertype == ETHERTYPE_VLAN {
+            ethertype = u16::from_be_bytes([*pkt.get(16)?, *pkt.get(17)?]);
+            l3 += VLAN_TAG_LEN;
+        }
+
+        let ip = pkt.get(l3..)?;
+
+        match ethertype {
+            ETHERTYPE_IPV4 if ip.len() >= IPV4_MIN_HEADER_LEN && ip[0] >> 4 == 4 => {
+                let header_len = usize::from(ip[0] & 0x0f) * 4;
+                let len = usize::from(u16::from_be_bytes([ip[2], ip[3]]));
+                if header_len < IPV4_MIN_HEADER_LEN || header_len > ip.len() || len < header_len {
+                    return None;
+                }
+
+                Some(HeaderOffsets {
+                    l3,
+                    l4: l3 + header_len,
+                    end: (l3 + len).min(pkt.len()),
+                    ipv4: true,
+                    protocol: ip[9],
+                })
+            }
+            ETHERTYPE_IPV6 if ip.len() >= IPV6_HEADER_LEN && ip[0] >> 4 == 6 => {
+                let len = usize::from(u16::from_be_bytes([ip[4], ip[5]]));
+
+                Some(HeaderOffsets {
+                    l3,
+                    l4: l3 + IPV6_HEADER_LEN,
+                    end: (l3 + IPV6_HEADER_LEN + len).min(pkt.len()),
+                    ipv4: false,
+                    protocol: ip[6],
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the length of the TCP header of `pkt`, or [`None`] if it is no valid TCP packet.
+    pub(crate) fn tcp_header_len(&self, pkt: &[u8]) -> Option<usize> {
+        if self.protocol != IPPROTO_TCP || self.l4 + TCP_MIN_HEADER_LEN > pkt.len() {
+            return None;
+        }
+
+        let len = usize::from(pkt[self.l4 + TCP_DATA_OFFSET] >> 4) * 4;
+        if len < TCP_MIN_HEADER_LEN || self.l4 + len > pkt.len() {
+            return None;
+        }
+
+        Some(len)
+    }
+
+    /// Returns the offset of the checksum field in the TCP or UDP header, or [`None`] for other
+    /// protocols.
+    pub(crate) fn l4_checksum_offset(&self) -> Option<usize> {
+        match self.protocol {
+            IPPROTO_TCP => Some(TCP_CHECKSUM_OFFSET),
+            IPPROTO_UDP => Some(UDP_CHECKSUM_OFFSET),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `checksum` to the TCP or UDP checksum field of `pkt` with the headers at `offsets`.
+pub(crate) fn write_l4_checksum(pkt: &mut [u8], offsets: &HeaderOffsets, checksum: u16) {
+    if let Some(offset) = offsets.l4_checksum_offset() {
+        let field = offsets.l4 + offset;
+        pkt[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+/// Fills in the IPv4 header checksum of `pkt` with the headers at `offsets`.
+pub(crate) fn fill_ipv4_checksum(pkt: &mut [u8], offsets: &HeaderOffsets) {
+    let checksum = ipv4_header_checksum(&pkt[offsets.l3..offsets.l4]);
+    let field = offsets.l3 + IPV4_CHECKSUM_OFFSET;
+
+    pkt[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Fills in the TCP or UDP checksum of `pkt` with the headers at `offsets`, the checksum field
+/// has to hold the checksum of the pseudo header. `pkt` has to hold the whole packet.
+pub(crate) fn fill_l4_checksum(pkt: &mut [u8], offsets: &HeaderOffsets) {
+    let field = match offsets.l4_checksum_offset() {
+        Some(offset) if offsets.l4 + offset + 2 <= offsets.end => offsets.l4 + offset,
+        _ => return,
+    };
+
+    let mut checksum = internet_checksum(&pkt[offsets.l4..offsets.end]);
+
+    // a zero UDP checksum means that there is none
+    if checksum == 0 && offsets.protocol == IPPROTO_UDP {
+        checksum = 0xffff;
+    }
+
+    pkt[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Folds `sum` into 16 bits with end-around carry and converts it from native to network order.
+fn fold(mut sum: u64) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    // the one's complement sum is byte order independent, see RFC 1071 section 2 (B)
+    u16::from_be(sum as u16)
+}
+
+/// Returns the sum of all native-endian 16 bit words of `data`.
+fn sum_words(data: &[u8]) -> u64 {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { sum_words_avx2(data) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            return unsafe { sum_words_sse2(data) };
+        }
+    }
+
+    sum_words_scalar(data)
+}
+
+fn sum_words_scalar(data: &[u8]) -> u64 {
+    let mut words = data.chunks_exact(2);
+    let mut sum: u64 = words
+        .by_ref()
+        .map(|w| u64::from(u16::from_ne_bytes([w[0], w[1]])))
+        .sum();
+
+    if let [last] = words.remainder() {
+        sum += u64::from(u16::from_ne_bytes([*last, 0]));
+    }
+
+    sum
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn sum_words_avx2(data: &[u8]) -> u64 {
+    let zero = x86::_mm256_setzero_si256();
+    let mut sum = 0;
+
+    for chunk in data.chunks(SIMD_FLUSH_BYTES) {
+        let mut blocks = chunk.chunks_exact(32);
+        let mut acc = x86::_mm256_setzero_si256();
+
+        // widen the 16 bit words to 32 bit lanes, the order of the words does not matter
+        for block in blocks.by_ref() {
+            let v = x86::_mm256_loadu_si256(block.as_ptr() as *const x86::__m256i);
+            acc = x86::_mm256_add_epi32(acc, x86::_mm256_unpacklo_epi16(v, zero));
+            acc = x86::_mm256_add_epi32(acc, x86::_mm256_unpackhi_epi16(v, zero));
+        }
+
+        let mut lanes = [0u32; 8];
+        x86::_mm256_storeu_si256(lanes.as_mut_ptr() as *mut x86::__m256i, acc);
+
+        sum += lanes.iter().map(|&l| u64::from(l)).sum::<u64>();
+        sum += sum_words_scalar(blocks.remainder());
+    }
+
+    sum
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn sum_words_sse2(data: &[u8]) -> u64 {
+    let zero = x86::_mm_setzero_si128();
+    let mut sum = 0;
+
+    for chunk in data.chunks(SIMD_FLUSH_BYTES) {
+        let mut blocks = chunk.chunks_exact(16);
+        let mut acc = x86::_mm_setzero_si128();
+
+        for block in blocks.by_ref() {
+            let v = x86::_mm_loadu_si128(block.as_ptr() as *const x86::__m128i);
+            acc = x86::_mm_add_epi32(acc, x86::_mm_unpacklo_epi16(v, zero));
+            acc = x86::_mm_add_epi32(acc, x86::_mm_unpackhi_epi16(v, zero));
+        }
+
+        let mut lanes = [0u32; 4];
+        x86::_mm_storeu_si128(lanes.as_mut_ptr() as *mut x86::__m128i, acc);
+
+        sum += lanes.iter().map(|&l| u64::from(l)).sum::<u64>();
+        sum += sum_words_scalar(blocks.remainder());
+    }
+
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // odd lengths, lengths around the simd block sizes and around the lane flush
+    const LENGTHS: [usize; 16] = [
+        0,
+        1,
+        2,
+        15,
+        16,
+        17,
+        31,
+        32,
+        33,
+        63,
+        1501,
+        SIMD_FLUSH_BYTES - 1,
+        SIMD_FLUSH_BYTES,
+        SIMD_FLUSH_BYTES + 1,
+        2 * SIMD_FLUSH_BYTES + 33,
+        100_001,
+    ];
+
+    /// Returns `len` pseudo-random bytes, xorshift keeps the tests deterministic.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Asserts that the simd sums the cpu supports equal the scalar sum of `data`.
+    fn assert_simd_sums(data: &[u8]) {
+        let expected = sum_words_scalar(data);
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") {
+                assert_eq!(
+                    unsafe { sum_words_avx2(data) },
+                    expected,
+                    "avx2, {} bytes",
+                    data.len()
+                );
+            }
+            if is_x86_feature_detected!("sse2") {
+                assert_eq!(
+                    unsafe { sum_words_sse2(data) },
+                    expected,
+                    "sse2, {} bytes",
+                    data.len()
+                );
+            }
+        }
+
+        assert_eq!(sum_words(data), expected, "{} bytes", data.len());
+    }
+
+    #[test]
+    fn simd_sums_match_scalar_sum() {
+        for &len in LENGTHS.iter() {
+            let data = random_bytes(len + 1);
+
+            assert_simd_sums(&data[..len]);
+            // unaligned start
+            assert_simd_sums(&data[1..]);
+        }
+    }
+
+    #[test]
+    fn simd_sums_of_all_ones_match_scalar_sum() {
+        // every word is 0xffff, the lanes grow as fast as possible and have to be flushed
+        for &len in LENGTHS.iter() {
+            assert_simd_sums(&vec![0xff; len]);
+        }
+    }
+
+    #[test]
+    fn checksum_of_all_ones_is_zero() {
+        // the one's complement sum of 0xffff words is 0xffff
+        assert_eq!(internet_checksum(&vec![0xff; 4 * SIMD_FLUSH_BYTES]), 0);
+    }
+}
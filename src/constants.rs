@@ -2,6 +2,7 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
+#![allow(unused_parens)]
 #![allow(clippy::all)]
 // list of all NIC registers and some structs
 // copied and changed from the ixy C driver
@@ -120,6 +121,9 @@ pub const IXGBE_DEV_ID_X550EM_X_VF_HV: u32                             = 0x15A9;
 // unused/unsupported by ixy
 pub fn IXGBE_BY_MAC(_hw: u32, _r: u32) -> u32 { 0 }
 
+/* Value read from the registers of a removed or resetting device */
+pub const IXGBE_FAILED_READ_REG: u32                                   = 0xFFFFFFFF;
+
 /* General Registers */
 pub const IXGBE_CTRL: u32                                              = 0x00000;
 pub const IXGBE_STATUS: u32                                            = 0x00008;
@@ -264,6 +268,8 @@ pub const IXGBE_EMC_DIODE1_DATA: u32                                   = 0x01;
 pub const IXGBE_EMC_DIODE1_THERM_LIMIT: u32                            = 0x19;
 pub const IXGBE_EMC_DIODE2_DATA: u32                                   = 0x23;
 pub const IXGBE_EMC_DIODE2_THERM_LIMIT: u32                            = 0x1A;
+pub const IXGBE_EMC_DIODE3_DATA: u32                                   = 0x2A;
+pub const IXGBE_EMC_DIODE3_THERM_LIMIT: u32                            = 0x30;
 
 pub const IXGBE_MAX_SENSORS: u32                                       = 3;
 
@@ -1832,6 +1838,107 @@ pub const IXGBE_VFRE_ENABLE_ALL: u32                                   = 0xFFFFF
 
 pub const IXGBE_VF_INIT_TIMEOUT: u32                                   = 200; /* Number of retries to clear RSTI */
 
+/* PF/VF mailbox, see ixgbe_mbx.h */
+pub const IXGBE_VFMAILBOX_SIZE: u32                                    = 16; /* 16 32 bit words - 64 bytes */
+
+pub const IXGBE_PFMAILBOX_STS: u32                                     = 0x00000001; /* Initiate message send to VF */
+pub const IXGBE_PFMAILBOX_ACK: u32                                     = 0x00000002; /* Ack message recv'd from VF */
+pub const IXGBE_PFMAILBOX_VFU: u32                                     = 0x00000004; /* VF owns the mailbox buffer */
+pub const IXGBE_PFMAILBOX_PFU: u32                                     = 0x00000008; /* PF owns the mailbox buffer */
+pub const IXGBE_PFMAILBOX_RVFU: u32                                    = 0x00000010; /* Reset VFU - used when VF stuck */
+
+pub const IXGBE_MBVFICR_VFREQ_MASK: u32                                = 0x0000FFFF; /* bits for VF messages */
+pub const IXGBE_MBVFICR_VFREQ_VF1: u32                                 = 0x00000001; /* bit for VF 1 message */
+pub const IXGBE_MBVFICR_VFACK_MASK: u32                                = 0xFFFF0000; /* bits for VF acks */
+pub const IXGBE_MBVFICR_VFACK_VF1: u32                                 = 0x00010000; /* bit for VF 1 ack */
+
+/* If it's a IXGBE_VF_* msg then it originates in the VF and is sent to the
+ * PF.  The reverse is true if it is IXGBE_PF_*.
+ * Message ACK's are the value or'd with 0xF0000000
+ */
+pub const IXGBE_VT_MSGTYPE_ACK: u32                                    = 0x80000000; /* Messages below or'd with this are the ACK */
+pub const IXGBE_VT_MSGTYPE_NACK: u32                                   = 0x40000000; /* Messages below or'd with this are the NACK */
+pub const IXGBE_VT_MSGTYPE_CTS: u32                                    = 0x20000000; /* Indicates that VF is still clear to send requests */
+pub const IXGBE_VT_MSGINFO_SHIFT: u32                                  = 16;
+pub const IXGBE_VT_MSGINFO_MASK: u32                                   = (0xFF << IXGBE_VT_MSGINFO_SHIFT); /* bits 23:16 are for extra info for certain messages */
+
+/* mailbox API versions */
+pub const IXGBE_MBOX_API_10: u32                                       = 0; /* API version 1.0, linux/freebsd VF driver */
+pub const IXGBE_MBOX_API_20: u32                                       = 1; /* API version 2.0, solaris Phase1 VF driver */
+pub const IXGBE_MBOX_API_11: u32                                       = 2; /* API version 1.1, linux/freebsd VF driver */
+
+pub const IXGBE_VF_RESET: u32                                          = 0x01; /* VF requests reset */
+pub const IXGBE_VF_SET_MAC_ADDR: u32                                   = 0x02; /* VF requests PF to set MAC addr */
+pub const IXGBE_VF_SET_MULTICAST: u32                                  = 0x03; /* VF requests PF to set MC addr */
+pub const IXGBE_VF_SET_VLAN: u32                                       = 0x04; /* VF requests PF to set VLAN */
+pub const IXGBE_VF_SET_LPE: u32                                        = 0x05; /* VF requests PF to set VMOLR.LPE */
+pub const IXGBE_VF_SET_MACVLAN: u32                                    = 0x06; /* VF requests PF for unicast filter */
+pub const IXGBE_VF_API_NEGOTIATE: u32                                  = 0x08; /* negotiate API version */
+pub const IXGBE_VF_GET_QUEUES: u32                                     = 0x09; /* get queue configuration */
+
+/* GET_QUEUES return data indices within the mailbox */
+pub const IXGBE_VF_TX_QUEUES: u32                                      = 1; /* number of Tx queues supported */
+pub const IXGBE_VF_RX_QUEUES: u32                                      = 2; /* number of Rx queues supported */
+pub const IXGBE_VF_TRANS_VLAN: u32                                     = 3; /* Indication of port vlan */
+pub const IXGBE_VF_DEF_QUEUE: u32                                      = 4; /* Default queue offset */
+
+/* length of permanent address message returned from PF */
+pub const IXGBE_VF_PERMADDR_MSG_LEN: u32                               = 4;
+/* word in permanent address message with the current multicast type */
+pub const IXGBE_VF_MC_TYPE_WORD: u32                                   = 3;
+
+/* virtual function registers, see ixgbevf regs.h */
+pub const IXGBE_VFCTRL: u32                                            = 0x00000;
+pub const IXGBE_VFSTATUS: u32                                          = 0x00008;
+pub const IXGBE_VFLINKS: u32                                           = 0x00010;
+pub const IXGBE_VTEICR: u32                                            = 0x00100;
+pub const IXGBE_VTEICS: u32                                            = 0x00104;
+pub const IXGBE_VTEIMS: u32                                            = 0x00108;
+pub const IXGBE_VTEIMC: u32                                            = 0x0010C;
+pub const IXGBE_VTEIAC: u32                                            = 0x00110;
+pub const IXGBE_VTEIAM: u32                                            = 0x00114;
+pub fn IXGBE_VTEITR(_i: u32) -> u32 { (0x00820 + ((_i) * 4)) }
+pub fn IXGBE_VTIVAR(_i: u32) -> u32 { (0x00120 + ((_i) * 4)) }
+pub const IXGBE_VTIVAR_MISC: u32                                       = 0x00140;
+pub const IXGBE_VFPSRTYPE: u32                                         = 0x00300;
+pub fn IXGBE_VFRDBAL(_i: u32) -> u32 { (0x01000 + ((_i) * 0x40)) }
+pub fn IXGBE_VFRDBAH(_i: u32) -> u32 { (0x01004 + ((_i) * 0x40)) }
+pub fn IXGBE_VFRDLEN(_i: u32) -> u32 { (0x01008 + ((_i) * 0x40)) }
+pub fn IXGBE_VFRDH(_i: u32) -> u32 { (0x01010 + ((_i) * 0x40)) }
+pub fn IXGBE_VFSRRCTL(_i: u32) -> u32 { (0x01014 + ((_i) * 0x40)) }
+pub fn IXGBE_VFRDT(_i: u32) -> u32 { (0x01018 + ((_i) * 0x40)) }
+pub fn IXGBE_VFRXDCTL(_i: u32) -> u32 { (0x01028 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTDBAL(_i: u32) -> u32 { (0x02000 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTDBAH(_i: u32) -> u32 { (0x02004 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTDLEN(_i: u32) -> u32 { (0x02008 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTDH(_i: u32) -> u32 { (0x02010 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTDT(_i: u32) -> u32 { (0x02018 + ((_i) * 0x40)) }
+pub fn IXGBE_VFTXDCTL(_i: u32) -> u32 { (0x02028 + ((_i) * 0x40)) }
+pub const IXGBE_VFGPRC: u32                                            = 0x0101C;
+pub const IXGBE_VFGORC_LSB: u32                                        = 0x01020;
+pub const IXGBE_VFGORC_MSB: u32                                        = 0x01024;
+pub const IXGBE_VFMPRC: u32                                            = 0x01034;
+pub const IXGBE_VFGPTC: u32                                            = 0x0201C;
+pub const IXGBE_VFGOTC_LSB: u32                                        = 0x02020;
+pub const IXGBE_VFGOTC_MSB: u32                                        = 0x02024;
+pub const IXGBE_VFMAILBOX: u32                                         = 0x002FC;
+pub fn IXGBE_VFMBMEM(_i: u32) -> u32 { (0x00200 + ((_i) * 4)) }
+
+pub const IXGBE_VF_IRQ_CLEAR_MASK: u32                                 = 7;
+pub const IXGBE_VF_MAX_TX_QUEUES: u32                                  = 8;
+pub const IXGBE_VF_MAX_RX_QUEUES: u32                                  = 8;
+
+/* VFMAILBOX bitmasks */
+pub const IXGBE_VFMAILBOX_REQ: u32                                     = 0x00000001; /* Request for PF Ready bit */
+pub const IXGBE_VFMAILBOX_ACK: u32                                     = 0x00000002; /* Ack PF message received */
+pub const IXGBE_VFMAILBOX_VFU: u32                                     = 0x00000004; /* VF owns the mailbox buffer */
+pub const IXGBE_VFMAILBOX_PFU: u32                                     = 0x00000008; /* PF owns the mailbox buffer */
+pub const IXGBE_VFMAILBOX_PFSTS: u32                                   = 0x00000010; /* PF wrote a message in the MB */
+pub const IXGBE_VFMAILBOX_PFACK: u32                                   = 0x00000020; /* PF ack the previous VF msg */
+pub const IXGBE_VFMAILBOX_RSTI: u32                                    = 0x00000040; /* PF has reset indication */
+pub const IXGBE_VFMAILBOX_RSTD: u32                                    = 0x00000080; /* PF has indicated reset done */
+pub const IXGBE_VFMAILBOX_R2C_BITS: u32                                = 0x000000B0; /* All read to clear bits */
+
 /* RDHMPN and TDHMPN bitmasks */
 pub const IXGBE_RDHMPN_RDICADDR: u32                                   = 0x007FF800;
 pub const IXGBE_RDHMPN_RDICRDREQ: u32                                  = 0x00800000;
@@ -2393,6 +2500,10 @@ pub const IXGBE_PBANUM1_PTR: u32                                       = 0x16;
 pub const IXGBE_ALT_MAC_ADDR_PTR: u32                                  = 0x37;
 pub const IXGBE_FREE_SPACE_PTR: u32                                    = 0x3E;
 
+/* NVM image version (eTrack ID) */
+pub const NVM_ETK_OFF_LOW: u32                                         = 0x2D;
+pub const NVM_ETK_OFF_HI: u32                                          = 0x2E;
+
 /* External Thermal Sensor Config */
 pub const IXGBE_ETS_CFG: u32                                           = 0x26;
 pub const IXGBE_ETS_LTHRES_DELTA_MASK: u32                             = 0x07C0;
@@ -0,0 +1,1818 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES,
+    NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-e1000";
+
+const INTEL_VENDOR_ID: u16 = 0x8086;
+
+// copper parts of the 8254x family and their PCIe successors, QEMU emulates the 82540EM as
+// e1000 and the 82574L as e1000e
+const E1000_DEV_ID_82540EM: u16 = 0x100E;
+const E1000_DEV_ID_82545EM_COPPER: u16 = 0x100F;
+const E1000_DEV_ID_82546EB_COPPER: u16 = 0x1010;
+const E1000_DEV_ID_82541PI: u16 = 0x107C;
+const E1000_DEV_ID_82574L: u16 = 0x10D3;
+const E1000_DEV_ID_82583V: u16 = 0x150C;
+
+const SUPPORTED_DEVICE_IDS: [u16; 6] = [
+    E1000_DEV_ID_82540EM,
+    E1000_DEV_ID_82545EM_COPPER,
+    E1000_DEV_ID_82546EB_COPPER,
+    E1000_DEV_ID_82541PI,
+    E1000_DEV_ID_82574L,
+    E1000_DEV_ID_82583V,
+];
+
+// the EEPROM read register moved its fields with the 82541
+const EERD_82540_DEVICE_IDS: [u16; 3] = [
+    E1000_DEV_ID_82540EM,
+    E1000_DEV_ID_82545EM_COPPER,
+    E1000_DEV_ID_82546EB_COPPER,
+];
+
+// registers, see section 13 of the 8254x developer's manual and section 10 of the 82574
+// datasheet
+const E1000_CTRL: u32 = 0x00000;
+const E1000_STATUS: u32 = 0x00008;
+const E1000_EERD: u32 = 0x00014;
+const E1000_FCAL: u32 = 0x00028;
+const E1000_FCAH: u32 = 0x0002C;
+const E1000_FCT: u32 = 0x00030;
+const E1000_ICR: u32 = 0x000C0;
+const E1000_IMS: u32 = 0x000D0;
+const E1000_IMC: u32 = 0x000D8;
+const E1000_RCTL: u32 = 0x00100;
+const E1000_FCTTV: u32 = 0x00170;
+const E1000_TCTL: u32 = 0x00400;
+const E1000_TIPG: u32 = 0x00410;
+const E1000_PBA: u32 = 0x01000;
+const E1000_FCRTL: u32 = 0x02160;
+const E1000_FCRTH: u32 = 0x02168;
+const E1000_RDBAL: u32 = 0x02800;
+const E1000_RDBAH: u32 = 0x02804;
+const E1000_RDLEN: u32 = 0x02808;
+const E1000_RDH: u32 = 0x02810;
+const E1000_RDT: u32 = 0x02818;
+const E1000_RXDCTL: u32 = 0x02828;
+const E1000_TDBAL: u32 = 0x03800;
+const E1000_TDBAH: u32 = 0x03804;
+const E1000_TDLEN: u32 = 0x03808;
+const E1000_TDH: u32 = 0x03810;
+const E1000_TDT: u32 = 0x03818;
+const E1000_TXDCTL: u32 = 0x03828;
+const E1000_MTA: u32 = 0x05200;
+const E1000_RA: u32 = 0x05400;
+
+// statistic counters, all of them are cleared on read
+const E1000_CRCERRS: u32 = 0x04000;
+const E1000_SYMERRS: u32 = 0x04008;
+const E1000_MPC: u32 = 0x04010;
+const E1000_RLEC: u32 = 0x04040;
+const E1000_XONRXC: u32 = 0x04048;
+const E1000_XONTXC: u32 = 0x0404C;
+const E1000_XOFFRXC: u32 = 0x04050;
+const E1000_XOFFTXC: u32 = 0x04054;
+const E1000_GPRC: u32 = 0x04074;
+const E1000_BPRC: u32 = 0x04078;
+const E1000_MPRC: u32 = 0x0407C;
+const E1000_GPTC: u32 = 0x04080;
+const E1000_GORCL: u32 = 0x04088;
+const E1000_GORCH: u32 = 0x0408C;
+const E1000_GOTCL: u32 = 0x04090;
+const E1000_GOTCH: u32 = 0x04094;
+const E1000_RNBC: u32 = 0x040A0;
+const E1000_RUC: u32 = 0x040A4;
+const E1000_RFC: u32 = 0x040A8;
+const E1000_ROC: u32 = 0x040AC;
+const E1000_RJC: u32 = 0x040B0;
+const E1000_MPTC: u32 = 0x040F0;
+const E1000_BPTC: u32 = 0x040F4;
+
+const E1000_CTRL_LRST: u32 = 1 << 3;
+const E1000_CTRL_ASDE: u32 = 1 << 5;
+const E1000_CTRL_SLU: u32 = 1 << 6;
+const E1000_CTRL_ILOS: u32 = 1 << 7;
+const E1000_CTRL_RST: u32 = 1 << 26;
+const E1000_CTRL_RFCE: u32 = 1 << 27;
+const E1000_CTRL_TFCE: u32 = 1 << 28;
+const E1000_CTRL_PHY_RST: u32 = 1 << 31;
+
+const E1000_STATUS_LU: u32 = 1 << 1;
+const E1000_STATUS_FUNC_1: u32 = 1 << 2;
+const E1000_STATUS_SPEED_MASK: u32 = 3 << 6;
+const E1000_STATUS_SPEED_10: u32 = 0;
+const E1000_STATUS_SPEED_100: u32 = 1 << 6;
+
+const E1000_EERD_START: u32 = 1 << 0;
+const E1000_EERD_DATA_SHIFT: u32 = 16;
+const E1000_EERD_DONE_82540: u32 = 1 << 4;
+const E1000_EERD_ADDR_SHIFT_82540: u32 = 8;
+const E1000_EERD_DONE: u32 = 1 << 1;
+const E1000_EERD_ADDR_SHIFT: u32 = 2;
+
+const E1000_RCTL_EN: u32 = 1 << 1;
+const E1000_RCTL_UPE: u32 = 1 << 3;
+const E1000_RCTL_MPE: u32 = 1 << 4;
+const E1000_RCTL_LPE: u32 = 1 << 5;
+const E1000_RCTL_BAM: u32 = 1 << 15;
+const E1000_RCTL_BSIZE_SHIFT: u32 = 16;
+const E1000_RCTL_BSIZE_MASK: u32 = 3 << E1000_RCTL_BSIZE_SHIFT;
+const E1000_RCTL_BSEX: u32 = 1 << 25;
+const E1000_RCTL_SECRC: u32 = 1 << 26;
+
+const E1000_TCTL_EN: u32 = 1 << 1;
+const E1000_TCTL_PSP: u32 = 1 << 3;
+const E1000_TCTL_CT_SHIFT: u32 = 4;
+const E1000_TCTL_COLD_SHIFT: u32 = 12;
+const E1000_TCTL_RTLC: u32 = 1 << 24;
+
+// recommended collision threshold and full duplex collision distance, see section 13.4.33
+const E1000_COLLISION_THRESHOLD: u32 = 0x0F;
+const E1000_COLLISION_DISTANCE: u32 = 0x3F;
+
+// inter packet gap for copper: IPGT 8, IPGR1 8, IPGR2 6, see section 13.4.34
+const E1000_TIPG_COPPER: u32 = 8 | (8 << 10) | (6 << 20);
+
+const E1000_RAH_AV: u32 = 1 << 31;
+const E1000_FCRTL_XONE: u32 = 1 << 31;
+const E1000_PBA_RX_MASK: u32 = 0xffff;
+
+// flow control address, type and the pause time of sent pause frames
+const E1000_FCAL_PAUSE: u32 = 0x00C2_8001;
+const E1000_FCAH_PAUSE: u32 = 0x0100;
+const E1000_FCT_PAUSE: u32 = 0x8808;
+const FC_PAUSE_TIME: u32 = 0x680;
+
+const E1000_RXD_STAT_DD: u8 = 1 << 0;
+const E1000_RXD_STAT_EOP: u8 = 1 << 1;
+
+const E1000_TXD_CMD_EOP: u8 = 1 << 0;
+const E1000_TXD_CMD_IFCS: u8 = 1 << 1;
+const E1000_TXD_CMD_RS: u8 = 1 << 3;
+const E1000_TXD_STAT_DD: u8 = 1 << 0;
+
+const E1000_IRQ_CLEAR_MASK: u32 = 0xFFFF_FFFF;
+const E1000_FAILED_READ_REG: u32 = 0xFFFF_FFFF;
+
+// EEPROM words, see section 5.6
+const EEPROM_VERSION: u16 = 0x05;
+const EEPROM_CHECKSUM_REG: u16 = 0x3F;
+const EEPROM_SUM: u16 = 0xBABA;
+
+const NUM_RAR_ENTRIES: u32 = 15;
+const NUM_MTA_ENTRIES: u32 = 128;
+
+// one rx and one tx queue, the second queues of the 82574 are not supported
+const MAX_E1000_QUEUES: u16 = 1;
+
+// pthresh, hthresh and wthresh of RXDCTL and TXDCTL are 6 bits wide
+const DESC_THRESH_MAX: u8 = 0x3f;
+
+// buffer sizes RCTL can describe, from the largest to the smallest
+const RX_BUFFER_SIZES: [(usize, u32); 7] = [
+    (16384, E1000_RCTL_BSEX | (1 << E1000_RCTL_BSIZE_SHIFT)),
+    (8192, E1000_RCTL_BSEX | (2 << E1000_RCTL_BSIZE_SHIFT)),
+    (4096, E1000_RCTL_BSEX | (3 << E1000_RCTL_BSIZE_SHIFT)),
+    (2048, 0),
+    (1024, 1 << E1000_RCTL_BSIZE_SHIFT),
+    (512, 2 << E1000_RCTL_BSIZE_SHIFT),
+    (256, 3 << E1000_RCTL_BSIZE_SHIFT),
+];
+
+const TX_MAX_SEGMENTS: usize = 40;
+const TX_CLEAN_BATCH: usize = 32;
+
+// the global reset takes about 1 µs, give the PHY some more time
+const RESET_DELAY: Duration = Duration::from_millis(10);
+
+/// Driver for the 8254x family of gigabit network cards and their PCIe successors 82574 and
+/// 82583, e.g. the e1000 and e1000e network cards emulated by QEMU.
+///
+/// The device has a single rx and tx queue with legacy descriptors.
+pub struct E1000Device {
+    pci_addr: String,
+    addr: *mut u8,
+    len: usize,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<E1000RxQueue>,
+    tx_queues: Vec<E1000TxQueue>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    // layout of the EEPROM read register
+    eerd_done: u32,
+    eerd_addr_shift: u32,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// Legacy receive descriptor, see section 3.2.3.
+#[repr(C)]
+#[allow(dead_code)]
+struct RxDesc {
+    buffer_addr: u64,
+    length: u16,
+    csum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+/// Legacy transmit descriptor, see section 3.3.3.
+#[repr(C)]
+#[allow(dead_code)]
+struct TxDesc {
+    buffer_addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+struct E1000RxQueue {
+    descriptors: *mut RxDesc,
+    // keeps the descriptor ring mapped
+    ring: Dma<RxDesc>,
+    num_descriptors: usize,
+    pool: Arc<Mempool>,
+    bufs_in_use: Vec<usize>,
+    rx_index: usize,
+    rx_tail: usize,
+    num_posted: usize,
+}
+
+impl E1000RxQueue {
+    /// Returns a queue on the descriptor ring `ring` that receives into buffers of `pool`.
+    fn new(ring: Dma<RxDesc>, pool: Arc<Mempool>) -> E1000RxQueue {
+        E1000RxQueue {
+            descriptors: ring.virt,
+            ring,
+            num_descriptors: NUM_RX_QUEUE_ENTRIES,
+            pool,
+            bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
+            rx_index: 0,
+            rx_tail: 0,
+            num_posted: NUM_RX_QUEUE_ENTRIES - 1,
+        }
+    }
+
+    /// Attaches a buffer of the mempool to every descriptor and returns the tail that posts
+    /// `num_posted` of them to the device.
+    fn fill(&mut self) -> Result<usize, IxyError> {
+        for i in 0..self.num_descriptors {
+            let buf = self.pool.alloc_buf().ok_or(IxyError::PoolExhausted)?;
+
+            unsafe {
+                write_rx_desc(self.descriptors.add(i), self.pool.get_phys_addr(buf));
+            }
+
+            // we need to remember which descriptor entry belongs to which mempool entry
+            self.bufs_in_use.push(buf);
+        }
+
+        self.rx_tail = self.num_posted;
+
+        Ok(self.rx_tail)
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills their
+    /// descriptors. Returns the number of received packets and the new tail of the ring if
+    /// refilled descriptors have to be handed back to the device.
+    fn receive(
+        &mut self,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> (usize, Option<usize>) {
+        let mut rx_index = self.rx_index;
+        let mut received_packets = 0;
+
+        {
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
+
+            while received_packets < num_packets {
+                // frames larger than a buffer span several descriptors, only take them once the
+                // device has written back all of them
+                let mut num_segments = 0;
+                let mut status = 0;
+                while (status & E1000_RXD_STAT_EOP) == 0 {
+                    let index = (rx_index + num_segments) % self.num_descriptors;
+                    status = unsafe { rx_desc_status(self, index) };
+
+                    if (status & E1000_RXD_STAT_DD) == 0 {
+                        break;
+                    }
+                    num_segments += 1;
+                }
+
+                if (status & E1000_RXD_STAT_DD) == 0 {
+                    break;
+                }
+
+                // the frame stays in the ring until the descriptors can be refilled without
+                // the buffers reserved for clones
+                if free_stack.available() < num_segments {
+                    break;
+                }
+
+                let pool = &self.pool;
+                let mut packet: Option<Packet> = None;
+
+                for _ in 0..num_segments {
+                    let desc = unsafe { self.descriptors.add(rx_index) };
+
+                    // replace currently used buffer with a free buffer of the mempool
+                    let new_buf = free_stack.pop().expect("no buffer available");
+                    let buf = mem::replace(&mut self.bufs_in_use[rx_index], new_buf);
+
+                    let p = unsafe {
+                        Packet {
+                            addr_virt: pool.get_virt_addr(buf),
+                            addr_phys: pool.get_phys_addr(buf),
+                            len: u16::from_le(ptr::read_volatile(&(*desc).length)) as usize,
+                            pool: pool.clone(),
+                            pool_entry: buf,
+                            next: None,
+                            meta: PacketMeta::default(),
+                        }
+                    };
+
+                    match packet {
+                        Some(ref mut packet) => packet.chain(p),
+                        None => packet = Some(p),
+                    }
+
+                    unsafe {
+                        write_rx_desc(desc, pool.get_phys_addr(new_buf));
+                    }
+
+                    rx_index = (rx_index + 1) % self.num_descriptors;
+                }
+
+                #[allow(unused_mut)]
+                let mut p = packet.unwrap();
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                buffer.push_back(p);
+                received_packets += 1;
+            }
+        }
+
+        let mut tail = None;
+
+        if rx_index != self.rx_index {
+            self.rx_index = rx_index;
+
+            // hand refilled descriptors back to the device, at most num_posted at a time
+            let posted = (self.rx_tail + self.num_descriptors - rx_index) % self.num_descriptors;
+            if posted < self.num_posted {
+                self.rx_tail = (rx_index + self.num_posted) % self.num_descriptors;
+                tail = Some(self.rx_tail);
+            }
+        }
+
+        (received_packets, tail)
+    }
+
+    /// Returns the content of the next received packet without taking it off the ring.
+    fn peek(&self) -> Option<&[u8]> {
+        if self.bufs_in_use.is_empty() {
+            return None;
+        }
+
+        let status = unsafe { rx_desc_status(self, self.rx_index) };
+        if (status & E1000_RXD_STAT_DD) == 0 {
+            return None;
+        }
+
+        unsafe {
+            let desc = self.descriptors.add(self.rx_index);
+            let len = u16::from_le(ptr::read_volatile(&(*desc).length)) as usize;
+            let addr = self.pool.get_virt_addr(self.bufs_in_use[self.rx_index]);
+
+            Some(slice::from_raw_parts(addr, len))
+        }
+    }
+
+    /// Limits the descriptors the device may receive into to `num_posted`, returns the new tail
+    /// if more descriptors have to be handed to the device right away.
+    fn set_posted(&mut self, num_posted: usize) -> Result<Option<usize>, IxyError> {
+        if num_posted >= self.num_descriptors {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                self.num_descriptors,
+                self.num_descriptors - 1
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // the tail never moves backwards, lowering the limit takes effect as packets arrive
+        let posted = (self.rx_tail + self.num_descriptors - self.rx_index) % self.num_descriptors;
+        if posted >= num_posted || self.bufs_in_use.is_empty() {
+            return Ok(None);
+        }
+
+        self.rx_tail = (self.rx_index + num_posted) % self.num_descriptors;
+
+        Ok(Some(self.rx_tail))
+    }
+
+    /// Returns all buffers of the ring to the mempool, the device must not access them anymore.
+    fn drain(&mut self) {
+        for buf in self.bufs_in_use.drain(..) {
+            self.pool.free_buf(buf);
+        }
+
+        // clear the descriptor done bits so rx_batch doesn't pick up the freed buffers
+        unsafe {
+            memset(
+                self.descriptors as *mut u8,
+                self.num_descriptors * mem::size_of::<RxDesc>(),
+                0x00,
+            );
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = 0;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<RxDesc>(),
+        )
+    }
+}
+
+struct E1000TxQueue {
+    descriptors: *mut TxDesc,
+    // keeps the descriptor ring mapped
+    ring: Dma<TxDesc>,
+    num_descriptors: usize,
+    pool: Option<Arc<Mempool>>,
+    bufs_in_use: VecDeque<usize>,
+    clean_index: usize,
+    tx_index: usize,
+    watchdog_head: usize,
+    watchdog_time: Instant,
+}
+
+impl E1000TxQueue {
+    /// Returns an empty queue on the descriptor ring `ring`.
+    fn new(ring: Dma<TxDesc>) -> E1000TxQueue {
+        E1000TxQueue {
+            descriptors: ring.virt,
+            ring,
+            num_descriptors: NUM_TX_QUEUE_ENTRIES,
+            pool: None,
+            bufs_in_use: VecDeque::with_capacity(NUM_TX_QUEUE_ENTRIES),
+            clean_index: 0,
+            tx_index: 0,
+            watchdog_head: 0,
+            watchdog_time: Instant::now(),
+        }
+    }
+
+    /// Pops as many packets as fit into the ring from `packets` and writes their descriptors,
+    /// the device only sends them once the tail is moved to `tail`.
+    fn send(&mut self, packets: &mut VecDeque<Packet>) -> usize {
+        let mut sent = 0;
+        let mut cur_index = self.tx_index;
+        let clean_index = self.clean();
+
+        if self.pool.is_none() {
+            if let Some(packet) = packets.front() {
+                self.pool = Some(packet.pool.clone());
+            }
+        }
+
+        while let Some(packet) = packets.pop_front() {
+            let num_segments = packet.num_segments();
+
+            assert!(
+                packet
+                    .segments()
+                    .all(|s| Arc::ptr_eq(self.pool.as_ref().unwrap(), &s.pool)),
+                "distinct memory pools for a single tx queue are not supported yet"
+            );
+            assert!(
+                num_segments <= TX_MAX_SEGMENTS,
+                "packet of {} segments exceeds the limit of {}",
+                num_segments,
+                TX_MAX_SEGMENTS
+            );
+
+            // one descriptor always stays empty to tell a full ring from an empty one
+            let free = (clean_index + self.num_descriptors - cur_index - 1) % self.num_descriptors;
+
+            if free < num_segments {
+                // tx queue of device is full, push packet back onto the
+                // queue of to-be-sent packets
+                packets.push_front(packet);
+                break;
+            }
+
+            let mut segment = Some(packet);
+
+            while let Some(mut p) = segment {
+                segment = p.unchain();
+
+                // every descriptor reports its status, clean checks arbitrary descriptors
+                let mut cmd = E1000_TXD_CMD_RS | E1000_TXD_CMD_IFCS;
+                if segment.is_none() {
+                    cmd |= E1000_TXD_CMD_EOP;
+                }
+
+                unsafe {
+                    write_tx_desc(
+                        self.descriptors.add(cur_index),
+                        p.get_phys_addr(),
+                        p.len(),
+                        cmd,
+                    );
+                }
+
+                self.bufs_in_use.push_back(p.pool_entry);
+                mem::forget(p);
+
+                cur_index = (cur_index + 1) % self.num_descriptors;
+            }
+
+            self.tx_index = cur_index;
+            sent += 1;
+        }
+
+        sent
+    }
+
+    /// Returns sent buffers to their mempool in multiples of `TX_CLEAN_BATCH` and returns the
+    /// new clean index.
+    fn clean(&mut self) -> usize {
+        let mut clean_index = self.clean_index;
+
+        loop {
+            let cleanable =
+                (self.tx_index + self.num_descriptors - clean_index) % self.num_descriptors;
+
+            if cleanable < TX_CLEAN_BATCH {
+                break;
+            }
+
+            let cleanup_to = (clean_index + TX_CLEAN_BATCH - 1) % self.num_descriptors;
+
+            let status = unsafe { ptr::read_volatile(&(*self.descriptors.add(cleanup_to)).status) };
+
+            if (status & E1000_TXD_STAT_DD) == 0 {
+                break;
+            }
+
+            if let Some(ref pool) = self.pool {
+                pool.free_bufs(self.bufs_in_use.drain(..TX_CLEAN_BATCH));
+            }
+
+            clean_index = (cleanup_to + 1) % self.num_descriptors;
+        }
+
+        self.clean_index = clean_index;
+
+        clean_index
+    }
+
+    /// Returns the index after the last written descriptor, i.e. the tail for the device.
+    fn tail(&self) -> usize {
+        self.tx_index
+    }
+
+    /// Returns whether the ring is empty or the device moved its `head` within `timeout`.
+    fn healthy(&mut self, head: usize, timeout: Duration) -> bool {
+        if head == self.tx_index || head != self.watchdog_head {
+            self.watchdog_head = head;
+            self.watchdog_time = Instant::now();
+
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Drops all pending packets and rewinds the ring, the device must not access it anymore.
+    fn reset(&mut self) {
+        self.release_sent();
+
+        self.clean_index = 0;
+        self.tx_index = 0;
+        self.watchdog_head = 0;
+        self.watchdog_time = Instant::now();
+    }
+
+    /// Returns all buffers to their mempool once the device's head caught up with the tail.
+    fn release_sent(&mut self) {
+        if let Some(ref pool) = self.pool {
+            for buf in self.bufs_in_use.drain(..) {
+                pool.free_buf(buf);
+            }
+        }
+
+        self.clean_index = self.tx_index;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<TxDesc>(),
+        )
+    }
+}
+
+impl IxyDevice for E1000Device {
+    /// Returns an initialized `E1000Device` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<E1000Device, IxyError> {
+        E1000Device::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.read_rar(0)
+    }
+
+    /// Sets the mac address of this device.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        self.write_rar(0, mac);
+    }
+
+    /// Adds `mac` to the first free receive address register and returns its index.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        // the address valid bit marks registers that are in use
+        let index = (1..NUM_RAR_ENTRIES)
+            .find(|&i| self.get_reg32(E1000_RA + 8 * i + 4) & E1000_RAH_AV == 0)
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(
+                    "all receive address registers are in use".to_string(),
+                )
+            })?;
+
+        self.write_rar(index, mac);
+
+        Ok(index as usize)
+    }
+
+    /// Clears the receive address register `index`.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        if index == 0 || index >= NUM_RAR_ENTRIES as usize {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "receive address register {} is not a unicast filter",
+                index
+            )));
+        }
+
+        let index = index as u32;
+
+        self.set_reg32(E1000_RA + 8 * index + 4, 0);
+        self.set_reg32(E1000_RA + 8 * index, 0);
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (received, tail) = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if let Some(tail) = tail {
+            self.set_reg32(E1000_RDT, tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the device was removed, i.e. its status register reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(E1000_STATUS) == E1000_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(num_posted)? {
+            self.set_reg32(E1000_RDT, tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(E1000_TDT, self.tx_queues[queue_id as usize].tail() as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let rx_pkts = u64::from(self.get_reg32(E1000_GPRC));
+        let tx_pkts = u64::from(self.get_reg32(E1000_GPTC));
+        // the octet counters are cleared when their high register is read
+        let rx_bytes =
+            u64::from(self.get_reg32(E1000_GORCL)) + (u64::from(self.get_reg32(E1000_GORCH)) << 32);
+        let tx_bytes =
+            u64::from(self.get_reg32(E1000_GOTCL)) + (u64::from(self.get_reg32(E1000_GOTCH)) << 32);
+
+        stats.rx_pkts += rx_pkts;
+        stats.tx_pkts += tx_pkts;
+        stats.rx_bytes += rx_bytes;
+        stats.tx_bytes += tx_bytes;
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.read_stats(&mut DeviceStats::default());
+        self.read_extended_stats(&mut ExtendedStats::default());
+    }
+
+    /// Reads the extended stats of this device into `stats`, the e1000 counts no MAC faults.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        // all of these registers are cleared on read
+        let reg = |r| u64::from(self.get_reg32(r));
+
+        stats.rx_crc_errors += reg(E1000_CRCERRS);
+        stats.rx_illegal_byte_errors += reg(E1000_SYMERRS);
+        stats.rx_length_errors += reg(E1000_RLEC);
+        stats.rx_undersize += reg(E1000_RUC);
+        stats.rx_fragments += reg(E1000_RFC);
+        stats.rx_oversize += reg(E1000_ROC);
+        stats.rx_jabbers += reg(E1000_RJC);
+        stats.rx_missed += reg(E1000_MPC);
+        stats.rx_no_buffer += reg(E1000_RNBC);
+        stats.rx_broadcast += reg(E1000_BPRC);
+        stats.rx_multicast += reg(E1000_MPRC);
+        stats.tx_broadcast += reg(E1000_BPTC);
+        stats.tx_multicast += reg(E1000_MPTC);
+        stats.rx_xon += reg(E1000_XONRXC);
+        stats.rx_xoff += reg(E1000_XOFFRXC);
+        stats.tx_xon += reg(E1000_XONTXC);
+        stats.tx_xoff += reg(E1000_XOFFTXC);
+    }
+
+    /// Returns the link speed of this device.
+    fn get_link_speed(&self) -> u32 {
+        let status = self.get_reg32(E1000_STATUS);
+        if (status & E1000_STATUS_LU) == 0 {
+            return 0;
+        }
+        match status & E1000_STATUS_SPEED_MASK {
+            E1000_STATUS_SPEED_10 => 10,
+            E1000_STATUS_SPEED_100 => 100,
+            _ => 1000,
+        }
+    }
+
+    /// Returns the PCIe link of this device, [`None`] for the conventional PCI 8254x.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        if self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues = num_rx_queues;
+            self.stop_rx_queue();
+            self.rx_queues.clear();
+        }
+
+        if self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues = num_tx_queues;
+            self.stop_tx_queue();
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                queue.reset();
+            }
+        }
+
+        if self.num_rx_queues < num_rx_queues {
+            self.init_rx_queue()?;
+            self.start_rx_queue()?;
+            self.num_rx_queues = num_rx_queues;
+        }
+
+        if self.num_tx_queues < num_tx_queues {
+            self.init_tx_queue()?;
+            self.start_tx_queue();
+            self.num_tx_queues = num_tx_queues;
+        }
+
+        Ok(())
+    }
+
+    /// Enables the receiver.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].bufs_in_use.is_empty() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        self.set_flags32(E1000_RCTL, E1000_RCTL_EN);
+
+        Ok(())
+    }
+
+    /// Disables the receiver.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue();
+
+        Ok(())
+    }
+
+    /// Returns whether the receiver is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(E1000_RCTL) & E1000_RCTL_EN) != 0
+    }
+
+    /// Enables the transmitter.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        debug!("enabling tx queue {}", queue_id);
+
+        self.set_flags32(E1000_TCTL, E1000_TCTL_EN);
+
+        Ok(())
+    }
+
+    /// Disables the transmitter.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue();
+
+        Ok(())
+    }
+
+    /// Returns whether the transmitter is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+            && (self.get_reg32(E1000_TCTL) & E1000_TCTL_EN) != 0
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.get_reg32(E1000_TDH) as usize;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Disables tx queue `queue_id`, drops all pending packets and enables it again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue();
+        self.tx_queues[queue_id as usize].reset();
+        self.start_tx_queue();
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue();
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.get_reg32(E1000_TDH) as usize != self.tx_queues[queue_id as usize].tail() {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// The e1000 keeps packets in its packet buffer until descriptors become available and
+    /// can't drop them per queue.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} does not support dropping packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of rx queue `queue_id`.
+    fn set_rx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.set_reg32(
+            E1000_RXDCTL,
+            desc_thresholds(self.get_reg32(E1000_RXDCTL), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of tx queue `queue_id`.
+    fn set_tx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        self.set_reg32(
+            E1000_TXDCTL,
+            desc_thresholds(self.get_reg32(E1000_TXDCTL), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Sets the flow control mode of this device, see section 14.8.
+    fn set_flow_control(&self, mode: FlowControl) {
+        info!("setting flow control mode to {:?}", mode);
+
+        let (rx_pause, tx_pause) = match mode {
+            FlowControl::None => (false, false),
+            FlowControl::RxPause => (true, false),
+            FlowControl::TxPause => (false, true),
+            FlowControl::Full => (true, true),
+        };
+
+        // pause frames are recognized by their address and type
+        self.set_reg32(E1000_FCAL, E1000_FCAL_PAUSE);
+        self.set_reg32(E1000_FCAH, E1000_FCAH_PAUSE);
+        self.set_reg32(E1000_FCT, E1000_FCT_PAUSE);
+        self.set_reg32(E1000_FCTTV, FC_PAUSE_TIME);
+
+        // derive the watermarks (in bytes) from the size of the rx packet buffer (in KB)
+        let pb_size = (self.get_reg32(E1000_PBA) & E1000_PBA_RX_MASK) << 10;
+        if tx_pause {
+            self.set_reg32(E1000_FCRTL, (pb_size / 2) | E1000_FCRTL_XONE);
+            self.set_reg32(E1000_FCRTH, pb_size * 3 / 4);
+        } else {
+            self.set_reg32(E1000_FCRTL, 0);
+            self.set_reg32(E1000_FCRTH, 0);
+        }
+
+        let mut ctrl = self.get_reg32(E1000_CTRL) & !(E1000_CTRL_RFCE | E1000_CTRL_TFCE);
+        if rx_pause {
+            ctrl |= E1000_CTRL_RFCE;
+        }
+        if tx_pause {
+            ctrl |= E1000_CTRL_TFCE;
+        }
+        self.set_reg32(E1000_CTRL, ctrl);
+    }
+
+    /// Returns the flow control mode of this device.
+    fn get_flow_control(&self) -> FlowControl {
+        let ctrl = self.get_reg32(E1000_CTRL);
+        let rx_pause = (ctrl & E1000_CTRL_RFCE) != 0;
+        let tx_pause = (ctrl & E1000_CTRL_TFCE) != 0;
+
+        match (rx_pause, tx_pause) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Sets whether the receiver strips the Ethernet FCS.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if enable {
+            self.set_flags32(E1000_RCTL, E1000_RCTL_SECRC);
+        } else {
+            self.clear_flags32(E1000_RCTL, E1000_RCTL_SECRC);
+        }
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.get_reg32(E1000_RCTL) & E1000_RCTL_SECRC != 0
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    ///
+    /// The e1000 only supports buffers of 256 bytes to 16 KiB whose size is a power of 2, sizes
+    /// are rounded up accordingly.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        let buffer_size = buffer_size.next_power_of_two().max(256);
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].pool = pool;
+        self.write_rx_buffer_size();
+
+        // accept frames of up to 16 KiB instead of 1522 bytes
+        if buffer_size > PKT_BUF_ENTRY_SIZE {
+            self.set_flags32(E1000_RCTL, E1000_RCTL_LPE);
+        }
+
+        self.start_rx_queue()
+    }
+
+    /// Reads the 16 bit word at `offset` of this device's EEPROM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the EEPROM address space accessible via `EERD`.
+    fn read_eeprom_word(&self, offset: u16) -> u16 {
+        assert!(
+            u32::from(offset) < 1 << (E1000_EERD_DATA_SHIFT - self.eerd_addr_shift),
+            "eeprom access out of bounds"
+        );
+
+        // section 5.3.1 - software-controlled read via the EEPROM read register
+        self.set_reg32(
+            E1000_EERD,
+            (u32::from(offset) << self.eerd_addr_shift) | E1000_EERD_START,
+        );
+
+        loop {
+            let eerd = self.get_reg32(E1000_EERD);
+            if (eerd & self.eerd_done) != 0 {
+                return (eerd >> E1000_EERD_DATA_SHIFT) as u16;
+            }
+            thread::sleep(Duration::from_micros(5));
+        }
+    }
+
+    /// Returns the image version stored in this device's EEPROM.
+    fn get_firmware_version(&self) -> u32 {
+        u32::from(self.read_eeprom_word(EEPROM_VERSION))
+    }
+
+    /// Returns whether the checksum stored in this device's EEPROM is valid, i.e. the first 64
+    /// words sum up to 0xBABA.
+    fn validate_eeprom_checksum(&self) -> bool {
+        let checksum = (0..=EEPROM_CHECKSUM_REG)
+            .fold(0u16, |sum, i| sum.wrapping_add(self.read_eeprom_word(i)));
+
+        checksum == EEPROM_SUM
+    }
+
+    /// The e1000 has no thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the control, queue and stats registers of this device.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("CTRL", E1000_CTRL),
+            ("STATUS", E1000_STATUS),
+            ("IMS", E1000_IMS),
+            ("RCTL", E1000_RCTL),
+            ("TCTL", E1000_TCTL),
+            ("TIPG", E1000_TIPG),
+            ("PBA", E1000_PBA),
+            ("FCRTL", E1000_FCRTL),
+            ("FCRTH", E1000_FCRTH),
+            ("RDBAL", E1000_RDBAL),
+            ("RDBAH", E1000_RDBAH),
+            ("RDLEN", E1000_RDLEN),
+            ("RDH", E1000_RDH),
+            ("RDT", E1000_RDT),
+            ("RXDCTL", E1000_RXDCTL),
+            ("TDBAL", E1000_TDBAL),
+            ("TDBAH", E1000_TDBAH),
+            ("TDLEN", E1000_TDLEN),
+            ("TDH", E1000_TDH),
+            ("TDT", E1000_TDT),
+            ("TXDCTL", E1000_TXDCTL),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        for i in 0..NUM_RAR_ENTRIES {
+            dump.push(format!("RAL[{}]", i), self.get_reg32(E1000_RA + 8 * i));
+            dump.push(format!("RAH[{}]", i), self.get_reg32(E1000_RA + 8 * i + 4));
+        }
+
+        // the counters are cleared on read, so they are not part of the dump
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for E1000Device {
+    fn drop(&mut self) {
+        // stop all dma before the descriptor rings and mempools are unmapped
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_CTRL, E1000_CTRL_RST);
+        thread::sleep(RESET_DELAY);
+    }
+}
+
+impl E1000Device {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == INTEL_VENDOR_ID && SUPPORTED_DEVICE_IDS.contains(&device_id)
+    }
+
+    /// Returns an initialized `E1000Device` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<E1000Device, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        let device_id = PciDevice::open(pci_addr)?.device_id()?;
+        let (eerd_done, eerd_addr_shift) = if EERD_82540_DEVICE_IDS.contains(&device_id) {
+            (E1000_EERD_DONE_82540, E1000_EERD_ADDR_SHIFT_82540)
+        } else {
+            (E1000_EERD_DONE, E1000_EERD_ADDR_SHIFT)
+        };
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory
+        check_privileges(pci_addr, vfio, dma_size(num_rx_queues, num_tx_queues))?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            pci_map_resource(pci_addr)?
+        };
+
+        let mut dev = E1000Device {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            eerd_done,
+            eerd_addr_shift,
+            removal: RemovalLatch::new(E1000_STATUS),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets and initializes this device, see section 14 of the 8254x developer's manual.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        // section 14.4 - disable interrupts, reset and disable them again
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_CTRL, self.get_reg32(E1000_CTRL) | E1000_CTRL_RST);
+        thread::sleep(RESET_DELAY);
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.get_reg32(E1000_ICR);
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        // the mac address is stored in the first three words of the EEPROM, the second port
+        // of a dual port card uses the address with the last bit flipped
+        let mut mac = [0; 6];
+        for i in 0..3 {
+            let word = self.read_eeprom_word(i as u16).to_le_bytes();
+            mac[2 * i] = word[0];
+            mac[2 * i + 1] = word[1];
+        }
+        if self.get_reg32(E1000_STATUS) & E1000_STATUS_FUNC_1 != 0 {
+            mac[5] ^= 1;
+        }
+
+        info!("initializing device {}", self.pci_addr);
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        // section 14.4 - receive address and multicast table
+        self.write_rar(0, mac);
+        for i in 1..NUM_RAR_ENTRIES {
+            self.set_reg32(E1000_RA + 8 * i + 4, 0);
+            self.set_reg32(E1000_RA + 8 * i, 0);
+        }
+        for i in 0..NUM_MTA_ENTRIES {
+            self.set_reg32(E1000_MTA + 4 * i, 0);
+        }
+
+        // section 14.3 - link up via auto-negotiation of the internal PHY
+        self.init_link();
+
+        // section 14.5 - statistical counters, cleared on read
+        self.reset_stats();
+
+        // section 14.4 - init rx
+        self.init_rx()?;
+
+        // section 14.5 - init tx
+        self.init_tx()?;
+
+        if self.num_rx_queues > 0 {
+            self.start_rx_queue()?;
+        }
+
+        if self.num_tx_queues > 0 {
+            self.start_tx_queue();
+        }
+
+        // enable promisc mode by default to make testing easier
+        self.set_flags32(E1000_RCTL, E1000_RCTL_UPE | E1000_RCTL_MPE);
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Sets the link up, speed and duplex are auto-negotiated by the PHY.
+    fn init_link(&self) {
+        let ctrl =
+            self.get_reg32(E1000_CTRL) & !(E1000_CTRL_LRST | E1000_CTRL_ILOS | E1000_CTRL_PHY_RST);
+
+        self.set_reg32(E1000_CTRL, ctrl | E1000_CTRL_SLU | E1000_CTRL_ASDE);
+    }
+
+    /// Configures the receiver, it is enabled once its queue is started.
+    fn init_rx(&mut self) -> Result<(), IxyError> {
+        // strip the CRC and accept broadcast packets
+        self.set_reg32(E1000_RCTL, E1000_RCTL_SECRC | E1000_RCTL_BAM);
+
+        if self.num_rx_queues > 0 {
+            self.init_rx_queue()?;
+        }
+
+        Ok(())
+    }
+
+    /// Configures the transmitter, it is enabled once its queue is started.
+    fn init_tx(&mut self) -> Result<(), IxyError> {
+        // pad short packets and retransmit after late collisions
+        self.set_reg32(
+            E1000_TCTL,
+            E1000_TCTL_PSP
+                | (E1000_COLLISION_THRESHOLD << E1000_TCTL_CT_SHIFT)
+                | (E1000_COLLISION_DISTANCE << E1000_TCTL_COLD_SHIFT)
+                | E1000_TCTL_RTLC,
+        );
+        self.set_reg32(E1000_TIPG, E1000_TIPG_COPPER);
+
+        if self.num_tx_queues > 0 {
+            self.init_tx_queue()?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring and mempool of the rx queue.
+    fn init_rx_queue(&mut self) -> Result<(), IxyError> {
+        debug!("initializing rx queue 0");
+
+        self.stop_rx_queue();
+
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<RxDesc>();
+
+        let dma: Dma<RxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(E1000_RDBAL, (dma.phys as u64 & 0xffff_ffff) as u32);
+        self.set_reg32(E1000_RDBAH, (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(E1000_RDLEN, ring_size_bytes as u32);
+
+        debug!("rx ring 0 phys addr: {:#x}", dma.phys);
+        debug!("rx ring 0 virt addr: {:p}", dma.virt);
+
+        // set ring to empty at start
+        self.set_reg32(E1000_RDH, 0);
+        self.set_reg32(E1000_RDT, 0);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(E1000RxQueue::new(dma, mempool));
+        self.write_rx_buffer_size();
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring of the tx queue.
+    fn init_tx_queue(&mut self) -> Result<(), IxyError> {
+        debug!("initializing tx queue 0");
+
+        self.stop_tx_queue();
+
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<TxDesc>();
+
+        let dma: Dma<TxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // all descriptors start out without the descriptor done bit
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0);
+        }
+
+        self.set_reg32(E1000_TDBAL, (dma.phys as u64 & 0xffff_ffff) as u32);
+        self.set_reg32(E1000_TDBAH, (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(E1000_TDLEN, ring_size_bytes as u32);
+
+        debug!("tx ring 0 phys addr: {:#x}", dma.phys);
+        debug!("tx ring 0 virt addr: {:p}", dma.virt);
+
+        self.tx_queues.push(E1000TxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Sets the rx queue's descriptors and enables the receiver.
+    fn start_rx_queue(&mut self) -> Result<(), IxyError> {
+        debug!("starting rx queue 0");
+
+        let tail = self.rx_queues[0].fill()?;
+
+        self.set_reg32(E1000_RDH, 0);
+        self.set_flags32(E1000_RCTL, E1000_RCTL_EN);
+
+        // rx queue starts out full
+        self.set_reg32(E1000_RDT, tail as u32);
+
+        Ok(())
+    }
+
+    /// Enables the transmitter.
+    fn start_tx_queue(&mut self) {
+        debug!("starting tx queue 0");
+
+        // tx queue starts out empty
+        self.set_reg32(E1000_TDH, 0);
+        self.set_reg32(E1000_TDT, 0);
+
+        self.set_flags32(E1000_TCTL, E1000_TCTL_EN);
+    }
+
+    /// Disables the receiver.
+    fn stop_rx_queue(&self) {
+        debug!("stopping rx queue 0");
+
+        self.clear_flags32(E1000_RCTL, E1000_RCTL_EN);
+    }
+
+    /// Disables the transmitter, packets that have not been sent yet stay in the ring.
+    fn stop_tx_queue(&self) {
+        debug!("stopping tx queue 0");
+
+        self.clear_flags32(E1000_TCTL, E1000_TCTL_EN);
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Sets the receive buffer size to the largest size RCTL can describe that fits into the
+    /// data area of the rx mempool's entries, i.e. without the headroom.
+    fn write_rx_buffer_size(&self) {
+        let pool = &self.rx_queues[0].pool;
+        let data_size = pool.entry_size() - pool.headroom();
+
+        let bsize = RX_BUFFER_SIZES
+            .iter()
+            .find(|&&(size, _)| size <= data_size)
+            .map_or(
+                RX_BUFFER_SIZES[RX_BUFFER_SIZES.len() - 1].1,
+                |&(_, bsize)| bsize,
+            );
+
+        self.set_reg32(
+            E1000_RCTL,
+            (self.get_reg32(E1000_RCTL) & !(E1000_RCTL_BSIZE_MASK | E1000_RCTL_BSEX)) | bsize,
+        );
+    }
+
+    /// Returns the address in receive address register `index`.
+    fn read_rar(&self, index: u32) -> [u8; 6] {
+        let low = self.get_reg32(E1000_RA + 8 * index).to_le_bytes();
+        let high = self.get_reg32(E1000_RA + 8 * index + 4).to_le_bytes();
+
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+
+    /// Writes `mac` to receive address register `index` and marks it valid.
+    fn write_rar(&self, index: u32, mac: [u8; 6]) {
+        let low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        let high = u32::from_le_bytes([mac[4], mac[5], 0, 0]);
+
+        self.set_reg32(E1000_RA + 8 * index, low);
+        self.set_reg32(E1000_RA + 8 * index + 4, high | E1000_RAH_AV);
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register at `self.addr` + `reg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
+    }
+
+    /// Sets the register at `self.addr` + `reg` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+
+    /// Sets the `flags` at `self.addr` + `reg`.
+    fn set_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) | flags);
+    }
+
+    /// Clears the `flags` at `self.addr` + `reg`.
+    fn clear_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) & !flags);
+    }
+}
+
+/// Returns an error if the e1000 doesn't have the given number of queues.
+fn check_num_queues(num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+    if num_rx_queues > MAX_E1000_QUEUES || num_tx_queues > MAX_E1000_QUEUES {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "cannot configure {} rx and {} tx queues: {} supports a single rx and tx queue",
+            num_rx_queues, num_tx_queues, DRIVER_NAME
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the descriptor control register value `dctl` with the prefetch, host and write-back
+/// thresholds replaced.
+fn desc_thresholds(dctl: u32, pthresh: u8, hthresh: u8, wthresh: u8) -> Result<u32, IxyError> {
+    if pthresh > DESC_THRESH_MAX || hthresh > DESC_THRESH_MAX || wthresh > DESC_THRESH_MAX {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "descriptor thresholds must not exceed {}",
+            DESC_THRESH_MAX
+        )));
+    }
+
+    // pthresh: 5:0, hthresh: 13:8, wthresh: 21:16, the bits in between are reserved
+    let mask = u32::from(DESC_THRESH_MAX);
+    let mut dctl = dctl;
+    dctl &= !(mask | (mask << 8) | (mask << 16));
+    dctl |= u32::from(pthresh) | (u32::from(hthresh) << 8) | (u32::from(wthresh) << 16);
+
+    Ok(dctl)
+}
+
+/// Points the rx descriptor `desc` to the buffer at `phys_addr` and clears its status.
+unsafe fn write_rx_desc(desc: *mut RxDesc, phys_addr: usize) {
+    ptr::write_volatile(&mut (*desc).buffer_addr, (phys_addr as u64).to_le());
+    ptr::write_volatile(&mut (*desc).status, 0);
+}
+
+/// Writes a data descriptor for the `len` bytes at `phys_addr` to `desc`.
+unsafe fn write_tx_desc(desc: *mut TxDesc, phys_addr: usize, len: usize, cmd: u8) {
+    ptr::write_volatile(&mut (*desc).buffer_addr, (phys_addr as u64).to_le());
+    ptr::write_volatile(&mut (*desc).length, (len as u16).to_le());
+    ptr::write_volatile(&mut (*desc).cso, 0);
+    ptr::write_volatile(&mut (*desc).cmd, cmd);
+    ptr::write_volatile(&mut (*desc).status, 0);
+    ptr::write_volatile(&mut (*desc).css, 0);
+    ptr::write_volatile(&mut (*desc).special, 0);
+}
+
+/// Returns the status of the rx descriptor at `index` of `queue`.
+unsafe fn rx_desc_status(queue: &E1000RxQueue, index: usize) -> u8 {
+    ptr::read_volatile(&(*queue.descriptors.add(index)).status)
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors returned by ixy.rs.
+#[derive(Debug)]
+pub enum IxyError {
+    /// Hugepage memory could not be allocated, i.e. hugepages are not enabled or none are free.
+    HugepageUnavailable(String),
+
+    /// Setting up the IOMMU via VFIO failed.
+    VfioSetup(io::Error),
+
+    /// The device at the given pci address is in an IOMMU group but not bound to `vfio-pci`.
+    VfioNotBound(String),
+
+    /// The device at the given pci address is not a network card.
+    NotNetworkDevice(String),
+
+    /// There is no driver for the device with the given vendor and device id.
+    UnsupportedDevice { vendor: u16, device: u16 },
+
+    /// Mapping memory for direct memory access failed.
+    DmaMapFailed(io::Error),
+
+    /// A mempool ran out of free packet buffers.
+    PoolExhausted,
+
+    /// The requested configuration is not supported by the device or driver.
+    InvalidConfiguration(String),
+
+    /// The process lacks the permissions or resource limits needed to use the device.
+    InsufficientPrivileges { detail: String },
+
+    /// The device at the given pci address was removed or is in reset, e.g. after a surprise
+    /// hot-unplug or a fatal PCIe error, and no longer responds to register reads.
+    DeviceRemoved(String),
+
+    /// An I/O error occurred while accessing the device or memory.
+    Io(io::Error),
+}
+
+impl fmt::Display for IxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IxyError::HugepageUnavailable(msg) => write!(f, "hugepage unavailable: {}", msg),
+            IxyError::VfioSetup(e) => write!(f, "VFIO setup failed: {}", e),
+            IxyError::VfioNotBound(pci_addr) => {
+                write!(f, "device {} is not bound to vfio-pci", pci_addr)
+            }
+            IxyError::NotNetworkDevice(pci_addr) => {
+                write!(f, "device {} is not a network card", pci_addr)
+            }
+            IxyError::UnsupportedDevice { vendor, device } => write!(
+                f,
+                "no driver for device {:04x}:{:04x} available",
+                vendor, device
+            ),
+            IxyError::DmaMapFailed(e) => write!(
+                f,
+                "failed to map the DMA memory - ulimit set for this user? ({})",
+                e
+            ),
+            IxyError::PoolExhausted => write!(f, "no free buffer in mempool available"),
+            IxyError::InvalidConfiguration(msg) => write!(f, "invalid configuration: {}", msg),
+            IxyError::InsufficientPrivileges { detail } => {
+                write!(f, "insufficient privileges: {}", detail)
+            }
+            IxyError::DeviceRemoved(pci_addr) => write!(f, "device {} was removed", pci_addr),
+            IxyError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for IxyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IxyError::VfioSetup(e) | IxyError::DmaMapFailed(e) | IxyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for IxyError {
+    fn from(e: io::Error) -> Self {
+        IxyError::Io(e)
+    }
+}
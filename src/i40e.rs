@@ -0,0 +1,2603 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES,
+    NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-i40e";
+
+const INTEL_VENDOR_ID: u16 = 0x8086;
+
+const I40E_DEV_ID_SFP_XL710: u16 = 0x1572;
+const I40E_DEV_ID_KX_B: u16 = 0x1580;
+const I40E_DEV_ID_KX_C: u16 = 0x1581;
+const I40E_DEV_ID_QSFP_A: u16 = 0x1583;
+const I40E_DEV_ID_QSFP_B: u16 = 0x1584;
+const I40E_DEV_ID_QSFP_C: u16 = 0x1585;
+const I40E_DEV_ID_10G_BASE_T: u16 = 0x1586;
+const I40E_DEV_ID_10G_BASE_T4: u16 = 0x1589;
+const I40E_DEV_ID_25G_B: u16 = 0x158A;
+const I40E_DEV_ID_25G_SFP28: u16 = 0x158B;
+const I40E_DEV_ID_SFP_X722: u16 = 0x37D0;
+const I40E_DEV_ID_10G_BASE_T_X722: u16 = 0x37D2;
+
+// physical functions of the X710, XL710, XXV710 and the X722 integrated into the C620 chipset
+const SUPPORTED_DEVICE_IDS: [u16; 12] = [
+    I40E_DEV_ID_SFP_XL710,
+    I40E_DEV_ID_KX_B,
+    I40E_DEV_ID_KX_C,
+    I40E_DEV_ID_QSFP_A,
+    I40E_DEV_ID_QSFP_B,
+    I40E_DEV_ID_QSFP_C,
+    I40E_DEV_ID_10G_BASE_T,
+    I40E_DEV_ID_10G_BASE_T4,
+    I40E_DEV_ID_25G_B,
+    I40E_DEV_ID_25G_SFP28,
+    I40E_DEV_ID_SFP_X722,
+    I40E_DEV_ID_10G_BASE_T_X722,
+];
+
+// admin queue registers of the physical function
+pub(crate) const I40E_PF_ATQBAL: u32 = 0x0008_0000;
+pub(crate) const I40E_PF_ARQBAL: u32 = 0x0008_0080;
+pub(crate) const I40E_PF_ATQBAH: u32 = 0x0008_0100;
+pub(crate) const I40E_PF_ARQBAH: u32 = 0x0008_0180;
+pub(crate) const I40E_PF_ATQLEN: u32 = 0x0008_0200;
+pub(crate) const I40E_PF_ARQLEN: u32 = 0x0008_0280;
+pub(crate) const I40E_PF_ATQH: u32 = 0x0008_0300;
+pub(crate) const I40E_PF_ARQH: u32 = 0x0008_0380;
+pub(crate) const I40E_PF_ATQT: u32 = 0x0008_0400;
+pub(crate) const I40E_PF_ARQT: u32 = 0x0008_0480;
+pub(crate) const I40E_PF_ATQLEN_ATQENABLE: u32 = 1 << 31;
+pub(crate) const I40E_PF_ARQLEN_ARQENABLE: u32 = 1 << 31;
+
+// general registers
+const I40E_PFINT_ICR0_ENA: u32 = 0x0003_8800;
+const I40E_PFGEN_CTRL: u32 = 0x0009_2400;
+const I40E_PFGEN_CTRL_PFSWR: u32 = 1 << 0;
+const I40E_PF_FUNC_RID: u32 = 0x0009_C000;
+const I40E_PF_FUNC_RID_FUNCTION_NUMBER_MASK: u32 = 0x7;
+pub(crate) const I40E_GLNVM_ULD: u32 = 0x000B_6008;
+pub(crate) const I40E_GLNVM_ULD_CORER_DONE: u32 = 1 << 3;
+pub(crate) const I40E_GLNVM_ULD_GLOBR_DONE: u32 = 1 << 4;
+pub(crate) const I40E_GLNVM_GENS: u32 = 0x000B_6100;
+pub(crate) const I40E_GLNVM_GENS_SR_SIZE_SHIFT: u32 = 5;
+pub(crate) const I40E_GLNVM_GENS_SR_SIZE_MASK: u32 = 0x7;
+const I40E_GLNVM_SRCTL: u32 = 0x000B_6110;
+const I40E_GLNVM_SRCTL_ADDR_SHIFT: u32 = 14;
+const I40E_GLNVM_SRCTL_START: u32 = 1 << 30;
+const I40E_GLNVM_SRCTL_DONE: u32 = 1 << 31;
+const I40E_GLNVM_SRDATA: u32 = 0x000B_6114;
+const I40E_GLNVM_SRDATA_RDDATA_SHIFT: u32 = 16;
+pub(crate) const I40E_GLGEN_RSTAT: u32 = 0x000B_8188;
+pub(crate) const I40E_GLGEN_RSTAT_DEVSTATE_MASK: u32 = 0x3;
+const I40E_PFLAN_QALLOC: u32 = 0x001C_0400;
+const I40E_PFLAN_QALLOC_FIRSTQ_MASK: u32 = 0x7FF;
+const I40E_PFLAN_QALLOC_LASTQ_SHIFT: u32 = 16;
+const I40E_PFLAN_QALLOC_LASTQ_MASK: u32 = 0x7FF;
+const I40E_PFLAN_QALLOC_VALID: u32 = 1 << 31;
+const I40E_PFGEN_PORTNUM: u32 = 0x001C_0480;
+const I40E_PFGEN_PORTNUM_PORT_NUM_MASK: u32 = 0x3;
+const I40E_GLLAN_RCTL_0: u32 = 0x0012_A500;
+const I40E_GLLAN_RCTL_0_PXE_MODE: u32 = 1 << 0;
+
+// LAN queue registers, indexed by the queue number relative to the physical function
+const I40E_QTX_HEAD_BASE: u32 = 0x000E_4000;
+const I40E_QTX_ENA_BASE: u32 = 0x0010_0000;
+const I40E_QTX_CTL_BASE: u32 = 0x0010_4000;
+const I40E_QTX_TAIL_BASE: u32 = 0x0010_8000;
+const I40E_QRX_ENA_BASE: u32 = 0x0012_0000;
+const I40E_QRX_TAIL_BASE: u32 = 0x0012_8000;
+const I40E_QENA_REQ: u32 = 1 << 0;
+const I40E_QENA_STAT: u32 = 1 << 2;
+const I40E_QTX_CTL_PF_QUEUE: u32 = 0x2;
+const I40E_QTX_CTL_PF_INDX_SHIFT: u32 = 2;
+
+// queue disable requests of the transmit scheduler, indexed by the absolute queue number
+const I40E_GLLAN_TXPRE_QDIS_BASE: u32 = 0x000E_6500;
+const I40E_GLLAN_TXPRE_QDIS_QINDX_MASK: u32 = 0x7FF;
+const I40E_GLLAN_TXPRE_QDIS_SET_QDIS: u32 = 1 << 30;
+const I40E_GLLAN_TXPRE_QDIS_CLEAR_QDIS: u32 = 1 << 31;
+const I40E_QUEUES_PER_TXPRE_QDIS: u32 = 128;
+
+// host memory cache, holds the LAN queue contexts in memory of the driver
+const I40E_PFHMC_SDCMD: u32 = 0x000C_0000;
+const I40E_PFHMC_SDCMD_PMSDWR: u32 = 1 << 31;
+const I40E_PFHMC_SDDATALOW: u32 = 0x000C_0100;
+const I40E_PFHMC_SDDATALOW_PMSDVALID: u32 = 1 << 0;
+const I40E_PFHMC_SDDATALOW_PMSDTYPE_DIRECT: u32 = 1 << 1;
+const I40E_PFHMC_SDDATALOW_PMSDBPCOUNT_SHIFT: u32 = 2;
+const I40E_PFHMC_SDDATAHIGH: u32 = 0x000C_0200;
+const I40E_GLHMC_LANTXOBJSZ: u32 = 0x000C_2004;
+const I40E_GLHMC_LANQMAX: u32 = 0x000C_2008;
+const I40E_GLHMC_LANRXOBJSZ: u32 = 0x000C_200C;
+const I40E_GLHMC_LANTXBASE_BASE: u32 = 0x000C_6200;
+const I40E_GLHMC_LANTXCNT_BASE: u32 = 0x000C_6300;
+const I40E_GLHMC_LANRXBASE_BASE: u32 = 0x000C_6400;
+const I40E_GLHMC_LANRXCNT_BASE: u32 = 0x000C_6500;
+const I40E_GLHMC_FCOEDDPBASE_BASE: u32 = 0x000C_6600;
+const I40E_GLHMC_FCOEDDPCNT_BASE: u32 = 0x000C_6700;
+const I40E_GLHMC_FCOEFBASE_BASE: u32 = 0x000C_6800;
+const I40E_GLHMC_FCOEFCNT_BASE: u32 = 0x000C_6900;
+
+// a direct segment descriptor maps 512 backing pages of 4 KiB, object bases are 512 byte units
+const HMC_BP_COUNT: u32 = 512;
+const HMC_SD_SIZE: usize = 2 * 1024 * 1024;
+const HMC_BASE_UNIT: u32 = 512;
+
+// statistic counters of the port, none of them is cleared on read
+const I40E_GLPRT_GORCL: u32 = 0x0030_0000;
+const I40E_GLPRT_MLFC: u32 = 0x0030_0020;
+const I40E_GLPRT_MRFC: u32 = 0x0030_0040;
+const I40E_GLPRT_CRCERRS: u32 = 0x0030_0080;
+const I40E_GLPRT_RLEC: u32 = 0x0030_00A0;
+const I40E_GLPRT_ILLERRC: u32 = 0x0030_00E0;
+const I40E_GLPRT_RUC: u32 = 0x0030_0100;
+const I40E_GLPRT_ROC: u32 = 0x0030_0120;
+const I40E_GLPRT_LXONRXC: u32 = 0x0030_0140;
+const I40E_GLPRT_LXOFFRXC: u32 = 0x0030_0160;
+const I40E_GLPRT_RFC: u32 = 0x0030_0560;
+const I40E_GLPRT_RJC: u32 = 0x0030_0580;
+const I40E_GLPRT_UPRCL: u32 = 0x0030_05A0;
+const I40E_GLPRT_MPRCL: u32 = 0x0030_05C0;
+const I40E_GLPRT_BPRCL: u32 = 0x0030_05E0;
+const I40E_GLPRT_RDPC: u32 = 0x0030_0600;
+const I40E_GLPRT_GOTCL: u32 = 0x0030_0680;
+const I40E_GLPRT_LXONTXC: u32 = 0x0030_0980;
+const I40E_GLPRT_LXOFFTXC: u32 = 0x0030_09A0;
+const I40E_GLPRT_UPTCL: u32 = 0x0030_09C0;
+const I40E_GLPRT_MPTCL: u32 = 0x0030_09E0;
+const I40E_GLPRT_BPTCL: u32 = 0x0030_0A00;
+const I40E_GLPRT_STRIDE: u32 = 8;
+
+// packet and octet counters are 48 bits wide and split into a low and a high register
+pub(crate) const COUNTER_48_MASK: u64 = (1 << 48) - 1;
+pub(crate) const COUNTER_32_MASK: u64 = (1 << 32) - 1;
+
+// counters of the extended stats with their width in the order of `PortCounters::extended`
+const EXTENDED_COUNTERS: [(u32, u64); 18] = [
+    (I40E_GLPRT_CRCERRS, COUNTER_32_MASK),
+    (I40E_GLPRT_ILLERRC, COUNTER_32_MASK),
+    (I40E_GLPRT_RLEC, COUNTER_32_MASK),
+    (I40E_GLPRT_RUC, COUNTER_32_MASK),
+    (I40E_GLPRT_RFC, COUNTER_32_MASK),
+    (I40E_GLPRT_ROC, COUNTER_32_MASK),
+    (I40E_GLPRT_RJC, COUNTER_32_MASK),
+    (I40E_GLPRT_RDPC, COUNTER_32_MASK),
+    (I40E_GLPRT_BPRCL, COUNTER_48_MASK),
+    (I40E_GLPRT_MPRCL, COUNTER_48_MASK),
+    (I40E_GLPRT_BPTCL, COUNTER_48_MASK),
+    (I40E_GLPRT_MPTCL, COUNTER_48_MASK),
+    (I40E_GLPRT_LXONRXC, COUNTER_32_MASK),
+    (I40E_GLPRT_LXOFFRXC, COUNTER_32_MASK),
+    (I40E_GLPRT_LXONTXC, COUNTER_32_MASK),
+    (I40E_GLPRT_LXOFFTXC, COUNTER_32_MASK),
+    (I40E_GLPRT_MLFC, COUNTER_32_MASK),
+    (I40E_GLPRT_MRFC, COUNTER_32_MASK),
+];
+
+// admin queue descriptor flags
+pub(crate) const I40E_AQ_FLAG_DD: u16 = 1 << 0;
+pub(crate) const I40E_AQ_FLAG_ERR: u16 = 1 << 2;
+pub(crate) const I40E_AQ_FLAG_LB: u16 = 1 << 9;
+pub(crate) const I40E_AQ_FLAG_RD: u16 = 1 << 10;
+pub(crate) const I40E_AQ_FLAG_BUF: u16 = 1 << 12;
+pub(crate) const I40E_AQ_FLAG_SI: u16 = 1 << 13;
+
+// admin queue commands
+pub(crate) const I40E_AQC_OPC_GET_VERSION: u16 = 0x0001;
+pub(crate) const I40E_AQC_OPC_QUEUE_SHUTDOWN: u16 = 0x0003;
+pub(crate) const I40E_AQC_OPC_MAC_ADDRESS_READ: u16 = 0x0107;
+pub(crate) const I40E_AQC_OPC_MAC_ADDRESS_WRITE: u16 = 0x0108;
+pub(crate) const I40E_AQC_OPC_CLEAR_PXE_MODE: u16 = 0x0110;
+pub(crate) const I40E_AQC_OPC_GET_SWITCH_CONFIG: u16 = 0x0200;
+pub(crate) const I40E_AQC_OPC_UPDATE_VSI_PARAMETERS: u16 = 0x0211;
+const I40E_AQC_OPC_GET_VSI_PARAMETERS: u16 = 0x0212;
+const I40E_AQC_OPC_ADD_MACVLAN: u16 = 0x0250;
+const I40E_AQC_OPC_REMOVE_MACVLAN: u16 = 0x0251;
+const I40E_AQC_OPC_SET_VSI_PROMISCUOUS_MODES: u16 = 0x0254;
+const I40E_AQC_OPC_GET_PHY_ABILITIES: u16 = 0x0600;
+const I40E_AQC_OPC_SET_PHY_CONFIG: u16 = 0x0601;
+pub(crate) const I40E_AQC_OPC_SET_MAC_CONFIG: u16 = 0x0603;
+pub(crate) const I40E_AQC_OPC_SET_LINK_RESTART_AN: u16 = 0x0605;
+pub(crate) const I40E_AQC_OPC_GET_LINK_STATUS: u16 = 0x0607;
+
+pub(crate) const I40E_AQ_DRIVER_UNLOADING: u32 = 0x1;
+pub(crate) const I40E_AQ_CLEAR_PXE_RX_CNT: u8 = 0x2;
+const I40E_AQC_LAN_ADDR_VALID: u16 = 0x10;
+const I40E_AQC_WRITE_TYPE_LAA_ONLY: u16 = 0x0000;
+const I40E_AQ_SW_ELEM_TYPE_VSI: u8 = 19;
+const I40E_AQC_VSI_PROM_CMD_SEID_MASK: u16 = 0x3FF;
+const I40E_AQC_SET_VSI_PROMISC_UNICAST: u16 = 0x01;
+const I40E_AQC_SET_VSI_PROMISC_MULTICAST: u16 = 0x02;
+const I40E_AQC_SET_VSI_PROMISC_BROADCAST: u16 = 0x04;
+const I40E_AQC_MACVLAN_CMD_SEID_VALID: u16 = 0x8000;
+const I40E_AQC_MACVLAN_ADD_PERFECT_MATCH: u16 = 0x0001;
+const I40E_AQC_MACVLAN_ADD_IGNORE_VLAN: u16 = 0x0004;
+const I40E_AQC_MACVLAN_DEL_PERFECT_MATCH: u8 = 0x01;
+const I40E_AQC_MACVLAN_DEL_IGNORE_VLAN: u8 = 0x08;
+const I40E_AQ_PHY_FLAG_PAUSE_TX: u8 = 0x01;
+const I40E_AQ_PHY_FLAG_PAUSE_RX: u8 = 0x02;
+const I40E_AQ_PHY_ENABLE_ATOMIC_LINK: u8 = 0x20;
+const I40E_AQ_SET_MAC_CONFIG_CRC_EN: u8 = 0x04;
+const I40E_AQ_PHY_RESTART_AN: u8 = 0x02;
+const I40E_AQ_PHY_LINK_ENABLE: u8 = 0x04;
+const I40E_AQ_LINK_UP: u8 = 0x01;
+const I40E_AQ_LINK_PAUSE_TX: u8 = 0x20;
+const I40E_AQ_LINK_PAUSE_RX: u8 = 0x40;
+
+const I40E_LINK_SPEED_100MB: u8 = 1 << 1;
+const I40E_LINK_SPEED_1GB: u8 = 1 << 2;
+const I40E_LINK_SPEED_10GB: u8 = 1 << 3;
+const I40E_LINK_SPEED_40GB: u8 = 1 << 4;
+const I40E_LINK_SPEED_20GB: u8 = 1 << 5;
+const I40E_LINK_SPEED_25GB: u8 = 1 << 6;
+
+// layout of the VSI properties exchanged with get and update VSI parameters
+pub(crate) const VSI_PROPERTIES_SIZE: usize = 128;
+pub(crate) const VSI_PROP_VALID_SECTIONS: usize = 0;
+pub(crate) const VSI_PROP_MAPPING_FLAGS: usize = 28;
+pub(crate) const VSI_PROP_QUEUE_MAPPING: usize = 30;
+pub(crate) const VSI_PROP_TC_MAPPING: usize = 62;
+const VSI_PROP_QS_HANDLE: usize = 96;
+pub(crate) const I40E_AQ_VSI_PROP_QUEUE_MAP_VALID: u16 = 0x0040;
+pub(crate) const I40E_AQ_VSI_QUE_MAP_CONTIG: u16 = 0x0;
+const I40E_AQ_VSI_TC_QUE_NUMBER_SHIFT: u16 = 9;
+
+// layout of the switch configuration, a header followed by 16 byte elements
+const SWITCH_CONFIG_HEADER_SIZE: usize = 16;
+const SWITCH_CONFIG_ELEMENT_SIZE: usize = 16;
+
+// shadow RAM words, the checksum covers everything but the VPD and PCIe ALT modules
+const I40E_SR_VPD_PTR: u16 = 0x2F;
+const I40E_SR_PCIE_ALT_AUTO_LOAD_PTR: u16 = 0x3E;
+const I40E_SR_SW_CHECKSUM_WORD: u16 = 0x3F;
+const I40E_SR_MODULE_MAX_SIZE: u32 = 1024;
+const I40E_SR_WORDS_IN_1KB: u32 = 512;
+const I40E_SR_SUM: u16 = 0xBABA;
+
+const I40E_RXD_STATUS_DD: u64 = 1 << 0;
+const I40E_RXD_STATUS_EOP: u64 = 1 << 1;
+const I40E_RXD_LENGTH_SHIFT: u64 = 38;
+const I40E_RXD_LENGTH_MASK: u64 = 0x3FFF;
+
+const I40E_TX_DESC_DTYPE_DATA: u64 = 0x0;
+const I40E_TX_DESC_DTYPE_MASK: u64 = 0xF;
+const I40E_TX_DESC_DTYPE_DESC_DONE: u64 = 0xF;
+const I40E_TXD_CMD_SHIFT: u64 = 4;
+const I40E_TXD_CMD_EOP: u64 = 0x1;
+const I40E_TXD_CMD_RS: u64 = 0x2;
+const I40E_TXD_CMD_ICRC: u64 = 0x4;
+const I40E_TXD_BUF_SZ_SHIFT: u64 = 34;
+
+// the context of a queue points to its ring in units of 128 bytes
+pub(crate) const I40E_QUEUE_BASE_SHIFT: u32 = 7;
+pub(crate) const I40E_RXQ_CTX_DBUFF_SHIFT: u32 = 7;
+pub(crate) const I40E_MAX_CHAINED_RX_BUFFERS: usize = 5;
+pub(crate) const I40E_MAX_FRAME_SIZE: usize = 9728;
+
+pub(crate) const AQ_LEN: usize = 64;
+pub(crate) const AQ_BUF_SIZE: usize = 4096;
+pub(crate) const AQ_LARGE_BUF: usize = 512;
+pub(crate) const AQ_TIMEOUT: Duration = Duration::from_secs(1);
+pub(crate) const AQ_POLL_DELAY: Duration = Duration::from_micros(100);
+
+// the admin queues, the command buffer and the event buffers share a huge page
+pub(crate) const ATQ_OFFSET: usize = 0;
+pub(crate) const ARQ_OFFSET: usize = ATQ_OFFSET + AQ_LEN * mem::size_of::<AqDesc>();
+pub(crate) const AQ_BUF_OFFSET: usize = ARQ_OFFSET + AQ_LEN * mem::size_of::<AqDesc>();
+pub(crate) const ARQ_BUFS_OFFSET: usize = AQ_BUF_OFFSET + AQ_BUF_SIZE;
+pub(crate) const AQ_MEM_SIZE: usize = ARQ_BUFS_OFFSET + AQ_LEN * AQ_BUF_SIZE;
+
+pub(crate) const RESET_TIMEOUT: Duration = Duration::from_secs(2);
+pub(crate) const RESET_POLL_DELAY: Duration = Duration::from_millis(1);
+pub(crate) const QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+pub(crate) const QUEUE_POLL_DELAY: Duration = Duration::from_micros(10);
+
+const NUM_UNICAST_FILTERS: usize = 64;
+
+pub(crate) const TX_MAX_SEGMENTS: usize = 8;
+const TX_CLEAN_BATCH: usize = 32;
+
+const I40E_FAILED_READ_REG: u32 = 0xFFFF_FFFF;
+
+/// Driver for the physical functions of the X710/XL710 family of 10 and 40 GbE network cards,
+/// see the Intel Ethernet Controller X710/XXV710/XL710 datasheet.
+///
+/// The firmware owns the port and the switch, the driver configures both via the admin queue
+/// and programs the contexts of its LAN queues into a host memory cache.
+pub struct I40eDevice {
+    pci_addr: String,
+    addr: *mut u8,
+    len: usize,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<I40eRxQueue>,
+    tx_queues: Vec<I40eTxQueue>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    admin_queue: RefCell<AdminQueue>,
+    hmc: Option<Hmc>,
+    pf_id: u32,
+    port: u32,
+    base_queue: u32,
+    max_queues: u16,
+    vsi_seid: u16,
+    qs_handle: u16,
+    fw_version: u32,
+    crc_strip: bool,
+    mac: Cell<[u8; 6]>,
+    unicast_filters: RefCell<Vec<Option<[u8; 6]>>>,
+    counters: Cell<PortCounters>,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// Values of the statistic counters of the port at their last read.
+#[derive(Clone, Copy, Default)]
+struct PortCounters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    extended: [u64; EXTENDED_COUNTERS.len()],
+}
+
+/// Admin queue descriptor, the parameters of direct commands are stored inline while indirect
+/// commands put the address of their buffer into the last 8 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct AqDesc {
+    pub(crate) flags: u16,
+    pub(crate) opcode: u16,
+    pub(crate) datalen: u16,
+    pub(crate) retval: u16,
+    pub(crate) cookie_high: u32,
+    pub(crate) cookie_low: u32,
+    pub(crate) params: [u8; 16],
+}
+
+impl AqDesc {
+    /// Returns a descriptor for command `opcode` without parameters.
+    pub(crate) fn new(opcode: u16) -> AqDesc {
+        AqDesc {
+            opcode,
+            ..Default::default()
+        }
+    }
+}
+
+/// Send and receive queue to the firmware with the memory of their buffers.
+pub(crate) struct AdminQueue {
+    // keeps the rings and buffers mapped
+    pub(crate) mem: Dma<u8>,
+    pub(crate) next: usize,
+}
+
+impl AdminQueue {
+    pub(crate) fn atq(&self) -> *mut AqDesc {
+        unsafe { self.mem.virt.add(ATQ_OFFSET) as *mut AqDesc }
+    }
+
+    pub(crate) fn arq(&self) -> *mut AqDesc {
+        unsafe { self.mem.virt.add(ARQ_OFFSET) as *mut AqDesc }
+    }
+
+    pub(crate) fn buffer(&self) -> *mut u8 {
+        unsafe { self.mem.virt.add(AQ_BUF_OFFSET) }
+    }
+}
+
+/// Backing memory of the host memory cache and the location of the LAN queue contexts in it.
+struct Hmc {
+    // keeps the backing page mapped
+    mem: Dma<u8>,
+    tx_base: usize,
+    tx_obj_size: usize,
+    rx_base: usize,
+    rx_obj_size: usize,
+}
+
+impl Hmc {
+    /// Writes the packed queue context `ctx` of an object at `base` + `index` * `size`.
+    fn write_ctx(&self, base: usize, size: usize, index: usize, ctx: &[u8]) {
+        assert!(ctx.len() <= size, "queue context exceeds its object");
+
+        unsafe {
+            let dst = self.mem.virt.add(base + index * size);
+            memset(dst, size, 0);
+            for (i, &b) in ctx.iter().enumerate() {
+                ptr::write_volatile(dst.add(i), b);
+            }
+        }
+    }
+}
+
+/// Receive descriptor in the 32 byte format, the device writes the status, error and length
+/// of a packet into the second quad word.
+#[repr(C)]
+#[allow(dead_code)]
+pub(crate) struct RxDesc {
+    pub(crate) pkt_addr: u64,
+    pub(crate) hdr_addr: u64,
+    rsvd1: u64,
+    rsvd2: u64,
+}
+
+/// Transmit data descriptor, the device sets the descriptor type to done after sending it.
+#[repr(C)]
+#[allow(dead_code)]
+pub(crate) struct TxDesc {
+    buffer_addr: u64,
+    cmd_type_offset_bsz: u64,
+}
+
+struct I40eRxQueue {
+    descriptors: *mut RxDesc,
+    // keeps the descriptor ring mapped
+    ring: Dma<RxDesc>,
+    num_descriptors: usize,
+    pool: Arc<Mempool>,
+    bufs_in_use: Vec<usize>,
+    rx_index: usize,
+    rx_tail: usize,
+    num_posted: usize,
+}
+
+impl I40eRxQueue {
+    /// Returns a queue on the descriptor ring `ring` that receives into buffers of `pool`.
+    fn new(ring: Dma<RxDesc>, pool: Arc<Mempool>) -> I40eRxQueue {
+        I40eRxQueue {
+            descriptors: ring.virt,
+            ring,
+            num_descriptors: NUM_RX_QUEUE_ENTRIES,
+            pool,
+            bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
+            rx_index: 0,
+            rx_tail: 0,
+            num_posted: NUM_RX_QUEUE_ENTRIES - 1,
+        }
+    }
+
+    /// Attaches a buffer of the mempool to every descriptor and returns the tail that posts
+    /// `num_posted` of them to the device.
+    fn fill(&mut self) -> Result<usize, IxyError> {
+        for i in 0..self.num_descriptors {
+            let buf = self.pool.alloc_buf().ok_or(IxyError::PoolExhausted)?;
+
+            unsafe {
+                write_rx_desc(self.descriptors.add(i), self.pool.get_phys_addr(buf));
+            }
+
+            // we need to remember which descriptor entry belongs to which mempool entry
+            self.bufs_in_use.push(buf);
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = self.num_posted;
+
+        Ok(self.rx_tail)
+    }
+
+    /// Returns the size of the buffers the device may write to, the mempool's entries without
+    /// the headroom in units of 128 bytes.
+    fn buffer_size(&self) -> usize {
+        let data_size = (self.pool.entry_size() - self.pool.headroom()).min(MAX_RX_BUFFER_SIZE);
+
+        data_size & !((1 << I40E_RXQ_CTX_DBUFF_SHIFT) - 1)
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills their
+    /// descriptors. Returns the number of received packets and the new tail of the ring if
+    /// refilled descriptors have to be handed back to the device.
+    fn receive(
+        &mut self,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> (usize, Option<usize>) {
+        let mut rx_index = self.rx_index;
+        let mut received_packets = 0;
+
+        {
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
+
+            while received_packets < num_packets {
+                // frames larger than a buffer span several descriptors, only take them once the
+                // device has written back all of them
+                let mut num_segments = 0;
+                let mut status = 0;
+                while (status & I40E_RXD_STATUS_EOP) == 0 {
+                    let index = (rx_index + num_segments) % self.num_descriptors;
+                    status = unsafe { rx_desc_status(self, index) };
+
+                    if (status & I40E_RXD_STATUS_DD) == 0 {
+                        break;
+                    }
+                    num_segments += 1;
+                }
+
+                if (status & I40E_RXD_STATUS_DD) == 0 {
+                    break;
+                }
+
+                // the frame stays in the ring until the descriptors can be refilled without
+                // the buffers reserved for clones
+                if free_stack.available() < num_segments {
+                    break;
+                }
+
+                let pool = &self.pool;
+                let mut packet: Option<Packet> = None;
+
+                for _ in 0..num_segments {
+                    let status = unsafe { rx_desc_status(self, rx_index) };
+
+                    // replace currently used buffer with a free buffer of the mempool
+                    let new_buf = free_stack.pop().expect("no buffer available");
+                    let buf = mem::replace(&mut self.bufs_in_use[rx_index], new_buf);
+
+                    let p = unsafe {
+                        Packet {
+                            addr_virt: pool.get_virt_addr(buf),
+                            addr_phys: pool.get_phys_addr(buf),
+                            len: ((status >> I40E_RXD_LENGTH_SHIFT) & I40E_RXD_LENGTH_MASK)
+                                as usize,
+                            pool: pool.clone(),
+                            pool_entry: buf,
+                            next: None,
+                            meta: PacketMeta::default(),
+                        }
+                    };
+
+                    match packet {
+                        Some(ref mut packet) => packet.chain(p),
+                        None => packet = Some(p),
+                    }
+
+                    unsafe {
+                        write_rx_desc(self.descriptors.add(rx_index), pool.get_phys_addr(new_buf));
+                    }
+
+                    rx_index = (rx_index + 1) % self.num_descriptors;
+                }
+
+                #[allow(unused_mut)]
+                let mut p = packet.unwrap();
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                buffer.push_back(p);
+                received_packets += 1;
+            }
+        }
+
+        let mut tail = None;
+
+        if rx_index != self.rx_index {
+            self.rx_index = rx_index;
+
+            // hand refilled descriptors back to the device, at most num_posted at a time
+            let posted = (self.rx_tail + self.num_descriptors - rx_index) % self.num_descriptors;
+            if posted < self.num_posted {
+                self.rx_tail = (rx_index + self.num_posted) % self.num_descriptors;
+                tail = Some(self.rx_tail);
+            }
+        }
+
+        (received_packets, tail)
+    }
+
+    /// Returns the content of the next received packet without taking it off the ring.
+    fn peek(&self) -> Option<&[u8]> {
+        if self.bufs_in_use.is_empty() {
+            return None;
+        }
+
+        let status = unsafe { rx_desc_status(self, self.rx_index) };
+        if (status & I40E_RXD_STATUS_DD) == 0 {
+            return None;
+        }
+
+        let len = ((status >> I40E_RXD_LENGTH_SHIFT) & I40E_RXD_LENGTH_MASK) as usize;
+
+        unsafe {
+            let addr = self.pool.get_virt_addr(self.bufs_in_use[self.rx_index]);
+
+            Some(slice::from_raw_parts(addr, len))
+        }
+    }
+
+    /// Limits the descriptors the device may receive into to `num_posted`, returns the new tail
+    /// if more descriptors have to be handed to the device right away.
+    fn set_posted(&mut self, num_posted: usize) -> Result<Option<usize>, IxyError> {
+        if num_posted >= self.num_descriptors {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                self.num_descriptors,
+                self.num_descriptors - 1
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // the tail never moves backwards, lowering the limit takes effect as packets arrive
+        let posted = (self.rx_tail + self.num_descriptors - self.rx_index) % self.num_descriptors;
+        if posted >= num_posted || self.bufs_in_use.is_empty() {
+            return Ok(None);
+        }
+
+        self.rx_tail = (self.rx_index + num_posted) % self.num_descriptors;
+
+        Ok(Some(self.rx_tail))
+    }
+
+    /// Returns all buffers of the ring to the mempool, the device must not access them anymore.
+    fn drain(&mut self) {
+        for buf in self.bufs_in_use.drain(..) {
+            self.pool.free_buf(buf);
+        }
+
+        // clear the descriptor done bits so rx_batch doesn't pick up the freed buffers
+        unsafe {
+            memset(
+                self.descriptors as *mut u8,
+                self.num_descriptors * mem::size_of::<RxDesc>(),
+                0x00,
+            );
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = 0;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<RxDesc>(),
+        )
+    }
+}
+
+pub(crate) struct I40eTxQueue {
+    descriptors: *mut TxDesc,
+    // keeps the descriptor ring mapped
+    ring: Dma<TxDesc>,
+    num_descriptors: usize,
+    pool: Option<Arc<Mempool>>,
+    bufs_in_use: VecDeque<usize>,
+    clean_index: usize,
+    tx_index: usize,
+    watchdog_head: usize,
+    watchdog_time: Instant,
+}
+
+impl I40eTxQueue {
+    /// Returns an empty queue on the descriptor ring `ring`.
+    pub(crate) fn new(ring: Dma<TxDesc>) -> I40eTxQueue {
+        I40eTxQueue {
+            descriptors: ring.virt,
+            ring,
+            num_descriptors: NUM_TX_QUEUE_ENTRIES,
+            pool: None,
+            bufs_in_use: VecDeque::with_capacity(NUM_TX_QUEUE_ENTRIES),
+            clean_index: 0,
+            tx_index: 0,
+            watchdog_head: 0,
+            watchdog_time: Instant::now(),
+        }
+    }
+
+    /// Pops as many packets as fit into the ring from `packets` and writes their descriptors,
+    /// the device only sends them once the tail is moved to `tail`.
+    pub(crate) fn send(&mut self, packets: &mut VecDeque<Packet>) -> usize {
+        let mut sent = 0;
+        let mut cur_index = self.tx_index;
+        let clean_index = self.clean();
+
+        if self.pool.is_none() {
+            if let Some(packet) = packets.front() {
+                self.pool = Some(packet.pool.clone());
+            }
+        }
+
+        while let Some(packet) = packets.pop_front() {
+            let num_segments = packet.num_segments();
+
+            assert!(
+                packet
+                    .segments()
+                    .all(|s| Arc::ptr_eq(self.pool.as_ref().unwrap(), &s.pool)),
+                "distinct memory pools for a single tx queue are not supported yet"
+            );
+            assert!(
+                num_segments <= TX_MAX_SEGMENTS,
+                "packet of {} segments exceeds the limit of {}",
+                num_segments,
+                TX_MAX_SEGMENTS
+            );
+
+            // one descriptor always stays empty to tell a full ring from an empty one
+            let free = (clean_index + self.num_descriptors - cur_index - 1) % self.num_descriptors;
+
+            if free < num_segments {
+                // tx queue of device is full, push packet back onto the
+                // queue of to-be-sent packets
+                packets.push_front(packet);
+                break;
+            }
+
+            let mut segment = Some(packet);
+
+            while let Some(mut p) = segment {
+                segment = p.unchain();
+
+                // every descriptor reports its status, clean checks arbitrary descriptors
+                let mut cmd = I40E_TXD_CMD_RS | I40E_TXD_CMD_ICRC;
+                if segment.is_none() {
+                    cmd |= I40E_TXD_CMD_EOP;
+                }
+
+                unsafe {
+                    write_tx_desc(
+                        self.descriptors.add(cur_index),
+                        p.get_phys_addr(),
+                        p.len(),
+                        cmd,
+                    );
+                }
+
+                self.bufs_in_use.push_back(p.pool_entry);
+                mem::forget(p);
+
+                cur_index = (cur_index + 1) % self.num_descriptors;
+            }
+
+            self.tx_index = cur_index;
+            sent += 1;
+        }
+
+        sent
+    }
+
+    /// Returns sent buffers to their mempool in multiples of `TX_CLEAN_BATCH` and returns the
+    /// new clean index.
+    fn clean(&mut self) -> usize {
+        let mut clean_index = self.clean_index;
+
+        loop {
+            let cleanable =
+                (self.tx_index + self.num_descriptors - clean_index) % self.num_descriptors;
+
+            if cleanable < TX_CLEAN_BATCH {
+                break;
+            }
+
+            let cleanup_to = (clean_index + TX_CLEAN_BATCH - 1) % self.num_descriptors;
+
+            let qword = u64::from_le(unsafe {
+                ptr::read_volatile(&(*self.descriptors.add(cleanup_to)).cmd_type_offset_bsz)
+            });
+
+            if (qword & I40E_TX_DESC_DTYPE_MASK) != I40E_TX_DESC_DTYPE_DESC_DONE {
+                break;
+            }
+
+            if let Some(ref pool) = self.pool {
+                pool.free_bufs(self.bufs_in_use.drain(..TX_CLEAN_BATCH));
+            }
+
+            clean_index = (cleanup_to + 1) % self.num_descriptors;
+        }
+
+        self.clean_index = clean_index;
+
+        clean_index
+    }
+
+    /// Returns the index after the last written descriptor, i.e. the tail for the device.
+    pub(crate) fn tail(&self) -> usize {
+        self.tx_index
+    }
+
+    /// Returns whether the ring is empty or the device moved its `head` within `timeout`.
+    pub(crate) fn healthy(&mut self, head: usize, timeout: Duration) -> bool {
+        if head == self.tx_index || head != self.watchdog_head {
+            self.watchdog_head = head;
+            self.watchdog_time = Instant::now();
+
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Drops all pending packets and rewinds the ring, the device must not access it anymore.
+    pub(crate) fn reset(&mut self) {
+        self.release_sent();
+
+        unsafe {
+            memset(
+                self.descriptors as *mut u8,
+                self.num_descriptors * mem::size_of::<TxDesc>(),
+                0x00,
+            );
+        }
+
+        self.clean_index = 0;
+        self.tx_index = 0;
+        self.watchdog_head = 0;
+        self.watchdog_time = Instant::now();
+    }
+
+    /// Returns all buffers to their mempool once the device's head caught up with the tail.
+    pub(crate) fn release_sent(&mut self) {
+        if let Some(ref pool) = self.pool {
+            for buf in self.bufs_in_use.drain(..) {
+                pool.free_buf(buf);
+            }
+        }
+
+        self.clean_index = self.tx_index;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    pub(crate) fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<TxDesc>(),
+        )
+    }
+}
+
+impl IxyDevice for I40eDevice {
+    /// Returns an initialized `I40eDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<I40eDevice, IxyError> {
+        I40eDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac.get()
+    }
+
+    /// Sets the mac address of this device, the switch forwards packets to the new address
+    /// instead of the old one.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        let old = self.mac.get();
+
+        let result = self
+            .remove_macvlan(old)
+            .and_then(|_| self.add_macvlan(mac))
+            .and_then(|_| self.write_mac_addr(mac));
+
+        match result {
+            Ok(()) => {
+                self.mac.set(mac);
+                self.unicast_filters.borrow_mut()[0] = Some(mac);
+            }
+            Err(e) => warn!("failed to set mac address of {}: {}", self.pci_addr, e),
+        }
+    }
+
+    /// Adds a perfect match filter for `mac` to the switch and returns its index.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        let index = (1..NUM_UNICAST_FILTERS)
+            .find(|&i| self.unicast_filters.borrow()[i].is_none())
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration("all unicast filters are in use".to_string())
+            })?;
+
+        self.add_macvlan(mac)?;
+        self.unicast_filters.borrow_mut()[index] = Some(mac);
+
+        Ok(index)
+    }
+
+    /// Removes the unicast filter `index` from the switch.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        let mac = match self.unicast_filters.borrow().get(index) {
+            Some(&Some(mac)) if index > 0 => mac,
+            _ => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "unicast filter {} is not in use",
+                    index
+                )))
+            }
+        };
+
+        self.remove_macvlan(mac)?;
+        self.unicast_filters.borrow_mut()[index] = None;
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (received, tail) = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if let Some(tail) = tail {
+            self.set_reg32(I40E_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the device was removed, i.e. its reset status register reads as all
+    /// ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(I40E_GLGEN_RSTAT) == I40E_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(num_posted)? {
+            self.set_reg32(I40E_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(
+                I40E_QTX_TAIL_BASE + 4 * queue_id,
+                self.tx_queues[queue_id as usize].tail() as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of the port of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_pkts += current.rx_pkts.wrapping_sub(last.rx_pkts) & COUNTER_48_MASK;
+        stats.tx_pkts += current.tx_pkts.wrapping_sub(last.tx_pkts) & COUNTER_48_MASK;
+        stats.rx_bytes += current.rx_bytes.wrapping_sub(last.rx_bytes) & COUNTER_48_MASK;
+        stats.tx_bytes += current.tx_bytes.wrapping_sub(last.tx_bytes) & COUNTER_48_MASK;
+
+        // the extended counters belong to the extended stats
+        self.counters.set(PortCounters {
+            extended: last.extended,
+            ..current
+        });
+    }
+
+    /// Resets the stats of the port of this device.
+    fn reset_stats(&self) {
+        self.counters.set(self.read_counters());
+    }
+
+    /// Reads the extended stats of the port of this device into `stats`.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        let mut last = self.counters.get();
+        let mut delta = [0; EXTENDED_COUNTERS.len()];
+
+        for (i, &(reg, mask)) in EXTENDED_COUNTERS.iter().enumerate() {
+            let value = self.read_counter(reg, mask);
+            delta[i] = value.wrapping_sub(last.extended[i]) & mask;
+            last.extended[i] = value;
+        }
+
+        stats.rx_crc_errors += delta[0];
+        stats.rx_illegal_byte_errors += delta[1];
+        stats.rx_length_errors += delta[2];
+        stats.rx_undersize += delta[3];
+        stats.rx_fragments += delta[4];
+        stats.rx_oversize += delta[5];
+        stats.rx_jabbers += delta[6];
+        stats.rx_missed += delta[7];
+        stats.rx_broadcast += delta[8];
+        stats.rx_multicast += delta[9];
+        stats.tx_broadcast += delta[10];
+        stats.tx_multicast += delta[11];
+        stats.rx_xon += delta[12];
+        stats.rx_xoff += delta[13];
+        stats.tx_xon += delta[14];
+        stats.tx_xoff += delta[15];
+        stats.mac_local_faults += delta[16];
+        stats.mac_remote_faults += delta[17];
+
+        self.counters.set(last);
+    }
+
+    /// Returns the link speed of this device as reported by the firmware.
+    fn get_link_speed(&self) -> u32 {
+        let status = match self.aq_command(AqDesc::new(I40E_AQC_OPC_GET_LINK_STATUS), None) {
+            Ok(desc) => desc,
+            Err(e) => {
+                warn!("failed to get link status of {}: {}", self.pci_addr, e);
+                return 0;
+            }
+        };
+
+        if (status.params[4] & I40E_AQ_LINK_UP) == 0 {
+            return 0;
+        }
+
+        match status.params[3] {
+            I40E_LINK_SPEED_100MB => 100,
+            I40E_LINK_SPEED_1GB => 1000,
+            I40E_LINK_SPEED_10GB => 10000,
+            I40E_LINK_SPEED_20GB => 20000,
+            I40E_LINK_SPEED_25GB => 25000,
+            I40E_LINK_SPEED_40GB => 40000,
+            _ => 0,
+        }
+    }
+
+    /// Returns the PCIe link of this device.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        self.check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        while self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues -= 1;
+            self.stop_rx_queue(u32::from(self.num_rx_queues))?;
+            self.rx_queues.pop();
+        }
+
+        while self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues -= 1;
+            self.stop_tx_queue(u32::from(self.num_tx_queues))?;
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                queue.reset();
+            }
+        }
+
+        while self.num_rx_queues < num_rx_queues {
+            let queue_id = u32::from(self.num_rx_queues);
+            self.init_rx_queue(queue_id)?;
+            self.start_rx_queue(queue_id)?;
+            self.num_rx_queues += 1;
+        }
+
+        while self.num_tx_queues < num_tx_queues {
+            let queue_id = u32::from(self.num_tx_queues);
+            self.init_tx_queue(queue_id)?;
+            self.start_tx_queue(queue_id)?;
+            self.num_tx_queues += 1;
+        }
+
+        self.update_vsi_queues()
+    }
+
+    /// Enables rx queue `queue_id`.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].bufs_in_use.is_empty() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        self.enable_queue(I40E_QRX_ENA_BASE + 4 * queue_id)
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id)
+    }
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(I40E_QRX_ENA_BASE + 4 * queue_id) & I40E_QENA_STAT) != 0
+    }
+
+    /// Enables tx queue `queue_id`.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        debug!("enabling tx queue {}", queue_id);
+
+        self.set_tx_queue_disable(queue_id, false);
+        self.enable_queue(I40E_QTX_ENA_BASE + 4 * queue_id)
+    }
+
+    /// Disables tx queue `queue_id`, packets that have not been sent yet stay in the ring.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue(queue_id)
+    }
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+            && (self.get_reg32(I40E_QTX_ENA_BASE + 4 * queue_id) & I40E_QENA_STAT) != 0
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.get_reg32(I40E_QTX_HEAD_BASE + 4 * queue_id) as usize;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Disables tx queue `queue_id`, drops all pending packets and enables it again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue(queue_id)?;
+        self.tx_queues[queue_id as usize].reset();
+
+        // the head is part of the queue context, rewrite it to start at the first descriptor
+        self.start_tx_queue(queue_id)
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.get_reg32(I40E_QTX_HEAD_BASE + 4 * queue_id) as usize
+            != self.tx_queues[queue_id as usize].tail()
+        {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// The X710 always drops packets for an rx queue without free descriptors.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} cannot keep packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the pause frames the PHY advertises, the firmware restarts auto-negotiation.
+    fn set_flow_control(&self, mode: FlowControl) {
+        info!("setting flow control mode to {:?}", mode);
+
+        let pause = match mode {
+            FlowControl::None => 0,
+            FlowControl::RxPause => I40E_AQ_PHY_FLAG_PAUSE_RX,
+            FlowControl::TxPause => I40E_AQ_PHY_FLAG_PAUSE_TX,
+            FlowControl::Full => I40E_AQ_PHY_FLAG_PAUSE_RX | I40E_AQ_PHY_FLAG_PAUSE_TX,
+        };
+
+        if let Err(e) = self.set_phy_pause(pause) {
+            warn!("failed to set flow control of {}: {}", self.pci_addr, e);
+        }
+    }
+
+    /// Returns the flow control mode negotiated on the link.
+    fn get_flow_control(&self) -> FlowControl {
+        let an_info = match self.aq_command(AqDesc::new(I40E_AQC_OPC_GET_LINK_STATUS), None) {
+            Ok(desc) => desc.params[5],
+            Err(e) => {
+                warn!("failed to get link status of {}: {}", self.pci_addr, e);
+                return FlowControl::None;
+            }
+        };
+
+        let rx_pause = (an_info & I40E_AQ_LINK_PAUSE_RX) != 0;
+        let tx_pause = (an_info & I40E_AQ_LINK_PAUSE_TX) != 0;
+
+        match (rx_pause, tx_pause) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Sets whether the rx queues strip the Ethernet FCS, the setting is part of the queue
+    /// contexts so all rx queues are drained and restarted.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        self.crc_strip = enable;
+
+        for queue_id in 0..u32::from(self.num_rx_queues) {
+            self.drain_rx_queue(queue_id)?;
+            self.start_rx_queue(queue_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.crc_strip
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    ///
+    /// The X710 receives into buffers of a multiple of 128 bytes, `buffer_size` is rounded up
+    /// accordingly.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        let buffer_size = buffer_size.next_multiple_of(1 << I40E_RXQ_CTX_DBUFF_SHIFT);
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].pool = pool;
+
+        self.start_rx_queue(queue_id)
+    }
+
+    /// Reads the 16 bit word at `offset` of this device's shadow RAM, i.e. the NVM contents
+    /// loaded by the device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the shadow RAM.
+    fn read_eeprom_word(&self, offset: u16) -> u16 {
+        assert!(
+            u32::from(offset) < self.shadow_ram_words(),
+            "eeprom access out of bounds"
+        );
+
+        self.wait_shadow_ram();
+        self.set_reg32(
+            I40E_GLNVM_SRCTL,
+            (u32::from(offset) << I40E_GLNVM_SRCTL_ADDR_SHIFT) | I40E_GLNVM_SRCTL_START,
+        );
+        self.wait_shadow_ram();
+
+        (self.get_reg32(I40E_GLNVM_SRDATA) >> I40E_GLNVM_SRDATA_RDDATA_SHIFT) as u16
+    }
+
+    /// Returns the version of the firmware, the major version in the upper 16 bits.
+    fn get_firmware_version(&self) -> u32 {
+        self.fw_version
+    }
+
+    /// Returns whether the checksum stored in this device's shadow RAM is valid, i.e. all
+    /// words but the VPD and PCIe ALT modules sum up to 0xBABA.
+    fn validate_eeprom_checksum(&self) -> bool {
+        let vpd_module = u32::from(self.read_eeprom_word(I40E_SR_VPD_PTR));
+        let pcie_alt_module = u32::from(self.read_eeprom_word(I40E_SR_PCIE_ALT_AUTO_LOAD_PTR));
+        let in_module = |word, module| word >= module && word < module + I40E_SR_MODULE_MAX_SIZE;
+
+        let checksum = (0..self.shadow_ram_words())
+            .filter(|&i| {
+                !in_module(i, vpd_module)
+                    && !in_module(i, pcie_alt_module)
+                    && i != u32::from(I40E_SR_SW_CHECKSUM_WORD)
+            })
+            .fold(0u16, |sum, i| {
+                sum.wrapping_add(self.read_eeprom_word(i as u16))
+            });
+
+        I40E_SR_SUM.wrapping_sub(checksum) == self.read_eeprom_word(I40E_SR_SW_CHECKSUM_WORD)
+    }
+
+    /// The X710 reports its temperature only to the firmware.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the global, admin queue and per-queue registers of this device.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("PFGEN_CTRL", I40E_PFGEN_CTRL),
+            ("GLGEN_RSTAT", I40E_GLGEN_RSTAT),
+            ("PF_FUNC_RID", I40E_PF_FUNC_RID),
+            ("PFGEN_PORTNUM", I40E_PFGEN_PORTNUM),
+            ("PFLAN_QALLOC", I40E_PFLAN_QALLOC),
+            ("PF_ATQLEN", I40E_PF_ATQLEN),
+            ("PF_ATQH", I40E_PF_ATQH),
+            ("PF_ATQT", I40E_PF_ATQT),
+            ("PF_ARQLEN", I40E_PF_ARQLEN),
+            ("PF_ARQH", I40E_PF_ARQH),
+            ("PF_ARQT", I40E_PF_ARQT),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            dump.push(
+                format!("QRX_ENA[{}]", i),
+                self.get_reg32(I40E_QRX_ENA_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QRX_TAIL[{}]", i),
+                self.get_reg32(I40E_QRX_TAIL_BASE + 4 * i),
+            );
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            dump.push(
+                format!("QTX_ENA[{}]", i),
+                self.get_reg32(I40E_QTX_ENA_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QTX_CTL[{}]", i),
+                self.get_reg32(I40E_QTX_CTL_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QTX_HEAD[{}]", i),
+                self.get_reg32(I40E_QTX_HEAD_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QTX_TAIL[{}]", i),
+                self.get_reg32(I40E_QTX_TAIL_BASE + 4 * i),
+            );
+        }
+
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for I40eDevice {
+    fn drop(&mut self) {
+        // tell the firmware the driver is gone before the admin queue is unmapped
+        let mut shutdown = AqDesc::new(I40E_AQC_OPC_QUEUE_SHUTDOWN);
+        put_u32(&mut shutdown.params, 0, I40E_AQ_DRIVER_UNLOADING);
+        if let Err(e) = self.aq_command(shutdown, None) {
+            warn!(
+                "failed to shut down admin queue of {}: {}",
+                self.pci_addr, e
+            );
+        }
+
+        // stop all dma before the descriptor rings, mempools and queue contexts are unmapped
+        self.set_reg32(I40E_PFGEN_CTRL, I40E_PFGEN_CTRL_PFSWR);
+        if let Err(e) = self.wait_clear_reg32(I40E_PFGEN_CTRL, I40E_PFGEN_CTRL_PFSWR) {
+            warn!("failed to reset {}: {}", self.pci_addr, e);
+        }
+    }
+}
+
+impl I40eDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == INTEL_VENDOR_ID && SUPPORTED_DEVICE_IDS.contains(&device_id)
+    }
+
+    /// Returns an initialized `I40eDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<I40eDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory, the admin
+        // queue and the host memory cache take a huge page each
+        check_privileges(
+            pci_addr,
+            vfio,
+            dma_size(num_rx_queues, num_tx_queues) + 2 * HUGE_PAGE_SIZE,
+        )?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            pci_map_resource(pci_addr)?
+        };
+
+        let aq_mem = allocate_dma(allocator.as_ref(), numa_node, AQ_MEM_SIZE)?;
+
+        let mut dev = I40eDevice {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            admin_queue: RefCell::new(AdminQueue {
+                mem: aq_mem,
+                next: 0,
+            }),
+            hmc: None,
+            pf_id: 0,
+            port: 0,
+            base_queue: 0,
+            max_queues: 0,
+            vsi_seid: 0,
+            qs_handle: 0,
+            fw_version: 0,
+            crc_strip: true,
+            mac: Cell::new([0; 6]),
+            unicast_filters: RefCell::new(vec![None; NUM_UNICAST_FILTERS]),
+            counters: Cell::new(PortCounters::default()),
+            removal: RemovalLatch::new(I40E_GLGEN_RSTAT),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets the physical function, sets up the admin queue and the host memory cache and
+    /// initializes the queues of the main VSI.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        // the driver polls, mask all interrupts
+        self.set_reg32(I40E_PFINT_ICR0_ENA, 0);
+
+        self.pf_reset()?;
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        info!("initializing device {}", self.pci_addr);
+
+        self.init_admin_queue();
+
+        let version = self.aq_command(AqDesc::new(I40E_AQC_OPC_GET_VERSION), None)?;
+        let fw_major = get_u16(&version.params, 8);
+        let fw_minor = get_u16(&version.params, 10);
+        self.fw_version = (u32::from(fw_major) << 16) | u32::from(fw_minor);
+        info!(
+            "firmware version {}.{}, api version {}.{}",
+            fw_major,
+            fw_minor,
+            get_u16(&version.params, 12),
+            get_u16(&version.params, 14)
+        );
+
+        // a device booted via PXE keeps its rx queues for the firmware until told otherwise
+        if self.get_reg32(I40E_GLLAN_RCTL_0) & I40E_GLLAN_RCTL_0_PXE_MODE != 0 {
+            let mut clear_pxe = AqDesc::new(I40E_AQC_OPC_CLEAR_PXE_MODE);
+            clear_pxe.params[0] = I40E_AQ_CLEAR_PXE_RX_CNT;
+            self.aq_command(clear_pxe, None)?;
+        }
+
+        self.pf_id = self.get_reg32(I40E_PF_FUNC_RID) & I40E_PF_FUNC_RID_FUNCTION_NUMBER_MASK;
+        self.port = self.get_reg32(I40E_PFGEN_PORTNUM) & I40E_PFGEN_PORTNUM_PORT_NUM_MASK;
+
+        // the LAN queues of the physical function are a range of the device's queues
+        let qalloc = self.get_reg32(I40E_PFLAN_QALLOC);
+        if qalloc & I40E_PFLAN_QALLOC_VALID == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "no LAN queues are assigned to {}",
+                self.pci_addr
+            )));
+        }
+        let first = qalloc & I40E_PFLAN_QALLOC_FIRSTQ_MASK;
+        let last = (qalloc >> I40E_PFLAN_QALLOC_LASTQ_SHIFT) & I40E_PFLAN_QALLOC_LASTQ_MASK;
+        self.base_queue = first;
+        self.max_queues = (last - first + 1) as u16;
+
+        self.check_num_queues(self.num_rx_queues, self.num_tx_queues)?;
+
+        let mac = self.read_mac_addr()?;
+        self.mac.set(mac);
+
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        // the firmware creates the main VSI of the physical function with a filter for its mac
+        let mut filters = vec![None; NUM_UNICAST_FILTERS];
+        filters[0] = Some(mac);
+        self.unicast_filters = RefCell::new(filters);
+
+        self.init_vsi()?;
+        self.init_hmc()?;
+
+        // statistic counters of the port keep counting across resets
+        self.reset_stats();
+
+        // accept jumbo frames and append the CRC to sent packets
+        let mut mac_config = AqDesc::new(I40E_AQC_OPC_SET_MAC_CONFIG);
+        put_u16(&mut mac_config.params, 0, I40E_MAX_FRAME_SIZE as u16);
+        mac_config.params[2] = I40E_AQ_SET_MAC_CONFIG_CRC_EN;
+        self.aq_command(mac_config, None)?;
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            self.init_rx_queue(i)?;
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            self.init_tx_queue(i)?;
+        }
+
+        self.update_vsi_queues()?;
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            self.start_rx_queue(i)?;
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            self.start_tx_queue(i)?;
+        }
+
+        // enable promisc mode by default to make testing easier
+        self.set_promisc(true)?;
+
+        let mut restart_an = AqDesc::new(I40E_AQC_OPC_SET_LINK_RESTART_AN);
+        restart_an.params[0] = I40E_AQ_PHY_RESTART_AN | I40E_AQ_PHY_LINK_ENABLE;
+        self.aq_command(restart_an, None)?;
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Resets the physical function once the firmware finished a pending core or global
+    /// reset.
+    fn pf_reset(&self) -> Result<(), IxyError> {
+        let start = Instant::now();
+
+        while self.get_reg32(I40E_GLGEN_RSTAT) & I40E_GLGEN_RSTAT_DEVSTATE_MASK != 0
+            || self.get_reg32(I40E_GLNVM_ULD)
+                & (I40E_GLNVM_ULD_CORER_DONE | I40E_GLNVM_ULD_GLOBR_DONE)
+                != (I40E_GLNVM_ULD_CORER_DONE | I40E_GLNVM_ULD_GLOBR_DONE)
+        {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "firmware of {} is not ready, is a global reset stuck?",
+                    self.pci_addr
+                )));
+            }
+            thread::sleep(RESET_POLL_DELAY);
+        }
+
+        self.set_flags32(I40E_PFGEN_CTRL, I40E_PFGEN_CTRL_PFSWR);
+        self.wait_clear_reg32(I40E_PFGEN_CTRL, I40E_PFGEN_CTRL_PFSWR)
+    }
+
+    /// Sets up the admin send and receive queue, every receive descriptor gets a buffer for
+    /// events of the firmware.
+    fn init_admin_queue(&self) {
+        let mut aq = self.admin_queue.borrow_mut();
+
+        unsafe {
+            memset(aq.mem.virt, AQ_MEM_SIZE, 0);
+        }
+
+        let phys = aq.mem.phys;
+        for i in 0..AQ_LEN {
+            let buf_phys = (phys + ARQ_BUFS_OFFSET + i * AQ_BUF_SIZE) as u64;
+
+            let mut desc = AqDesc {
+                flags: I40E_AQ_FLAG_BUF | I40E_AQ_FLAG_LB,
+                datalen: AQ_BUF_SIZE as u16,
+                ..Default::default()
+            };
+            put_u32(&mut desc.params, 8, (buf_phys >> 32) as u32);
+            put_u32(&mut desc.params, 12, buf_phys as u32);
+
+            unsafe {
+                write_aq_desc(aq.arq().add(i), &desc);
+            }
+        }
+        aq.next = 0;
+
+        let atq_phys = (phys + ATQ_OFFSET) as u64;
+        self.set_reg32(I40E_PF_ATQH, 0);
+        self.set_reg32(I40E_PF_ATQT, 0);
+        self.set_reg32(I40E_PF_ATQBAL, atq_phys as u32);
+        self.set_reg32(I40E_PF_ATQBAH, (atq_phys >> 32) as u32);
+        self.set_reg32(I40E_PF_ATQLEN, AQ_LEN as u32 | I40E_PF_ATQLEN_ATQENABLE);
+
+        let arq_phys = (phys + ARQ_OFFSET) as u64;
+        self.set_reg32(I40E_PF_ARQH, 0);
+        self.set_reg32(I40E_PF_ARQT, 0);
+        self.set_reg32(I40E_PF_ARQBAL, arq_phys as u32);
+        self.set_reg32(I40E_PF_ARQBAH, (arq_phys >> 32) as u32);
+        self.set_reg32(I40E_PF_ARQLEN, AQ_LEN as u32 | I40E_PF_ARQLEN_ARQENABLE);
+
+        // all event buffers are posted to the firmware
+        self.set_reg32(I40E_PF_ARQT, AQ_LEN as u32 - 1);
+    }
+
+    /// Sends `desc` to the firmware and returns the descriptor written back on completion. The
+    /// firmware reads its input from and writes its output to `buffer` if the command has one.
+    fn aq_command(&self, desc: AqDesc, buffer: Option<&mut [u8]>) -> Result<AqDesc, IxyError> {
+        let mut aq = self.admin_queue.borrow_mut();
+        let mut desc = desc;
+        let index = aq.next;
+
+        desc.flags |= I40E_AQ_FLAG_SI;
+
+        if let Some(ref buf) = buffer {
+            assert!(buf.len() <= AQ_BUF_SIZE, "admin queue buffer too large");
+
+            unsafe {
+                ptr::copy_nonoverlapping(buf.as_ptr(), aq.buffer(), buf.len());
+            }
+
+            let buf_phys = (aq.mem.phys + AQ_BUF_OFFSET) as u64;
+            desc.flags |= I40E_AQ_FLAG_BUF;
+            if buf.len() > AQ_LARGE_BUF {
+                desc.flags |= I40E_AQ_FLAG_LB;
+            }
+            desc.datalen = buf.len() as u16;
+            put_u32(&mut desc.params, 8, (buf_phys >> 32) as u32);
+            put_u32(&mut desc.params, 12, buf_phys as u32);
+        }
+
+        unsafe {
+            write_aq_desc(aq.atq().add(index), &desc);
+        }
+
+        aq.next = (index + 1) % AQ_LEN;
+        self.set_reg32(I40E_PF_ATQT, aq.next as u32);
+
+        // the firmware moves the head past the descriptor once it wrote back the completion
+        let start = Instant::now();
+        while self.get_reg32(I40E_PF_ATQH) as usize != aq.next {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > AQ_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "admin queue command {:#06x} of {} timed out",
+                    desc.opcode, self.pci_addr
+                )));
+            }
+            thread::sleep(AQ_POLL_DELAY);
+        }
+
+        let completion = unsafe { read_aq_desc(aq.atq().add(index)) };
+
+        if completion.flags & I40E_AQ_FLAG_DD == 0 || completion.flags & I40E_AQ_FLAG_ERR != 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "admin queue command {:#06x} of {} failed with error {}",
+                desc.opcode, self.pci_addr, completion.retval
+            )));
+        }
+
+        if let Some(buf) = buffer {
+            unsafe {
+                ptr::copy_nonoverlapping(aq.buffer(), buf.as_mut_ptr(), buf.len());
+            }
+        }
+
+        Ok(completion)
+    }
+
+    /// Returns the LAN mac address of the physical function stored by the firmware.
+    fn read_mac_addr(&self) -> Result<[u8; 6], IxyError> {
+        let mut addrs = [0; 24];
+        let desc = self.aq_command(AqDesc::new(I40E_AQC_OPC_MAC_ADDRESS_READ), Some(&mut addrs))?;
+
+        if get_u16(&desc.params, 0) & I40E_AQC_LAN_ADDR_VALID == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "firmware of {} has no valid mac address",
+                self.pci_addr
+            )));
+        }
+
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&addrs[..6]);
+
+        Ok(mac)
+    }
+
+    /// Stores `mac` as the locally administered address used for pause frames.
+    fn write_mac_addr(&self, mac: [u8; 6]) -> Result<(), IxyError> {
+        let mut desc = AqDesc::new(I40E_AQC_OPC_MAC_ADDRESS_WRITE);
+        put_u16(&mut desc.params, 0, I40E_AQC_WRITE_TYPE_LAA_ONLY);
+        put_u16(&mut desc.params, 2, u16::from_be_bytes([mac[0], mac[1]]));
+        put_u32(
+            &mut desc.params,
+            4,
+            u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]),
+        );
+
+        self.aq_command(desc, None).map(|_| ())
+    }
+
+    /// Adds a perfect match filter for `mac` to the main VSI.
+    fn add_macvlan(&self, mac: [u8; 6]) -> Result<(), IxyError> {
+        let mut element = [0; 16];
+        element[..6].copy_from_slice(&mac);
+        put_u16(
+            &mut element,
+            8,
+            I40E_AQC_MACVLAN_ADD_PERFECT_MATCH | I40E_AQC_MACVLAN_ADD_IGNORE_VLAN,
+        );
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_ADD_MACVLAN);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, 1);
+        put_u16(
+            &mut desc.params,
+            2,
+            self.vsi_seid | I40E_AQC_MACVLAN_CMD_SEID_VALID,
+        );
+
+        self.aq_command(desc, Some(&mut element)).map(|_| ())
+    }
+
+    /// Removes the perfect match filter for `mac` from the main VSI.
+    fn remove_macvlan(&self, mac: [u8; 6]) -> Result<(), IxyError> {
+        let mut element = [0; 16];
+        element[..6].copy_from_slice(&mac);
+        element[8] = I40E_AQC_MACVLAN_DEL_PERFECT_MATCH | I40E_AQC_MACVLAN_DEL_IGNORE_VLAN;
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_REMOVE_MACVLAN);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, 1);
+        put_u16(
+            &mut desc.params,
+            2,
+            self.vsi_seid | I40E_AQC_MACVLAN_CMD_SEID_VALID,
+        );
+
+        self.aq_command(desc, Some(&mut element)).map(|_| ())
+    }
+
+    /// Looks up the main VSI of the physical function in the switch and the queue set its tx
+    /// queues are scheduled in.
+    fn init_vsi(&mut self) -> Result<(), IxyError> {
+        let mut config = [0; AQ_BUF_SIZE];
+        self.aq_command(
+            AqDesc::new(I40E_AQC_OPC_GET_SWITCH_CONFIG),
+            Some(&mut config),
+        )?;
+
+        let num_reported = usize::from(get_u16(&config, 0));
+        self.vsi_seid = (0..num_reported)
+            .map(|i| &config[SWITCH_CONFIG_HEADER_SIZE + i * SWITCH_CONFIG_ELEMENT_SIZE..])
+            .find(|element| element[0] == I40E_AQ_SW_ELEM_TYPE_VSI)
+            .map(|element| get_u16(element, 2))
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(format!(
+                    "switch of {} has no VSI for the physical function",
+                    self.pci_addr
+                ))
+            })?;
+
+        let properties = self.get_vsi_properties()?;
+        self.qs_handle = get_u16(&properties, VSI_PROP_QS_HANDLE);
+
+        debug!(
+            "main VSI has seid {}, queue set {}",
+            self.vsi_seid, self.qs_handle
+        );
+
+        Ok(())
+    }
+
+    /// Returns the properties of the main VSI.
+    fn get_vsi_properties(&self) -> Result<[u8; VSI_PROPERTIES_SIZE], IxyError> {
+        let mut properties = [0; VSI_PROPERTIES_SIZE];
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_GET_VSI_PARAMETERS);
+        put_u16(&mut desc.params, 0, self.vsi_seid);
+        self.aq_command(desc, Some(&mut properties))?;
+
+        Ok(properties)
+    }
+
+    /// Maps the configured queues contiguously to the first traffic class of the main VSI.
+    fn update_vsi_queues(&self) -> Result<(), IxyError> {
+        let num_queues = self.num_rx_queues.max(self.num_tx_queues).max(1);
+        // the traffic class holds a power of 2 queues
+        let num_queues_log2 = num_queues.next_power_of_two().trailing_zeros() as u16;
+
+        let mut properties = self.get_vsi_properties()?;
+        put_u16(
+            &mut properties,
+            VSI_PROP_VALID_SECTIONS,
+            I40E_AQ_VSI_PROP_QUEUE_MAP_VALID,
+        );
+        put_u16(
+            &mut properties,
+            VSI_PROP_MAPPING_FLAGS,
+            I40E_AQ_VSI_QUE_MAP_CONTIG,
+        );
+        put_u16(&mut properties, VSI_PROP_QUEUE_MAPPING, 0);
+        put_u16(
+            &mut properties,
+            VSI_PROP_TC_MAPPING,
+            num_queues_log2 << I40E_AQ_VSI_TC_QUE_NUMBER_SHIFT,
+        );
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_UPDATE_VSI_PARAMETERS);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, self.vsi_seid);
+
+        self.aq_command(desc, Some(&mut properties)).map(|_| ())
+    }
+
+    /// Enables or disables unicast, multicast and broadcast promiscuous mode of the main VSI.
+    fn set_promisc(&self, enable: bool) -> Result<(), IxyError> {
+        let flags = I40E_AQC_SET_VSI_PROMISC_UNICAST
+            | I40E_AQC_SET_VSI_PROMISC_MULTICAST
+            | I40E_AQC_SET_VSI_PROMISC_BROADCAST;
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_SET_VSI_PROMISCUOUS_MODES);
+        put_u16(&mut desc.params, 0, if enable { flags } else { 0 });
+        put_u16(&mut desc.params, 2, flags);
+        put_u16(
+            &mut desc.params,
+            4,
+            self.vsi_seid & I40E_AQC_VSI_PROM_CMD_SEID_MASK,
+        );
+
+        self.aq_command(desc, None).map(|_| ())
+    }
+
+    /// Replaces the pause abilities the PHY advertises with `pause`.
+    fn set_phy_pause(&self, pause: u8) -> Result<(), IxyError> {
+        let mut abilities = [0; AQ_BUF_SIZE];
+        self.aq_command(
+            AqDesc::new(I40E_AQC_OPC_GET_PHY_ABILITIES),
+            Some(&mut abilities),
+        )?;
+
+        // phy type, speed and eee settings are kept as reported, only the pause bits change
+        let mut desc = AqDesc::new(I40E_AQC_OPC_SET_PHY_CONFIG);
+        desc.params[..14].copy_from_slice(&abilities[..14]);
+        desc.params[5] = (abilities[5] & !(I40E_AQ_PHY_FLAG_PAUSE_TX | I40E_AQ_PHY_FLAG_PAUSE_RX))
+            | pause
+            | I40E_AQ_PHY_ENABLE_ATOMIC_LINK;
+
+        self.aq_command(desc, None).map(|_| ())
+    }
+
+    /// Places the LAN queue contexts of the physical function in a direct backing page of the
+    /// host memory cache, the tx contexts first and the rx contexts behind them.
+    fn init_hmc(&mut self) -> Result<(), IxyError> {
+        let tx_obj_size = 1 << self.get_reg32(I40E_GLHMC_LANTXOBJSZ);
+        let rx_obj_size = 1 << self.get_reg32(I40E_GLHMC_LANRXOBJSZ);
+        let count = u32::from(self.max_queues).min(self.get_reg32(I40E_GLHMC_LANQMAX));
+
+        let tx_base = 0;
+        let rx_base = (tx_base + count * tx_obj_size).next_multiple_of(HMC_BASE_UNIT);
+        let end = (rx_base + count * rx_obj_size).next_multiple_of(HMC_BASE_UNIT);
+
+        if end as usize > HMC_SD_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "queue contexts of {} exceed a segment of the host memory cache",
+                self.pci_addr
+            )));
+        }
+
+        let pf = self.pf_id;
+        self.set_reg32(I40E_GLHMC_LANTXBASE_BASE + 4 * pf, tx_base / HMC_BASE_UNIT);
+        self.set_reg32(I40E_GLHMC_LANTXCNT_BASE + 4 * pf, count);
+        self.set_reg32(I40E_GLHMC_LANRXBASE_BASE + 4 * pf, rx_base / HMC_BASE_UNIT);
+        self.set_reg32(I40E_GLHMC_LANRXCNT_BASE + 4 * pf, count);
+
+        // no FCoE contexts, they start behind the LAN queues
+        self.set_reg32(I40E_GLHMC_FCOEDDPBASE_BASE + 4 * pf, end / HMC_BASE_UNIT);
+        self.set_reg32(I40E_GLHMC_FCOEDDPCNT_BASE + 4 * pf, 0);
+        self.set_reg32(I40E_GLHMC_FCOEFBASE_BASE + 4 * pf, end / HMC_BASE_UNIT);
+        self.set_reg32(I40E_GLHMC_FCOEFCNT_BASE + 4 * pf, 0);
+
+        let mem = match self.hmc.take() {
+            Some(hmc) => hmc.mem,
+            None => allocate_dma(self.allocator.as_ref(), self.numa_node, HMC_SD_SIZE)?,
+        };
+
+        unsafe {
+            memset(mem.virt, HMC_SD_SIZE, 0);
+        }
+
+        // segment descriptor 0 covers the first 2 MiB of the function's private memory
+        let phys = mem.phys as u64;
+        self.set_reg32(I40E_PFHMC_SDDATAHIGH, (phys >> 32) as u32);
+        self.set_reg32(
+            I40E_PFHMC_SDDATALOW,
+            phys as u32
+                | (HMC_BP_COUNT << I40E_PFHMC_SDDATALOW_PMSDBPCOUNT_SHIFT)
+                | I40E_PFHMC_SDDATALOW_PMSDTYPE_DIRECT
+                | I40E_PFHMC_SDDATALOW_PMSDVALID,
+        );
+        self.set_reg32(I40E_PFHMC_SDCMD, I40E_PFHMC_SDCMD_PMSDWR);
+
+        debug!("host memory cache phys addr: {:#x}", mem.phys);
+
+        self.hmc = Some(Hmc {
+            mem,
+            tx_base: tx_base as usize,
+            tx_obj_size: tx_obj_size as usize,
+            rx_base: rx_base as usize,
+            rx_obj_size: rx_obj_size as usize,
+        });
+
+        Ok(())
+    }
+
+    /// Allocates the descriptor ring and mempool of rx queue `queue_id`.
+    fn init_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<RxDesc>();
+
+        let dma: Dma<RxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(I40eRxQueue::new(dma, mempool));
+
+        Ok(())
+    }
+
+    /// Allocates the descriptor ring of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<TxDesc>();
+
+        let dma: Dma<TxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // all descriptors start out without the descriptor done type
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0);
+        }
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        self.tx_queues.push(I40eTxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Fills rx queue `queue_id`, writes its context and enables it.
+    fn start_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("starting rx queue {}", queue_id);
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        let tail = queue.fill()?;
+        let buffer_size = queue.buffer_size();
+        let max_frame = (I40E_MAX_CHAINED_RX_BUFFERS * buffer_size).min(I40E_MAX_FRAME_SIZE);
+
+        // see struct i40e_hmc_obj_rxq in Intel's shared code for the layout
+        let mut ctx = [0; 32];
+        set_ctx_bits(&mut ctx, 0, 13, 0); // head
+        set_ctx_bits(
+            &mut ctx,
+            32,
+            57,
+            queue.ring.phys as u64 >> I40E_QUEUE_BASE_SHIFT,
+        ); // base
+        set_ctx_bits(&mut ctx, 89, 13, queue.num_descriptors as u64); // qlen
+        set_ctx_bits(
+            &mut ctx,
+            102,
+            7,
+            (buffer_size >> I40E_RXQ_CTX_DBUFF_SHIFT) as u64,
+        ); // dbuff
+        set_ctx_bits(&mut ctx, 116, 1, 1); // dsize: 32 byte descriptors
+        set_ctx_bits(&mut ctx, 117, 1, u64::from(self.crc_strip)); // crcstrip
+        set_ctx_bits(&mut ctx, 119, 1, 1); // l2tsel
+        set_ctx_bits(&mut ctx, 174, 14, max_frame as u64); // rxmax
+        set_ctx_bits(&mut ctx, 198, 3, 1); // lrxqthresh
+        set_ctx_bits(&mut ctx, 201, 1, 1); // prefena
+
+        let hmc = self
+            .hmc
+            .as_ref()
+            .expect("host memory cache not initialized");
+        hmc.write_ctx(hmc.rx_base, hmc.rx_obj_size, queue_id as usize, &ctx);
+
+        self.set_reg32(I40E_QRX_TAIL_BASE + 4 * queue_id, 0);
+        self.enable_queue(I40E_QRX_ENA_BASE + 4 * queue_id)?;
+
+        // rx queue starts out full
+        self.set_reg32(I40E_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+
+        Ok(())
+    }
+
+    /// Writes the context of tx queue `queue_id`, assigns it to the physical function and
+    /// enables it.
+    fn start_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("starting tx queue {}", queue_id);
+
+        let queue = &self.tx_queues[queue_id as usize];
+
+        // see struct i40e_hmc_obj_txq in Intel's shared code for the layout
+        let mut ctx = [0; 128];
+        set_ctx_bits(&mut ctx, 0, 13, 0); // head
+        set_ctx_bits(&mut ctx, 30, 1, 1); // new_context
+        set_ctx_bits(
+            &mut ctx,
+            32,
+            57,
+            queue.ring.phys as u64 >> I40E_QUEUE_BASE_SHIFT,
+        ); // base
+        set_ctx_bits(&mut ctx, 128 + 33, 13, queue.num_descriptors as u64); // qlen
+        set_ctx_bits(&mut ctx, 7 * 128 + 84, 10, u64::from(self.qs_handle)); // rdylist
+
+        let hmc = self
+            .hmc
+            .as_ref()
+            .expect("host memory cache not initialized");
+        hmc.write_ctx(hmc.tx_base, hmc.tx_obj_size, queue_id as usize, &ctx);
+
+        self.set_reg32(
+            I40E_QTX_CTL_BASE + 4 * queue_id,
+            I40E_QTX_CTL_PF_QUEUE | (self.pf_id << I40E_QTX_CTL_PF_INDX_SHIFT),
+        );
+
+        // tx queue starts out empty
+        self.set_reg32(I40E_QTX_TAIL_BASE + 4 * queue_id, 0);
+
+        self.set_tx_queue_disable(queue_id, false);
+        self.enable_queue(I40E_QTX_ENA_BASE + 4 * queue_id)
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn stop_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("stopping rx queue {}", queue_id);
+
+        self.disable_queue(I40E_QRX_ENA_BASE + 4 * queue_id)
+    }
+
+    /// Disables tx queue `queue_id`, packets that have not been sent yet stay in the ring.
+    fn stop_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("stopping tx queue {}", queue_id);
+
+        self.set_tx_queue_disable(queue_id, true);
+        self.disable_queue(I40E_QTX_ENA_BASE + 4 * queue_id)
+    }
+
+    /// Requests queue enable register `reg` to enable its queue and waits until it did.
+    fn enable_queue(&self, reg: u32) -> Result<(), IxyError> {
+        self.set_flags32(reg, I40E_QENA_REQ);
+        self.wait_queue(reg, true)
+    }
+
+    /// Requests queue enable register `reg` to disable its queue and waits until it did.
+    fn disable_queue(&self, reg: u32) -> Result<(), IxyError> {
+        self.clear_flags32(reg, I40E_QENA_REQ);
+        self.wait_queue(reg, false)
+    }
+
+    /// Waits until the queue of queue enable register `reg` reports to be `enabled`.
+    fn wait_queue(&self, reg: u32, enabled: bool) -> Result<(), IxyError> {
+        let start = Instant::now();
+
+        while ((self.get_reg32(reg) & I40E_QENA_STAT) != 0) != enabled {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > QUEUE_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "queue of {} did not become {}",
+                    self.pci_addr,
+                    if enabled { "enabled" } else { "disabled" }
+                )));
+            }
+            thread::sleep(QUEUE_POLL_DELAY);
+        }
+
+        Ok(())
+    }
+
+    /// Tells the transmit scheduler that tx queue `queue_id` is about to be disabled or
+    /// enabled.
+    fn set_tx_queue_disable(&self, queue_id: u32, disable: bool) {
+        let abs_queue = self.base_queue + queue_id;
+        let reg = I40E_GLLAN_TXPRE_QDIS_BASE + 4 * (abs_queue / I40E_QUEUES_PER_TXPRE_QDIS);
+
+        let flag = if disable {
+            I40E_GLLAN_TXPRE_QDIS_SET_QDIS
+        } else {
+            I40E_GLLAN_TXPRE_QDIS_CLEAR_QDIS
+        };
+
+        self.set_reg32(reg, (abs_queue & I40E_GLLAN_TXPRE_QDIS_QINDX_MASK) | flag);
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Returns an error if the physical function doesn't have the given number of queues.
+    fn check_num_queues(&self, num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+        if num_rx_queues > self.max_queues || num_tx_queues > self.max_queues {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot configure {} rx and {} tx queues: {} has {} queues",
+                num_rx_queues, num_tx_queues, self.pci_addr, self.max_queues
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current values of the statistic counters of the port.
+    fn read_counters(&self) -> PortCounters {
+        let reg = |r| self.read_counter(r, COUNTER_48_MASK);
+
+        PortCounters {
+            rx_pkts: reg(I40E_GLPRT_UPRCL) + reg(I40E_GLPRT_MPRCL) + reg(I40E_GLPRT_BPRCL),
+            tx_pkts: reg(I40E_GLPRT_UPTCL) + reg(I40E_GLPRT_MPTCL) + reg(I40E_GLPRT_BPTCL),
+            rx_bytes: reg(I40E_GLPRT_GORCL),
+            tx_bytes: reg(I40E_GLPRT_GOTCL),
+            extended: self.counters.get().extended,
+        }
+    }
+
+    /// Returns the value of the port counter `reg`, 48 bit counters continue in the next
+    /// register.
+    fn read_counter(&self, reg: u32, mask: u64) -> u64 {
+        let reg = reg + I40E_GLPRT_STRIDE * self.port;
+        let low = u64::from(self.get_reg32(reg));
+
+        if mask == COUNTER_32_MASK {
+            return low;
+        }
+
+        (low | (u64::from(self.get_reg32(reg + 4)) << 32)) & mask
+    }
+
+    /// Returns the size of the shadow RAM in words.
+    fn shadow_ram_words(&self) -> u32 {
+        let sr_size = (self.get_reg32(I40E_GLNVM_GENS) >> I40E_GLNVM_GENS_SR_SIZE_SHIFT)
+            & I40E_GLNVM_GENS_SR_SIZE_MASK;
+
+        (1 << sr_size) * I40E_SR_WORDS_IN_1KB
+    }
+
+    /// Waits until the shadow RAM finished the last read.
+    fn wait_shadow_ram(&self) {
+        while self.get_reg32(I40E_GLNVM_SRCTL) & I40E_GLNVM_SRCTL_DONE == 0 {
+            if self.removal.is_removed() {
+                return;
+            }
+            thread::sleep(Duration::from_micros(5));
+        }
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register at `self.addr` + `reg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
+    }
+
+    /// Sets the register at `self.addr` + `reg` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+
+    /// Sets the `flags` at `self.addr` + `reg`.
+    fn set_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) | flags);
+    }
+
+    /// Clears the `flags` at `self.addr` + `reg`.
+    fn clear_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) & !flags);
+    }
+
+    /// Waits for `self.addr` + `reg` to clear `value`.
+    fn wait_clear_reg32(&self, reg: u32, value: u32) -> Result<(), IxyError> {
+        let start = Instant::now();
+
+        while (self.get_reg32(reg) & value) != 0 {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "register {:#x} of {} did not clear {:#x}",
+                    reg, self.pci_addr, value
+                )));
+            }
+            thread::sleep(RESET_POLL_DELAY);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the lowest `width` bits of `value` to the packed context `ctx` starting at bit `lsb`.
+pub(crate) fn set_ctx_bits(ctx: &mut [u8], lsb: usize, width: usize, value: u64) {
+    for bit in 0..width {
+        let pos = lsb + bit;
+        if (value >> bit) & 1 != 0 {
+            ctx[pos / 8] |= 1 << (pos % 8);
+        } else {
+            ctx[pos / 8] &= !(1 << (pos % 8));
+        }
+    }
+}
+
+/// Returns the little endian u16 at `offset` of `buf`.
+pub(crate) fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+/// Writes `value` as little endian u16 to `offset` of `buf`.
+pub(crate) fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Writes `value` as little endian u32 to `offset` of `buf`.
+pub(crate) fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Writes the admin queue descriptor `desc` to `dst` in the little endian layout of the device.
+pub(crate) unsafe fn write_aq_desc(dst: *mut AqDesc, desc: &AqDesc) {
+    ptr::write_volatile(
+        dst,
+        AqDesc {
+            flags: desc.flags.to_le(),
+            opcode: desc.opcode.to_le(),
+            datalen: desc.datalen.to_le(),
+            retval: desc.retval.to_le(),
+            cookie_high: desc.cookie_high.to_le(),
+            cookie_low: desc.cookie_low.to_le(),
+            params: desc.params,
+        },
+    );
+}
+
+/// Returns the admin queue descriptor at `src` written back by the firmware.
+pub(crate) unsafe fn read_aq_desc(src: *const AqDesc) -> AqDesc {
+    let desc = ptr::read_volatile(src);
+
+    AqDesc {
+        flags: u16::from_le(desc.flags),
+        opcode: u16::from_le(desc.opcode),
+        datalen: u16::from_le(desc.datalen),
+        retval: u16::from_le(desc.retval),
+        cookie_high: u32::from_le(desc.cookie_high),
+        cookie_low: u32::from_le(desc.cookie_low),
+        params: desc.params,
+    }
+}
+
+/// Points the rx descriptor `desc` to the buffer at `phys_addr` and clears its status, which
+/// shares the second quad word with the header address.
+pub(crate) unsafe fn write_rx_desc(desc: *mut RxDesc, phys_addr: usize) {
+    ptr::write_volatile(&mut (*desc).pkt_addr, (phys_addr as u64).to_le());
+    ptr::write_volatile(&mut (*desc).hdr_addr, 0);
+}
+
+/// Writes a data descriptor for the `len` bytes at `phys_addr` to `desc`.
+unsafe fn write_tx_desc(desc: *mut TxDesc, phys_addr: usize, len: usize, cmd: u64) {
+    let qword = I40E_TX_DESC_DTYPE_DATA
+        | (cmd << I40E_TXD_CMD_SHIFT)
+        | ((len as u64) << I40E_TXD_BUF_SZ_SHIFT);
+
+    ptr::write_volatile(&mut (*desc).buffer_addr, (phys_addr as u64).to_le());
+    ptr::write_volatile(&mut (*desc).cmd_type_offset_bsz, qword.to_le());
+}
+
+/// Returns the status, error and length quad word of the rx descriptor at `index` of `queue`.
+unsafe fn rx_desc_status(queue: &I40eRxQueue, index: usize) -> u64 {
+    u64::from_le(ptr::read_volatile(
+        &(*queue.descriptors.add(index)).hdr_addr,
+    ))
+}
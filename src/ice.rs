@@ -0,0 +1,2198 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::i40e::*;
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES,
+    NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-ice";
+
+const INTEL_VENDOR_ID: u16 = 0x8086;
+
+const ICE_DEV_ID_E810C_BACKPLANE: u16 = 0x1591;
+const ICE_DEV_ID_E810C_QSFP: u16 = 0x1592;
+const ICE_DEV_ID_E810C_SFP: u16 = 0x1593;
+const ICE_DEV_ID_E810_XXV_BACKPLANE: u16 = 0x1599;
+const ICE_DEV_ID_E810_XXV_QSFP: u16 = 0x159A;
+const ICE_DEV_ID_E810_XXV_SFP: u16 = 0x159B;
+
+// physical functions of the E810-C (100 GbE) and the E810-XXV (25 GbE)
+const SUPPORTED_DEVICE_IDS: [u16; 6] = [
+    ICE_DEV_ID_E810C_BACKPLANE,
+    ICE_DEV_ID_E810C_QSFP,
+    ICE_DEV_ID_E810C_SFP,
+    ICE_DEV_ID_E810_XXV_BACKPLANE,
+    ICE_DEV_ID_E810_XXV_QSFP,
+    ICE_DEV_ID_E810_XXV_SFP,
+];
+
+// general registers, the admin queue and reset status registers are the ones of the X710
+const ICE_PFGEN_CTRL: u32 = 0x0009_1000;
+const ICE_PFGEN_CTRL_PFSWR: u32 = 1 << 0;
+const ICE_PF_FUNC_RID: u32 = 0x0009_E880;
+const ICE_PF_FUNC_RID_FUNCTION_NUMBER_MASK: u32 = 0x7;
+const ICE_PFINT_OICR_ENA: u32 = 0x0016_C900;
+const ICE_PFGEN_PORTNUM: u32 = 0x001D_2400;
+const ICE_PFGEN_PORTNUM_PORT_NUM_MASK: u32 = 0x7;
+const ICE_PFLAN_RX_QALLOC: u32 = 0x001D_2500;
+const ICE_PFLAN_TX_QALLOC: u32 = 0x001D_2580;
+const ICE_PFLAN_QALLOC_FIRSTQ_MASK: u32 = 0x7FF;
+const ICE_PFLAN_QALLOC_LASTQ_SHIFT: u32 = 16;
+const ICE_PFLAN_QALLOC_LASTQ_MASK: u32 = 0x7FF;
+const ICE_PFLAN_QALLOC_VALID: u32 = 1 << 31;
+
+// LAN queue registers, indexed by the queue number relative to the physical function
+const ICE_QTX_COMM_HEAD_BASE: u32 = 0x000E_0000;
+const ICE_QTX_COMM_HEAD_MASK: u32 = 0x1FFF;
+const ICE_QRX_CTRL_BASE: u32 = 0x0012_0000;
+const ICE_QRX_CTRL_QENA_REQ: u32 = 1 << 0;
+const ICE_QRX_CTRL_QENA_STAT: u32 = 1 << 2;
+const ICE_QRX_CONTEXT_BASE: u32 = 0x0028_0000;
+const ICE_QRX_CONTEXT_STRIDE: u32 = 0x2000;
+const ICE_QRX_TAIL_BASE: u32 = 0x0029_0000;
+const ICE_QTX_COMM_DBELL_BASE: u32 = 0x002C_0000;
+const ICE_QRXFLXP_CNTXT_BASE: u32 = 0x0048_0000;
+const ICE_QRXFLXP_CNTXT_RXDID_IDX_MASK: u32 = 0x3F;
+const ICE_QRXFLXP_CNTXT_RXDID_PRIO_SHIFT: u32 = 8;
+const ICE_QRXFLXP_CNTXT_RXDID_PRIO_MASK: u32 = 0x7 << ICE_QRXFLXP_CNTXT_RXDID_PRIO_SHIFT;
+
+// statistic counters of the port, none of them is cleared on read
+const ICE_GLPRT_GORCL: u32 = 0x0038_0000;
+const ICE_GLPRT_MLFC: u32 = 0x0038_0040;
+const ICE_GLPRT_MRFC: u32 = 0x0038_0080;
+const ICE_GLPRT_CRCERRS: u32 = 0x0038_0100;
+const ICE_GLPRT_RLEC: u32 = 0x0038_0140;
+const ICE_GLPRT_ILLERRC: u32 = 0x0038_01C0;
+const ICE_GLPRT_RUC: u32 = 0x0038_0200;
+const ICE_GLPRT_ROC: u32 = 0x0038_0240;
+const ICE_GLPRT_LXONRXC: u32 = 0x0038_0280;
+const ICE_GLPRT_LXOFFRXC: u32 = 0x0038_02C0;
+const ICE_GLPRT_RFC: u32 = 0x0038_0AC0;
+const ICE_GLPRT_RJC: u32 = 0x0038_0B00;
+const ICE_GLPRT_GOTCL: u32 = 0x0038_0B40;
+const ICE_GLPRT_LXONTXC: u32 = 0x0038_1140;
+const ICE_GLPRT_LXOFFTXC: u32 = 0x0038_1180;
+const ICE_GLPRT_UPTCL: u32 = 0x0038_11C0;
+const ICE_GLPRT_MPTCL: u32 = 0x0038_1200;
+const ICE_GLPRT_BPTCL: u32 = 0x0038_1240;
+const ICE_GLPRT_UPRCL: u32 = 0x0038_1300;
+const ICE_GLPRT_MPRCL: u32 = 0x0038_1340;
+const ICE_GLPRT_BPRCL: u32 = 0x0038_1380;
+const ICE_GLPRT_STRIDE: u32 = 8;
+
+// counters of the extended stats with their width in the order of `PortCounters::extended`
+const EXTENDED_COUNTERS: [(u32, u64); 17] = [
+    (ICE_GLPRT_CRCERRS, COUNTER_32_MASK),
+    (ICE_GLPRT_ILLERRC, COUNTER_32_MASK),
+    (ICE_GLPRT_RLEC, COUNTER_32_MASK),
+    (ICE_GLPRT_RUC, COUNTER_32_MASK),
+    (ICE_GLPRT_RFC, COUNTER_32_MASK),
+    (ICE_GLPRT_ROC, COUNTER_32_MASK),
+    (ICE_GLPRT_RJC, COUNTER_32_MASK),
+    (ICE_GLPRT_BPRCL, COUNTER_48_MASK),
+    (ICE_GLPRT_MPRCL, COUNTER_48_MASK),
+    (ICE_GLPRT_BPTCL, COUNTER_48_MASK),
+    (ICE_GLPRT_MPTCL, COUNTER_48_MASK),
+    (ICE_GLPRT_LXONRXC, COUNTER_32_MASK),
+    (ICE_GLPRT_LXOFFRXC, COUNTER_32_MASK),
+    (ICE_GLPRT_LXONTXC, COUNTER_32_MASK),
+    (ICE_GLPRT_LXOFFTXC, COUNTER_32_MASK),
+    (ICE_GLPRT_MLFC, COUNTER_32_MASK),
+    (ICE_GLPRT_MRFC, COUNTER_32_MASK),
+];
+
+// admin queue commands of the E810, the basic ones share their opcode with the X710
+const ICE_AQC_OPC_REQ_RES: u16 = 0x0008;
+const ICE_AQC_OPC_RELEASE_RES: u16 = 0x0009;
+const ICE_AQC_OPC_ADD_VSI: u16 = 0x0210;
+const ICE_AQC_OPC_ADD_SW_RULES: u16 = 0x02A0;
+const ICE_AQC_OPC_REMOVE_SW_RULES: u16 = 0x02A2;
+const ICE_AQC_OPC_GET_DFLT_TOPO: u16 = 0x0400;
+const ICE_AQC_OPC_ADD_SCHED_ELEMS: u16 = 0x0401;
+const ICE_AQC_OPC_QUERY_SCHED_RES: u16 = 0x0412;
+const ICE_AQC_OPC_NVM_READ: u16 = 0x0701;
+const ICE_AQC_OPC_NVM_CHECKSUM: u16 = 0x0706;
+const ICE_AQC_OPC_ADD_TXQS: u16 = 0x0C30;
+const ICE_AQC_OPC_DIS_TXQS: u16 = 0x0C31;
+
+const ICE_NVM_RES_ID: u16 = 1;
+const ICE_RES_READ: u16 = 1;
+const ICE_NVM_TIMEOUT_MS: u32 = 3000;
+const ICE_AQC_NVM_LAST_CMD: u8 = 0x01;
+const ICE_AQC_NVM_CHECKSUM_VERIFY: u8 = 0x01;
+const ICE_AQC_NVM_CHECKSUM_CORRECT: u16 = 0xBABA;
+
+const ICE_AQC_MAN_MAC_ADDR_TYPE_LAN: u8 = 0;
+const ICE_AQC_MAN_MAC_READ_SIZE: usize = 8;
+const ICE_AQC_GET_SW_CONF_RESP_TYPE_SHIFT: u16 = 14;
+const ICE_AQC_GET_SW_CONF_RESP_PHYS_PORT: u16 = 0;
+const ICE_AQC_GET_SW_CONF_RESP_IS_VF: u16 = 1 << 15;
+const ICE_AQC_GET_SW_CONF_RESP_SIZE: usize = 6;
+const ICE_LPORT_MASK: u16 = 0xFF;
+
+const ICE_AQ_VSI_TYPE_PF: u16 = 0x2;
+const ICE_AQ_VSI_NUM_MASK: u16 = 0x3FF;
+const ICE_AQ_VSI_IS_VALID: u16 = 1 << 15;
+const ICE_AQ_VSI_PROP_SW_VALID: u16 = 1 << 0;
+const ICE_AQ_VSI_SW_FLAG_LAN_ENA: u8 = 1 << 4;
+const ICE_AQ_VSI_TC_Q_NUM_SHIFT: u16 = 11;
+const VSI_PROP_SW_ID: usize = 2;
+const VSI_PROP_SW_FLAGS2: usize = 4;
+
+// switch rules forwarding received packets to the VSI, all of them match a dummy Ethernet
+// header
+const ICE_AQC_SW_RULES_T_LKUP_RX: u16 = 0x0;
+const ICE_SW_LKUP_MAC: u16 = 1;
+const ICE_SW_LKUP_DFLT: u16 = 5;
+const ICE_SINGLE_ACT_VSI_FORWARDING: u32 = 0x0;
+const ICE_SINGLE_ACT_VSI_ID_SHIFT: u32 = 4;
+const ICE_SINGLE_ACT_VALID_BIT: u32 = 1 << 17;
+const ICE_SW_RULE_NO_HDR_SIZE: usize = 16;
+const DUMMY_ETH_HDR: [u8; 16] = [0x2, 0, 0, 0, 0, 0, 0x2, 0, 0, 0, 0, 0, 0x81, 0, 0, 0];
+
+// tx scheduler, the tx queues hang off a queue group node two layers above the leaves
+const ICE_AQC_ELEM_TYPE_LEAF: u8 = 5;
+const ICE_AQC_ELEM_TYPE_SE_GENERIC: u8 = 3;
+const ICE_AQC_ELEM_VALID_GENERIC: u8 = 1 << 0;
+const ICE_AQC_ELEM_VALID_CIR: u8 = 1 << 1;
+const ICE_AQC_ELEM_VALID_EIR: u8 = 1 << 2;
+const ICE_SCHED_DFLT_BW_WT: u16 = 4;
+const ICE_QGRP_LAYER_OFFSET: usize = 2;
+const SCHED_RES_SIZE: usize = 32 + 9 * 24;
+const SCHED_TOPO_HEADER_SIZE: usize = 8;
+const SCHED_ELEM_SIZE: usize = 24;
+
+// tx queue context, part of the add tx queues command
+const ICE_TXQ_CTX_SIZE: usize = 22;
+const ICE_TLAN_CTX_VMVF_TYPE_PF: u64 = 2;
+const ICE_AQC_Q_DIS_CMD_FLUSH_PIPE: u8 = 1 << 2;
+const ICE_AQC_Q_DIS_TIMEOUT_SHIFT: u16 = 10;
+const ICE_AQC_Q_DIS_TIMEOUT: u16 = 5;
+
+// rx queue context, written to the QRX_CONTEXT registers
+const ICE_RXQ_CTX_SIZE: usize = 32;
+const ICE_RXDID_FLEX_NIC: u32 = 2;
+const ICE_RXDID_PRIO: u32 = 3;
+
+const ICE_AQ_LINK_UP: u8 = 1 << 0;
+const ICE_AQ_LINK_PAUSE_TX: u8 = 1 << 5;
+const ICE_AQ_LINK_PAUSE_RX: u8 = 1 << 6;
+const ICE_AQ_PHY_RESTART_AN: u8 = 1 << 1;
+const ICE_AQ_PHY_LINK_ENABLE: u8 = 1 << 2;
+const LINK_STATUS_SIZE: usize = 48;
+
+// link speeds of the link status, in Mbit/s
+const ICE_LINK_SPEEDS: [u32; 11] = [
+    10, 100, 1000, 2500, 5000, 10000, 20000, 25000, 40000, 50000, 100_000,
+];
+
+const ICE_RX_FLEX_DESC_STATUS0_DD: u64 = 1 << 0;
+const ICE_RX_FLEX_DESC_STATUS0_EOF: u64 = 1 << 1;
+const ICE_RX_FLEX_DESC_PKT_LEN_SHIFT: u64 = 32;
+const ICE_RX_FLEX_DESC_PKT_LEN_MASK: u64 = 0x3FFF;
+
+const ICE_SR_WORDS_IN_1KB: u32 = 512;
+
+const NUM_UNICAST_FILTERS: usize = 64;
+
+const ICE_FAILED_READ_REG: u32 = 0xFFFF_FFFF;
+
+/// Driver for the physical functions of the E810 family of 25 and 100 GbE network cards, see
+/// the Intel Ethernet Controller E810 datasheet.
+///
+/// Like on the X710 the firmware owns the port, but the driver creates its own VSI, attaches
+/// it to the tx scheduler and steers received packets to it with switch rules. Packets are
+/// received with flexible descriptors.
+pub struct IceDevice {
+    pci_addr: String,
+    addr: *mut u8,
+    len: usize,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<IceRxQueue>,
+    tx_queues: Vec<I40eTxQueue>,
+    // scheduler node of each enabled tx queue
+    tx_teids: Vec<Cell<Option<u32>>>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    admin_queue: RefCell<AdminQueue>,
+    pf_id: u32,
+    port: u32,
+    lport: u8,
+    sw_id: u8,
+    max_rx_queues: u16,
+    max_tx_queues: u16,
+    vsi_num: u16,
+    qgroup_teid: u32,
+    fw_version: u32,
+    crc_strip: bool,
+    mac: Cell<[u8; 6]>,
+    unicast_filters: RefCell<Vec<Option<UnicastFilter>>>,
+    counters: Cell<PortCounters>,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// Mac address of a unicast filter and the index of the switch rule forwarding it to the VSI.
+type UnicastFilter = ([u8; 6], u16);
+
+/// Values of the statistic counters of the port at their last read.
+#[derive(Clone, Copy, Default)]
+struct PortCounters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    extended: [u64; EXTENDED_COUNTERS.len()],
+}
+
+/// Receive queue on 32 byte descriptors, the device writes back the flexible NIC descriptor
+/// format with the length in the first and the status in the second quad word.
+struct IceRxQueue {
+    descriptors: *mut RxDesc,
+    // keeps the descriptor ring mapped
+    ring: Dma<RxDesc>,
+    num_descriptors: usize,
+    pool: Arc<Mempool>,
+    bufs_in_use: Vec<usize>,
+    rx_index: usize,
+    rx_tail: usize,
+    num_posted: usize,
+}
+
+impl IceRxQueue {
+    /// Returns a queue on the descriptor ring `ring` that receives into buffers of `pool`.
+    fn new(ring: Dma<RxDesc>, pool: Arc<Mempool>) -> IceRxQueue {
+        IceRxQueue {
+            descriptors: ring.virt,
+            ring,
+            num_descriptors: NUM_RX_QUEUE_ENTRIES,
+            pool,
+            bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
+            rx_index: 0,
+            rx_tail: 0,
+            num_posted: NUM_RX_QUEUE_ENTRIES - 1,
+        }
+    }
+
+    /// Attaches a buffer of the mempool to every descriptor and returns the tail that posts
+    /// `num_posted` of them to the device.
+    fn fill(&mut self) -> Result<usize, IxyError> {
+        for i in 0..self.num_descriptors {
+            let buf = self.pool.alloc_buf().ok_or(IxyError::PoolExhausted)?;
+
+            unsafe {
+                write_rx_desc(self.descriptors.add(i), self.pool.get_phys_addr(buf));
+            }
+
+            // we need to remember which descriptor entry belongs to which mempool entry
+            self.bufs_in_use.push(buf);
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = self.num_posted;
+
+        Ok(self.rx_tail)
+    }
+
+    /// Returns the size of the buffers the device may write to, the mempool's entries without
+    /// the headroom in units of 128 bytes.
+    fn buffer_size(&self) -> usize {
+        let data_size = (self.pool.entry_size() - self.pool.headroom()).min(MAX_RX_BUFFER_SIZE);
+
+        data_size & !((1 << I40E_RXQ_CTX_DBUFF_SHIFT) - 1)
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills their
+    /// descriptors. Returns the number of received packets and the new tail of the ring if
+    /// refilled descriptors have to be handed back to the device.
+    fn receive(
+        &mut self,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> (usize, Option<usize>) {
+        let mut rx_index = self.rx_index;
+        let mut received_packets = 0;
+
+        {
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
+
+            while received_packets < num_packets {
+                // frames larger than a buffer span several descriptors, only take them once the
+                // device has written back all of them
+                let mut num_segments = 0;
+                let mut status = 0;
+                while (status & ICE_RX_FLEX_DESC_STATUS0_EOF) == 0 {
+                    let index = (rx_index + num_segments) % self.num_descriptors;
+                    status = unsafe { rx_desc_status(self, index) };
+
+                    if (status & ICE_RX_FLEX_DESC_STATUS0_DD) == 0 {
+                        break;
+                    }
+                    num_segments += 1;
+                }
+
+                if (status & ICE_RX_FLEX_DESC_STATUS0_DD) == 0 {
+                    break;
+                }
+
+                // the frame stays in the ring until the descriptors can be refilled without
+                // the buffers reserved for clones
+                if free_stack.available() < num_segments {
+                    break;
+                }
+
+                let pool = &self.pool;
+                let mut packet: Option<Packet> = None;
+
+                for _ in 0..num_segments {
+                    let len = unsafe { rx_desc_len(self, rx_index) };
+
+                    // replace currently used buffer with a free buffer of the mempool
+                    let new_buf = free_stack.pop().expect("no buffer available");
+                    let buf = mem::replace(&mut self.bufs_in_use[rx_index], new_buf);
+
+                    let p = unsafe {
+                        Packet {
+                            addr_virt: pool.get_virt_addr(buf),
+                            addr_phys: pool.get_phys_addr(buf),
+                            len,
+                            pool: pool.clone(),
+                            pool_entry: buf,
+                            next: None,
+                            meta: PacketMeta::default(),
+                        }
+                    };
+
+                    match packet {
+                        Some(ref mut packet) => packet.chain(p),
+                        None => packet = Some(p),
+                    }
+
+                    unsafe {
+                        write_rx_desc(self.descriptors.add(rx_index), pool.get_phys_addr(new_buf));
+                    }
+
+                    rx_index = (rx_index + 1) % self.num_descriptors;
+                }
+
+                #[allow(unused_mut)]
+                let mut p = packet.unwrap();
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                buffer.push_back(p);
+                received_packets += 1;
+            }
+        }
+
+        let mut tail = None;
+
+        if rx_index != self.rx_index {
+            self.rx_index = rx_index;
+
+            // hand refilled descriptors back to the device, at most num_posted at a time
+            let posted = (self.rx_tail + self.num_descriptors - rx_index) % self.num_descriptors;
+            if posted < self.num_posted {
+                self.rx_tail = (rx_index + self.num_posted) % self.num_descriptors;
+                tail = Some(self.rx_tail);
+            }
+        }
+
+        (received_packets, tail)
+    }
+
+    /// Returns the content of the next received packet without taking it off the ring.
+    fn peek(&self) -> Option<&[u8]> {
+        if self.bufs_in_use.is_empty() {
+            return None;
+        }
+
+        let status = unsafe { rx_desc_status(self, self.rx_index) };
+        if (status & ICE_RX_FLEX_DESC_STATUS0_DD) == 0 {
+            return None;
+        }
+
+        unsafe {
+            let len = rx_desc_len(self, self.rx_index);
+            let addr = self.pool.get_virt_addr(self.bufs_in_use[self.rx_index]);
+
+            Some(slice::from_raw_parts(addr, len))
+        }
+    }
+
+    /// Limits the descriptors the device may receive into to `num_posted`, returns the new tail
+    /// if more descriptors have to be handed to the device right away.
+    fn set_posted(&mut self, num_posted: usize) -> Result<Option<usize>, IxyError> {
+        if num_posted >= self.num_descriptors {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                self.num_descriptors,
+                self.num_descriptors - 1
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // the tail never moves backwards, lowering the limit takes effect as packets arrive
+        let posted = (self.rx_tail + self.num_descriptors - self.rx_index) % self.num_descriptors;
+        if posted >= num_posted || self.bufs_in_use.is_empty() {
+            return Ok(None);
+        }
+
+        self.rx_tail = (self.rx_index + num_posted) % self.num_descriptors;
+
+        Ok(Some(self.rx_tail))
+    }
+
+    /// Returns all buffers of the ring to the mempool, the device must not access them anymore.
+    fn drain(&mut self) {
+        for buf in self.bufs_in_use.drain(..) {
+            self.pool.free_buf(buf);
+        }
+
+        // clear the descriptor done bits so rx_batch doesn't pick up the freed buffers
+        unsafe {
+            memset(
+                self.descriptors as *mut u8,
+                self.num_descriptors * mem::size_of::<RxDesc>(),
+                0x00,
+            );
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = 0;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<RxDesc>(),
+        )
+    }
+}
+
+impl IxyDevice for IceDevice {
+    /// Returns an initialized `IceDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(pci_addr: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<IceDevice, IxyError> {
+        IceDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac.get()
+    }
+
+    /// Sets the mac address of this device, the switch rule of the old address is replaced
+    /// by one for the new address.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        let old_rule = self.unicast_filters.borrow()[0].map(|(_, rule)| rule);
+
+        let result = old_rule
+            .map_or(Ok(()), |rule| self.remove_rx_rule(ICE_SW_LKUP_MAC, rule))
+            .and_then(|_| self.add_rx_rule(ICE_SW_LKUP_MAC, Some(mac)))
+            .and_then(|rule| {
+                self.unicast_filters.borrow_mut()[0] = Some((mac, rule));
+                self.write_mac_addr(mac)
+            });
+
+        match result {
+            Ok(()) => self.mac.set(mac),
+            Err(e) => warn!("failed to set mac address of {}: {}", self.pci_addr, e),
+        }
+    }
+
+    /// Adds a switch rule forwarding packets for `mac` to this device and returns its index.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        let index = (1..NUM_UNICAST_FILTERS)
+            .find(|&i| self.unicast_filters.borrow()[i].is_none())
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration("all unicast filters are in use".to_string())
+            })?;
+
+        let rule = self.add_rx_rule(ICE_SW_LKUP_MAC, Some(mac))?;
+        self.unicast_filters.borrow_mut()[index] = Some((mac, rule));
+
+        Ok(index)
+    }
+
+    /// Removes the switch rule of unicast filter `index`.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        let rule = match self.unicast_filters.borrow().get(index) {
+            Some(&Some((_, rule))) if index > 0 => rule,
+            _ => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "unicast filter {} is not in use",
+                    index
+                )))
+            }
+        };
+
+        self.remove_rx_rule(ICE_SW_LKUP_MAC, rule)?;
+        self.unicast_filters.borrow_mut()[index] = None;
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (received, tail) = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if let Some(tail) = tail {
+            self.set_reg32(ICE_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the device was removed, i.e. its reset status register reads as all
+    /// ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(I40E_GLGEN_RSTAT) == ICE_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(num_posted)? {
+            self.set_reg32(ICE_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(
+                ICE_QTX_COMM_DBELL_BASE + 4 * queue_id,
+                self.tx_queues[queue_id as usize].tail() as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of the port of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_pkts += current.rx_pkts.wrapping_sub(last.rx_pkts) & COUNTER_48_MASK;
+        stats.tx_pkts += current.tx_pkts.wrapping_sub(last.tx_pkts) & COUNTER_48_MASK;
+        stats.rx_bytes += current.rx_bytes.wrapping_sub(last.rx_bytes) & COUNTER_48_MASK;
+        stats.tx_bytes += current.tx_bytes.wrapping_sub(last.tx_bytes) & COUNTER_48_MASK;
+
+        self.counters.set(current);
+    }
+
+    /// Resets the stats of the port of this device.
+    fn reset_stats(&self) {
+        let mut counters = self.read_counters();
+
+        for (i, &(reg, mask)) in EXTENDED_COUNTERS.iter().enumerate() {
+            counters.extended[i] = self.read_counter(reg, mask);
+        }
+
+        self.counters.set(counters);
+    }
+
+    /// Reads the extended stats of the port of this device into `stats`.
+    ///
+    /// The E810 counts packets dropped for a lack of descriptors per queue, they are not part
+    /// of the stats.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        let mut last = self.counters.get();
+        let mut delta = [0; EXTENDED_COUNTERS.len()];
+
+        for (i, &(reg, mask)) in EXTENDED_COUNTERS.iter().enumerate() {
+            let value = self.read_counter(reg, mask);
+            delta[i] = value.wrapping_sub(last.extended[i]) & mask;
+            last.extended[i] = value;
+        }
+
+        stats.rx_crc_errors += delta[0];
+        stats.rx_illegal_byte_errors += delta[1];
+        stats.rx_length_errors += delta[2];
+        stats.rx_undersize += delta[3];
+        stats.rx_fragments += delta[4];
+        stats.rx_oversize += delta[5];
+        stats.rx_jabbers += delta[6];
+        stats.rx_broadcast += delta[7];
+        stats.rx_multicast += delta[8];
+        stats.tx_broadcast += delta[9];
+        stats.tx_multicast += delta[10];
+        stats.rx_xon += delta[11];
+        stats.rx_xoff += delta[12];
+        stats.tx_xon += delta[13];
+        stats.tx_xoff += delta[14];
+        stats.mac_local_faults += delta[15];
+        stats.mac_remote_faults += delta[16];
+
+        self.counters.set(last);
+    }
+
+    /// Returns the link speed of this device as reported by the firmware.
+    fn get_link_speed(&self) -> u32 {
+        let status = match self.get_link_status() {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("failed to get link status of {}: {}", self.pci_addr, e);
+                return 0;
+            }
+        };
+
+        if (status[2] & ICE_AQ_LINK_UP) == 0 {
+            return 0;
+        }
+
+        // a single bit is set for the current speed
+        let speed = get_u16(&status, 10);
+        ICE_LINK_SPEEDS
+            .iter()
+            .enumerate()
+            .find(|&(i, _)| speed & (1 << i) != 0)
+            .map_or(0, |(_, &speed)| speed)
+    }
+
+    /// Returns the PCIe link of this device.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        self.check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        while self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues -= 1;
+            self.stop_rx_queue(u32::from(self.num_rx_queues))?;
+            self.rx_queues.pop();
+        }
+
+        while self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues -= 1;
+            self.stop_tx_queue(u32::from(self.num_tx_queues))?;
+            self.tx_teids.pop();
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                queue.reset();
+            }
+        }
+
+        while self.num_rx_queues < num_rx_queues {
+            let queue_id = u32::from(self.num_rx_queues);
+            self.init_rx_queue(queue_id)?;
+            self.start_rx_queue(queue_id)?;
+            self.num_rx_queues += 1;
+        }
+
+        while self.num_tx_queues < num_tx_queues {
+            let queue_id = u32::from(self.num_tx_queues);
+            self.init_tx_queue(queue_id)?;
+            self.start_tx_queue(queue_id)?;
+            self.num_tx_queues += 1;
+        }
+
+        self.update_vsi_queues()
+    }
+
+    /// Enables rx queue `queue_id`.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].bufs_in_use.is_empty() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        self.set_flags32(ICE_QRX_CTRL_BASE + 4 * queue_id, ICE_QRX_CTRL_QENA_REQ);
+        self.wait_rx_queue(queue_id, true)
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id)
+    }
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(ICE_QRX_CTRL_BASE + 4 * queue_id) & ICE_QRX_CTRL_QENA_STAT) != 0
+    }
+
+    /// Tx queues of the E810 are enabled by adding them to the scheduler, which resets them.
+    fn enable_tx_queue(&self, _queue_id: u32) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} enables tx queues only with reset_tx_queue",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Disables tx queue `queue_id` by removing it from the scheduler, use `reset_tx_queue` to
+    /// enable it again.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue(queue_id)
+    }
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        self.tx_teids
+            .get(queue_id as usize)
+            .is_some_and(|teid| teid.get().is_some())
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.tx_head(queue_id);
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Removes tx queue `queue_id` from the scheduler, drops all pending packets and adds it
+    /// again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue(queue_id)?;
+        self.tx_queues[queue_id as usize].reset();
+
+        self.start_tx_queue(queue_id)
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.tx_head(queue_id) != self.tx_queues[queue_id as usize].tail() {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// The E810 always drops packets for an rx queue without free descriptors.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} cannot keep packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Flow control is not supported by this driver yet, the PHY keeps the pause abilities
+    /// configured by the firmware.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "{} does not support setting flow control mode {:?}",
+            DRIVER_NAME, mode
+        );
+    }
+
+    /// Returns the flow control mode negotiated on the link.
+    fn get_flow_control(&self) -> FlowControl {
+        let an_info = match self.get_link_status() {
+            Ok(status) => status[3],
+            Err(e) => {
+                warn!("failed to get link status of {}: {}", self.pci_addr, e);
+                return FlowControl::None;
+            }
+        };
+
+        let rx_pause = (an_info & ICE_AQ_LINK_PAUSE_RX) != 0;
+        let tx_pause = (an_info & ICE_AQ_LINK_PAUSE_TX) != 0;
+
+        match (rx_pause, tx_pause) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Sets whether the rx queues strip the Ethernet FCS, the setting is part of the queue
+    /// contexts so all rx queues are drained and restarted.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        self.crc_strip = enable;
+
+        for queue_id in 0..u32::from(self.num_rx_queues) {
+            self.drain_rx_queue(queue_id)?;
+            self.start_rx_queue(queue_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.crc_strip
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    ///
+    /// The E810 receives into buffers of a multiple of 128 bytes, `buffer_size` is rounded up
+    /// accordingly.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        let buffer_size = buffer_size.next_multiple_of(1 << I40E_RXQ_CTX_DBUFF_SHIFT);
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].pool = pool;
+
+        self.start_rx_queue(queue_id)
+    }
+
+    /// Reads the 16 bit word at `offset` of this device's shadow RAM via the firmware, words
+    /// that cannot be read read as 0xffff.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the shadow RAM.
+    fn read_eeprom_word(&self, offset: u16) -> u16 {
+        assert!(
+            u32::from(offset) < self.shadow_ram_words(),
+            "eeprom access out of bounds"
+        );
+
+        let mut word = [0; 2];
+        let result = self.with_nvm(|| {
+            let mut desc = AqDesc::new(ICE_AQC_OPC_NVM_READ);
+            put_u16(&mut desc.params, 0, offset.wrapping_mul(2));
+            desc.params[2] = (offset >> 15) as u8;
+            desc.params[3] = ICE_AQC_NVM_LAST_CMD;
+            put_u16(&mut desc.params, 6, word.len() as u16);
+
+            self.aq_command(desc, Some(&mut word)).map(|_| ())
+        });
+
+        match result {
+            Ok(()) => u16::from_le_bytes(word),
+            Err(e) => {
+                warn!("failed to read eeprom of {}: {}", self.pci_addr, e);
+                0xFFFF
+            }
+        }
+    }
+
+    /// Returns the version of the firmware, major, minor and patch version in the lower three
+    /// bytes.
+    fn get_firmware_version(&self) -> u32 {
+        self.fw_version
+    }
+
+    /// Returns whether the firmware verified the checksum of this device's shadow RAM.
+    fn validate_eeprom_checksum(&self) -> bool {
+        let result = self.with_nvm(|| {
+            let mut desc = AqDesc::new(ICE_AQC_OPC_NVM_CHECKSUM);
+            desc.params[0] = ICE_AQC_NVM_CHECKSUM_VERIFY;
+
+            self.aq_command(desc, None)
+                .map(|completion| get_u16(&completion.params, 2))
+        });
+
+        match result {
+            Ok(checksum) => checksum == ICE_AQC_NVM_CHECKSUM_CORRECT,
+            Err(e) => {
+                warn!(
+                    "failed to verify eeprom checksum of {}: {}",
+                    self.pci_addr, e
+                );
+                false
+            }
+        }
+    }
+
+    /// The E810 reports its temperature only to the firmware.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the global, admin queue and per-queue registers of this device.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("PFGEN_CTRL", ICE_PFGEN_CTRL),
+            ("GLGEN_RSTAT", I40E_GLGEN_RSTAT),
+            ("PF_FUNC_RID", ICE_PF_FUNC_RID),
+            ("PFGEN_PORTNUM", ICE_PFGEN_PORTNUM),
+            ("PFLAN_RX_QALLOC", ICE_PFLAN_RX_QALLOC),
+            ("PFLAN_TX_QALLOC", ICE_PFLAN_TX_QALLOC),
+            ("PF_FW_ATQLEN", I40E_PF_ATQLEN),
+            ("PF_FW_ATQH", I40E_PF_ATQH),
+            ("PF_FW_ATQT", I40E_PF_ATQT),
+            ("PF_FW_ARQLEN", I40E_PF_ARQLEN),
+            ("PF_FW_ARQH", I40E_PF_ARQH),
+            ("PF_FW_ARQT", I40E_PF_ARQT),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            dump.push(
+                format!("QRX_CTRL[{}]", i),
+                self.get_reg32(ICE_QRX_CTRL_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QRX_TAIL[{}]", i),
+                self.get_reg32(ICE_QRX_TAIL_BASE + 4 * i),
+            );
+            dump.push(
+                format!("QRXFLXP_CNTXT[{}]", i),
+                self.get_reg32(ICE_QRXFLXP_CNTXT_BASE + 4 * i),
+            );
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            dump.push(
+                format!("QTX_COMM_HEAD[{}]", i),
+                self.get_reg32(ICE_QTX_COMM_HEAD_BASE + 4 * i),
+            );
+        }
+
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+        self.tx_teids.clear();
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for IceDevice {
+    fn drop(&mut self) {
+        // tell the firmware the driver is gone before the admin queue is unmapped
+        let mut shutdown = AqDesc::new(I40E_AQC_OPC_QUEUE_SHUTDOWN);
+        put_u32(&mut shutdown.params, 0, I40E_AQ_DRIVER_UNLOADING);
+        if let Err(e) = self.aq_command(shutdown, None) {
+            warn!(
+                "failed to shut down admin queue of {}: {}",
+                self.pci_addr, e
+            );
+        }
+
+        // stop all dma before the descriptor rings and mempools are unmapped, the firmware
+        // removes the VSI, its switch rules and scheduler nodes as well
+        self.set_reg32(ICE_PFGEN_CTRL, ICE_PFGEN_CTRL_PFSWR);
+        if let Err(e) = self.wait_clear_reg32(ICE_PFGEN_CTRL, ICE_PFGEN_CTRL_PFSWR) {
+            warn!("failed to reset {}: {}", self.pci_addr, e);
+        }
+    }
+}
+
+impl IceDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == INTEL_VENDOR_ID && SUPPORTED_DEVICE_IDS.contains(&device_id)
+    }
+
+    /// Returns an initialized `IceDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<IceDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory, the admin
+        // queue takes a huge page
+        check_privileges(
+            pci_addr,
+            vfio,
+            dma_size(num_rx_queues, num_tx_queues) + HUGE_PAGE_SIZE,
+        )?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            pci_map_resource(pci_addr)?
+        };
+
+        let aq_mem = allocate_dma(allocator.as_ref(), numa_node, AQ_MEM_SIZE)?;
+
+        let mut dev = IceDevice {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            tx_teids: Vec::with_capacity(num_tx_queues as usize),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            admin_queue: RefCell::new(AdminQueue {
+                mem: aq_mem,
+                next: 0,
+            }),
+            pf_id: 0,
+            port: 0,
+            lport: 0,
+            sw_id: 0,
+            max_rx_queues: 0,
+            max_tx_queues: 0,
+            vsi_num: 0,
+            qgroup_teid: 0,
+            fw_version: 0,
+            crc_strip: true,
+            mac: Cell::new([0; 6]),
+            unicast_filters: RefCell::new(vec![None; NUM_UNICAST_FILTERS]),
+            counters: Cell::new(PortCounters::default()),
+            removal: RemovalLatch::new(I40E_GLGEN_RSTAT),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets the physical function, sets up the admin queue, creates a VSI with its scheduler
+    /// nodes and initializes its queues.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        // the driver polls, mask all interrupts
+        self.set_reg32(ICE_PFINT_OICR_ENA, 0);
+
+        self.pf_reset()?;
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        info!("initializing device {}", self.pci_addr);
+
+        self.init_admin_queue();
+
+        let version = self.aq_command(AqDesc::new(I40E_AQC_OPC_GET_VERSION), None)?;
+        let (fw_major, fw_minor, fw_patch) =
+            (version.params[9], version.params[10], version.params[11]);
+        self.fw_version =
+            (u32::from(fw_major) << 16) | (u32::from(fw_minor) << 8) | u32::from(fw_patch);
+        info!(
+            "firmware version {}.{}.{}, api version {}.{}.{}",
+            fw_major,
+            fw_minor,
+            fw_patch,
+            version.params[13],
+            version.params[14],
+            version.params[15]
+        );
+
+        // a device booted via PXE keeps its rx queues for the firmware until told otherwise
+        let mut clear_pxe = AqDesc::new(I40E_AQC_OPC_CLEAR_PXE_MODE);
+        clear_pxe.params[0] = I40E_AQ_CLEAR_PXE_RX_CNT;
+        self.aq_command(clear_pxe, None)?;
+
+        self.pf_id = self.get_reg32(ICE_PF_FUNC_RID) & ICE_PF_FUNC_RID_FUNCTION_NUMBER_MASK;
+        self.port = self.get_reg32(ICE_PFGEN_PORTNUM) & ICE_PFGEN_PORTNUM_PORT_NUM_MASK;
+
+        self.max_rx_queues = self.read_qalloc(ICE_PFLAN_RX_QALLOC)?;
+        self.max_tx_queues = self.read_qalloc(ICE_PFLAN_TX_QALLOC)?;
+
+        self.check_num_queues(self.num_rx_queues, self.num_tx_queues)?;
+
+        let mac = self.read_mac_addr()?;
+        self.mac.set(mac);
+
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        self.init_switch()?;
+        self.add_vsi()?;
+        self.qgroup_teid = self.init_scheduler()?;
+
+        // statistic counters of the port keep counting across resets
+        self.reset_stats();
+
+        // accept jumbo frames
+        let mut mac_config = AqDesc::new(I40E_AQC_OPC_SET_MAC_CONFIG);
+        put_u16(&mut mac_config.params, 0, I40E_MAX_FRAME_SIZE as u16);
+        self.aq_command(mac_config, None)?;
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            self.init_rx_queue(i)?;
+            self.start_rx_queue(i)?;
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            self.init_tx_queue(i)?;
+            self.start_tx_queue(i)?;
+        }
+
+        // forward packets for our mac address, the filter can't be removed
+        let rule = self.add_rx_rule(ICE_SW_LKUP_MAC, Some(mac))?;
+        let mut filters = vec![None; NUM_UNICAST_FILTERS];
+        filters[0] = Some((mac, rule));
+        self.unicast_filters = RefCell::new(filters);
+
+        // enable promisc mode by default to make testing easier, the VSI becomes the default
+        // for all packets no other rule matches
+        self.add_rx_rule(ICE_SW_LKUP_DFLT, None)?;
+
+        let mut restart_an = AqDesc::new(I40E_AQC_OPC_SET_LINK_RESTART_AN);
+        restart_an.params[0] = self.lport;
+        restart_an.params[2] = ICE_AQ_PHY_RESTART_AN | ICE_AQ_PHY_LINK_ENABLE;
+        self.aq_command(restart_an, None)?;
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Resets the physical function once the firmware finished a pending core or global
+    /// reset.
+    fn pf_reset(&self) -> Result<(), IxyError> {
+        let start = Instant::now();
+        let done = I40E_GLNVM_ULD_CORER_DONE | I40E_GLNVM_ULD_GLOBR_DONE;
+
+        while self.get_reg32(I40E_GLGEN_RSTAT) & I40E_GLGEN_RSTAT_DEVSTATE_MASK != 0
+            || self.get_reg32(I40E_GLNVM_ULD) & done != done
+        {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "firmware of {} is not ready, is a global reset stuck?",
+                    self.pci_addr
+                )));
+            }
+            thread::sleep(RESET_POLL_DELAY);
+        }
+
+        self.set_flags32(ICE_PFGEN_CTRL, ICE_PFGEN_CTRL_PFSWR);
+        self.wait_clear_reg32(ICE_PFGEN_CTRL, ICE_PFGEN_CTRL_PFSWR)
+    }
+
+    /// Sets up the admin send and receive queue, every receive descriptor gets a buffer for
+    /// events of the firmware.
+    fn init_admin_queue(&self) {
+        let mut aq = self.admin_queue.borrow_mut();
+
+        unsafe {
+            memset(aq.mem.virt, AQ_MEM_SIZE, 0);
+        }
+
+        let phys = aq.mem.phys;
+        for i in 0..AQ_LEN {
+            let buf_phys = (phys + ARQ_BUFS_OFFSET + i * AQ_BUF_SIZE) as u64;
+
+            let mut desc = AqDesc {
+                flags: I40E_AQ_FLAG_BUF | I40E_AQ_FLAG_LB,
+                datalen: AQ_BUF_SIZE as u16,
+                ..Default::default()
+            };
+            put_u32(&mut desc.params, 8, (buf_phys >> 32) as u32);
+            put_u32(&mut desc.params, 12, buf_phys as u32);
+
+            unsafe {
+                write_aq_desc(aq.arq().add(i), &desc);
+            }
+        }
+        aq.next = 0;
+
+        let atq_phys = (phys + ATQ_OFFSET) as u64;
+        self.set_reg32(I40E_PF_ATQH, 0);
+        self.set_reg32(I40E_PF_ATQT, 0);
+        self.set_reg32(I40E_PF_ATQBAL, atq_phys as u32);
+        self.set_reg32(I40E_PF_ATQBAH, (atq_phys >> 32) as u32);
+        self.set_reg32(I40E_PF_ATQLEN, AQ_LEN as u32 | I40E_PF_ATQLEN_ATQENABLE);
+
+        let arq_phys = (phys + ARQ_OFFSET) as u64;
+        self.set_reg32(I40E_PF_ARQH, 0);
+        self.set_reg32(I40E_PF_ARQT, 0);
+        self.set_reg32(I40E_PF_ARQBAL, arq_phys as u32);
+        self.set_reg32(I40E_PF_ARQBAH, (arq_phys >> 32) as u32);
+        self.set_reg32(I40E_PF_ARQLEN, AQ_LEN as u32 | I40E_PF_ARQLEN_ARQENABLE);
+
+        // all event buffers are posted to the firmware
+        self.set_reg32(I40E_PF_ARQT, AQ_LEN as u32 - 1);
+    }
+
+    /// Sends `desc` to the firmware and returns the descriptor written back on completion. The
+    /// firmware reads its input from and writes its output to `buffer` if the command has one.
+    fn aq_command(&self, desc: AqDesc, buffer: Option<&mut [u8]>) -> Result<AqDesc, IxyError> {
+        let mut aq = self.admin_queue.borrow_mut();
+        let mut desc = desc;
+        let index = aq.next;
+
+        desc.flags |= I40E_AQ_FLAG_SI;
+
+        if let Some(ref buf) = buffer {
+            assert!(buf.len() <= AQ_BUF_SIZE, "admin queue buffer too large");
+
+            unsafe {
+                ptr::copy_nonoverlapping(buf.as_ptr(), aq.buffer(), buf.len());
+            }
+
+            let buf_phys = (aq.mem.phys + AQ_BUF_OFFSET) as u64;
+            desc.flags |= I40E_AQ_FLAG_BUF;
+            if buf.len() > AQ_LARGE_BUF {
+                desc.flags |= I40E_AQ_FLAG_LB;
+            }
+            desc.datalen = buf.len() as u16;
+            put_u32(&mut desc.params, 8, (buf_phys >> 32) as u32);
+            put_u32(&mut desc.params, 12, buf_phys as u32);
+        }
+
+        unsafe {
+            write_aq_desc(aq.atq().add(index), &desc);
+        }
+
+        aq.next = (index + 1) % AQ_LEN;
+        self.set_reg32(I40E_PF_ATQT, aq.next as u32);
+
+        // the firmware moves the head past the descriptor once it wrote back the completion
+        let start = Instant::now();
+        while self.get_reg32(I40E_PF_ATQH) as usize != aq.next {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > AQ_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "admin queue command {:#06x} of {} timed out",
+                    desc.opcode, self.pci_addr
+                )));
+            }
+            thread::sleep(AQ_POLL_DELAY);
+        }
+
+        let completion = unsafe { read_aq_desc(aq.atq().add(index)) };
+
+        if completion.flags & I40E_AQ_FLAG_DD == 0 || completion.flags & I40E_AQ_FLAG_ERR != 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "admin queue command {:#06x} of {} failed with error {}",
+                desc.opcode, self.pci_addr, completion.retval
+            )));
+        }
+
+        if let Some(buf) = buffer {
+            unsafe {
+                ptr::copy_nonoverlapping(aq.buffer(), buf.as_mut_ptr(), buf.len());
+            }
+        }
+
+        Ok(completion)
+    }
+
+    /// Returns the number of LAN queues the queue allocation register `reg` assigns to the
+    /// physical function, the first of them is queue 0 of the function's registers.
+    fn read_qalloc(&self, reg: u32) -> Result<u16, IxyError> {
+        let qalloc = self.get_reg32(reg);
+        if qalloc & ICE_PFLAN_QALLOC_VALID == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "no LAN queues are assigned to {}",
+                self.pci_addr
+            )));
+        }
+
+        let first = qalloc & ICE_PFLAN_QALLOC_FIRSTQ_MASK;
+        let last = (qalloc >> ICE_PFLAN_QALLOC_LASTQ_SHIFT) & ICE_PFLAN_QALLOC_LASTQ_MASK;
+
+        Ok((last - first + 1) as u16)
+    }
+
+    /// Returns the LAN mac address of the port stored by the firmware.
+    fn read_mac_addr(&self) -> Result<[u8; 6], IxyError> {
+        let mut addrs = [0; 2 * ICE_AQC_MAN_MAC_READ_SIZE];
+        let desc = self.aq_command(AqDesc::new(I40E_AQC_OPC_MAC_ADDRESS_READ), Some(&mut addrs))?;
+
+        let num_addrs = usize::from(desc.params[4]).min(2);
+        addrs
+            .chunks(ICE_AQC_MAN_MAC_READ_SIZE)
+            .take(num_addrs)
+            .find(|addr| addr[1] == ICE_AQC_MAN_MAC_ADDR_TYPE_LAN)
+            .map(|addr| {
+                let mut mac = [0; 6];
+                mac.copy_from_slice(&addr[2..8]);
+                mac
+            })
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(format!(
+                    "firmware of {} has no LAN mac address",
+                    self.pci_addr
+                ))
+            })
+    }
+
+    /// Stores `mac` as the locally administered address of the port.
+    fn write_mac_addr(&self, mac: [u8; 6]) -> Result<(), IxyError> {
+        let mut desc = AqDesc::new(I40E_AQC_OPC_MAC_ADDRESS_WRITE);
+        desc.params[2..8].copy_from_slice(&mac);
+
+        self.aq_command(desc, None).map(|_| ())
+    }
+
+    /// Looks up the logical port and the switch of the physical function.
+    fn init_switch(&mut self) -> Result<(), IxyError> {
+        let mut config = [0; AQ_BUF_SIZE];
+        let desc = self.aq_command(
+            AqDesc::new(I40E_AQC_OPC_GET_SWITCH_CONFIG),
+            Some(&mut config),
+        )?;
+
+        let num_elems = usize::from(get_u16(&desc.params, 4));
+        let port = config
+            .chunks(ICE_AQC_GET_SW_CONF_RESP_SIZE)
+            .take(num_elems)
+            .find(|elem| {
+                get_u16(elem, 0) >> ICE_AQC_GET_SW_CONF_RESP_TYPE_SHIFT
+                    == ICE_AQC_GET_SW_CONF_RESP_PHYS_PORT
+                    && get_u16(elem, 4) & ICE_AQC_GET_SW_CONF_RESP_IS_VF == 0
+            })
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(format!(
+                    "switch of {} has no port for the physical function",
+                    self.pci_addr
+                ))
+            })?;
+
+        self.lport = (get_u16(port, 0) & ICE_LPORT_MASK) as u8;
+        self.sw_id = get_u16(port, 2) as u8;
+
+        debug!("logical port {}, switch {}", self.lport, self.sw_id);
+
+        Ok(())
+    }
+
+    /// Creates the VSI of the physical function on the switch of the port.
+    fn add_vsi(&mut self) -> Result<(), IxyError> {
+        let mut properties = [0; VSI_PROPERTIES_SIZE];
+        put_u16(
+            &mut properties,
+            VSI_PROP_VALID_SECTIONS,
+            ICE_AQ_VSI_PROP_SW_VALID | I40E_AQ_VSI_PROP_QUEUE_MAP_VALID,
+        );
+        properties[VSI_PROP_SW_ID] = self.sw_id;
+        properties[VSI_PROP_SW_FLAGS2] = ICE_AQ_VSI_SW_FLAG_LAN_ENA;
+        self.write_vsi_queue_map(&mut properties);
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_ADD_VSI);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 6, ICE_AQ_VSI_TYPE_PF);
+
+        let completion = self.aq_command(desc, Some(&mut properties))?;
+        self.vsi_num = get_u16(&completion.params, 0) & ICE_AQ_VSI_NUM_MASK;
+
+        debug!("added VSI {}", self.vsi_num);
+
+        Ok(())
+    }
+
+    /// Maps the configured queues contiguously to the first traffic class of the VSI.
+    fn update_vsi_queues(&self) -> Result<(), IxyError> {
+        let mut properties = [0; VSI_PROPERTIES_SIZE];
+        put_u16(
+            &mut properties,
+            VSI_PROP_VALID_SECTIONS,
+            I40E_AQ_VSI_PROP_QUEUE_MAP_VALID,
+        );
+        self.write_vsi_queue_map(&mut properties);
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_UPDATE_VSI_PARAMETERS);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, self.vsi_num | ICE_AQ_VSI_IS_VALID);
+
+        self.aq_command(desc, Some(&mut properties)).map(|_| ())
+    }
+
+    /// Writes the queue mapping section for the configured queues to the VSI `properties`.
+    fn write_vsi_queue_map(&self, properties: &mut [u8]) {
+        let num_queues = self.num_rx_queues.max(self.num_tx_queues).max(1);
+        // the traffic class holds a power of 2 queues
+        let num_queues_log2 = num_queues.next_power_of_two().trailing_zeros() as u16;
+
+        put_u16(
+            properties,
+            VSI_PROP_MAPPING_FLAGS,
+            I40E_AQ_VSI_QUE_MAP_CONTIG,
+        );
+        put_u16(properties, VSI_PROP_QUEUE_MAPPING, 0);
+        put_u16(properties, VSI_PROP_QUEUE_MAPPING + 2, num_queues);
+        put_u16(
+            properties,
+            VSI_PROP_TC_MAPPING,
+            num_queues_log2 << ICE_AQ_VSI_TC_Q_NUM_SHIFT,
+        );
+    }
+
+    /// Adds a rule forwarding packets received on the port to the VSI and returns its index.
+    /// Rules of the MAC recipe match packets for `mac`, the default recipe matches packets no
+    /// other rule forwards.
+    fn add_rx_rule(&self, recipe: u16, mac: Option<[u8; 6]>) -> Result<u16, IxyError> {
+        let mut rule = [0; ICE_SW_RULE_NO_HDR_SIZE + DUMMY_ETH_HDR.len()];
+        put_u16(&mut rule, 0, ICE_AQC_SW_RULES_T_LKUP_RX);
+        put_u16(&mut rule, 4, recipe);
+        put_u16(&mut rule, 6, u16::from(self.lport));
+        put_u32(
+            &mut rule,
+            8,
+            ICE_SINGLE_ACT_VSI_FORWARDING
+                | (u32::from(self.vsi_num) << ICE_SINGLE_ACT_VSI_ID_SHIFT)
+                | ICE_SINGLE_ACT_VALID_BIT,
+        );
+        put_u16(&mut rule, 14, DUMMY_ETH_HDR.len() as u16);
+        rule[ICE_SW_RULE_NO_HDR_SIZE..].copy_from_slice(&DUMMY_ETH_HDR);
+        if let Some(mac) = mac {
+            rule[ICE_SW_RULE_NO_HDR_SIZE..ICE_SW_RULE_NO_HDR_SIZE + 6].copy_from_slice(&mac);
+        }
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_ADD_SW_RULES);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, 1);
+        self.aq_command(desc, Some(&mut rule))?;
+
+        // the firmware writes back the index of the new rule
+        Ok(get_u16(&rule, 12))
+    }
+
+    /// Removes the rule `index` of recipe `recipe` added by `add_rx_rule`.
+    fn remove_rx_rule(&self, recipe: u16, index: u16) -> Result<(), IxyError> {
+        let mut rule = [0; ICE_SW_RULE_NO_HDR_SIZE];
+        put_u16(&mut rule, 0, ICE_AQC_SW_RULES_T_LKUP_RX);
+        put_u16(&mut rule, 4, recipe);
+        put_u16(&mut rule, 6, u16::from(self.lport));
+        put_u16(&mut rule, 12, index);
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_REMOVE_SW_RULES);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, 1);
+
+        self.aq_command(desc, Some(&mut rule)).map(|_| ())
+    }
+
+    /// Adds a chain of scheduler nodes below the default topology of the first traffic class
+    /// down to the queue group layer and returns the node the tx queues are attached to.
+    fn init_scheduler(&self) -> Result<u32, IxyError> {
+        let mut resources = [0; SCHED_RES_SIZE];
+        self.aq_command(
+            AqDesc::new(ICE_AQC_OPC_QUERY_SCHED_RES),
+            Some(&mut resources),
+        )?;
+        let num_layers = usize::from(get_u16(&resources, 2));
+
+        let mut topology = [0; AQ_BUF_SIZE];
+        let mut desc = AqDesc::new(ICE_AQC_OPC_GET_DFLT_TOPO);
+        desc.params[0] = self.lport;
+        let completion = self.aq_command(desc, Some(&mut topology))?;
+
+        if completion.params[1] == 0 || num_layers <= ICE_QGRP_LAYER_OFFSET {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx scheduler of {} has no default topology",
+                self.pci_addr
+            )));
+        }
+
+        // the first branch belongs to the first traffic class, its nodes lead from the root
+        // down to the entry point for VSIs, maybe followed by a leaf
+        let element = |i: usize| &topology[SCHED_TOPO_HEADER_SIZE + i * SCHED_ELEM_SIZE..];
+        let mut depth = usize::from(get_u16(&topology, 4));
+        if depth > 2 && element(depth - 1)[8] == ICE_AQC_ELEM_TYPE_LEAF {
+            depth -= 1;
+        }
+
+        let mut parent = get_u32(element(depth - 1), 4);
+        for layer in depth..=num_layers - ICE_QGRP_LAYER_OFFSET {
+            parent = self.add_sched_node(parent)?;
+
+            debug!("added scheduler node {:#x} on layer {}", parent, layer);
+        }
+
+        Ok(parent)
+    }
+
+    /// Adds a generic scheduler node below `parent` and returns its id.
+    fn add_sched_node(&self, parent: u32) -> Result<u32, IxyError> {
+        let mut buf = [0; SCHED_TOPO_HEADER_SIZE + SCHED_ELEM_SIZE];
+        put_u32(&mut buf, 0, parent);
+        put_u16(&mut buf, 4, 1);
+        put_u32(&mut buf, 8, parent);
+        write_sched_elem(&mut buf[16..], ICE_AQC_ELEM_TYPE_SE_GENERIC);
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_ADD_SCHED_ELEMS);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        put_u16(&mut desc.params, 0, 1);
+        self.aq_command(desc, Some(&mut buf))?;
+
+        Ok(get_u32(&buf, 12))
+    }
+
+    /// Allocates the descriptor ring and mempool of rx queue `queue_id`.
+    fn init_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<RxDesc>();
+
+        let dma: Dma<RxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(IceRxQueue::new(dma, mempool));
+
+        Ok(())
+    }
+
+    /// Allocates the descriptor ring of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<TxDesc>();
+
+        let dma: Dma<TxDesc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // all descriptors start out without the descriptor done type
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0);
+        }
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        self.tx_queues.push(I40eTxQueue::new(dma));
+        self.tx_teids.push(Cell::new(None));
+
+        Ok(())
+    }
+
+    /// Fills rx queue `queue_id`, writes its context and enables it.
+    fn start_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("starting rx queue {}", queue_id);
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        let tail = queue.fill()?;
+        let buffer_size = queue.buffer_size();
+        let max_frame = (I40E_MAX_CHAINED_RX_BUFFERS * buffer_size).min(I40E_MAX_FRAME_SIZE);
+
+        // see struct ice_rlan_ctx in Intel's shared code for the layout
+        let mut ctx = [0; ICE_RXQ_CTX_SIZE];
+        set_ctx_bits(&mut ctx, 0, 13, 0); // head
+        set_ctx_bits(
+            &mut ctx,
+            32,
+            57,
+            queue.ring_phys().0 as u64 >> I40E_QUEUE_BASE_SHIFT,
+        ); // base
+        set_ctx_bits(&mut ctx, 89, 13, queue.num_descriptors as u64); // qlen
+        set_ctx_bits(
+            &mut ctx,
+            102,
+            7,
+            (buffer_size >> I40E_RXQ_CTX_DBUFF_SHIFT) as u64,
+        ); // dbuf
+        set_ctx_bits(&mut ctx, 116, 1, 1); // dsize: 32 byte descriptors
+        set_ctx_bits(&mut ctx, 117, 1, u64::from(self.crc_strip)); // crcstrip
+        set_ctx_bits(&mut ctx, 119, 1, 1); // l2tsel
+        set_ctx_bits(&mut ctx, 174, 14, max_frame as u64); // rxmax
+        set_ctx_bits(&mut ctx, 198, 3, 1); // lrxqthresh
+        set_ctx_bits(&mut ctx, 201, 1, 1); // prefena
+
+        for (i, dword) in ctx.chunks(4).enumerate() {
+            self.set_reg32(
+                ICE_QRX_CONTEXT_BASE + ICE_QRX_CONTEXT_STRIDE * i as u32 + 4 * queue_id,
+                u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]),
+            );
+        }
+
+        // write back the flexible NIC descriptor format
+        let flxp = ICE_QRXFLXP_CNTXT_BASE + 4 * queue_id;
+        let value = self.get_reg32(flxp)
+            & !(ICE_QRXFLXP_CNTXT_RXDID_IDX_MASK | ICE_QRXFLXP_CNTXT_RXDID_PRIO_MASK);
+        self.set_reg32(
+            flxp,
+            value | ICE_RXDID_FLEX_NIC | (ICE_RXDID_PRIO << ICE_QRXFLXP_CNTXT_RXDID_PRIO_SHIFT),
+        );
+
+        self.set_reg32(ICE_QRX_TAIL_BASE + 4 * queue_id, 0);
+        self.set_flags32(ICE_QRX_CTRL_BASE + 4 * queue_id, ICE_QRX_CTRL_QENA_REQ);
+        self.wait_rx_queue(queue_id, true)?;
+
+        // rx queue starts out full
+        self.set_reg32(ICE_QRX_TAIL_BASE + 4 * queue_id, tail as u32);
+
+        Ok(())
+    }
+
+    /// Adds tx queue `queue_id` with its context to the queue group node of the scheduler,
+    /// which enables it.
+    fn start_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("starting tx queue {}", queue_id);
+
+        let queue = &self.tx_queues[queue_id as usize];
+
+        // see struct ice_tlan_ctx in Intel's shared code for the layout
+        let mut ctx = [0; ICE_TXQ_CTX_SIZE];
+        set_ctx_bits(
+            &mut ctx,
+            0,
+            57,
+            queue.ring_phys().0 as u64 >> I40E_QUEUE_BASE_SHIFT,
+        ); // base
+        set_ctx_bits(&mut ctx, 57, 3, u64::from(self.lport)); // port_num
+        set_ctx_bits(&mut ctx, 65, 3, u64::from(self.pf_id)); // pf_num
+        set_ctx_bits(&mut ctx, 78, 2, ICE_TLAN_CTX_VMVF_TYPE_PF); // vmvf_type
+        set_ctx_bits(&mut ctx, 80, 10, u64::from(self.vsi_num)); // src_vsi
+        set_ctx_bits(&mut ctx, 114, 14, u64::from(queue_id)); // qnum_in_func
+        set_ctx_bits(&mut ctx, 135, 13, NUM_TX_QUEUE_ENTRIES as u64); // qlen
+        set_ctx_bits(&mut ctx, 164, 1, 1); // legacy_int
+
+        // a queue group with a single queue: header, queue id, queue node id, context and the
+        // scheduler element of the queue
+        let mut buf = [0; 56];
+        put_u32(&mut buf, 0, self.qgroup_teid);
+        buf[4] = 1;
+        put_u16(&mut buf, 8, queue_id as u16);
+        buf[16..16 + ICE_TXQ_CTX_SIZE].copy_from_slice(&ctx);
+        write_sched_elem(&mut buf[40..], 0);
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_ADD_TXQS);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        desc.params[0] = 1;
+        self.aq_command(desc, Some(&mut buf))?;
+
+        self.tx_teids[queue_id as usize].set(Some(get_u32(&buf, 12)));
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn stop_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        debug!("stopping rx queue {}", queue_id);
+
+        self.clear_flags32(ICE_QRX_CTRL_BASE + 4 * queue_id, ICE_QRX_CTRL_QENA_REQ);
+        self.wait_rx_queue(queue_id, false)
+    }
+
+    /// Removes tx queue `queue_id` from the scheduler, packets that have not been sent yet stay
+    /// in the ring.
+    fn stop_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if self.tx_teids[queue_id as usize].get().is_none() {
+            return Ok(());
+        }
+
+        debug!("stopping tx queue {}", queue_id);
+
+        let mut buf = [0; 8];
+        put_u32(&mut buf, 0, self.qgroup_teid);
+        buf[4] = 1;
+        put_u16(&mut buf, 6, queue_id as u16);
+
+        let mut desc = AqDesc::new(ICE_AQC_OPC_DIS_TXQS);
+        desc.flags |= I40E_AQ_FLAG_RD;
+        desc.params[0] = ICE_AQC_Q_DIS_CMD_FLUSH_PIPE;
+        desc.params[1] = 1;
+        put_u16(
+            &mut desc.params,
+            2,
+            ICE_AQC_Q_DIS_TIMEOUT << ICE_AQC_Q_DIS_TIMEOUT_SHIFT,
+        );
+        self.aq_command(desc, Some(&mut buf))?;
+
+        self.tx_teids[queue_id as usize].set(None);
+
+        Ok(())
+    }
+
+    /// Waits until rx queue `queue_id` reports to be `enabled`.
+    fn wait_rx_queue(&self, queue_id: u32, enabled: bool) -> Result<(), IxyError> {
+        let start = Instant::now();
+        let reg = ICE_QRX_CTRL_BASE + 4 * queue_id;
+
+        while ((self.get_reg32(reg) & ICE_QRX_CTRL_QENA_STAT) != 0) != enabled {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > QUEUE_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "rx queue {} of {} did not become {}",
+                    queue_id,
+                    self.pci_addr,
+                    if enabled { "enabled" } else { "disabled" }
+                )));
+            }
+            thread::sleep(QUEUE_POLL_DELAY);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the head of tx queue `queue_id`.
+    fn tx_head(&self, queue_id: u32) -> usize {
+        (self.get_reg32(ICE_QTX_COMM_HEAD_BASE + 4 * queue_id) & ICE_QTX_COMM_HEAD_MASK) as usize
+    }
+
+    /// Returns the link status data of the port.
+    fn get_link_status(&self) -> Result<[u8; LINK_STATUS_SIZE], IxyError> {
+        let mut status = [0; LINK_STATUS_SIZE];
+
+        let mut desc = AqDesc::new(I40E_AQC_OPC_GET_LINK_STATUS);
+        desc.params[0] = self.lport;
+        self.aq_command(desc, Some(&mut status))?;
+
+        Ok(status)
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Returns an error if the physical function doesn't have the given number of queues.
+    fn check_num_queues(&self, num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+        if num_rx_queues > self.max_rx_queues || num_tx_queues > self.max_tx_queues {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot configure {} rx and {} tx queues: {} has {} rx and {} tx queues",
+                num_rx_queues, num_tx_queues, self.pci_addr, self.max_rx_queues, self.max_tx_queues
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f` while holding the NVM resource for reading.
+    fn with_nvm<T>(&self, f: impl FnOnce() -> Result<T, IxyError>) -> Result<T, IxyError> {
+        let mut acquire = AqDesc::new(ICE_AQC_OPC_REQ_RES);
+        put_u16(&mut acquire.params, 0, ICE_NVM_RES_ID);
+        put_u16(&mut acquire.params, 2, ICE_RES_READ);
+        put_u32(&mut acquire.params, 4, ICE_NVM_TIMEOUT_MS);
+        self.aq_command(acquire, None)?;
+
+        let result = f();
+
+        let mut release = AqDesc::new(ICE_AQC_OPC_RELEASE_RES);
+        put_u16(&mut release.params, 0, ICE_NVM_RES_ID);
+        self.aq_command(release, None)?;
+
+        result
+    }
+
+    /// Returns the current values of the packet and octet counters of the port.
+    fn read_counters(&self) -> PortCounters {
+        let reg = |r| self.read_counter(r, COUNTER_48_MASK);
+
+        PortCounters {
+            rx_pkts: reg(ICE_GLPRT_UPRCL) + reg(ICE_GLPRT_MPRCL) + reg(ICE_GLPRT_BPRCL),
+            tx_pkts: reg(ICE_GLPRT_UPTCL) + reg(ICE_GLPRT_MPTCL) + reg(ICE_GLPRT_BPTCL),
+            rx_bytes: reg(ICE_GLPRT_GORCL),
+            tx_bytes: reg(ICE_GLPRT_GOTCL),
+            extended: self.counters.get().extended,
+        }
+    }
+
+    /// Returns the value of the port counter `reg`, 48 bit counters continue in the next
+    /// register.
+    fn read_counter(&self, reg: u32, mask: u64) -> u64 {
+        let reg = reg + ICE_GLPRT_STRIDE * self.port;
+        let low = u64::from(self.get_reg32(reg));
+
+        if mask == COUNTER_32_MASK {
+            return low;
+        }
+
+        (low | (u64::from(self.get_reg32(reg + 4)) << 32)) & mask
+    }
+
+    /// Returns the size of the shadow RAM in words.
+    fn shadow_ram_words(&self) -> u32 {
+        let sr_size = (self.get_reg32(I40E_GLNVM_GENS) >> I40E_GLNVM_GENS_SR_SIZE_SHIFT)
+            & I40E_GLNVM_GENS_SR_SIZE_MASK;
+
+        (1 << sr_size) * ICE_SR_WORDS_IN_1KB
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register at `self.addr` + `reg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
+    }
+
+    /// Sets the register at `self.addr` + `reg` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+
+    /// Sets the `flags` at `self.addr` + `reg`.
+    fn set_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) | flags);
+    }
+
+    /// Clears the `flags` at `self.addr` + `reg`.
+    fn clear_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) & !flags);
+    }
+
+    /// Waits for `self.addr` + `reg` to clear `value`.
+    fn wait_clear_reg32(&self, reg: u32, value: u32) -> Result<(), IxyError> {
+        let start = Instant::now();
+
+        while (self.get_reg32(reg) & value) != 0 {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "register {:#x} of {} did not clear {:#x}",
+                    reg, self.pci_addr, value
+                )));
+            }
+            thread::sleep(RESET_POLL_DELAY);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a scheduler element of type `elem_type` with the default bandwidth profiles to
+/// `buf`.
+fn write_sched_elem(buf: &mut [u8], elem_type: u8) {
+    buf[0] = elem_type;
+    buf[1] = ICE_AQC_ELEM_VALID_GENERIC | ICE_AQC_ELEM_VALID_CIR | ICE_AQC_ELEM_VALID_EIR;
+    put_u16(buf, 6, ICE_SCHED_DFLT_BW_WT);
+    put_u16(buf, 10, ICE_SCHED_DFLT_BW_WT);
+}
+
+/// Returns the little endian u32 at `offset` of `buf`.
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// Returns the status quad word of the rx descriptor at `index` of `queue`.
+unsafe fn rx_desc_status(queue: &IceRxQueue, index: usize) -> u64 {
+    u64::from_le(ptr::read_volatile(
+        &(*queue.descriptors.add(index)).hdr_addr,
+    ))
+}
+
+/// Returns the packet length the device wrote back to the rx descriptor at `index` of `queue`.
+unsafe fn rx_desc_len(queue: &IceRxQueue, index: usize) -> usize {
+    let qword = u64::from_le(ptr::read_volatile(
+        &(*queue.descriptors.add(index)).pkt_addr,
+    ));
+
+    ((qword >> ICE_RX_FLEX_DESC_PKT_LEN_SHIFT) & ICE_RX_FLEX_DESC_PKT_LEN_MASK) as usize
+}
@@ -0,0 +1,1591 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+use crate::vfio::*;
+
+// the advanced descriptors of the 82576 and its successors have the same layout as the ones of
+// the 82599, so the rings are handled by the queues of the ixgbe driver
+use crate::constants::{ixgbe_adv_rx_desc, ixgbe_adv_tx_desc};
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, IxgbeRxQueue, IxgbeTxQueue, MAX_RX_BUFFER_SIZE,
+    NUM_RX_QUEUE_ENTRIES, NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-igb";
+
+const INTEL_VENDOR_ID: u16 = 0x8086;
+
+// copper ports of the 82576 and the I350, fiber and SerDes ports need their PCS configured
+// which is not supported
+const E1000_DEV_ID_82576: u16 = 0x10C9;
+const E1000_DEV_ID_82576_QUAD_COPPER: u16 = 0x10E8;
+const E1000_DEV_ID_82576_NS: u16 = 0x150A;
+const E1000_DEV_ID_82576_QUAD_COPPER_ET2: u16 = 0x1526;
+const E1000_DEV_ID_I350_COPPER: u16 = 0x1521;
+
+const SUPPORTED_DEVICE_IDS: [u16; 5] = [
+    E1000_DEV_ID_82576,
+    E1000_DEV_ID_82576_QUAD_COPPER,
+    E1000_DEV_ID_82576_NS,
+    E1000_DEV_ID_82576_QUAD_COPPER_ET2,
+    E1000_DEV_ID_I350_COPPER,
+];
+
+// registers, see section 8 of the 82576 and the I350 datasheets
+const E1000_CTRL: u32 = 0x00000;
+const E1000_STATUS: u32 = 0x00008;
+const E1000_EECD: u32 = 0x00010;
+const E1000_EERD: u32 = 0x00014;
+const E1000_CTRL_EXT: u32 = 0x00018;
+const E1000_MDIC: u32 = 0x00020;
+const E1000_FCAL: u32 = 0x00028;
+const E1000_FCAH: u32 = 0x0002C;
+const E1000_FCT: u32 = 0x00030;
+const E1000_ICR: u32 = 0x000C0;
+const E1000_IMS: u32 = 0x000D0;
+const E1000_IMC: u32 = 0x000D8;
+const E1000_RCTL: u32 = 0x00100;
+const E1000_FCTTV: u32 = 0x00170;
+const E1000_TCTL: u32 = 0x00400;
+const E1000_EIMC: u32 = 0x01528;
+const E1000_FCRTL: u32 = 0x02160;
+const E1000_FCRTH: u32 = 0x02168;
+const E1000_RXPBS: u32 = 0x02404;
+const E1000_RLPML: u32 = 0x05004;
+const E1000_MTA: u32 = 0x05200;
+const E1000_RA: u32 = 0x05400;
+
+// registers of rx queue 0, the registers of queue n follow at 0x40 * n
+const E1000_RDBAL: u32 = 0x0C000;
+const E1000_RDBAH: u32 = 0x0C004;
+const E1000_RDLEN: u32 = 0x0C008;
+const E1000_SRRCTL: u32 = 0x0C00C;
+const E1000_RDH: u32 = 0x0C010;
+const E1000_RDT: u32 = 0x0C018;
+const E1000_RXDCTL: u32 = 0x0C028;
+
+// registers of tx queue 0, the registers of queue n follow at 0x40 * n
+const E1000_TDBAL: u32 = 0x0E000;
+const E1000_TDBAH: u32 = 0x0E004;
+const E1000_TDLEN: u32 = 0x0E008;
+const E1000_TDH: u32 = 0x0E010;
+const E1000_TDT: u32 = 0x0E018;
+const E1000_TXDCTL: u32 = 0x0E028;
+
+// statistic counters, all of them are cleared on read
+const E1000_CRCERRS: u32 = 0x04000;
+const E1000_SYMERRS: u32 = 0x04008;
+const E1000_MPC: u32 = 0x04010;
+const E1000_RLEC: u32 = 0x04040;
+const E1000_XONRXC: u32 = 0x04048;
+const E1000_XONTXC: u32 = 0x0404C;
+const E1000_XOFFRXC: u32 = 0x04050;
+const E1000_XOFFTXC: u32 = 0x04054;
+const E1000_GPRC: u32 = 0x04074;
+const E1000_BPRC: u32 = 0x04078;
+const E1000_MPRC: u32 = 0x0407C;
+const E1000_GPTC: u32 = 0x04080;
+const E1000_GORCL: u32 = 0x04088;
+const E1000_GORCH: u32 = 0x0408C;
+const E1000_GOTCL: u32 = 0x04090;
+const E1000_GOTCH: u32 = 0x04094;
+const E1000_RNBC: u32 = 0x040A0;
+const E1000_RUC: u32 = 0x040A4;
+const E1000_RFC: u32 = 0x040A8;
+const E1000_ROC: u32 = 0x040AC;
+const E1000_RJC: u32 = 0x040B0;
+const E1000_MPTC: u32 = 0x040F0;
+const E1000_BPTC: u32 = 0x040F4;
+
+const E1000_CTRL_GIO_MASTER_DISABLE: u32 = 1 << 2;
+const E1000_CTRL_SLU: u32 = 1 << 6;
+const E1000_CTRL_RST: u32 = 1 << 26;
+const E1000_CTRL_RFCE: u32 = 1 << 27;
+const E1000_CTRL_TFCE: u32 = 1 << 28;
+
+const E1000_STATUS_LU: u32 = 1 << 1;
+const E1000_STATUS_SPEED_MASK: u32 = 3 << 6;
+const E1000_STATUS_SPEED_10: u32 = 0;
+const E1000_STATUS_SPEED_100: u32 = 1 << 6;
+const E1000_STATUS_GIO_MASTER_ENABLE: u32 = 1 << 19;
+
+const E1000_EECD_AUTO_RD: u32 = 1 << 9;
+
+const E1000_EERD_START: u32 = 1 << 0;
+const E1000_EERD_DONE: u32 = 1 << 1;
+const E1000_EERD_ADDR_SHIFT: u32 = 2;
+const E1000_EERD_DATA_SHIFT: u32 = 16;
+
+const E1000_CTRL_EXT_DRV_LOAD: u32 = 1 << 28;
+
+const E1000_MDIC_REG_SHIFT: u32 = 16;
+const E1000_MDIC_PHY_SHIFT: u32 = 21;
+const E1000_MDIC_OP_WRITE: u32 = 1 << 26;
+const E1000_MDIC_OP_READ: u32 = 2 << 26;
+const E1000_MDIC_READY: u32 = 1 << 28;
+const E1000_MDIC_ERROR: u32 = 1 << 30;
+
+// the internal copper PHY and its control register, see section 8.25 of the 82576 datasheet
+const PHY_ADDR: u32 = 1;
+const PHY_CONTROL: u32 = 0;
+const PHY_CONTROL_POWER_DOWN: u16 = 1 << 11;
+
+const E1000_RCTL_EN: u32 = 1 << 1;
+const E1000_RCTL_UPE: u32 = 1 << 3;
+const E1000_RCTL_MPE: u32 = 1 << 4;
+const E1000_RCTL_LPE: u32 = 1 << 5;
+const E1000_RCTL_BAM: u32 = 1 << 15;
+const E1000_RCTL_SECRC: u32 = 1 << 26;
+
+const E1000_TCTL_EN: u32 = 1 << 1;
+const E1000_TCTL_PSP: u32 = 1 << 3;
+const E1000_TCTL_CT_SHIFT: u32 = 4;
+const E1000_TCTL_COLD_SHIFT: u32 = 12;
+const E1000_TCTL_RTLC: u32 = 1 << 24;
+
+// recommended collision threshold and full duplex collision distance
+const E1000_COLLISION_THRESHOLD: u32 = 0x0F;
+const E1000_COLLISION_DISTANCE: u32 = 0x3F;
+
+const E1000_SRRCTL_BSIZEPKT_SHIFT: u32 = 10;
+const E1000_SRRCTL_BSIZEPKT_MASK: u32 = 0x7F;
+const E1000_SRRCTL_DESCTYPE_MASK: u32 = 7 << 25;
+const E1000_SRRCTL_DESCTYPE_ADV_ONEBUF: u32 = 1 << 25;
+const E1000_SRRCTL_DROP_EN: u32 = 1 << 31;
+
+const E1000_RXDCTL_QUEUE_ENABLE: u32 = 1 << 25;
+const E1000_TXDCTL_QUEUE_ENABLE: u32 = 1 << 25;
+
+const E1000_RAH_AV: u32 = 1 << 31;
+const E1000_FCRTL_XONE: u32 = 1 << 31;
+const E1000_FCRT_MASK: u32 = 0xFFF0;
+const E1000_RLPML_MASK: u32 = 0x3FFF;
+
+// the rx packet buffer size of the 82576 is given in KB, the I350 encodes it
+const E1000_RXPBS_SIZE_MASK_82576: u32 = 0x7F;
+const E1000_RXPBS_SIZE_MASK_I350: u32 = 0xF;
+const RXPBS_TABLE_I350: [u32; 11] = [36, 72, 144, 1, 2, 4, 8, 16, 35, 70, 140];
+
+// flow control address, type and the pause time of sent pause frames
+const E1000_FCAL_PAUSE: u32 = 0x00C2_8001;
+const E1000_FCAH_PAUSE: u32 = 0x0100;
+const E1000_FCT_PAUSE: u32 = 0x8808;
+const FC_PAUSE_TIME: u32 = 0x680;
+
+const E1000_IRQ_CLEAR_MASK: u32 = 0xFFFF_FFFF;
+const E1000_FAILED_READ_REG: u32 = 0xFFFF_FFFF;
+
+// EEPROM words, see section 6 of the datasheets
+const EEPROM_VERSION: u16 = 0x05;
+const EEPROM_CHECKSUM_REG: u16 = 0x3F;
+const EEPROM_SUM: u16 = 0xBABA;
+const NUM_LAN_FUNCTIONS_I350: u16 = 4;
+
+const NUM_RAR_ENTRIES: u32 = 16;
+const NUM_MTA_ENTRIES: u32 = 128;
+
+const MAX_82576_QUEUES: u16 = 16;
+const MAX_I350_QUEUES: u16 = 8;
+
+// pthresh, hthresh and wthresh of RXDCTL and TXDCTL are 5 bits wide
+const DESC_THRESH_MAX: u8 = 0x1f;
+
+// the descriptor thresholds of the linux driver
+const RX_PTHRESH: u8 = 8;
+const RX_HTHRESH: u8 = 8;
+const RX_WTHRESH: u8 = 4;
+const TX_PTHRESH: u8 = 8;
+const TX_HTHRESH: u8 = 1;
+const TX_WTHRESH: u8 = 16;
+
+// the reset takes about 1 µs, the EEPROM is loaded within 10 ms afterwards
+const RESET_DELAY: Duration = Duration::from_millis(10);
+const MASTER_DISABLE_TIMEOUT: Duration = Duration::from_millis(80);
+const MDIC_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Driver for the 82576 and I350 gigabit network cards, see the 82576 and the I350
+/// datasheets.
+///
+/// Rx queues other than queue 0 only receive packets once a filter steers packets to them.
+pub struct IgbDevice {
+    pci_addr: String,
+    addr: *mut u8,
+    len: usize,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<IxgbeRxQueue>,
+    tx_queues: Vec<IxgbeTxQueue>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    // the I350 has half the queues, a different packet buffer and EEPROM layout
+    i350: bool,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+impl IxyDevice for IgbDevice {
+    /// Returns an initialized `IgbDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(pci_addr: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<IgbDevice, IxyError> {
+        IgbDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.read_rar(0)
+    }
+
+    /// Sets the mac address of this device.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        self.write_rar(0, mac);
+    }
+
+    /// Adds `mac` to the first free receive address register and returns its index.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        // the address valid bit marks registers that are in use
+        let index = (1..NUM_RAR_ENTRIES)
+            .find(|&i| self.get_reg32(E1000_RA + 8 * i + 4) & E1000_RAH_AV == 0)
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(
+                    "all receive address registers are in use".to_string(),
+                )
+            })?;
+
+        self.write_rar(index, mac);
+
+        Ok(index as usize)
+    }
+
+    /// Clears the receive address register `index`.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        if index == 0 || index >= NUM_RAR_ENTRIES as usize {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "receive address register {} is not a unicast filter",
+                index
+            )));
+        }
+
+        let index = index as u32;
+
+        self.set_reg32(E1000_RA + 8 * index + 4, 0);
+        self.set_reg32(E1000_RA + 8 * index, 0);
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        // timestamping is not enabled, so no descriptor carries the timestamp bit
+        let batch = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if let Some(tail) = batch.tail {
+            self.set_reg32(E1000_RDT + 0x40 * queue_id, tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            batch.received,
+            num_packets
+        );
+
+        batch.received
+    }
+
+    /// Returns whether the device was removed, i.e. its status register reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(E1000_STATUS) == E1000_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(queue_id, num_posted)? {
+            self.set_reg32(E1000_RDT + 0x40 * queue_id, tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted()
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(
+                E1000_TDT + 0x40 * queue_id,
+                self.tx_queues[queue_id as usize].tail() as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let rx_pkts = u64::from(self.get_reg32(E1000_GPRC));
+        let tx_pkts = u64::from(self.get_reg32(E1000_GPTC));
+        // the octet counters are cleared when their high register is read
+        let rx_bytes =
+            u64::from(self.get_reg32(E1000_GORCL)) + (u64::from(self.get_reg32(E1000_GORCH)) << 32);
+        let tx_bytes =
+            u64::from(self.get_reg32(E1000_GOTCL)) + (u64::from(self.get_reg32(E1000_GOTCH)) << 32);
+
+        stats.rx_pkts += rx_pkts;
+        stats.tx_pkts += tx_pkts;
+        stats.rx_bytes += rx_bytes;
+        stats.tx_bytes += tx_bytes;
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.read_stats(&mut DeviceStats::default());
+        self.read_extended_stats(&mut ExtendedStats::default());
+    }
+
+    /// Reads the extended stats of this device into `stats`, MAC faults are not counted.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        // all of these registers are cleared on read
+        let reg = |r| u64::from(self.get_reg32(r));
+
+        stats.rx_crc_errors += reg(E1000_CRCERRS);
+        stats.rx_illegal_byte_errors += reg(E1000_SYMERRS);
+        stats.rx_length_errors += reg(E1000_RLEC);
+        stats.rx_undersize += reg(E1000_RUC);
+        stats.rx_fragments += reg(E1000_RFC);
+        stats.rx_oversize += reg(E1000_ROC);
+        stats.rx_jabbers += reg(E1000_RJC);
+        stats.rx_missed += reg(E1000_MPC);
+        stats.rx_no_buffer += reg(E1000_RNBC);
+        stats.rx_broadcast += reg(E1000_BPRC);
+        stats.rx_multicast += reg(E1000_MPRC);
+        stats.tx_broadcast += reg(E1000_BPTC);
+        stats.tx_multicast += reg(E1000_MPTC);
+        stats.rx_xon += reg(E1000_XONRXC);
+        stats.rx_xoff += reg(E1000_XOFFRXC);
+        stats.tx_xon += reg(E1000_XONTXC);
+        stats.tx_xoff += reg(E1000_XOFFTXC);
+    }
+
+    /// Returns the link speed of this device.
+    fn get_link_speed(&self) -> u32 {
+        let status = self.get_reg32(E1000_STATUS);
+        if (status & E1000_STATUS_LU) == 0 {
+            return 0;
+        }
+        match status & E1000_STATUS_SPEED_MASK {
+            E1000_STATUS_SPEED_10 => 10,
+            E1000_STATUS_SPEED_100 => 100,
+            _ => 1000,
+        }
+    }
+
+    /// Returns the PCIe link of this device.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(self.i350, num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        // remove queues from the top, queues below the new counts are left untouched
+        while self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues -= 1;
+            self.stop_rx_queue(self.num_rx_queues);
+            self.rx_queues.pop();
+        }
+
+        while self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues -= 1;
+            self.stop_tx_queue(self.num_tx_queues);
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                queue.reset();
+            }
+        }
+
+        while self.num_rx_queues < num_rx_queues {
+            self.init_rx_queue(self.num_rx_queues)?;
+            self.write_rx_drop_enable(self.num_rx_queues, num_rx_queues > 1);
+            self.start_rx_queue(self.num_rx_queues)?;
+            self.num_rx_queues += 1;
+        }
+
+        while self.num_tx_queues < num_tx_queues {
+            self.init_tx_queue(self.num_tx_queues)?;
+            self.start_tx_queue(self.num_tx_queues);
+            self.num_tx_queues += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Enables rx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].is_drained() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        self.set_flags32(E1000_RXDCTL + 0x40 * queue_id, E1000_RXDCTL_QUEUE_ENABLE);
+        self.wait_set_reg32(E1000_RXDCTL + 0x40 * queue_id, E1000_RXDCTL_QUEUE_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(E1000_RXDCTL + 0x40 * queue_id) & E1000_RXDCTL_QUEUE_ENABLE) != 0
+    }
+
+    /// Enables tx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        debug!("enabling tx queue {}", queue_id);
+
+        self.set_flags32(E1000_TXDCTL + 0x40 * queue_id, E1000_TXDCTL_QUEUE_ENABLE);
+        self.wait_set_reg32(E1000_TXDCTL + 0x40 * queue_id, E1000_TXDCTL_QUEUE_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables tx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+            && (self.get_reg32(E1000_TXDCTL + 0x40 * queue_id) & E1000_TXDCTL_QUEUE_ENABLE) != 0
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.get_reg32(E1000_TDH + 0x40 * queue_id) as usize;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Disables tx queue `queue_id`, drops all pending packets and enables it again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue(queue_id as u16);
+        self.tx_queues[queue_id as usize].reset();
+        self.start_tx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.get_reg32(E1000_TDH + 0x40 * queue_id) as usize
+            != self.tx_queues[queue_id as usize].tail()
+        {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// Sets whether rx queue `queue_id` drops packets when its ring is full.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.write_rx_drop_enable(queue_id as u16, enable);
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of rx queue `queue_id`.
+    fn set_rx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let reg = E1000_RXDCTL + 0x40 * queue_id;
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of tx queue `queue_id`.
+    fn set_tx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let reg = E1000_TXDCTL + 0x40 * queue_id;
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Sets the flow control mode of this device, see section 3.7.5 of the 82576 datasheet.
+    fn set_flow_control(&self, mode: FlowControl) {
+        info!("setting flow control mode to {:?}", mode);
+
+        let (rx_pause, tx_pause) = match mode {
+            FlowControl::None => (false, false),
+            FlowControl::RxPause => (true, false),
+            FlowControl::TxPause => (false, true),
+            FlowControl::Full => (true, true),
+        };
+
+        // pause frames are recognized by their address and type
+        self.set_reg32(E1000_FCAL, E1000_FCAL_PAUSE);
+        self.set_reg32(E1000_FCAH, E1000_FCAH_PAUSE);
+        self.set_reg32(E1000_FCT, E1000_FCT_PAUSE);
+        self.set_reg32(E1000_FCTTV, FC_PAUSE_TIME);
+
+        // derive the watermarks (in bytes) from the size of the rx packet buffer (in KB)
+        let pb_size = self.rx_packet_buffer_size() << 10;
+        if tx_pause {
+            self.set_reg32(
+                E1000_FCRTL,
+                ((pb_size / 2) & E1000_FCRT_MASK) | E1000_FCRTL_XONE,
+            );
+            self.set_reg32(E1000_FCRTH, (pb_size * 3 / 4) & E1000_FCRT_MASK);
+        } else {
+            self.set_reg32(E1000_FCRTL, 0);
+            self.set_reg32(E1000_FCRTH, 0);
+        }
+
+        let mut ctrl = self.get_reg32(E1000_CTRL) & !(E1000_CTRL_RFCE | E1000_CTRL_TFCE);
+        if rx_pause {
+            ctrl |= E1000_CTRL_RFCE;
+        }
+        if tx_pause {
+            ctrl |= E1000_CTRL_TFCE;
+        }
+        self.set_reg32(E1000_CTRL, ctrl);
+    }
+
+    /// Returns the flow control mode of this device.
+    fn get_flow_control(&self) -> FlowControl {
+        let ctrl = self.get_reg32(E1000_CTRL);
+        let rx_pause = (ctrl & E1000_CTRL_RFCE) != 0;
+        let tx_pause = (ctrl & E1000_CTRL_TFCE) != 0;
+
+        match (rx_pause, tx_pause) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Sets whether the receiver strips the Ethernet FCS.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if enable {
+            self.set_flags32(E1000_RCTL, E1000_RCTL_SECRC);
+        } else {
+            self.clear_flags32(E1000_RCTL, E1000_RCTL_SECRC);
+        }
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.get_reg32(E1000_RCTL) & E1000_RCTL_SECRC != 0
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        // the device's buffer size has a granularity of 1 KiB
+        let buffer_size = buffer_size.next_multiple_of(1 << E1000_SRRCTL_BSIZEPKT_SHIFT);
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].set_pool(pool);
+        self.write_rx_buffer_size(queue_id as u16);
+
+        // accept frames larger than 1522 bytes up to the maximum length in RLPML
+        if buffer_size > PKT_BUF_ENTRY_SIZE {
+            let rlpml = self.get_reg32(E1000_RLPML);
+            self.set_reg32(
+                E1000_RLPML,
+                rlpml.max((buffer_size as u32).min(E1000_RLPML_MASK)),
+            );
+            self.set_flags32(E1000_RCTL, E1000_RCTL_LPE);
+        }
+
+        self.start_rx_queue(queue_id as u16)
+    }
+
+    /// Reads the 16 bit word at `offset` of this device's EEPROM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the EEPROM address space accessible via `EERD`.
+    fn read_eeprom_word(&self, offset: u16) -> u16 {
+        assert!(
+            u32::from(offset) < 1 << (E1000_EERD_DATA_SHIFT - E1000_EERD_ADDR_SHIFT),
+            "eeprom access out of bounds"
+        );
+
+        // software-controlled read via the EEPROM read register
+        self.set_reg32(
+            E1000_EERD,
+            (u32::from(offset) << E1000_EERD_ADDR_SHIFT) | E1000_EERD_START,
+        );
+
+        loop {
+            let eerd = self.get_reg32(E1000_EERD);
+            if (eerd & E1000_EERD_DONE) != 0 {
+                return (eerd >> E1000_EERD_DATA_SHIFT) as u16;
+            }
+            thread::sleep(Duration::from_micros(5));
+        }
+    }
+
+    /// Returns the image version stored in this device's EEPROM.
+    fn get_firmware_version(&self) -> u32 {
+        u32::from(self.read_eeprom_word(EEPROM_VERSION))
+    }
+
+    /// Returns whether the checksums stored in this device's EEPROM are valid, i.e. the 64 words
+    /// of every LAN function's section sum up to 0xBABA. The 82576 has a single section for all
+    /// ports, the I350 one per port.
+    fn validate_eeprom_checksum(&self) -> bool {
+        let sections = if self.i350 { NUM_LAN_FUNCTIONS_I350 } else { 1 };
+
+        (0..sections).all(|function| {
+            // the section of the first function is at the start of the EEPROM
+            let offset = if function == 0 {
+                0
+            } else {
+                0x40 + 0x40 * function
+            };
+
+            let checksum = (offset..=offset + EEPROM_CHECKSUM_REG)
+                .fold(0u16, |sum, i| sum.wrapping_add(self.read_eeprom_word(i)));
+
+            checksum == EEPROM_SUM
+        })
+    }
+
+    /// The thermal sensor of the I350 is not supported by this driver.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the control, per-queue and receive address registers of this device.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("CTRL", E1000_CTRL),
+            ("STATUS", E1000_STATUS),
+            ("CTRL_EXT", E1000_CTRL_EXT),
+            ("IMS", E1000_IMS),
+            ("RCTL", E1000_RCTL),
+            ("TCTL", E1000_TCTL),
+            ("RXPBS", E1000_RXPBS),
+            ("FCRTL", E1000_FCRTL),
+            ("FCRTH", E1000_FCRTH),
+            ("RLPML", E1000_RLPML),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            let queue = [
+                ("RDBAL", E1000_RDBAL),
+                ("RDBAH", E1000_RDBAH),
+                ("RDLEN", E1000_RDLEN),
+                ("RDH", E1000_RDH),
+                ("RDT", E1000_RDT),
+                ("RXDCTL", E1000_RXDCTL),
+                ("SRRCTL", E1000_SRRCTL),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg + 0x40 * i));
+            }
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            let queue = [
+                ("TDBAL", E1000_TDBAL),
+                ("TDBAH", E1000_TDBAH),
+                ("TDLEN", E1000_TDLEN),
+                ("TDH", E1000_TDH),
+                ("TDT", E1000_TDT),
+                ("TXDCTL", E1000_TXDCTL),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg + 0x40 * i));
+            }
+        }
+
+        for i in 0..NUM_RAR_ENTRIES {
+            dump.push(format!("RAL[{}]", i), self.get_reg32(E1000_RA + 8 * i));
+            dump.push(format!("RAH[{}]", i), self.get_reg32(E1000_RA + 8 * i + 4));
+        }
+
+        // the counters are cleared on read, so they are not part of the dump
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for IgbDevice {
+    fn drop(&mut self) {
+        // stop all dma before the descriptor rings and mempools are unmapped
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_EIMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_CTRL, E1000_CTRL_RST);
+        thread::sleep(RESET_DELAY);
+    }
+}
+
+impl IgbDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == INTEL_VENDOR_ID && SUPPORTED_DEVICE_IDS.contains(&device_id)
+    }
+
+    /// Returns an initialized `IgbDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<IgbDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let device_id = PciDevice::open(pci_addr)?.device_id()?;
+        let i350 = device_id == E1000_DEV_ID_I350_COPPER;
+        check_num_queues(i350, num_rx_queues, num_tx_queues)?;
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory
+        check_privileges(pci_addr, vfio, dma_size(num_rx_queues, num_tx_queues))?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            pci_map_resource(pci_addr)?
+        };
+
+        let mut dev = IgbDevice {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            i350,
+            removal: RemovalLatch::new(E1000_STATUS),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets and initializes this device, see section 4.5 of the 82576 datasheet.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        // section 4.5.1 - stop dma and disable interrupts before the reset
+        self.disable_master();
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_EIMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_RCTL, 0);
+        self.set_reg32(E1000_TCTL, E1000_TCTL_PSP);
+
+        self.set_flags32(E1000_CTRL, E1000_CTRL_RST);
+        thread::sleep(RESET_DELAY);
+
+        // the device reloads its configuration from the EEPROM after the reset
+        let start = Instant::now();
+        while self.get_reg32(E1000_EECD) & E1000_EECD_AUTO_RD == 0 {
+            if self.removal.is_removed() || start.elapsed() > RESET_DELAY {
+                warn!("auto read of the eeprom of {} timed out", self.pci_addr);
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.set_reg32(E1000_IMC, E1000_IRQ_CLEAR_MASK);
+        self.set_reg32(E1000_EIMC, E1000_IRQ_CLEAR_MASK);
+        self.get_reg32(E1000_ICR);
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        // tell the firmware that a driver took over the port
+        self.set_flags32(E1000_CTRL_EXT, E1000_CTRL_EXT_DRV_LOAD);
+
+        // the device loads the mac address of its port from the EEPROM into the first receive
+        // address register
+        let mac = self.get_mac_addr();
+
+        info!("initializing device {}", self.pci_addr);
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        // section 4.5.9 - receive address and multicast table
+        for i in 1..NUM_RAR_ENTRIES {
+            self.set_reg32(E1000_RA + 8 * i + 4, 0);
+            self.set_reg32(E1000_RA + 8 * i, 0);
+        }
+        for i in 0..NUM_MTA_ENTRIES {
+            self.set_reg32(E1000_MTA + 4 * i, 0);
+        }
+
+        // section 4.5.7 - link up via auto-negotiation of the internal PHY
+        self.init_link()?;
+
+        // section 4.5.8 - statistical counters, cleared on read
+        self.reset_stats();
+
+        // section 4.5.9 - init rx
+        self.init_rx()?;
+
+        // section 4.5.10 - init tx
+        self.init_tx()?;
+
+        for i in 0..self.num_rx_queues {
+            self.start_rx_queue(i)?;
+        }
+
+        for i in 0..self.num_tx_queues {
+            self.start_tx_queue(i);
+        }
+
+        // enable promisc mode by default to make testing easier
+        self.set_flags32(E1000_RCTL, E1000_RCTL_UPE | E1000_RCTL_MPE);
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Stops the dma of the device and waits until pending requests completed.
+    fn disable_master(&self) {
+        self.set_flags32(E1000_CTRL, E1000_CTRL_GIO_MASTER_DISABLE);
+
+        let start = Instant::now();
+        while self.get_reg32(E1000_STATUS) & E1000_STATUS_GIO_MASTER_ENABLE != 0 {
+            if self.removal.is_removed() || start.elapsed() > MASTER_DISABLE_TIMEOUT {
+                warn!(
+                    "pending requests of {} did not complete, resetting anyway",
+                    self.pci_addr
+                );
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Powers up the PHY and sets the link up, speed and duplex are auto-negotiated by the PHY.
+    fn init_link(&self) -> Result<(), IxyError> {
+        let control = self.read_phy_reg(PHY_CONTROL)?;
+        if control & PHY_CONTROL_POWER_DOWN != 0 {
+            debug!("powering up the phy");
+            self.write_phy_reg(PHY_CONTROL, control & !PHY_CONTROL_POWER_DOWN)?;
+        }
+
+        self.set_flags32(E1000_CTRL, E1000_CTRL_SLU);
+
+        Ok(())
+    }
+
+    /// Configures the receiver, its queues are enabled once they are started.
+    fn init_rx(&mut self) -> Result<(), IxyError> {
+        // strip the CRC and accept broadcast packets, the buffer sizes are set per queue
+        self.set_reg32(E1000_RCTL, E1000_RCTL_SECRC | E1000_RCTL_BAM);
+
+        for i in 0..self.num_rx_queues {
+            self.init_rx_queue(i)?;
+            self.write_rx_drop_enable(i, self.num_rx_queues > 1);
+        }
+
+        // packets are only received into enabled queues
+        self.set_flags32(E1000_RCTL, E1000_RCTL_EN);
+
+        Ok(())
+    }
+
+    /// Configures the transmitter, its queues are enabled once they are started.
+    fn init_tx(&mut self) -> Result<(), IxyError> {
+        // pad short packets and retransmit after late collisions
+        self.set_reg32(
+            E1000_TCTL,
+            E1000_TCTL_PSP
+                | (E1000_COLLISION_THRESHOLD << E1000_TCTL_CT_SHIFT)
+                | (E1000_COLLISION_DISTANCE << E1000_TCTL_COLD_SHIFT)
+                | E1000_TCTL_RTLC,
+        );
+
+        for i in 0..self.num_tx_queues {
+            self.init_tx_queue(i)?;
+        }
+
+        self.set_flags32(E1000_TCTL, E1000_TCTL_EN);
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring and mempool of rx queue `queue_id`.
+    fn init_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+
+        let i = u32::from(queue_id);
+
+        self.stop_rx_queue(queue_id);
+
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_rx_desc>();
+
+        let dma: Dma<ixgbe_adv_rx_desc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(
+            E1000_RDBAL + 0x40 * i,
+            (dma.phys as u64 & 0xffff_ffff) as u32,
+        );
+        self.set_reg32(E1000_RDBAH + 0x40 * i, (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(E1000_RDLEN + 0x40 * i, ring_size_bytes as u32);
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        // set ring to empty at start
+        self.set_reg32(E1000_RDH + 0x40 * i, 0);
+        self.set_reg32(E1000_RDT + 0x40 * i, 0);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(IxgbeRxQueue::new(dma, mempool));
+
+        // enable advanced rx descriptors
+        self.set_reg32(
+            E1000_SRRCTL + 0x40 * i,
+            (self.get_reg32(E1000_SRRCTL + 0x40 * i) & !E1000_SRRCTL_DESCTYPE_MASK)
+                | E1000_SRRCTL_DESCTYPE_ADV_ONEBUF,
+        );
+        self.write_rx_buffer_size(queue_id);
+
+        let reg = E1000_RXDCTL + 0x40 * i;
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), RX_PTHRESH, RX_HTHRESH, RX_WTHRESH)?,
+        );
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+
+        let i = u32::from(queue_id);
+
+        self.stop_tx_queue(queue_id);
+
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_tx_desc>();
+
+        let dma: Dma<ixgbe_adv_tx_desc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(
+            E1000_TDBAL + 0x40 * i,
+            (dma.phys as u64 & 0xffff_ffff) as u32,
+        );
+        self.set_reg32(E1000_TDBAH + 0x40 * i, (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(E1000_TDLEN + 0x40 * i, ring_size_bytes as u32);
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        let reg = E1000_TXDCTL + 0x40 * i;
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), TX_PTHRESH, TX_HTHRESH, TX_WTHRESH)?,
+        );
+
+        self.tx_queues.push(IxgbeTxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Sets the rx queues` descriptors and enables the queues.
+    fn start_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("starting rx queue {}", queue_id);
+
+        let i = u32::from(queue_id);
+
+        let tail = self.rx_queues[queue_id as usize].fill()?;
+
+        self.set_reg32(E1000_RDH + 0x40 * i, 0);
+
+        // enable queue and wait if necessary
+        self.set_flags32(E1000_RXDCTL + 0x40 * i, E1000_RXDCTL_QUEUE_ENABLE);
+        self.wait_set_reg32(E1000_RXDCTL + 0x40 * i, E1000_RXDCTL_QUEUE_ENABLE);
+
+        // rx queue starts out full
+        self.set_reg32(E1000_RDT + 0x40 * i, tail as u32);
+
+        Ok(())
+    }
+
+    /// Enables tx queue `queue_id`.
+    fn start_tx_queue(&mut self, queue_id: u16) {
+        debug!("starting tx queue {}", queue_id);
+
+        let i = u32::from(queue_id);
+
+        // tx queue starts out empty
+        self.set_reg32(E1000_TDH + 0x40 * i, 0);
+        self.set_reg32(E1000_TDT + 0x40 * i, 0);
+
+        // enable queue and wait if necessary
+        self.set_flags32(E1000_TXDCTL + 0x40 * i, E1000_TXDCTL_QUEUE_ENABLE);
+        self.wait_set_reg32(E1000_TXDCTL + 0x40 * i, E1000_TXDCTL_QUEUE_ENABLE);
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn stop_rx_queue(&self, queue_id: u16) {
+        debug!("stopping rx queue {}", queue_id);
+
+        let reg = E1000_RXDCTL + 0x40 * u32::from(queue_id);
+        self.clear_flags32(reg, E1000_RXDCTL_QUEUE_ENABLE);
+        self.wait_clear_reg32(reg, E1000_RXDCTL_QUEUE_ENABLE);
+    }
+
+    /// Disables tx queue `queue_id`, packets that have not been sent yet stay in the ring.
+    fn stop_tx_queue(&self, queue_id: u16) {
+        debug!("stopping tx queue {}", queue_id);
+
+        let reg = E1000_TXDCTL + 0x40 * u32::from(queue_id);
+        self.clear_flags32(reg, E1000_TXDCTL_QUEUE_ENABLE);
+        self.wait_clear_reg32(reg, E1000_TXDCTL_QUEUE_ENABLE);
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Sets or clears the drop enable bit of rx queue `queue_id`.
+    fn write_rx_drop_enable(&self, queue_id: u16, enable: bool) {
+        let reg = E1000_SRRCTL + 0x40 * u32::from(queue_id);
+
+        if enable {
+            self.set_flags32(reg, E1000_SRRCTL_DROP_EN);
+        } else {
+            self.clear_flags32(reg, E1000_SRRCTL_DROP_EN);
+        }
+    }
+
+    /// Sets the receive buffer size of rx queue `queue_id` to the data size of its mempool's
+    /// entries, i.e. without the headroom.
+    fn write_rx_buffer_size(&self, queue_id: u16) {
+        let pool = self.rx_queues[queue_id as usize].pool();
+        let bsize = ((pool.entry_size() - pool.headroom()) >> E1000_SRRCTL_BSIZEPKT_SHIFT) as u32;
+
+        let reg = E1000_SRRCTL + 0x40 * u32::from(queue_id);
+        self.set_reg32(
+            reg,
+            (self.get_reg32(reg) & !E1000_SRRCTL_BSIZEPKT_MASK)
+                | bsize.min(E1000_SRRCTL_BSIZEPKT_MASK),
+        );
+    }
+
+    /// Returns the size of the rx packet buffer in KB.
+    fn rx_packet_buffer_size(&self) -> u32 {
+        let rxpbs = self.get_reg32(E1000_RXPBS);
+
+        if self.i350 {
+            RXPBS_TABLE_I350
+                .get((rxpbs & E1000_RXPBS_SIZE_MASK_I350) as usize)
+                .copied()
+                .unwrap_or(0)
+        } else {
+            rxpbs & E1000_RXPBS_SIZE_MASK_82576
+        }
+    }
+
+    /// Returns the address in receive address register `index`.
+    fn read_rar(&self, index: u32) -> [u8; 6] {
+        let low = self.get_reg32(E1000_RA + 8 * index).to_le_bytes();
+        let high = self.get_reg32(E1000_RA + 8 * index + 4).to_le_bytes();
+
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+
+    /// Writes `mac` to receive address register `index` and marks it valid.
+    fn write_rar(&self, index: u32, mac: [u8; 6]) {
+        let low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        let high = u32::from_le_bytes([mac[4], mac[5], 0, 0]);
+
+        self.set_reg32(E1000_RA + 8 * index, low);
+        self.set_reg32(E1000_RA + 8 * index + 4, high | E1000_RAH_AV);
+    }
+
+    /// Returns the PHY register `reg` of the internal PHY, see section 8.2.4 of the 82576
+    /// datasheet.
+    fn read_phy_reg(&self, reg: u32) -> Result<u16, IxyError> {
+        self.set_reg32(
+            E1000_MDIC,
+            (reg << E1000_MDIC_REG_SHIFT) | (PHY_ADDR << E1000_MDIC_PHY_SHIFT) | E1000_MDIC_OP_READ,
+        );
+
+        Ok(self.wait_mdic()? as u16)
+    }
+
+    /// Writes `value` to the PHY register `reg` of the internal PHY.
+    fn write_phy_reg(&self, reg: u32, value: u16) -> Result<(), IxyError> {
+        self.set_reg32(
+            E1000_MDIC,
+            u32::from(value)
+                | (reg << E1000_MDIC_REG_SHIFT)
+                | (PHY_ADDR << E1000_MDIC_PHY_SHIFT)
+                | E1000_MDIC_OP_WRITE,
+        );
+
+        self.wait_mdic().map(|_| ())
+    }
+
+    /// Waits until the MDI access in progress completed and returns the MDI control register.
+    fn wait_mdic(&self) -> Result<u32, IxyError> {
+        let start = Instant::now();
+
+        loop {
+            let mdic = self.get_reg32(E1000_MDIC);
+
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if mdic & E1000_MDIC_ERROR != 0 {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "phy of {} did not answer",
+                    self.pci_addr
+                )));
+            }
+            if mdic & E1000_MDIC_READY != 0 {
+                return Ok(mdic);
+            }
+            if start.elapsed() > MDIC_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "access to the phy of {} timed out",
+                    self.pci_addr
+                )));
+            }
+
+            thread::sleep(Duration::from_micros(50));
+        }
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register at `self.addr` + `reg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
+    }
+
+    /// Sets the register at `self.addr` + `reg` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+
+    /// Sets the `flags` at `self.addr` + `reg`.
+    fn set_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) | flags);
+    }
+
+    /// Clears the `flags` at `self.addr` + `reg`.
+    fn clear_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) & !flags);
+    }
+
+    /// Waits for `self.addr` + `reg` to clear `value`.
+    fn wait_clear_reg32(&self, reg: u32, value: u32) {
+        loop {
+            let current = self.get_reg32(reg);
+            if (current & value) == 0 || self.removal.is_removed() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Waits for `self.addr` + `reg` to set `value`.
+    fn wait_set_reg32(&self, reg: u32, value: u32) {
+        loop {
+            let current = self.get_reg32(reg);
+            if (current & value) == value {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Returns an error if the 82576, or the I350 if `i350` is set, doesn't have the given number
+/// of queues.
+fn check_num_queues(i350: bool, num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+    let (name, max_queues) = if i350 {
+        ("I350", MAX_I350_QUEUES)
+    } else {
+        ("82576", MAX_82576_QUEUES)
+    };
+
+    if num_rx_queues > max_queues || num_tx_queues > max_queues {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "cannot configure {} rx and {} tx queues: the {} has {} of each",
+            num_rx_queues, num_tx_queues, name, max_queues
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the descriptor control register value `dctl` with the prefetch, host and write-back
+/// thresholds replaced.
+fn desc_thresholds(dctl: u32, pthresh: u8, hthresh: u8, wthresh: u8) -> Result<u32, IxyError> {
+    if pthresh > DESC_THRESH_MAX || hthresh > DESC_THRESH_MAX || wthresh > DESC_THRESH_MAX {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "descriptor thresholds must not exceed {}",
+            DESC_THRESH_MAX
+        )));
+    }
+
+    // pthresh: 4:0, hthresh: 12:8, wthresh: 20:16, the bits in between are reserved
+    let mask = u32::from(DESC_THRESH_MAX);
+    let mut dctl = dctl;
+    dctl &= !(mask | (mask << 8) | (mask << 16));
+    dctl |= u32::from(pthresh) | (u32::from(hthresh) << 8) | (u32::from(wthresh) << 16);
+
+    Ok(dctl)
+}
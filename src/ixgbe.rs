@@ -1,32 +1,179 @@
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
-use std::error::Error;
 use std::mem;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::path::Path;
 use std::ptr;
-use std::rc::Rc;
+use std::slice;
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::checksum::{
+    pseudo_header_checksum_without_len, write_l4_checksum, HeaderOffsets, IPPROTO_TCP, IPPROTO_UDP,
+    IPV4_CHECKSUM_OFFSET,
+};
 use crate::constants::*;
 use crate::memory::*;
 use crate::vfio::*;
 
-use crate::pci::pci_map_resource;
+use crate::pci::{
+    check_privileges, pci_disable_sriov, pci_enable_sriov, pci_map_resource, pci_mask_msix_vectors,
+    pci_numa_node, pci_reset_function, unbind_driver, MappedBar, PciDevice, PcieLink, RemovalLatch,
+};
 use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
 use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowAction;
+use crate::FlowControl;
+use crate::FlowProtocol;
+use crate::FlowRule;
+use crate::InterruptModeration;
 use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::RssConfig;
 use crate::MAX_QUEUES;
 
 const DRIVER_NAME: &str = "ixy-ixgbe";
 
-const PKT_BUF_ENTRY_SIZE: usize = 2048;
+// physical functions of the 82599 and its successors X540 and X550
+const SUPPORTED_DEVICE_IDS: [u32; 21] = [
+    IXGBE_DEV_ID_82599_KX4,
+    IXGBE_DEV_ID_82599_KX4_MEZZ,
+    IXGBE_DEV_ID_82599_KR,
+    IXGBE_DEV_ID_82599_COMBO_BACKPLANE,
+    IXGBE_DEV_ID_82599_CX4,
+    IXGBE_DEV_ID_82599_SFP,
+    IXGBE_DEV_ID_82599_BACKPLANE_FCOE,
+    IXGBE_DEV_ID_82599_SFP_FCOE,
+    IXGBE_DEV_ID_82599_SFP_EM,
+    IXGBE_DEV_ID_82599_SFP_SF2,
+    IXGBE_DEV_ID_82599_SFP_SF_QP,
+    IXGBE_DEV_ID_82599_QSFP_SF_QP,
+    IXGBE_DEV_ID_82599EN_SFP,
+    IXGBE_DEV_ID_82599_XAUI_LOM,
+    IXGBE_DEV_ID_82599_T3_LOM,
+    IXGBE_DEV_ID_82599_LS,
+    IXGBE_DEV_ID_X540T,
+    IXGBE_DEV_ID_X540T1,
+    IXGBE_DEV_ID_X550T,
+    IXGBE_DEV_ID_X550T1,
+    IXGBE_DEV_ID_X550EM_X_10G_T,
+];
+
+// 10GBASE-T parts, their link is negotiated by the PHY instead of being configured via AUTOC
+const X540_DEVICE_IDS: [u32; 2] = [IXGBE_DEV_ID_X540T, IXGBE_DEV_ID_X540T1];
+const X550_DEVICE_IDS: [u32; 2] = [IXGBE_DEV_ID_X550T, IXGBE_DEV_ID_X550T1];
+const X550EM_DEVICE_IDS: [u32; 1] = [IXGBE_DEV_ID_X550EM_X_10G_T];
+
+pub(crate) const PKT_BUF_ENTRY_SIZE: usize = 2048;
+// largest receive buffer the device supports, see SRRCTL.BSIZEPACKET
+pub(crate) const MAX_RX_BUFFER_SIZE: usize = 16 * 1024;
 const MIN_MEMPOOL_SIZE: usize = 4096;
 
-const NUM_RX_QUEUE_ENTRIES: usize = 512;
-const NUM_TX_QUEUE_ENTRIES: usize = 512;
+pub(crate) const NUM_RX_QUEUE_ENTRIES: usize = 512;
+pub(crate) const NUM_TX_QUEUE_ENTRIES: usize = 512;
+
+// every rx queue gets its own mempool
+const RX_MEMPOOL_SIZE: usize = if NUM_RX_QUEUE_ENTRIES + NUM_TX_QUEUE_ENTRIES < MIN_MEMPOOL_SIZE {
+    MIN_MEMPOOL_SIZE
+} else {
+    NUM_RX_QUEUE_ENTRIES + NUM_TX_QUEUE_ENTRIES
+};
+
+// the device fetches at most 40 data descriptors per packet
+const TX_MAX_SEGMENTS: usize = 40;
+
 const TX_CLEAN_BATCH: usize = 32;
 
+// descriptors sharing a cache line of the ring, the rings are at least 128-byte aligned
+const TX_DESCS_PER_CACHE_LINE: usize = 64 / mem::size_of::<ixgbe_adv_tx_desc>();
+
+pub(crate) const TX_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// receive address registers, the first one holds the primary mac address
+const NUM_RAR_ENTRIES: u32 = 128;
+
+// the VLAN filter table has a bit for each of the 4096 VLAN ids
+const NUM_VFTA_ENTRIES: u32 = 128;
+
+// the multicast table array has a bit for each of the 4096 values of the hashed address bits
+const NUM_MTA_ENTRIES: u32 = 128;
+
+// MACLEN of context descriptors of packets without checksum offloads
+const ETHERNET_HEADER_LEN: u32 = 14;
+
+// redirection table entries are 4 bits wide, so RSS spreads flows over at most 16 queues
+const MAX_RSS_QUEUES: u16 = 16;
+
+// perfect match filters of a 64 KiB flow director table, same limit as the linux driver
+const NUM_FLOW_RULES: usize = (1024 << IXGBE_FDIRCTRL_PBALLOC_64K) - 2;
+// flow director commands usually complete within a few µs
+const FDIR_CMD_DELAY: Duration = Duration::from_micros(10);
+
+// bit-banged i2c runs at less than 100 kHz, this covers all setup and hold times
+const I2C_BIT_DELAY: Duration = Duration::from_micros(5);
+
+// tries to acquire the software/firmware semaphore, same as the linux driver
+const SWFW_SYNC_TRIES: usize = 200;
+
+// an MDIO command takes about 20 µs, IXGBE_MDIO_COMMAND_TIMEOUT polls cover all of them
+const MDIO_COMMAND_DELAY: Duration = Duration::from_micros(10);
+
+// VMDq pools, receive addresses are assigned to them via the MPSAR registers
+const NUM_POOLS: u32 = 64;
+
+// virtual function n owns pool n and the physical function the pool after the last one
+const MAX_VFS: u16 = (NUM_POOLS - 1) as u16;
+// with up to 31 virtual functions the pools have 4 queues each, 2 otherwise
+const MAX_VFS_32_POOLS: u16 = 31;
+
+// tries to lock a vf mailbox before giving up until the vf retries its request
+const MBX_LOCK_TRIES: usize = 10;
+const MBX_LOCK_DELAY: Duration = Duration::from_micros(10);
+
+// descriptor thresholds are 7 bit fields in RXDCTL and TXDCTL
+const DESC_THRESH_MAX: u8 = 0x7f;
+
+// pause time in units of 512 bit times, same default as the linux driver
+const FC_PAUSE_TIME: u32 = 0x680;
+
+// interrupt throttling interval of rx queues in µs, see section 7.3.2.1
+const DEFAULT_ITR_US: u32 = 10;
+// the interval is a 9 bit field in units of 2 µs
+const MAX_ITR_US: u32 = (IXGBE_MAX_EITR >> 3) * 2;
+
+// adaptive moderation samples the packet rate of a queue at most this often and averages the
+// last samples, like the C version of ixy
+const ITR_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+const ITR_MOVING_AVERAGE_RANGE: usize = 5;
+// packet rates below which adaptive moderation picks the lowest resp. low latency interval,
+// similar to the latency classes of the linux driver
+const ITR_LOWEST_LATENCY_PPS: u64 = 10_000;
+const ITR_LOW_LATENCY_PPS: u64 = 200_000;
+const ITR_LOWEST_LATENCY_US: u32 = 10;
+const ITR_LOW_LATENCY_US: u32 = 50;
+const ITR_BULK_US: u32 = 125;
+
+// tx descriptor thresholds, we just use the defaults from DPDK here
+pub(crate) const TX_PTHRESH: u8 = 36;
+pub(crate) const TX_HTHRESH: u8 = 8;
+pub(crate) const TX_WTHRESH: u8 = 4;
+
+/// Returns the interrupt throttling interval for an rx queue receiving `pps` packets per second,
+/// trading latency for fewer interrupts as the rate rises.
+fn adaptive_itr_us(pps: u64) -> u32 {
+    if pps < ITR_LOWEST_LATENCY_PPS {
+        ITR_LOWEST_LATENCY_US
+    } else if pps < ITR_LOW_LATENCY_PPS {
+        ITR_LOW_LATENCY_US
+    } else {
+        ITR_BULK_US
+    }
+}
+
 fn wrap_ring(index: usize, ring_size: usize) -> usize {
     (index + 1) & (ring_size - 1)
 }
@@ -40,656 +187,4069 @@ pub struct IxgbeDevice {
     rx_queues: Vec<IxgbeRxQueue>,
     tx_queues: Vec<IxgbeTxQueue>,
     vfio: bool,
-    vfio_fd: RawFd,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    // eventfd of each rx queue's MSI-X vector, empty until interrupts are used
+    interrupts: Vec<OwnedFd>,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    sriov: Option<Sriov>,
+    mac_type: MacType,
+    // MDIO address of the 10GBASE-T PHY, found while initializing the link
+    phy_addr: Option<u32>,
+    // perfect match filters of the flow director, indexed by their software index
+    flow_rules: RefCell<Vec<Option<FlowRule>>>,
+    // configuration of RSS while it is enabled
+    rss: RefCell<Option<RssConfig>>,
+    // strip VLAN tags of received packets, applied to every rx queue
+    vlan_strip: bool,
+    // accept all multicast packets, FCTRL.MPE is also set while promisc mode is enabled
+    allmulti: Cell<bool>,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// The generations of physical functions this driver supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MacType {
+    /// 82599, its link is configured via AUTOC.
+    Mac82599,
+    /// X540 with an internal 10GBASE-T PHY negotiating 100M, 1G and 10G.
+    X540,
+    /// X550 with an internal 10GBASE-T PHY that also negotiates 2.5G and 5G.
+    X550,
+    /// X550 embedded in a SoC with an external 10GBASE-T PHY.
+    X550Em,
 }
 
-struct IxgbeRxQueue {
+impl MacType {
+    /// Returns the generation of the physical function with the given device id.
+    fn from_device_id(device_id: u16) -> MacType {
+        let device_id = u32::from(device_id);
+
+        if X540_DEVICE_IDS.contains(&device_id) {
+            MacType::X540
+        } else if X550_DEVICE_IDS.contains(&device_id) {
+            MacType::X550
+        } else if X550EM_DEVICE_IDS.contains(&device_id) {
+            MacType::X550Em
+        } else {
+            MacType::Mac82599
+        }
+    }
+
+    /// Returns whether the link is negotiated by a 10GBASE-T PHY.
+    fn is_copper(self) -> bool {
+        self != MacType::Mac82599
+    }
+}
+
+pub(crate) struct IxgbeRxQueue {
     descriptors: *mut ixgbe_adv_rx_desc,
+    // keeps the descriptor ring mapped
+    ring: Dma<ixgbe_adv_rx_desc>,
     num_descriptors: usize,
-    pool: Rc<Mempool>,
+    pool: Arc<Mempool>,
     bufs_in_use: Vec<usize>,
+    external_bufs: Option<VecDeque<usize>>,
     rx_index: usize,
+    rx_tail: usize,
+    num_posted: usize,
+    moderation: InterruptModeration,
+    rx_rate: RxRate,
+    // the device strips VLAN tags into the descriptors
+    vlan_strip: bool,
+    // partial packets by the index of the descriptor holding their next segment, only used
+    // once RSC was enabled as coalesced packets span non-consecutive descriptors
+    rsc_chains: Vec<Option<Packet>>,
 }
 
-struct IxgbeTxQueue {
-    descriptors: *mut ixgbe_adv_tx_desc,
-    num_descriptors: usize,
-    pool: Option<Rc<Mempool>>,
-    bufs_in_use: VecDeque<usize>,
-    clean_index: usize,
-    tx_index: usize,
+/// Packet rate of an rx queue for adaptive interrupt moderation, a moving average over the
+/// last samples.
+struct RxRate {
+    // packets received since the last sample
+    rx_pkts: u64,
+    last_sample: Instant,
+    samples: [u64; ITR_MOVING_AVERAGE_RANGE],
+    index: usize,
+    len: usize,
 }
 
-impl IxyDevice for IxgbeDevice {
-    /// Returns an initialized `IxgbeDevice` on success.
-    ///
-    /// # Panics
-    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
-    fn init(
-        pci_addr: &str,
-        num_rx_queues: u16,
-        num_tx_queues: u16,
-    ) -> Result<IxgbeDevice, Box<dyn Error>> {
-        if unsafe { libc::getuid() } != 0 {
-            warn!("not running as root, this will probably fail");
+impl RxRate {
+    fn new() -> RxRate {
+        RxRate {
+            rx_pkts: 0,
+            last_sample: Instant::now(),
+            samples: [0; ITR_MOVING_AVERAGE_RANGE],
+            index: 0,
+            len: 0,
         }
+    }
 
-        assert!(
-            num_rx_queues <= MAX_QUEUES,
-            "cannot configure {} rx queues: limit is {}",
-            num_rx_queues,
-            MAX_QUEUES
-        );
-        assert!(
-            num_tx_queues <= MAX_QUEUES,
-            "cannot configure {} tx queues: limit is {}",
-            num_tx_queues,
-            MAX_QUEUES
-        );
-
-        // Check if the NIC is IOMMU enabled...
-        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
-
-        let device_fd: RawFd;
-        let (addr, len) = if vfio {
-            device_fd = vfio_init(pci_addr)?;
-            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
-        } else {
-            pci_map_resource(pci_addr)?
-        };
-
-        // initialize RX and TX queue
-        let rx_queues = Vec::with_capacity(num_rx_queues as usize);
-        let tx_queues = Vec::with_capacity(num_tx_queues as usize);
+    /// Samples the packet rate if the sample interval passed and returns the average rate in
+    /// packets per second, or [`None`] if it is too early.
+    fn sample(&mut self) -> Option<u64> {
+        let elapsed = self.last_sample.elapsed();
+        if elapsed < ITR_SAMPLE_INTERVAL {
+            return None;
+        }
 
-        // create the IxyDevice
-        let mut dev = IxgbeDevice {
-            pci_addr: pci_addr.to_string(),
-            addr,
-            len,
-            num_rx_queues,
-            num_tx_queues,
-            rx_queues,
-            tx_queues,
-            vfio,
-            vfio_fd: unsafe { VFIO_CONTAINER_FILE_DESCRIPTOR },
-        };
+        let pps = (self.rx_pkts as f64 / elapsed.as_secs_f64()) as u64;
+        self.rx_pkts = 0;
+        self.last_sample = Instant::now();
 
-        dev.reset_and_init(pci_addr)?;
+        self.samples[self.index] = pps;
+        self.index = (self.index + 1) % ITR_MOVING_AVERAGE_RANGE;
+        self.len = (self.len + 1).min(ITR_MOVING_AVERAGE_RANGE);
 
-        Ok(dev)
+        Some(self.samples[..self.len].iter().sum::<u64>() / self.len as u64)
     }
+}
 
-    /// Returns the driver's name of this device.
-    fn get_driver_name(&self) -> &str {
-        DRIVER_NAME
-    }
+/// Packets taken off an rx ring by `IxgbeRxQueue::receive`.
+pub(crate) struct RxBatch {
+    pub(crate) received: usize,
+    /// Index in the buffer of the packet the device latched a timestamp for.
+    pub(crate) timestamped: Option<usize>,
+    /// New tail of the ring if refilled descriptors have to be handed back to the device.
+    pub(crate) tail: Option<usize>,
+}
 
-    /// Returns the card's iommu capability.
-    fn is_card_iommu_capable(&self) -> bool {
-        self.vfio
+impl IxgbeRxQueue {
+    /// Returns a queue on the descriptor ring `ring` that receives into buffers of `pool`.
+    pub(crate) fn new(ring: Dma<ixgbe_adv_rx_desc>, pool: Arc<Mempool>) -> IxgbeRxQueue {
+        IxgbeRxQueue {
+            descriptors: ring.virt,
+            ring,
+            pool,
+            num_descriptors: NUM_RX_QUEUE_ENTRIES,
+            rx_index: 0,
+            rx_tail: 0,
+            num_posted: NUM_RX_QUEUE_ENTRIES - 1,
+            bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
+            external_bufs: None,
+            moderation: InterruptModeration::Static(DEFAULT_ITR_US),
+            rx_rate: RxRate::new(),
+            vlan_strip: false,
+            rsc_chains: Vec::new(),
+        }
     }
 
-    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
-    fn get_vfio_container(&self) -> Option<RawFd> {
-        if self.vfio {
-            Some(self.vfio_fd)
-        } else {
-            None
+    /// Attaches a buffer of the mempool to every descriptor and returns the tail that posts
+    /// `num_posted` of them to the device.
+    pub(crate) fn fill(&mut self) -> Result<usize, IxyError> {
+        if self.num_descriptors & (self.num_descriptors - 1) != 0 {
+            return Err(IxyError::InvalidConfiguration(
+                "number of queue entries must be a power of 2".to_string(),
+            ));
         }
-    }
 
-    /// Returns the pci address of this device.
-    fn get_pci_addr(&self) -> &str {
-        &self.pci_addr
-    }
+        for i in 0..self.num_descriptors {
+            let pool = &self.pool;
 
-    /// Returns the mac address of this device.
-    fn get_mac_addr(&self) -> [u8; 6] {
-        let low = self.get_reg32(IXGBE_RAL(0));
-        let high = self.get_reg32(IXGBE_RAH(0));
+            let buf = match pool.alloc_buf() {
+                Some(x) => x,
+                None => return Err(IxyError::PoolExhausted),
+            };
 
-        [
-            (low & 0xff) as u8,
-            (low >> 8 & 0xff) as u8,
-            (low >> 16 & 0xff) as u8,
-            (low >> 24) as u8,
-            (high & 0xff) as u8,
-            (high >> 8 & 0xff) as u8,
-        ]
-    }
+            unsafe {
+                ptr::write_volatile(
+                    &mut (*self.descriptors.add(i)).read.pkt_addr as *mut u64,
+                    (pool.get_phys_addr(buf) as u64).to_le(),
+                );
 
-    /// Sets the mac address of this device.
-    fn set_mac_addr(&self, mac: [u8; 6]) {
-        let low: u32 = u32::from(mac[0])
-            + (u32::from(mac[1]) << 8)
-            + (u32::from(mac[2]) << 16)
-            + (u32::from(mac[3]) << 24);
-        let high: u32 = u32::from(mac[4]) + (u32::from(mac[5]) << 8);
+                ptr::write_volatile(&mut (*self.descriptors.add(i)).read.hdr_addr as *mut u64, 0);
+            }
+
+            // we need to remember which descriptor entry belongs to which mempool entry
+            self.bufs_in_use.push(buf);
+        }
+
+        self.rx_tail = self.num_posted;
 
-        self.set_reg32(IXGBE_RAL(0), low);
-        self.set_reg32(IXGBE_RAH(0), high);
+        Ok(self.rx_tail)
     }
 
-    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
-    fn rx_batch(
-        &mut self,
-        queue_id: u32,
-        buffer: &mut VecDeque<Packet>,
-        num_packets: usize,
-    ) -> usize {
-        let mut rx_index;
-        let mut last_rx_index;
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills their
+    /// descriptors.
+    pub(crate) fn receive(&mut self, buffer: &mut VecDeque<Packet>, num_packets: usize) -> RxBatch {
+        if !self.rsc_chains.is_empty() {
+            return self.receive_rsc(buffer, num_packets);
+        }
+
+        let mut rx_index = self.rx_index;
+        let mut last_rx_index = self.rx_index;
         let mut received_packets = 0;
+        let mut timestamped = None;
 
         {
-            let queue = &mut self.rx_queues[queue_id as usize];
-
-            rx_index = queue.rx_index;
-            last_rx_index = queue.rx_index;
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
 
             for i in 0..num_packets {
-                let desc = unsafe { queue.descriptors.add(rx_index) as *mut ixgbe_adv_rx_desc };
-                let status =
-                    unsafe { ptr::read_volatile(&mut (*desc).wb.upper.status_error as *mut u32) };
+                let status = unsafe { rx_desc_status(self, rx_index) };
+
+                if (status & IXGBE_RXDADV_STAT_DD) == 0 {
+                    break;
+                }
 
-                if (status & IXGBE_RXDADV_STAT_DD) != 0 {
-                    if (status & IXGBE_RXDADV_STAT_EOP) == 0 {
-                        panic!("increase buffer size or decrease MTU")
+                // frames larger than a buffer span several descriptors, only take them once the
+                // device has written back all of them
+                let mut num_segments = 1;
+                let mut eop_status = status;
+                while (eop_status & IXGBE_RXDADV_STAT_EOP) == 0 {
+                    let index = (rx_index + num_segments) % self.num_descriptors;
+                    eop_status = unsafe { rx_desc_status(self, index) };
+
+                    if (eop_status & IXGBE_RXDADV_STAT_DD) == 0 {
+                        break;
                     }
+                    num_segments += 1;
+                }
+
+                if (eop_status & IXGBE_RXDADV_STAT_DD) == 0 {
+                    break;
+                }
+
+                // the frame stays in the ring until the descriptors can be refilled without
+                // the buffers reserved for clones
+                if free_stack.available() < num_segments {
+                    break;
+                }
 
-                    let pool = &queue.pool;
+                let pool = &self.pool;
+                let mut packet: Option<Packet> = None;
+                let eop_index = (rx_index + num_segments - 1) % self.num_descriptors;
+                let meta = unsafe { rx_desc_meta(self, rx_index, eop_index, eop_status) };
+
+                for _ in 0..num_segments {
+                    let desc = unsafe { self.descriptors.add(rx_index) };
 
                     // get a free buffer from the mempool
-                    let buf = pool.alloc_buf().expect("no buffer available");
+                    let buf = free_stack.pop().expect("no buffer available");
 
                     // replace currently used buffer with new buffer
-                    let buf = mem::replace(&mut queue.bufs_in_use[rx_index], buf);
+                    let buf = mem::replace(&mut self.bufs_in_use[rx_index], buf);
 
                     let p = unsafe {
                         Packet {
                             addr_virt: pool.get_virt_addr(buf),
                             addr_phys: pool.get_phys_addr(buf),
-                            len: ptr::read_volatile(&(*desc).wb.upper.length as *const u16)
+                            len: u16::from_le(ptr::read_volatile(&(*desc).wb.upper.length))
                                 as usize,
                             pool: pool.clone(),
                             pool_entry: buf,
+                            next: None,
+                            meta: PacketMeta::default(),
                         }
                     };
 
-                    #[cfg(all(
-                        any(target_arch = "x86", target_arch = "x86_64"),
-                        target_feature = "sse"
-                    ))]
-                    p.prefetch(Prefetch::Time1);
-
-                    buffer.push_back(p);
+                    match packet {
+                        Some(ref mut packet) => packet.chain(p),
+                        None => packet = Some(p),
+                    }
 
                     unsafe {
                         ptr::write_volatile(
                             &mut (*desc).read.pkt_addr as *mut u64,
-                            pool.get_phys_addr(queue.bufs_in_use[rx_index]) as u64,
+                            (pool.get_phys_addr(self.bufs_in_use[rx_index]) as u64).to_le(),
                         );
                         ptr::write_volatile(&mut (*desc).read.hdr_addr as *mut u64, 0);
                     }
 
                     last_rx_index = rx_index;
-                    rx_index = wrap_ring(rx_index, queue.num_descriptors);
-                    received_packets = i + 1;
-                } else {
-                    break;
+                    rx_index = wrap_ring(rx_index, self.num_descriptors);
                 }
-            }
-        }
 
-        if rx_index != last_rx_index {
-            self.set_reg32(IXGBE_RDT(queue_id), last_rx_index as u32);
-            self.rx_queues[queue_id as usize].rx_index = rx_index;
+                let mut p = packet.unwrap();
+                p.meta = meta;
+
+                // the device latches the timestamp of a single packet until it is read
+                if (eop_status & IXGBE_RXDADV_STAT_TS) != 0 {
+                    timestamped = Some(buffer.len());
+                }
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                buffer.push_back(p);
+                received_packets = i + 1;
+            }
         }
 
-        received_packets
+        self.finish_batch(rx_index, last_rx_index, received_packets, timestamped)
     }
 
-    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
-    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
-        let mut sent = 0;
+    /// Like `receive`, but follows the descriptors of packets coalesced by RSC. Their segments
+    /// are written to arbitrary descriptors, each one points to the next via NEXTP, see section
+    /// 7.11.5.
+    fn receive_rsc(&mut self, buffer: &mut VecDeque<Packet>, num_packets: usize) -> RxBatch {
+        let mut rx_index = self.rx_index;
+        let mut last_rx_index = self.rx_index;
+        let mut received_packets = 0;
+        let mut timestamped = None;
 
         {
-            let mut queue = &mut self.tx_queues[queue_id as usize];
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
 
-            let mut cur_index = queue.tx_index;
-            let clean_index = clean_tx_queue(&mut queue);
+            while received_packets < num_packets {
+                let status = unsafe { rx_desc_status(self, rx_index) };
 
-            if queue.pool.is_none() {
-                if let Some(packet) = packets.get(0) {
-                    queue.pool = Some(packet.pool.clone());
+                if (status & IXGBE_RXDADV_STAT_DD) == 0 || free_stack.available() == 0 {
+                    break;
                 }
-            }
 
-            while let Some(packet) = packets.pop_front() {
-                assert!(
-                    Rc::ptr_eq(queue.pool.as_ref().unwrap(), &packet.pool),
-                    "distinct memory pools for a single tx queue are not supported yet"
-                );
-
-                let next_index = wrap_ring(cur_index, queue.num_descriptors);
+                let desc = unsafe { self.descriptors.add(rx_index) };
+                let pool = &self.pool;
 
-                if clean_index == next_index {
-                    // tx queue of device is full, push packet back onto the
-                    // queue of to-be-sent packets
-                    packets.push_front(packet);
-                    break;
-                }
+                // the last descriptor of a coalesced packet holds the metadata of all of it
+                let meta = if (status & IXGBE_RXDADV_STAT_EOP) != 0 {
+                    Some(unsafe { rx_desc_meta(self, rx_index, rx_index, status) })
+                } else {
+                    None
+                };
+                let rsc_count = unsafe {
+                    u32::from_le(ptr::read_volatile(&(*desc).wb.lower.lo_dword.data))
+                        & IXGBE_RXDADV_RSCCNT_MASK
+                };
 
-                queue.tx_index = wrap_ring(queue.tx_index, queue.num_descriptors);
+                // get a free buffer from the mempool
+                let buf = free_stack.pop().expect("no buffer available");
+
+                // replace currently used buffer with new buffer
+                let buf = mem::replace(&mut self.bufs_in_use[rx_index], buf);
+
+                let p = unsafe {
+                    Packet {
+                        addr_virt: pool.get_virt_addr(buf),
+                        addr_phys: pool.get_phys_addr(buf),
+                        len: u16::from_le(ptr::read_volatile(&(*desc).wb.upper.length)) as usize,
+                        pool: pool.clone(),
+                        pool_entry: buf,
+                        next: None,
+                        meta: PacketMeta::default(),
+                    }
+                };
 
                 unsafe {
                     ptr::write_volatile(
-                        &mut (*queue.descriptors.add(cur_index)).read.buffer_addr as *mut u64,
-                        packet.get_phys_addr() as u64,
-                    );
-                    ptr::write_volatile(
-                        &mut (*queue.descriptors.add(cur_index)).read.cmd_type_len as *mut u32,
-                        IXGBE_ADVTXD_DCMD_EOP
-                            | IXGBE_ADVTXD_DCMD_RS
-                            | IXGBE_ADVTXD_DCMD_IFCS
-                            | IXGBE_ADVTXD_DCMD_DEXT
-                            | IXGBE_ADVTXD_DTYP_DATA
-                            | packet.len() as u32,
-                    );
-                    ptr::write_volatile(
-                        &mut (*queue.descriptors.add(cur_index)).read.olinfo_status as *mut u32,
-                        (packet.len() as u32) << IXGBE_ADVTXD_PAYLEN_SHIFT,
+                        &mut (*desc).read.pkt_addr as *mut u64,
+                        (pool.get_phys_addr(self.bufs_in_use[rx_index]) as u64).to_le(),
                     );
+                    ptr::write_volatile(&mut (*desc).read.hdr_addr as *mut u64, 0);
+                }
+
+                let mut packet = match self.rsc_chains[rx_index].take() {
+                    Some(mut packet) => {
+                        packet.chain(p);
+                        packet
+                    }
+                    None => p,
+                };
+
+                last_rx_index = rx_index;
+                rx_index = wrap_ring(rx_index, self.num_descriptors);
+
+                let meta = match meta {
+                    Some(meta) => meta,
+                    None => {
+                        // segments of frames that are not coalesced are consecutive
+                        let next = if rsc_count != 0 {
+                            ((status & IXGBE_RXDADV_NEXTP_MASK) >> IXGBE_RXDADV_NEXTP_SHIFT)
+                                as usize
+                        } else {
+                            rx_index
+                        };
+
+                        self.rsc_chains[next] = Some(packet);
+                        continue;
+                    }
+                };
+
+                packet.meta = meta;
+
+                // the device latches the timestamp of a single packet until it is read
+                if (status & IXGBE_RXDADV_STAT_TS) != 0 {
+                    timestamped = Some(buffer.len());
                 }
 
-                queue.bufs_in_use.push_back(packet.pool_entry);
-                mem::forget(packet);
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                packet.prefetch(Prefetch::Time1);
 
-                cur_index = next_index;
-                sent += 1;
+                buffer.push_back(packet);
+                received_packets += 1;
             }
         }
 
-        self.set_reg32(
-            IXGBE_TDT(queue_id),
-            self.tx_queues[queue_id as usize].tx_index as u32,
-        );
-
-        sent
+        self.finish_batch(rx_index, last_rx_index, received_packets, timestamped)
     }
 
-    /// Reads the stats of this device into `stats`.
-    fn read_stats(&self, stats: &mut DeviceStats) {
-        let rx_pkts = u64::from(self.get_reg32(IXGBE_GPRC));
-        let tx_pkts = u64::from(self.get_reg32(IXGBE_GPTC));
-        let rx_bytes =
-            u64::from(self.get_reg32(IXGBE_GORCL)) + (u64::from(self.get_reg32(IXGBE_GORCH)) << 32);
-        let tx_bytes =
-            u64::from(self.get_reg32(IXGBE_GOTCL)) + (u64::from(self.get_reg32(IXGBE_GOTCH)) << 32);
+    /// Moves the queue on to `rx_index` after `received_packets` were taken off the ring, the
+    /// last descriptor of the batch being at `last_rx_index`.
+    fn finish_batch(
+        &mut self,
+        rx_index: usize,
+        last_rx_index: usize,
+        received_packets: usize,
+        timestamped: Option<usize>,
+    ) -> RxBatch {
+        self.rx_rate.rx_pkts += received_packets as u64;
 
-        stats.rx_pkts += rx_pkts;
-        stats.tx_pkts += tx_pkts;
-        stats.rx_bytes += rx_bytes;
-        stats.tx_bytes += tx_bytes;
-    }
+        let mut tail = None;
 
-    /// Resets the stats of this device.
-    fn reset_stats(&self) {
-        self.get_reg32(IXGBE_GPRC);
-        self.get_reg32(IXGBE_GPTC);
-        self.get_reg32(IXGBE_GORCL);
-        self.get_reg32(IXGBE_GORCH);
-        self.get_reg32(IXGBE_GOTCL);
-        self.get_reg32(IXGBE_GOTCH);
-    }
+        if rx_index != last_rx_index {
+            self.rx_index = rx_index;
 
-    /// Returns the link speed of this device.
-    fn get_link_speed(&self) -> u16 {
-        let speed = self.get_reg32(IXGBE_LINKS);
-        if (speed & IXGBE_LINKS_UP) == 0 {
-            return 0;
+            // hand refilled descriptors back to the device, at most num_posted at a time
+            let posted = (self.rx_tail + self.num_descriptors - rx_index) % self.num_descriptors;
+            if posted < self.num_posted {
+                self.rx_tail = (rx_index + self.num_posted) % self.num_descriptors;
+                tail = Some(self.rx_tail);
+            }
         }
-        match speed & IXGBE_LINKS_SPEED_82599 {
-            IXGBE_LINKS_SPEED_100_82599 => 100,
-            IXGBE_LINKS_SPEED_1G_82599 => 1000,
-            IXGBE_LINKS_SPEED_10G_82599 => 10000,
-            _ => 0,
+
+        RxBatch {
+            received: received_packets,
+            timestamped,
+            tail,
         }
     }
-}
 
-impl IxgbeDevice {
-    /// Resets and initializes this device.
-    fn reset_and_init(&mut self, pci_addr: &str) -> Result<(), Box<dyn Error>> {
-        info!("resetting device {}", pci_addr);
-        // section 4.6.3.1 - disable all interrupts
-        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+    /// Returns the content of the next received packet without taking it off the ring.
+    pub(crate) fn peek(&self) -> Option<&[u8]> {
+        if self.external_bufs.is_some() {
+            return None;
+        }
+        let desc = unsafe { self.descriptors.add(self.rx_index) };
+        let status = unsafe { u32::from_le(ptr::read_volatile(&(*desc).wb.upper.status_error)) };
 
-        // section 4.6.3.2
-        self.set_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
-        self.wait_clear_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
-        thread::sleep(Duration::from_millis(10));
+        if (status & IXGBE_RXDADV_STAT_DD) == 0 {
+            return None;
+        }
 
-        // section 4.6.3.1 - disable interrupts again after reset
-        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+        unsafe {
+            let len = u16::from_le(ptr::read_volatile(&(*desc).wb.upper.length)) as usize;
+            let addr = self.pool.get_virt_addr(self.bufs_in_use[self.rx_index]);
 
-        let mac = self.get_mac_addr();
-        info!("initializing device {}", pci_addr);
-        info!(
-            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
-        );
+            Some(slice::from_raw_parts(addr, len))
+        }
+    }
 
-        // section 4.6.3 - wait for EEPROM auto read completion
-        self.wait_set_reg32(IXGBE_EEC, IXGBE_EEC_ARD);
+    /// Limits the descriptors the device may receive into to `num_posted`, returns the new tail
+    /// if more descriptors have to be handed to the device right away.
+    pub(crate) fn set_posted(
+        &mut self,
+        queue_id: u32,
+        num_posted: usize,
+    ) -> Result<Option<usize>, IxyError> {
+        if num_posted >= self.num_descriptors {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                self.num_descriptors,
+                self.num_descriptors - 1
+            )));
+        }
 
-        // section 4.6.3 - wait for dma initialization done
-        self.wait_set_reg32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_DMAIDONE);
+        if self.external_bufs.is_some() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} receives into external buffers",
+                queue_id
+            )));
+        }
 
-        // skip last step from 4.6.3 - we don't want interrupts
+        self.num_posted = num_posted;
 
-        // section 4.6.4 - initialize link (auto negotiation)
-        self.init_link();
+        // the tail never moves backwards, lowering the limit takes effect as packets arrive
+        let posted = (self.rx_tail + self.num_descriptors - self.rx_index) % self.num_descriptors;
+        if posted >= num_posted || self.bufs_in_use.is_empty() {
+            return Ok(None);
+        }
 
-        // section 4.6.5 - statistical counters
-        // reset-on-read registers, just read them once
-        self.reset_stats();
+        self.rx_tail = (self.rx_index + num_posted) % self.num_descriptors;
+
+        Ok(Some(self.rx_tail))
+    }
+
+    /// Returns the number of descriptors the device may receive into.
+    pub(crate) fn num_posted(&self) -> usize {
+        self.num_posted
+    }
+
+    /// Returns the mempool the queue receives into.
+    pub(crate) fn pool(&self) -> &Arc<Mempool> {
+        &self.pool
+    }
+
+    /// Replaces the mempool of a drained queue, `fill` takes the buffers from `pool` then.
+    pub(crate) fn set_pool(&mut self, pool: Arc<Mempool>) {
+        self.pool = pool;
+    }
+
+    /// Prepares the queue for packets coalesced by RSC. The queue keeps following their
+    /// descriptors after RSC is disabled again, packets may still be in flight.
+    fn enable_rsc(&mut self) {
+        if self.rsc_chains.is_empty() {
+            self.rsc_chains.resize_with(self.num_descriptors, || None);
+        }
+    }
+
+    /// Returns whether the ring holds no buffers, i.e. it was drained.
+    pub(crate) fn is_drained(&self) -> bool {
+        self.bufs_in_use.is_empty() && self.external_bufs.is_none()
+    }
+
+    /// Returns all buffers of the ring to the mempool, the device must not access them anymore.
+    pub(crate) fn drain(&mut self) {
+        for buf in self.bufs_in_use.drain(..) {
+            self.pool.free_buf(buf);
+        }
+
+        // partial packets are never completed, their buffers go back to the pool as well
+        for chain in self.rsc_chains.iter_mut() {
+            chain.take();
+        }
+
+        // the device no longer accesses external buffers, they belong to the caller again
+        self.external_bufs = None;
+
+        // clear the descriptor done bits so rx_batch doesn't pick up the freed buffers
+        unsafe {
+            memset(
+                self.descriptors as *mut u8,
+                self.num_descriptors * mem::size_of::<ixgbe_adv_rx_desc>(),
+                0x00,
+            );
+        }
+
+        self.rx_index = 0;
+        self.rx_tail = 0;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    pub(crate) fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<ixgbe_adv_rx_desc>(),
+        )
+    }
+}
+
+pub(crate) struct IxgbeTxQueue {
+    descriptors: *mut ixgbe_adv_tx_desc,
+    // keeps the descriptor ring mapped
+    ring: Dma<ixgbe_adv_tx_desc>,
+    num_descriptors: usize,
+    pool: Option<Arc<Mempool>>,
+    bufs_in_use: VecDeque<TxBuffer>,
+    completed_external: VecDeque<usize>,
+    clean_index: usize,
+    tx_index: usize,
+    watchdog_head: usize,
+    watchdog_time: Instant,
+}
+
+impl IxgbeTxQueue {
+    /// Returns an empty queue on the descriptor ring `ring`.
+    pub(crate) fn new(ring: Dma<ixgbe_adv_tx_desc>) -> IxgbeTxQueue {
+        IxgbeTxQueue {
+            descriptors: ring.virt,
+            ring,
+            bufs_in_use: VecDeque::with_capacity(NUM_TX_QUEUE_ENTRIES),
+            completed_external: VecDeque::new(),
+            pool: None,
+            num_descriptors: NUM_TX_QUEUE_ENTRIES,
+            clean_index: 0,
+            tx_index: 0,
+            watchdog_head: 0,
+            watchdog_time: Instant::now(),
+        }
+    }
+
+    /// Pops as many packets as fit into the ring from `packets` and writes their descriptors,
+    /// the device only sends them once the tail is moved to `tail`.
+    pub(crate) fn send(&mut self, packets: &mut VecDeque<Packet>) -> usize {
+        let mut sent = 0;
+        let mut cur_index = self.tx_index;
+        let clean_index = clean_tx_queue(self);
+        let mut writer = TxDescWriter::new(self.descriptors, self.num_descriptors, cur_index);
+
+        if self.pool.is_none() {
+            if let Some(packet) = packets.front() {
+                self.pool = Some(packet.pool.clone());
+            }
+        }
+
+        while let Some(mut packet) = packets.pop_front() {
+            let num_segments = packet.num_segments();
+
+            assert!(
+                packet
+                    .segments()
+                    .all(|s| Arc::ptr_eq(self.pool.as_ref().unwrap(), &s.pool)),
+                "distinct memory pools for a single tx queue are not supported yet"
+            );
+            assert!(
+                num_segments <= TX_MAX_SEGMENTS,
+                "packet of {} segments exceeds the limit of {}",
+                num_segments,
+                TX_MAX_SEGMENTS
+            );
+
+            // offloads need a context descriptor in front of the data descriptors
+            let offload = TxOffload::from_packet(&mut packet);
+            let num_needed = num_segments + usize::from(offload.is_some());
+
+            // one descriptor always stays empty to tell a full ring from an empty one
+            let free = (clean_index + self.num_descriptors - cur_index - 1) % self.num_descriptors;
+
+            if free < num_needed {
+                // tx queue of device is full, push packet back onto the
+                // queue of to-be-sent packets
+                packets.push_front(packet);
+                break;
+            }
+
+            if let Some(ref offload) = offload {
+                unsafe {
+                    writer.push(tx_context_desc(offload));
+                }
+
+                self.bufs_in_use.push_back(TxBuffer::Context);
+                cur_index = wrap_ring(cur_index, self.num_descriptors);
+            }
+
+            let offload = offload.unwrap_or_default();
+            let total_len = packet.total_len();
+            let mut segment = Some(packet);
+
+            while let Some(mut p) = segment {
+                segment = p.unchain();
+
+                unsafe {
+                    writer.push(tx_data_desc(
+                        p.get_phys_addr(),
+                        p.len(),
+                        total_len,
+                        segment.is_none(),
+                        &offload,
+                    ));
+                }
+
+                self.bufs_in_use.push_back(TxBuffer::Pool(p.pool_entry));
+                mem::forget(p);
+
+                cur_index = wrap_ring(cur_index, self.num_descriptors);
+            }
+
+            self.tx_index = cur_index;
+            sent += 1;
+        }
+
+        unsafe {
+            writer.flush();
+        }
+
+        sent
+    }
+
+    /// Returns the index after the last written descriptor, i.e. the tail for the device.
+    pub(crate) fn tail(&self) -> usize {
+        self.tx_index
+    }
+
+    /// Returns whether the ring is empty or the device moved its `head` within `timeout`.
+    pub(crate) fn healthy(&mut self, head: usize, timeout: Duration) -> bool {
+        if head == self.tx_index || head != self.watchdog_head {
+            self.watchdog_head = head;
+            self.watchdog_time = Instant::now();
+
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Drops all pending packets and rewinds the ring, the device must not access it anymore.
+    pub(crate) fn reset(&mut self) {
+        release_tx_buffers(self);
+
+        self.clean_index = 0;
+        self.tx_index = 0;
+        self.watchdog_head = 0;
+        self.watchdog_time = Instant::now();
+    }
+
+    /// Returns all buffers to their mempool once the device's head caught up with the tail.
+    pub(crate) fn release_sent(&mut self) {
+        release_tx_buffers(self);
+        self.clean_index = self.tx_index;
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    pub(crate) fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<ixgbe_adv_tx_desc>(),
+        )
+    }
+}
+
+/// SR-IOV configuration of the physical function, see section 7.10.
+struct Sriov {
+    /// Pci addresses of the virtual functions, virtual function n owns VMDq pool n.
+    vfs: Vec<String>,
+    /// 4 with 32 pools, 2 with 64 pools.
+    queues_per_pool: u32,
+    vf_states: Vec<VfState>,
+}
+
+/// What the physical function knows about one of its virtual functions.
+#[derive(Clone, Copy)]
+struct VfState {
+    mac: [u8; 6],
+    /// Set via `set_vf_mac`, the virtual function may not change it.
+    mac_locked: bool,
+    /// Port VLAN inserted on tx and required on rx.
+    vlan: Option<u16>,
+    /// The virtual function completed the reset handshake and may send other requests.
+    clear_to_send: bool,
+    api_version: u32,
+}
+
+/// A buffer referenced by a tx descriptor that has not been cleaned up yet.
+enum TxBuffer {
+    /// Entry of the queue's mempool, returned to the pool once sent.
+    Pool(usize),
+    /// Externally-owned buffer, identified by its physical address.
+    External(usize),
+    /// Context descriptor of the following packet, it has no buffer.
+    Context,
+}
+
+/// Offloads of a packet to be sent, i.e. the fields of the context descriptor it needs and the
+/// flags of its data descriptors, see section 7.2.3.2.3.
+#[derive(Clone, Copy, Default)]
+struct TxOffload {
+    vlan_macip_lens: u32,
+    type_tucmd_mlhl: u32,
+    mss_l4len_idx: u32,
+    cmd_type_len: u32,
+    olinfo_status: u32,
+    // headers of a packet to be segmented, they are not part of the payload length
+    header_len: usize,
+}
+
+impl TxOffload {
+    /// Returns the offloads the metadata of `packet` asks for, or [`None`] if it doesn't need a
+    /// context descriptor. Prepares the headers of packets to be segmented.
+    fn from_packet(packet: &mut Packet) -> Option<TxOffload> {
+        let meta = *packet.meta();
+        let mut offload = TxOffload::default();
+        let mut needs_context = false;
+        let mut maclen = ETHERNET_HEADER_LEN;
+        let mut iplen = 0;
+
+        // section 7.2.3.2.3 - the tag is inserted after the mac addresses
+        if let Some(vlan_tci) = meta.vlan_tci {
+            offload.vlan_macip_lens |= u32::from(vlan_tci) << IXGBE_ADVTXD_VLAN_SHIFT;
+            offload.cmd_type_len |= IXGBE_ADVTXD_DCMD_VLE;
+            needs_context = true;
+        }
+
+        let offloads = meta.tx_offloads;
+        let tso_mss = offloads.tso_mss.filter(|&mss| mss > 0);
+        let checksums = offloads.ipv4_checksum || offloads.l4_checksum || tso_mss.is_some();
+
+        // section 7.2.5 - the device needs the header lengths to fill in the checksums
+        if let Some(offsets) = HeaderOffsets::parse(packet).filter(|_| checksums) {
+            maclen = offsets.l3 as u32;
+            iplen = (offsets.l4 - offsets.l3) as u32;
+            needs_context = true;
+
+            if offsets.ipv4 {
+                offload.type_tucmd_mlhl |= IXGBE_ADVTXD_TUCMD_IPV4;
+            }
+
+            let tcp_header_len = offsets.tcp_header_len(packet);
+
+            match (tso_mss, tcp_header_len) {
+                // section 7.2.4 - the device adjusts the IP header and the TCP checksum of every
+                // segment, the checksum field holds the pseudo header without a length
+                (Some(mss), Some(l4len)) => {
+                    let checksum =
+                        pseudo_header_checksum_without_len(&packet[offsets.l3..offsets.l4]);
+                    write_l4_checksum(packet, &offsets, checksum);
+
+                    if offsets.ipv4 {
+                        packet[offsets.l3 + IPV4_CHECKSUM_OFFSET..][..2].fill(0);
+                        offload.olinfo_status |= IXGBE_ADVTXD_POPTS_IXSM;
+                    }
+
+                    offload.type_tucmd_mlhl |= IXGBE_ADVTXD_TUCMD_L4T_TCP;
+                    offload.mss_l4len_idx = (u32::from(mss) << IXGBE_ADVTXD_MSS_SHIFT)
+                        | ((l4len as u32) << IXGBE_ADVTXD_L4LEN_SHIFT);
+                    offload.cmd_type_len |= IXGBE_ADVTXD_DCMD_TSE;
+                    offload.olinfo_status |= IXGBE_ADVTXD_POPTS_TXSM;
+                    offload.header_len = offsets.l4 + l4len;
+                }
+                _ => {
+                    if offsets.ipv4 && offloads.ipv4_checksum {
+                        offload.olinfo_status |= IXGBE_ADVTXD_POPTS_IXSM;
+                    }
+
+                    if offloads.l4_checksum {
+                        match offsets.protocol {
+                            IPPROTO_TCP => {
+                                offload.type_tucmd_mlhl |= IXGBE_ADVTXD_TUCMD_L4T_TCP;
+                                offload.olinfo_status |= IXGBE_ADVTXD_POPTS_TXSM;
+                            }
+                            IPPROTO_UDP => {
+                                offload.type_tucmd_mlhl |= IXGBE_ADVTXD_TUCMD_L4T_UDP;
+                                offload.olinfo_status |= IXGBE_ADVTXD_POPTS_TXSM;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        if !needs_context {
+            return None;
+        }
+
+        offload.vlan_macip_lens |= (maclen << IXGBE_ADVTXD_MACLEN_SHIFT) | iplen;
+
+        Some(offload)
+    }
+}
+
+impl IxyDevice for IxgbeDevice {
+    /// Returns an initialized `IxgbeDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<IxgbeDevice, IxyError> {
+        IxgbeDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        let low = self.get_reg32(IXGBE_RAL(0));
+        let high = self.get_reg32(IXGBE_RAH(0));
+
+        [
+            (low & 0xff) as u8,
+            (low >> 8 & 0xff) as u8,
+            (low >> 16 & 0xff) as u8,
+            (low >> 24) as u8,
+            (high & 0xff) as u8,
+            (high >> 8 & 0xff) as u8,
+        ]
+    }
+
+    /// Sets the mac address of this device.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        self.write_rar(0, mac, self.default_pool());
+    }
+
+    /// Sets the mac address of this device and associates it with VMDq pool `pool`.
+    fn set_mac_addr_pool(&self, mac: [u8; 6], pool: u8) -> Result<(), IxyError> {
+        if u32::from(pool) >= NUM_POOLS {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "pool {} exceeds the {} VMDq pools",
+                pool, NUM_POOLS
+            )));
+        }
+
+        self.write_rar(0, mac, pool);
+
+        Ok(())
+    }
+
+    /// Adds `mac` to the first free receive address register and returns its index.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        // the address valid bit marks registers that are in use
+        let index = (1..NUM_RAR_ENTRIES)
+            .find(|&i| self.get_reg32(IXGBE_RAH(i)) & IXGBE_RAH_AV == 0)
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(
+                    "all receive address registers are in use".to_string(),
+                )
+            })?;
+
+        // associate the address with the pool of the primary address
+        self.write_rar(index, mac, self.default_pool());
+
+        Ok(index as usize)
+    }
+
+    /// Clears the receive address register `index`.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        if index == 0 || index >= NUM_RAR_ENTRIES as usize {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "receive address register {} is not a unicast filter",
+                index
+            )));
+        }
+
+        let index = index as u32;
+
+        self.set_reg32(IXGBE_RAH(index), 0);
+        self.set_reg32(IXGBE_RAL(index), 0);
+        self.set_reg32(IXGBE_MPSAR_LO(index), 0);
+
+        Ok(())
+    }
+
+    /// Enables or disables promisc mode of this device.
+    fn set_promisc(&self, enabled: bool) -> Result<(), IxyError> {
+        if enabled {
+            info!("enabling promisc mode");
+            self.set_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE | IXGBE_FCTRL_UPE);
+        } else if self.allmulti.get() {
+            info!("disabling promisc mode");
+            self.clear_flags32(IXGBE_FCTRL, IXGBE_FCTRL_UPE);
+        } else {
+            info!("disabling promisc mode");
+            self.clear_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE | IXGBE_FCTRL_UPE);
+        }
+
+        Ok(())
+    }
+
+    /// Enables or disables the reception of all multicast packets, see section 7.1.1.1.
+    fn set_allmulti(&self, enabled: bool) -> Result<(), IxyError> {
+        self.allmulti.set(enabled);
+
+        // promisc mode accepts all multicast packets anyway
+        if enabled {
+            self.set_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE);
+        } else if self.get_reg32(IXGBE_FCTRL) & IXGBE_FCTRL_UPE == 0 {
+            self.clear_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the multicast table array with the hashes of `macs`. The table is imperfect,
+    /// packets of other addresses with the same hash are accepted as well.
+    fn set_multicast_filters(&self, macs: &[[u8; 6]]) -> Result<(), IxyError> {
+        self.write_multicast_filters(macs);
+
+        Ok(())
+    }
+
+    // section 7.1.2.8
+    /// Programs the hash key, the redirection table and the hashed fields and enables RSS.
+    fn enable_rss(&self, config: &RssConfig) -> Result<(), IxyError> {
+        if self.sriov.is_some() {
+            return Err(IxyError::InvalidConfiguration(
+                "cannot enable RSS while SR-IOV is enabled".to_string(),
+            ));
+        }
+
+        let max_queue = self.num_rx_queues.min(MAX_RSS_QUEUES);
+        if let Some(queue) = config.reta.iter().find(|&&q| q >= max_queue) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot redirect flows to rx queue {}: RSS uses the first {} rx queues",
+                queue, max_queue
+            )));
+        }
+
+        // the key is written in the byte order it is applied in
+        for (i, key) in config.key.chunks(4).enumerate() {
+            let key = u32::from_le_bytes([key[0], key[1], key[2], key[3]]);
+            self.set_reg32(IXGBE_RSSRK(i as u32), key);
+        }
+
+        // 4 entries per register, entry n in byte n % 4
+        for (i, entries) in config.reta.chunks(4).enumerate() {
+            let reta = entries
+                .iter()
+                .enumerate()
+                .fold(0, |reta, (j, &q)| reta | u32::from(q) << (j * 8));
+            self.set_reg32(IXGBE_RETA(i as u32), reta);
+        }
+
+        let fields = [
+            (config.fields.ipv4, IXGBE_MRQC_RSS_FIELD_IPV4),
+            (config.fields.ipv4_tcp, IXGBE_MRQC_RSS_FIELD_IPV4_TCP),
+            (config.fields.ipv4_udp, IXGBE_MRQC_RSS_FIELD_IPV4_UDP),
+            (config.fields.ipv6, IXGBE_MRQC_RSS_FIELD_IPV6),
+            (config.fields.ipv6_tcp, IXGBE_MRQC_RSS_FIELD_IPV6_TCP),
+            (config.fields.ipv6_udp, IXGBE_MRQC_RSS_FIELD_IPV6_UDP),
+        ];
+        let mrqc = fields
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(IXGBE_MRQC_RSSEN, |mrqc, (_, field)| mrqc | field);
+
+        // the rx descriptor holds either the hash or the packet checksum
+        self.set_flags32(IXGBE_RXCSUM, IXGBE_RXCSUM_PCSD);
+        self.set_reg32(IXGBE_MRQC, mrqc);
+
+        info!(
+            "enabled RSS over {} rx queues",
+            config.reta.iter().max().map_or(0, |&q| q + 1)
+        );
+
+        *self.rss.borrow_mut() = Some(config.clone());
+
+        Ok(())
+    }
+
+    /// Disables RSS, all packets are received on queue 0.
+    fn disable_rss(&self) -> Result<(), IxyError> {
+        // virtualization owns MRQC while SR-IOV is enabled, RSS can't be enabled then
+        if self.sriov.is_none() {
+            self.set_reg32(IXGBE_MRQC, 0);
+            self.clear_flags32(IXGBE_RXCSUM, IXGBE_RXCSUM_PCSD);
+        }
+
+        *self.rss.borrow_mut() = None;
+
+        Ok(())
+    }
+
+    // section 7.1.2.7
+    /// Adds `rule` as perfect match filter of the flow director. All rules share the masks of
+    /// the first one.
+    fn add_flow_rule(&self, rule: &FlowRule) -> Result<usize, IxyError> {
+        if rule.protocol == FlowProtocol::Other && (rule.src_port_mask | rule.dst_port_mask) != 0 {
+            return Err(IxyError::InvalidConfiguration(
+                "cannot match the ports of packets without ports".to_string(),
+            ));
+        }
+
+        if let FlowAction::Queue(queue) = rule.action {
+            if queue >= self.num_rx_queues {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "cannot steer flows to rx queue {}: only {} are configured",
+                    queue, self.num_rx_queues
+                )));
+            }
+        }
+
+        let mut rules = self.flow_rules.borrow_mut();
+
+        // the masks are global and can only change while the table is empty
+        match rules.iter().flatten().next() {
+            Some(first) if !fdir_masks_equal(first, rule) => {
+                return Err(IxyError::InvalidConfiguration(
+                    "all flow rules have to use the same masks".to_string(),
+                ));
+            }
+            Some(_) => {}
+            None => self.write_fdir_masks(rule),
+        }
+
+        let index = match rules.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if rules.len() < NUM_FLOW_RULES => {
+                rules.push(None);
+                rules.len() - 1
+            }
+            None => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "all {} flow rules are in use",
+                    NUM_FLOW_RULES
+                )))
+            }
+        };
+
+        let (flow_type, src_ip, dst_ip, ports) = fdir_input(rule);
+
+        self.set_reg32(IXGBE_FDIRIPSA, src_ip);
+        self.set_reg32(IXGBE_FDIRIPDA, dst_ip);
+        self.set_reg32(IXGBE_FDIRPORT, ports);
+        self.set_reg32(IXGBE_FDIRVLAN, 0);
+        self.set_reg32(
+            IXGBE_FDIRHASH,
+            fdir_bucket_hash(rule) | ((index as u32) << IXGBE_FDIRHASH_SIG_SW_INDEX_SHIFT),
+        );
+
+        let (queue, drop) = match rule.action {
+            FlowAction::Queue(queue) => (self.hw_queue(queue), 0),
+            FlowAction::Drop => (IXGBE_FDIR_DROP_QUEUE, IXGBE_FDIRCMD_DROP),
+        };
+
+        self.write_fdir_cmd(
+            IXGBE_FDIRCMD_CMD_ADD_FLOW
+                | IXGBE_FDIRCMD_FILTER_UPDATE
+                | IXGBE_FDIRCMD_LAST
+                | IXGBE_FDIRCMD_QUEUE_EN
+                | drop
+                | (flow_type << IXGBE_FDIRCMD_FLOW_TYPE_SHIFT)
+                | (queue << IXGBE_FDIRCMD_RX_QUEUE_SHIFT)
+                | (u32::from(self.default_pool()) << IXGBE_FDIRCMD_VT_POOL_SHIFT),
+        )?;
+
+        rules[index] = Some(*rule);
+
+        Ok(index)
+    }
+
+    /// Removes the perfect match filter `index` of the flow director.
+    fn remove_flow_rule(&self, index: usize) -> Result<(), IxyError> {
+        let mut rules = self.flow_rules.borrow_mut();
+
+        let rule = match rules.get(index) {
+            Some(Some(rule)) => *rule,
+            _ => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "flow rule {} does not exist",
+                    index
+                )))
+            }
+        };
+
+        let fdirhash =
+            fdir_bucket_hash(&rule) | ((index as u32) << IXGBE_FDIRHASH_SIG_SW_INDEX_SHIFT);
+
+        // the filter is looked up by its hash and software index
+        self.set_reg32(IXGBE_FDIRHASH, fdirhash);
+        let fdircmd = self.write_fdir_cmd(IXGBE_FDIRCMD_CMD_QUERY_REM_FILT)?;
+
+        if fdircmd & IXGBE_FDIRCMD_FILTER_VALID != 0 {
+            self.set_reg32(IXGBE_FDIRHASH, fdirhash);
+            self.write_fdir_cmd(IXGBE_FDIRCMD_CMD_REMOVE_FLOW)?;
+        }
+
+        rules[index] = None;
+
+        Ok(())
+    }
+
+    /// Enables or disables VLAN stripping on all rx queues, see section 7.4.5.
+    fn set_vlan_strip(&mut self, enabled: bool) -> Result<(), IxyError> {
+        self.vlan_strip = enabled;
+
+        for i in 0..self.num_rx_queues {
+            self.write_vlan_strip(i);
+        }
+
+        Ok(())
+    }
+
+    /// Adds `vlan` to the VLAN filter table and enables filtering, see section 7.1.1.2.
+    fn add_vlan_filter(&self, vlan: u16) -> Result<(), IxyError> {
+        self.check_vlan_filter(vlan)?;
+
+        let vlan = u32::from(vlan);
+        self.set_flags32(IXGBE_VFTA(vlan / 32), 1 << (vlan % 32));
+        self.set_flags32(IXGBE_VLNCTRL, IXGBE_VLNCTRL_VFE);
+
+        Ok(())
+    }
+
+    /// Removes `vlan` from the VLAN filter table and disables filtering once it is empty.
+    fn remove_vlan_filter(&self, vlan: u16) -> Result<(), IxyError> {
+        self.check_vlan_filter(vlan)?;
+
+        let vlan = u32::from(vlan);
+        self.clear_flags32(IXGBE_VFTA(vlan / 32), 1 << (vlan % 32));
+
+        if (0..NUM_VFTA_ENTRIES).all(|i| self.get_reg32(IXGBE_VFTA(i)) == 0) {
+            self.clear_flags32(IXGBE_VLNCTRL, IXGBE_VLNCTRL_VFE);
+        }
+
+        Ok(())
+    }
+
+    /// Enables or disables RSC on rx queue `queue_id`, see section 7.11. The queue is stopped
+    /// while it is reconfigured.
+    fn set_rsc(&mut self, queue_id: u32, enabled: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.sriov.is_some() {
+            return Err(IxyError::InvalidConfiguration(
+                "RSC is not supported with SR-IOV".to_string(),
+            ));
+        }
+
+        if enabled && (self.get_reg32(IXGBE_HLREG0) & IXGBE_HLREG0_RXCRCSTRP) == 0 {
+            return Err(IxyError::InvalidConfiguration(
+                "RSC requires CRC stripping".to_string(),
+            ));
+        }
+
+        if self.rx_queues[queue_id as usize].external_bufs.is_some() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} receives into external buffers",
+                queue_id
+            )));
+        }
+
+        let was_enabled = self.is_rx_queue_enabled(queue_id);
+        self.stop_rx_queue(queue_id as u16);
+
+        let hw_queue = self.hw_queue(queue_id);
+
+        if enabled {
+            debug!("enabling RSC on rx queue {}", queue_id);
+
+            // section 4.6.7.2.1 - NFS headers are not coalesced
+            self.clear_flags32(IXGBE_RFCTL, IXGBE_RFCTL_RSC_DIS);
+            self.set_flags32(IXGBE_RFCTL, IXGBE_RFCTL_NFSW_DIS | IXGBE_RFCTL_NFSR_DIS);
+            self.set_flags32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_RSCACKC);
+
+            // the device needs to find the TCP header of a packet, even in a single buffer
+            self.set_flags32(
+                IXGBE_PSRTYPE(u32::from(self.default_pool())),
+                IXGBE_PSRTYPE_TCPHDR,
+            );
+            self.set_reg32(
+                IXGBE_SRRCTL(hw_queue),
+                (self.get_reg32(IXGBE_SRRCTL(hw_queue)) & !IXGBE_SRRCTL_BSIZEHDR_MASK)
+                    | ((256 << IXGBE_SRRCTL_BSIZEHDRSIZE_SHIFT) & IXGBE_SRRCTL_BSIZEHDR_MASK),
+            );
+
+            // coalescing ends when the interrupt throttling interval of the queue expires
+            self.write_ivar(queue_id);
+
+            // a coalesced packet must not exceed 64 KiB, see section 8.2.3.8.13
+            let pool = &self.rx_queues[queue_id as usize].pool;
+            let maxdesc = match 65535 / (pool.entry_size() - pool.headroom()) {
+                n if n >= 16 => IXGBE_RSCCTL_MAXDESC_16,
+                n if n >= 8 => IXGBE_RSCCTL_MAXDESC_8,
+                n if n >= 4 => IXGBE_RSCCTL_MAXDESC_4,
+                _ => IXGBE_RSCCTL_MAXDESC_1,
+            };
+            self.set_reg32(IXGBE_RSCCTL(hw_queue), IXGBE_RSCCTL_RSCEN | maxdesc);
+
+            self.rx_queues[queue_id as usize].enable_rsc();
+        } else {
+            debug!("disabling RSC on rx queue {}", queue_id);
+
+            self.set_reg32(IXGBE_RSCCTL(hw_queue), 0);
+        }
+
+        if was_enabled {
+            self.enable_rx_queue(queue_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let batch = {
+            let queue = &mut self.rx_queues[queue_id as usize];
+
+            // packets of queues receiving into external buffers are fetched by rx_external_batch
+            if queue.external_bufs.is_some() {
+                return 0;
+            }
+
+            queue.receive(buffer, num_packets)
+        };
+
+        if let Some(index) = batch.timestamped {
+            let timestamp = u64::from(self.get_reg32(IXGBE_RXSTMPL))
+                | (u64::from(self.get_reg32(IXGBE_RXSTMPH)) << 32);
+            buffer[index].meta.timestamp = Some(timestamp);
+        }
+
+        if let Some(tail) = batch.tail {
+            self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            batch.received,
+            num_packets
+        );
+
+        batch.received
+    }
+
+    /// Returns whether the device was removed, i.e. its status register reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(IXGBE_STATUS) == IXGBE_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(queue_id, num_posted)? {
+            self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Disables rx queue `queue_id`, returns its buffers to the mempool and enables it again
+    /// without buffers.
+    fn set_rx_external(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].external_bufs =
+            Some(VecDeque::with_capacity(NUM_RX_QUEUE_ENTRIES));
+
+        // rx queue starts out empty, rx_external_post moves the tail past each added buffer
+        self.set_reg32(IXGBE_RDH(self.hw_queue(queue_id)), 0);
+        self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), 0);
+        self.set_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Writes an rx descriptor for the external buffer at `phys_addr` and moves the tail past it.
+    fn rx_external_post(&mut self, queue_id: u32, phys_addr: usize) -> bool {
+        let tail = {
+            let queue = &mut self.rx_queues[queue_id as usize];
+            let num_descriptors = queue.num_descriptors;
+
+            let bufs = match queue.external_bufs {
+                Some(ref mut bufs) => bufs,
+                None => return false,
+            };
+
+            // one descriptor stays unused, tail == head means the ring is empty
+            if bufs.len() == num_descriptors - 1 {
+                return false;
+            }
+
+            let index = (queue.rx_index + bufs.len()) % num_descriptors;
+
+            unsafe {
+                let desc = queue.descriptors.add(index);
+                ptr::write_volatile(
+                    &mut (*desc).read.pkt_addr as *mut u64,
+                    (phys_addr as u64).to_le(),
+                );
+                ptr::write_volatile(&mut (*desc).read.hdr_addr as *mut u64, 0);
+            }
+
+            bufs.push_back(phys_addr);
+
+            wrap_ring(index, num_descriptors)
+        };
+
+        self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), tail as u32);
+
+        true
+    }
+
+    /// Pops up to `num_packets` filled external buffers from rx queue `queue_id`. Frames that
+    /// spill over into further buffers are dropped, their buffers are posted again.
+    fn rx_external_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<(usize, usize)>,
+        num_packets: usize,
+    ) -> usize {
+        let mut received_packets = 0;
+        let mut reposted = false;
+
+        let tail = {
+            let queue = &mut self.rx_queues[queue_id as usize];
+            let num_descriptors = queue.num_descriptors;
+            let descriptors = queue.descriptors;
+
+            let bufs = match queue.external_bufs {
+                Some(ref mut bufs) => bufs,
+                None => return 0,
+            };
+
+            let status = |index: usize| unsafe {
+                u32::from_le(ptr::read_volatile(
+                    &(*descriptors.add(index)).wb.upper.status_error,
+                ))
+            };
+
+            'frames: while received_packets < num_packets && !bufs.is_empty() {
+                let mut eop_status = status(queue.rx_index);
+
+                if (eop_status & IXGBE_RXDADV_STAT_DD) == 0 {
+                    break;
+                }
+
+                // frames larger than the buffer size continue in the following descriptors
+                let mut num_bufs = 1;
+                while (eop_status & IXGBE_RXDADV_STAT_EOP) == 0 {
+                    if num_bufs == bufs.len() {
+                        break 'frames;
+                    }
+
+                    eop_status = status((queue.rx_index + num_bufs) % num_descriptors);
+
+                    if (eop_status & IXGBE_RXDADV_STAT_DD) == 0 {
+                        break 'frames;
+                    }
+
+                    num_bufs += 1;
+                }
+
+                if num_bufs > 1 {
+                    // the caller expects whole frames, their buffers receive the next ones
+                    for _ in 0..num_bufs {
+                        let phys_addr = bufs.pop_front().unwrap();
+                        queue.rx_index = wrap_ring(queue.rx_index, num_descriptors);
+
+                        let index = (queue.rx_index + bufs.len()) % num_descriptors;
+                        unsafe {
+                            let desc = descriptors.add(index);
+                            ptr::write_volatile(
+                                &mut (*desc).read.pkt_addr as *mut u64,
+                                (phys_addr as u64).to_le(),
+                            );
+                            ptr::write_volatile(&mut (*desc).read.hdr_addr as *mut u64, 0);
+                        }
+
+                        bufs.push_back(phys_addr);
+                    }
+
+                    reposted = true;
+                    continue;
+                }
+
+                let desc = unsafe { descriptors.add(queue.rx_index) };
+                let len = unsafe { u16::from_le(ptr::read_volatile(&(*desc).wb.upper.length)) };
+
+                if let Some(phys_addr) = bufs.pop_front() {
+                    buffer.push_back((phys_addr, len as usize));
+                }
+
+                queue.rx_index = wrap_ring(queue.rx_index, num_descriptors);
+                received_packets += 1;
+            }
+
+            (queue.rx_index + bufs.len()) % num_descriptors
+        };
+
+        if reposted {
+            self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), tail as u32);
+        }
+
+        received_packets
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(
+                IXGBE_TDT(self.hw_queue(queue_id)),
+                self.tx_queues[queue_id as usize].tx_index as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Puts the externally-owned buffer at `phys_addr` into the device`s tx queue.
+    fn tx_external(&mut self, queue_id: u32, phys_addr: usize, len: usize) -> bool {
+        {
+            let queue = &mut self.tx_queues[queue_id as usize];
+
+            let cur_index = queue.tx_index;
+            let clean_index = clean_tx_queue(queue);
+
+            if clean_index == wrap_ring(cur_index, queue.num_descriptors) {
+                // tx queue of device is full
+                return false;
+            }
+
+            queue.tx_index = wrap_ring(cur_index, queue.num_descriptors);
+
+            unsafe {
+                ptr::write_volatile(
+                    queue.descriptors.add(cur_index),
+                    tx_data_desc(phys_addr, len, len, true, &TxOffload::default()),
+                );
+            }
+
+            queue.bufs_in_use.push_back(TxBuffer::External(phys_addr));
+        }
+
+        self.set_reg32(
+            IXGBE_TDT(self.hw_queue(queue_id)),
+            self.tx_queues[queue_id as usize].tx_index as u32,
+        );
+
+        true
+    }
+
+    /// Pushes the physical addresses of all sent external buffers onto `completed`.
+    fn tx_external_completions(&mut self, queue_id: u32, completed: &mut VecDeque<usize>) -> usize {
+        let queue = &mut self.tx_queues[queue_id as usize];
+
+        clean_tx_queue(queue);
+
+        let num_completed = queue.completed_external.len();
+        completed.extend(queue.completed_external.drain(..));
+
+        num_completed
+    }
+
+    /// Reads the stats of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let rx_pkts = u64::from(self.get_reg32(IXGBE_GPRC));
+        let tx_pkts = u64::from(self.get_reg32(IXGBE_GPTC));
+        let rx_bytes =
+            u64::from(self.get_reg32(IXGBE_GORCL)) + (u64::from(self.get_reg32(IXGBE_GORCH)) << 32);
+        let tx_bytes =
+            u64::from(self.get_reg32(IXGBE_GOTCL)) + (u64::from(self.get_reg32(IXGBE_GOTCH)) << 32);
+
+        stats.rx_pkts += rx_pkts;
+        stats.tx_pkts += tx_pkts;
+        stats.rx_bytes += rx_bytes;
+        stats.tx_bytes += tx_bytes;
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.get_reg32(IXGBE_GPRC);
+        self.get_reg32(IXGBE_GPTC);
+        self.get_reg32(IXGBE_GORCL);
+        self.get_reg32(IXGBE_GORCH);
+        self.get_reg32(IXGBE_GOTCL);
+        self.get_reg32(IXGBE_GOTCH);
+
+        self.read_extended_stats(&mut ExtendedStats::default());
+    }
+
+    /// Reads the extended stats of this device into `stats`.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        // all of these registers are cleared on read
+        let reg = |r| u64::from(self.get_reg32(r));
+
+        stats.rx_crc_errors += reg(IXGBE_CRCERRS);
+        stats.rx_illegal_byte_errors += reg(IXGBE_ILLERRC);
+        stats.rx_length_errors += reg(IXGBE_RLEC);
+        stats.rx_undersize += reg(IXGBE_RUC);
+        stats.rx_fragments += reg(IXGBE_RFC);
+        stats.rx_oversize += reg(IXGBE_ROC);
+        stats.rx_jabbers += reg(IXGBE_RJC);
+        stats.rx_missed += (0..8).map(|i| reg(IXGBE_MPC(i))).sum::<u64>();
+        // RNBC only exists on the 82598, the 82599 counts these drops per queue
+        stats.rx_no_buffer += (0..16).map(|i| reg(IXGBE_QPRDC(i))).sum::<u64>();
+        stats.rx_broadcast += reg(IXGBE_BPRC);
+        stats.rx_multicast += reg(IXGBE_MPRC);
+        stats.tx_broadcast += reg(IXGBE_BPTC);
+        stats.tx_multicast += reg(IXGBE_MPTC);
+        stats.rx_xon += reg(IXGBE_LXONRXCNT);
+        stats.rx_xoff += reg(IXGBE_LXOFFRXCNT);
+        stats.tx_xon += reg(IXGBE_LXONTXC);
+        stats.tx_xoff += reg(IXGBE_LXOFFTXC);
+        stats.mac_local_faults += reg(IXGBE_MLFC);
+        stats.mac_remote_faults += reg(IXGBE_MRFC);
+        stats.rx_flow_matches += reg(IXGBE_FDIRMATCH);
+        stats.rx_flow_misses += reg(IXGBE_FDIRMISS);
+    }
+
+    /// Returns the PCIe link of this device.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Returns the link speed of this device.
+    fn get_link_speed(&self) -> u32 {
+        let speed = self.get_reg32(IXGBE_LINKS);
+        if (speed & IXGBE_LINKS_UP) == 0 {
+            return 0;
+        }
+        // the X550 reports the NBASE-T rates as non-standard variants of 100M and 10G
+        let non_std = self.mac_type != MacType::Mac82599
+            && self.mac_type != MacType::X540
+            && (speed & IXGBE_LINKS_SPEED_NON_STD) != 0;
+        match speed & IXGBE_LINKS_SPEED_82599 {
+            IXGBE_LINKS_SPEED_100_82599 if non_std => 5000,
+            IXGBE_LINKS_SPEED_100_82599 => 100,
+            IXGBE_LINKS_SPEED_1G_82599 => 1000,
+            IXGBE_LINKS_SPEED_10G_82599 if non_std => 2500,
+            IXGBE_LINKS_SPEED_10G_82599 => 10000,
+            _ => 0,
+        }
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        self.check_pool_queues(num_rx_queues, num_tx_queues)?;
+
+        // packets of these flows would be dropped silently once their queue is gone
+        let stale_rule =
+            self.flow_rules
+                .borrow()
+                .iter()
+                .flatten()
+                .find_map(|rule| match rule.action {
+                    FlowAction::Queue(queue) if queue >= num_rx_queues => Some(queue),
+                    _ => None,
+                });
+        if let Some(queue) = stale_rule {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot remove rx queue {}: flow rules steer flows to it",
+                queue
+            )));
+        }
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        // redirect flows away from removed queues before they are stopped
+        if num_rx_queues < self.num_rx_queues {
+            self.spread_rss(num_rx_queues)?;
+        }
+
+        // remove queues from the top, queues below the new counts are left untouched
+        while self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues -= 1;
+            self.stop_rx_queue(self.num_rx_queues);
+            self.rx_queues.pop();
+        }
+
+        while self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues -= 1;
+            self.stop_tx_queue(self.num_tx_queues);
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                release_tx_buffers(&mut queue);
+            }
+        }
+
+        while self.num_rx_queues < num_rx_queues {
+            self.init_rx_queue(self.num_rx_queues)?;
+            self.write_rx_drop_enable(self.num_rx_queues, num_rx_queues > 1);
+            self.start_rx_queue(self.num_rx_queues)?;
+            self.num_rx_queues += 1;
+        }
+
+        while self.num_tx_queues < num_tx_queues {
+            self.init_tx_queue(self.num_tx_queues)?;
+            self.start_tx_queue(self.num_tx_queues)?;
+            self.num_tx_queues += 1;
+        }
+
+        self.spread_rss(num_rx_queues)?;
+
+        Ok(())
+    }
+
+    /// Enables rx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let queue = &self.rx_queues[queue_id as usize];
+
+        if queue.is_drained() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        // head and tail survive while the queue is disabled, so the device resumes where it stopped
+        self.set_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(IXGBE_RXDCTL(self.hw_queue(queue_id))) & IXGBE_RXDCTL_ENABLE) != 0
+    }
+
+    /// Enables tx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        debug!("enabling tx queue {}", queue_id);
+
+        self.set_flags32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables tx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+            && (self.get_reg32(IXGBE_TXDCTL(self.hw_queue(queue_id))) & IXGBE_TXDCTL_ENABLE) != 0
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.get_reg32(IXGBE_TDH(self.hw_queue(queue_id))) as usize;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Disables tx queue `queue_id`, drops all pending packets and enables it again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue(queue_id as u16);
+        self.tx_queues[queue_id as usize].reset();
+
+        self.start_tx_queue(queue_id as u16)
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.get_reg32(IXGBE_TDH(self.hw_queue(queue_id))) as usize
+            != self.tx_queues[queue_id as usize].tx_index
+        {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of rx queue `queue_id`.
+    fn set_rx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.set_desc_thresholds(
+            IXGBE_RXDCTL(self.hw_queue(queue_id)),
+            pthresh,
+            hthresh,
+            wthresh,
+        )
+    }
+
+    /// Sets whether rx queue `queue_id` drops packets when its ring is full.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.write_rx_drop_enable(queue_id as u16, enable);
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of tx queue `queue_id`.
+    fn set_tx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        self.set_desc_thresholds(
+            IXGBE_TXDCTL(self.hw_queue(queue_id)),
+            pthresh,
+            hthresh,
+            wthresh,
+        )
+    }
+
+    /// Enables direct cache access to the cache of `cpu_id` for rx queue `queue_id`.
+    #[cfg(feature = "dca")]
+    fn enable_rx_dca(&self, queue_id: u32, cpu_id: u8) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        info!("enabling dca for rx queue {} on cpu {}", queue_id, cpu_id);
+
+        // always use mode CB2, like the linux driver does
+        self.set_reg32(
+            IXGBE_DCA_CTRL,
+            IXGBE_DCA_CTRL_DCA_ENABLE | IXGBE_DCA_CTRL_DCA_MODE_CB2,
+        );
+
+        let mut dca_rxctrl = self.get_reg32(IXGBE_DCA_RXCTRL(self.hw_queue(queue_id)));
+        dca_rxctrl &= !IXGBE_DCA_RXCTRL_CPUID_MASK_82599;
+        dca_rxctrl |= (u32::from(cpu_id) << IXGBE_DCA_RXCTRL_CPUID_SHIFT_82599)
+            | IXGBE_DCA_RXCTRL_DESC_DCA_EN
+            | IXGBE_DCA_RXCTRL_HEAD_DCA_EN
+            | IXGBE_DCA_RXCTRL_DATA_DCA_EN;
+        self.set_reg32(IXGBE_DCA_RXCTRL(self.hw_queue(queue_id)), dca_rxctrl);
+
+        Ok(())
+    }
+
+    /// Sets the flow control mode of this device.
+    fn set_flow_control(&self, mode: FlowControl) {
+        info!("setting flow control mode to {:?}", mode);
+
+        let (rx_pause, tx_pause) = match mode {
+            FlowControl::None => (false, false),
+            FlowControl::RxPause => (true, false),
+            FlowControl::TxPause => (false, true),
+            FlowControl::Full => (true, true),
+        };
+
+        // honor received pause frames, but never pass them to the host
+        let mut mflcn = self.get_reg32(IXGBE_MFLCN) & !(IXGBE_MFLCN_RPFCE_MASK | IXGBE_MFLCN_RFCE);
+        mflcn |= IXGBE_MFLCN_DPF;
+        if rx_pause {
+            mflcn |= IXGBE_MFLCN_RFCE;
+        }
+
+        // send pause frames when the packet buffer fills up
+        let mut fccfg =
+            self.get_reg32(IXGBE_FCCFG) & !(IXGBE_FCCFG_TFCE_802_3X | IXGBE_FCCFG_TFCE_PRIORITY);
+        if tx_pause {
+            fccfg |= IXGBE_FCCFG_TFCE_802_3X;
+        }
+
+        self.set_reg32(IXGBE_MFLCN, mflcn);
+        self.set_reg32(IXGBE_FCCFG, fccfg);
+
+        // all traffic goes to packet buffer 0, derive its watermarks (in KB) from its size
+        let pb_size =
+            (self.get_reg32(IXGBE_RXPBSIZE(0)) & IXGBE_RXPBSIZE_MASK) >> IXGBE_RXPBSIZE_SHIFT;
+        let high_water = pb_size * 3 / 4;
+        let low_water = pb_size / 2;
+
+        if tx_pause {
+            self.set_reg32(IXGBE_FCRTL_82599(0), (low_water << 10) | IXGBE_FCRTL_XONE);
+            self.set_reg32(IXGBE_FCRTH_82599(0), (high_water << 10) | IXGBE_FCRTH_FCEN);
+        } else {
+            self.set_reg32(IXGBE_FCRTL_82599(0), 0);
+            self.set_reg32(IXGBE_FCRTH_82599(0), 0);
+        }
+
+        // pause time of sent pause frames and the time after which they are refreshed
+        for i in 0..4 {
+            self.set_reg32(IXGBE_FCTTV(i), FC_PAUSE_TIME * 0x0001_0001);
+        }
+        self.set_reg32(IXGBE_FCRTV, FC_PAUSE_TIME / 2);
+    }
+
+    /// Returns the flow control mode of this device.
+    fn get_flow_control(&self) -> FlowControl {
+        let rx_pause = (self.get_reg32(IXGBE_MFLCN) & IXGBE_MFLCN_RFCE) != 0;
+        let tx_pause = (self.get_reg32(IXGBE_FCCFG) & IXGBE_FCCFG_TFCE_802_3X) != 0;
+
+        match (rx_pause, tx_pause) {
+            (false, false) => FlowControl::None,
+            (true, false) => FlowControl::RxPause,
+            (false, true) => FlowControl::TxPause,
+            (true, true) => FlowControl::Full,
+        }
+    }
+
+    /// Writes both CRC strip bits while rx is disabled. RSC needs stripping, so it can't be
+    /// disabled while any rx queue coalesces packets.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            if let Some(queue_id) = (0..u32::from(self.num_rx_queues)).find(|&queue_id| {
+                self.get_reg32(IXGBE_RSCCTL(self.hw_queue(queue_id))) & IXGBE_RSCCTL_RSCEN != 0
+            }) {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "cannot disable CRC stripping: RSC is enabled on rx queue {}",
+                    queue_id
+                )));
+            }
+        }
+
+        let rxctrl = self.get_reg32(IXGBE_RXCTRL);
+
+        self.clear_flags32(IXGBE_RXCTRL, IXGBE_RXCTRL_RXEN);
+        self.write_crc_strip(enable);
+        self.set_reg32(IXGBE_RXCTRL, rxctrl);
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.get_reg32(IXGBE_HLREG0) & IXGBE_HLREG0_RXCRCSTRP != 0
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        // the device's buffer size has a granularity of 1 KiB
+        let buffer_size = buffer_size.next_multiple_of(1 << IXGBE_SRRCTL_BSIZEPKT_SHIFT);
+        let pool = self.allocate_mempool(buffer_size)?;
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].pool = pool;
+        self.write_rx_buffer_size(queue_id as u16);
+
+        if buffer_size > PKT_BUF_ENTRY_SIZE {
+            self.enable_jumbo_frames(buffer_size);
+        }
+
+        self.start_rx_queue(queue_id as u16)
+    }
+
+    /// Unmasks the MSI-X vector of rx queue `queue_id`, setting up MSI-X on first use.
+    fn enable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.interrupts.len() <= queue_id as usize {
+            self.setup_interrupts()?;
+        }
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        if queue.moderation == InterruptModeration::Adaptive {
+            if let Some(pps) = queue.rx_rate.sample() {
+                self.write_itr(queue_id, adaptive_itr_us(pps));
+            }
+        }
+
+        self.set_reg32(IXGBE_EIMS_EX(queue_id / 32), 1 << (queue_id % 32));
+
+        Ok(())
+    }
+
+    /// Masks the MSI-X vector of rx queue `queue_id`.
+    fn disable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.set_reg32(IXGBE_EIMC_EX(queue_id / 32), 1 << (queue_id % 32));
+
+        Ok(())
+    }
+
+    /// Sets the interrupt throttling interval of rx queue `queue_id`, see section 7.3.2.1.
+    fn set_interrupt_moderation(
+        &mut self,
+        queue_id: u32,
+        moderation: InterruptModeration,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let itr_us = match moderation {
+            InterruptModeration::Static(us) if us > MAX_ITR_US => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "interrupt interval of {} µs exceeds the maximum of {} µs",
+                    us, MAX_ITR_US
+                )));
+            }
+            InterruptModeration::Static(us) => us,
+            // start with low latency until the first sample
+            InterruptModeration::Adaptive => ITR_LOWEST_LATENCY_US,
+        };
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        queue.moderation = moderation;
+        queue.rx_rate = RxRate::new();
+
+        self.write_itr(queue_id, itr_us);
+
+        Ok(())
+    }
+
+    /// Returns the interrupt moderation of rx queue `queue_id`.
+    fn get_interrupt_moderation(&self, queue_id: u32) -> InterruptModeration {
+        self.rx_queues[queue_id as usize].moderation
+    }
+
+    /// Waits for the MSI-X vector of rx queue `queue_id`, unless a packet is already waiting.
+    fn wait_rx_interrupt(
+        &mut self,
+        queue_id: u32,
+        timeout: Option<Duration>,
+    ) -> Result<bool, IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let eventfd = match self.interrupts.get(queue_id as usize) {
+            Some(fd) => fd.as_raw_fd(),
+            None => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "interrupts of rx queue {} are not enabled",
+                    queue_id
+                )))
+            }
+        };
+
+        // a packet that arrived before the interrupt was enabled doesn't trigger it
+        let queue = &self.rx_queues[queue_id as usize];
+        if unsafe { rx_desc_status(queue, queue.rx_index) } & IXGBE_RXDADV_STAT_DD != 0 {
+            return Ok(true);
+        }
+
+        vfio_wait_eventfd(eventfd, timeout)
+    }
+
+    /// Returns the eventfd of the MSI-X vector of rx queue `queue_id` once it was set up.
+    fn rx_interrupt_fd(&self, queue_id: u32) -> Option<RawFd> {
+        self.interrupts
+            .get(queue_id as usize)
+            .map(|eventfd| eventfd.as_raw_fd())
+    }
+
+    /// Reads the 16 bit word at `offset` of this device's EEPROM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` exceeds the EEPROM address space accessible via `EERD`.
+    fn read_eeprom_word(&self, offset: u16) -> u16 {
+        assert!(
+            u32::from(offset) <= IXGBE_EERD_MAX_ADDR,
+            "eeprom access out of bounds"
+        );
+
+        // section 8.2.3.2.2 - software-controlled read via the EEPROM read register
+        self.set_reg32(
+            IXGBE_EERD,
+            (u32::from(offset) << IXGBE_EEPROM_RW_ADDR_SHIFT) | IXGBE_EEPROM_RW_REG_START,
+        );
+
+        loop {
+            let eerd = self.get_reg32(IXGBE_EERD);
+            if (eerd & IXGBE_EEPROM_RW_REG_DONE) != 0 {
+                return (eerd >> IXGBE_EEPROM_RW_REG_DATA) as u16;
+            }
+            thread::sleep(Duration::from_micros(5));
+        }
+    }
+
+    /// Returns the eTrack ID identifying the NVM image of this device.
+    fn get_firmware_version(&self) -> u32 {
+        let low = self.read_eeprom_word(NVM_ETK_OFF_LOW as u16);
+        let high = self.read_eeprom_word(NVM_ETK_OFF_HI as u16);
+
+        (u32::from(high) << 16) | u32::from(low)
+    }
+
+    /// Returns whether the checksum stored in this device's EEPROM is valid.
+    fn validate_eeprom_checksum(&self) -> bool {
+        let mut checksum: u16 = 0;
+
+        // include all words of the first section except the checksum itself
+        for i in 0..IXGBE_EEPROM_CHECKSUM as u16 {
+            checksum = checksum.wrapping_add(self.read_eeprom_word(i));
+        }
+
+        // include all sections referenced by the pointers in front of the firmware pointer
+        for i in IXGBE_PCIE_ANALOG_PTR as u16..IXGBE_FW_PTR as u16 {
+            let pointer = self.read_eeprom_word(i);
+
+            // skip sections that are not present
+            if pointer == 0xffff || pointer == 0 || u32::from(pointer) > IXGBE_EERD_MAX_ADDR {
+                continue;
+            }
+
+            let length = self.read_eeprom_word(pointer);
+
+            if length == 0xffff || length == 0 {
+                continue;
+            }
+
+            for j in pointer + 1..=pointer.saturating_add(length) {
+                if u32::from(j) > IXGBE_EERD_MAX_ADDR {
+                    break;
+                }
+                checksum = checksum.wrapping_add(self.read_eeprom_word(j));
+            }
+        }
+
+        let checksum = (IXGBE_EEPROM_SUM as u16).wrapping_sub(checksum);
+
+        checksum == self.read_eeprom_word(IXGBE_EEPROM_CHECKSUM as u16)
+    }
+
+    /// Returns the highest temperature reported by the external thermal sensor of an 82599.
+    fn get_temperature(&self) -> Option<i16> {
+        // 10GBASE-T parts have no sensor on the i2c bus
+        if self.mac_type.is_copper() {
+            return None;
+        }
+
+        // the thermal sensor is only attached to the i2c bus of the first port
+        if self.get_reg32(IXGBE_STATUS) & IXGBE_STATUS_LAN_ID != IXGBE_STATUS_LAN_ID_0 {
+            return None;
+        }
+
+        // the external thermal sensor configuration is found via a pointer in the EEPROM
+        let ets_offset = self.read_eeprom_word(IXGBE_ETS_CFG as u16);
+        if ets_offset == 0 || ets_offset == 0xffff {
+            return None;
+        }
+
+        let ets_cfg = u32::from(self.read_eeprom_word(ets_offset));
+        if (ets_cfg & IXGBE_ETS_TYPE_MASK) >> IXGBE_ETS_TYPE_SHIFT != IXGBE_ETS_TYPE_EMC {
+            return None;
+        }
+
+        let num_sensors = (ets_cfg & IXGBE_ETS_NUM_SENSORS_MASK).min(3) as u16;
+        if u32::from(ets_offset) + u32::from(num_sensors) > IXGBE_EERD_MAX_ADDR {
+            return None;
+        }
+
+        let emc_data = [
+            IXGBE_EMC_INTERNAL_DATA,
+            IXGBE_EMC_DIODE1_DATA,
+            IXGBE_EMC_DIODE2_DATA,
+            IXGBE_EMC_DIODE3_DATA,
+        ];
+
+        let mut temperature = None;
+
+        for i in 0..num_sensors {
+            let sensor = u32::from(self.read_eeprom_word(ets_offset + 1 + i));
+            let index = (sensor & IXGBE_ETS_DATA_INDEX_MASK) >> IXGBE_ETS_DATA_INDEX_SHIFT;
+            let location = (sensor & IXGBE_ETS_DATA_LOC_MASK) >> IXGBE_ETS_DATA_LOC_SHIFT;
+
+            // a location of zero means that the sensor is not used
+            if location == 0 {
+                continue;
+            }
+
+            if let Some(value) = self.read_i2c_byte(
+                IXGBE_I2C_THERMAL_SENSOR_ADDR as u8,
+                emc_data[index as usize] as u8,
+            ) {
+                let value = i16::from(value);
+                temperature = Some(temperature.map_or(value, |t: i16| t.max(value)));
+            }
+        }
+
+        temperature
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the global, per-queue and stats registers of this device.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("CTRL", IXGBE_CTRL),
+            ("CTRL_EXT", IXGBE_CTRL_EXT),
+            ("STATUS", IXGBE_STATUS),
+            ("LINKS", IXGBE_LINKS),
+            ("AUTOC", IXGBE_AUTOC),
+            ("HLREG0", IXGBE_HLREG0),
+            ("FCTRL", IXGBE_FCTRL),
+            ("RXCTRL", IXGBE_RXCTRL),
+            ("RDRXCTL", IXGBE_RDRXCTL),
+            ("DMATXCTL", IXGBE_DMATXCTL),
+            ("MFLCN", IXGBE_MFLCN),
+            ("FCCFG", IXGBE_FCCFG),
+            ("EIMS", IXGBE_EIMS),
+            ("VT_CTL", IXGBE_VT_CTL),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        for queue_id in 0..u32::from(self.num_rx_queues) {
+            let i = self.hw_queue(queue_id);
+            let queue = [
+                ("RDBAL", IXGBE_RDBAL(i)),
+                ("RDBAH", IXGBE_RDBAH(i)),
+                ("RDLEN", IXGBE_RDLEN(i)),
+                ("RDH", IXGBE_RDH(i)),
+                ("RDT", IXGBE_RDT(i)),
+                ("RXDCTL", IXGBE_RXDCTL(i)),
+                ("SRRCTL", IXGBE_SRRCTL(i)),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg));
+            }
+        }
+
+        for queue_id in 0..u32::from(self.num_tx_queues) {
+            let i = self.hw_queue(queue_id);
+            let queue = [
+                ("TDBAL", IXGBE_TDBAL(i)),
+                ("TDBAH", IXGBE_TDBAH(i)),
+                ("TDLEN", IXGBE_TDLEN(i)),
+                ("TDH", IXGBE_TDH(i)),
+                ("TDT", IXGBE_TDT(i)),
+                ("TXDCTL", IXGBE_TXDCTL(i)),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg));
+            }
+        }
+
+        // these are cleared on read
+        let stats = [
+            ("GPRC", IXGBE_GPRC),
+            ("GPTC", IXGBE_GPTC),
+            ("GORCL", IXGBE_GORCL),
+            ("GORCH", IXGBE_GORCH),
+            ("GOTCL", IXGBE_GOTCL),
+            ("GOTCH", IXGBE_GOTCH),
+            ("CRCERRS", IXGBE_CRCERRS),
+            ("RLEC", IXGBE_RLEC),
+            ("MPC[0]", IXGBE_MPC(0)),
+        ];
+
+        for &(name, reg) in stats.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if !self.interrupts.is_empty() {
+            vfio_disable_msix(self.device_fd)?;
+            self.interrupts.clear();
+        }
+
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        let pci_addr = self.pci_addr.clone();
+        self.reset_and_init(&pci_addr)
+    }
+
+    /// Creates `num_vfs` virtual functions and initializes this device again, see section 7.10.
+    fn enable_sriov(&mut self, num_vfs: u16) -> Result<Vec<String>, IxyError> {
+        if num_vfs == 0 || num_vfs > MAX_VFS {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot create {} virtual functions: limit is {}",
+                num_vfs, MAX_VFS
+            )));
+        }
+
+        let queues_per_pool = if num_vfs <= MAX_VFS_32_POOLS { 4 } else { 2 };
+        if self.num_rx_queues.max(self.num_tx_queues) > queues_per_pool {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "the physical function is limited to {} queues with {} virtual functions",
+                queues_per_pool, num_vfs
+            )));
+        }
+
+        self.configure_sriov(num_vfs)?;
+
+        Ok(self.get_vfs())
+    }
+
+    /// Removes all virtual functions and initializes this device again.
+    fn disable_sriov(&mut self) -> Result<(), IxyError> {
+        if self.sriov.is_none() {
+            return Ok(());
+        }
+
+        self.configure_sriov(0)
+    }
+
+    /// Returns the pci addresses of the virtual functions.
+    fn get_vfs(&self) -> Vec<String> {
+        self.sriov
+            .as_ref()
+            .map_or_else(Vec::new, |sriov| sriov.vfs.clone())
+    }
+
+    /// Sets the mac address of virtual function `vf` and locks it.
+    fn set_vf_mac(&mut self, vf: u16, mac: [u8; 6]) -> Result<(), IxyError> {
+        self.check_vf(vf)?;
+
+        let vf = u32::from(vf);
+        let mut state = self.vf_state(vf);
+        state.mac = mac;
+        state.mac_locked = true;
+        // the virtual function only learns the new address when it resets
+        state.clear_to_send = false;
+        self.set_vf_state(vf, state);
+
+        self.write_vf_filters(vf);
+
+        Ok(())
+    }
+
+    /// Sets or removes the port VLAN of virtual function `vf`.
+    fn set_vf_vlan(&mut self, vf: u16, vlan: Option<u16>) -> Result<(), IxyError> {
+        self.check_vf(vf)?;
+
+        if let Some(vlan) = vlan {
+            if vlan == 0 || u32::from(vlan) > IXGBE_VLVF_VLANID_MASK {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "invalid VLAN id {}",
+                    vlan
+                )));
+            }
+        }
+
+        let vf = u32::from(vf);
+        let mut state = self.vf_state(vf);
+        state.vlan = vlan;
+        state.clear_to_send = false;
+        self.set_vf_state(vf, state);
+
+        self.write_vf_filters(vf);
+
+        Ok(())
+    }
+
+    /// Answers the pending mailbox requests of the virtual functions.
+    fn process_vf_mailbox(&mut self) -> Result<usize, IxyError> {
+        let num_vfs = self
+            .sriov
+            .as_ref()
+            .map_or(0, |sriov| sriov.vfs.len() as u32);
+        let mut handled = 0;
+
+        for vf in 0..num_vfs {
+            let request_bit = IXGBE_MBVFICR_VFREQ_VF1 << (vf % 16);
+            if self.get_reg32(IXGBE_PFMBICR(vf / 16)) & request_bit == 0 {
+                continue;
+            }
+
+            // write 1 to clear
+            self.set_reg32(IXGBE_PFMBICR(vf / 16), request_bit);
+
+            let mut msg = [0; IXGBE_VFMAILBOX_SIZE as usize];
+            if !self.read_vf_mailbox(vf, &mut msg) {
+                warn!("mailbox of virtual function {} is busy", vf);
+                continue;
+            }
+
+            self.handle_vf_request(vf, &mut msg);
+
+            if !self.write_vf_mailbox(vf, &msg) {
+                warn!("mailbox of virtual function {} is busy", vf);
+                continue;
+            }
+
+            handled += 1;
+        }
+
+        Ok(handled)
+    }
+}
+
+impl Drop for IxgbeDevice {
+    fn drop(&mut self) {
+        if !self.interrupts.is_empty() {
+            if let Err(e) = vfio_disable_msix(self.device_fd) {
+                warn!("failed to disable msi-x of {}: {}", self.pci_addr, e);
+            }
+        }
+
+        // stop all dma before the descriptor rings and mempools are unmapped, a global reset
+        // also disables the queues, see section 4.6.3.2
+        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+        self.set_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
+        thread::sleep(Duration::from_millis(10));
+
+        // virtual functions can't work without the mailbox of the physical function
+        if self.sriov.is_some() {
+            if let Err(e) = pci_disable_sriov(&self.pci_addr) {
+                warn!("failed to disable SR-IOV of {}: {}", self.pci_addr, e);
+            }
+        }
+    }
+}
+
+impl IxgbeDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        u32::from(vendor_id) == IXGBE_INTEL_VENDOR_ID
+            && SUPPORTED_DEVICE_IDS.contains(&u32::from(device_id))
+    }
+
+    /// Returns an initialized `IxgbeDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<IxgbeDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let mac_type = MacType::from_device_id(PciDevice::open(pci_addr)?.device_id()?);
+
+        // Check if the NIC is IOMMU enabled...
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory
+        check_privileges(pci_addr, vfio, dma_size(num_rx_queues, num_tx_queues))?;
+
+        let numa_node = pci_numa_node(pci_addr);
+        if let Some(node) = numa_node {
+            info!("{} is attached to numa node {}", pci_addr, node);
+        }
+
+        // a card in a narrow or old slot silently loses throughput, so complain loudly
+        match PciDevice::open(pci_addr).and_then(|dev| dev.pcie_link()) {
+            Ok(Some(link)) if link.is_downtrained() => warn!(
+                "pcie link of {} is downtrained to {}, limiting it to {} of {} Mbit/s",
+                pci_addr,
+                link,
+                link.bandwidth(),
+                link.max_bandwidth()
+            ),
+            Ok(Some(link)) => info!("pcie link of {}: {}", pci_addr, link),
+            Ok(None) => {}
+            Err(e) => warn!("failed to read pcie link of {}: {}", pci_addr, e),
+        }
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+
+            // start from a clean state even if a crashed process left the device hanging
+            if let Err(e) = vfio_reset_device(device_fd) {
+                warn!("failed to reset {}: {}", pci_addr, e);
+            }
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            // mask the vectors of the previous driver before DMA is enabled again
+            unbind_driver(pci_addr)?;
+            pci_mask_msix_vectors(pci_addr)?;
+
+            pci_map_resource(pci_addr)?
+        };
+
+        // initialize RX and TX queue
+        let rx_queues = Vec::with_capacity(num_rx_queues as usize);
+        let tx_queues = Vec::with_capacity(num_tx_queues as usize);
+
+        // create the IxyDevice
+        let mut dev = IxgbeDevice {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues,
+            tx_queues,
+            vfio,
+            container: vfio_container,
+            device_fd,
+            interrupts: Vec::new(),
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            sriov: None,
+            mac_type,
+            phy_addr: None,
+            flow_rules: RefCell::new(Vec::new()),
+            rss: RefCell::new(None),
+            vlan_strip: false,
+            allmulti: Cell::new(false),
+            removal: RemovalLatch::new(IXGBE_STATUS),
+        };
+
+        dev.reset_and_init(pci_addr)?;
+
+        Ok(dev)
+    }
+
+    /// Resets and initializes this device.
+    fn reset_and_init(&mut self, pci_addr: &str) -> Result<(), IxyError> {
+        info!("resetting device {}", pci_addr);
+        // section 4.6.3.1 - disable all interrupts
+        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+
+        // section 4.6.3.2
+        self.set_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
+        self.wait_clear_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
+        thread::sleep(Duration::from_millis(10));
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(pci_addr.to_string()));
+        }
+
+        // section 4.6.3.1 - disable interrupts again after reset
+        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+
+        let mac = self.get_mac_addr();
+        info!("initializing device {}", pci_addr);
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        // section 4.6.3 - wait for EEPROM auto read completion
+        self.wait_set_reg32(IXGBE_EEC, IXGBE_EEC_ARD);
+
+        // section 4.6.3 - wait for dma initialization done
+        self.wait_set_reg32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_DMAIDONE);
+
+        // skip last step from 4.6.3 - we don't want interrupts
+
+        // section 4.6.4 - initialize link (auto negotiation)
+        self.init_link();
+
+        // section 4.6.5 - statistical counters
+        // reset-on-read registers, just read them once
+        self.reset_stats();
+
+        // the VLAN filter table is not cleared by the reset
+        self.clear_vlan_filters();
+        self.vlan_strip = false;
+
+        // neither is the multicast table array
+        self.write_multicast_filters(&[]);
+        self.allmulti.set(false);
+
+        // the reset disabled RSS
+        *self.rss.get_mut() = None;
+
+        // section 4.6.10.1 - enable virtualization before the queues of the pf are set up
+        self.init_sriov();
 
         // section 4.6.7 - init rx
         self.init_rx()?;
 
-        // section 4.6.8 - init tx
-        self.init_tx()?;
+        // section 4.6.8 - init tx
+        self.init_tx()?;
+
+        for i in 0..self.num_rx_queues {
+            self.start_rx_queue(i)?;
+        }
+
+        for i in 0..self.num_tx_queues {
+            self.start_tx_queue(i)?;
+        }
+
+        // enable promisc mode by default to make testing easier
+        self.set_promisc(true)?;
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    // sections 4.6.7
+    /// Initializes the rx queues of this device.
+    fn init_rx(&mut self) -> Result<(), IxyError> {
+        // disable rx while re-configuring it
+        self.clear_flags32(IXGBE_RXCTRL, IXGBE_RXCTRL_RXEN);
+
+        // section 4.6.11.3.4 - allocate all queues and traffic to PB0
+        self.set_reg32(IXGBE_RXPBSIZE(0), IXGBE_RXPBSIZE_128KB);
+        for i in 1..8 {
+            self.set_reg32(IXGBE_RXPBSIZE(i), 0);
+        }
+
+        // the flow director table takes its memory from the rest of the packet buffer
+        self.init_flow_director()?;
+
+        // enable CRC offloading
+        self.write_crc_strip(true);
+
+        // accept broadcast packets
+        self.set_flags32(IXGBE_FCTRL, IXGBE_FCTRL_BAM);
+
+        // configure queues, same for all queues
+        for i in 0..self.num_rx_queues {
+            self.init_rx_queue(i)?;
+            self.write_rx_drop_enable(i, self.num_rx_queues > 1);
+        }
+
+        // last sentence of section 4.6.7 - set some magic bits
+        self.set_flags32(IXGBE_CTRL_EXT, IXGBE_CTRL_EXT_NS_DIS);
+
+        // start rx
+        self.set_flags32(IXGBE_RXCTRL, IXGBE_RXCTRL_RXEN);
+
+        Ok(())
+    }
+
+    // section 4.6.8
+    /// Initializes the tx queues of this device.
+    fn init_tx(&mut self) -> Result<(), IxyError> {
+        // crc offload and small packet padding
+        self.set_flags32(IXGBE_HLREG0, IXGBE_HLREG0_TXCRCEN | IXGBE_HLREG0_TXPADEN);
+
+        // section 4.6.11.3.4 - set default buffer size allocations
+        self.set_reg32(IXGBE_TXPBSIZE(0), IXGBE_TXPBSIZE_40KB);
+        for i in 1..8 {
+            self.set_reg32(IXGBE_TXPBSIZE(i), 0);
+        }
+
+        // required when not using DCB/VTd
+        self.set_reg32(IXGBE_DTXMXSZRQ, 0xffff);
+
+        // section 8.2.3.9.15 - MTQC may only be changed while the tx arbiter is disabled
+        if let Some(ref sriov) = self.sriov {
+            self.set_flags32(IXGBE_RTTDCS, IXGBE_RTTDCS_ARBDIS);
+            self.set_reg32(
+                IXGBE_MTQC,
+                IXGBE_MTQC_VT_ENA
+                    | if sriov.queues_per_pool == 4 {
+                        IXGBE_MTQC_32VF
+                    } else {
+                        IXGBE_MTQC_64VF
+                    },
+            );
+        }
+        self.clear_flags32(IXGBE_RTTDCS, IXGBE_RTTDCS_ARBDIS);
+
+        // configure queues
+        for i in 0..self.num_tx_queues {
+            self.init_tx_queue(i)?;
+        }
+
+        // final step: enable DMA
+        self.set_reg32(IXGBE_DMATXCTL, IXGBE_DMATXCTL_TE);
+
+        Ok(())
+    }
+
+    /// Allocates contiguous dma memory with the device's allocator, or on the numa node of the
+    /// device if it is known.
+    fn allocate_dma<T>(&self, size: usize) -> Result<Dma<T>, IxyError> {
+        allocate_dma(self.allocator.as_ref(), self.numa_node, size)
+    }
+
+    /// Allocates an rx mempool of `entry_size` byte buffers with the device's allocator, or on
+    /// the numa node of the device if it is known.
+    fn allocate_mempool(&self, entry_size: usize) -> Result<Arc<Mempool>, IxyError> {
+        allocate_mempool(self.allocator.as_ref(), self.numa_node, entry_size)
+    }
+
+    /// Allocates and configures the descriptor ring and mempool of rx queue `queue_id`.
+    fn init_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+        // enable advanced rx descriptors
+        self.set_reg32(
+            IXGBE_SRRCTL(self.hw_queue(queue_id)),
+            (self.get_reg32(IXGBE_SRRCTL(self.hw_queue(queue_id))) & !IXGBE_SRRCTL_DESCTYPE_MASK)
+                | IXGBE_SRRCTL_DESCTYPE_ADV_ONEBUF,
+        );
+
+        // section 7.1.9 - setup descriptor ring
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_rx_desc>();
+
+        let dma: Dma<ixgbe_adv_rx_desc> = self.allocate_dma(ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(
+            IXGBE_RDBAL(self.hw_queue(queue_id)),
+            (dma.phys as u64 & 0xffff_ffff) as u32,
+        );
+        self.set_reg32(
+            IXGBE_RDBAH(self.hw_queue(queue_id)),
+            (dma.phys as u64 >> 32) as u32,
+        );
+        self.set_reg32(IXGBE_RDLEN(self.hw_queue(queue_id)), ring_size_bytes as u32);
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        // set ring to empty at start
+        self.set_reg32(IXGBE_RDH(self.hw_queue(queue_id)), 0);
+        self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), 0);
+
+        let mempool = self.allocate_mempool(PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(IxgbeRxQueue::new(dma, mempool));
+        self.write_rx_buffer_size(queue_id);
+        self.write_vlan_strip(queue_id);
+
+        // RSC stays disabled until it is requested for the queue
+        self.set_reg32(IXGBE_RSCCTL(self.hw_queue(queue_id)), 0);
+
+        // probably a broken feature, this flag is initialized with 1 but has to be set to 0
+        self.clear_flags32(IXGBE_DCA_RXCTRL(self.hw_queue(queue_id)), 1 << 12);
+
+        Ok(())
+    }
+
+    /// Enables or disables VLAN stripping on rx queue `queue_id` as configured for the device.
+    fn write_vlan_strip(&mut self, queue_id: u16) {
+        if self.vlan_strip {
+            self.set_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_VME);
+        } else {
+            self.clear_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_VME);
+        }
+
+        self.rx_queues[queue_id as usize].vlan_strip = self.vlan_strip;
+    }
+
+    /// Returns an error if `vlan` is no valid VLAN id or the VLAN filter table is used for the
+    /// port VLANs of virtual functions.
+    fn check_vlan_filter(&self, vlan: u16) -> Result<(), IxyError> {
+        if u32::from(vlan) > IXGBE_VLVF_VLANID_MASK {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "invalid VLAN id {}",
+                vlan
+            )));
+        }
+
+        if self.sriov.is_some() {
+            return Err(IxyError::InvalidConfiguration(
+                "VLAN filters are not supported with SR-IOV".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the bits of the multicast table array for `macs` and clears all others, section
+    /// 8.2.3.7.3. Bits 47:36 of an address select its bit, MCSTCTRL.MO is left at 0 for that.
+    fn write_multicast_filters(&self, macs: &[[u8; 6]]) {
+        let mut mta = [0u32; NUM_MTA_ENTRIES as usize];
+
+        for mac in macs {
+            let vector = ((u32::from(mac[4]) >> 4) | (u32::from(mac[5]) << 4)) & 0xfff;
+            mta[(vector >> 5) as usize] |= 1 << (vector & 0x1f);
+        }
+
+        for (i, bits) in mta.iter().enumerate() {
+            self.set_reg32(IXGBE_MTA(i as u32), *bits);
+        }
+
+        let mcstctrl = if macs.is_empty() {
+            0
+        } else {
+            IXGBE_MCSTCTRL_MFE
+        };
+        self.set_reg32(IXGBE_MCSTCTRL, mcstctrl);
+    }
+
+    /// Empties the VLAN filter table and disables filtering.
+    fn clear_vlan_filters(&self) {
+        self.clear_flags32(IXGBE_VLNCTRL, IXGBE_VLNCTRL_VFE);
+
+        for i in 0..NUM_VFTA_ENTRIES {
+            self.set_reg32(IXGBE_VFTA(i), 0);
+        }
+    }
+
+    /// Allocates and configures the descriptor ring of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+        // section 7.1.9 - setup descriptor ring
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_tx_desc>();
+
+        let dma: Dma<ixgbe_adv_tx_desc> = self.allocate_dma(ring_size_bytes)?;
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(
+            IXGBE_TDBAL(self.hw_queue(queue_id)),
+            (dma.phys as u64 & 0xffff_ffff) as u32,
+        );
+        self.set_reg32(
+            IXGBE_TDBAH(self.hw_queue(queue_id)),
+            (dma.phys as u64 >> 32) as u32,
+        );
+        self.set_reg32(IXGBE_TDLEN(self.hw_queue(queue_id)), ring_size_bytes as u32);
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        // descriptor writeback magic values, important to get good performance and low PCIe overhead
+        // see 7.2.3.4.1 and 7.2.3.5 for an explanation of these values and how to find good ones
+        // we just use the defaults from DPDK here, but this is a potentially interesting point for optimizations
+        self.set_desc_thresholds(
+            IXGBE_TXDCTL(self.hw_queue(queue_id)),
+            TX_PTHRESH,
+            TX_HTHRESH,
+            TX_WTHRESH,
+        )?;
+
+        self.tx_queues.push(IxgbeTxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Sets the rx queues` descriptors and enables the queues.
+    fn start_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("starting rx queue {}", queue_id);
+
+        let tail = self.rx_queues[queue_id as usize].fill()?;
+
+        // enable queue and wait if necessary
+        self.set_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+
+        // rx queue starts out full
+        self.set_reg32(IXGBE_RDH(self.hw_queue(queue_id)), 0);
+
+        // was set to 0 before in the init function
+        self.set_reg32(IXGBE_RDT(self.hw_queue(queue_id)), tail as u32);
+
+        Ok(())
+    }
+
+    /// Enables the tx queues.
+    fn start_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("starting tx queue {}", queue_id);
+
+        {
+            let queue = &mut self.tx_queues[queue_id as usize];
+
+            if queue.num_descriptors & (queue.num_descriptors - 1) != 0 {
+                return Err(IxyError::InvalidConfiguration(
+                    "number of queue entries must be a power of 2".to_string(),
+                ));
+            }
+        }
+
+        // tx queue starts out empty
+        self.set_reg32(IXGBE_TDH(self.hw_queue(queue_id)), 0);
+        self.set_reg32(IXGBE_TDT(self.hw_queue(queue_id)), 0);
+
+        // enable queue and wait if necessary
+        self.set_flags32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn stop_rx_queue(&self, queue_id: u16) {
+        debug!("stopping rx queue {}", queue_id);
+
+        self.clear_flags32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+        self.wait_clear_reg32(IXGBE_RXDCTL(self.hw_queue(queue_id)), IXGBE_RXDCTL_ENABLE);
+    }
 
-        for i in 0..self.num_rx_queues {
-            self.start_rx_queue(i)?;
+    /// Disables tx queue `queue_id`, packets that have not been sent yet stay in the ring.
+    fn stop_tx_queue(&self, queue_id: u16) {
+        debug!("stopping tx queue {}", queue_id);
+
+        self.clear_flags32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+        self.wait_clear_reg32(IXGBE_TXDCTL(self.hw_queue(queue_id)), IXGBE_TXDCTL_ENABLE);
+    }
+
+    /// Initializes the link of this device.
+    fn init_link(&mut self) {
+        if !self.mac_type.is_copper() {
+            self.init_link_82599();
+            return;
+        }
+
+        // the PHY negotiates with its default advertisement even if it can't be configured
+        if let Err(e) = self.init_link_copper() {
+            warn!("failed to set up the phy of {}: {}", self.pci_addr, e);
+        }
+    }
+
+    // see section 4.6.4
+    /// Initializes the link of an 82599 via AUTOC.
+    fn init_link_82599(&self) {
+        // link auto-configuration register should already be set correctly, we're resetting it anyway
+        self.set_reg32(
+            IXGBE_AUTOC,
+            (self.get_reg32(IXGBE_AUTOC) & !IXGBE_AUTOC_LMS_MASK) | IXGBE_AUTOC_LMS_10G_SERIAL,
+        );
+        self.set_reg32(
+            IXGBE_AUTOC,
+            (self.get_reg32(IXGBE_AUTOC) & !IXGBE_AUTOC_10G_PMA_PMD_MASK) | IXGBE_AUTOC_10G_XAUI,
+        );
+        // negotiate link
+        self.set_flags32(IXGBE_AUTOC, IXGBE_AUTOC_AN_RESTART);
+        // datasheet wants us to wait for the link here, but we can continue and wait afterwards
+    }
+
+    /// Finds the 10GBASE-T PHY, advertises all speeds it supports and restarts
+    /// auto-negotiation, see section 3.5.3 of the X540 datasheet.
+    fn init_link_copper(&mut self) -> Result<(), IxyError> {
+        // the PHY is the first MDIO device with a valid identifier, same as the linux driver
+        let phy_addr = (0..IXGBE_MAX_PHY_ADDR)
+            .find(|&addr| {
+                matches!(
+                    self.read_phy_reg(addr, IXGBE_MDIO_PMA_PMD_DEV_TYPE, IXGBE_MDIO_PHY_ID_HIGH),
+                    Ok(id) if id != 0 && id != 0xffff
+                )
+            })
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(format!("no phy found on {}", self.pci_addr))
+            })?;
+        self.phy_addr = Some(phy_addr);
+
+        debug!("phy of {} has mdio address {}", self.pci_addr, phy_addr);
+
+        let an = IXGBE_MDIO_AUTO_NEG_DEV_TYPE;
+
+        let reg = self.read_phy_reg(phy_addr, an, IXGBE_MII_10GBASE_T_AUTONEG_CTRL_REG)?;
+        self.write_phy_reg(
+            phy_addr,
+            an,
+            IXGBE_MII_10GBASE_T_AUTONEG_CTRL_REG,
+            reg | IXGBE_MII_10GBASE_T_ADVERTISE as u16,
+        )?;
+
+        // 1G and the NBASE-T rates share a vendor specific register
+        let mut advertise = IXGBE_MII_1GBASE_T_ADVERTISE;
+        if self.mac_type == MacType::X550 {
+            advertise |= IXGBE_MII_2_5GBASE_T_ADVERTISE | IXGBE_MII_5GBASE_T_ADVERTISE;
+        }
+        let reg = self.read_phy_reg(phy_addr, an, IXGBE_MII_AUTONEG_VENDOR_PROVISION_1_REG)?;
+        self.write_phy_reg(
+            phy_addr,
+            an,
+            IXGBE_MII_AUTONEG_VENDOR_PROVISION_1_REG,
+            reg | advertise as u16,
+        )?;
+
+        let reg = self.read_phy_reg(phy_addr, an, IXGBE_MII_AUTONEG_ADVERTISE_REG)?;
+        self.write_phy_reg(
+            phy_addr,
+            an,
+            IXGBE_MII_AUTONEG_ADVERTISE_REG,
+            reg | IXGBE_MII_100BASE_T_ADVERTISE as u16,
+        )?;
+
+        // negotiate link
+        let reg = self.read_phy_reg(phy_addr, an, IXGBE_MII_AUTONEG_REG)?;
+        self.write_phy_reg(
+            phy_addr,
+            an,
+            IXGBE_MII_AUTONEG_REG,
+            reg | IXGBE_MII_RESTART as u16,
+        )
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Sets or clears both CRC strip bits, RDRXCTL.CRCStrip has to match HLREG0.RXCRCSTRP, see
+    /// section 8.2.3.8.8.
+    fn write_crc_strip(&self, enable: bool) {
+        if enable {
+            self.set_flags32(IXGBE_HLREG0, IXGBE_HLREG0_RXCRCSTRP);
+            self.set_flags32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_CRCSTRIP);
+        } else {
+            self.clear_flags32(IXGBE_HLREG0, IXGBE_HLREG0_RXCRCSTRP);
+            self.clear_flags32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_CRCSTRIP);
+        }
+    }
+
+    /// Rewrites the redirection table to spread the flows evenly over the first `num_queues` rx
+    /// queues if RSS is enabled, key and hashed fields are kept. Disables RSS without queues.
+    fn spread_rss(&self, num_queues: u16) -> Result<(), IxyError> {
+        let mut config = match *self.rss.borrow() {
+            Some(ref config) => config.clone(),
+            None => return Ok(()),
+        };
+
+        if num_queues == 0 {
+            return self.disable_rss();
+        }
+
+        let num_queues = usize::from(num_queues.min(MAX_RSS_QUEUES));
+        for (i, entry) in config.reta.iter_mut().enumerate() {
+            *entry = (i % num_queues) as u16;
+        }
+
+        self.enable_rss(&config)
+    }
+
+    /// Sets or clears the drop enable bit of rx queue `queue_id`.
+    fn write_rx_drop_enable(&self, queue_id: u16, enable: bool) {
+        // let nic drop packets if no rx descriptor is available instead of buffering them
+        if enable {
+            self.set_flags32(IXGBE_SRRCTL(self.hw_queue(queue_id)), IXGBE_SRRCTL_DROP_EN);
+        } else {
+            self.clear_flags32(IXGBE_SRRCTL(self.hw_queue(queue_id)), IXGBE_SRRCTL_DROP_EN);
+        }
+    }
+
+    /// Initializes the flow director for perfect match filters, all filters are removed.
+    fn init_flow_director(&self) -> Result<(), IxyError> {
+        self.flow_rules.borrow_mut().clear();
+
+        self.set_reg32(IXGBE_FDIRHKEY, IXGBE_ATR_BUCKET_HASH_KEY);
+        self.set_reg32(IXGBE_FDIRSKEY, IXGBE_ATR_SIGNATURE_HASH_KEY);
+
+        // same flexible byte offset, maximum bucket length and full threshold as linux
+        self.set_reg32(
+            IXGBE_FDIRCTRL,
+            IXGBE_FDIRCTRL_PBALLOC_64K
+                | IXGBE_FDIRCTRL_PERFECT_MATCH
+                | IXGBE_FDIRCTRL_REPORT_STATUS
+                | (IXGBE_FDIR_DROP_QUEUE << IXGBE_FDIRCTRL_DROP_Q_SHIFT)
+                | (0x6 << IXGBE_FDIRCTRL_FLEX_SHIFT)
+                | (0xa << IXGBE_FDIRCTRL_MAX_LENGTH_SHIFT)
+                | (0x4 << IXGBE_FDIRCTRL_FULL_THRESH_SHIFT),
+        );
+
+        for _ in 0..IXGBE_FDIR_INIT_DONE_POLL {
+            if self.get_reg32(IXGBE_FDIRCTRL) & IXGBE_FDIRCTRL_INIT_DONE != 0 {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "flow director of {} failed to initialize",
+            self.pci_addr
+        )))
+    }
+
+    /// Programs the global masks of the flow director to those of `rule`, the vlan, pool and
+    /// flexible bytes are ignored.
+    fn write_fdir_masks(&self, rule: &FlowRule) {
+        let mut fdirm = IXGBE_FDIRM_DIPv6
+            | IXGBE_FDIRM_POOL
+            | IXGBE_FDIRM_FLEX
+            | IXGBE_FDIRM_VLANID
+            | IXGBE_FDIRM_VLANP;
+        if rule.protocol == FlowProtocol::Other {
+            fdirm |= IXGBE_FDIRM_L4P;
+        }
+        self.set_reg32(IXGBE_FDIRM, fdirm);
+
+        // set bits of these masks are ignored, the port masks are bit reversed
+        let port_mask = (u32::from(rule.dst_port_mask.reverse_bits())
+            << IXGBE_FDIRTCPM_DPORTM_SHIFT)
+            | u32::from(rule.src_port_mask.reverse_bits());
+        self.set_reg32(IXGBE_FDIRTCPM, !port_mask);
+        self.set_reg32(IXGBE_FDIRUDPM, !port_mask);
+        self.set_reg32(IXGBE_FDIRSIP4M, !u32::from(rule.src_ip_mask));
+        self.set_reg32(IXGBE_FDIRDIP4M, !u32::from(rule.dst_ip_mask));
+    }
+
+    /// Writes `cmd` to FDIRCMD and waits for the flow director to complete it. Returns the
+    /// final value of FDIRCMD.
+    fn write_fdir_cmd(&self, cmd: u32) -> Result<u32, IxyError> {
+        self.set_reg32(IXGBE_FDIRCMD, cmd);
+
+        for _ in 0..IXGBE_FDIRCMD_CMD_POLL * 10 {
+            let fdircmd = self.get_reg32(IXGBE_FDIRCMD);
+            if fdircmd & IXGBE_FDIRCMD_CMD_MASK == 0 {
+                return Ok(fdircmd);
+            }
+            thread::sleep(FDIR_CMD_DELAY);
+        }
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "flow director command {:#x} of {} timed out",
+            cmd, self.pci_addr
+        )))
+    }
+
+    /// Returns the VMDq pool of the physical function, i.e. the pool after the last virtual
+    /// function.
+    fn default_pool(&self) -> u8 {
+        self.sriov.as_ref().map_or(0, |sriov| sriov.vfs.len() as u8)
+    }
+
+    /// Returns the index of the hardware queue behind queue `queue_id`, the queues of the
+    /// physical function start at its pool with SR-IOV.
+    fn hw_queue(&self, queue_id: impl Into<u32>) -> u32 {
+        let offset = self
+            .sriov
+            .as_ref()
+            .map_or(0, |sriov| sriov.vfs.len() as u32 * sriov.queues_per_pool);
+
+        offset + queue_id.into()
+    }
+
+    /// Returns an error if the pool of the physical function can't hold the queues.
+    fn check_pool_queues(&self, num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+        let sriov = match self.sriov {
+            Some(ref sriov) => sriov,
+            None => return Ok(()),
+        };
+
+        if u32::from(num_rx_queues.max(num_tx_queues)) > sriov.queues_per_pool {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "the physical function is limited to {} queues with {} virtual functions",
+                sriov.queues_per_pool,
+                sriov.vfs.len()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if `vf` is not a virtual function of this device.
+    fn check_vf(&self, vf: u16) -> Result<(), IxyError> {
+        let num_vfs = self.sriov.as_ref().map_or(0, |sriov| sriov.vfs.len());
+
+        if usize::from(vf) >= num_vfs {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "virtual function {} does not exist, {} has {}",
+                vf, self.pci_addr, num_vfs
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the state of virtual function `vf`.
+    ///
+    /// # Panics
+    /// Panics if SR-IOV is disabled or `vf` does not exist.
+    fn vf_state(&self, vf: u32) -> VfState {
+        self.sriov.as_ref().expect("SR-IOV is disabled").vf_states[vf as usize]
+    }
+
+    /// Replaces the state of virtual function `vf`.
+    fn set_vf_state(&mut self, vf: u32, state: VfState) {
+        if let Some(ref mut sriov) = self.sriov {
+            sriov.vf_states[vf as usize] = state;
+        }
+    }
+
+    /// Creates `num_vfs` virtual functions, or removes them if it is 0, moves the queues of the
+    /// physical function to its new pool and initializes the device again.
+    fn configure_sriov(&mut self, num_vfs: u16) -> Result<(), IxyError> {
+        // the kernel refuses virtual functions if vfio-pci wasn't loaded with enable_sriov=1,
+        // so change them before the device is torn down to leave it working on errors
+        let sriov = if num_vfs == 0 {
+            pci_disable_sriov(&self.pci_addr)?;
+            None
+        } else {
+            let vfs = pci_enable_sriov(&self.pci_addr, num_vfs)?;
+
+            // locally administered addresses derived from the address of the physical function
+            let mac = self.get_mac_addr();
+            let vf_states = (0..vfs.len())
+                .map(|vf| {
+                    let mut vf_mac = mac;
+                    vf_mac[0] |= 0x02;
+                    vf_mac[5] = vf_mac[5].wrapping_add(vf as u8 + 1);
+
+                    VfState {
+                        mac: vf_mac,
+                        mac_locked: false,
+                        vlan: None,
+                        clear_to_send: false,
+                        api_version: IXGBE_MBOX_API_10,
+                    }
+                })
+                .collect();
+
+            Some(Sriov {
+                vfs,
+                queues_per_pool: if num_vfs <= MAX_VFS_32_POOLS { 4 } else { 2 },
+                vf_states,
+            })
+        };
+
+        if !self.interrupts.is_empty() {
+            vfio_disable_msix(self.device_fd)?;
+            self.interrupts.clear();
+        }
+
+        // stop all dma before dropping the rings
+        self.set_reg32(IXGBE_EIMC, 0x7fff_ffff);
+        self.set_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
+        self.wait_clear_reg32(IXGBE_CTRL, IXGBE_CTRL_RST_MASK);
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.sriov = sriov;
+
+        let pci_addr = self.pci_addr.clone();
+        self.reset_and_init(&pci_addr)
+    }
+
+    // section 4.6.10.1
+    /// Enables virtualization if SR-IOV is configured, disables it otherwise.
+    fn init_sriov(&self) {
+        let sriov = match self.sriov {
+            Some(ref sriov) => sriov,
+            None => {
+                // unlike the other virtualization registers GCR_EXT survives a software reset
+                self.clear_flags32(IXGBE_GCR_EXT, IXGBE_GCR_EXT_VT_MODE_MASK);
+                return;
+            }
+        };
+
+        let pf_pool = u32::from(self.default_pool());
+
+        // 32 pools with 4 queues each (the rss of a pool isn't configured) or 64 with 2 each
+        let (vt_mode, mrqc) = if sriov.queues_per_pool == 4 {
+            (IXGBE_GCR_EXT_VT_MODE_32, IXGBE_MRQC_VMDQRSS32EN)
+        } else {
+            (IXGBE_GCR_EXT_VT_MODE_64, IXGBE_MRQC_VMDQEN)
+        };
+        self.set_reg32(IXGBE_GCR_EXT, IXGBE_GCR_EXT_MSIX_EN | vt_mode);
+        self.set_reg32(IXGBE_MRQC, mrqc);
+
+        // unmatched packets go to the physical function, broadcasts and multicasts to all pools
+        self.set_reg32(
+            IXGBE_VT_CTL,
+            IXGBE_VT_CTL_VT_ENABLE | IXGBE_VT_CTL_REPLEN | (pf_pool << IXGBE_VT_CTL_POOL_SHIFT),
+        );
+
+        // virtual functions are enabled once they reset, see process_vf_mailbox
+        for i in 0..2 {
+            self.set_reg32(IXGBE_VFRE(i), 0);
+            self.set_reg32(IXGBE_VFTE(i), 0);
+        }
+        self.set_flags32(IXGBE_VFRE(pf_pool / 32), 1 << (pf_pool % 32));
+        self.set_flags32(IXGBE_VFTE(pf_pool / 32), 1 << (pf_pool % 32));
+
+        // let the functions talk to each other without going through the wire
+        self.set_reg32(IXGBE_PFDTXGSWC, IXGBE_PFDTXGSWC_VT_LBEN);
+
+        self.write_rar(0, self.get_mac_addr(), pf_pool as u8);
+        self.set_reg32(
+            IXGBE_VMOLR(pf_pool),
+            IXGBE_VMOLR_AUPE | IXGBE_VMOLR_BAM | IXGBE_VMOLR_MPE,
+        );
+
+        for vf in 0..sriov.vfs.len() as u32 {
+            self.write_vf_filters(vf);
+        }
+
+        info!(
+            "SR-IOV enabled with {} virtual functions, the physical function uses pool {}",
+            sriov.vfs.len(),
+            pf_pool
+        );
+    }
+
+    /// Programs the mac address and port VLAN of virtual function `vf`.
+    fn write_vf_filters(&self, vf: u32) {
+        let state = self.vf_state(vf);
+
+        // the addresses of the virtual functions are allocated top down
+        self.write_rar(NUM_RAR_ENTRIES - 1 - vf, state.mac, vf as u8);
+
+        // virtual functions get all multicasts instead of maintaining the multicast table
+        let mut vmolr = IXGBE_VMOLR_BAM | IXGBE_VMOLR_MPE;
+
+        match state.vlan {
+            Some(vlan) => {
+                // section 7.10.3.6 - insert the tag on tx and only accept it on rx
+                self.set_reg32(IXGBE_VMVIR(vf), IXGBE_VMVIR_VLANA_DEFAULT | u32::from(vlan));
+                self.write_vlan_pool(vf, Some(vlan));
+            }
+            None => {
+                vmolr |= IXGBE_VMOLR_AUPE;
+                self.set_reg32(IXGBE_VMVIR(vf), 0);
+                self.write_vlan_pool(vf, None);
+            }
+        }
+
+        self.set_reg32(IXGBE_VMOLR(vf), vmolr);
+    }
+
+    /// Adds `pool` to the VLAN pool filter of `vlan` and removes it from all others.
+    fn write_vlan_pool(&self, pool: u32, vlan: Option<u16>) {
+        let bank = pool / 32;
+        let bit = 1 << (pool % 32);
+
+        for i in 0..IXGBE_VLVF_ENTRIES {
+            let vlvf = self.get_reg32(IXGBE_VLVF(i));
+            if vlvf & IXGBE_VLVF_VIEN == 0 || Some((vlvf & IXGBE_VLVF_VLANID_MASK) as u16) == vlan {
+                continue;
+            }
+
+            self.clear_flags32(IXGBE_VLVFB(i * 2 + bank), bit);
+
+            // free entries without pools
+            if self.get_reg32(IXGBE_VLVFB(i * 2)) == 0
+                && self.get_reg32(IXGBE_VLVFB(i * 2 + 1)) == 0
+            {
+                self.set_reg32(IXGBE_VLVF(i), 0);
+            }
+        }
+
+        let vlan = match vlan {
+            Some(vlan) => u32::from(vlan),
+            None => return,
+        };
+
+        let existing = (0..IXGBE_VLVF_ENTRIES)
+            .find(|&i| self.get_reg32(IXGBE_VLVF(i)) == IXGBE_VLVF_VIEN | vlan);
+        let free = || {
+            (0..IXGBE_VLVF_ENTRIES).find(|&i| self.get_reg32(IXGBE_VLVF(i)) & IXGBE_VLVF_VIEN == 0)
+        };
+
+        match existing.or_else(free) {
+            Some(i) => {
+                self.set_reg32(IXGBE_VLVF(i), IXGBE_VLVF_VIEN | vlan);
+                self.set_flags32(IXGBE_VLVFB(i * 2 + bank), bit);
+                self.set_flags32(IXGBE_VFTA(vlan / 32), 1 << (vlan % 32));
+            }
+            None => warn!("no free VLAN pool filter for VLAN {}", vlan),
+        }
+    }
+
+    /// Handles the request in `msg` from virtual function `vf` and replaces it with the reply,
+    /// the protocol is the one of the linux driver, see its ixgbe_sriov.c.
+    fn handle_vf_request(&mut self, vf: u32, msg: &mut [u32]) {
+        let request = msg[0] & 0xffff;
+        let mut state = self.vf_state(vf);
+
+        if request == IXGBE_VF_RESET {
+            info!("virtual function {} of {} reset", vf, self.pci_addr);
+
+            state.clear_to_send = true;
+            state.api_version = IXGBE_MBOX_API_10;
+            self.set_vf_state(vf, state);
+
+            self.write_vf_filters(vf);
+            self.set_flags32(IXGBE_VFRE(vf / 32), 1 << (vf % 32));
+            self.set_flags32(IXGBE_VFTE(vf / 32), 1 << (vf % 32));
+
+            // the reply carries the mac address and the multicast filter type
+            for word in msg.iter_mut() {
+                *word = 0;
+            }
+            msg[0] = IXGBE_VF_RESET | IXGBE_VT_MSGTYPE_ACK;
+            msg[1] = u32::from_le_bytes([state.mac[0], state.mac[1], state.mac[2], state.mac[3]]);
+            msg[2] = u32::from_le_bytes([state.mac[4], state.mac[5], 0, 0]);
+            msg[IXGBE_VF_MC_TYPE_WORD as usize] = 0;
+
+            return;
+        }
+
+        // everything else has to wait for the reset
+        if !state.clear_to_send {
+            msg[0] |= IXGBE_VT_MSGTYPE_NACK;
+            return;
+        }
+
+        let ack = match request {
+            IXGBE_VF_SET_MAC_ADDR => {
+                let (low, high) = (msg[1].to_le_bytes(), msg[2].to_le_bytes());
+                let mac = [low[0], low[1], low[2], low[3], high[0], high[1]];
+
+                let allowed = !state.mac_locked || mac == state.mac;
+                if allowed {
+                    state.mac = mac;
+                    self.set_vf_state(vf, state);
+                    self.write_vf_filters(vf);
+                }
+                allowed
+            }
+            // the virtual functions are multicast promiscuous and the frame size is global
+            IXGBE_VF_SET_MULTICAST | IXGBE_VF_SET_LPE => true,
+            IXGBE_VF_API_NEGOTIATE => match msg[1] {
+                IXGBE_MBOX_API_10 | IXGBE_MBOX_API_11 => {
+                    state.api_version = msg[1];
+                    self.set_vf_state(vf, state);
+                    true
+                }
+                _ => false,
+            },
+            IXGBE_VF_GET_QUEUES if state.api_version == IXGBE_MBOX_API_11 => {
+                let queues = self.sriov.as_ref().map_or(1, |sriov| sriov.queues_per_pool);
+
+                msg[IXGBE_VF_TX_QUEUES as usize] = queues;
+                msg[IXGBE_VF_RX_QUEUES as usize] = queues;
+                msg[IXGBE_VF_TRANS_VLAN as usize] = u32::from(state.vlan.is_some());
+                msg[IXGBE_VF_DEF_QUEUE as usize] = 0;
+                true
+            }
+            // VLAN filters and additional addresses are up to the physical function
+            _ => false,
+        };
+
+        msg[0] |= IXGBE_VT_MSGTYPE_CTS
+            | if ack {
+                IXGBE_VT_MSGTYPE_ACK
+            } else {
+                IXGBE_VT_MSGTYPE_NACK
+            };
+    }
+
+    /// Tries to take the mailbox of virtual function `vf` from the virtual function.
+    fn lock_vf_mailbox(&self, vf: u32) -> bool {
+        for _ in 0..MBX_LOCK_TRIES {
+            self.set_reg32(IXGBE_PFMAILBOX(vf), IXGBE_PFMAILBOX_PFU);
+            if self.get_reg32(IXGBE_PFMAILBOX(vf)) & IXGBE_PFMAILBOX_PFU != 0 {
+                return true;
+            }
+            thread::sleep(MBX_LOCK_DELAY);
+        }
+
+        false
+    }
+
+    /// Reads the message in the mailbox of virtual function `vf` and acknowledges it.
+    fn read_vf_mailbox(&self, vf: u32, msg: &mut [u32]) -> bool {
+        if !self.lock_vf_mailbox(vf) {
+            return false;
         }
 
-        for i in 0..self.num_tx_queues {
-            self.start_tx_queue(i)?;
-        }
+        for (i, word) in msg.iter_mut().enumerate() {
+            *word = self.get_reg32(IXGBE_PFMBMEM(vf) + 4 * i as u32);
+        }
+
+        // acknowledging also releases the mailbox
+        self.set_reg32(IXGBE_PFMAILBOX(vf), IXGBE_PFMAILBOX_ACK);
+
+        true
+    }
+
+    /// Writes `msg` to the mailbox of virtual function `vf` and notifies it.
+    fn write_vf_mailbox(&self, vf: u32, msg: &[u32]) -> bool {
+        if !self.lock_vf_mailbox(vf) {
+            return false;
+        }
+
+        // drop a stale ack of the previous message
+        self.set_reg32(IXGBE_PFMBICR(vf / 16), IXGBE_MBVFICR_VFACK_VF1 << (vf % 16));
+
+        for (i, word) in msg.iter().enumerate() {
+            self.set_reg32(IXGBE_PFMBMEM(vf) + 4 * i as u32, *word);
+        }
+
+        // notifying also releases the mailbox
+        self.set_reg32(IXGBE_PFMAILBOX(vf), IXGBE_PFMAILBOX_STS);
+
+        true
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the receive buffer size of rx queue `queue_id` to the data size of its mempool's
+    /// entries, i.e. without the headroom, see section 8.2.3.8.7.
+    fn write_rx_buffer_size(&self, queue_id: u16) {
+        let pool = &self.rx_queues[queue_id as usize].pool;
+        let bsize = ((pool.entry_size() - pool.headroom()) >> IXGBE_SRRCTL_BSIZEPKT_SHIFT) as u32;
+
+        self.set_reg32(
+            IXGBE_SRRCTL(self.hw_queue(queue_id)),
+            (self.get_reg32(IXGBE_SRRCTL(self.hw_queue(queue_id))) & !IXGBE_SRRCTL_BSIZEPKT_MASK)
+                | bsize.min(IXGBE_SRRCTL_BSIZEPKT_MASK),
+        );
+    }
+
+    /// Accepts frames of up to `max_frame_size` bytes, unless larger ones are accepted already.
+    fn enable_jumbo_frames(&self, max_frame_size: usize) {
+        let maxfrs = self.get_reg32(IXGBE_MAXFRS);
+
+        if (maxfrs >> IXGBE_MHADD_MFS_SHIFT) as usize >= max_frame_size {
+            return;
+        }
+
+        debug!("accepting frames of up to {} bytes", max_frame_size);
+
+        // section 8.2.3.22.13 - the maximum frame size includes the FCS
+        self.set_reg32(
+            IXGBE_MAXFRS,
+            (maxfrs & !IXGBE_MHADD_MFS_MASK) | ((max_frame_size as u32) << IXGBE_MHADD_MFS_SHIFT),
+        );
+        self.set_flags32(IXGBE_HLREG0, IXGBE_HLREG0_JUMBOEN);
+    }
+
+    /// Assigns an MSI-X vector with an eventfd to each rx queue, see section 7.3.
+    ///
+    /// Vectors are masked after they fire, so an interrupt has to be enabled again before each
+    /// wait.
+    fn setup_interrupts(&mut self) -> Result<(), IxyError> {
+        if !self.vfio {
+            return Err(IxyError::InvalidConfiguration(
+                "interrupts require a device bound to vfio-pci".to_string(),
+            ));
+        }
+
+        if !self.interrupts.is_empty() {
+            vfio_disable_msix(self.device_fd)?;
+            self.interrupts.clear();
+        }
+
+        self.interrupts = vfio_enable_msix(self.device_fd, u32::from(self.num_rx_queues))?;
+
+        self.set_flags32(
+            IXGBE_GPIE,
+            IXGBE_GPIE_MSIX_MODE | IXGBE_GPIE_PBA_SUPPORT | IXGBE_GPIE_EIAME,
+        );
+
+        for queue_id in 0..u32::from(self.num_rx_queues) {
+            self.write_ivar(queue_id);
+
+            let itr_us = match self.rx_queues[queue_id as usize].moderation {
+                InterruptModeration::Static(us) => us,
+                InterruptModeration::Adaptive => ITR_LOWEST_LATENCY_US,
+            };
+            self.write_itr(queue_id, itr_us);
+
+            // mask the vector once it fires and clear its cause automatically
+            self.set_flags32(IXGBE_EIAM_EX(queue_id / 32), 1 << (queue_id % 32));
+            if queue_id < 16 {
+                self.set_flags32(IXGBE_EIAC, 1 << queue_id);
+            }
+        }
+
+        debug!(
+            "msi-x enabled for {} rx queues of {}",
+            self.num_rx_queues, self.pci_addr
+        );
+
+        Ok(())
+    }
+
+    /// Maps rx queue `queue_id` to the interrupt vector of the same number.
+    fn write_ivar(&self, queue_id: u32) {
+        // each IVAR register holds the vectors of two rx queues
+        let ivar = IXGBE_IVAR(self.hw_queue(queue_id) / 2);
+        let shift = (self.hw_queue(queue_id) % 2) * 16;
+        let vector = (queue_id | IXGBE_IVAR_ALLOC_VAL) << shift;
+        self.set_reg32(ivar, (self.get_reg32(ivar) & !(0xff << shift)) | vector);
+    }
+
+    /// Sets the interrupt throttling interval of rx queue `queue_id` to `itr_us` µs, 0 disables
+    /// throttling.
+    fn write_itr(&self, queue_id: u32, itr_us: u32) {
+        self.set_reg32(
+            IXGBE_EITR(queue_id),
+            ((itr_us / 2) << 3) & IXGBE_EITR_ITR_INT_MASK | IXGBE_EITR_CNT_WDIS,
+        );
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Writes `mac` to receive address register `index`, marks it valid and assigns it to `pool`.
+    fn write_rar(&self, index: u32, mac: [u8; 6], pool: u8) {
+        let low: u32 = u32::from(mac[0])
+            + (u32::from(mac[1]) << 8)
+            + (u32::from(mac[2]) << 16)
+            + (u32::from(mac[3]) << 24);
+        let high: u32 = u32::from(mac[4]) + (u32::from(mac[5]) << 8);
+
+        // section 8.2.3.7.10 - on the 82599 the pool is selected by the MPSAR bitmap instead of
+        // the VIND field of RAH that only exists on the 82598
+        let pool_bit = 1u64 << pool;
+        self.set_reg32(IXGBE_MPSAR_LO(index), pool_bit as u32);
+        self.set_reg32(IXGBE_MPSAR_HI(index), (pool_bit >> 32) as u32);
 
-        // enable promisc mode by default to make testing easier
-        self.set_promisc(true);
+        self.set_reg32(IXGBE_RAL(index), low);
+        self.set_reg32(IXGBE_RAH(index), high | IXGBE_RAH_AV);
+    }
 
-        // wait some time for the link to come up
-        self.wait_for_link();
+    /// Sets the prefetch, host and write-back thresholds of the descriptor control register `reg`.
+    fn set_desc_thresholds(
+        &self,
+        reg: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), pthresh, hthresh, wthresh)?,
+        );
 
         Ok(())
     }
 
-    // sections 4.6.7
-    /// Initializes the rx queues of this device.
-    fn init_rx(&mut self) -> Result<(), Box<dyn Error>> {
-        // disable rx while re-configuring it
-        self.clear_flags32(IXGBE_RXCTRL, IXGBE_RXCTRL_RXEN);
-
-        // section 4.6.11.3.4 - allocate all queues and traffic to PB0
-        self.set_reg32(IXGBE_RXPBSIZE(0), IXGBE_RXPBSIZE_128KB);
-        for i in 1..8 {
-            self.set_reg32(IXGBE_RXPBSIZE(i), 0);
-        }
+    /// Acquires the software/firmware semaphore for the resources in `mask`.
+    fn acquire_swfw_sync(&self, mask: u32) -> bool {
+        // firmware owns a resource if the bit shifted by five is set in GSSR
+        let fw_mask = mask << 5;
 
-        // enable CRC offloading
-        self.set_flags32(IXGBE_HLREG0, IXGBE_HLREG0_RXCRCSTRP);
-        self.set_flags32(IXGBE_RDRXCTL, IXGBE_RDRXCTL_CRCSTRIP);
+        for _ in 0..SWFW_SYNC_TRIES {
+            if !self.acquire_swsm() {
+                return false;
+            }
 
-        // accept broadcast packets
-        self.set_flags32(IXGBE_FCTRL, IXGBE_FCTRL_BAM);
+            let gssr = self.get_reg32(IXGBE_GSSR);
+            if gssr & (mask | fw_mask) == 0 {
+                self.set_reg32(IXGBE_GSSR, gssr | mask);
+                self.release_swsm();
+                return true;
+            }
 
-        // configure queues, same for all queues
-        for i in 0..self.num_rx_queues {
-            debug!("initializing rx queue {}", i);
-            // enable advanced rx descriptors
-            self.set_reg32(
-                IXGBE_SRRCTL(u32::from(i)),
-                (self.get_reg32(IXGBE_SRRCTL(u32::from(i))) & !IXGBE_SRRCTL_DESCTYPE_MASK)
-                    | IXGBE_SRRCTL_DESCTYPE_ADV_ONEBUF,
-            );
-            // let nic drop packets if no rx descriptor is available instead of buffering them
-            self.set_flags32(IXGBE_SRRCTL(u32::from(i)), IXGBE_SRRCTL_DROP_EN);
+            self.release_swsm();
+            thread::sleep(Duration::from_millis(5));
+        }
 
-            // section 7.1.9 - setup descriptor ring
-            let ring_size_bytes =
-                (NUM_RX_QUEUE_ENTRIES) as usize * mem::size_of::<ixgbe_adv_rx_desc>();
+        false
+    }
 
-            let dma: Dma<ixgbe_adv_rx_desc> = Dma::allocate(ring_size_bytes, true)?;
+    /// Releases the software/firmware semaphore for the resources in `mask`.
+    fn release_swfw_sync(&self, mask: u32) {
+        if self.acquire_swsm() {
+            self.clear_flags32(IXGBE_GSSR, mask);
+            self.release_swsm();
+        }
+    }
 
-            // initialize to 0xff to prevent rogue memory accesses on premature dma activation
-            unsafe {
-                memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+    /// Acquires the semaphore protecting the GSSR register.
+    fn acquire_swsm(&self) -> bool {
+        // section 10.5.4 - reading SWSM sets SMBI, we own it if it was clear before
+        let acquired = (0..2000).any(|_| {
+            if self.get_reg32(IXGBE_SWSM) & IXGBE_SWSM_SMBI == 0 {
+                return true;
             }
+            thread::sleep(Duration::from_micros(50));
+            false
+        });
 
-            self.set_reg32(
-                IXGBE_RDBAL(u32::from(i)),
-                (dma.phys as u64 & 0xffff_ffff) as u32,
-            );
-            self.set_reg32(IXGBE_RDBAH(u32::from(i)), (dma.phys as u64 >> 32) as u32);
-            self.set_reg32(IXGBE_RDLEN(u32::from(i)), ring_size_bytes as u32);
+        if !acquired {
+            return false;
+        }
 
-            debug!("rx ring {} phys addr: {:#x}", i, dma.phys);
-            debug!("rx ring {} virt addr: {:p}", i, dma.virt);
+        let acquired = if self.mac_type == MacType::Mac82599 {
+            (0..2000).any(|_| {
+                self.set_flags32(IXGBE_SWSM, IXGBE_SWSM_SWESMBI);
+                if self.get_reg32(IXGBE_SWSM) & IXGBE_SWSM_SWESMBI != 0 {
+                    return true;
+                }
+                thread::sleep(Duration::from_micros(50));
+                false
+            })
+        } else {
+            // the X540 and its successors replaced SWESMBI by the register semaphore bit of
+            // GSSR, reading it sets it as well
+            (0..2000).any(|_| {
+                if self.get_reg32(IXGBE_SWFW_SYNC) & IXGBE_SWFW_REGSMP == 0 {
+                    return true;
+                }
+                thread::sleep(Duration::from_micros(50));
+                false
+            })
+        };
 
-            // set ring to empty at start
-            self.set_reg32(IXGBE_RDH(u32::from(i)), 0);
-            self.set_reg32(IXGBE_RDT(u32::from(i)), 0);
+        if !acquired {
+            self.release_swsm();
+        }
 
-            let mempool_size = if NUM_RX_QUEUE_ENTRIES + NUM_TX_QUEUE_ENTRIES < MIN_MEMPOOL_SIZE {
-                MIN_MEMPOOL_SIZE
-            } else {
-                NUM_RX_QUEUE_ENTRIES + NUM_TX_QUEUE_ENTRIES
-            };
+        acquired
+    }
 
-            let mempool = Mempool::allocate(mempool_size as usize, PKT_BUF_ENTRY_SIZE).unwrap();
+    /// Releases the semaphore protecting the GSSR register.
+    fn release_swsm(&self) {
+        if self.mac_type != MacType::Mac82599 {
+            self.clear_flags32(IXGBE_SWFW_SYNC, IXGBE_SWFW_REGSMP);
+        }
+        self.clear_flags32(IXGBE_SWSM, IXGBE_SWSM_SWESMBI | IXGBE_SWSM_SMBI);
+    }
 
-            let rx_queue = IxgbeRxQueue {
-                descriptors: dma.virt,
-                pool: mempool,
-                num_descriptors: NUM_RX_QUEUE_ENTRIES,
-                rx_index: 0,
-                bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
-            };
+    /// Returns the software/firmware semaphore of the PHY and i2c bus of this port.
+    fn phy_semaphore_mask(&self) -> u32 {
+        if self.get_reg32(IXGBE_STATUS) & IXGBE_STATUS_LAN_ID_1 != 0 {
+            IXGBE_GSSR_PHY1_SM
+        } else {
+            IXGBE_GSSR_PHY0_SM
+        }
+    }
 
-            self.rx_queues.push(rx_queue);
+    /// Reads the clause 45 register `reg` of MDIO device `dev_type` of the PHY at `phy_addr`.
+    fn read_phy_reg(&self, phy_addr: u32, dev_type: u32, reg: u32) -> Result<u16, IxyError> {
+        let mask = self.phy_semaphore_mask();
+        if !self.acquire_swfw_sync(mask) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot acquire the phy semaphore of {}",
+                self.pci_addr
+            )));
         }
 
-        // last sentence of section 4.6.7 - set some magic bits
-        self.set_flags32(IXGBE_CTRL_EXT, IXGBE_CTRL_EXT_NS_DIS);
+        let result = self
+            .mdio_command(phy_addr, dev_type, reg, IXGBE_MSCA_ADDR_CYCLE)
+            .and_then(|_| self.mdio_command(phy_addr, dev_type, reg, IXGBE_MSCA_READ))
+            .map(|_| (self.get_reg32(IXGBE_MSRWD) >> IXGBE_MSRWD_READ_DATA_SHIFT) as u16);
 
-        // probably a broken feature, this flag is initialized with 1 but has to be set to 0
-        for i in 0..self.num_rx_queues {
-            self.clear_flags32(IXGBE_DCA_RXCTRL(u32::from(i)), 1 << 12);
+        self.release_swfw_sync(mask);
+
+        result
+    }
+
+    /// Writes `value` to the clause 45 register `reg` of MDIO device `dev_type` of the PHY at
+    /// `phy_addr`.
+    fn write_phy_reg(
+        &self,
+        phy_addr: u32,
+        dev_type: u32,
+        reg: u32,
+        value: u16,
+    ) -> Result<(), IxyError> {
+        let mask = self.phy_semaphore_mask();
+        if !self.acquire_swfw_sync(mask) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot acquire the phy semaphore of {}",
+                self.pci_addr
+            )));
         }
 
-        // start rx
-        self.set_flags32(IXGBE_RXCTRL, IXGBE_RXCTRL_RXEN);
+        self.set_reg32(IXGBE_MSRWD, u32::from(value));
 
-        Ok(())
+        let result = self
+            .mdio_command(phy_addr, dev_type, reg, IXGBE_MSCA_ADDR_CYCLE)
+            .and_then(|_| self.mdio_command(phy_addr, dev_type, reg, IXGBE_MSCA_WRITE));
+
+        self.release_swfw_sync(mask);
+
+        result
     }
 
-    // section 4.6.8
-    /// Initializes the tx queues of this device.
-    fn init_tx(&mut self) -> Result<(), Box<dyn Error>> {
-        // crc offload and small packet padding
-        self.set_flags32(IXGBE_HLREG0, IXGBE_HLREG0_TXCRCEN | IXGBE_HLREG0_TXPADEN);
+    /// Issues the MDIO cycle `op_code` and waits for its completion, see section 3.5.4 of the
+    /// X540 datasheet.
+    fn mdio_command(
+        &self,
+        phy_addr: u32,
+        dev_type: u32,
+        reg: u32,
+        op_code: u32,
+    ) -> Result<(), IxyError> {
+        self.set_reg32(
+            IXGBE_MSCA,
+            (reg << IXGBE_MSCA_NP_ADDR_SHIFT)
+                | (dev_type << IXGBE_MSCA_DEV_TYPE_SHIFT)
+                | (phy_addr << IXGBE_MSCA_PHY_ADDR_SHIFT)
+                | op_code
+                | IXGBE_MSCA_MDI_COMMAND,
+        );
 
-        // section 4.6.11.3.4 - set default buffer size allocations
-        self.set_reg32(IXGBE_TXPBSIZE(0), IXGBE_TXPBSIZE_40KB);
-        for i in 1..8 {
-            self.set_reg32(IXGBE_TXPBSIZE(i), 0);
+        for _ in 0..IXGBE_MDIO_COMMAND_TIMEOUT {
+            thread::sleep(MDIO_COMMAND_DELAY);
+            if self.get_reg32(IXGBE_MSCA) & IXGBE_MSCA_MDI_COMMAND == 0 {
+                return Ok(());
+            }
         }
 
-        // required when not using DCB/VTd
-        self.set_reg32(IXGBE_DTXMXSZRQ, 0xffff);
-        self.clear_flags32(IXGBE_RTTDCS, IXGBE_RTTDCS_ARBDIS);
+        Err(IxyError::InvalidConfiguration(format!(
+            "mdio command {:#x} of {} timed out",
+            op_code, self.pci_addr
+        )))
+    }
 
-        // configure queues
-        for i in 0..self.num_tx_queues {
-            debug!("initializing tx queue {}", i);
-            // section 7.1.9 - setup descriptor ring
-            let ring_size_bytes =
-                NUM_TX_QUEUE_ENTRIES as usize * mem::size_of::<ixgbe_adv_tx_desc>();
+    /// Reads the byte at `offset` of the i2c device at `dev_addr` by bit-banging `I2CCTL`.
+    ///
+    /// Returns `None` if the bus is busy or the device does not acknowledge.
+    fn read_i2c_byte(&self, dev_addr: u8, offset: u8) -> Option<u8> {
+        let mask = self.phy_semaphore_mask();
 
-            let dma: Dma<ixgbe_adv_tx_desc> = Dma::allocate(ring_size_bytes, true)?;
-            unsafe {
-                memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
-            }
+        if !self.acquire_swfw_sync(mask) {
+            return None;
+        }
 
-            self.set_reg32(
-                IXGBE_TDBAL(u32::from(i)),
-                (dma.phys as u64 & 0xffff_ffff) as u32,
-            );
-            self.set_reg32(IXGBE_TDBAH(u32::from(i)), (dma.phys as u64 >> 32) as u32);
-            self.set_reg32(IXGBE_TDLEN(u32::from(i)), ring_size_bytes as u32);
-
-            debug!("tx ring {} phys addr: {:#x}", i, dma.phys);
-            debug!("tx ring {} virt addr: {:p}", i, dma.virt);
-
-            // descriptor writeback magic values, important to get good performance and low PCIe overhead
-            // see 7.2.3.4.1 and 7.2.3.5 for an explanation of these values and how to find good ones
-            // we just use the defaults from DPDK here, but this is a potentially interesting point for optimizations
-            let mut txdctl = self.get_reg32(IXGBE_TXDCTL(u32::from(i)));
-            // there are no defines for this in constants.rs for some reason
-            // pthresh: 6:0, hthresh: 14:8, wthresh: 22:16
-            txdctl &= !(0x3F | (0x3F << 8) | (0x3F << 16));
-            txdctl |= 36 | (8 << 8) | (4 << 16);
-
-            self.set_reg32(IXGBE_TXDCTL(u32::from(i)), txdctl);
-
-            let tx_queue = IxgbeTxQueue {
-                descriptors: dma.virt,
-                bufs_in_use: VecDeque::with_capacity(NUM_TX_QUEUE_ENTRIES),
-                pool: None,
-                num_descriptors: NUM_TX_QUEUE_ENTRIES,
-                clean_index: 0,
-                tx_index: 0,
-            };
+        let data = self.i2c_transfer(dev_addr, offset);
 
-            self.tx_queues.push(tx_queue);
+        if data.is_none() {
+            self.i2c_bus_clear();
         }
 
-        // final step: enable DMA
-        self.set_reg32(IXGBE_DMATXCTL, IXGBE_DMATXCTL_TE);
+        self.release_swfw_sync(mask);
 
-        Ok(())
+        data
     }
 
-    /// Sets the rx queues` descriptors and enables the queues.
-    fn start_rx_queue(&mut self, queue_id: u16) -> Result<(), Box<dyn Error>> {
-        debug!("starting rx queue {}", queue_id);
+    /// Writes `offset` to the i2c device at `dev_addr` and reads back one byte.
+    fn i2c_transfer(&self, dev_addr: u8, offset: u8) -> Option<u8> {
+        self.i2c_start();
 
-        {
-            let queue = &mut self.rx_queues[queue_id as usize];
+        if !self.i2c_write_byte(dev_addr) || !self.i2c_write_byte(offset) {
+            self.i2c_stop();
+            return None;
+        }
 
-            if queue.num_descriptors & (queue.num_descriptors - 1) != 0 {
-                return Err("number of queue entries must be a power of 2".into());
-            }
+        // repeated start condition to switch to reading
+        self.i2c_start();
 
-            for i in 0..queue.num_descriptors {
-                let pool = &queue.pool;
+        if !self.i2c_write_byte(dev_addr | 1) {
+            self.i2c_stop();
+            return None;
+        }
 
-                let buf = match pool.alloc_buf() {
-                    Some(x) => x,
-                    None => return Err("failed to allocate rx descriptor".into()),
-                };
+        let data = (0..8).fold(0, |data, _| (data << 1) | u8::from(self.i2c_read_bit()));
 
-                unsafe {
-                    ptr::write_volatile(
-                        &mut (*queue.descriptors.add(i)).read.pkt_addr as *mut u64,
-                        pool.get_phys_addr(buf) as u64,
-                    );
+        // nack the last byte
+        self.i2c_write_bit(true);
+        self.i2c_stop();
 
-                    ptr::write_volatile(
-                        &mut (*queue.descriptors.add(i)).read.hdr_addr as *mut u64,
-                        0,
-                    );
-                }
+        Some(data)
+    }
 
-                // we need to remember which descriptor entry belongs to which mempool entry
-                queue.bufs_in_use.push(buf);
-            }
+    /// Writes `byte` to the i2c bus and returns whether the device acknowledged it.
+    fn i2c_write_byte(&self, byte: u8) -> bool {
+        for i in (0..8).rev() {
+            self.i2c_write_bit(byte & (1 << i) != 0);
         }
 
-        let queue = &self.rx_queues[queue_id as usize];
+        // release the data line, the device acknowledges by pulling it low
+        !self.i2c_read_bit()
+    }
 
-        // enable queue and wait if necessary
-        self.set_flags32(IXGBE_RXDCTL(u32::from(queue_id)), IXGBE_RXDCTL_ENABLE);
-        self.wait_set_reg32(IXGBE_RXDCTL(u32::from(queue_id)), IXGBE_RXDCTL_ENABLE);
+    fn i2c_write_bit(&self, bit: bool) {
+        self.i2c_set_data(bit);
+        self.i2c_set_clock(true);
+        self.i2c_set_clock(false);
+    }
 
-        // rx queue starts out full
-        self.set_reg32(IXGBE_RDH(u32::from(queue_id)), 0);
+    fn i2c_read_bit(&self) -> bool {
+        self.i2c_set_data(true);
+        self.i2c_set_clock(true);
+        let bit = self.get_reg32(IXGBE_I2CCTL) & IXGBE_I2C_DATA_IN != 0;
+        self.i2c_set_clock(false);
 
-        // was set to 0 before in the init function
-        self.set_reg32(
-            IXGBE_RDT(u32::from(queue_id)),
-            (queue.num_descriptors - 1) as u32,
-        );
+        bit
+    }
 
-        Ok(())
+    /// Sends an i2c start condition, data goes low while the clock is high.
+    fn i2c_start(&self) {
+        self.i2c_set_data(true);
+        self.i2c_set_clock(true);
+        self.i2c_set_data(false);
+        self.i2c_set_clock(false);
     }
 
-    /// Enables the tx queues.
-    fn start_tx_queue(&mut self, queue_id: u16) -> Result<(), Box<dyn Error>> {
-        debug!("starting tx queue {}", queue_id);
+    /// Sends an i2c stop condition, data goes high while the clock is high.
+    fn i2c_stop(&self) {
+        self.i2c_set_data(false);
+        self.i2c_set_clock(true);
+        self.i2c_set_data(true);
+    }
 
-        {
-            let queue = &mut self.tx_queues[queue_id as usize];
+    /// Clocks out a device that is stuck in the middle of a transfer.
+    fn i2c_bus_clear(&self) {
+        self.i2c_start();
+        self.i2c_set_data(true);
 
-            if queue.num_descriptors & (queue.num_descriptors - 1) != 0 {
-                return Err("number of queue entries must be a power of 2".into());
-            }
+        for _ in 0..9 {
+            self.i2c_set_clock(true);
+            self.i2c_set_clock(false);
         }
 
-        // tx queue starts out empty
-        self.set_reg32(IXGBE_TDH(u32::from(queue_id)), 0);
-        self.set_reg32(IXGBE_TDT(u32::from(queue_id)), 0);
-
-        // enable queue and wait if necessary
-        self.set_flags32(IXGBE_TXDCTL(u32::from(queue_id)), IXGBE_TXDCTL_ENABLE);
-        self.wait_set_reg32(IXGBE_TXDCTL(u32::from(queue_id)), IXGBE_TXDCTL_ENABLE);
-
-        Ok(())
-    }
-
-    // see section 4.6.4
-    /// Initializes the link of this device.
-    fn init_link(&self) {
-        // link auto-configuration register should already be set correctly, we're resetting it anyway
-        self.set_reg32(
-            IXGBE_AUTOC,
-            (self.get_reg32(IXGBE_AUTOC) & !IXGBE_AUTOC_LMS_MASK) | IXGBE_AUTOC_LMS_10G_SERIAL,
-        );
-        self.set_reg32(
-            IXGBE_AUTOC,
-            (self.get_reg32(IXGBE_AUTOC) & !IXGBE_AUTOC_10G_PMA_PMD_MASK) | IXGBE_AUTOC_10G_XAUI,
-        );
-        // negotiate link
-        self.set_flags32(IXGBE_AUTOC, IXGBE_AUTOC_AN_RESTART);
-        // datasheet wants us to wait for the link here, but we can continue and wait afterwards
+        self.i2c_start();
+        self.i2c_stop();
     }
 
-    /// Waits for the link to come up.
-    fn wait_for_link(&self) {
-        info!("waiting for link");
-        let time = Instant::now();
-        let mut speed = self.get_link_speed();
-        while speed == 0 && time.elapsed().as_secs() < 10 {
-            thread::sleep(Duration::from_millis(100));
-            speed = self.get_link_speed();
+    fn i2c_set_data(&self, high: bool) {
+        if high {
+            self.set_flags32(IXGBE_I2CCTL, IXGBE_I2C_DATA_OUT);
+        } else {
+            self.clear_flags32(IXGBE_I2CCTL, IXGBE_I2C_DATA_OUT);
         }
-        info!("link speed is {} Mbit/s", self.get_link_speed());
+        thread::sleep(I2C_BIT_DELAY);
     }
 
-    /// Enables or disables promisc mode of this device.
-    fn set_promisc(&self, enabled: bool) {
-        if enabled {
-            info!("enabling promisc mode");
-            self.set_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE | IXGBE_FCTRL_UPE);
+    fn i2c_set_clock(&self, high: bool) {
+        if high {
+            self.set_flags32(IXGBE_I2CCTL, IXGBE_I2C_CLK_OUT);
+
+            // the device may stretch the clock by holding it low
+            for _ in 0..IXGBE_I2C_CLOCK_STRETCHING_TIMEOUT {
+                if self.get_reg32(IXGBE_I2CCTL) & IXGBE_I2C_CLK_IN != 0 {
+                    break;
+                }
+                thread::sleep(Duration::from_micros(1));
+            }
         } else {
-            info!("disabling promisc mode");
-            self.clear_flags32(IXGBE_FCTRL, IXGBE_FCTRL_MPE | IXGBE_FCTRL_UPE);
+            self.clear_flags32(IXGBE_I2CCTL, IXGBE_I2C_CLK_OUT);
         }
+        thread::sleep(I2C_BIT_DELAY);
     }
 
     /// Returns the register at `self.addr` + `reg`.
@@ -698,12 +4258,14 @@ impl IxgbeDevice {
     ///
     /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
     fn get_reg32(&self, reg: u32) -> u32 {
-        assert!(
-            reg as usize <= self.len - 4 as usize,
-            "memory access out of bounds"
-        );
-
-        unsafe { ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32) }
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
     }
 
     /// Sets the register at `self.addr` + `reg` to `value`.
@@ -712,13 +4274,17 @@ impl IxgbeDevice {
     ///
     /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
     fn set_reg32(&self, reg: u32, value: u32) {
-        assert!(
-            reg as usize <= self.len - 4 as usize,
-            "memory access out of bounds"
-        );
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
 
         unsafe {
-            ptr::write_volatile((self.addr as usize + reg as usize) as *mut u32, value);
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
         }
     }
 
@@ -736,7 +4302,7 @@ impl IxgbeDevice {
     fn wait_clear_reg32(&self, reg: u32, value: u32) {
         loop {
             let current = self.get_reg32(reg);
-            if (current & value) == 0 {
+            if (current & value) == 0 || self.removal.is_removed() {
                 break;
             }
             thread::sleep(Duration::from_millis(100));
@@ -755,6 +4321,147 @@ impl IxgbeDevice {
     }
 }
 
+/// Returns whether the flow rules `a` and `b` use the same masks, i.e. whether both can be
+/// programmed into the flow director at the same time.
+fn fdir_masks_equal(a: &FlowRule, b: &FlowRule) -> bool {
+    (a.protocol == FlowProtocol::Other) == (b.protocol == FlowProtocol::Other)
+        && a.src_ip_mask == b.src_ip_mask
+        && a.dst_ip_mask == b.dst_ip_mask
+        && a.src_port_mask == b.src_port_mask
+        && a.dst_port_mask == b.dst_port_mask
+}
+
+/// Returns the flow type, the source and destination address and the ports of `rule` after
+/// applying its masks, in the layout of the flow director registers.
+fn fdir_input(rule: &FlowRule) -> (u32, u32, u32, u32) {
+    let flow_type = match rule.protocol {
+        FlowProtocol::Tcp => IXGBE_ATR_L4TYPE_TCP,
+        FlowProtocol::Udp => IXGBE_ATR_L4TYPE_UDP,
+        FlowProtocol::Sctp => IXGBE_ATR_L4TYPE_SCTP,
+        FlowProtocol::Other => 0,
+    };
+    let src_ip = u32::from(rule.src_ip) & u32::from(rule.src_ip_mask);
+    let dst_ip = u32::from(rule.dst_ip) & u32::from(rule.dst_ip_mask);
+    let ports = (u32::from(rule.dst_port & rule.dst_port_mask) << IXGBE_FDIRPORT_DESTINATION_SHIFT)
+        | u32::from(rule.src_port & rule.src_port_mask);
+
+    (flow_type, src_ip, dst_ip, ports)
+}
+
+/// Returns the bucket hash of `rule` the flow director stores perfect match filters under,
+/// computed like `ixgbe_atr_compute_perfect_hash_82599` of the linux driver.
+fn fdir_bucket_hash(rule: &FlowRule) -> u32 {
+    let (flow_type, src_ip, dst_ip, ports) = fdir_input(rule);
+
+    // the hash input is the big endian dword stream of the filter: flow type, vm pool and vlan
+    // followed by the addresses and ports, the source port comes first in the stream
+    let flow_vm_vlan = flow_type << 16;
+    let ports = ports.rotate_left(16);
+
+    let mut hi_hash_dword = dst_ip ^ src_ip ^ ports;
+    let mut lo_hash_dword = hi_hash_dword.rotate_left(16);
+    hi_hash_dword ^= flow_vm_vlan ^ (flow_vm_vlan >> 16);
+
+    let mut bucket_hash = 0;
+    let mut iterate = |n: u32, lo: u32, hi: u32| {
+        if IXGBE_ATR_BUCKET_HASH_KEY & (1 << n) != 0 {
+            bucket_hash ^= lo >> n;
+        }
+        if IXGBE_ATR_BUCKET_HASH_KEY & (1 << (n + 16)) != 0 {
+            bucket_hash ^= hi >> n;
+        }
+    };
+
+    // bit 0 is processed before the flow type is applied to the low dword
+    iterate(0, lo_hash_dword, hi_hash_dword);
+    lo_hash_dword ^= flow_vm_vlan ^ (flow_vm_vlan << 16);
+    for n in 1..16 {
+        iterate(n, lo_hash_dword, hi_hash_dword);
+    }
+
+    // the table has at most 8K buckets
+    bucket_hash & 0x1fff
+}
+
+/// Returns the amount of dma memory needed for the descriptor rings and mempools of the given
+/// number of queues, every allocation occupies whole huge pages.
+pub(crate) fn dma_size(num_rx_queues: u16, num_tx_queues: u16) -> usize {
+    let rx_ring = (NUM_RX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_rx_desc>())
+        .next_multiple_of(HUGE_PAGE_SIZE);
+    let tx_ring = (NUM_TX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_tx_desc>())
+        .next_multiple_of(HUGE_PAGE_SIZE);
+    let rx_mempool = (RX_MEMPOOL_SIZE * PKT_BUF_ENTRY_SIZE).next_multiple_of(HUGE_PAGE_SIZE);
+
+    usize::from(num_rx_queues) * (rx_ring + rx_mempool) + usize::from(num_tx_queues) * tx_ring
+}
+
+/// Allocates contiguous dma memory with `allocator`, or on `numa_node` if [`None`] and the node
+/// is known.
+pub(crate) fn allocate_dma<T>(
+    allocator: Option<&Arc<dyn DmaAllocator>>,
+    numa_node: Option<u32>,
+    size: usize,
+) -> Result<Dma<T>, IxyError> {
+    if let Some(allocator) = allocator {
+        return Ok(allocator.allocate(size, true)?.cast());
+    }
+
+    if let Some(node) = numa_node {
+        match Dma::allocate_on_node(size, true, node) {
+            Ok(dma) => return Ok(dma),
+            Err(e) => warn!("cannot allocate dma memory on numa node {}: {}", node, e),
+        }
+    }
+
+    Dma::allocate(size, true)
+}
+
+/// Allocates an rx mempool of `entry_size` byte buffers with `allocator`, or on `numa_node` if
+/// [`None`] and the node is known.
+pub(crate) fn allocate_mempool(
+    allocator: Option<&Arc<dyn DmaAllocator>>,
+    numa_node: Option<u32>,
+    entry_size: usize,
+) -> Result<Arc<Mempool>, IxyError> {
+    if let Some(allocator) = allocator {
+        return Mempool::allocate_in(&**allocator, RX_MEMPOOL_SIZE, entry_size);
+    }
+
+    if let Some(node) = numa_node {
+        match Mempool::allocate_on_node(RX_MEMPOOL_SIZE, entry_size, node) {
+            Ok(pool) => return Ok(pool),
+            Err(e) => warn!("cannot allocate mempool on numa node {}: {}", node, e),
+        }
+    }
+
+    Mempool::allocate(RX_MEMPOOL_SIZE, entry_size)
+}
+
+/// Returns the descriptor control register value `dctl` with the prefetch, host and write-back
+/// thresholds replaced.
+pub(crate) fn desc_thresholds(
+    dctl: u32,
+    pthresh: u8,
+    hthresh: u8,
+    wthresh: u8,
+) -> Result<u32, IxyError> {
+    if pthresh > DESC_THRESH_MAX || hthresh > DESC_THRESH_MAX || wthresh > DESC_THRESH_MAX {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "descriptor thresholds must not exceed {}",
+            DESC_THRESH_MAX
+        )));
+    }
+
+    // there are no defines for this in constants.rs for some reason
+    // pthresh: 6:0, hthresh: 14:8, wthresh: 22:16
+    let mask = u32::from(DESC_THRESH_MAX);
+    let mut dctl = dctl;
+    dctl &= !(mask | (mask << 8) | (mask << 16));
+    dctl |= u32::from(pthresh) | (u32::from(hthresh) << 8) | (u32::from(wthresh) << 16);
+
+    Ok(dctl)
+}
+
 /// Removes multiples of `TX_CLEAN_BATCH` packets from `queue`.
 fn clean_tx_queue(queue: &mut IxgbeTxQueue) -> usize {
     let mut clean_index = queue.clean_index;
@@ -777,21 +4484,38 @@ fn clean_tx_queue(queue: &mut IxgbeTxQueue) -> usize {
             cleanup_to -= queue.num_descriptors;
         }
 
+        // context descriptors report no status, the data descriptor following them does
+        let status_index = match queue.bufs_in_use.get(TX_CLEAN_BATCH - 1) {
+            Some(TxBuffer::Context) => wrap_ring(cleanup_to, queue.num_descriptors),
+            _ => cleanup_to,
+        };
+
         let status = unsafe {
-            ptr::read_volatile(&(*queue.descriptors.add(cleanup_to)).wb.status as *const u32)
+            u32::from_le(ptr::read_volatile(
+                &(*queue.descriptors.add(status_index)).wb.status,
+            ))
         };
 
         if (status & IXGBE_ADVTXD_STAT_DD) != 0 {
-            if let Some(ref p) = queue.pool {
-                if TX_CLEAN_BATCH as usize >= queue.bufs_in_use.len() {
-                    p.free_stack
-                        .borrow_mut()
-                        .extend(queue.bufs_in_use.drain(..))
-                } else {
-                    p.free_stack
-                        .borrow_mut()
-                        .extend(queue.bufs_in_use.drain(..TX_CLEAN_BATCH))
-                }
+            let batch = TX_CLEAN_BATCH.min(queue.bufs_in_use.len());
+            let completed_external = &mut queue.completed_external;
+
+            let entries = queue
+                .bufs_in_use
+                .drain(..batch)
+                .filter_map(|buf| match buf {
+                    TxBuffer::Pool(entry) => Some(entry),
+                    TxBuffer::External(phys_addr) => {
+                        completed_external.push_back(phys_addr);
+                        None
+                    }
+                    TxBuffer::Context => None,
+                });
+
+            match queue.pool {
+                Some(ref pool) => pool.free_bufs(entries),
+                // external buffers still have to be completed
+                None => entries.for_each(drop),
             }
 
             clean_index = wrap_ring(cleanup_to, queue.num_descriptors);
@@ -804,3 +4528,190 @@ fn clean_tx_queue(queue: &mut IxgbeTxQueue) -> usize {
 
     clean_index
 }
+
+/// Returns all buffers of `queue` that have not been cleaned up yet, as if they were sent.
+fn release_tx_buffers(queue: &mut IxgbeTxQueue) {
+    for buf in queue.bufs_in_use.drain(..) {
+        match buf {
+            TxBuffer::Pool(entry) => {
+                if let Some(ref pool) = queue.pool {
+                    pool.free_buf(entry);
+                }
+            }
+            TxBuffer::External(phys_addr) => queue.completed_external.push_back(phys_addr),
+            TxBuffer::Context => {}
+        }
+    }
+}
+
+/// Collects the descriptors of a tx batch and writes them to the ring a cache line at a time.
+///
+/// Descriptors are staged until their cache line is complete, full lines are written at once
+/// instead of field by field. Partial lines are written when the batch is flushed.
+struct TxDescWriter {
+    descriptors: *mut ixgbe_adv_tx_desc,
+    num_descriptors: usize,
+    // ring index of the first staged descriptor
+    index: usize,
+    staged: [ixgbe_adv_tx_desc; TX_DESCS_PER_CACHE_LINE],
+    num_staged: usize,
+}
+
+impl TxDescWriter {
+    /// Returns a writer that starts at `index` of the ring `descriptors`.
+    fn new(descriptors: *mut ixgbe_adv_tx_desc, num_descriptors: usize, index: usize) -> Self {
+        TxDescWriter {
+            descriptors,
+            num_descriptors,
+            index,
+            staged: [ixgbe_adv_tx_desc {
+                read: ixgbe_adv_tx_desc_read {
+                    buffer_addr: 0,
+                    cmd_type_len: 0,
+                    olinfo_status: 0,
+                },
+            }; TX_DESCS_PER_CACHE_LINE],
+            num_staged: 0,
+        }
+    }
+
+    /// Stages `desc` for the next index of the ring, writes the cache line once it's complete.
+    unsafe fn push(&mut self, desc: ixgbe_adv_tx_desc) {
+        self.staged[self.num_staged] = desc;
+        self.num_staged += 1;
+
+        // the ring size is a multiple of the line size, lines never wrap around the ring
+        if (self.index + self.num_staged).is_multiple_of(TX_DESCS_PER_CACHE_LINE) {
+            self.flush();
+        }
+    }
+
+    /// Writes all staged descriptors to the ring.
+    unsafe fn flush(&mut self) {
+        let dst = self.descriptors.add(self.index);
+
+        if self.num_staged == TX_DESCS_PER_CACHE_LINE {
+            ptr::write_volatile(
+                dst as *mut [ixgbe_adv_tx_desc; TX_DESCS_PER_CACHE_LINE],
+                self.staged,
+            );
+        } else {
+            for (i, desc) in self.staged[..self.num_staged].iter().enumerate() {
+                ptr::write_volatile(dst.add(i), *desc);
+            }
+        }
+
+        self.index = (self.index + self.num_staged) & (self.num_descriptors - 1);
+        self.num_staged = 0;
+    }
+}
+
+/// Returns a data descriptor for the `len` bytes at `phys_addr`.
+///
+/// `packet_len` is the length of the whole packet the buffer belongs to, `end_of_packet` marks
+/// its last buffer. `offload` holds the flags of the packet's offloads.
+fn tx_data_desc(
+    phys_addr: usize,
+    len: usize,
+    packet_len: usize,
+    end_of_packet: bool,
+    offload: &TxOffload,
+) -> ixgbe_adv_tx_desc {
+    // every descriptor reports its status, clean_tx_queue checks arbitrary descriptors
+    let mut cmd_type_len = IXGBE_ADVTXD_DCMD_RS
+        | IXGBE_ADVTXD_DCMD_IFCS
+        | IXGBE_ADVTXD_DCMD_DEXT
+        | IXGBE_ADVTXD_DTYP_DATA
+        | offload.cmd_type_len
+        | len as u32;
+
+    if end_of_packet {
+        cmd_type_len |= IXGBE_ADVTXD_DCMD_EOP;
+    }
+
+    ixgbe_adv_tx_desc {
+        read: ixgbe_adv_tx_desc_read {
+            buffer_addr: (phys_addr as u64).to_le(),
+            cmd_type_len: cmd_type_len.to_le(),
+            olinfo_status: ((((packet_len - offload.header_len) as u32)
+                << IXGBE_ADVTXD_PAYLEN_SHIFT)
+                | offload.olinfo_status)
+                .to_le(),
+        },
+    }
+}
+
+/// Returns a context descriptor with the fields of `offload`, it applies to the packet whose data
+/// descriptors follow it.
+fn tx_context_desc(offload: &TxOffload) -> ixgbe_adv_tx_desc {
+    let desc = ixgbe_adv_tx_context_desc {
+        vlan_macip_lens: offload.vlan_macip_lens.to_le(),
+        seqnum_seed: 0,
+        type_tucmd_mlhl: (IXGBE_TXD_CMD_DEXT | IXGBE_ADVTXD_DTYP_CTXT | offload.type_tucmd_mlhl)
+            .to_le(),
+        mss_l4len_idx: offload.mss_l4len_idx.to_le(),
+    };
+
+    // both descriptor layouts are 16 bytes of plain integers
+    unsafe { mem::transmute::<ixgbe_adv_tx_context_desc, ixgbe_adv_tx_desc>(desc) }
+}
+
+/// Returns the metadata the device wrote back to the first rx descriptor of a packet at `index`
+/// of `queue`, with `status` of its last descriptor at `eop_index`.
+unsafe fn rx_desc_meta(
+    queue: &IxgbeRxQueue,
+    index: usize,
+    eop_index: usize,
+    status: u32,
+) -> PacketMeta {
+    let lower = ptr::read_volatile(&(*queue.descriptors.add(index)).wb.lower);
+    let pkt_info = u32::from_le(lower.lo_dword.data);
+
+    // section 7.1.6.2 - the stripped tag is only valid in the last descriptor
+    let vlan_tci = if queue.vlan_strip && (status & IXGBE_RXDADV_STAT_VP) != 0 {
+        Some(u16::from_le(ptr::read_volatile(
+            &(*queue.descriptors.add(eop_index)).wb.upper.vlan,
+        )))
+    } else {
+        None
+    };
+
+    // the checksums are only valid if the device calculated them
+    let l3_checksum_ok = if (status & IXGBE_RXD_STAT_IPCS) != 0 {
+        Some((status & IXGBE_RXDADV_ERR_IPE) == 0)
+    } else {
+        None
+    };
+
+    // the 82599 reports UDP packets without a checksum as errors, like the linux driver we
+    // don't trust its verdict on UDP packets
+    let l4_checksum_ok = if (status & IXGBE_RXD_STAT_L4CS) == 0 {
+        None
+    } else if (status & IXGBE_RXDADV_ERR_TCPE) == 0 {
+        Some(true)
+    } else if (pkt_info & IXGBE_RXDADV_PKTTYPE_UDP) != 0 {
+        None
+    } else {
+        Some(false)
+    };
+
+    PacketMeta {
+        rss_hash: match pkt_info & IXGBE_RXDADV_RSSTYPE_MASK {
+            IXGBE_RXDADV_RSSTYPE_NONE => None,
+            _ => Some(u32::from_le(lower.hi_dword.rss)),
+        },
+        packet_type: pkt_info & IXGBE_RXDADV_PKTTYPE_MASK,
+        offload_flags: status,
+        vlan_tci,
+        l3_checksum_ok,
+        l4_checksum_ok,
+        ..PacketMeta::default()
+    }
+}
+
+/// Returns the status of the rx descriptor at `index` of `queue`.
+unsafe fn rx_desc_status(queue: &IxgbeRxQueue, index: usize) -> u32 {
+    u32::from_le(ptr::read_volatile(
+        &(*queue.descriptors.add(index)).wb.upper.status_error,
+    ))
+}
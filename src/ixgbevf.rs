@@ -0,0 +1,1422 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constants::*;
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, desc_thresholds, dma_size, IxgbeRxQueue, IxgbeTxQueue,
+    MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES, NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE,
+    TX_DRAIN_TIMEOUT, TX_HTHRESH, TX_PTHRESH, TX_WTHRESH,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-ixgbevf";
+
+// virtual functions of the 82599 and its successors X540 and X550, the Hyper-V variants talk
+// to their physical function via the hypervisor instead of the mailbox
+const SUPPORTED_DEVICE_IDS: [u32; 5] = [
+    IXGBE_DEV_ID_82599_VF,
+    IXGBE_DEV_ID_X540_VF,
+    IXGBE_DEV_ID_X550_VF,
+    IXGBE_DEV_ID_X550EM_X_VF,
+    IXGBE_DEV_ID_X550EM_A_VF,
+];
+
+// the physical function answers requests from its interrupt handler or poll loop
+const MBX_TIMEOUT: Duration = Duration::from_secs(1);
+const MBX_POLL_DELAY: Duration = Duration::from_micros(500);
+const MBX_LOCK_TRIES: usize = 10;
+
+// reset done is signaled within IXGBE_VF_INIT_TIMEOUT polls
+const RESET_POLL_DELAY: Duration = Duration::from_micros(5);
+
+// the octet counters of a virtual function are 36 bits wide
+const OCTET_COUNTER_MASK: u64 = (1 << 36) - 1;
+
+/// Driver for the virtual functions of the 82599 and its successors, see section 7.10 and the
+/// 82599 Virtual Function specification.
+///
+/// A virtual function only owns its queues, everything else is configured by the driver of
+/// its physical function on request via the mailbox.
+pub struct IxgbeVfDevice {
+    pci_addr: String,
+    addr: *mut u8,
+    len: usize,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<IxgbeRxQueue>,
+    tx_queues: Vec<IxgbeTxQueue>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    mac: Cell<[u8; 6]>,
+    api_version: u32,
+    // queues the physical function grants this virtual function
+    max_rx_queues: u16,
+    max_tx_queues: u16,
+    // read-to-clear bits of the mailbox register that were read but not handled yet
+    mailbox_bits: Cell<u32>,
+    // the counters of a virtual function are not cleared on read
+    counters: Cell<VfCounters>,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// Values of the statistic counters of a virtual function at their last read.
+#[derive(Clone, Copy, Default)]
+struct VfCounters {
+    rx_pkts: u32,
+    tx_pkts: u32,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_multicast: u32,
+}
+
+impl IxyDevice for IxgbeVfDevice {
+    /// Returns an initialized `IxgbeVfDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<IxgbeVfDevice, IxyError> {
+        IxgbeVfDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address the physical function assigned to this virtual function.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac.get()
+    }
+
+    /// Asks the physical function to set the mac address of this virtual function, it may
+    /// refuse if the address was assigned by the host.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        let mut msg = [
+            IXGBE_VF_SET_MAC_ADDR,
+            u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+            u32::from_le_bytes([mac[4], mac[5], 0, 0]),
+        ];
+
+        match self.request(&mut msg) {
+            Ok(true) => self.mac.set(mac),
+            Ok(false) => warn!(
+                "physical function of {} refused mac address {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+                self.pci_addr, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+            ),
+            Err(e) => warn!("failed to set mac address of {}: {}", self.pci_addr, e),
+        }
+    }
+
+    /// Virtual functions are a VMDq pool themselves.
+    fn set_mac_addr_pool(&self, _mac: [u8; 6], _pool: u8) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(
+            "virtual functions have no VMDq pools".to_string(),
+        ))
+    }
+
+    /// The receive address registers belong to the physical function.
+    fn add_unicast_filter(&self, _mac: [u8; 6]) -> Result<usize, IxyError> {
+        Err(IxyError::InvalidConfiguration(
+            "unicast filters of virtual functions are not supported".to_string(),
+        ))
+    }
+
+    /// The receive address registers belong to the physical function.
+    fn remove_unicast_filter(&self, _index: usize) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(
+            "unicast filters of virtual functions are not supported".to_string(),
+        ))
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        // virtual functions can't read the rx timestamp registers
+        let batch = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if let Some(tail) = batch.tail {
+            self.set_reg32(IXGBE_VFRDT(queue_id), tail as u32);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            batch.received,
+            num_packets
+        );
+
+        batch.received
+    }
+
+    /// Returns whether the device was removed, i.e. its status register reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(IXGBE_VFSTATUS) == IXGBE_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(tail) = self.rx_queues[queue_id as usize].set_posted(queue_id, num_posted)? {
+            self.set_reg32(IXGBE_VFRDT(queue_id), tail as u32);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted()
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_reg32(
+                IXGBE_VFTDT(queue_id),
+                self.tx_queues[queue_id as usize].tail() as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this virtual function into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_pkts += u64::from(current.rx_pkts.wrapping_sub(last.rx_pkts));
+        stats.tx_pkts += u64::from(current.tx_pkts.wrapping_sub(last.tx_pkts));
+        stats.rx_bytes += current.rx_bytes.wrapping_sub(last.rx_bytes) & OCTET_COUNTER_MASK;
+        stats.tx_bytes += current.tx_bytes.wrapping_sub(last.tx_bytes) & OCTET_COUNTER_MASK;
+
+        // the multicast counter belongs to the extended stats
+        self.counters.set(VfCounters {
+            rx_multicast: last.rx_multicast,
+            ..current
+        });
+    }
+
+    /// Resets the stats of this virtual function.
+    fn reset_stats(&self) {
+        self.counters.set(self.read_counters());
+    }
+
+    /// Reads the extended stats of this virtual function into `stats`, only the multicast
+    /// packets are counted per virtual function.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        let last = self.counters.get();
+        let rx_multicast = self.get_reg32(IXGBE_VFMPRC);
+
+        stats.rx_multicast += u64::from(rx_multicast.wrapping_sub(last.rx_multicast));
+
+        self.counters.set(VfCounters {
+            rx_multicast,
+            ..last
+        });
+    }
+
+    /// Returns the link speed of the port of this virtual function.
+    fn get_link_speed(&self) -> u32 {
+        let speed = self.get_reg32(IXGBE_VFLINKS);
+        if (speed & IXGBE_LINKS_UP) == 0 {
+            return 0;
+        }
+        match speed & IXGBE_LINKS_SPEED_82599 {
+            IXGBE_LINKS_SPEED_100_82599 => 100,
+            IXGBE_LINKS_SPEED_1G_82599 => 1000,
+            IXGBE_LINKS_SPEED_10G_82599 => 10000,
+            _ => 0,
+        }
+    }
+
+    /// Returns the PCIe link of this device.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device without resetting it.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        self.check_granted_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        // remove queues from the top, queues below the new counts are left untouched
+        while self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues -= 1;
+            self.stop_rx_queue(self.num_rx_queues);
+            self.rx_queues.pop();
+        }
+
+        while self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues -= 1;
+            self.stop_tx_queue(self.num_tx_queues);
+
+            // packets still in the ring will never be sent, return them to their pool
+            if let Some(mut queue) = self.tx_queues.pop() {
+                queue.reset();
+            }
+        }
+
+        while self.num_rx_queues < num_rx_queues {
+            self.init_rx_queue(self.num_rx_queues)?;
+            self.write_rx_drop_enable(self.num_rx_queues, num_rx_queues > 1);
+            self.start_rx_queue(self.num_rx_queues)?;
+            self.num_rx_queues += 1;
+        }
+
+        while self.num_tx_queues < num_tx_queues {
+            self.init_tx_queue(self.num_tx_queues)?;
+            self.start_tx_queue(self.num_tx_queues);
+            self.num_tx_queues += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Enables rx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].is_drained() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        debug!("enabling rx queue {}", queue_id);
+
+        self.set_flags32(IXGBE_VFRXDCTL(queue_id), IXGBE_RXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_VFRXDCTL(queue_id), IXGBE_RXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues)
+            && (self.get_reg32(IXGBE_VFRXDCTL(queue_id)) & IXGBE_RXDCTL_ENABLE) != 0
+    }
+
+    /// Enables tx queue `queue_id` and waits until the device acknowledges it.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        debug!("enabling tx queue {}", queue_id);
+
+        self.set_flags32(IXGBE_VFTXDCTL(queue_id), IXGBE_TXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_VFTXDCTL(queue_id), IXGBE_TXDCTL_ENABLE);
+
+        Ok(())
+    }
+
+    /// Disables tx queue `queue_id` and waits until the device acknowledges it.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.stop_tx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+            && (self.get_reg32(IXGBE_VFTXDCTL(queue_id)) & IXGBE_TXDCTL_ENABLE) != 0
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or its head moved within `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let head = self.get_reg32(IXGBE_VFTDH(queue_id)) as usize;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(head, timeout))
+    }
+
+    /// Disables tx queue `queue_id`, drops all pending packets and enables it again.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop_tx_queue(queue_id as u16);
+        self.tx_queues[queue_id as usize].reset();
+        self.start_tx_queue(queue_id as u16);
+
+        Ok(())
+    }
+
+    /// Disables rx queue `queue_id` and returns all buffers of its ring to the mempool.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.stop_rx_queue(queue_id as u16);
+        self.rx_queues[queue_id as usize].drain();
+
+        Ok(())
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the head catches up with the tail once all descriptors have been processed
+        while self.get_reg32(IXGBE_VFTDH(queue_id)) as usize
+            != self.tx_queues[queue_id as usize].tail()
+        {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.tx_queues[queue_id as usize].release_sent();
+
+        Ok(())
+    }
+
+    /// Sets whether rx queue `queue_id` drops packets when its ring is full.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.write_rx_drop_enable(queue_id as u16, enable);
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of rx queue `queue_id`.
+    fn set_rx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let reg = IXGBE_VFRXDCTL(queue_id);
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Sets the descriptor thresholds of tx queue `queue_id`.
+    fn set_tx_thresholds(
+        &self,
+        queue_id: u32,
+        pthresh: u8,
+        hthresh: u8,
+        wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let reg = IXGBE_VFTXDCTL(queue_id);
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), pthresh, hthresh, wthresh)?,
+        );
+
+        Ok(())
+    }
+
+    /// Flow control is a setting of the port, only its physical function can change it.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "ignoring flow control mode {:?} of {}: it is set by the physical function",
+            mode, self.pci_addr
+        );
+    }
+
+    /// Returns [`FlowControl::None`], a virtual function can't see the mode of its port.
+    fn get_flow_control(&self) -> FlowControl {
+        FlowControl::None
+    }
+
+    /// CRC stripping is a setting of the port, only its physical function can change it.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot keep the crc on {}: stripping is set by the physical function",
+                self.pci_addr
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, physical function drivers strip the CRC for their virtual functions.
+    fn get_crc_strip(&self) -> bool {
+        true
+    }
+
+    /// Drains rx queue `queue_id` and restarts it with a new mempool of `buffer_size` bytes.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        // the device's buffer size has a granularity of 1 KiB
+        let buffer_size = buffer_size.next_multiple_of(1 << IXGBE_SRRCTL_BSIZEPKT_SHIFT);
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        // jumbo frames have to be enabled by the physical function
+        if buffer_size > PKT_BUF_ENTRY_SIZE {
+            let mut msg = [IXGBE_VF_SET_LPE, buffer_size as u32];
+            if !self.request(&mut msg)? {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "physical function of {} refused frames of {} bytes",
+                    self.pci_addr, buffer_size
+                )));
+            }
+        }
+
+        self.drain_rx_queue(queue_id)?;
+        self.rx_queues[queue_id as usize].set_pool(pool);
+        self.write_rx_buffer_size(queue_id as u16);
+
+        self.start_rx_queue(queue_id as u16)
+    }
+
+    /// Virtual functions have no access to the EEPROM.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// Virtual functions have no access to the thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the global, per-queue and stats registers of this virtual function.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("VFCTRL", IXGBE_VFCTRL),
+            ("VFSTATUS", IXGBE_VFSTATUS),
+            ("VFLINKS", IXGBE_VFLINKS),
+            ("VTEIMS", IXGBE_VTEIMS),
+            ("VFPSRTYPE", IXGBE_VFPSRTYPE),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        // reading the mailbox clears some of its bits, keep them for the mailbox protocol
+        dump.push("VFMAILBOX", self.read_mailbox_reg());
+
+        for i in 0..u32::from(self.num_rx_queues) {
+            let queue = [
+                ("VFRDBAL", IXGBE_VFRDBAL(i)),
+                ("VFRDBAH", IXGBE_VFRDBAH(i)),
+                ("VFRDLEN", IXGBE_VFRDLEN(i)),
+                ("VFRDH", IXGBE_VFRDH(i)),
+                ("VFRDT", IXGBE_VFRDT(i)),
+                ("VFRXDCTL", IXGBE_VFRXDCTL(i)),
+                ("VFSRRCTL", IXGBE_VFSRRCTL(i)),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg));
+            }
+        }
+
+        for i in 0..u32::from(self.num_tx_queues) {
+            let queue = [
+                ("VFTDBAL", IXGBE_VFTDBAL(i)),
+                ("VFTDBAH", IXGBE_VFTDBAH(i)),
+                ("VFTDLEN", IXGBE_VFTDLEN(i)),
+                ("VFTDH", IXGBE_VFTDH(i)),
+                ("VFTDT", IXGBE_VFTDT(i)),
+                ("VFTXDCTL", IXGBE_VFTXDCTL(i)),
+            ];
+
+            for &(name, reg) in queue.iter() {
+                dump.push(format!("{}[{}]", name, i), self.get_reg32(reg));
+            }
+        }
+
+        // unlike the counters of the physical function these are not cleared on read
+        let stats = [
+            ("VFGPRC", IXGBE_VFGPRC),
+            ("VFGPTC", IXGBE_VFGPTC),
+            ("VFGORC_LSB", IXGBE_VFGORC_LSB),
+            ("VFGORC_MSB", IXGBE_VFGORC_MSB),
+            ("VFGOTC_LSB", IXGBE_VFGOTC_LSB),
+            ("VFGOTC_MSB", IXGBE_VFGOTC_MSB),
+            ("VFMPRC", IXGBE_VFMPRC),
+        ];
+
+        for &(name, reg) in stats.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.reset_and_init()
+    }
+
+    /// Virtual functions can't have virtual functions of their own.
+    fn enable_sriov(&mut self, _num_vfs: u16) -> Result<Vec<String>, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} is a virtual function itself",
+            self.pci_addr
+        )))
+    }
+
+    fn disable_sriov(&mut self) -> Result<(), IxyError> {
+        Ok(())
+    }
+
+    fn get_vfs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Virtual functions can't have virtual functions of their own.
+    fn set_vf_mac(&mut self, _vf: u16, _mac: [u8; 6]) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} is a virtual function itself",
+            self.pci_addr
+        )))
+    }
+
+    /// Virtual functions can't have virtual functions of their own.
+    fn set_vf_vlan(&mut self, _vf: u16, _vlan: Option<u16>) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} is a virtual function itself",
+            self.pci_addr
+        )))
+    }
+
+    fn process_vf_mailbox(&mut self) -> Result<usize, IxyError> {
+        Ok(0)
+    }
+}
+
+impl Drop for IxgbeVfDevice {
+    fn drop(&mut self) {
+        // stop all dma before the descriptor rings and mempools are unmapped
+        self.set_reg32(IXGBE_VTEIMC, IXGBE_VF_IRQ_CLEAR_MASK);
+        self.set_reg32(IXGBE_VFCTRL, IXGBE_CTRL_RST);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+impl IxgbeVfDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        u32::from(vendor_id) == IXGBE_INTEL_VENDOR_ID
+            && SUPPORTED_DEVICE_IDS.contains(&u32::from(device_id))
+    }
+
+    /// Returns an initialized `IxgbeVfDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<IxgbeVfDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory
+        check_privileges(pci_addr, vfio, dma_size(num_rx_queues, num_tx_queues))?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?
+        } else {
+            pci_map_resource(pci_addr)?
+        };
+
+        let mut dev = IxgbeVfDevice {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            mac: Cell::new([0; 6]),
+            api_version: IXGBE_MBOX_API_10,
+            max_rx_queues: 1,
+            max_tx_queues: 1,
+            mailbox_bits: Cell::new(0),
+            counters: Cell::new(VfCounters::default()),
+            removal: RemovalLatch::new(IXGBE_VFSTATUS),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets this virtual function, negotiates with its physical function and initializes
+    /// the queues.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        // the driver polls, mask all interrupts
+        self.set_reg32(IXGBE_VTEIMC, IXGBE_VF_IRQ_CLEAR_MASK);
+        self.get_reg32(IXGBE_VTEICR);
+
+        self.set_reg32(IXGBE_VFCTRL, IXGBE_CTRL_RST);
+
+        // the physical function signals the end of the reset in the mailbox
+        let mut tries = 0;
+        while !self.check_mailbox(IXGBE_VFMAILBOX_RSTD | IXGBE_VFMAILBOX_RSTI) {
+            if tries == IXGBE_VF_INIT_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "reset of {} timed out, is the driver of its physical function loaded?",
+                    self.pci_addr
+                )));
+            }
+            tries += 1;
+            thread::sleep(RESET_POLL_DELAY);
+        }
+
+        // a removed device also sets the reset bits of the mailbox
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        // the reply to the reset request carries the mac address assigned by the host
+        let mut msg = [0; IXGBE_VF_PERMADDR_MSG_LEN as usize];
+        msg[0] = IXGBE_VF_RESET;
+        if self.request(&mut msg)? {
+            let low = msg[1].to_le_bytes();
+            let high = msg[2].to_le_bytes();
+            self.mac
+                .set([low[0], low[1], low[2], low[3], high[0], high[1]]);
+        } else {
+            warn!(
+                "physical function assigned no mac address to {}, set one with set_mac_addr",
+                self.pci_addr
+            );
+        }
+
+        let mac = self.mac.get();
+        info!("initializing device {}", self.pci_addr);
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        self.negotiate_api()?;
+        self.get_queues()?;
+        self.check_granted_queues(self.num_rx_queues, self.num_tx_queues)?;
+
+        // the counters keep running across resets of the virtual function
+        self.reset_stats();
+
+        self.init_rx()?;
+        self.init_tx()?;
+
+        for i in 0..self.num_rx_queues {
+            self.start_rx_queue(i)?;
+        }
+
+        for i in 0..self.num_tx_queues {
+            self.start_tx_queue(i);
+        }
+
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Agrees on the newest mailbox API both functions speak, the physical function only
+    /// tells the queue configuration with API 1.1.
+    fn negotiate_api(&mut self) -> Result<(), IxyError> {
+        for &version in [IXGBE_MBOX_API_11, IXGBE_MBOX_API_10].iter() {
+            let mut msg = [IXGBE_VF_API_NEGOTIATE, version];
+
+            if self.request(&mut msg)? {
+                debug!(
+                    "negotiated mailbox api {} with the physical function",
+                    version
+                );
+                self.api_version = version;
+
+                return Ok(());
+            }
+        }
+
+        // physical functions that don't know the request speak API 1.0
+        self.api_version = IXGBE_MBOX_API_10;
+
+        Ok(())
+    }
+
+    /// Asks the physical function how many queues this virtual function may use.
+    fn get_queues(&mut self) -> Result<(), IxyError> {
+        if self.api_version != IXGBE_MBOX_API_11 {
+            self.max_rx_queues = 1;
+            self.max_tx_queues = 1;
+
+            return Ok(());
+        }
+
+        let mut msg = [0; 5];
+        msg[0] = IXGBE_VF_GET_QUEUES;
+        if !self.request(&mut msg)? {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "physical function of {} refused to tell the queue configuration",
+                self.pci_addr
+            )));
+        }
+
+        // like the linux driver, treat nonsense as the maximum
+        let granted = |queues: u32, max: u32| {
+            if queues == 0 || queues > max {
+                max as u16
+            } else {
+                queues as u16
+            }
+        };
+        self.max_tx_queues = granted(msg[IXGBE_VF_TX_QUEUES as usize], IXGBE_VF_MAX_TX_QUEUES);
+        self.max_rx_queues = granted(msg[IXGBE_VF_RX_QUEUES as usize], IXGBE_VF_MAX_RX_QUEUES);
+
+        if msg[IXGBE_VF_TRANS_VLAN as usize] != 0 {
+            info!(
+                "physical function of {} enforces a port vlan",
+                self.pci_addr
+            );
+        }
+
+        debug!(
+            "physical function grants {} rx and {} tx queues",
+            self.max_rx_queues, self.max_tx_queues
+        );
+
+        Ok(())
+    }
+
+    /// Returns an error if the physical function doesn't grant the queues.
+    fn check_granted_queues(&self, num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+        if num_rx_queues > self.max_rx_queues || num_tx_queues > self.max_tx_queues {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot configure {} rx and {} tx queues: physical function of {} grants {} and {}",
+                num_rx_queues, num_tx_queues, self.pci_addr, self.max_rx_queues, self.max_tx_queues
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Initializes the rx queues of this virtual function.
+    fn init_rx(&mut self) -> Result<(), IxyError> {
+        // RQPL spreads the packets of this pool over 2^RQPL queues with the RSS hash
+        let rqpl = match self.num_rx_queues {
+            0 | 1 => 0,
+            2 | 3 => 1,
+            _ => 2,
+        };
+        self.set_reg32(IXGBE_VFPSRTYPE, rqpl << IXGBE_PSRTYPE_RQPL_SHIFT);
+
+        for i in 0..self.num_rx_queues {
+            self.init_rx_queue(i)?;
+            self.write_rx_drop_enable(i, self.num_rx_queues > 1);
+        }
+
+        Ok(())
+    }
+
+    /// Initializes the tx queues of this virtual function.
+    fn init_tx(&mut self) -> Result<(), IxyError> {
+        for i in 0..self.num_tx_queues {
+            self.init_tx_queue(i)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring and mempool of rx queue `queue_id`.
+    fn init_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+        let i = u32::from(queue_id);
+
+        self.stop_rx_queue(queue_id);
+
+        let ring_size_bytes = NUM_RX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_rx_desc>();
+
+        let dma: Dma<ixgbe_adv_rx_desc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+
+        // initialize to 0xff to prevent rogue memory accesses on premature dma activation
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(IXGBE_VFRDBAL(i), (dma.phys as u64 & 0xffff_ffff) as u32);
+        self.set_reg32(IXGBE_VFRDBAH(i), (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(IXGBE_VFRDLEN(i), ring_size_bytes as u32);
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        // set ring to empty at start
+        self.set_reg32(IXGBE_VFRDH(i), 0);
+        self.set_reg32(IXGBE_VFRDT(i), 0);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(IxgbeRxQueue::new(dma, mempool));
+
+        // enable advanced rx descriptors
+        self.set_reg32(
+            IXGBE_VFSRRCTL(i),
+            (self.get_reg32(IXGBE_VFSRRCTL(i)) & !IXGBE_SRRCTL_DESCTYPE_MASK)
+                | IXGBE_SRRCTL_DESCTYPE_ADV_ONEBUF,
+        );
+        self.write_rx_buffer_size(queue_id);
+
+        Ok(())
+    }
+
+    /// Allocates and configures the descriptor ring of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+        let i = u32::from(queue_id);
+
+        self.stop_tx_queue(queue_id);
+
+        let ring_size_bytes = NUM_TX_QUEUE_ENTRIES * mem::size_of::<ixgbe_adv_tx_desc>();
+
+        let dma: Dma<ixgbe_adv_tx_desc> =
+            allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size_bytes)?;
+        unsafe {
+            memset(dma.virt as *mut u8, ring_size_bytes, 0xff);
+        }
+
+        self.set_reg32(IXGBE_VFTDBAL(i), (dma.phys as u64 & 0xffff_ffff) as u32);
+        self.set_reg32(IXGBE_VFTDBAH(i), (dma.phys as u64 >> 32) as u32);
+        self.set_reg32(IXGBE_VFTDLEN(i), ring_size_bytes as u32);
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        // same descriptor writeback thresholds as the physical function
+        let reg = IXGBE_VFTXDCTL(i);
+        self.set_reg32(
+            reg,
+            desc_thresholds(self.get_reg32(reg), TX_PTHRESH, TX_HTHRESH, TX_WTHRESH)?,
+        );
+
+        self.tx_queues.push(IxgbeTxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Sets the rx queues` descriptors and enables the queues.
+    fn start_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("starting rx queue {}", queue_id);
+        let i = u32::from(queue_id);
+
+        let tail = self.rx_queues[queue_id as usize].fill()?;
+
+        self.set_reg32(IXGBE_VFRDH(i), 0);
+
+        // enable queue and wait if necessary
+        self.set_flags32(IXGBE_VFRXDCTL(i), IXGBE_RXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_VFRXDCTL(i), IXGBE_RXDCTL_ENABLE);
+
+        // rx queue starts out full
+        self.set_reg32(IXGBE_VFRDT(i), tail as u32);
+
+        Ok(())
+    }
+
+    /// Enables tx queue `queue_id`.
+    fn start_tx_queue(&mut self, queue_id: u16) {
+        debug!("starting tx queue {}", queue_id);
+        let i = u32::from(queue_id);
+
+        // tx queue starts out empty
+        self.set_reg32(IXGBE_VFTDH(i), 0);
+        self.set_reg32(IXGBE_VFTDT(i), 0);
+
+        // enable queue and wait if necessary
+        self.set_flags32(IXGBE_VFTXDCTL(i), IXGBE_TXDCTL_ENABLE);
+        self.wait_set_reg32(IXGBE_VFTXDCTL(i), IXGBE_TXDCTL_ENABLE);
+    }
+
+    /// Disables rx queue `queue_id`.
+    fn stop_rx_queue(&self, queue_id: u16) {
+        debug!("stopping rx queue {}", queue_id);
+
+        self.clear_flags32(IXGBE_VFRXDCTL(u32::from(queue_id)), IXGBE_RXDCTL_ENABLE);
+        self.wait_clear_reg32(IXGBE_VFRXDCTL(u32::from(queue_id)), IXGBE_RXDCTL_ENABLE);
+    }
+
+    /// Disables tx queue `queue_id`, packets that have not been sent yet stay in the ring.
+    fn stop_tx_queue(&self, queue_id: u16) {
+        debug!("stopping tx queue {}", queue_id);
+
+        self.clear_flags32(IXGBE_VFTXDCTL(u32::from(queue_id)), IXGBE_TXDCTL_ENABLE);
+        self.wait_clear_reg32(IXGBE_VFTXDCTL(u32::from(queue_id)), IXGBE_TXDCTL_ENABLE);
+    }
+
+    /// Waits for the link of the port to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Sets or clears the drop enable bit of rx queue `queue_id`.
+    fn write_rx_drop_enable(&self, queue_id: u16, enable: bool) {
+        let reg = IXGBE_VFSRRCTL(u32::from(queue_id));
+
+        if enable {
+            self.set_flags32(reg, IXGBE_SRRCTL_DROP_EN);
+        } else {
+            self.clear_flags32(reg, IXGBE_SRRCTL_DROP_EN);
+        }
+    }
+
+    /// Sets the receive buffer size of rx queue `queue_id` to the data size of its mempool's
+    /// entries, i.e. without the headroom.
+    fn write_rx_buffer_size(&self, queue_id: u16) {
+        let pool = self.rx_queues[queue_id as usize].pool();
+        let bsize = ((pool.entry_size() - pool.headroom()) >> IXGBE_SRRCTL_BSIZEPKT_SHIFT) as u32;
+        let reg = IXGBE_VFSRRCTL(u32::from(queue_id));
+
+        self.set_reg32(
+            reg,
+            (self.get_reg32(reg) & !IXGBE_SRRCTL_BSIZEPKT_MASK)
+                | bsize.min(IXGBE_SRRCTL_BSIZEPKT_MASK),
+        );
+    }
+
+    /// Returns the current values of the statistic counters.
+    fn read_counters(&self) -> VfCounters {
+        // the octet counters are split, the MSB register holds the upper 4 bits
+        let octets = |lsb, msb| {
+            u64::from(self.get_reg32(lsb)) | (u64::from(self.get_reg32(msb) & 0xf) << 32)
+        };
+
+        VfCounters {
+            rx_pkts: self.get_reg32(IXGBE_VFGPRC),
+            tx_pkts: self.get_reg32(IXGBE_VFGPTC),
+            rx_bytes: octets(IXGBE_VFGORC_LSB, IXGBE_VFGORC_MSB),
+            tx_bytes: octets(IXGBE_VFGOTC_LSB, IXGBE_VFGOTC_MSB),
+            rx_multicast: self.get_reg32(IXGBE_VFMPRC),
+        }
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Sends the request `msg` to the physical function and overwrites it with the reply.
+    /// Returns whether the physical function acknowledged the request.
+    fn request(&self, msg: &mut [u32]) -> Result<bool, IxyError> {
+        let request = msg[0];
+
+        self.write_mailbox(msg)?;
+        self.read_mailbox(msg)?;
+
+        // clear to send only tells that the reset handshake is done
+        let reply = msg[0] & !IXGBE_VT_MSGTYPE_CTS;
+        if reply == request | IXGBE_VT_MSGTYPE_ACK {
+            Ok(true)
+        } else if reply == request | IXGBE_VT_MSGTYPE_NACK {
+            Ok(false)
+        } else {
+            Err(IxyError::InvalidConfiguration(format!(
+                "physical function of {} answered request {:#x} with {:#x}",
+                self.pci_addr, request, msg[0]
+            )))
+        }
+    }
+
+    /// Writes `msg` to the mailbox and waits until the physical function acknowledges it.
+    fn write_mailbox(&self, msg: &[u32]) -> Result<(), IxyError> {
+        self.lock_mailbox()?;
+
+        // drop a stale message or acknowledgement, the request starts a new exchange
+        self.check_mailbox(IXGBE_VFMAILBOX_PFSTS);
+        self.check_mailbox(IXGBE_VFMAILBOX_PFACK);
+
+        for (i, &word) in msg.iter().enumerate() {
+            self.set_reg32(IXGBE_VFMBMEM(i as u32), word);
+        }
+
+        // hand the message to the physical function, this releases the lock
+        self.set_reg32(IXGBE_VFMAILBOX, IXGBE_VFMAILBOX_REQ);
+
+        self.poll_mailbox(IXGBE_VFMAILBOX_PFACK)
+    }
+
+    /// Waits for a message of the physical function and reads it into `msg`.
+    fn read_mailbox(&self, msg: &mut [u32]) -> Result<(), IxyError> {
+        self.poll_mailbox(IXGBE_VFMAILBOX_PFSTS)?;
+        self.lock_mailbox()?;
+
+        for (i, word) in msg.iter_mut().enumerate() {
+            *word = self.get_reg32(IXGBE_VFMBMEM(i as u32));
+        }
+
+        // acknowledge the message, this releases the lock
+        self.set_reg32(IXGBE_VFMAILBOX, IXGBE_VFMAILBOX_ACK);
+
+        Ok(())
+    }
+
+    /// Takes ownership of the mailbox memory.
+    fn lock_mailbox(&self) -> Result<(), IxyError> {
+        for _ in 0..MBX_LOCK_TRIES {
+            self.set_reg32(IXGBE_VFMAILBOX, IXGBE_VFMAILBOX_VFU);
+
+            if self.read_mailbox_reg() & IXGBE_VFMAILBOX_VFU != 0 {
+                return Ok(());
+            }
+
+            thread::sleep(MBX_POLL_DELAY);
+        }
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "cannot lock mailbox of {}: the physical function holds it",
+            self.pci_addr
+        )))
+    }
+
+    /// Waits until one of the mailbox bits in `mask` is set.
+    fn poll_mailbox(&self, mask: u32) -> Result<(), IxyError> {
+        let start = Instant::now();
+
+        while !self.check_mailbox(mask) {
+            if start.elapsed() > MBX_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "mailbox of {} timed out, is the driver of its physical function loaded?",
+                    self.pci_addr
+                )));
+            }
+            thread::sleep(MBX_POLL_DELAY);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether one of the mailbox bits in `mask` is set and consumes them.
+    fn check_mailbox(&self, mask: u32) -> bool {
+        let set = self.read_mailbox_reg() & mask != 0;
+
+        if set {
+            self.mailbox_bits.set(self.mailbox_bits.get() & !mask);
+        }
+
+        set
+    }
+
+    /// Returns the mailbox register including read-to-clear bits of earlier reads that were
+    /// not consumed yet.
+    fn read_mailbox_reg(&self) -> u32 {
+        let mailbox = self.get_reg32(IXGBE_VFMAILBOX);
+
+        self.mailbox_bits
+            .set(self.mailbox_bits.get() | (mailbox & IXGBE_VFMAILBOX_R2C_BITS));
+
+        mailbox | self.mailbox_bits.get()
+    }
+
+    /// Returns the register at `self.addr` + `reg`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        // registers are little endian, see PCIe specification
+        self.removal.read(&self.pci_addr, reg, |reg| {
+            u32::from_le(unsafe {
+                ptr::read_volatile((self.addr as usize + reg as usize) as *mut u32)
+            })
+        })
+    }
+
+    /// Sets the register at `self.addr` + `reg` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+
+    /// Sets the `flags` at `self.addr` + `reg`.
+    fn set_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) | flags);
+    }
+
+    /// Clears the `flags` at `self.addr` + `reg`.
+    fn clear_flags32(&self, reg: u32, flags: u32) {
+        self.set_reg32(reg, self.get_reg32(reg) & !flags);
+    }
+
+    /// Waits for `self.addr` + `reg` to clear `value`.
+    fn wait_clear_reg32(&self, reg: u32, value: u32) {
+        loop {
+            let current = self.get_reg32(reg);
+            if (current & value) == 0 || self.removal.is_removed() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Waits for `self.addr` + `reg` to set `value`.
+    fn wait_set_reg32(&self, reg: u32, value: u32) {
+        loop {
+            let current = self.get_reg32(reg);
+            if (current & value) == value {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
@@ -2,34 +2,95 @@
 //!
 //! ixy.rs is a Rust rewrite of the ixy userspace network driver.
 //! It is designed to be readable, idiomatic Rust code.
-//! It supports Intel 82599 10GbE NICs (ixgbe family).
+//! It supports Intel 82599 10GbE NICs (ixgbe family) and their virtual functions (ixgbevf).
 
 #![warn(rust_2018_idioms)]
 
 #[macro_use]
 extern crate log;
 
+mod af_xdp;
+pub mod checksum;
 #[rustfmt::skip]
 mod constants;
+mod e1000;
+mod error;
+mod i40e;
+mod ice;
+mod igb;
 mod ixgbe;
+mod ixgbevf;
 pub mod memory;
-mod pci;
-mod vfio;
+#[cfg(feature = "testing")]
+pub mod mock;
+pub mod pci;
+mod tap;
+pub mod vfio;
+mod virtio;
+mod vmxnet3;
 
+pub use self::error::IxyError;
+pub use self::vfio::VfioContainer;
+pub use self::virtio::{
+    VIRTIO_F_ACCESS_PLATFORM, VIRTIO_F_RING_PACKED, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM,
+    VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_HOST_TSO4,
+    VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MRG_RXBUF, VIRTIO_NET_F_MTU,
+    VIRTIO_NET_F_SPEED_DUPLEX, VIRTIO_NET_F_STATUS, VIRTIO_RING_F_EVENT_IDX,
+};
+
+use self::af_xdp::*;
+use self::e1000::*;
+use self::i40e::*;
+use self::ice::*;
+use self::igb::*;
 use self::ixgbe::*;
+use self::ixgbevf::*;
 use self::memory::*;
 use self::pci::*;
+use self::tap::*;
+use self::virtio::*;
+use self::vmxnet3::*;
 
+use std::collections::vec_deque;
 use std::collections::VecDeque;
-use std::error::Error;
-use std::os::unix::io::RawFd;
+use std::fmt;
+use std::future::Future;
+use std::hint;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 const MAX_QUEUES: u16 = 64;
 
+// empty polls after which the backoff poll strategy stops spinning resp. yielding
+const BACKOFF_SPIN_POLLS: u32 = 64;
+const BACKOFF_YIELD_POLLS: u32 = 128;
+
+// empty polls after which rx_poll checks whether the device was removed
+const REMOVAL_CHECK_POLLS: u32 = 1024;
+
+/// Number of bytes of the Toeplitz hash key of [`RssConfig`].
+pub const RSS_KEY_LEN: usize = 40;
+
+/// Number of entries of the redirection table of [`RssConfig`].
+pub const RSS_RETA_LEN: usize = 128;
+
+// the key Microsoft's RSS specification uses for its verification suite, also used by DPDK
+const DEFAULT_RSS_KEY: [u8; RSS_KEY_LEN] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
 /// Used for implementing an ixy device driver like ixgbe or virtio.
 pub trait IxyDevice {
     /// Initializes an intel 82599 network card.
-    fn init(pci_addr: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<Self, Box<dyn Error>>
+    fn init(pci_addr: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<Self, IxyError>
     where
         Self: Sized;
 
@@ -39,72 +100,1225 @@ pub trait IxyDevice {
     /// Returns the card's iommu capability.
     fn is_card_iommu_capable(&self) -> bool;
 
-    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
-    fn get_vfio_container(&self) -> Option<RawFd>;
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd>;
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str;
+
+    /// Maps BAR `bar` of this device, via VFIO if the device was initialized with it.
+    ///
+    /// The driver keeps its own mapping of BAR0, registers written through the returned
+    /// mapping bypass the driver.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// // BAR4 of the 82599 holds the MSI-X table
+    /// let bar = dev.map_bar(4).unwrap();
+    /// println!("msi-x vector 0 message address {:08x}", bar.read_32(0));
+    /// ```
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError>;
+
+    /// Returns the feature bits negotiated with a virtio device, i.e. the features offered by
+    /// the device that the driver accepted, e.g. [`VIRTIO_NET_F_MRG_RXBUF`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:00:03.0", 1, 1).unwrap();
+    /// println!("features: {:#018x}", dev.get_negotiated_features().unwrap());
+    /// ```
+    fn get_negotiated_features(&self) -> Result<u64, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not negotiate features",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Returns whether the feature `bit`, e.g. [`VIRTIO_NET_F_MRG_RXBUF`], was negotiated with
+    /// a virtio device. Devices that don't negotiate features have none.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:00:03.0", 1, 1).unwrap();
+    ///
+    /// if !dev.has_feature(VIRTIO_NET_F_MRG_RXBUF) {
+    ///     println!("jumbo frames need buffers of the mtu's size");
+    /// }
+    /// ```
+    fn has_feature(&self, bit: u64) -> bool {
+        self.get_negotiated_features()
+            .is_ok_and(|features| (features & bit) != 0)
+    }
+
+    /// Returns the layer 2 address of this device.
+    fn get_mac_addr(&self) -> [u8; 6];
+
+    /// Sets the layer 2 address of this device.
+    ///
+    /// The address is associated with the default VMDq pool 0.
+    fn set_mac_addr(&self, mac: [u8; 6]);
+
+    /// Sets the layer 2 address of this device and associates it with VMDq pool `pool`, i.e.
+    /// packets for this address are steered to that pool once VMDq is enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_mac_addr_pool([0x02, 0, 0, 0, 0, 0x01], 1).unwrap();
+    /// ```
+    fn set_mac_addr_pool(&self, _mac: [u8; 6], _pool: u8) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support VMDq pools",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Makes the network card accept packets for the additional unicast address `mac` without
+    /// enabling promiscuous mode. Returns the index of the filter, which identifies it for
+    /// `remove_unicast_filter`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// let index = dev.add_unicast_filter([0x02, 0, 0, 0, 0, 0x42]).unwrap();
+    /// dev.remove_unicast_filter(index).unwrap();
+    /// ```
+    fn add_unicast_filter(&self, _mac: [u8; 6]) -> Result<usize, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support unicast filters",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Removes the unicast address filter `index` added by `add_unicast_filter`.
+    fn remove_unicast_filter(&self, _index: usize) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support unicast filters",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Enables or disables promiscuous mode, i.e. whether the network card accepts packets for
+    /// any destination address.
+    ///
+    /// Returns an error if the driver has no control over its receive filters.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_promisc(false).unwrap();
+    /// ```
+    fn set_promisc(&self, _enabled: bool) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support promiscuous mode",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Enables or disables the reception of all multicast packets regardless of the multicast
+    /// filters set by `set_multicast_filters`.
+    ///
+    /// Returns an error if the driver has no control over its receive filters.
+    fn set_allmulti(&self, _enabled: bool) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support all-multicast mode",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Makes the network card accept multicast packets for the addresses in `macs`, replacing
+    /// the previous multicast filters.
+    ///
+    /// Returns an error if the driver has no control over its receive filters.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// // all IPv4 hosts
+    /// dev.set_multicast_filters(&[[0x01, 0x00, 0x5e, 0x00, 0x00, 0x01]]).unwrap();
+    /// ```
+    fn set_multicast_filters(&self, _macs: &[[u8; 6]]) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support multicast filters",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Enables receive side scaling (RSS), i.e. spreads received packets over the rx queues by
+    /// a Toeplitz hash of their flow as configured by `config`.
+    ///
+    /// Packets of the same flow always end up on the same queue. Packets without the hashed
+    /// headers, e.g. ARP requests, go to the queue of the first entry of the redirection table.
+    /// The configuration is lost on `reset`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 4, 4).unwrap();
+    /// dev.enable_rss(&RssConfig::new(4)).unwrap();
+    /// ```
+    fn enable_rss(&self, _config: &RssConfig) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support RSS",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Disables receive side scaling, all packets are received on rx queue 0 again.
+    fn disable_rss(&self) -> Result<(), IxyError> {
+        Ok(())
+    }
+
+    /// Adds a flow steering rule in the network card, e.g. to receive a flow on a dedicated rx
+    /// queue or to drop it in hardware. Returns the index of the rule, which identifies it for
+    /// `remove_flow_rule`.
+    ///
+    /// Rules take precedence over receive side scaling. Network cards may require all rules to
+    /// share the same masks. Packets matching a rule are counted in `rx_flow_matches` of the
+    /// [`ExtendedStats`]. Rules are lost on `reset`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 2, 1).unwrap();
+    ///
+    /// // receive DNS responses on queue 1
+    /// let mut rule = FlowRule::new(FlowProtocol::Udp, FlowAction::Queue(1));
+    /// rule.src_port = 53;
+    /// rule.src_port_mask = 0xffff;
+    /// let index = dev.add_flow_rule(&rule).unwrap();
+    ///
+    /// // drop NTP responses
+    /// let mut rule = FlowRule::new(FlowProtocol::Udp, FlowAction::Drop);
+    /// rule.src_port = 123;
+    /// rule.src_port_mask = 0xffff;
+    /// dev.add_flow_rule(&rule).unwrap();
+    ///
+    /// dev.remove_flow_rule(index).unwrap();
+    /// ```
+    fn add_flow_rule(&self, _rule: &FlowRule) -> Result<usize, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support flow rules",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Removes the flow steering rule `index` added by `add_flow_rule`.
+    fn remove_flow_rule(&self, index: usize) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "flow rule {} does not exist",
+            index
+        )))
+    }
+
+    /// Enables or disables stripping of 802.1Q VLAN tags from received packets.
+    ///
+    /// Stripped tags are reported in `vlan_tci` of the packet's [`PacketMeta`]. Drivers that
+    /// support stripping also insert a tag into sent packets whose `vlan_tci` is set, so
+    /// forwarded packets keep their tag.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_vlan_strip(true).unwrap();
+    ///
+    /// let mut buffer = VecDeque::new();
+    /// dev.rx_batch(0, &mut buffer, 32);
+    ///
+    /// for p in buffer.iter() {
+    ///     if let Some(tci) = p.meta().vlan_tci {
+    ///         println!("received packet of VLAN {}", tci & 0xfff);
+    ///     }
+    /// }
+    /// ```
+    fn set_vlan_strip(&mut self, _enabled: bool) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support VLAN offloads",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Adds `vlan` to the VLAN filter table. As long as the table is not empty, tagged packets
+    /// are only received if their VLAN is in the table, untagged packets are always received.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.add_vlan_filter(42).unwrap();
+    /// ```
+    fn add_vlan_filter(&self, _vlan: u16) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support VLAN filters",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Removes `vlan` from the VLAN filter table, tagged packets of all VLANs are received
+    /// again once the table is empty.
+    fn remove_vlan_filter(&self, _vlan: u16) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support VLAN filters",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Enables or disables receive side coalescing (RSC) on rx queue `queue_id`.
+    ///
+    /// The network card merges consecutive TCP segments of a flow into a single packet of
+    /// chained segments, see [`Packet::segments`](memory::Packet::segments), and updates the
+    /// headers of the first segment to cover the whole payload. A merged packet is handed over
+    /// once the flow is interrupted or the interrupt throttling interval of the queue expires,
+    /// see `set_interrupt_moderation`, longer intervals merge more segments.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_interrupt_moderation(0, InterruptModeration::Static(500)).unwrap();
+    /// dev.set_rsc(0, true).unwrap();
+    ///
+    /// for p in dev.rx_iter(0, 32) {
+    ///     println!("received {} bytes in {} segments", p.total_len(), p.num_segments());
+    /// }
+    /// ```
+    fn set_rsc(&mut self, _queue_id: u32, _enabled: bool) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support receive side coalescing",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Pushes up to `num_packets` `Packet`s onto `buffer` depending on the amount of
+    /// received packets by the network card. Returns the number of received packets.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Packet;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    ///
+    /// dev.rx_batch(0, &mut buf, 32);
+    /// ```
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize;
+
+    /// Returns whether the device was removed or is in reset, i.e. its registers read as all
+    /// ones. Once detected, the removal is latched and the driver stops touching the device.
+    ///
+    /// This reads a register and thus does not belong into the fast path, `try_rx_batch` and
+    /// `try_tx_batch` only check it if nothing could be received or sent.
+    fn is_removed(&self) -> bool;
+
+    /// Like `rx_batch`, but returns [`IxyError::DeviceRemoved`] instead of receiving nothing
+    /// forever if the device was removed, e.g. by a surprise hot-unplug or a PCIe error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Packet;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    ///
+    /// while let Ok(_) = dev.try_rx_batch(0, &mut buf, 32) {
+    ///     buf.clear();
+    /// }
+    /// ```
+    fn try_rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> Result<usize, IxyError> {
+        let received = self.rx_batch(queue_id, buffer, num_packets);
+        if received == 0 && self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.get_pci_addr().to_string()));
+        }
+
+        Ok(received)
+    }
+
+    /// Returns an iterator over up to `num_packets` `Packet`s received by the network card.
+    ///
+    /// The packets are taken off the rx queue in a single batch when calling this method.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// for p in dev.rx_iter(0, 32) {
+    ///     println!("received packet of {} bytes", p.len());
+    /// }
+    /// ```
+    fn rx_iter(&mut self, queue_id: u32, num_packets: usize) -> RxIter {
+        let mut buffer = VecDeque::with_capacity(num_packets);
+        self.rx_batch(queue_id, &mut buffer, num_packets);
+
+        RxIter {
+            packets: buffer.into_iter(),
+        }
+    }
+
+    /// Waits until at least one packet is received on `queue_id` and pushes up to `num_packets`
+    /// `Packet`s onto `buffer`. Returns the number of received packets.
+    ///
+    /// Between empty polls of the rx queue this waits according to the device's
+    /// [`PollStrategy`], see `set_poll_strategy`. Returns 0 if the device was removed while
+    /// waiting, see `is_removed`, and right away if `num_packets` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Packet;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    ///
+    /// loop {
+    ///     dev.rx_poll(0, &mut buf, 32);
+    ///     buf.clear();
+    /// }
+    /// ```
+    fn rx_poll(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        // no packet could ever be received
+        if num_packets == 0 {
+            return 0;
+        }
+
+        let strategy = self.get_poll_strategy();
+        let mut empty_polls: u32 = 0;
+        // counted separately, empty_polls stops counting once it saturates
+        let mut polls_since_check = 0;
+
+        loop {
+            let received = self.rx_batch(queue_id, buffer, num_packets);
+            if received > 0 {
+                return received;
+            }
+
+            strategy.wait(empty_polls);
+            empty_polls = empty_polls.saturating_add(1);
+            polls_since_check += 1;
+
+            if polls_since_check == REMOVAL_CHECK_POLLS {
+                if self.is_removed() {
+                    return 0;
+                }
+                polls_since_check = 0;
+            }
+        }
+    }
+
+    /// Sets how `rx_poll` waits between empty polls of an rx queue, trading cpu time for
+    /// latency. Defaults to [`PollStrategy::BusySpin`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// dev.set_poll_strategy(PollStrategy::Backoff {
+    ///     max: Duration::from_millis(1),
+    /// });
+    /// ```
+    fn set_poll_strategy(&mut self, strategy: PollStrategy);
+
+    /// Returns how `rx_poll` waits between empty polls of an rx queue.
+    fn get_poll_strategy(&self) -> PollStrategy;
+
+    /// Returns the content of the next received packet on `queue_id` without removing it from
+    /// the rx queue, or [`None`] if no packet has been received. The packet is returned by the
+    /// next call to `rx_batch`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// if let Some(data) = dev.rx_peek(0) {
+    ///     println!("next packet has ether type {:02x}{:02x}", data[12], data[13]);
+    /// }
+    /// ```
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]>;
+
+    /// Limits the number of descriptors of rx queue `queue_id` that are handed to the network
+    /// card to receive into. `num_posted` must be smaller than the ring size of 512.
+    ///
+    /// By default all but one descriptor are posted. With fewer descriptors the queue runs
+    /// out of buffers sooner and drops packets, the buffers of the other descriptors stay
+    /// allocated though. Lowering the limit takes effect as packets are received.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// dev.set_rx_posted(0, 128).unwrap();
+    /// assert_eq!(dev.get_rx_posted(0), 128);
+    /// ```
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError>;
+
+    /// Returns the number of descriptors of rx queue `queue_id` that may be posted.
+    fn get_rx_posted(&self, queue_id: u32) -> usize;
+
+    /// Switches rx queue `queue_id` to receive into externally-owned buffers instead of its
+    /// `Mempool`. Packets that have been received but not fetched yet are dropped.
+    ///
+    /// The queue starts out without buffers, they are supplied by `rx_external_post` and
+    /// returned with the received packets by `rx_external_batch`. `rx_batch` and `rx_peek`
+    /// return nothing on such a queue.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Dma;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let dma: Dma<u8> = Dma::allocate(2048, true).unwrap();
+    ///
+    /// dev.set_rx_external(0).unwrap();
+    /// dev.rx_external_post(0, dma.phys);
+    ///
+    /// let mut received = VecDeque::new();
+    /// while dev.rx_external_batch(0, &mut received, 1) == 0 {}
+    /// ```
+    fn set_rx_external(&mut self, _queue_id: u32) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support external rx buffers",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Hands the externally-owned buffer at `phys_addr` to rx queue `queue_id` to receive a
+    /// packet into. Returns `false` if the queue holds as many buffers as it can.
+    ///
+    /// Buffers must hold at least the queue's receive buffer size, 2048 bytes unless changed
+    /// with `set_rx_buffer_size` before switching the queue, and `phys_addr` has to be a DMA
+    /// address the network card can access, i.e. an IOVA within the VFIO container when using
+    /// the IOMMU like the memory of a `Dma`. The caller must not reuse or free the buffer until
+    /// it was returned by `rx_external_batch`.
+    fn rx_external_post(&mut self, _queue_id: u32, _phys_addr: usize) -> bool {
+        false
+    }
+
+    /// Pushes the physical address and length of up to `num_packets` packets received into
+    /// externally-owned buffers on `queue_id` onto `buffer`. Returns the number of received
+    /// packets.
+    ///
+    /// Frames larger than the receive buffer size are dropped, the buffers they occupied are
+    /// posted to the queue again.
+    fn rx_external_batch(
+        &mut self,
+        _queue_id: u32,
+        _buffer: &mut VecDeque<(usize, usize)>,
+        _num_packets: usize,
+    ) -> usize {
+        0
+    }
+
+    /// Takes `Packet`s out of `buffer` until `buffer` is empty or the network card's tx
+    /// queue is full. Returns the number of sent packets.
+    ///
+    /// The tail register is written at most once per call, so sending large batches amortizes
+    /// the costly MMIO write over many packets.
+    ///
+    /// Packets of several chained segments are sent as a single frame, see [`Packet::chain`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Packet;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    ///
+    /// assert_eq!(dev.tx_batch(0, &mut buf), 0);
+    /// ```
+    fn tx_batch(&mut self, queue_id: u32, buffer: &mut VecDeque<Packet>) -> usize;
+
+    /// Like `tx_batch`, but returns [`IxyError::DeviceRemoved`] if nothing could be sent
+    /// because the device was removed. The packets stay in `buffer` in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Packet;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    ///
+    /// if let Err(e) = dev.try_tx_batch(0, &mut buf) {
+    ///     eprintln!("{}", e);
+    /// }
+    /// ```
+    fn try_tx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+    ) -> Result<usize, IxyError> {
+        let pending = buffer.len();
+        let sent = self.tx_batch(queue_id, buffer);
+        if sent == 0 && pending > 0 && self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.get_pci_addr().to_string()));
+        }
+
+        Ok(sent)
+    }
+
+    /// Puts a single externally-owned buffer of `len` bytes at `phys_addr` into the network
+    /// card's tx queue without copying it into a `Mempool`. Returns `false` if the queue is full.
+    ///
+    /// `phys_addr` has to be a DMA address the network card can access, i.e. an IOVA within the
+    /// VFIO container when using the IOMMU. The caller must not reuse or free the buffer until
+    /// its address was returned by `tx_external_completions`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use ixy::memory::Dma;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let dma: Dma<u8> = Dma::allocate(2048, true).unwrap();
+    ///
+    /// while !dev.tx_external(0, dma.phys, 60) {}
+    /// ```
+    fn tx_external(&mut self, _queue_id: u32, _phys_addr: usize, _len: usize) -> bool {
+        false
+    }
+
+    /// Pushes the physical addresses of all externally-owned buffers whose transmission has
+    /// completed since the last call onto `completed`. Returns the number of completed buffers.
+    ///
+    /// Completed descriptors are cleaned up in batches, so buffers may be reported with a delay.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut completed: VecDeque<usize> = VecDeque::new();
+    ///
+    /// dev.tx_external_completions(0, &mut completed);
+    /// ```
+    fn tx_external_completions(
+        &mut self,
+        _queue_id: u32,
+        _completed: &mut VecDeque<usize>,
+    ) -> usize {
+        0
+    }
+
+    /// Reads the network card's stats registers into `stats`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut stats: DeviceStats = Default::default();
+    ///
+    /// dev.read_stats(&mut stats);
+    /// ```
+    fn read_stats(&self, stats: &mut DeviceStats);
+
+    /// Resets the network card's stats registers.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.reset_stats();
+    /// ```
+    fn reset_stats(&self);
+
+    /// Reads the network card's error, flow control and broadcast/multicast counters into
+    /// `stats`. Like `read_stats`, the counters are added to the values already in `stats`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut stats: ExtendedStats = Default::default();
+    ///
+    /// dev.read_extended_stats(&mut stats);
+    /// println!("{} packets missed", stats.rx_missed);
+    /// ```
+    fn read_extended_stats(&self, stats: &mut ExtendedStats);
+
+    /// Returns the network card's link speed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// println!("Link speed is {} Mbit/s", dev.get_link_speed());
+    /// ```
+    fn get_link_speed(&self) -> u32;
+
+    /// Returns the negotiated and maximum speed and width of the network card's PCIe link, or
+    /// [`None`] if the card is not attached via PCIe.
+    ///
+    /// A card in a narrower or slower slot than it supports can't reach line rate, see
+    /// [`PcieLink::bandwidth`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// if let Some(link) = dev.get_pcie_link().unwrap() {
+    ///     println!("pcie link: {}", link);
+    /// }
+    /// ```
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError>;
+
+    /// Changes the number of rx and tx queues to `num_rx_queues` and `num_tx_queues` without
+    /// resetting the network card.
+    ///
+    /// The link, the mac address, filter settings and the stats registers survive, as do all
+    /// queues below the new counts including their descriptor rings, mempools and in-flight
+    /// packets. Removed queues are disabled and lose all packets that were not yet received or
+    /// sent. Added queues start out empty with a newly allocated mempool. Rx queues that flow
+    /// rules steer flows to can't be removed.
+    ///
+    /// If receive side scaling is enabled, its redirection table is rewritten to spread the
+    /// flows evenly over the new rx queues, its key and hashed fields are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.reconfigure_queues(4, 1).unwrap();
+    /// ```
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError>;
+
+    /// Enables rx queue `queue_id` after it was disabled by `disable_rx_queue`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 2, 1).unwrap();
+    ///
+    /// dev.disable_rx_queue(1).unwrap();
+    /// assert!(!dev.is_rx_queue_enabled(1));
+    /// dev.enable_rx_queue(1).unwrap();
+    /// ```
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Disables rx queue `queue_id`. The queue's descriptor ring and mempool stay intact, so
+    /// re-enabling it resumes reception where it stopped.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Returns whether rx queue `queue_id` is enabled.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool;
+
+    /// Enables tx queue `queue_id` after it was disabled by `disable_tx_queue`.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Disables tx queue `queue_id`. Packets that have not been sent yet stay in the queue and
+    /// are sent once it is re-enabled.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Returns whether tx queue `queue_id` is enabled.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool;
+
+    /// Returns whether tx queue `queue_id` makes progress, i.e. it is empty or the device sent
+    /// a packet within `timeout`.
+    ///
+    /// This is a watchdog meant to be called periodically. A stall is detected once the device
+    /// has not sent a packet for `timeout` since an earlier call saw the same pending packets.
+    /// Disabled queues with pending packets are reported as stalled as well.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// if !dev.tx_healthy(0, Duration::from_secs(1)).unwrap() {
+    ///     dev.reset_tx_queue(0).unwrap();
+    /// }
+    /// ```
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError>;
+
+    /// Resets tx queue `queue_id` by disabling it, emptying its descriptor ring and enabling it
+    /// again.
+    ///
+    /// Packets that have not been sent yet are dropped. Pending externally-owned buffers are
+    /// reported by `tx_external_completions` as the device no longer accesses them.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Disables rx queue `queue_id` and returns all buffers in its descriptor ring to the
+    /// queue's mempool. Packets that have been received but not fetched yet are dropped.
+    ///
+    /// This is meant for shutting down a queue, a drained queue can't be enabled again.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    ///
+    /// dev.drain_rx_queue(0).unwrap();
+    /// dev.drain_tx_queue(0).unwrap();
+    /// ```
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Waits until all packets of tx queue `queue_id` are sent and returns their buffers to
+    /// their mempool. The queue stays usable.
+    ///
+    /// If the packets are not sent within a second, the queue is reset as by `reset_tx_queue`
+    /// and the remaining packets are dropped.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError>;
+
+    /// Sets whether rx queue `queue_id` drops incoming packets while its descriptor ring is
+    /// full.
+    ///
+    /// Drop enable defaults to on if more than one rx queue is configured, queues added by
+    /// `reconfigure_queues` use the default of the new number of queues. With drop enable
+    /// off, packets for a full queue are held back in the shared rx packet buffer, so a single
+    /// slow queue blocks all other queues (head-of-line blocking) until it is drained.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 4, 4).unwrap();
+    /// dev.set_rx_drop_enable(0, false).unwrap();
+    /// ```
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError>;
+
+    /// Sets the descriptor prefetch (`pthresh`), host (`hthresh`) and write-back (`wthresh`)
+    /// thresholds of rx queue `queue_id`. Each threshold must fit into 7 bits.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_rx_thresholds(0, 8, 8, 4).unwrap();
+    /// ```
+    fn set_rx_thresholds(
+        &self,
+        _queue_id: u32,
+        _pthresh: u8,
+        _hthresh: u8,
+        _wthresh: u8,
+    ) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support descriptor thresholds",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Sets the descriptor prefetch (`pthresh`), host (`hthresh`) and write-back (`wthresh`)
+    /// thresholds of tx queue `queue_id`. Each threshold must fit into 7 bits.
+    ///
+    /// Tx queues default to a prefetch threshold of 36, a host threshold of 8 and a write-back
+    /// threshold of 4.
+    fn set_tx_thresholds(
+        &self,
+        _queue_id: u32,
+        _pthresh: u8,
+        _hthresh: u8,
+        _wthresh: u8,
+    ) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support descriptor thresholds",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Enables direct cache access (DCA) for rx queue `queue_id`, so the network card writes
+    /// received descriptors and packets directly into the cache of `cpu_id`.
+    ///
+    /// The platform's chipset has to support DCA, otherwise this has no effect.
+    #[cfg(feature = "dca")]
+    fn enable_rx_dca(&self, _queue_id: u32, _cpu_id: u8) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support dca",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Sets the flow control (pause frame) mode of the network card.
+    ///
+    /// Pause frames are sent once the receive packet buffer is filled to three quarters and
+    /// stop once it drained to half of its size.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_flow_control(FlowControl::Full);
+    /// ```
+    fn set_flow_control(&self, mode: FlowControl);
+
+    /// Returns the flow control (pause frame) mode of the network card.
+    fn get_flow_control(&self) -> FlowControl;
+
+    /// Enables or disables stripping the 4 byte Ethernet FCS from received packets. Stripping
+    /// is enabled by default, when disabled the FCS is included in the length of a `Packet`.
+    ///
+    /// On the 82599 both `HLREG0.RXCRCSTRP` and `RDRXCTL.CRCStrip` control stripping and rx
+    /// misbehaves if they disagree, so they are always written together while rx is paused.
+    ///
+    /// Returns an error if the FCS can't be kept, e.g. because the device never passes it to
+    /// the driver or RSC is enabled on an rx queue, see `set_rsc`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_crc_strip(false).unwrap();
+    /// ```
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError>;
+
+    /// Returns whether the Ethernet FCS is stripped from received packets.
+    fn get_crc_strip(&self) -> bool;
+
+    /// Replaces the mempool of rx queue `queue_id` with one of `buffer_size` byte buffers, e.g.
+    /// 9216 bytes so that jumbo frames land in a single buffer instead of a chain of segments.
+    ///
+    /// The queue is drained, packets in its ring are lost. Frames larger than 1518 bytes are
+    /// accepted once any rx queue uses buffers larger than 2048 bytes.
+    ///
+    /// Returns an error if `buffer_size` is zero or exceeds 16 KiB, the largest receive buffer
+    /// of the 82599. Sizes are rounded up to a multiple of 1 KiB.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.set_rx_buffer_size(0, 9216).unwrap();
+    /// ```
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError>;
+
+    /// Enables the interrupt of rx queue `queue_id`, which fires once a packet arrives.
+    ///
+    /// Interrupts require a device bound to `vfio-pci`, they are delivered via MSI-X. An
+    /// interrupt is disabled again after it fired, so it has to be enabled before each
+    /// `wait_rx_interrupt`.
+    fn enable_rx_interrupt(&mut self, _queue_id: u32) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support interrupts",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Disables the interrupt of rx queue `queue_id`.
+    fn disable_rx_interrupt(&mut self, _queue_id: u32) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support interrupts",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Sets the interrupt moderation of rx queue `queue_id`, the default is a static interval
+    /// of 10 µs.
+    ///
+    /// Short intervals suit latency-sensitive workloads, long ones reduce the cpu load of
+    /// interrupts at high packet rates. Adaptive moderation measures the packet rate whenever
+    /// the interrupt is enabled and picks the interval accordingly.
+    ///
+    /// Returns an error if a static interval exceeds the device's maximum, 1022 µs for ixgbe.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 2, 1).unwrap();
+    /// dev.set_interrupt_moderation(0, InterruptModeration::Static(0)).unwrap();
+    /// dev.set_interrupt_moderation(1, InterruptModeration::Adaptive).unwrap();
+    /// ```
+    fn set_interrupt_moderation(
+        &mut self,
+        _queue_id: u32,
+        _moderation: InterruptModeration,
+    ) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support interrupt moderation",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Returns the interrupt moderation of rx queue `queue_id`.
+    fn get_interrupt_moderation(&self, _queue_id: u32) -> InterruptModeration {
+        InterruptModeration::Static(0)
+    }
+
+    /// Sleeps until the interrupt of rx queue `queue_id` fires or `timeout` passed, forever if
+    /// it is [`None`], and returns whether packets may have arrived.
+    ///
+    /// Returns immediately if a packet is already waiting. This allows idle applications to
+    /// sleep instead of busy polling and to switch back to polling under load.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buffer = VecDeque::new();
+    ///
+    /// loop {
+    ///     if dev.rx_batch(0, &mut buffer, 32) == 0 {
+    ///         // nothing to do, sleep until the next packet arrives
+    ///         dev.enable_rx_interrupt(0).unwrap();
+    ///         dev.wait_rx_interrupt(0, None).unwrap();
+    ///         continue;
+    ///     }
+    ///
+    ///     buffer.clear();
+    /// }
+    /// ```
+    fn wait_rx_interrupt(
+        &mut self,
+        _queue_id: u32,
+        _timeout: Option<Duration>,
+    ) -> Result<bool, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support interrupts",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Returns the eventfd the interrupt of rx queue `queue_id` signals, or [`None`] if the
+    /// driver has none or `enable_rx_interrupt` didn't set it up yet.
+    ///
+    /// The eventfd becomes readable when the interrupt fires, reading it resets it. This allows
+    /// registering it with an async runtime, e.g. tokio's `AsyncFd`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// dev.enable_rx_interrupt(0).unwrap();
+    ///
+    /// let eventfd = dev.rx_interrupt_fd(0).unwrap();
+    /// ```
+    fn rx_interrupt_fd(&self, _queue_id: u32) -> Option<RawFd> {
+        None
+    }
+
+    /// Reads the 16 bit word at `offset` of the network card's EEPROM.
+    fn read_eeprom_word(&self, offset: u16) -> u16;
 
-    /// Returns the pci address of this device.
-    fn get_pci_addr(&self) -> &str;
+    /// Returns the version of the network card's firmware/NVM image.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// println!("NVM version is {:#x}", dev.get_firmware_version());
+    /// ```
+    fn get_firmware_version(&self) -> u32;
 
-    /// Returns the layer 2 address of this device.
-    fn get_mac_addr(&self) -> [u8; 6];
+    /// Returns whether the checksum stored in the network card's EEPROM is valid.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// if !dev.validate_eeprom_checksum() {
+    ///     eprintln!("EEPROM of {} is corrupt", dev.get_pci_addr());
+    /// }
+    /// ```
+    fn validate_eeprom_checksum(&self) -> bool;
 
-    /// Sets the layer 2 address of this device.
-    fn set_mac_addr(&self, mac: [u8; 6]);
+    /// Returns the temperature of the network card in degrees Celsius.
+    ///
+    /// Returns `None` if the network card has no thermal sensor or it could not be read.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// match dev.get_temperature() {
+    ///     Some(temperature) => println!("{} is at {} °C", dev.get_pci_addr(), temperature),
+    ///     None => println!("{} has no thermal sensor", dev.get_pci_addr()),
+    /// }
+    /// ```
+    fn get_temperature(&self) -> Option<i16>;
 
-    /// Pushes up to `num_packets` `Packet`s onto `buffer` depending on the amount of
-    /// received packets by the network card. Returns the number of received packets.
+    /// Returns the DMA base address and the length in bytes of the descriptor ring of rx queue
+    /// `queue_id`, i.e. the values programmed into `RDBA` and `RDLEN` on the 82599.
+    ///
+    /// The address is an IOVA when using the IOMMU. This is meant for correlating the driver's
+    /// behavior with PCIe analyzers or kernel tracing.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
     /// use ixy::*;
-    /// use ixy::memory::Packet;
-    /// use std::collections::VecDeque;
     ///
-    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
-    /// let mut buf: VecDeque<Packet> = VecDeque::new();
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
     ///
-    /// dev.rx_batch(0, &mut buf, 32);
+    /// let (base, len) = dev.rx_ring_phys(0);
+    /// println!("rx ring 0 at {:#x}..{:#x}", base, base + len);
     /// ```
-    fn rx_batch(
-        &mut self,
-        queue_id: u32,
-        buffer: &mut VecDeque<Packet>,
-        num_packets: usize,
-    ) -> usize;
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize);
 
-    /// Takes `Packet`s out of `buffer` until `buffer` is empty or the network card's tx
-    /// queue is full. Returns the number of sent packets.
+    /// Returns the DMA base address and the length in bytes of the descriptor ring of tx queue
+    /// `queue_id`, i.e. the values programmed into `TDBA` and `TDLEN` on the 82599.
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize);
+
+    /// Returns a snapshot of the network card's key registers for bug reports and diagnostics.
+    ///
+    /// The stats registers are cleared on read, so this resets the stats like `reset_stats`.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
     /// use ixy::*;
-    /// use ixy::memory::Packet;
-    /// use std::collections::VecDeque;
     ///
-    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
-    /// let mut buf: VecDeque<Packet> = VecDeque::new();
-    ///
-    /// assert_eq!(dev.tx_batch(0, &mut buf), 0);
+    /// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// println!("{}", dev.dump_registers());
     /// ```
-    fn tx_batch(&mut self, queue_id: u32, buffer: &mut VecDeque<Packet>) -> usize;
+    fn dump_registers(&self) -> RegisterDump;
 
-    /// Reads the network card's stats registers into `stats`.
+    /// Resets the network card via a PCI function-level reset and initializes it again as
+    /// `ixy_init` does, e.g. to recover a device that stopped responding.
+    ///
+    /// All configuration is lost and all queues start over with new mempools, packets in the
+    /// descriptor rings are dropped. Packets received before stay valid.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
     /// use ixy::*;
+    /// use std::time::Duration;
     ///
     /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
-    /// let mut stats: DeviceStats = Default::default();
     ///
-    /// dev.read_stats(&mut stats);
+    /// if !dev.tx_healthy(0, Duration::from_secs(1)).unwrap() {
+    ///     dev.reset().unwrap();
+    /// }
     /// ```
-    fn read_stats(&self, stats: &mut DeviceStats);
+    fn reset(&mut self) -> Result<(), IxyError>;
 
-    /// Resets the network card's stats registers.
+    /// Creates `num_vfs` SR-IOV virtual functions of the network card and returns their pci
+    /// addresses, e.g. to pass them to VMs.
+    ///
+    /// The kernel creates virtual functions via the driver of the network card, so it has to be
+    /// bound to `vfio-pci` loaded with `enable_sriov=1`. The network card is initialized again
+    /// like by `reset` and its own queues move behind those of the virtual functions, which
+    /// limits it to 4 queues, or 2 queues with more than 31 virtual functions.
+    ///
+    /// Virtual functions talk to the driver of the network card via a mailbox, they don't work
+    /// unless `process_vf_mailbox` is called regularly.
     ///
     /// # Examples
     ///
@@ -112,21 +1326,496 @@ pub trait IxyDevice {
     /// use ixy::*;
     ///
     /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
-    /// dev.reset_stats();
+    ///
+    /// for (vf, pci_addr) in dev.enable_sriov(2).unwrap().iter().enumerate() {
+    ///     dev.set_vf_vlan(vf as u16, Some(100 + vf as u16)).unwrap();
+    ///     println!("virtual function {} is {}", vf, pci_addr);
+    /// }
+    ///
+    /// loop {
+    ///     dev.process_vf_mailbox().unwrap();
+    ///     // forward packets of the network card
+    /// }
     /// ```
-    fn reset_stats(&self);
+    fn enable_sriov(&mut self, _num_vfs: u16) -> Result<Vec<String>, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support SR-IOV",
+            self.get_driver_name()
+        )))
+    }
 
-    /// Returns the network card's link speed.
+    /// Removes all virtual functions created by `enable_sriov` and initializes the network card
+    /// again.
+    fn disable_sriov(&mut self) -> Result<(), IxyError> {
+        Ok(())
+    }
+
+    /// Returns the pci addresses of the virtual functions, virtual function `n` is at index `n`.
+    fn get_vfs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Sets the mac address of virtual function `vf`, the virtual function can't change it
+    /// anymore. It takes effect once the virtual function resets.
+    fn set_vf_mac(&mut self, _vf: u16, _mac: [u8; 6]) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support SR-IOV",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Sets the port VLAN of virtual function `vf`, or removes it with [`None`].
+    ///
+    /// The network card inserts the tag into all packets sent by the virtual function, which
+    /// only receives packets with this tag then.
+    fn set_vf_vlan(&mut self, _vf: u16, _vlan: Option<u16>) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} does not support SR-IOV",
+            self.get_driver_name()
+        )))
+    }
+
+    /// Answers the pending mailbox requests of the virtual functions, e.g. resets and mac
+    /// address changes, and returns the number of answered requests.
+    fn process_vf_mailbox(&mut self) -> Result<usize, IxyError> {
+        Ok(0)
+    }
+}
+
+/// Iterator over a batch of received packets, see [`IxyDevice::rx_iter`].
+pub struct RxIter {
+    packets: vec_deque::IntoIter<Packet>,
+}
+
+impl Iterator for RxIter {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        self.packets.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.packets.size_hint()
+    }
+}
+
+impl ExactSizeIterator for RxIter {}
+
+impl<'a> dyn IxyDevice + 'a {
+    /// Returns a future that resolves to the next batch of up to `num_packets` packets received
+    /// on `queue_id`.
+    ///
+    /// The future doesn't depend on a specific async runtime. If the interrupt of the queue can
+    /// be enabled and has an eventfd, see `rx_interrupt_fd`, a helper thread of the future waits
+    /// for it and wakes the task. Otherwise the future polls the queue once per `poll` and
+    /// yields to the executor while no packets arrive.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
     /// use ixy::*;
     ///
-    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
-    /// println!("Link speed is {} Mbit/s", dev.get_link_speed());
+    /// async fn forward(rx: &mut dyn IxyDevice, tx: &mut dyn IxyDevice) {
+    ///     loop {
+    ///         let mut packets = rx.recv(0, 32).await;
+    ///         tx.tx_batch(0, &mut packets);
+    ///     }
+    /// }
     /// ```
-    fn get_link_speed(&self) -> u16;
+    pub fn recv(&mut self, queue_id: u32, num_packets: usize) -> RxFuture<'_> {
+        RxFuture {
+            dev: self,
+            queue_id,
+            buffer: VecDeque::with_capacity(num_packets),
+            num_packets,
+            interrupts: true,
+            waiter: None,
+        }
+    }
+}
+
+/// Future resolving to a batch of received packets, see `recv` on [`IxyDevice`].
+pub struct RxFuture<'a> {
+    dev: &'a mut dyn IxyDevice,
+    queue_id: u32,
+    buffer: VecDeque<Packet>,
+    num_packets: usize,
+    // cleared once the interrupt turned out to be unavailable
+    interrupts: bool,
+    waiter: Option<InterruptWaiter>,
+}
+
+impl Future for RxFuture<'_> {
+    type Output = VecDeque<Packet>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<VecDeque<Packet>> {
+        let RxFuture {
+            ref mut dev,
+            queue_id,
+            ref mut buffer,
+            num_packets,
+            ref mut interrupts,
+            ref mut waiter,
+        } = *self;
+
+        if dev.rx_batch(queue_id, buffer, num_packets) > 0 {
+            return Poll::Ready(mem::take(buffer));
+        }
+
+        if *interrupts {
+            if waiter.is_none() {
+                *waiter = dev
+                    .enable_rx_interrupt(queue_id)
+                    .ok()
+                    .and_then(|_| dev.rx_interrupt_fd(queue_id))
+                    .and_then(|eventfd| InterruptWaiter::spawn(eventfd).ok());
+                *interrupts = waiter.is_some();
+            }
+
+            if let Some(ref waiter) = waiter {
+                // the interrupt is disabled after it fired, arm it again after the waker is set
+                waiter.set_waker(cx.waker());
+
+                if dev.enable_rx_interrupt(queue_id).is_ok() {
+                    // packets that arrived before the interrupt was enabled don't trigger it
+                    if dev.rx_batch(queue_id, buffer, num_packets) > 0 {
+                        return Poll::Ready(mem::take(buffer));
+                    }
+
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        // ask to be polled again after other tasks had their turn
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Helper thread of an [`RxFuture`] that wakes its task whenever an interrupt eventfd fires.
+struct InterruptWaiter {
+    waker: Arc<Mutex<Option<Waker>>>,
+    // wakes the thread up to stop it, the eventfd has to outlive the thread
+    stop: OwnedFd,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InterruptWaiter {
+    /// Starts a thread waiting for `eventfd`.
+    fn spawn(eventfd: RawFd) -> Result<InterruptWaiter, IxyError> {
+        let stop = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if stop == -1 {
+            return Err(IxyError::Io(std::io::Error::last_os_error()));
+        }
+        let stop = unsafe { OwnedFd::from_raw_fd(stop) };
+
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let thread_waker = waker.clone();
+        let stop_fd = stop.as_raw_fd();
+
+        let thread = thread::Builder::new()
+            .name("ixy-rx-interrupt".to_string())
+            .spawn(move || loop {
+                let mut pollfds = [
+                    libc::pollfd {
+                        fd: eventfd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                    libc::pollfd {
+                        fd: stop_fd,
+                        events: libc::POLLIN,
+                        revents: 0,
+                    },
+                ];
+
+                if unsafe { libc::poll(pollfds.as_mut_ptr(), 2, -1) } == -1 {
+                    if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return;
+                }
+
+                if pollfds[1].revents != 0 {
+                    return;
+                }
+
+                if pollfds[0].revents != 0 {
+                    // reset the counter, the eventfd is non-blocking so this can't hang
+                    let mut counter = 0u64;
+                    unsafe {
+                        libc::read(
+                            eventfd,
+                            &mut counter as *mut u64 as *mut libc::c_void,
+                            mem::size_of::<u64>(),
+                        )
+                    };
+
+                    if let Some(waker) = thread_waker.lock().unwrap().take() {
+                        waker.wake();
+                    }
+                }
+            })
+            .map_err(IxyError::Io)?;
+
+        Ok(InterruptWaiter {
+            waker,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Replaces the waker woken by the next interrupt.
+    fn set_waker(&self, waker: &Waker) {
+        *self.waker.lock().unwrap() = Some(waker.clone());
+    }
+}
+
+impl Drop for InterruptWaiter {
+    fn drop(&mut self) {
+        let value = 1u64;
+        unsafe {
+            libc::write(
+                self.stop.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                mem::size_of::<u64>(),
+            )
+        };
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Flow control modes, i.e. which direction of IEEE 802.3x pause frames is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    /// Pause frames are neither sent nor honored.
+    None,
+
+    /// Received pause frames pause transmission, but no pause frames are sent.
+    RxPause,
+
+    /// Pause frames are sent when the receive buffer fills up, received ones are ignored.
+    TxPause,
+
+    /// Pause frames are sent and honored.
+    Full,
+}
+
+/// Configuration of receive side scaling (RSS), see `enable_rss` on [`IxyDevice`].
+///
+/// # Examples
+///
+/// ```rust
+/// use ixy::*;
+///
+/// // hash only the addresses, so that fragments stay on the queue of their flow
+/// let mut config = RssConfig::new(4);
+/// config.fields = RssFields {
+///     ipv4: true,
+///     ipv6: true,
+///     ..RssFields::default()
+/// };
+///
+/// // send a quarter of the flows to queue 0 and the rest to queue 1
+/// for (i, entry) in config.reta.iter_mut().enumerate() {
+///     *entry = if i % 4 == 0 { 0 } else { 1 };
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RssConfig {
+    /// Key of the Toeplitz hash, a random key makes the distribution hard to predict.
+    pub key: [u8; RSS_KEY_LEN],
+
+    /// Headers the hash is computed over.
+    pub fields: RssFields,
+
+    /// Redirection table, the packet is received on the rx queue of entry `hash % RSS_RETA_LEN`.
+    pub reta: [u16; RSS_RETA_LEN],
+}
+
+impl RssConfig {
+    /// Returns a configuration that spreads the flows of all IPv4/IPv6 TCP and UDP packets
+    /// evenly over `num_queues` rx queues, using the well-known key of Microsoft's RSS
+    /// specification.
+    ///
+    /// # Panics
+    /// Panics if `num_queues` is 0.
+    pub fn new(num_queues: u16) -> RssConfig {
+        assert!(num_queues > 0, "cannot spread flows over 0 queues");
+
+        let mut reta = [0; RSS_RETA_LEN];
+        for (i, entry) in reta.iter_mut().enumerate() {
+            *entry = (i % usize::from(num_queues)) as u16;
+        }
+
+        RssConfig {
+            key: DEFAULT_RSS_KEY,
+            fields: RssFields {
+                ipv4: true,
+                ipv4_tcp: true,
+                ipv4_udp: true,
+                ipv6: true,
+                ipv6_tcp: true,
+                ipv6_udp: true,
+            },
+            reta,
+        }
+    }
+}
+
+/// Headers the RSS hash is computed over, per type of packet. Packets of types that are not
+/// hashed go to the queue of the first entry of the redirection table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RssFields {
+    /// IPv4 source and destination address of IPv4 packets that are not hashed otherwise.
+    pub ipv4: bool,
+
+    /// IPv4 addresses and TCP ports.
+    pub ipv4_tcp: bool,
+
+    /// IPv4 addresses and UDP ports.
+    pub ipv4_udp: bool,
+
+    /// IPv6 source and destination address of IPv6 packets that are not hashed otherwise.
+    pub ipv6: bool,
+
+    /// IPv6 addresses and TCP ports.
+    pub ipv6_tcp: bool,
+
+    /// IPv6 addresses and UDP ports.
+    pub ipv6_udp: bool,
+}
+
+/// IPv4 5-tuple of a flow steering rule and what to do with matching packets, see
+/// `add_flow_rule` on [`IxyDevice`].
+///
+/// Every field is compared with the packet under its mask, a mask of 0 matches any value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlowRule {
+    /// Transport protocol of the packets.
+    pub protocol: FlowProtocol,
+
+    /// Source address.
+    pub src_ip: Ipv4Addr,
+
+    /// Bits of `src_ip` that are compared.
+    pub src_ip_mask: Ipv4Addr,
+
+    /// Destination address.
+    pub dst_ip: Ipv4Addr,
+
+    /// Bits of `dst_ip` that are compared.
+    pub dst_ip_mask: Ipv4Addr,
+
+    /// Source port, only for TCP, UDP and SCTP.
+    pub src_port: u16,
+
+    /// Bits of `src_port` that are compared.
+    pub src_port_mask: u16,
+
+    /// Destination port, only for TCP, UDP and SCTP.
+    pub dst_port: u16,
+
+    /// Bits of `dst_port` that are compared.
+    pub dst_port_mask: u16,
+
+    /// What to do with matching packets.
+    pub action: FlowAction,
+}
+
+impl FlowRule {
+    /// Returns a rule that matches all IPv4 packets of `protocol`.
+    pub fn new(protocol: FlowProtocol, action: FlowAction) -> FlowRule {
+        FlowRule {
+            protocol,
+            src_ip: Ipv4Addr::UNSPECIFIED,
+            src_ip_mask: Ipv4Addr::UNSPECIFIED,
+            dst_ip: Ipv4Addr::UNSPECIFIED,
+            dst_ip_mask: Ipv4Addr::UNSPECIFIED,
+            src_port: 0,
+            src_port_mask: 0,
+            dst_port: 0,
+            dst_port_mask: 0,
+            action,
+        }
+    }
+}
+
+/// Transport protocols of IPv4 packets a [`FlowRule`] can match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowProtocol {
+    /// TCP segments.
+    Tcp,
+
+    /// UDP datagrams.
+    Udp,
+
+    /// SCTP packets.
+    Sctp,
+
+    /// IPv4 packets of any other protocol, these have no ports.
+    Other,
+}
+
+/// Actions of a [`FlowRule`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowAction {
+    /// Receives matching packets on the given rx queue.
+    Queue(u16),
+
+    /// Drops matching packets in the network card.
+    Drop,
+}
+
+/// Interrupt moderation of an rx queue, i.e. how long the device delays interrupts to fire one
+/// for several packets, see `set_interrupt_moderation` on [`IxyDevice`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptModeration {
+    /// Fires at most one interrupt per the given number of µs, 0 fires one per packet.
+    Static(u32),
+
+    /// Adjusts the interval to a moving average of the queue's packet rate: low latency while
+    /// the queue is mostly idle, fewer interrupts under load.
+    Adaptive,
+}
+
+/// Strategies for waiting between empty polls of an rx queue, see `rx_poll` on [`IxyDevice`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PollStrategy {
+    /// Polls again immediately, lowest latency at the cost of a fully used cpu core.
+    BusySpin,
+
+    /// Spins, then yields to other threads and finally sleeps for exponentially longer times
+    /// of up to `max` while no packets arrive.
+    Backoff { max: Duration },
+
+    /// Sleeps for the given time after every empty poll.
+    Sleep(Duration),
+}
+
+impl PollStrategy {
+    /// Waits after `empty_polls` consecutive empty polls.
+    fn wait(self, empty_polls: u32) {
+        match self {
+            PollStrategy::BusySpin => {}
+            PollStrategy::Backoff { max } => {
+                if empty_polls < BACKOFF_SPIN_POLLS {
+                    hint::spin_loop();
+                } else if empty_polls < BACKOFF_YIELD_POLLS {
+                    thread::yield_now();
+                } else {
+                    // start sleeping at 1 µs and double it with every empty poll
+                    let exp = (empty_polls - BACKOFF_YIELD_POLLS).min(20);
+                    thread::sleep(Duration::from_micros(1 << exp).min(max));
+                }
+            }
+            PollStrategy::Sleep(duration) => thread::sleep(duration),
+        }
+    }
 }
 
 /// Holds network card stats about sent and received packets.
@@ -172,9 +1861,9 @@ impl DeviceStats {
         pkts_old: u64,
         nanos: u32,
     ) -> f64 {
-        (((bytes_new - bytes_old) as f64 / 1_000_000.0 / (f64::from(nanos) / 1_000_000_000.0))
+        ((bytes_new - bytes_old) as f64 / 1_000_000.0 / (f64::from(nanos) / 1_000_000_000.0))
             * f64::from(8)
-            + self.diff_mpps(pkts_new, pkts_old, nanos) * f64::from(20) * f64::from(8))
+            + self.diff_mpps(pkts_new, pkts_old, nanos) * f64::from(20) * f64::from(8)
     }
 
     /// Returns Mpps between two points in time.
@@ -183,29 +1872,320 @@ impl DeviceStats {
     }
 }
 
+/// Snapshot of the registers of a network card, see `dump_registers` on [`IxyDevice`].
+#[derive(Default, Clone, Debug)]
+pub struct RegisterDump {
+    registers: Vec<(String, u32)>,
+}
+
+impl RegisterDump {
+    /// Appends register `name` with its `value` to the snapshot.
+    pub(crate) fn push(&mut self, name: impl Into<String>, value: u32) {
+        self.registers.push((name.into(), value));
+    }
+
+    /// Returns the value of register `name`, e.g. `"STATUS"` or `"RDT[0]"`.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.registers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, value)| value)
+    }
+
+    /// Returns an iterator over the names and values of the registers in the order they were
+    /// read.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.registers
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+}
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+        for (name, value) in self.iter() {
+            writeln!(f, "{:<width$}  {:#010x}", name, value, width = width)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Holds network card stats about errors, pause frames and broadcast/multicast packets.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct ExtendedStats {
+    /// Packets received with a CRC error.
+    pub rx_crc_errors: u64,
+    /// Packets received with an illegal symbol.
+    pub rx_illegal_byte_errors: u64,
+    /// Packets received with a length error.
+    pub rx_length_errors: u64,
+    /// Packets received that were too short.
+    pub rx_undersize: u64,
+    /// Packets received that were too short and had a bad CRC.
+    pub rx_fragments: u64,
+    /// Packets received that were too long.
+    pub rx_oversize: u64,
+    /// Packets received that were too long and had a bad CRC.
+    pub rx_jabbers: u64,
+    /// Packets missed because the rx packet buffer was full.
+    pub rx_missed: u64,
+    /// Packets dropped because an rx queue had no free descriptor.
+    pub rx_no_buffer: u64,
+    /// Broadcast packets received.
+    pub rx_broadcast: u64,
+    /// Multicast packets received.
+    pub rx_multicast: u64,
+    /// Broadcast packets sent.
+    pub tx_broadcast: u64,
+    /// Multicast packets sent.
+    pub tx_multicast: u64,
+    /// XON pause frames received.
+    pub rx_xon: u64,
+    /// XOFF pause frames received.
+    pub rx_xoff: u64,
+    /// XON pause frames sent.
+    pub tx_xon: u64,
+    /// XOFF pause frames sent.
+    pub tx_xoff: u64,
+    /// MAC local faults.
+    pub mac_local_faults: u64,
+    /// MAC remote faults.
+    pub mac_remote_faults: u64,
+    /// Packets received that matched a flow rule.
+    pub rx_flow_matches: u64,
+    /// Packets received that matched no flow rule.
+    pub rx_flow_misses: u64,
+}
+
 /// Initializes the network card at `pci_addr`.
 ///
 /// `rx_queues` and `tx_queues` specify the number of queues that will be initialized and used.
+///
+/// A `pci_addr` of the form `af_xdp:<interface>` uses an AF_XDP socket per queue on a network
+/// interface of the kernel instead, e.g. for cards without a driver in ixy.rs. A `pci_addr` of
+/// the form `tap:<interface>` exchanges packets with the kernel via a TAP interface, e.g. to
+/// develop applications without a network card or hugepages.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::*;
+///
+/// let dev = ixy_init("af_xdp:eth0", 1, 1).unwrap();
+/// let tap = ixy_init("tap:ixy0", 1, 1).unwrap();
+/// ```
 pub fn ixy_init(
     pci_addr: &str,
     rx_queues: u16,
     tx_queues: u16,
-) -> Result<Box<dyn IxyDevice>, Box<dyn Error>> {
-    let mut config_file = pci_open_resource(pci_addr, "config").expect("wrong pci address");
+) -> Result<Box<dyn IxyDevice>, IxyError> {
+    init_device(pci_addr, rx_queues, tx_queues, None, None)
+}
+
+/// Initializes the network card at `pci_addr` like [`ixy_init`], but allocates its descriptor
+/// rings and mempools with `allocator`.
+///
+/// The device has to be able to access the memory of `allocator`, i.e. memory for devices that
+/// use VFIO has to be mapped into the VFIO container like [`VfioAllocator`] does.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::HugepageAllocator;
+/// use ixy::*;
+/// use std::sync::Arc;
+///
+/// let dev = ixy_init_with_allocator("0000:01:00.0", 1, 1, Arc::new(HugepageAllocator)).unwrap();
+/// ```
+pub fn ixy_init_with_allocator(
+    pci_addr: &str,
+    rx_queues: u16,
+    tx_queues: u16,
+    allocator: Arc<dyn DmaAllocator>,
+) -> Result<Box<dyn IxyDevice>, IxyError> {
+    init_device(pci_addr, rx_queues, tx_queues, Some(allocator), None)
+}
+
+/// Initializes the network card at `pci_addr` like [`ixy_init`], but adds it to `container`
+/// instead of the VFIO container shared by all devices.
+///
+/// The descriptor rings and mempools of the device are allocated in `container`, so a
+/// container of its own isolates the device's memory from other devices. Devices that are not
+/// bound to vfio-pci ignore the container.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::*;
+///
+/// let dev1 = ixy_init_with_container("0000:01:00.0", 1, 1, &VfioContainer::new().unwrap());
+/// let dev2 = ixy_init_with_container("0000:01:00.1", 1, 1, &VfioContainer::new().unwrap());
+/// ```
+pub fn ixy_init_with_container(
+    pci_addr: &str,
+    rx_queues: u16,
+    tx_queues: u16,
+    container: &Arc<VfioContainer>,
+) -> Result<Box<dyn IxyDevice>, IxyError> {
+    init_device(
+        pci_addr,
+        rx_queues,
+        tx_queues,
+        None,
+        Some(Arc::clone(container)),
+    )
+}
+
+/// Returns whether one of the drivers supports the device with the given ids.
+pub(crate) fn is_supported_device(vendor_id: u16, device_id: u16) -> bool {
+    IxgbeDevice::supports(vendor_id, device_id)
+        || IxgbeVfDevice::supports(vendor_id, device_id)
+        || E1000Device::supports(vendor_id, device_id)
+        || I40eDevice::supports(vendor_id, device_id)
+        || IceDevice::supports(vendor_id, device_id)
+        || IgbDevice::supports(vendor_id, device_id)
+        || Vmxnet3Device::supports(vendor_id, device_id)
+        || VirtioDevice::supports(vendor_id, device_id)
+}
+
+/// Initializes the network card at `pci_addr` with the driver matching its ids.
+fn init_device(
+    pci_addr: &str,
+    rx_queues: u16,
+    tx_queues: u16,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    container: Option<Arc<VfioContainer>>,
+) -> Result<Box<dyn IxyDevice>, IxyError> {
+    // kernel interfaces are no pci devices, the kernel driver keeps the card
+    if pci_addr.starts_with(AF_XDP_PREFIX) {
+        let device = AfXdpDevice::init_with_allocator(pci_addr, rx_queues, tx_queues, allocator)?;
+
+        info!(
+            "using {} for interface {} with {} rx and {} tx queues",
+            device.get_driver_name(),
+            pci_addr,
+            rx_queues,
+            tx_queues
+        );
+
+        return Ok(Box::new(device));
+    }
+
+    if pci_addr.starts_with(TAP_PREFIX) {
+        let device = TapDevice::init(pci_addr, rx_queues, tx_queues)?;
+
+        info!(
+            "using {} for interface {} with {} rx and {} tx queues",
+            device.get_driver_name(),
+            pci_addr,
+            rx_queues,
+            tx_queues
+        );
+
+        return Ok(Box::new(device));
+    }
+
+    let config = PciDevice::open(pci_addr)?;
 
-    let vendor_id = read_io16(&mut config_file, 0)?;
-    let device_id = read_io16(&mut config_file, 2)?;
-    let class_id = read_io32(&mut config_file, 8)? >> 24;
+    let vendor_id = config.vendor_id()?;
+    let device_id = config.device_id()?;
+    let class_id = config.class()?;
 
     if class_id != 2 {
-        return Err(format!("device {} is not a network card", pci_addr).into());
+        return Err(IxyError::NotNetworkDevice(pci_addr.to_string()));
     }
 
-    if vendor_id == 0x1af4 && device_id >= 0x1000 {
-        unimplemented!("virtio driver is not implemented yet");
+    let device: Box<dyn IxyDevice> = if VirtioDevice::supports(vendor_id, device_id) {
+        Box::new(VirtioDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if IxgbeVfDevice::supports(vendor_id, device_id) {
+        Box::new(IxgbeVfDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if E1000Device::supports(vendor_id, device_id) {
+        Box::new(E1000Device::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if I40eDevice::supports(vendor_id, device_id) {
+        Box::new(I40eDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if IceDevice::supports(vendor_id, device_id) {
+        Box::new(IceDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if IgbDevice::supports(vendor_id, device_id) {
+        Box::new(IgbDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    } else if Vmxnet3Device::supports(vendor_id, device_id) {
+        Box::new(Vmxnet3Device::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
     } else {
         // let's give it a try with ixgbe
-        let device: IxgbeDevice = IxgbeDevice::init(pci_addr, rx_queues, tx_queues)?;
-        Ok(Box::new(device))
+        Box::new(IxgbeDevice::init_with_allocator(
+            pci_addr, rx_queues, tx_queues, allocator, container,
+        )?)
+    };
+
+    // only virtio devices negotiate features
+    let features = match device.get_negotiated_features() {
+        Ok(features) => format!(", negotiated features {:#018x}", features),
+        Err(_) => String::new(),
+    };
+
+    info!(
+        "using driver {} for device {} with {} rx and {} tx queues{}",
+        device.get_driver_name(),
+        pci_addr,
+        rx_queues,
+        tx_queues,
+        features
+    );
+
+    Ok(device)
+}
+
+/// Receives up to `max` packets on `src_queue` of `src` and sends them on `dst_queue` of `dst`
+/// without copying them. Packets `dst` can't send are dropped. Returns the number of forwarded
+/// packets.
+///
+/// `dst` transmits the packets straight from the mempool of `src`, so the mempool has to be
+/// accessible by `dst`. This is always the case without the IOMMU. With the IOMMU, ixy.rs
+/// shares a single VFIO container between all devices by default, so both devices have to use
+/// VFIO and must not have been initialized with a container of their own.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::*;
+///
+/// let mut dev1 = ixy_init("0000:01:00.0", 1, 1).unwrap();
+/// let mut dev2 = ixy_init("0000:01:00.1", 1, 1).unwrap();
+///
+/// loop {
+///     forward(&mut *dev1, 0, &mut *dev2, 0, 32);
+///     forward(&mut *dev2, 0, &mut *dev1, 0, 32);
+/// }
+/// ```
+pub fn forward(
+    src: &mut dyn IxyDevice,
+    src_queue: u32,
+    dst: &mut dyn IxyDevice,
+    dst_queue: u32,
+    max: usize,
+) -> usize {
+    let mut buffer = VecDeque::with_capacity(max);
+
+    if src.rx_batch(src_queue, &mut buffer, max) == 0 {
+        return 0;
     }
+
+    // packets left in the buffer are returned to their mempool when it is dropped
+    dst.tx_batch(dst_queue, &mut buffer)
 }
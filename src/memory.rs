@@ -1,45 +1,256 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::error::Error;
+use std::env;
+use std::ffi::CString;
 use std::fs;
-use std::io::{self, Read, Seek};
-use std::mem;
+use std::io;
+use std::mem::{self, MaybeUninit};
 use std::ops::{Deref, DerefMut};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
 use std::{ptr, slice};
 
-use crate::vfio::vfio_map_dma;
+use crate::vfio::VfioContainer;
+use crate::IxyError;
 
 const HUGE_PAGE_BITS: u32 = 21;
-const HUGE_PAGE_SIZE: usize = 1 << HUGE_PAGE_BITS;
+pub(crate) const HUGE_PAGE_SIZE: usize = 1 << HUGE_PAGE_BITS;
+const HUGE_PAGE_1G_SIZE: usize = 1 << 30;
+
+const CACHE_LINE_SIZE: usize = 64;
+
+// largest entry size that is padded if it doesn't divide the page size
+const MAX_PADDED_ENTRY_SIZE: usize = 16 * 1024;
+
+// an 802.1Q tag follows the mac addresses, it consists of the tag protocol identifier and TCI
+const VLAN_TPID: [u8; 2] = [0x81, 0x00];
+const VLAN_TAG_OFFSET: usize = 12;
+const VLAN_TAG_LEN: usize = 4;
 
 static HUGEPAGE_ID: AtomicUsize = AtomicUsize::new(0);
+static MEMPOOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // free entries cached by this thread, one cache per mempool
+    static MEMPOOL_CACHES: RefCell<Vec<MempoolCache>> = const { RefCell::new(Vec::new()) };
+}
 
-// we want one VFIO Container for all NICs, so every NIC can read from every
-// other NICs memory, especially the mempool. When not using the IOMMU / VFIO,
-// this variable is unused.
-pub(crate) static mut VFIO_CONTAINER_FILE_DESCRIPTOR: RawFd = -1;
+// overrides the hugetlbfs mount point used for 2 MiB pages
+const HUGETLBFS_PATH_ENV: &str = "IXY_HUGETLB_PATH";
+const DEFAULT_HUGETLBFS_PATH: &str = "/mnt/huge";
 
+/// Memory a network card can access via DMA.
+///
+/// The memory is unmapped when the `Dma` is dropped, so it has to outlive all descriptors and
+/// packets that point into it.
 pub struct Dma<T> {
     pub virt: *mut T,
     pub phys: usize,
+    pub size: usize,
+    mapping: Mapping,
+}
+
+/// How the memory of a `Dma` was mapped, i.e. what has to be undone when it is dropped.
+enum Mapping {
+    /// Locked hugepages accessed by their physical address.
+    Hugepage,
+    /// Hugepages mapped into the VFIO container.
+    Vfio(Arc<VfioContainer>),
+    /// Memory owned by someone else, e.g. a custom `DmaAllocator`.
+    External,
+    /// Ordinary memory of devices that don't use DMA.
+    Anonymous,
 }
 
 const MAP_HUGE_2MB: i32 = 0x5400_0000; // 21 << 26
+const MAP_HUGE_1GB: i32 = 0x7800_0000; // 30 << 26
+
+// pagemap entry bits, see Documentation/admin-guide/mm/pagemap.rst
+const PAGEMAP_PRESENT: u64 = 1 << 63;
+const PAGEMAP_SWAPPED: u64 = 1 << 62;
+const PAGEMAP_PFN_MASK: u64 = 0x007f_ffff_ffff_ffff;
+
+// memory policy for mbind, grabbed from linux/mempolicy.h
+const MPOL_BIND: i32 = 2;
+const MAX_NUMA_NODES: u32 = 64;
 
 impl<T> Dma<T> {
     /// Allocates dma memory on a huge page.
-    pub fn allocate(size: usize, require_contigous: bool) -> Result<Dma<T>, Box<dyn Error>> {
-        let size = if size % HUGE_PAGE_SIZE != 0 {
-            ((size >> HUGE_PAGE_BITS) + 1) << HUGE_PAGE_BITS
+    ///
+    /// Allocations of at least 1 GiB and contiguous ones larger than 2 MiB use 1 GiB pages if
+    /// enough are free, all others use 2 MiB pages. When using the IOMMU `phys` is an IOVA of the
+    /// shared VFIO container that is reused once the memory is dropped.
+    pub fn allocate(size: usize, require_contigous: bool) -> Result<Dma<T>, IxyError> {
+        Self::allocate_mapped(
+            size,
+            require_contigous,
+            None,
+            None,
+            shared_iommu().as_deref(),
+        )
+    }
+
+    /// Allocates dma memory on a huge page for the devices of `container`, see
+    /// [`Dma::allocate`].
+    ///
+    /// The memory is mapped into `container` if it uses an IOMMU, devices of other containers
+    /// can't access it.
+    pub fn allocate_in_container(
+        size: usize,
+        require_contigous: bool,
+        container: &VfioContainer,
+    ) -> Result<Dma<T>, IxyError> {
+        let container = if container.uses_iommu() {
+            Some(container)
         } else {
-            size
+            None
+        };
+
+        Self::allocate_mapped(size, require_contigous, None, None, container)
+    }
+
+    /// Allocates dma memory on a huge page of numa node `node`, e.g. the node a network card is
+    /// attached to, see [`Dma::allocate`].
+    ///
+    /// Fails if the node doesn't have enough free huge pages.
+    pub fn allocate_on_node(
+        size: usize,
+        require_contigous: bool,
+        node: u32,
+    ) -> Result<Dma<T>, IxyError> {
+        Self::allocate_mapped(
+            size,
+            require_contigous,
+            None,
+            Some(node),
+            shared_iommu().as_deref(),
+        )
+    }
+
+    /// Allocates dma memory on a huge page that is mapped at the IOMMU address `iova` of the
+    /// shared VFIO container.
+    ///
+    /// Requires the IOMMU, fails if `iova` is not aligned to the huge page size or the range
+    /// conflicts with an existing mapping.
+    pub fn allocate_at(size: usize, iova: usize) -> Result<Dma<T>, IxyError> {
+        let container = shared_iommu().ok_or_else(|| {
+            IxyError::InvalidConfiguration(
+                "choosing the iova requires the IOMMU / VFIO".to_string(),
+            )
+        })?;
+
+        if !iova.is_multiple_of(HUGE_PAGE_SIZE) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "iova {:#x} is not aligned to the huge page size",
+                iova
+            )));
+        }
+
+        Self::allocate_mapped(size, false, Some(iova), None, Some(&container))
+    }
+
+    /// Returns a `Dma` for `size` bytes of memory at `virt` that the network card accesses at
+    /// `phys`, e.g. memory of a custom [`DmaAllocator`].
+    ///
+    /// The memory is not freed when the `Dma` is dropped, its owner has to free it.
+    ///
+    /// # Safety
+    ///
+    /// The memory has to stay valid and mapped for the network card for the lifetime of the
+    /// `Dma`.
+    pub unsafe fn from_raw_parts(virt: *mut T, phys: usize, size: usize) -> Dma<T> {
+        Dma {
+            virt,
+            phys,
+            size,
+            mapping: Mapping::External,
+        }
+    }
+
+    /// Faults in all pages of this memory by touching them, so the first accesses in the hot
+    /// path don't take page faults. The content of the memory is preserved.
+    ///
+    /// Memory of [`Dma::allocate`] is locked or pinned by the IOMMU and therefore already
+    /// resident, this is meant for memory of custom allocators and is done for all mempools.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::Dma;
+    ///
+    /// let dma: Dma<u8> = Dma::allocate(16 * 1024 * 1024, false).unwrap();
+    /// dma.prefault();
+    /// ```
+    pub fn prefault(&self) {
+        let pagesize = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) } as usize;
+        let base = self.virt as *mut u8;
+
+        for offset in (0..self.size).step_by(pagesize) {
+            unsafe {
+                let addr = base.add(offset);
+                ptr::write_volatile(addr, ptr::read_volatile(addr));
+            }
+        }
+    }
+
+    /// Returns this memory as memory of type `U` without unmapping it.
+    pub(crate) fn cast<U>(self) -> Dma<U> {
+        let dma = mem::ManuallyDrop::new(self);
+
+        Dma {
+            virt: dma.virt as *mut U,
+            phys: dma.phys,
+            size: dma.size,
+            // moved out of the ManuallyDrop, which is never dropped
+            mapping: unsafe { ptr::read(&dma.mapping) },
+        }
+    }
+
+    /// Allocates dma memory that is mapped into `container` if there is one.
+    fn allocate_mapped(
+        size: usize,
+        require_contigous: bool,
+        iova: Option<usize>,
+        node: Option<u32>,
+        container: Option<&VfioContainer>,
+    ) -> Result<Dma<T>, IxyError> {
+        // use 1 GiB pages for huge allocations and contiguous ones that don't fit a 2 MiB page,
+        // but only if enough of them are free, otherwise fall back to 2 MiB pages
+        let wants_1g = size >= HUGE_PAGE_1G_SIZE || (require_contigous && size > HUGE_PAGE_SIZE);
+        let page_size = if wants_1g
+            && free_huge_pages(HUGE_PAGE_1G_SIZE, node) >= size.div_ceil(HUGE_PAGE_1G_SIZE)
+        {
+            HUGE_PAGE_1G_SIZE
+        } else {
+            HUGE_PAGE_SIZE
         };
 
-        if get_vfio_container() != -1 {
+        let size = size.next_multiple_of(page_size);
+
+        // the node's pages are only taken once the memory is touched, fail early instead
+        if let Some(node) = node {
+            if free_huge_pages(page_size, Some(node)) < size / page_size {
+                return Err(IxyError::HugepageUnavailable(format!(
+                    "numa node {} has less than {} free hugepages of {} KiB",
+                    node,
+                    size / page_size,
+                    page_size / 1024
+                )));
+            }
+        }
+
+        let page_size_flag = if page_size == HUGE_PAGE_1G_SIZE {
+            MAP_HUGE_1GB
+        } else {
+            MAP_HUGE_2MB
+        };
+
+        if let Some(container) = container {
             debug!("allocating dma memory via VFIO");
 
             let ptr = unsafe {
@@ -47,7 +258,7 @@ impl<T> Dma<T> {
                     ptr::null_mut(),
                     size,
                     libc::PROT_READ | libc::PROT_WRITE,
-                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB | MAP_HUGE_2MB,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB | page_size_flag,
                     -1,
                     0,
                 )
@@ -55,29 +266,49 @@ impl<T> Dma<T> {
 
             // This is the main IOMMU work: IOMMU DMA MAP the memory...
             if ptr == libc::MAP_FAILED {
-                Err("failed to memory map ".into())
+                Err(IxyError::HugepageUnavailable(
+                    "failed to memory map hugepage - hugepages enabled and free?".to_string(),
+                ))
             } else {
-                let iova = vfio_map_dma(ptr as usize, size)?;
+                if let Some(node) = node {
+                    bind_to_node(ptr, size, node)?;
+                }
+
+                let iova = match iova {
+                    Some(iova) => container.map_dma_at(ptr as usize, size, iova)?,
+                    None => container.map_dma(ptr as usize, size)?,
+                };
 
                 let memory = Dma {
                     virt: ptr as *mut T,
                     phys: iova,
+                    size,
+                    mapping: Mapping::Vfio(container.arc()),
                 };
 
                 Ok(memory)
             }
         } else {
-            if require_contigous && size > HUGE_PAGE_SIZE {
-                return Err("failed to map physically contigous memory".into());
+            if require_contigous && size > page_size {
+                return Err(IxyError::HugepageUnavailable(format!(
+                    "failed to map {} bytes of physically contigous memory",
+                    size
+                )));
+            }
+
+            // the hugetlbfs mount uses 2 MiB pages, so 1 GiB pages are mapped anonymously
+            if page_size == HUGE_PAGE_1G_SIZE {
+                return Self::lock(map_anonymous(size, page_size_flag), size, node);
             }
 
             let id = HUGEPAGE_ID.fetch_add(1, Ordering::SeqCst);
-            let path = format!("/mnt/huge/ixy-{}-{}", process::id(), id);
+            let path = hugetlbfs_path().join(format!("ixy-{}-{}", process::id(), id));
 
             match fs::OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(path.clone())
             {
                 Ok(f) => {
@@ -89,49 +320,346 @@ impl<T> Dma<T> {
                             libc::MAP_SHARED | libc::MAP_HUGETLB,
                             f.as_raw_fd(),
                             0,
-                        ) as *mut T
+                        )
                     };
 
-                    if ptr.is_null() {
-                        Err("failed to memory map hugepage - hugepages enabled and free?".into())
-                    } else if unsafe { libc::mlock(ptr as *mut libc::c_void, size) } == 0 {
-                        let memory = Dma {
-                            virt: ptr,
-                            phys: virt_to_phys(ptr as usize)?,
-                        };
-
-                        Ok(memory)
-                    } else {
-                        Err("failed to memory lock hugepage".into())
+                    // the mapping keeps the hugepages alive, removing the file right away returns
+                    // them to the system once they are unmapped or the process exits, even if it crashes
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("failed to remove hugepage {}: {}", path.display(), e);
                     }
+
+                    Self::lock(ptr, size, node)
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    // hugetlbfs is not mounted, anonymous hugepages don't need a mount
+                    debug!(
+                        "hugepage {} could not be created, using anonymous hugepages",
+                        path.display()
+                    );
+
+                    Self::lock(map_anonymous(size, page_size_flag), size, node)
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(Box::new(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    format!(
-                        "hugepage {} could not be created - hugepages enabled?",
-                        path
-                    ),
-                ))),
-                Err(e) => Err(Box::new(e)),
+                Err(e) => Err(IxyError::Io(e)),
             }
         }
     }
+
+    /// Locks the `size` bytes of hugepage memory mapped at `ptr`, optionally on numa node
+    /// `node`, and resolves its physical address.
+    fn lock(ptr: *mut libc::c_void, size: usize, node: Option<u32>) -> Result<Dma<T>, IxyError> {
+        if ptr == libc::MAP_FAILED {
+            return Err(IxyError::HugepageUnavailable(
+                "failed to memory map hugepage - hugepages enabled and free?".to_string(),
+            ));
+        }
+
+        if let Some(node) = node {
+            bind_to_node(ptr, size, node)?;
+        }
+
+        if unsafe { libc::mlock(ptr, size) } == 0 {
+            let memory = Dma {
+                virt: ptr as *mut T,
+                phys: virt_to_phys(ptr as usize)?,
+                size,
+                mapping: Mapping::Hugepage,
+            };
+
+            Ok(memory)
+        } else {
+            Err(IxyError::InsufficientPrivileges {
+                detail: format!(
+                    "failed to memory lock hugepage, RLIMIT_MEMLOCK is {}",
+                    format_memlock_limit()
+                ),
+            })
+        }
+    }
+}
+
+// the memory is owned by the `Dma` and only accessed through its raw pointer
+unsafe impl<T: Send> Send for Dma<T> {}
+unsafe impl<T: Sync> Sync for Dma<T> {}
+
+impl<T> Drop for Dma<T> {
+    fn drop(&mut self) {
+        match self.mapping {
+            Mapping::External => return,
+            Mapping::Vfio(ref container) => {
+                if let Err(e) = container.unmap_dma(self.phys, self.size) {
+                    warn!("failed to unmap dma memory at iova {:#x}: {}", self.phys, e);
+                }
+            }
+            Mapping::Hugepage => unsafe {
+                libc::munlock(self.virt as *mut libc::c_void, self.size);
+            },
+            Mapping::Anonymous => {}
+        }
+
+        if unsafe { libc::munmap(self.virt as *mut libc::c_void, self.size) } != 0 {
+            warn!(
+                "failed to unmap dma memory at {:p}: {}",
+                self.virt,
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Allocates the memory network cards access via DMA and translates its addresses.
+///
+/// Mempools and drivers allocate their memory through a `DmaAllocator`, which allows plugging
+/// in custom backends like reserved memory regions or test shims. [`HugepageAllocator`] and
+/// [`VfioAllocator`] are the built-in ones, [`default_allocator`] picks the one that matches
+/// the current setup. A [`VfioContainer`] allocates memory mapped into that container.
+///
+/// Custom allocators return memory they own via [`Dma::from_raw_parts`], which is not unmapped
+/// when dropped.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::{default_allocator, Mempool};
+///
+/// let pool = Mempool::allocate_in(default_allocator(), 2048, 0).unwrap();
+/// ```
+pub trait DmaAllocator: Send + Sync {
+    /// Allocates `size` bytes of dma memory, physically contiguous if `require_contiguous` is
+    /// set.
+    fn allocate(&self, size: usize, require_contiguous: bool) -> Result<Dma<u8>, IxyError>;
+
+    /// Returns the address the network card uses to access the byte `offset` bytes into `dma`,
+    /// which has been allocated by this allocator.
+    fn translate(&self, dma: &Dma<u8>, offset: usize) -> Result<usize, IxyError>;
+
+    /// Returns the addresses of all `offsets` into `dma`, see [`DmaAllocator::translate`].
+    fn translate_batch(&self, dma: &Dma<u8>, offsets: &[usize]) -> Result<Vec<usize>, IxyError> {
+        offsets
+            .iter()
+            .map(|&offset| self.translate(dma, offset))
+            .collect()
+    }
+}
+
+/// Allocates hugepages that network cards access by their physical addresses, i.e. without
+/// the IOMMU.
+pub struct HugepageAllocator;
+
+impl DmaAllocator for HugepageAllocator {
+    fn allocate(&self, size: usize, require_contiguous: bool) -> Result<Dma<u8>, IxyError> {
+        Dma::allocate_mapped(size, require_contiguous, None, None, None)
+    }
+
+    fn translate(&self, dma: &Dma<u8>, offset: usize) -> Result<usize, IxyError> {
+        virt_to_phys(dma.virt as usize + offset)
+    }
+
+    fn translate_batch(&self, dma: &Dma<u8>, offsets: &[usize]) -> Result<Vec<usize>, IxyError> {
+        let addrs: Vec<usize> = offsets.iter().map(|o| dma.virt as usize + o).collect();
+
+        virt_to_phys_batch(&addrs)
+    }
+}
+
+/// Allocates hugepages that are mapped into the shared VFIO container, network cards access
+/// them by their IOMMU addresses.
+///
+/// Requires a device that was initialized with VFIO.
+pub struct VfioAllocator;
+
+impl DmaAllocator for VfioAllocator {
+    fn allocate(&self, size: usize, require_contiguous: bool) -> Result<Dma<u8>, IxyError> {
+        let container = shared_iommu().ok_or_else(|| {
+            IxyError::InvalidConfiguration(
+                "no VFIO container with an IOMMU, initialize a device with VFIO first".to_string(),
+            )
+        })?;
+
+        Dma::allocate_mapped(size, require_contiguous, None, None, Some(&container))
+    }
+
+    fn translate(&self, dma: &Dma<u8>, offset: usize) -> Result<usize, IxyError> {
+        // the whole region is mapped in one piece
+        Ok(dma.phys + offset)
+    }
+}
+
+impl DmaAllocator for VfioContainer {
+    fn allocate(&self, size: usize, require_contiguous: bool) -> Result<Dma<u8>, IxyError> {
+        Dma::allocate_in_container(size, require_contiguous, self)
+    }
+
+    fn translate(&self, dma: &Dma<u8>, offset: usize) -> Result<usize, IxyError> {
+        if self.uses_iommu() {
+            Ok(dma.phys + offset)
+        } else {
+            HugepageAllocator.translate(dma, offset)
+        }
+    }
+
+    fn translate_batch(&self, dma: &Dma<u8>, offsets: &[usize]) -> Result<Vec<usize>, IxyError> {
+        if self.uses_iommu() {
+            Ok(offsets.iter().map(|o| dma.phys + o).collect())
+        } else {
+            HugepageAllocator.translate_batch(dma, offsets)
+        }
+    }
+}
+
+/// Returns the [`VfioAllocator`] if a device uses VFIO, the [`HugepageAllocator`] otherwise.
+///
+/// This is the allocator [`Dma::allocate`] and [`Mempool::allocate`] use.
+pub fn default_allocator() -> &'static dyn DmaAllocator {
+    if shared_iommu().is_some() {
+        &VfioAllocator
+    } else {
+        &HugepageAllocator
+    }
+}
+
+/// Returns the shared VFIO container if dma memory is mapped into it, i.e. a device uses it
+/// and it has an IOMMU.
+fn shared_iommu() -> Option<Arc<VfioContainer>> {
+    VfioContainer::shared_if_open().filter(|container| container.uses_iommu())
 }
 
 pub struct Packet {
     pub(crate) addr_virt: *mut u8,
     pub(crate) addr_phys: usize,
     pub(crate) len: usize,
-    pub(crate) pool: Rc<Mempool>,
+    pub(crate) pool: Arc<Mempool>,
     pub(crate) pool_entry: usize,
+    pub(crate) next: Option<Box<Packet>>,
+    pub(crate) meta: PacketMeta,
+}
+
+/// Metadata of a [`Packet`], filled in by the driver that received it and the offloads
+/// requested from the driver that sends it.
+///
+/// Fields a driver or network card doesn't support keep their default values. Chained segments
+/// carry the metadata of the whole packet in their first segment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketMeta {
+    /// RSS hash the network card computed over the packet's flow, if any.
+    pub rss_hash: Option<u32>,
+
+    /// Driver specific packet type, e.g. the PKTTYPE field of the rx descriptor for ixgbe.
+    pub packet_type: u32,
+
+    /// Receive timestamp in units of the network card's clock, if timestamping is enabled.
+    pub timestamp: Option<u64>,
+
+    /// Driver specific offload status and error flags, e.g. the status and error bits of the rx
+    /// descriptor for ixgbe.
+    pub offload_flags: u32,
+
+    /// Tag control information (TCI) of the packet's VLAN tag, i.e. priority and VLAN id.
+    ///
+    /// On rx this is the tag the driver stripped if VLAN stripping is enabled, see
+    /// `set_vlan_strip` on [`IxyDevice`](crate::IxyDevice). On tx drivers that support VLAN
+    /// stripping insert a tag with this TCI.
+    pub vlan_tci: Option<u16>,
+
+    /// Whether the network card found the IPv4 header checksum to be valid, [`None`] if it
+    /// didn't verify it, e.g. for other protocols.
+    pub l3_checksum_ok: Option<bool>,
+
+    /// Whether the network card found the TCP or UDP checksum to be valid, [`None`] if it
+    /// didn't verify it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let verified = dev
+    ///     .rx_iter(0, 32)
+    ///     .filter(|p| p.meta().l4_checksum_ok == Some(true))
+    ///     .count();
+    /// ```
+    pub l4_checksum_ok: Option<bool>,
+
+    /// Checksums and segmentation the driver applies when sending the packet.
+    pub tx_offloads: TxOffloads,
+
+    /// Scratch space for applications, drivers never touch it.
+    pub user: [u8; 16],
+}
+
+/// Checksums and segmentation a driver applies when sending a [`Packet`], offloaded to the
+/// network card if it supports that.
+///
+/// Ethernet frames with IPv4 or IPv6 and TCP or UDP are supported, with up to one VLAN tag but
+/// without IPv6 extension headers. The headers have to be in the first segment of the packet.
+/// Drivers without these offloads ignore them.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::checksum::pseudo_header_checksum;
+/// use ixy::memory::{alloc_pkt, Mempool};
+///
+/// let pool = Mempool::allocate(2048, 0).unwrap();
+/// let mut p = alloc_pkt(&pool, 60).unwrap();
+/// // ... fill in the ethernet, IPv4 and UDP headers
+///
+/// let checksum = pseudo_header_checksum(&p[14..34]);
+/// p[40..42].copy_from_slice(&checksum.to_be_bytes());
+///
+/// p.meta_mut().tx_offloads.ipv4_checksum = true;
+/// p.meta_mut().tx_offloads.l4_checksum = true;
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TxOffloads {
+    /// Fill in the header checksum of an IPv4 packet, its checksum field has to be zero.
+    pub ipv4_checksum: bool,
+
+    /// Fill in the TCP or UDP checksum, the checksum field has to hold the checksum of the
+    /// pseudo header, see [`pseudo_header_checksum`](crate::checksum::pseudo_header_checksum).
+    pub l4_checksum: bool,
+
+    /// Split a TCP packet into segments of at most this many payload bytes each (TCP
+    /// segmentation offload), e.g. to send a packet larger than the mtu.
+    ///
+    /// The headers of the packet describe the whole packet, i.e. the IP length fields cover its
+    /// entire payload. The driver fills in the IPv4 and TCP checksums of all segments, the other
+    /// fields of this struct are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{alloc_pkt, Mempool};
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    ///
+    /// let mut p = alloc_pkt(&pool, 1514).unwrap();
+    /// // ... fill in the ethernet, IPv4 and TCP headers for all segments
+    ///
+    /// // the payload continues in chained segments
+    /// for _ in 0..8 {
+    ///     p.chain(alloc_pkt(&pool, 1500).unwrap());
+    /// }
+    ///
+    /// p.meta_mut().tx_offloads.tso_mss = Some(1460);
+    ///
+    /// dev.tx_batch(0, &mut VecDeque::from(vec![p]));
+    /// ```
+    pub tso_mss: Option<u16>,
 }
 
 impl Clone for Packet {
+    /// Returns a copy of this packet allocated from the same pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is empty, see [`Packet::try_clone`].
     fn clone(&self) -> Self {
-        let mut p = alloc_pkt(&self.pool, self.len).expect("no buffer available");
-        p.clone_from_slice(&self);
-
-        p
+        self.try_clone().expect("no buffer available")
     }
 }
 
@@ -149,6 +677,9 @@ impl DerefMut for Packet {
     }
 }
 
+// the packet owns its pool entry and the pool keeps the entry's memory alive
+unsafe impl Send for Packet {}
+
 impl Drop for Packet {
     fn drop(&mut self) {
         //println!("drop");
@@ -162,7 +693,7 @@ impl Packet {
         addr_virt: *mut u8,
         addr_phys: usize,
         len: usize,
-        pool: Rc<Mempool>,
+        pool: Arc<Mempool>,
         pool_entry: usize,
     ) -> Packet {
         Packet {
@@ -171,6 +702,8 @@ impl Packet {
             len,
             pool,
             pool_entry,
+            next: None,
+            meta: PacketMeta::default(),
         }
     }
 
@@ -184,135 +717,1244 @@ impl Packet {
         self.addr_phys
     }
 
-    /// Returns a reference to the packet`s pool.
-    pub fn get_pool(&self) -> &Rc<Mempool> {
-        &self.pool
+    /// Returns the number of unused bytes in front of the packet data.
+    pub fn headroom(&self) -> usize {
+        self.addr_virt as usize - unsafe { self.pool.get_entry_addr(self.pool_entry) } as usize
     }
 
-    /// Prefetch the (first cacheline of) packet content.
+    /// Returns the number of unused bytes behind the packet data.
+    pub fn tailroom(&self) -> usize {
+        self.pool.entry_size - self.headroom() - self.len
+    }
+
+    /// Grows the packet by `n` bytes at the front, e.g. to add an encapsulation header, and
+    /// returns the new bytes. Returns [`None`] if the headroom is smaller than `n`.
     ///
-    /// The temporal consistency is chosen by the user, where strong consistency will lead to lower
-    /// access times at the cost of cache space in stepwise lower cache tiers (smaller). This
-    /// method is only available on `x86` or `x86_64` architectures with `sse` enabled.
+    /// The new bytes are not initialized, i.e. they contain whatever the buffer held before.
     ///
-    /// ```bash
-    /// RUSTFLAGS="-C target-cpu=native -C target-feature=+sse" cargo build …
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{alloc_pkt, Mempool};
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    /// pool.set_headroom(128);
+    ///
+    /// let mut p = alloc_pkt(&pool, 60).unwrap();
+    /// p.prepend(4).unwrap().copy_from_slice(&[0x81, 0x00, 0x00, 0x2a]);
+    /// assert_eq!(p.len(), 64);
     /// ```
-    #[cfg(all(
-        any(target_arch = "x86", target_arch = "x86_64"),
-        target_feature = "sse"
-    ))]
-    #[inline(always)]
-    pub fn prefetch(&self, hint: Prefetch) {
-        #[cfg(target_arch = "x86")]
-        use core::arch::x86;
-        #[cfg(target_arch = "x86_64")]
-        use core::arch::x86_64 as x86;
-
-        let addr = self.get_virt_addr() as *const _;
-        unsafe {
-            match hint {
-                Prefetch::Time0 => x86::_mm_prefetch(addr, x86::_MM_HINT_T0),
-                Prefetch::Time1 => x86::_mm_prefetch(addr, x86::_MM_HINT_T1),
-                Prefetch::Time2 => x86::_mm_prefetch(addr, x86::_MM_HINT_T2),
-                Prefetch::NonTemporal => x86::_mm_prefetch(addr, x86::_MM_HINT_NTA),
-            }
+    pub fn prepend(&mut self, n: usize) -> Option<&mut [u8]> {
+        if n > self.headroom() {
+            return None;
         }
-    }
-}
 
-/// Common representation for prefetch strategies.
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Prefetch {
-    /// Corresponds to _MM_HINT_T0 on x86 sse.
-    Time0,
+        self.addr_virt = unsafe { self.addr_virt.sub(n) };
+        self.addr_phys -= n;
+        self.len += n;
 
-    /// Corresponds to _MM_HINT_T1 on x86 sse.
-    Time1,
+        Some(&mut self[..n])
+    }
 
-    /// Corresponds to _MM_HINT_T2 on x86 sse.
-    Time2,
+    /// Grows the packet by `n` bytes at the end and returns the new bytes. Returns [`None`] if
+    /// the tailroom is smaller than `n`.
+    ///
+    /// The new bytes are not initialized, i.e. they contain whatever the buffer held before.
+    pub fn append(&mut self, n: usize) -> Option<&mut [u8]> {
+        if n > self.tailroom() {
+            return None;
+        }
 
-    /// Corresponds to _MM_HINT_NTA on x86 sse.
-    NonTemporal,
-}
+        self.len += n;
 
-pub struct Mempool {
-    base_addr: *mut u8,
-    num_entries: usize,
-    entry_size: usize,
-    phys_addresses: Vec<usize>,
-    pub(crate) free_stack: RefCell<Vec<usize>>,
-}
+        let len = self.len;
+        Some(&mut self[len - n..])
+    }
 
-impl Mempool {
-    /// Allocates a new `Mempool`.
+    /// Removes the 802.1Q tag following the mac addresses and returns its TCI, or returns
+    /// [`None`] if the packet is untagged.
     ///
-    /// # Panics
+    /// The mac addresses are moved by the length of the tag, the rest of the packet stays in
+    /// place.
     ///
-    /// Panics if `size` is not a divisor of the page size.
-    pub fn allocate(entries: usize, size: usize) -> Result<Rc<Mempool>, Box<dyn Error>> {
-        let entry_size = match size {
-            0 => 2048,
-            x => x,
-        };
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{alloc_pkt, Mempool};
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    ///
+    /// let mut p = alloc_pkt(&pool, 64).unwrap();
+    /// p[12..16].copy_from_slice(&[0x81, 0x00, 0x00, 0x2a]);
+    ///
+    /// assert_eq!(p.strip_vlan_tag(), Some(42));
+    /// assert_eq!(p.len(), 60);
+    /// ```
+    pub fn strip_vlan_tag(&mut self) -> Option<u16> {
+        let tci = self.vlan_tag()?;
+
+        self[..VLAN_TAG_OFFSET + VLAN_TAG_LEN].copy_within(..VLAN_TAG_OFFSET, VLAN_TAG_LEN);
+        self.addr_virt = unsafe { self.addr_virt.add(VLAN_TAG_LEN) };
+        self.addr_phys += VLAN_TAG_LEN;
+        self.len -= VLAN_TAG_LEN;
 
-        if (get_vfio_container() == -1) && HUGE_PAGE_SIZE % entry_size != 0 {
-            panic!("entry size must be a divisor of the page size");
+        Some(tci)
+    }
+
+    /// Returns the TCI of the 802.1Q tag following the mac addresses, or [`None`] if the packet
+    /// is untagged.
+    pub(crate) fn vlan_tag(&self) -> Option<u16> {
+        if self.len < VLAN_TAG_OFFSET + VLAN_TAG_LEN
+            || self[VLAN_TAG_OFFSET..VLAN_TAG_OFFSET + 2] != VLAN_TPID
+        {
+            return None;
         }
 
-        let dma: Dma<u8> = Dma::allocate(entries * entry_size, false)?;
-        let mut phys_addresses = Vec::with_capacity(entries);
+        Some(u16::from_be_bytes([
+            self[VLAN_TAG_OFFSET + 2],
+            self[VLAN_TAG_OFFSET + 3],
+        ]))
+    }
 
-        for i in 0..entries {
-            if get_vfio_container() != -1 {
-                phys_addresses.push(unsafe { dma.virt.add(i * entry_size) } as usize);
-            } else {
-                phys_addresses
-                    .push(unsafe { virt_to_phys(dma.virt.add(i * entry_size) as usize)? });
-            }
+    /// Inserts an 802.1Q tag with `tci` after the mac addresses, using the headroom of the
+    /// packet. Returns false if the packet is shorter than the mac addresses or the headroom is
+    /// smaller than the tag.
+    pub fn insert_vlan_tag(&mut self, tci: u16) -> bool {
+        if self.len < VLAN_TAG_OFFSET || self.prepend(VLAN_TAG_LEN).is_none() {
+            return false;
         }
 
-        let pool = Mempool {
-            base_addr: dma.virt,
-            num_entries: entries,
-            entry_size,
-            phys_addresses,
-            free_stack: RefCell::new(Vec::with_capacity(entries)),
-        };
+        self[..VLAN_TAG_OFFSET + VLAN_TAG_LEN].copy_within(VLAN_TAG_LEN.., 0);
+        self[VLAN_TAG_OFFSET..VLAN_TAG_OFFSET + 2].copy_from_slice(&VLAN_TPID);
+        self[VLAN_TAG_OFFSET + 2..VLAN_TAG_OFFSET + 4].copy_from_slice(&tci.to_be_bytes());
 
-        unsafe { memset(pool.base_addr, pool.num_entries * pool.entry_size, 0x00) }
+        true
+    }
 
-        let pool = Rc::new(pool);
-        pool.free_stack.borrow_mut().extend(0..entries);
+    /// Shrinks the packet by `n` bytes at the end, e.g. to remove a trailer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the length of the packet.
+    pub fn trim(&mut self, n: usize) {
+        assert!(
+            n <= self.len,
+            "cannot trim {} bytes from packet of {} bytes",
+            n,
+            self.len
+        );
 
-        Ok(pool)
+        self.len -= n;
     }
 
-    /// Removes a packet from the packet pool and returns it, or [`None`] if the pool is empty.
-    pub(crate) fn alloc_buf(&self) -> Option<usize> {
-        self.free_stack.borrow_mut().pop()
+    /// Returns the maximum length of the packet, i.e. its buffer size minus the headroom.
+    pub fn capacity(&self) -> usize {
+        self.pool.entry_size - self.headroom()
     }
 
-    /// Returns a packet to the packet pool.
-    pub(crate) fn free_buf(&self, id: usize) {
-        self.free_stack.borrow_mut().push(id);
+    /// Shrinks or grows the packet to `new_len` bytes in place, e.g. to reuse a received buffer
+    /// for a packet of a different size.
+    ///
+    /// Grown bytes are not initialized, i.e. they contain whatever the buffer held before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` exceeds the capacity of the packet.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{alloc_pkt, Mempool};
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    ///
+    /// let mut p = alloc_pkt(&pool, 1500).unwrap();
+    /// p.set_len(64);
+    /// assert_eq!(p.len(), 64);
+    /// ```
+    pub fn set_len(&mut self, new_len: usize) {
+        assert!(
+            new_len <= self.capacity(),
+            "cannot resize packet to {} bytes: capacity is {}",
+            new_len,
+            self.capacity()
+        );
+
+        self.len = new_len;
     }
 
-    /// Returns a packet to the packet pool.
-    pub(crate) unsafe fn get_virt_addr(&self, id: usize) -> *mut u8 {
-        self.base_addr.add(id * self.entry_size)
+    /// Returns the metadata of this packet.
+    pub fn meta(&self) -> &PacketMeta {
+        &self.meta
     }
 
-    /// Returns a packet to the packet pool.
+    /// Returns the metadata of this packet for modification, e.g. to use its scratch space.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let mut buffer = VecDeque::new();
+    ///
+    /// dev.rx_batch(0, &mut buffer, 32);
+    /// for p in buffer.iter_mut() {
+    ///     let hash = p.meta().rss_hash.unwrap_or(0);
+    ///     p.meta_mut().user[..4].copy_from_slice(&hash.to_le_bytes());
+    /// }
+    /// ```
+    pub fn meta_mut(&mut self) -> &mut PacketMeta {
+        &mut self.meta
+    }
+
+    /// Appends `next` and all segments chained to it to the last segment of this packet.
+    ///
+    /// A packet whose data doesn't fit into a single mempool entry, e.g. a jumbo frame, consists
+    /// of several chained segments. Network cards send them as one frame and received frames
+    /// that span several buffers are chained as well. Dereferencing a packet only yields the
+    /// data of its first segment, see [`Packet::segments`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{alloc_pkt, Mempool};
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    ///
+    /// let mut header = alloc_pkt(&pool, 42).unwrap();
+    /// let payload = alloc_pkt(&pool, 1024).unwrap();
+    /// header.chain(payload);
+    ///
+    /// assert_eq!(header.num_segments(), 2);
+    /// assert_eq!(header.total_len(), 1066);
+    /// ```
+    pub fn chain(&mut self, next: Packet) {
+        let mut last = self;
+        while last.next.is_some() {
+            last = last.next.as_mut().unwrap();
+        }
+
+        last.next = Some(Box::new(next));
+    }
+
+    /// Detaches and returns the segments chained to this packet, if any.
+    pub fn unchain(&mut self) -> Option<Packet> {
+        self.next.take().map(|next| *next)
+    }
+
+    /// Returns an iterator over the segments of this packet, starting with this packet.
+    pub fn segments(&self) -> Segments<'_> {
+        Segments { next: Some(self) }
+    }
+
+    /// Returns the number of segments of this packet.
+    pub fn num_segments(&self) -> usize {
+        self.segments().count()
+    }
+
+    /// Returns the length of all segments of this packet combined.
+    pub fn total_len(&self) -> usize {
+        self.segments().map(|s| s.len).sum()
+    }
+
+    /// Returns a copy of this packet allocated from the same pool, or [`None`] if the pool is
+    /// empty. Clones may use the pool's reserved buffers, see [`Mempool::set_reserve`].
+    ///
+    /// The copy has the same headroom and segments as this packet.
+    pub fn try_clone(&self) -> Option<Packet> {
+        self.clone_segments(|segment| segment.try_clone_segment(&segment.pool, segment.headroom()))
+    }
+
+    /// Returns a copy of this packet allocated from `pool`, e.g. to keep it around without
+    /// holding on to a buffer of the device's rx pool. Returns [`None`] if `pool` is empty or a
+    /// segment doesn't fit into an entry of `pool`.
+    ///
+    /// The copy has the same segments as this packet and the headroom of `pool`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::Mempool;
+    /// use ixy::*;
+    ///
+    /// let mut dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+    /// let pool = Mempool::allocate(1024, 0).unwrap();
+    ///
+    /// let copies: Vec<_> = dev
+    ///     .rx_iter(0, 32)
+    ///     .filter_map(|p| p.try_clone_into(&pool))
+    ///     .collect();
+    /// ```
+    pub fn try_clone_into(&self, pool: &Arc<Mempool>) -> Option<Packet> {
+        let headroom = pool.headroom();
+
+        self.clone_segments(|segment| {
+            if segment.len > pool.entry_size - headroom {
+                return None;
+            }
+
+            segment.try_clone_segment(pool, headroom)
+        })
+    }
+
+    /// Returns a copy of this packet and its metadata whose segments are cloned by
+    /// `clone_segment`.
+    fn clone_segments<F>(&self, clone_segment: F) -> Option<Packet>
+    where
+        F: Fn(&Packet) -> Option<Packet>,
+    {
+        let mut segments = self.segments();
+        let mut p = clone_segment(segments.next()?)?;
+        p.meta = self.meta;
+
+        for segment in segments {
+            p.chain(clone_segment(segment)?);
+        }
+
+        Some(p)
+    }
+
+    /// Returns a copy of this segment in `pool` without the segments chained to it.
+    fn try_clone_segment(&self, pool: &Arc<Mempool>, headroom: usize) -> Option<Packet> {
+        let id = pool.alloc_buf()?;
+
+        let mut p = unsafe {
+            Packet::new(
+                pool.get_entry_addr(id).add(headroom),
+                pool.phys_addresses[id] + headroom,
+                self.len,
+                pool.clone(),
+                id,
+            )
+        };
+        p.clone_from_slice(self);
+
+        Some(p)
+    }
+
+    /// Returns the `Packet` for entry `id` of `pool`.
+    pub(crate) unsafe fn from_pool(pool: &Arc<Mempool>, id: usize, len: usize) -> Packet {
+        Packet::new(
+            pool.get_virt_addr(id),
+            pool.get_phys_addr(id),
+            len,
+            pool.clone(),
+            id,
+        )
+    }
+
+    /// Returns a reference to the packet`s pool.
+    pub fn get_pool(&self) -> &Arc<Mempool> {
+        &self.pool
+    }
+
+    /// Prefetch the (first cacheline of) packet content.
+    ///
+    /// The temporal consistency is chosen by the user, where strong consistency will lead to lower
+    /// access times at the cost of cache space in stepwise lower cache tiers (smaller). This
+    /// method is only available on `x86` or `x86_64` architectures with `sse` enabled.
+    ///
+    /// ```bash
+    /// RUSTFLAGS="-C target-cpu=native -C target-feature=+sse" cargo build …
+    /// ```
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse"
+    ))]
+    #[inline(always)]
+    pub fn prefetch(&self, hint: Prefetch) {
+        #[cfg(target_arch = "x86")]
+        use core::arch::x86;
+        #[cfg(target_arch = "x86_64")]
+        use core::arch::x86_64 as x86;
+
+        let addr = self.get_virt_addr() as *const _;
+        unsafe {
+            match hint {
+                Prefetch::Time0 => x86::_mm_prefetch(addr, x86::_MM_HINT_T0),
+                Prefetch::Time1 => x86::_mm_prefetch(addr, x86::_MM_HINT_T1),
+                Prefetch::Time2 => x86::_mm_prefetch(addr, x86::_MM_HINT_T2),
+                Prefetch::NonTemporal => x86::_mm_prefetch(addr, x86::_MM_HINT_NTA),
+            }
+        }
+    }
+}
+
+/// Iterator over the segments of a [`Packet`], see [`Packet::segments`].
+pub struct Segments<'a> {
+    next: Option<&'a Packet>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a Packet;
+
+    fn next(&mut self) -> Option<&'a Packet> {
+        let segment = self.next?;
+        self.next = segment.next.as_deref();
+
+        Some(segment)
+    }
+}
+
+/// Common representation for prefetch strategies.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Prefetch {
+    /// Corresponds to _MM_HINT_T0 on x86 sse.
+    Time0,
+
+    /// Corresponds to _MM_HINT_T1 on x86 sse.
+    Time1,
+
+    /// Corresponds to _MM_HINT_T2 on x86 sse.
+    Time2,
+
+    /// Corresponds to _MM_HINT_NTA on x86 sse.
+    NonTemporal,
+}
+
+/// A pool of fixed-size dma buffers that packets are allocated from.
+///
+/// Pools are shared via `Arc` and are safe to use from several threads, e.g. to receive packets
+/// on one core and transmit or drop them on another. Freed buffers return to the pool they were
+/// allocated from.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::{alloc_pkt, Mempool};
+/// use std::thread;
+///
+/// let pool = Mempool::allocate(2048, 0).unwrap();
+/// let packet = alloc_pkt(&pool, 60).unwrap();
+///
+/// thread::spawn(move || drop(packet)).join().unwrap();
+/// assert_eq!(pool.free_count(), 2048);
+/// ```
+pub struct Mempool {
+    base_addr: *mut u8,
+    num_entries: usize,
+    entry_size: usize,
+    entry_stride: usize,
+    phys_addresses: Vec<usize>,
+    free_stack: Mutex<Vec<usize>>,
+    reserve: AtomicUsize,
+    headroom: AtomicUsize,
+    id: usize,
+    cache_size: AtomicUsize,
+    self_ref: Weak<Mempool>,
+    low_watermark: AtomicUsize,
+    alloc_failures: AtomicUsize,
+    scrub: AtomicU8,
+    // keeps the memory of the entries mapped, None for memory that is never freed
+    _memory: Option<Arc<Dma<u8>>>,
+}
+
+/// What a [`Mempool`] writes into the buffers of freed packets, see [`Mempool::set_scrub`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scrub {
+    /// Buffers keep their old content.
+    Off = 0,
+    /// Buffers are zeroed, so payload never leaks into later packets.
+    Zero = 1,
+    /// Buffers are filled with `0x6b`, which makes reads of stale data stand out when debugging.
+    Poison = 2,
+}
+
+// fill byte of poisoned buffers, same as the kernel's POISON_FREE
+const POISON_BYTE: u8 = 0x6b;
+
+/// Occupancy statistics of a [`Mempool`], see [`Mempool::stats`].
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MempoolStats {
+    /// Total number of entries of the pool.
+    pub capacity: usize,
+
+    /// Number of entries currently free.
+    pub free: usize,
+
+    /// Number of entries currently held by packets, queues or per-thread caches.
+    pub in_use: usize,
+
+    /// Lowest number of free entries since the pool was created.
+    pub low_watermark: usize,
+
+    /// Number of allocations that failed because the pool was exhausted.
+    pub alloc_failures: usize,
+}
+
+/// Locked stack of free entries of a `Mempool`, tracks the pool's low watermark when released.
+pub(crate) struct FreeStack<'a> {
+    stack: MutexGuard<'a, Vec<usize>>,
+    low_watermark: &'a AtomicUsize,
+    reserve: usize,
+}
+
+impl FreeStack<'_> {
+    /// Returns the number of free entries that are not reserved for cloning packets, i.e. the
+    /// entries rx queues may refill their rings with.
+    pub(crate) fn available(&self) -> usize {
+        self.stack.len().saturating_sub(self.reserve)
+    }
+}
+
+impl Deref for FreeStack<'_> {
+    type Target = Vec<usize>;
+
+    fn deref(&self) -> &Vec<usize> {
+        &self.stack
+    }
+}
+
+impl DerefMut for FreeStack<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<usize> {
+        &mut self.stack
+    }
+}
+
+impl Drop for FreeStack<'_> {
+    fn drop(&mut self) {
+        // the lock is still held, so there are no concurrent updates
+        if self.stack.len() < self.low_watermark.load(Ordering::Relaxed) {
+            self.low_watermark
+                .store(self.stack.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Free entries of a `Mempool` cached by a single thread.
+struct MempoolCache {
+    pool_id: usize,
+    pool: Weak<Mempool>,
+    entries: Vec<usize>,
+}
+
+impl Drop for MempoolCache {
+    fn drop(&mut self) {
+        // the thread exits, return the cached entries to the pool if it still exists
+        if let Some(pool) = self.pool.upgrade() {
+            pool.free_stack().append(&mut self.entries);
+        }
+    }
+}
+
+// the base address points to dma memory kept mapped by the pool, entries are handed out exclusively
+unsafe impl Send for Mempool {}
+unsafe impl Sync for Mempool {}
+
+impl Mempool {
+    /// Allocates a new `Mempool`.
+    ///
+    /// If `size` is not a multiple of the cache line size, entries don't start on cache line
+    /// boundaries, which makes prefetching packets less effective. Use
+    /// [`Mempool::allocate_aligned`] to pad the entries instead.
+    ///
+    /// Entries whose size doesn't divide the page size, e.g. 9216 bytes for jumbo frames, are
+    /// padded to the next power of two so that no entry spans two huge pages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` exceeds 16 KiB and is not a divisor of the page size.
+    pub fn allocate(entries: usize, size: usize) -> Result<Arc<Mempool>, IxyError> {
+        Mempool::allocate_with_stride(entries, size, 0)
+    }
+
+    /// Allocates a new `Mempool` whose entries of `size` bytes each start on a cache line
+    /// boundary by padding them to a multiple of the cache line size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` exceeds 16 KiB and is not a divisor of the page size.
+    pub fn allocate_aligned(entries: usize, size: usize) -> Result<Arc<Mempool>, IxyError> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+        let stride = entry_size.div_ceil(CACHE_LINE_SIZE) * CACHE_LINE_SIZE;
+
+        Mempool::allocate_with_stride(entries, entry_size, stride)
+    }
+
+    /// Allocates a new `Mempool` whose entries of `size` bytes each start on an `align` byte
+    /// boundary and whose packet data starts `data_offset` bytes into each entry.
+    ///
+    /// The data offset is the pool's headroom, see [`Mempool::set_headroom`]. It allows placing
+    /// e.g. the IP header of received packets on a cache line boundary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` exceeds 16 KiB and is not a divisor of the page size, `align` is not a
+    /// power of two that divides the page size or `data_offset` is not smaller than `size`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::Mempool;
+    ///
+    /// // 128 byte aligned entries, the IP header behind the 14 byte ethernet header starts at 64
+    /// let pool = Mempool::allocate_with_layout(2048, 2048, 128, 50).unwrap();
+    /// ```
+    pub fn allocate_with_layout(
+        entries: usize,
+        size: usize,
+        align: usize,
+        data_offset: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+
+        assert!(
+            align.is_power_of_two() && HUGE_PAGE_SIZE.is_multiple_of(align),
+            "alignment {} is not a power of two that divides the page size",
+            align
+        );
+
+        let pool =
+            Mempool::allocate_with_stride(entries, entry_size, entry_size.next_multiple_of(align))?;
+        pool.set_headroom(data_offset);
+
+        Ok(pool)
+    }
+
+    /// Allocates a new `Mempool` whose entries of `size` usable bytes start `stride` bytes
+    /// apart, e.g. to spread consecutive entries across cache sets.
+    ///
+    /// A `stride` of 0 packs the entries contiguously like [`Mempool::allocate`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` exceeds 16 KiB and is not a divisor of the page size or `stride` is
+    /// smaller than `size`.
+    pub fn allocate_with_stride(
+        entries: usize,
+        size: usize,
+        stride: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        Mempool::allocate_placed(entries, size, stride, None)
+    }
+
+    /// Allocates a new `Mempool` on numa node `node`, e.g. the node the network card that
+    /// receives into it is attached to, see [`Mempool::allocate`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` exceeds 16 KiB and is not a divisor of the page size.
+    pub fn allocate_on_node(
+        entries: usize,
+        size: usize,
+        node: u32,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        Mempool::allocate_placed(entries, size, 0, Some(node))
+    }
+
+    /// Allocates a new `Mempool` with the given stride, optionally on numa node `node`.
+    fn allocate_placed(
+        entries: usize,
+        size: usize,
+        stride: usize,
+        node: Option<u32>,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+        let mut entry_stride = match stride {
+            0 => entry_size,
+            x => x,
+        };
+
+        assert!(
+            entry_stride >= entry_size,
+            "entry stride {} is smaller than entry size {}",
+            entry_stride,
+            entry_size
+        );
+
+        // without the IOMMU entries must not span two huge pages, which are not physically
+        // contiguous, so pad them to a divisor of the page size
+        if shared_iommu().is_none() && !HUGE_PAGE_SIZE.is_multiple_of(entry_stride) {
+            assert!(
+                entry_size <= MAX_PADDED_ENTRY_SIZE,
+                "entry size {} must be a divisor of the page size or at most {} bytes",
+                entry_size,
+                MAX_PADDED_ENTRY_SIZE
+            );
+
+            entry_stride = entry_stride.next_power_of_two();
+        }
+
+        if !entry_stride.is_multiple_of(CACHE_LINE_SIZE) {
+            warn!(
+                "mempool entries of {} bytes are not cache line aligned, consider Mempool::allocate_aligned",
+                entry_stride
+            );
+        }
+
+        let dma: Dma<u8> = match node {
+            Some(node) => Dma::allocate_on_node(entries * entry_stride, false, node)?,
+            None => Dma::allocate(entries * entry_stride, false)?,
+        };
+        dma.prefault();
+
+        Mempool::from_dma_with_stride(
+            default_allocator(),
+            &Arc::new(dma),
+            0,
+            entries,
+            entry_size,
+            entry_stride,
+        )
+    }
+
+    /// Allocates a new `Mempool` in memory of `allocator`, e.g. a custom [`DmaAllocator`].
+    ///
+    /// Each entry has to be physically contiguous, i.e. accessible by the network card at a
+    /// single address range.
+    pub fn allocate_in(
+        allocator: &dyn DmaAllocator,
+        entries: usize,
+        size: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+
+        let dma = allocator.allocate(entries * entry_size, false)?;
+        dma.prefault();
+
+        Mempool::from_dma_with_stride(
+            allocator,
+            &Arc::new(dma),
+            0,
+            entries,
+            entry_size,
+            entry_size,
+        )
+    }
+
+    /// Builds a new `Mempool` of `entries` entries with `entry_size` bytes each at `offset`
+    /// bytes into `dma` without allocating memory. `dma` has to be allocated by `allocator`,
+    /// which translates the addresses of the entries.
+    ///
+    /// This allows carving several mempools out of one dma region. Each mempool keeps the
+    /// region mapped until the mempool and all its packets are dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{DmaAllocator, Mempool};
+    /// use ixy::VfioContainer;
+    /// use std::sync::Arc;
+    ///
+    /// // the region is mapped into a private container, so only it can translate addresses
+    /// let container = VfioContainer::new().unwrap();
+    /// let dma = Arc::new(container.allocate(2 * 1024 * 2048, false).unwrap());
+    ///
+    /// let rx_pool = Mempool::from_dma(&*container, &dma, 0, 1024, 2048).unwrap();
+    /// let tx_pool = Mempool::from_dma(&*container, &dma, 1024 * 2048, 1024, 2048).unwrap();
+    /// ```
+    pub fn from_dma(
+        allocator: &dyn DmaAllocator,
+        dma: &Arc<Dma<u8>>,
+        offset: usize,
+        entries: usize,
+        entry_size: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        Mempool::from_dma_with_stride(allocator, dma, offset, entries, entry_size, entry_size)
+    }
+
+    /// Builds a new `Mempool` in `dma` of `allocator` whose entries start `entry_stride` bytes
+    /// apart.
+    fn from_dma_with_stride(
+        allocator: &dyn DmaAllocator,
+        dma: &Arc<Dma<u8>>,
+        offset: usize,
+        entries: usize,
+        entry_size: usize,
+        entry_stride: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        if offset + entries * entry_stride > dma.size {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} entries of {} bytes at offset {} exceed dma region of {} bytes",
+                entries, entry_stride, offset, dma.size
+            )));
+        }
+
+        let base_addr = unsafe { dma.virt.add(offset) };
+
+        // translate the first and the last byte of each entry
+        let offsets: Vec<usize> = (0..entries)
+            .map(|i| offset + i * entry_stride)
+            .flat_map(|start| [start, start + entry_size - 1])
+            .collect();
+        let translated = allocator.translate_batch(dma, &offsets)?;
+        let mut phys_addresses = Vec::with_capacity(entries);
+
+        for (i, range) in translated.chunks_exact(2).enumerate() {
+            let phys = range[0];
+
+            // entries must not cross page boundaries unless the physical memory is contiguous
+            if range[1] != phys + entry_size - 1 {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "entry {} at offset {} is not physically contiguous",
+                    i,
+                    offset + i * entry_stride
+                )));
+            }
+
+            phys_addresses.push(phys);
+        }
+
+        Ok(Mempool::new(
+            base_addr,
+            entries,
+            entry_size,
+            entry_stride,
+            phys_addresses,
+            Some(Arc::clone(dma)),
+        ))
+    }
+
+    /// Allocates a new `Mempool` in ordinary memory for backends that don't use DMA, e.g. the
+    /// TAP backend. The physical address of each entry is its virtual address.
+    pub(crate) fn allocate_anonymous(
+        entries: usize,
+        size: usize,
+    ) -> Result<Arc<Mempool>, IxyError> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+        let len = entries * entry_size;
+
+        let virt = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if virt == libc::MAP_FAILED {
+            return Err(IxyError::Io(io::Error::last_os_error()));
+        }
+
+        let base_addr = virt as *mut u8;
+        let dma = Dma {
+            virt: base_addr,
+            phys: base_addr as usize,
+            size: len,
+            mapping: Mapping::Anonymous,
+        };
+        let phys_addresses = (0..entries)
+            .map(|i| base_addr as usize + i * entry_size)
+            .collect();
+
+        Ok(Mempool::new(
+            base_addr,
+            entries,
+            entry_size,
+            entry_size,
+            phys_addresses,
+            Some(Arc::new(dma)),
+        ))
+    }
+
+    /// Allocates a new `Mempool` in ordinary memory.
+    ///
+    /// The network card can't access this memory, it is meant for testing code against
+    /// devices that don't use DMA like [`MockDevice`](crate::mock::MockDevice). The physical
+    /// address of each entry is its virtual address.
+    #[cfg(feature = "testing")]
+    pub fn allocate_unpinned(entries: usize, size: usize) -> Arc<Mempool> {
+        let entry_size = match size {
+            0 => 2048,
+            x => x,
+        };
+
+        // never freed
+        let memory = Box::leak(vec![0u8; entries * entry_size].into_boxed_slice());
+        let base_addr = memory.as_mut_ptr();
+        let phys_addresses = (0..entries)
+            .map(|i| base_addr as usize + i * entry_size)
+            .collect();
+
+        Mempool::new(
+            base_addr,
+            entries,
+            entry_size,
+            entry_size,
+            phys_addresses,
+            None,
+        )
+    }
+
+    /// Returns a `Mempool` managing `entries` entries of `entry_size` bytes at `base_addr`,
+    /// starting `entry_stride` bytes apart and kept mapped by `memory`.
+    fn new(
+        base_addr: *mut u8,
+        entries: usize,
+        entry_size: usize,
+        entry_stride: usize,
+        phys_addresses: Vec<usize>,
+        memory: Option<Arc<Dma<u8>>>,
+    ) -> Arc<Mempool> {
+        let pool = Arc::new_cyclic(|self_ref| Mempool {
+            base_addr,
+            num_entries: entries,
+            entry_size,
+            entry_stride,
+            phys_addresses,
+            free_stack: Mutex::new(Vec::with_capacity(entries)),
+            reserve: AtomicUsize::new(0),
+            headroom: AtomicUsize::new(0),
+            id: MEMPOOL_ID.fetch_add(1, Ordering::Relaxed),
+            cache_size: AtomicUsize::new(0),
+            self_ref: self_ref.clone(),
+            low_watermark: AtomicUsize::new(entries),
+            alloc_failures: AtomicUsize::new(0),
+            scrub: AtomicU8::new(Scrub::Off as u8),
+            _memory: memory,
+        });
+
+        unsafe { memset(pool.base_addr, pool.num_entries * pool.entry_stride, 0x00) }
+
+        pool.free_stack.lock().unwrap().extend(0..entries);
+
+        pool
+    }
+
+    /// Reserves `num_entries` buffers of this pool for cloning packets.
+    ///
+    /// `alloc_pkt` and `alloc_pkt_batch` fail once only reserved buffers are left, while
+    /// [`Packet::clone`] and [`Packet::try_clone`] may still use them. Rx queues receiving into
+    /// this pool leave packets in their rings until their buffers can be replaced with
+    /// unreserved ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_entries` exceeds the number of entries of this pool.
+    pub fn set_reserve(&self, num_entries: usize) {
+        assert!(
+            num_entries <= self.num_entries,
+            "cannot reserve {} entries: pool has only {}",
+            num_entries,
+            self.num_entries
+        );
+
+        self.reserve.store(num_entries, Ordering::Relaxed);
+    }
+
+    /// Reserves `headroom` bytes in front of the data of packets allocated from this pool, so
+    /// headers can be added with [`Packet::prepend`] without moving the data.
+    ///
+    /// Packets are limited to the remaining bytes of each entry. Set the headroom before the
+    /// pool is used, buffers that are already handed to a network card keep their old layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `headroom` is not smaller than the entry size of this pool.
+    pub fn set_headroom(&self, headroom: usize) {
+        assert!(
+            headroom < self.entry_size,
+            "headroom of {} bytes exceeds entry size of {} bytes",
+            headroom,
+            self.entry_size
+        );
+
+        self.headroom.store(headroom, Ordering::Relaxed);
+    }
+
+    /// Sets what is written into the whole buffer of each packet freed to this pool, e.g. to
+    /// make sure that payload never leaks between tenants.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::{Mempool, Scrub};
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    /// pool.set_scrub(Scrub::Zero);
+    /// ```
+    pub fn set_scrub(&self, scrub: Scrub) {
+        self.scrub.store(scrub as u8, Ordering::Relaxed);
+    }
+
+    /// Returns the number of bytes reserved in front of the data of new packets.
+    pub fn headroom(&self) -> usize {
+        self.headroom.load(Ordering::Relaxed)
+    }
+
+    /// Enables per-thread caches of up to `size` free entries, `0` disables them.
+    ///
+    /// Allocating and freeing packets takes the pool's lock on every call, which becomes the
+    /// bottleneck when several cores share a pool. With caches each thread allocates from and
+    /// frees to its own stack, which is refilled from the pool with `size` entries at once and
+    /// flushed back once it holds twice as many. Set the cache size before the pool is used.
+    ///
+    /// Cached entries are not counted by [`Mempool::free_count`] and can't be used by other
+    /// threads, so size the pool to cover the caches of all threads.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::Mempool;
+    ///
+    /// let pool = Mempool::allocate(4096, 0).unwrap();
+    /// pool.set_cache_size(256);
+    /// ```
+    pub fn set_cache_size(&self, size: usize) {
+        self.cache_size.store(size, Ordering::Relaxed);
+    }
+
+    /// Returns the size of each entry of this pool in bytes, including the headroom.
+    pub fn entry_size(&self) -> usize {
+        self.entry_size
+    }
+
+    /// Returns the total number of entries of this pool.
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Returns the distance between the starts of two entries of this pool in bytes.
+    pub(crate) fn entry_stride(&self) -> usize {
+        self.entry_stride
+    }
+
+    /// Returns the number of entries currently free in this pool.
+    pub fn free_count(&self) -> usize {
+        self.free_stack().len()
+    }
+
+    /// Returns the occupancy statistics of this pool.
+    ///
+    /// Entries in per-thread caches count as in use, see [`Mempool::set_cache_size`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use ixy::memory::Mempool;
+    ///
+    /// let pool = Mempool::allocate(2048, 0).unwrap();
+    ///
+    /// let stats = pool.stats();
+    /// if stats.alloc_failures > 0 {
+    ///     println!("pool ran dry, at least {} entries were free", stats.low_watermark);
+    /// }
+    /// ```
+    pub fn stats(&self) -> MempoolStats {
+        let free = self.free_count();
+
+        MempoolStats {
+            capacity: self.num_entries,
+            free,
+            in_use: self.num_entries - free,
+            low_watermark: self.low_watermark.load(Ordering::Relaxed),
+            alloc_failures: self.alloc_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns a snapshot of the ids of all entries currently free in this pool.
+    ///
+    /// Entries missing from the snapshot are in flight, i.e. held by a `Packet` or a queue.
+    /// This is meant for debugging buffer leaks.
+    pub fn free_entries(&self) -> Vec<usize> {
+        self.free_stack().clone()
+    }
+
+    /// Returns the virtual address of the entry with the given `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not an entry of this pool.
+    pub fn entry_virt_addr(&self, id: usize) -> *mut u8 {
+        assert!(
+            id < self.num_entries,
+            "entry {} out of range: pool has {} entries",
+            id,
+            self.num_entries
+        );
+
+        unsafe { self.get_entry_addr(id) }
+    }
+
+    /// Removes a packet from the packet pool and returns it, or [`None`] if the pool is empty.
+    pub(crate) fn alloc_buf(&self) -> Option<usize> {
+        let buf = self
+            .alloc_cached_buf(0)
+            .unwrap_or_else(|| self.free_stack().pop());
+
+        self.count_failure(buf)
+    }
+
+    /// Removes a packet from the packet pool and returns it, or [`None`] if only reserved
+    /// packets are left.
+    fn alloc_unreserved_buf(&self) -> Option<usize> {
+        let reserve = self.reserve.load(Ordering::Relaxed);
+
+        let buf = self.alloc_cached_buf(reserve).unwrap_or_else(|| {
+            let mut free_stack = self.free_stack();
+
+            if free_stack.len() > reserve {
+                free_stack.pop()
+            } else {
+                None
+            }
+        });
+
+        self.count_failure(buf)
+    }
+
+    /// Counts `buf` as a failed allocation if it is [`None`] and returns it.
+    fn count_failure(&self, buf: Option<usize>) -> Option<usize> {
+        if buf.is_none() {
+            self.alloc_failures.fetch_add(1, Ordering::Relaxed);
+        }
+
+        buf
+    }
+
+    /// Returns a packet to the packet pool.
+    pub(crate) fn free_buf(&self, id: usize) {
+        let fill = self.scrub_fill();
+
+        if let Some(fill) = fill {
+            self.scrub_buf(id, fill);
+        }
+
+        if !self.free_cached_buf(id) {
+            self.free_stack().push(id);
+        }
+    }
+
+    /// Returns the packets `ids` to the packet pool under a single lock, e.g. after they were
+    /// sent. Unlike `free_buf` this bypasses the thread's cache.
+    pub(crate) fn free_bufs(&self, ids: impl IntoIterator<Item = usize>) {
+        let fill = self.scrub_fill();
+        let mut free_stack = self.free_stack();
+
+        for id in ids {
+            if let Some(fill) = fill {
+                self.scrub_buf(id, fill);
+            }
+
+            free_stack.push(id);
+        }
+    }
+
+    /// Returns the byte freed buffers are filled with, [`None`] if they are not scrubbed.
+    fn scrub_fill(&self) -> Option<u8> {
+        match self.scrub.load(Ordering::Relaxed) {
+            x if x == Scrub::Zero as u8 => Some(0),
+            x if x == Scrub::Poison as u8 => Some(POISON_BYTE),
+            _ => None,
+        }
+    }
+
+    /// Fills the whole entry `id` with `fill`.
+    fn scrub_buf(&self, id: usize, fill: u8) {
+        // unlike memset this isn't volatile, so it is vectorized
+        unsafe { ptr::write_bytes(self.get_entry_addr(id), fill, self.entry_size) }
+    }
+
+    /// Removes a packet from this thread's cache, refilling it from the packet pool while
+    /// leaving `reserve` packets in the pool. Returns [`None`] if caching is disabled.
+    fn alloc_cached_buf(&self, reserve: usize) -> Option<Option<usize>> {
+        let cache_size = self.cache_size.load(Ordering::Relaxed);
+        if cache_size == 0 {
+            return None;
+        }
+
+        self.with_cache(|entries| {
+            if entries.is_empty() {
+                let mut free_stack = self.free_stack();
+                let available = free_stack.len().saturating_sub(reserve);
+                let start = free_stack.len() - available.min(cache_size);
+
+                entries.extend(free_stack.drain(start..));
+            }
+
+            entries.pop()
+        })
+    }
+
+    /// Puts a packet into this thread's cache, flushing half of it to the packet pool if it is
+    /// full. Returns `false` if caching is disabled.
+    fn free_cached_buf(&self, id: usize) -> bool {
+        let cache_size = self.cache_size.load(Ordering::Relaxed);
+        if cache_size == 0 {
+            return false;
+        }
+
+        self.with_cache(|entries| {
+            entries.push(id);
+
+            if entries.len() >= 2 * cache_size {
+                self.free_stack().extend(entries.drain(cache_size..));
+            }
+        })
+        .is_some()
+    }
+
+    /// Calls `f` with this thread's cache of this pool, or returns [`None`] if the thread is
+    /// exiting and its caches are gone.
+    fn with_cache<R>(&self, f: impl FnOnce(&mut Vec<usize>) -> R) -> Option<R> {
+        MEMPOOL_CACHES
+            .try_with(|caches| {
+                let mut caches = caches.borrow_mut();
+
+                let index = match caches.iter().position(|c| c.pool_id == self.id) {
+                    Some(index) => index,
+                    None => {
+                        caches.push(MempoolCache {
+                            pool_id: self.id,
+                            pool: self.self_ref.clone(),
+                            entries: Vec::new(),
+                        });
+                        caches.len() - 1
+                    }
+                };
+
+                f(&mut caches[index].entries)
+            })
+            .ok()
+    }
+
+    /// Locks and returns the stack of free entries, lock it once for batch operations.
+    pub(crate) fn free_stack(&self) -> FreeStack<'_> {
+        FreeStack {
+            stack: self.free_stack.lock().unwrap(),
+            low_watermark: &self.low_watermark,
+            reserve: self.reserve.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the virtual address of the start of entry `id`.
+    pub(crate) unsafe fn get_entry_addr(&self, id: usize) -> *mut u8 {
+        self.base_addr.add(id * self.entry_stride)
+    }
+
+    /// Returns the virtual address of the packet data of entry `id`, i.e. behind the headroom.
+    pub(crate) unsafe fn get_virt_addr(&self, id: usize) -> *mut u8 {
+        self.get_entry_addr(id).add(self.headroom())
+    }
+
+    /// Returns the physical address of the packet data of entry `id`, i.e. behind the headroom.
     pub(crate) unsafe fn get_phys_addr(&self, id: usize) -> usize {
-        self.phys_addresses[id]
+        self.phys_addresses[id] + self.headroom()
     }
 }
 
 /// Returns `num_packets` free packets from the `pool` with size `packet_size`.
 pub fn alloc_pkt_batch(
-    pool: &Rc<Mempool>,
+    pool: &Arc<Mempool>,
     buffer: &mut VecDeque<Packet>,
     num_packets: usize,
     packet_size: usize,
@@ -331,57 +1973,498 @@ pub fn alloc_pkt_batch(
     allocated
 }
 
+/// Fills `buffer` with free packets from the `pool` with size `packet_size` and returns the
+/// number of packets, which are written to the start of `buffer`.
+///
+/// Unlike [`alloc_pkt_batch`] this doesn't need heap-backed storage, so a fixed array can be
+/// reused for every batch. Release the packets with [`free_pkt_batch`] or by reading them out,
+/// packets left in `buffer` are leaked.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::{alloc_pkt_batch_into, free_pkt_batch, Mempool, Packet};
+/// use std::mem::MaybeUninit;
+///
+/// let pool = Mempool::allocate(2048, 0).unwrap();
+/// let mut batch: [MaybeUninit<Packet>; 32] = [const { MaybeUninit::uninit() }; 32];
+///
+/// loop {
+///     let n = alloc_pkt_batch_into(&pool, &mut batch, 60);
+///     // fill in the packets ...
+///     unsafe { free_pkt_batch(&mut batch[..n]) };
+/// }
+/// ```
+pub fn alloc_pkt_batch_into(
+    pool: &Arc<Mempool>,
+    buffer: &mut [MaybeUninit<Packet>],
+    packet_size: usize,
+) -> usize {
+    let mut allocated = 0;
+
+    for slot in buffer.iter_mut() {
+        match alloc_pkt(pool, packet_size) {
+            Some(p) => {
+                slot.write(p);
+                allocated += 1;
+            }
+            None => break,
+        }
+    }
+
+    allocated
+}
+
+/// Returns all `packets` to their pools, e.g. those written by [`alloc_pkt_batch_into`].
+///
+/// # Safety
+///
+/// All `packets` have to be initialized, they are uninitialized afterwards.
+pub unsafe fn free_pkt_batch(packets: &mut [MaybeUninit<Packet>]) {
+    for p in packets {
+        p.assume_init_drop();
+    }
+}
+
+/// Returns `num_packets` free packets with size `packet_size`, taken from `pools` in turn.
+///
+/// Exhausted pools are skipped, so fewer packets are only returned once all pools are
+/// exhausted. `cursor` is the index of the pool to start with, it is advanced past the last
+/// pool tried so that consecutive calls keep spreading their packets evenly over the pools.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::{alloc_pkt_batch_multi, Mempool};
+/// use std::collections::VecDeque;
+///
+/// let pools = [Mempool::allocate(2048, 0).unwrap(), Mempool::allocate(2048, 0).unwrap()];
+/// let mut cursor = 0;
+/// let mut buffer = VecDeque::new();
+///
+/// alloc_pkt_batch_multi(&pools, &mut cursor, &mut buffer, 3, 60);
+///
+/// // the pools took turns starting with the first one, this continues with the second one
+/// alloc_pkt_batch_multi(&pools, &mut cursor, &mut buffer, 1, 60);
+/// ```
+pub fn alloc_pkt_batch_multi(
+    pools: &[Arc<Mempool>],
+    cursor: &mut usize,
+    buffer: &mut VecDeque<Packet>,
+    num_packets: usize,
+    packet_size: usize,
+) -> usize {
+    let mut allocated = 0;
+    // number of pools in a row that had no packet left
+    let mut failed = 0;
+    let mut index = *cursor % pools.len().max(1);
+
+    while allocated < num_packets && failed < pools.len() {
+        match alloc_pkt(&pools[index], packet_size) {
+            Some(p) => {
+                buffer.push_back(p);
+                allocated += 1;
+                failed = 0;
+            }
+            None => failed += 1,
+        }
+
+        index = (index + 1) % pools.len();
+    }
+
+    *cursor = index;
+
+    allocated
+}
+
 /// Returns a free packet from the `pool`, or [`None`] if the requested packet size exceeds the
-/// maximum size for that pool or if the pool is empty.
-pub fn alloc_pkt(pool: &Rc<Mempool>, size: usize) -> Option<Packet> {
-    if size > pool.entry_size {
+/// maximum size for that pool or if the pool is empty except for its reserved packets.
+pub fn alloc_pkt(pool: &Arc<Mempool>, size: usize) -> Option<Packet> {
+    if size > pool.entry_size - pool.headroom() {
         return None;
     }
 
-    match pool.alloc_buf() {
-        Some(packet) => unsafe {
-            Some(Packet::new(
-                pool.get_virt_addr(packet),
-                pool.get_phys_addr(packet),
-                size,
-                pool.clone(),
-                packet,
-            ))
-        },
-        _ => None,
-    }
+    pool.alloc_unreserved_buf()
+        .map(|id| unsafe { Packet::from_pool(pool, id, size) })
 }
 
 /// Initializes `len` fields of type `T` at `addr` with `value`.
 pub(crate) unsafe fn memset<T: Copy>(addr: *mut T, len: usize, value: T) {
     for i in 0..len {
-        ptr::write_volatile(addr.add(i) as *mut T, value);
+        ptr::write_volatile(addr.add(i), value);
     }
 }
 
 /// Translates a virtual address to its physical counterpart.
-pub(crate) fn virt_to_phys(addr: usize) -> Result<usize, Box<dyn Error>> {
+pub(crate) fn virt_to_phys(addr: usize) -> Result<usize, IxyError> {
     let pagesize = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) } as usize;
 
-    let mut file = fs::OpenOptions::new()
+    let offset = addr / pagesize * mem::size_of::<u64>();
+    let mut buffer = [0; mem::size_of::<u64>()];
+    pagemap()?.read_exact_at(&mut buffer, offset as u64)?;
+
+    pagemap_entry_to_phys(u64::from_ne_bytes(buffer), addr, pagesize)
+}
+
+/// Translates the virtual addresses `addrs` to physical addresses.
+///
+/// The page table entries of all pages between the lowest and the highest address are read at
+/// once, so the addresses should belong to a single region.
+pub(crate) fn virt_to_phys_batch(addrs: &[usize]) -> Result<Vec<usize>, IxyError> {
+    let pagesize = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) } as usize;
+
+    let (first, last) = match (addrs.iter().min(), addrs.iter().max()) {
+        (Some(min), Some(max)) => (min / pagesize, max / pagesize),
+        _ => return Ok(Vec::new()),
+    };
+
+    let entry_size = mem::size_of::<u64>();
+    let mut buffer = vec![0; (last - first + 1) * entry_size];
+    pagemap()?.read_exact_at(&mut buffer, (first * entry_size) as u64)?;
+
+    addrs
+        .iter()
+        .map(|&addr| {
+            let start = (addr / pagesize - first) * entry_size;
+            let mut entry = [0; mem::size_of::<u64>()];
+            entry.copy_from_slice(&buffer[start..start + entry_size]);
+
+            pagemap_entry_to_phys(u64::from_ne_bytes(entry), addr, pagesize)
+        })
+        .collect()
+}
+
+/// Returns the physical address of `addr` described by its pagemap `entry`.
+///
+/// Fails if the page is not present or swapped out, its frame number would be meaningless.
+fn pagemap_entry_to_phys(entry: u64, addr: usize, pagesize: usize) -> Result<usize, IxyError> {
+    if entry & PAGEMAP_SWAPPED != 0 {
+        return Err(page_unavailable(addr, "swapped out"));
+    }
+
+    if entry & PAGEMAP_PRESENT == 0 {
+        return Err(page_unavailable(addr, "not present"));
+    }
+
+    Ok((entry & PAGEMAP_PFN_MASK) as usize * pagesize + addr % pagesize)
+}
+
+/// Returns the error for the page of `addr` that has no physical address.
+fn page_unavailable(addr: usize, reason: &str) -> IxyError {
+    IxyError::DmaMapFailed(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "page at {:#x} is {}, it has no physical address",
+            addr, reason
+        ),
+    ))
+}
+
+/// Returns `/proc/self/pagemap`, which is opened once and kept open.
+fn pagemap() -> Result<&'static fs::File, IxyError> {
+    static PAGEMAP: OnceLock<fs::File> = OnceLock::new();
+
+    if let Some(file) = PAGEMAP.get() {
+        return Ok(file);
+    }
+
+    let file = fs::OpenOptions::new()
         .read(true)
         .open("/proc/self/pagemap")?;
 
-    file.seek(io::SeekFrom::Start(
-        (addr / pagesize * mem::size_of::<usize>()) as u64,
-    ))?;
+    Ok(PAGEMAP.get_or_init(|| file))
+}
+
+/// Returns the number of free huge pages of `page_size` bytes in total or on numa node `node`,
+/// zero if the size is unsupported.
+fn free_huge_pages(page_size: usize, node: Option<u32>) -> usize {
+    let path = match node {
+        Some(node) => format!(
+            "/sys/devices/system/node/node{}/hugepages/hugepages-{}kB/free_hugepages",
+            node,
+            page_size / 1024
+        ),
+        None => format!(
+            "/sys/kernel/mm/hugepages/hugepages-{}kB/free_hugepages",
+            page_size / 1024
+        ),
+    };
+
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|free| free.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Makes sure that at least `count` huge pages of `size` bytes are free, e.g. 2 MiB or 1 GiB.
+///
+/// Missing pages are reserved via `nr_hugepages` and hugetlbfs is mounted at `/mnt/huge` if
+/// 2 MiB pages are requested and no hugetlbfs is mounted, both require root. This allows
+/// applications to provision hugepages themselves instead of running `setup-hugetlbfs.sh`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::ensure_hugepages;
+///
+/// ensure_hugepages(512, 2 * 1024 * 1024).unwrap();
+/// ```
+pub fn ensure_hugepages(count: usize, size: usize) -> Result<(), IxyError> {
+    let sysfs = format!("/sys/kernel/mm/hugepages/hugepages-{}kB", size / 1024);
+    if !Path::new(&sysfs).exists() {
+        return Err(IxyError::HugepageUnavailable(format!(
+            "huge pages of {} bytes are not supported",
+            size
+        )));
+    }
+
+    let is_root = unsafe { libc::geteuid() } == 0;
+
+    if size == HUGE_PAGE_SIZE
+        && env::var_os(HUGETLBFS_PATH_ENV).is_none()
+        && hugetlbfs_mount().is_none()
+    {
+        if is_root {
+            mount_hugetlbfs(Path::new(DEFAULT_HUGETLBFS_PATH))?;
+        } else {
+            // anonymous hugepages still work without a mount
+            warn!("hugetlbfs is not mounted, run as root to mount it");
+        }
+    }
+
+    let free = free_huge_pages(size, None);
+    if free >= count {
+        return Ok(());
+    }
+
+    if !is_root {
+        return Err(IxyError::InsufficientPrivileges {
+            detail: format!(
+                "{} of {} huge pages of {} bytes are free, reserving more requires root",
+                free, count, size
+            ),
+        });
+    }
+
+    let nr_path = format!("{}/nr_hugepages", sysfs);
+    let reserved: usize = fs::read_to_string(&nr_path)?
+        .trim()
+        .parse()
+        .map_err(|_| IxyError::HugepageUnavailable(format!("cannot parse {}", nr_path)))?;
+
+    info!(
+        "reserving {} huge pages of {} bytes",
+        reserved + count - free,
+        size
+    );
+    fs::write(&nr_path, (reserved + count - free).to_string())?;
+
+    // the kernel reserves fewer pages if it can't find enough free memory
+    let free = free_huge_pages(size, None);
+    if free < count {
+        return Err(IxyError::HugepageUnavailable(format!(
+            "only {} of {} huge pages of {} bytes could be reserved",
+            free, count, size
+        )));
+    }
+
+    Ok(())
+}
+
+/// Mounts hugetlbfs for 2 MiB pages at `path`.
+fn mount_hugetlbfs(path: &Path) -> Result<(), IxyError> {
+    fs::create_dir_all(path)?;
+
+    let invalid = |e| IxyError::Io(io::Error::new(io::ErrorKind::InvalidInput, e));
+    let target = CString::new(path.as_os_str().as_bytes()).map_err(invalid)?;
+    let fstype = CString::new("hugetlbfs").map_err(invalid)?;
+    let options = CString::new(format!("pagesize={}M", HUGE_PAGE_SIZE >> 20)).map_err(invalid)?;
+
+    info!("mounting hugetlbfs at {}", path.display());
+
+    let result = unsafe {
+        libc::mount(
+            fstype.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            0,
+            options.as_ptr() as *const libc::c_void,
+        )
+    };
+
+    if result != 0 {
+        return Err(IxyError::HugepageUnavailable(format!(
+            "failed to mount hugetlbfs at {}: {}",
+            path.display(),
+            io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Removes hugepage files left behind in the hugetlbfs mount by ixy processes that no longer
+/// exist and returns the number of removed files.
+///
+/// Current versions remove their hugepage files right after mapping them, but processes that
+/// crashed while allocating or older versions may have leaked some.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::remove_stale_hugepages;
+///
+/// let removed = remove_stale_hugepages().unwrap();
+/// println!("reclaimed {} hugepage files", removed);
+/// ```
+pub fn remove_stale_hugepages() -> Result<usize, IxyError> {
+    let mut removed = 0;
+
+    for entry in fs::read_dir(hugetlbfs_path())? {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        // hugepage files are named ixy-<pid>-<id>
+        let pid = match name.to_str().and_then(|n| n.strip_prefix("ixy-")) {
+            Some(n) => match n.split('-').next().and_then(|pid| pid.parse::<u32>().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            },
+            None => continue,
+        };
+
+        if pid == process::id() || Path::new(&format!("/proc/{}", pid)).exists() {
+            continue;
+        }
+
+        debug!("removing stale hugepage {}", entry.path().display());
+        fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Maps `size` bytes of anonymous shared hugepage memory with the hugepage size `page_size_flag`.
+fn map_anonymous(size: usize, page_size_flag: i32) -> *mut libc::c_void {
+    unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB | page_size_flag,
+            -1,
+            0,
+        )
+    }
+}
+
+/// Returns the hugetlbfs mount point for 2 MiB pages.
+///
+/// Uses `IXY_HUGETLB_PATH` if set, otherwise the first matching hugetlbfs mount in
+/// `/proc/mounts`, e.g. `/dev/hugepages`, and `/mnt/huge` if none is mounted.
+fn hugetlbfs_path() -> PathBuf {
+    if let Some(path) = env::var_os(HUGETLBFS_PATH_ENV) {
+        return PathBuf::from(path);
+    }
+
+    hugetlbfs_mount().unwrap_or_else(|| PathBuf::from(DEFAULT_HUGETLBFS_PATH))
+}
+
+/// Returns the first hugetlbfs mount point for 2 MiB pages in `/proc/mounts`.
+fn hugetlbfs_mount() -> Option<PathBuf> {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    let pagesize = format!("pagesize={}M", HUGE_PAGE_SIZE >> 20);
+
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if let [_, mount_point, "hugetlbfs", options, ..] = fields[..] {
+            // mounts without a pagesize option use the default huge page size, which is 2 MiB
+            // on x86
+            let options: Vec<&str> = options.split(',').collect();
+            if options.contains(&pagesize.as_str())
+                || !options.iter().any(|o| o.starts_with("pagesize="))
+            {
+                // spaces in mount points are escaped as octal
+                return Some(PathBuf::from(mount_point.replace("\\040", " ")));
+            }
+        }
+    }
+
+    None
+}
+
+/// Binds the `size` bytes of memory at `ptr` to numa node `node`, this only affects pages that
+/// have not been touched yet.
+fn bind_to_node(ptr: *mut libc::c_void, size: usize, node: u32) -> Result<(), IxyError> {
+    if node >= MAX_NUMA_NODES {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "numa node {} exceeds the limit of {} nodes",
+            node, MAX_NUMA_NODES
+        )));
+    }
+
+    let nodemask: u64 = 1 << node;
 
-    let mut buffer = [0; mem::size_of::<usize>()];
-    file.read_exact(&mut buffer)?;
+    // the kernel ignores the last bit of the mask, so maxnode is one more than its bits
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            ptr,
+            size,
+            MPOL_BIND,
+            &nodemask as *const u64,
+            u64::from(MAX_NUMA_NODES) + 1,
+            0,
+        )
+    };
 
-    let phys = unsafe { mem::transmute::<[u8; mem::size_of::<usize>()], usize>(buffer) };
-    Ok((phys & 0x007f_ffff_ffff_ffff) * pagesize + addr % pagesize)
+    if result != 0 {
+        let err = io::Error::last_os_error();
+        return Err(IxyError::Io(io::Error::new(
+            err.kind(),
+            format!("failed to bind dma memory to numa node {}: {}", node, err),
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns whether this process has the effective capability `cap`, see linux/capability.h.
+pub(crate) fn has_capability(cap: u32) -> bool {
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << cap) != 0)
 }
 
-pub(crate) fn get_vfio_container() -> RawFd {
-    unsafe { VFIO_CONTAINER_FILE_DESCRIPTOR }
+/// Returns the soft limit for locked memory of this process in bytes, `None` if unlimited.
+pub(crate) fn memlock_limit() -> Option<libc::rlim_t> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        None
+    } else {
+        Some(limit.rlim_cur)
+    }
 }
 
-pub(crate) fn set_vfio_container(cfd: RawFd) {
-    unsafe { VFIO_CONTAINER_FILE_DESCRIPTOR = cfd }
+/// Returns `RLIMIT_MEMLOCK` in a human-readable form for error messages.
+pub(crate) fn format_memlock_limit() -> String {
+    match memlock_limit() {
+        Some(limit) => format!("{} KiB (raise it with ulimit -l)", limit / 1024),
+        None => "unlimited".to_string(),
+    }
 }
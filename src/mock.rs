@@ -0,0 +1,904 @@
+//! Software-simulated device for testing code built on [`IxyDevice`] without hardware.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::memory::{alloc_pkt, Mempool, Packet};
+use crate::pci::{MappedBar, PcieLink, PcieSpeed};
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::InterruptModeration;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-mock";
+
+const PKT_BUF_ENTRY_SIZE: usize = 2048;
+const MAX_RX_BUFFER_SIZE: usize = 16 * 1024;
+const MEMPOOL_SIZE: usize = 4096;
+
+const NUM_RX_QUEUE_ENTRIES: usize = 512;
+
+const LINK_SPEED: u32 = 10000;
+
+const DEFAULT_ITR_US: u32 = 10;
+
+const NUM_UNICAST_FILTERS: usize = 128;
+const NUM_POOLS: usize = 64;
+
+/// A device that hands out scripted packets on rx and captures sent packets on tx.
+///
+/// Received packets are allocated from a real [`Mempool`] in ordinary memory, so the device
+/// works without hugepages and network cards.
+///
+/// # Examples
+///
+/// ```rust
+/// use ixy::mock::MockDevice;
+/// use ixy::*;
+/// use std::collections::VecDeque;
+///
+/// let mut dev = MockDevice::init("mock", 1, 1).unwrap();
+/// dev.push_rx(0, &[0xff; 60]);
+///
+/// let mut buffer = VecDeque::new();
+/// assert_eq!(dev.rx_batch(0, &mut buffer, 32), 1);
+/// assert_eq!(dev.tx_batch(0, &mut buffer), 1);
+///
+/// assert_eq!(dev.take_tx(0), vec![vec![0xff; 60]]);
+/// ```
+pub struct MockDevice {
+    pci_addr: String,
+    mac_addr: Cell<[u8; 6]>,
+    unicast_filters: RefCell<Vec<Option<[u8; 6]>>>,
+    pool: Arc<Mempool>,
+    rx_queues: Vec<VecDeque<Vec<u8>>>,
+    rx_posted: Vec<usize>,
+    tx_queues: Vec<Vec<Vec<u8>>>,
+    tx_external_completed: Vec<VecDeque<usize>>,
+    rx_enabled: RefCell<Vec<bool>>,
+    tx_enabled: RefCell<Vec<bool>>,
+    interrupt_moderation: Vec<InterruptModeration>,
+    flow_control: Cell<FlowControl>,
+    poll_strategy: PollStrategy,
+    crc_strip: bool,
+    stats: Cell<DeviceStats>,
+    link_speed: u32,
+    loopback: bool,
+    removed: bool,
+}
+
+impl MockDevice {
+    /// Queues `data` to be received on rx queue `queue_id`. Frames arriving while the link is
+    /// down are lost.
+    pub fn push_rx(&mut self, queue_id: u32, data: &[u8]) {
+        if self.link_speed == 0 {
+            return;
+        }
+
+        self.rx_queues[queue_id as usize].push_back(data.to_vec());
+    }
+
+    /// Returns the contents of all packets sent on tx queue `queue_id` since the last call.
+    pub fn take_tx(&mut self, queue_id: u32) -> Vec<Vec<u8>> {
+        mem::take(&mut self.tx_queues[queue_id as usize])
+    }
+
+    /// Simulates a surprise removal of the device, nothing is received or sent afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use ixy::mock::MockDevice;
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = MockDevice::init("mock", 1, 1).unwrap();
+    /// dev.push_rx(0, &[0xff; 60]);
+    /// dev.remove();
+    ///
+    /// let mut buffer = VecDeque::new();
+    /// assert!(dev.try_rx_batch(0, &mut buffer, 32).is_err());
+    /// ```
+    pub fn remove(&mut self) {
+        self.removed = true;
+    }
+
+    /// Simulates a link change to `speed` Mbit/s, 0 takes the link down.
+    ///
+    /// While the link is down, scripted frames are lost and `tx_batch` sends nothing, i.e. the
+    /// packets stay in the caller's queue as if the tx ring was full. The link survives
+    /// `reset`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use ixy::mock::MockDevice;
+    /// use ixy::*;
+    ///
+    /// let mut dev = MockDevice::init("mock", 1, 1).unwrap();
+    /// dev.set_link_speed(0);
+    ///
+    /// assert_eq!(dev.get_link_speed(), 0);
+    /// ```
+    pub fn set_link_speed(&mut self, speed: u32) {
+        self.link_speed = speed;
+    }
+
+    /// Enables or disables loopback, sent packets are then also received on the rx queue with
+    /// the same index, if any.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use ixy::mock::MockDevice;
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = MockDevice::init("mock", 1, 1).unwrap();
+    /// dev.set_loopback(true);
+    /// dev.push_rx(0, &[0xff; 60]);
+    ///
+    /// let mut buffer = VecDeque::new();
+    /// dev.rx_batch(0, &mut buffer, 32);
+    /// dev.tx_batch(0, &mut buffer);
+    ///
+    /// assert_eq!(dev.rx_batch(0, &mut buffer, 32), 1);
+    /// ```
+    pub fn set_loopback(&mut self, enabled: bool) {
+        self.loopback = enabled;
+    }
+
+    /// Returns the mempool received packets are allocated from.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use ixy::memory::{alloc_pkt, Scrub};
+    /// use ixy::mock::MockDevice;
+    /// use ixy::*;
+    /// use std::collections::VecDeque;
+    ///
+    /// let mut dev = MockDevice::init("mock", 1, 1).unwrap();
+    /// dev.get_pool().set_scrub(Scrub::Zero);
+    /// dev.push_rx(0, &[0xff; 60]);
+    ///
+    /// let mut buffer = VecDeque::new();
+    /// dev.rx_batch(0, &mut buffer, 32);
+    /// dev.tx_batch(0, &mut buffer);
+    ///
+    /// // the buffer of the sent packet is handed out again, without its payload
+    /// let p = alloc_pkt(dev.get_pool(), 60).unwrap();
+    /// assert!(p.iter().all(|&b| b == 0));
+    /// ```
+    pub fn get_pool(&self) -> &Arc<Mempool> {
+        &self.pool
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id as usize >= self.rx_queues.len() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id as usize >= self.tx_queues.len() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Adds the given amounts to the stats of this device.
+    fn count(&self, rx_pkts: u64, rx_bytes: u64, tx_pkts: u64, tx_bytes: u64) {
+        let mut stats = self.stats.get();
+        stats.rx_pkts += rx_pkts;
+        stats.rx_bytes += rx_bytes;
+        stats.tx_pkts += tx_pkts;
+        stats.tx_bytes += tx_bytes;
+        self.stats.set(stats);
+    }
+}
+
+impl IxyDevice for MockDevice {
+    /// Returns a `MockDevice` with the given number of empty queues.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(pci_addr: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<Self, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        Ok(MockDevice {
+            pci_addr: pci_addr.to_string(),
+            mac_addr: Cell::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]),
+            unicast_filters: RefCell::new(vec![None; NUM_UNICAST_FILTERS]),
+            pool: Mempool::allocate_unpinned(MEMPOOL_SIZE, PKT_BUF_ENTRY_SIZE),
+            rx_queues: vec![VecDeque::new(); num_rx_queues as usize],
+            rx_posted: vec![NUM_RX_QUEUE_ENTRIES - 1; num_rx_queues as usize],
+            tx_queues: vec![Vec::new(); num_tx_queues as usize],
+            tx_external_completed: vec![VecDeque::new(); num_tx_queues as usize],
+            rx_enabled: RefCell::new(vec![true; num_rx_queues as usize]),
+            tx_enabled: RefCell::new(vec![true; num_tx_queues as usize]),
+            interrupt_moderation: vec![
+                InterruptModeration::Static(DEFAULT_ITR_US);
+                num_rx_queues as usize
+            ],
+            flow_control: Cell::new(FlowControl::None),
+            poll_strategy: PollStrategy::BusySpin,
+            crc_strip: true,
+            stats: Cell::new(DeviceStats::default()),
+            link_speed: LINK_SPEED,
+            loopback: false,
+            removed: false,
+        })
+    }
+
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    fn is_card_iommu_capable(&self) -> bool {
+        false
+    }
+
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        None
+    }
+
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "mock device has no BAR{} to map",
+            bar
+        )))
+    }
+
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac_addr.get()
+    }
+
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        self.mac_addr.set(mac);
+    }
+
+    /// The mock device accepts the same pools as the 82599 but doesn't steer packets.
+    fn set_mac_addr_pool(&self, mac: [u8; 6], pool: u8) -> Result<(), IxyError> {
+        if usize::from(pool) >= NUM_POOLS {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "pool {} exceeds the {} VMDq pools",
+                pool, NUM_POOLS
+            )));
+        }
+
+        self.mac_addr.set(mac);
+
+        Ok(())
+    }
+
+    /// Records `mac` in the first free filter slot, the primary address uses slot 0.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        let mut filters = self.unicast_filters.borrow_mut();
+
+        let index = (1..NUM_UNICAST_FILTERS)
+            .find(|&i| filters[i].is_none())
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration(
+                    "all receive address registers are in use".to_string(),
+                )
+            })?;
+        filters[index] = Some(mac);
+
+        Ok(index)
+    }
+
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        if index == 0 || index >= NUM_UNICAST_FILTERS {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "receive address register {} is not a unicast filter",
+                index
+            )));
+        }
+
+        self.unicast_filters.borrow_mut()[index] = None;
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` scripted packets onto `buffer`.
+    ///
+    /// # Panics
+    /// Panics if a scripted packet exceeds the mempool's entry size.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        if self.removed || !self.rx_enabled.borrow()[queue_id as usize] {
+            return 0;
+        }
+
+        // a real device can't have received more packets than descriptors are posted
+        let num_packets = num_packets.min(self.rx_posted[queue_id as usize]);
+
+        let mut received_packets = 0;
+        let mut received_bytes = 0;
+
+        while received_packets < num_packets {
+            let queue = &mut self.rx_queues[queue_id as usize];
+
+            let data = match queue.front() {
+                Some(data) => data,
+                None => break,
+            };
+
+            // the fcs is appended like a real device does without crc stripping
+            let mut frame = data.clone();
+            if !self.crc_strip {
+                frame.extend_from_slice(&crc32(data).to_le_bytes());
+            }
+
+            // frames larger than a buffer are split into chained segments
+            let p = match alloc_segments(&self.pool, &frame) {
+                Some(p) => p,
+                None => break,
+            };
+
+            received_packets += 1;
+            received_bytes += p.total_len() as u64;
+
+            queue.pop_front();
+            buffer.push_back(p);
+        }
+
+        self.count(received_packets as u64, received_bytes, 0, 0);
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received_packets,
+            num_packets
+        );
+
+        received_packets
+    }
+
+    fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        if !self.rx_enabled.borrow()[queue_id as usize] {
+            return None;
+        }
+
+        self.rx_queues[queue_id as usize]
+            .front()
+            .map(|data| data.as_slice())
+    }
+
+    /// Limits the number of packets a single `rx_batch` call returns to `num_posted`.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if num_posted >= NUM_RX_QUEUE_ENTRIES {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                NUM_RX_QUEUE_ENTRIES,
+                NUM_RX_QUEUE_ENTRIES - 1
+            )));
+        }
+
+        self.rx_posted[queue_id as usize] = num_posted;
+
+        Ok(())
+    }
+
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_posted[queue_id as usize]
+    }
+
+    /// The mock device has no DMA, so it can't receive into external buffers.
+    fn set_rx_external(&mut self, _queue_id: u32) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(
+            "external rx buffers are not supported by the mock device".to_string(),
+        ))
+    }
+
+    fn rx_external_post(&mut self, _queue_id: u32, _phys_addr: usize) -> bool {
+        false
+    }
+
+    fn rx_external_batch(
+        &mut self,
+        _queue_id: u32,
+        _buffer: &mut VecDeque<(usize, usize)>,
+        _num_packets: usize,
+    ) -> usize {
+        0
+    }
+
+    /// Captures and drops all packets of `packets`.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        if self.removed || self.link_speed == 0 || !self.tx_enabled.borrow()[queue_id as usize] {
+            return 0;
+        }
+
+        let mut sent = 0;
+        let mut sent_bytes = 0;
+
+        for p in packets.drain(..) {
+            let frame: Vec<u8> = p.segments().flat_map(|s| s.iter().copied()).collect();
+
+            if self.loopback {
+                if let Some(queue) = self.rx_queues.get_mut(queue_id as usize) {
+                    queue.push_back(frame.clone());
+                }
+            }
+
+            self.tx_queues[queue_id as usize].push(frame);
+
+            sent += 1;
+            sent_bytes += p.total_len() as u64;
+        }
+
+        self.count(0, 0, sent as u64, sent_bytes);
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Completes the external buffer immediately, its contents are not captured.
+    fn tx_external(&mut self, queue_id: u32, phys_addr: usize, len: usize) -> bool {
+        if !self.tx_enabled.borrow()[queue_id as usize] {
+            return false;
+        }
+
+        self.tx_external_completed[queue_id as usize].push_back(phys_addr);
+        self.count(0, 0, 1, len as u64);
+
+        true
+    }
+
+    fn tx_external_completions(&mut self, queue_id: u32, completed: &mut VecDeque<usize>) -> usize {
+        let queue = &mut self.tx_external_completed[queue_id as usize];
+
+        let num_completed = queue.len();
+        completed.extend(queue.drain(..));
+
+        num_completed
+    }
+
+    /// Adds the stats collected since the last call to `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let new = self.stats.replace(DeviceStats::default());
+
+        stats.rx_pkts += new.rx_pkts;
+        stats.tx_pkts += new.tx_pkts;
+        stats.rx_bytes += new.rx_bytes;
+        stats.tx_bytes += new.tx_bytes;
+    }
+
+    fn reset_stats(&self) {
+        self.stats.set(DeviceStats::default());
+    }
+
+    /// The mock device has no errors and doesn't count broadcast/multicast packets.
+    fn read_extended_stats(&self, _stats: &mut ExtendedStats) {}
+
+    /// Returns the simulated link speed, 10 Gbit/s unless changed by `set_link_speed`.
+    fn get_link_speed(&self) -> u32 {
+        self.link_speed
+    }
+
+    /// The mock device pretends to sit in a slot that fits an 82599, i.e. 5 GT/s x8.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        Ok(Some(PcieLink {
+            speed: PcieSpeed::Gen2,
+            width: 8,
+            max_speed: PcieSpeed::Gen2,
+            max_width: 8,
+        }))
+    }
+
+    /// Changes the number of queues, removed queues lose their scripted and captured packets.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        self.rx_queues
+            .resize(num_rx_queues as usize, VecDeque::new());
+        self.rx_posted
+            .resize(num_rx_queues as usize, NUM_RX_QUEUE_ENTRIES - 1);
+        self.interrupt_moderation.resize(
+            num_rx_queues as usize,
+            InterruptModeration::Static(DEFAULT_ITR_US),
+        );
+        self.tx_queues.resize(num_tx_queues as usize, Vec::new());
+        self.tx_external_completed
+            .resize(num_tx_queues as usize, VecDeque::new());
+        self.rx_enabled
+            .borrow_mut()
+            .resize(num_rx_queues as usize, true);
+        self.tx_enabled
+            .borrow_mut()
+            .resize(num_tx_queues as usize, true);
+
+        Ok(())
+    }
+
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.rx_enabled.borrow_mut()[queue_id as usize] = true;
+
+        Ok(())
+    }
+
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.rx_enabled.borrow_mut()[queue_id as usize] = false;
+
+        Ok(())
+    }
+
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        self.rx_enabled
+            .borrow()
+            .get(queue_id as usize)
+            .cloned()
+            .unwrap_or(false)
+    }
+
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.tx_enabled.borrow_mut()[queue_id as usize] = true;
+
+        Ok(())
+    }
+
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+        self.tx_enabled.borrow_mut()[queue_id as usize] = false;
+
+        Ok(())
+    }
+
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        self.tx_enabled
+            .borrow()
+            .get(queue_id as usize)
+            .cloned()
+            .unwrap_or(false)
+    }
+
+    /// The mock device sends packets immediately, so its tx queues never stall.
+    fn tx_healthy(&mut self, queue_id: u32, _timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Ok(true)
+    }
+
+    /// Validates the queue, sent packets are completed immediately.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Disables rx queue `queue_id` and drops its scripted packets.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.rx_enabled.borrow_mut()[queue_id as usize] = false;
+        self.rx_queues[queue_id as usize].clear();
+
+        Ok(())
+    }
+
+    /// Validates the queue, sent packets are completed immediately.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Validates the queue, the mock device never runs out of descriptors.
+    fn set_rx_drop_enable(&self, queue_id: u32, _enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Validates the queue but otherwise ignores the thresholds.
+    fn set_rx_thresholds(
+        &self,
+        queue_id: u32,
+        _pthresh: u8,
+        _hthresh: u8,
+        _wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Validates the queue but otherwise ignores the thresholds.
+    fn set_tx_thresholds(
+        &self,
+        queue_id: u32,
+        _pthresh: u8,
+        _hthresh: u8,
+        _wthresh: u8,
+    ) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    #[cfg(feature = "dca")]
+    fn enable_rx_dca(&self, queue_id: u32, _cpu_id: u8) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    fn set_flow_control(&self, mode: FlowControl) {
+        self.flow_control.set(mode);
+    }
+
+    fn get_flow_control(&self) -> FlowControl {
+        self.flow_control.get()
+    }
+
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        self.crc_strip = enable;
+
+        Ok(())
+    }
+
+    fn get_crc_strip(&self) -> bool {
+        self.crc_strip
+    }
+
+    /// Replaces the mempool of all queues, as they share one, and drops pending rx frames of
+    /// queue `queue_id`.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        self.rx_queues[queue_id as usize].clear();
+        self.pool = Mempool::allocate_unpinned(MEMPOOL_SIZE, buffer_size.next_multiple_of(1024));
+
+        Ok(())
+    }
+
+    /// Validates the queue, the mock device has no interrupts to enable.
+    fn enable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Validates the queue, the mock device has no interrupts to disable.
+    fn disable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Records the moderation, the mock device has no interrupts to moderate.
+    fn set_interrupt_moderation(
+        &mut self,
+        queue_id: u32,
+        moderation: InterruptModeration,
+    ) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.interrupt_moderation[queue_id as usize] = moderation;
+
+        Ok(())
+    }
+
+    fn get_interrupt_moderation(&self, queue_id: u32) -> InterruptModeration {
+        self.interrupt_moderation[queue_id as usize]
+    }
+
+    /// Returns whether frames are queued without waiting, frames only arrive via `push_rx`.
+    fn wait_rx_interrupt(
+        &mut self,
+        queue_id: u32,
+        _timeout: Option<Duration>,
+    ) -> Result<bool, IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        Ok(!self.rx_queues[queue_id as usize].is_empty())
+    }
+
+    /// The mock device has an empty EEPROM.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// The mock device has no thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    /// The mock device has no descriptor rings.
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            (queue_id as usize) < self.rx_queues.len(),
+            "rx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// The mock device has no descriptor rings.
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            (queue_id as usize) < self.tx_queues.len(),
+            "tx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// The mock device has no registers.
+    fn dump_registers(&self) -> RegisterDump {
+        RegisterDump::default()
+    }
+
+    /// Restores the state after `init`, dropping queued frames and captured packets.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        let link_speed = self.link_speed;
+
+        *self = MockDevice::init(
+            &self.pci_addr,
+            self.rx_queues.len() as u16,
+            self.tx_queues.len() as u16,
+        )?;
+        self.link_speed = link_speed;
+
+        Ok(())
+    }
+
+    /// The mock device has no virtual functions.
+    fn enable_sriov(&mut self, _num_vfs: u16) -> Result<Vec<String>, IxyError> {
+        Err(IxyError::InvalidConfiguration(
+            "SR-IOV is not supported by the mock device".to_string(),
+        ))
+    }
+
+    fn disable_sriov(&mut self) -> Result<(), IxyError> {
+        Ok(())
+    }
+
+    fn get_vfs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn set_vf_mac(&mut self, vf: u16, _mac: [u8; 6]) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "virtual function {} does not exist",
+            vf
+        )))
+    }
+
+    fn set_vf_vlan(&mut self, vf: u16, _vlan: Option<u16>) -> Result<(), IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "virtual function {} does not exist",
+            vf
+        )))
+    }
+
+    fn process_vf_mailbox(&mut self) -> Result<usize, IxyError> {
+        Ok(0)
+    }
+}
+
+/// Copies `frame` into a packet of as many segments of `pool` as needed, or returns [`None`] if
+/// the pool is exhausted.
+fn alloc_segments(pool: &Arc<Mempool>, frame: &[u8]) -> Option<Packet> {
+    if frame.is_empty() {
+        return alloc_pkt(pool, 0);
+    }
+
+    let mut packet: Option<Packet> = None;
+
+    for chunk in frame.chunks(pool.entry_size() - pool.headroom()) {
+        let mut p = alloc_pkt(pool, chunk.len())?;
+        p.copy_from_slice(chunk);
+
+        match packet {
+            Some(ref mut packet) => packet.chain(p),
+            None => packet = Some(p),
+        }
+    }
+
+    packet
+}
+
+/// Returns the CRC-32 of `data` as used for the Ethernet FCS.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -1,18 +1,374 @@
-use std::error::Error;
+//! Access to PCI devices via sysfs and binding them to vfio-pci.
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, Seek, SeekFrom, Write};
-use std::os::unix::prelude::AsRawFd;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Not;
+use std::os::unix::fs::FileExt;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::path::Path;
 use std::ptr;
 
-use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crate::memory::{format_memlock_limit, has_capability, memlock_limit};
+use crate::vfio::{vfio_map_region, VFIO_PCI_BAR0_REGION_INDEX, VFIO_PCI_DRIVER};
+use crate::IxyError;
 
 // write to the command register (offset 4) in the PCIe config space
-pub const COMMAND_REGISTER_OFFSET: u64 = 4;
+pub(crate) const COMMAND_REGISTER_OFFSET: u64 = 4;
 // bit 2 is "bus master enable", see PCIe 3.0 specification section 7.5.1.1
-pub const BUS_MASTER_ENABLE_BIT: u64 = 2;
+pub(crate) const BUS_MASTER_ENABLE_BIT: u64 = 2;
+
+// a type 0 header has six base address registers
+const NUM_BARS: u8 = 6;
+
+const VENDOR_ID_OFFSET: u16 = 0;
+const DEVICE_ID_OFFSET: u16 = 2;
+const CLASS_CODE_OFFSET: u16 = 0x0b;
+
+// capabilities are only present if bit 4 of the status register is set
+const STATUS_REGISTER_OFFSET: u16 = 6;
+const STATUS_CAPABILITIES_LIST_BIT: u16 = 4;
+const CAPABILITIES_POINTER_OFFSET: u16 = 0x34;
+const CAPABILITY_ID_MSIX: u8 = 0x11;
+const CAPABILITY_ID_PCIE: u8 = 0x10;
+
+// entries of the MSI-X table, see PCIe 3.0 specification section 6.8.2.9
+const MSIX_ENTRY_SIZE: usize = 16;
+const MSIX_ENTRY_VECTOR_CONTROL: usize = 12;
+const MSIX_VECTOR_MASK_BIT: u32 = 0;
+// capabilities are at least 4 bytes long and located after the 64 byte header
+const MAX_CAPABILITIES: usize = (256 - 64) / 4;
+
+// extended capabilities follow the 256 byte pci compatible config space
+const EXTENDED_CAPABILITIES_OFFSET: u16 = 0x100;
+const MAX_EXTENDED_CAPABILITIES: usize = (4096 - 256) / 4;
+const EXT_CAPABILITY_ID_DSN: u16 = 0x0003;
+
+// link capabilities and status registers within the pci express capability
+const PCIE_LINK_CAPABILITIES_OFFSET: u16 = 0x0c;
+const PCIE_LINK_STATUS_OFFSET: u16 = 0x12;
+const PCIE_LINK_SPEED_MASK: u32 = 0xf;
+const PCIE_LINK_WIDTH_SHIFT: u32 = 4;
+const PCIE_LINK_WIDTH_MASK: u32 = 0x3f;
+
+// base class of network controllers
+const CLASS_NETWORK: u8 = 0x02;
+
+// process capabilities, grabbed from linux/capability.h
+const CAP_IPC_LOCK: u32 = 14;
+const CAP_SYS_ADMIN: u32 = 21;
+
+/// Location of the MSI-X table and pending bit array, see PCIe 3.0 specification section 7.7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsixCapability {
+    /// Number of entries of the MSI-X table.
+    pub table_size: u16,
+    /// Index of the BAR holding the MSI-X table.
+    pub table_bar: u8,
+    /// Offset of the MSI-X table within its BAR.
+    pub table_offset: u32,
+    /// Index of the BAR holding the pending bit array.
+    pub pba_bar: u8,
+    /// Offset of the pending bit array within its BAR.
+    pub pba_offset: u32,
+}
+
+/// Transfer rate of a PCIe link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PcieSpeed {
+    /// 2.5 GT/s.
+    Gen1,
+    /// 5 GT/s.
+    Gen2,
+    /// 8 GT/s.
+    Gen3,
+    /// 16 GT/s.
+    Gen4,
+    /// 32 GT/s.
+    Gen5,
+    /// 64 GT/s.
+    Gen6,
+    /// A speed encoding this driver doesn't know.
+    Unknown(u8),
+}
+
+impl PcieSpeed {
+    fn from_encoding(encoding: u8) -> PcieSpeed {
+        match encoding {
+            1 => PcieSpeed::Gen1,
+            2 => PcieSpeed::Gen2,
+            3 => PcieSpeed::Gen3,
+            4 => PcieSpeed::Gen4,
+            5 => PcieSpeed::Gen5,
+            6 => PcieSpeed::Gen6,
+            _ => PcieSpeed::Unknown(encoding),
+        }
+    }
+
+    /// Returns the usable bandwidth of a single lane in Mbit/s, i.e. without the line encoding
+    /// overhead, or 0 if the speed is unknown.
+    pub fn lane_bandwidth(self) -> u32 {
+        match self {
+            // 8b/10b encoding
+            PcieSpeed::Gen1 => 2000,
+            PcieSpeed::Gen2 => 4000,
+            // 128b/130b encoding
+            PcieSpeed::Gen3 => 7877,
+            PcieSpeed::Gen4 => 15754,
+            PcieSpeed::Gen5 => 31508,
+            // flit mode, 242 of 256 bytes carry data
+            PcieSpeed::Gen6 => 60500,
+            PcieSpeed::Unknown(_) => 0,
+        }
+    }
+}
+
+impl fmt::Display for PcieSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcieSpeed::Gen1 => write!(f, "2.5 GT/s"),
+            PcieSpeed::Gen2 => write!(f, "5 GT/s"),
+            PcieSpeed::Gen3 => write!(f, "8 GT/s"),
+            PcieSpeed::Gen4 => write!(f, "16 GT/s"),
+            PcieSpeed::Gen5 => write!(f, "32 GT/s"),
+            PcieSpeed::Gen6 => write!(f, "64 GT/s"),
+            PcieSpeed::Unknown(encoding) => write!(f, "unknown speed {}", encoding),
+        }
+    }
+}
+
+/// The negotiated and the maximum speed and width of a PCIe link, see PCIe 3.0 specification
+/// sections 7.8.6 and 7.8.8.
+///
+/// The maximum is what the device supports, the negotiated link may be slower or narrower if
+/// the slot or a bridge in between is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcieLink {
+    /// Negotiated transfer rate.
+    pub speed: PcieSpeed,
+    /// Negotiated number of lanes.
+    pub width: u8,
+    /// Maximum transfer rate of the device.
+    pub max_speed: PcieSpeed,
+    /// Maximum number of lanes of the device.
+    pub max_width: u8,
+}
+
+impl PcieLink {
+    /// Returns whether the link was trained to a lower speed or width than the device supports.
+    pub fn is_downtrained(&self) -> bool {
+        self.speed < self.max_speed || self.width < self.max_width
+    }
+
+    /// Returns the usable bandwidth of the negotiated link in Mbit/s per direction.
+    pub fn bandwidth(&self) -> u32 {
+        self.speed.lane_bandwidth() * u32::from(self.width)
+    }
+
+    /// Returns the usable bandwidth of the link at maximum speed and width in Mbit/s per
+    /// direction.
+    pub fn max_bandwidth(&self) -> u32 {
+        self.max_speed.lane_bandwidth() * u32::from(self.max_width)
+    }
+}
+
+impl fmt::Display for PcieLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} x{} (device supports {} x{})",
+            self.speed, self.width, self.max_speed, self.max_width
+        )
+    }
+}
+
+/// Checks that this process may access the device at `pci_addr` and lock `dma_size` bytes of
+/// dma memory, either via VFIO or via sysfs and `/proc/self/pagemap`.
+pub(crate) fn check_privileges(
+    pci_addr: &str,
+    vfio: bool,
+    dma_size: usize,
+) -> Result<(), IxyError> {
+    if vfio {
+        let link = fs::read_link(format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr))?;
+        let group = link
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+
+        check_access("/dev/vfio/vfio")?;
+
+        let noiommu_group = format!("/dev/vfio/noiommu-{}", group);
+        if Path::new(&noiommu_group).exists() {
+            // without an IOMMU the device uses physical addresses just like without VFIO
+            if !has_capability(CAP_SYS_ADMIN) {
+                return Err(IxyError::InsufficientPrivileges {
+                    detail: "CAP_SYS_ADMIN is required to use VFIO without an IOMMU".to_string(),
+                });
+            }
+
+            check_access(&noiommu_group)?;
+        } else {
+            check_access(&format!("/dev/vfio/{}", group))?;
+        }
+    } else {
+        // without CAP_SYS_ADMIN the kernel hides physical addresses in /proc/self/pagemap
+        if !has_capability(CAP_SYS_ADMIN) {
+            return Err(IxyError::InsufficientPrivileges {
+                detail: "CAP_SYS_ADMIN is required to use a device without the IOMMU, \
+                         run as root or bind the device to vfio-pci"
+                    .to_string(),
+            });
+        }
+
+        check_access(&format!("/sys/bus/pci/devices/{}/config", pci_addr))?;
+        check_access(&format!("/sys/bus/pci/devices/{}/resource0", pci_addr))?;
+    }
+
+    // dma memory is locked, which counts against RLIMIT_MEMLOCK without CAP_IPC_LOCK
+    if !has_capability(CAP_IPC_LOCK) {
+        if let Some(limit) = memlock_limit() {
+            if limit < dma_size as libc::rlim_t {
+                return Err(IxyError::InsufficientPrivileges {
+                    detail: format!(
+                        "{} KiB of dma memory are needed, but RLIMIT_MEMLOCK is {}",
+                        dma_size / 1024,
+                        format_memlock_limit()
+                    ),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns an `InsufficientPrivileges` error if `path` exists but can't be read and written.
+fn check_access(path: &str) -> Result<(), IxyError> {
+    let c_path = CString::new(path).map_err(|e| IxyError::Io(e.into()))?;
+
+    if unsafe {
+        libc::faccessat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            libc::R_OK | libc::W_OK,
+            libc::AT_EACCESS,
+        )
+    } != 0
+        && io::Error::last_os_error().kind() == io::ErrorKind::PermissionDenied
+    {
+        return Err(IxyError::InsufficientPrivileges {
+            detail: format!("no read and write access to {}", path),
+        });
+    }
+
+    Ok(())
+}
+
+/// A network device found by [`enumerate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDeviceInfo {
+    /// The pci address of the device, e.g. `0000:01:00.0`.
+    pub pci_addr: String,
+    /// The pci vendor id, e.g. `0x8086` for Intel.
+    pub vendor_id: u16,
+    /// The pci device id.
+    pub device_id: u16,
+    /// The kernel driver the device is bound to, if any.
+    pub driver: Option<String>,
+    /// The numa node the device is attached to, [`None`] if unknown.
+    pub numa_node: Option<u32>,
+    /// Whether one of ixy's drivers supports the device.
+    pub supported: bool,
+}
+
+/// Returns all network devices of this machine sorted by their pci address.
+///
+/// This only reads world-readable sysfs attributes, so it works without root.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::pci::enumerate;
+///
+/// for dev in enumerate().unwrap().iter().filter(|dev| dev.supported) {
+///     println!(
+///         "{} [{:04x}:{:04x}] bound to {}",
+///         dev.pci_addr,
+///         dev.vendor_id,
+///         dev.device_id,
+///         dev.driver.as_deref().unwrap_or("no driver")
+///     );
+/// }
+/// ```
+pub fn enumerate() -> Result<Vec<PciDeviceInfo>, IxyError> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir("/sys/bus/pci/devices")? {
+        let pci_addr = entry?.file_name().to_string_lossy().into_owned();
+
+        let class = read_sysfs_hex(&pci_addr, "class")?;
+        if (class >> 16) as u8 != CLASS_NETWORK {
+            continue;
+        }
+
+        let vendor_id = read_sysfs_hex(&pci_addr, "vendor")? as u16;
+        let device_id = read_sysfs_hex(&pci_addr, "device")? as u16;
+
+        devices.push(PciDeviceInfo {
+            driver: pci_driver(&pci_addr),
+            numa_node: pci_numa_node(&pci_addr),
+            supported: crate::is_supported_device(vendor_id, device_id),
+            pci_addr,
+            vendor_id,
+            device_id,
+        });
+    }
+
+    devices.sort_by(|a, b| a.pci_addr.cmp(&b.pci_addr));
+
+    Ok(devices)
+}
+
+/// Reads the hexadecimal sysfs attribute `name` of the device at `pci_addr`, e.g. `0x8086`.
+fn read_sysfs_hex(pci_addr: &str, name: &str) -> Result<u32, IxyError> {
+    let path = format!("/sys/bus/pci/devices/{}/{}", pci_addr, name);
+    let value = fs::read_to_string(&path)?;
+
+    u32::from_str_radix(value.trim().trim_start_matches("0x"), 16).map_err(|_| {
+        IxyError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid value {:?} in {}", value.trim(), path),
+        ))
+    })
+}
+
+/// Reads the decimal sysfs attribute `name` of the device at `pci_addr`.
+fn read_sysfs_dec(pci_addr: &str, name: &str) -> Result<u32, IxyError> {
+    let path = format!("/sys/bus/pci/devices/{}/{}", pci_addr, name);
+    let value = fs::read_to_string(&path)?;
+
+    value.trim().parse().map_err(|_| {
+        IxyError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid value {:?} in {}", value.trim(), path),
+        ))
+    })
+}
+
+/// Returns the numa node the device at `pci_addr` is attached to, [`None`] if unknown.
+pub(crate) fn pci_numa_node(pci_addr: &str) -> Option<u32> {
+    // the kernel reports -1 for devices on single-node machines
+    fs::read_to_string(format!("/sys/bus/pci/devices/{}/numa_node", pci_addr))
+        .ok()
+        .and_then(|node| node.trim().parse().ok())
+}
 
 /// Unbinds the driver from the device at `pci_addr`.
-pub fn unbind_driver(pci_addr: &str) -> Result<(), Box<dyn Error>> {
+pub(crate) fn unbind_driver(pci_addr: &str) -> Result<(), IxyError> {
     let path = format!("/sys/bus/pci/devices/{}/driver/unbind", pci_addr);
 
     match fs::OpenOptions::new().write(true).open(path) {
@@ -21,39 +377,140 @@ pub fn unbind_driver(pci_addr: &str) -> Result<(), Box<dyn Error>> {
             Ok(())
         }
         Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
-        Err(e) => Err(Box::new(e)),
+        Err(e) => Err(IxyError::Io(e)),
     }
 }
 
-/// Enables direct memory access for the device at `pci_addr`.
-pub fn enable_dma(pci_addr: &str) -> Result<(), Box<dyn Error>> {
-    let path = format!("/sys/bus/pci/devices/{}/config", pci_addr);
-    let mut file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
+/// Resets the device at `pci_addr`, preferring a function-level reset. The kernel restores the
+/// device's config space afterwards, so DMA stays enabled.
+pub(crate) fn pci_reset_function(pci_addr: &str) -> Result<(), IxyError> {
+    write_sysfs(&format!("/sys/bus/pci/devices/{}/reset", pci_addr), "1")
+}
 
-    assert_eq!(
-        file.seek(SeekFrom::Start(COMMAND_REGISTER_OFFSET))?,
-        COMMAND_REGISTER_OFFSET
-    );
-    let mut dma = file.read_u16::<NativeEndian>()?;
+/// Creates `num_vfs` SR-IOV virtual functions of the physical function at `pci_addr` and
+/// returns their pci addresses.
+///
+/// The kernel creates virtual functions via the driver of the physical function, i.e. it has to
+/// be bound to `vfio-pci` loaded with `enable_sriov=1`.
+pub(crate) fn pci_enable_sriov(pci_addr: &str, num_vfs: u16) -> Result<Vec<String>, IxyError> {
+    let total = match read_sysfs_dec(pci_addr, "sriov_totalvfs") {
+        Ok(total) => total,
+        Err(IxyError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} does not support SR-IOV",
+                pci_addr
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    if u32::from(num_vfs) > total {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "{} supports at most {} virtual functions",
+            pci_addr, total
+        )));
+    }
 
-    dma |= 1 << BUS_MASTER_ENABLE_BIT;
+    let path = format!("/sys/bus/pci/devices/{}/sriov_numvfs", pci_addr);
+    let current = read_sysfs_dec(pci_addr, "sriov_numvfs")?;
 
-    assert_eq!(
-        file.seek(SeekFrom::Start(COMMAND_REGISTER_OFFSET))?,
-        COMMAND_REGISTER_OFFSET
+    if current != u32::from(num_vfs) {
+        // the number of virtual functions can only be changed from or to zero
+        if current != 0 {
+            write_sysfs(&path, "0")?;
+        }
+
+        write_sysfs(&path, &num_vfs.to_string()).map_err(|e| match e {
+            IxyError::Io(ref err)
+                if err.raw_os_error() == Some(libc::ENOENT)
+                    || err.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+            {
+                IxyError::InvalidConfiguration(format!(
+                    "the driver of {} can't create virtual functions, bind it to {} loaded \
+                     with enable_sriov=1",
+                    pci_addr, VFIO_PCI_DRIVER
+                ))
+            }
+            e => e,
+        })?;
+    }
+
+    pci_virtual_functions(pci_addr)
+}
+
+/// Removes all SR-IOV virtual functions of the physical function at `pci_addr`.
+pub(crate) fn pci_disable_sriov(pci_addr: &str) -> Result<(), IxyError> {
+    write_sysfs(
+        &format!("/sys/bus/pci/devices/{}/sriov_numvfs", pci_addr),
+        "0",
+    )
+}
+
+/// Returns the pci addresses of the SR-IOV virtual functions of the physical function at
+/// `pci_addr`, ordered by their index.
+pub(crate) fn pci_virtual_functions(pci_addr: &str) -> Result<Vec<String>, IxyError> {
+    let num_vfs = read_sysfs_dec(pci_addr, "sriov_numvfs")?;
+
+    (0..num_vfs)
+        .map(|vf| {
+            let link = fs::read_link(format!("/sys/bus/pci/devices/{}/virtfn{}", pci_addr, vf))?;
+
+            Ok(link
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Masks all vectors in the MSI-X table of the device at `pci_addr`, mapping the BAR its MSI-X
+/// capability points to. Does nothing for devices without MSI-X.
+///
+/// The previous driver's vectors stay programmed, unmasked ones would let the device write
+/// their messages to stale addresses once DMA is enabled.
+pub(crate) fn pci_mask_msix_vectors(pci_addr: &str) -> Result<(), IxyError> {
+    let msix = match PciDevice::open(pci_addr)?.msix()? {
+        Some(msix) => msix,
+        None => return Ok(()),
+    };
+
+    debug!(
+        "msi-x table with {} entries in bar {} at {:#x}, pba in bar {} at {:#x}",
+        msix.table_size, msix.table_bar, msix.table_offset, msix.pba_bar, msix.pba_offset
     );
-    file.write_u16::<NativeEndian>(dma)?;
+
+    let table = MappedBar::map(pci_addr, msix.table_bar)?;
+
+    for entry in 0..usize::from(msix.table_size) {
+        let offset =
+            msix.table_offset as usize + entry * MSIX_ENTRY_SIZE + MSIX_ENTRY_VECTOR_CONTROL;
+        table.write_32(offset, table.read_32(offset) | (1 << MSIX_VECTOR_MASK_BIT));
+    }
 
     Ok(())
 }
 
-/// Mmaps a pci resource and returns a pointer to the mapped memory.
-pub fn pci_map_resource(pci_addr: &str) -> Result<(*mut u8, usize), Box<dyn Error>> {
-    let path = format!("/sys/bus/pci/devices/{}/resource0", pci_addr);
+/// Enables direct memory access for the device at `pci_addr`.
+pub(crate) fn enable_dma(pci_addr: &str) -> Result<(), IxyError> {
+    PciDevice::open(pci_addr)?.set_bus_master(true)
+}
 
+/// Unbinds the kernel driver of the device at `pci_addr`, enables DMA and mmaps its BAR0.
+/// Returns a pointer to the mapped memory and its length.
+///
+/// Other BARs are mapped with [`MappedBar::map`], which neither unbinds the driver nor enables
+/// DMA.
+pub fn pci_map_resource(pci_addr: &str) -> Result<(*mut u8, usize), IxyError> {
     unbind_driver(pci_addr)?;
     enable_dma(pci_addr)?;
 
+    pci_map_bar(pci_addr, 0)
+}
+
+/// Mmaps BAR `bar` of the device at `pci_addr` and returns a pointer to the mapped memory.
+fn pci_map_bar(pci_addr: &str, bar: u8) -> Result<(*mut u8, usize), IxyError> {
+    let path = format!("/sys/bus/pci/devices/{}/resource{}", pci_addr, bar);
+
     let file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
     let len = fs::metadata(&path)?.len() as usize;
 
@@ -68,27 +525,615 @@ pub fn pci_map_resource(pci_addr: &str) -> Result<(*mut u8, usize), Box<dyn Erro
         ) as *mut u8
     };
 
-    if ptr.is_null() || len == 0 {
-        Err("pci mapping failed".into())
+    if ptr as *mut libc::c_void == libc::MAP_FAILED || len == 0 {
+        Err(IxyError::Io(io::Error::other(format!(
+            "failed to map {}",
+            path
+        ))))
     } else {
         Ok((ptr, len))
     }
 }
 
-/// Opens a pci resource file at the given address.
-pub fn pci_open_resource(pci_addr: &str, resource: &str) -> Result<File, Box<dyn Error>> {
-    let path = format!("/sys/bus/pci/devices/{}/{}", pci_addr, resource);
-    Ok(File::open(path)?)
+/// A memory-mapped base address register (BAR) of a PCI device, unmapped on drop.
+///
+/// Drivers map BAR0 themselves, this gives access to the other BARs some devices place their
+/// admin queues or MSI-X tables in.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::pci::MappedBar;
+///
+/// let bar = MappedBar::map("0000:01:00.0", 4).unwrap();
+///
+/// println!("BAR4 has {} bytes, starts with {:08x}", bar.len(), bar.read_32(0));
+/// ```
+pub struct MappedBar {
+    addr: *mut u8,
+    len: usize,
+    bar: u8,
+}
+
+impl MappedBar {
+    /// Maps BAR `bar` of the device at `pci_addr` via its sysfs resource file, requires root.
+    pub fn map(pci_addr: &str, bar: u8) -> Result<MappedBar, IxyError> {
+        check_bar(bar)?;
+
+        let (addr, len) = pci_map_bar(pci_addr, bar)?;
+
+        Ok(MappedBar { addr, len, bar })
+    }
+
+    /// Maps BAR `bar` of the VFIO device `device_fd` via its VFIO region.
+    pub fn map_vfio(device_fd: RawFd, bar: u8) -> Result<MappedBar, IxyError> {
+        check_bar(bar)?;
+
+        let (addr, len) = vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX + u32::from(bar))?;
+
+        Ok(MappedBar { addr, len, bar })
+    }
+
+    /// Returns the index of this BAR.
+    pub fn bar(&self) -> u8 {
+        self.bar
+    }
+
+    /// Returns the size of this BAR in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether this BAR has a size of zero, i.e. is not implemented by the device.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a pointer to the start of the mapped memory.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.addr
+    }
+
+    /// Reads the byte at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` does not belong to the mapped memory.
+    pub fn read_8(&self, offset: usize) -> u8 {
+        unsafe { ptr::read_volatile(self.ptr::<u8>(offset)) }
+    }
+
+    /// Reads the little endian word at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is unaligned or does not belong to the mapped memory.
+    pub fn read_16(&self, offset: usize) -> u16 {
+        u16::from_le(unsafe { ptr::read_volatile(self.ptr::<u16>(offset)) })
+    }
+
+    /// Reads the little endian double word at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is unaligned or does not belong to the mapped memory.
+    pub fn read_32(&self, offset: usize) -> u32 {
+        u32::from_le(unsafe { ptr::read_volatile(self.ptr::<u32>(offset)) })
+    }
+
+    /// Writes the byte `value` to `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` does not belong to the mapped memory.
+    pub fn write_8(&self, offset: usize, value: u8) {
+        unsafe { ptr::write_volatile(self.ptr::<u8>(offset), value) }
+    }
+
+    /// Writes the little endian word `value` to `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is unaligned or does not belong to the mapped memory.
+    pub fn write_16(&self, offset: usize, value: u16) {
+        unsafe { ptr::write_volatile(self.ptr::<u16>(offset), value.to_le()) }
+    }
+
+    /// Writes the little endian double word `value` to `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is unaligned or does not belong to the mapped memory.
+    pub fn write_32(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.ptr::<u32>(offset), value.to_le()) }
+    }
+
+    /// Returns a pointer to the `T` at `offset`.
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        let size = mem::size_of::<T>();
+        assert!(
+            self.len >= size && offset <= self.len - size,
+            "memory access out of bounds"
+        );
+        assert_eq!(offset % size, 0, "unaligned memory access");
+
+        (self.addr as usize + offset) as *mut T
+    }
+}
+
+impl Drop for MappedBar {
+    fn drop(&mut self) {
+        if unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) } != 0 {
+            warn!(
+                "failed to unmap BAR{} at {:p}: {}",
+                self.bar,
+                self.addr,
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Detects the surprise removal of a device from its registers reading as all ones.
+///
+/// A removed device answers every read with all ones only after a completion timeout, so the
+/// removal is latched and drivers stop accessing the device. Other registers may legitimately
+/// read as all ones, the status register `R` never does.
+pub(crate) struct RemovalLatch<R> {
+    status_reg: R,
+    removed: Cell<bool>,
+}
+
+impl<R: Copy + PartialEq> RemovalLatch<R> {
+    /// Returns a latch for a device that is present, `status_reg` never reads as all ones.
+    pub(crate) fn new(status_reg: R) -> RemovalLatch<R> {
+        RemovalLatch {
+            status_reg,
+            removed: Cell::new(false),
+        }
+    }
+
+    /// Returns whether the device was removed.
+    pub(crate) fn is_removed(&self) -> bool {
+        self.removed.get()
+    }
+
+    /// Returns register `reg` as read by `read`, or all ones without reading it once the device
+    /// at `pci_addr` was removed. A read of all ones is confirmed with the status register.
+    pub(crate) fn read<T>(&self, pci_addr: &str, reg: R, read: impl Fn(R) -> T) -> T
+    where
+        T: Copy + Default + PartialEq + Not<Output = T>,
+    {
+        let failed = !T::default();
+
+        if self.removed.get() {
+            return failed;
+        }
+
+        let value = read(reg);
+
+        if value == failed
+            && (reg == self.status_reg || read(self.status_reg) == failed)
+            && !self.removed.replace(true)
+        {
+            warn!("device {} was removed or is in reset", pci_addr);
+        }
+
+        value
+    }
+}
+
+/// Returns an error if `bar` is not a valid BAR index.
+fn check_bar(bar: u8) -> Result<(), IxyError> {
+    if bar >= NUM_BARS {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "BAR{} does not exist, devices have at most {} BARs",
+            bar, NUM_BARS
+        )));
+    }
+
+    Ok(())
 }
 
-/// Reads and returns an u16 at `offset` in `file`.
-pub fn read_io16(file: &mut File, offset: usize) -> Result<u16, Box<dyn Error>> {
-    file.seek(SeekFrom::Start(offset as u64))?;
-    Ok(file.read_u16::<NativeEndian>()?)
+/// The configuration space of a PCI device, accessed via sysfs.
+///
+/// Reads beyond the first 64 bytes and all writes require root, other processes see the rest
+/// of the config space as zeros.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::pci::PciDevice;
+///
+/// let dev = PciDevice::open("0000:01:00.0").unwrap();
+///
+/// println!("{:04x}:{:04x}", dev.vendor_id().unwrap(), dev.device_id().unwrap());
+/// if let Some(serial) = dev.serial_number().unwrap() {
+///     println!("serial number {:016x}", serial);
+/// }
+/// ```
+pub struct PciDevice {
+    pci_addr: String,
+    config: File,
 }
 
-/// Reads and returns an u32 at `offset` in `file`.
-pub fn read_io32(file: &mut File, offset: usize) -> Result<u32, Box<dyn Error>> {
-    file.seek(SeekFrom::Start(offset as u64))?;
-    Ok(file.read_u32::<NativeEndian>()?)
+impl PciDevice {
+    /// Opens the config space of the device at `pci_addr`, read-only if this process may not
+    /// write it.
+    pub fn open(pci_addr: &str) -> Result<PciDevice, IxyError> {
+        let path = format!("/sys/bus/pci/devices/{}/config", pci_addr);
+
+        let config = match fs::OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => File::open(&path)?,
+            Err(e) => return Err(IxyError::Io(e)),
+        };
+
+        Ok(PciDevice {
+            pci_addr: pci_addr.to_string(),
+            config,
+        })
+    }
+
+    /// Returns the pci address of this device.
+    pub fn pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    /// Reads the byte at `offset` of the config space.
+    pub fn read_config_8(&self, offset: u16) -> Result<u8, IxyError> {
+        let mut buf = [0; 1];
+        self.config.read_exact_at(&mut buf, u64::from(offset))?;
+        Ok(buf[0])
+    }
+
+    /// Reads the 16 bit word at `offset` of the config space.
+    pub fn read_config_16(&self, offset: u16) -> Result<u16, IxyError> {
+        let mut buf = [0; 2];
+        self.config.read_exact_at(&mut buf, u64::from(offset))?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    /// Reads the 32 bit word at `offset` of the config space.
+    pub fn read_config_32(&self, offset: u16) -> Result<u32, IxyError> {
+        let mut buf = [0; 4];
+        self.config.read_exact_at(&mut buf, u64::from(offset))?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    /// Writes the byte at `offset` of the config space.
+    pub fn write_config_8(&self, offset: u16, value: u8) -> Result<(), IxyError> {
+        self.write_config(offset, &[value])
+    }
+
+    /// Writes the 16 bit word at `offset` of the config space.
+    pub fn write_config_16(&self, offset: u16, value: u16) -> Result<(), IxyError> {
+        self.write_config(offset, &value.to_le_bytes())
+    }
+
+    /// Writes the 32 bit word at `offset` of the config space.
+    pub fn write_config_32(&self, offset: u16, value: u32) -> Result<(), IxyError> {
+        self.write_config(offset, &value.to_le_bytes())
+    }
+
+    fn write_config(&self, offset: u16, value: &[u8]) -> Result<(), IxyError> {
+        self.config
+            .write_all_at(value, u64::from(offset))
+            .map_err(|e| match e.raw_os_error() {
+                Some(libc::EBADF) => IxyError::InsufficientPrivileges {
+                    detail: format!("no write access to the config space of {}", self.pci_addr),
+                },
+                _ => IxyError::Io(e),
+            })
+    }
+
+    /// Returns the vendor id of this device.
+    pub fn vendor_id(&self) -> Result<u16, IxyError> {
+        self.read_config_16(VENDOR_ID_OFFSET)
+    }
+
+    /// Returns the device id of this device.
+    pub fn device_id(&self) -> Result<u16, IxyError> {
+        self.read_config_16(DEVICE_ID_OFFSET)
+    }
+
+    /// Returns the class code of this device, e.g. 0x02 for network controllers.
+    pub fn class(&self) -> Result<u8, IxyError> {
+        self.read_config_8(CLASS_CODE_OFFSET)
+    }
+
+    /// Enables or disables bus mastering, i.e. whether the device may access memory via DMA.
+    pub fn set_bus_master(&self, enable: bool) -> Result<(), IxyError> {
+        let offset = COMMAND_REGISTER_OFFSET as u16;
+        let command = self.read_config_16(offset)?;
+
+        let command = if enable {
+            command | (1 << BUS_MASTER_ENABLE_BIT)
+        } else {
+            command & !(1 << BUS_MASTER_ENABLE_BIT)
+        };
+
+        self.write_config_16(offset, command)
+    }
+
+    /// Returns the ids and offsets of all capabilities in the standard config space, see PCIe
+    /// 3.0 specification section 7.8.
+    pub fn capabilities(&self) -> Result<Vec<(u8, u16)>, IxyError> {
+        let mut capabilities = Vec::new();
+
+        if self.read_config_16(STATUS_REGISTER_OFFSET)? & (1 << STATUS_CAPABILITIES_LIST_BIT) == 0 {
+            return Ok(capabilities);
+        }
+
+        // walk the linked list of capabilities, the lowest two bits of pointers are reserved
+        let mut cap = u16::from(self.read_config_8(CAPABILITIES_POINTER_OFFSET)? & !0x3);
+
+        // a broken list may loop, but can hold at most this many capabilities
+        while cap != 0 && capabilities.len() < MAX_CAPABILITIES {
+            capabilities.push((self.read_config_8(cap)?, cap));
+            cap = u16::from(self.read_config_8(cap + 1)? & !0x3);
+        }
+
+        Ok(capabilities)
+    }
+
+    /// Returns the offset of the capability `id` in the standard config space, or [`None`] if
+    /// the device doesn't have it.
+    pub fn find_capability(&self, id: u8) -> Result<Option<u16>, IxyError> {
+        Ok(self
+            .capabilities()?
+            .into_iter()
+            .find(|&(cap_id, _)| cap_id == id)
+            .map(|(_, offset)| offset))
+    }
+
+    /// Returns the offset of the extended capability `id` in the extended config space, or
+    /// [`None`] if the device doesn't have it, see PCIe 3.0 specification section 7.9.
+    ///
+    /// The extended config space is only readable by root.
+    pub fn find_extended_capability(&self, id: u16) -> Result<Option<u16>, IxyError> {
+        let mut offset = EXTENDED_CAPABILITIES_OFFSET;
+
+        for _ in 0..MAX_EXTENDED_CAPABILITIES {
+            let header = match self.read_config_32(offset) {
+                Ok(header) => header,
+                // conventional pci devices have no extended config space
+                Err(IxyError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+
+            if header == 0 || header == 0xffff_ffff {
+                return Ok(None);
+            }
+            if header as u16 == id {
+                return Ok(Some(offset));
+            }
+
+            // the next pointer is in bits 31:20, its lowest two bits are reserved
+            offset = (header >> 20) as u16 & !0x3;
+            if offset < EXTENDED_CAPABILITIES_OFFSET {
+                return Ok(None);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the MSI-X capability of this device, or [`None`] if it has none.
+    pub fn msix(&self) -> Result<Option<MsixCapability>, IxyError> {
+        let cap = match self.find_capability(CAPABILITY_ID_MSIX)? {
+            Some(cap) => cap,
+            None => return Ok(None),
+        };
+
+        let control = self.read_config_16(cap + 2)?;
+        let table = self.read_config_32(cap + 4)?;
+        let pba = self.read_config_32(cap + 8)?;
+
+        // the lowest three bits of the offsets select the BAR
+        Ok(Some(MsixCapability {
+            table_size: (control & 0x7ff) + 1,
+            table_bar: (table & 0x7) as u8,
+            table_offset: table & !0x7,
+            pba_bar: (pba & 0x7) as u8,
+            pba_offset: pba & !0x7,
+        }))
+    }
+
+    /// Returns the device serial number, or [`None`] if the device doesn't report one, see
+    /// PCIe 3.0 specification section 7.13.
+    pub fn serial_number(&self) -> Result<Option<u64>, IxyError> {
+        let cap = match self.find_extended_capability(EXT_CAPABILITY_ID_DSN)? {
+            Some(cap) => cap,
+            None => return Ok(None),
+        };
+
+        let low = self.read_config_32(cap + 4)?;
+        let high = self.read_config_32(cap + 8)?;
+
+        Ok(Some(u64::from(low) | (u64::from(high) << 32)))
+    }
+
+    /// Returns the negotiated and maximum speed and width of the PCIe link, or [`None`] if
+    /// this is not a PCIe device.
+    pub fn pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        let cap = match self.find_capability(CAPABILITY_ID_PCIE)? {
+            Some(cap) => cap,
+            None => return Ok(None),
+        };
+
+        let capabilities = self.read_config_32(cap + PCIE_LINK_CAPABILITIES_OFFSET)?;
+        let status = u32::from(self.read_config_16(cap + PCIE_LINK_STATUS_OFFSET)?);
+
+        let speed = |reg: u32| PcieSpeed::from_encoding((reg & PCIE_LINK_SPEED_MASK) as u8);
+        let width = |reg: u32| ((reg >> PCIE_LINK_WIDTH_SHIFT) & PCIE_LINK_WIDTH_MASK) as u8;
+
+        Ok(Some(PcieLink {
+            speed: speed(status),
+            width: width(status),
+            max_speed: speed(capabilities),
+            max_width: width(capabilities),
+        }))
+    }
+}
+
+/// A device that [`bind_to_vfio`] took over from its kernel driver.
+///
+/// Dropping the binding hands the device back to its previous driver, so it has to outlive the
+/// `IxyDevice` using it: unbinding from vfio-pci blocks until the device is closed.
+pub struct VfioBinding {
+    pci_addr: String,
+    previous_driver: Option<String>,
+    // whether the device was bound by us, devices already bound to vfio-pci are left alone
+    restore: bool,
+}
+
+impl VfioBinding {
+    /// Returns the pci address of the device.
+    pub fn pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    /// Returns the driver the device was bound to before, if any.
+    pub fn previous_driver(&self) -> Option<&str> {
+        self.previous_driver.as_deref()
+    }
+
+    /// Unbinds the device from vfio-pci and binds it to its previous driver again.
+    pub fn unbind(mut self) -> Result<(), IxyError> {
+        self.restore_driver()
+    }
+
+    fn restore_driver(&mut self) -> Result<(), IxyError> {
+        if !mem::replace(&mut self.restore, false) {
+            return Ok(());
+        }
+
+        write_sysfs(
+            &format!("/sys/bus/pci/drivers/{}/unbind", VFIO_PCI_DRIVER),
+            &self.pci_addr,
+        )?;
+        // an empty override lets the device match drivers by its ids again
+        write_sysfs(
+            &format!("/sys/bus/pci/devices/{}/driver_override", self.pci_addr),
+            "\n",
+        )?;
+
+        if let Some(ref driver) = self.previous_driver {
+            write_sysfs(
+                &format!("/sys/bus/pci/drivers/{}/bind", driver),
+                &self.pci_addr,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for VfioBinding {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore_driver() {
+            warn!("failed to unbind {} from vfio-pci: {}", self.pci_addr, e);
+        }
+    }
+}
+
+/// Binds the device at `pci_addr` to vfio-pci, unbinding it from its kernel driver first.
+///
+/// This does what the README describes for using the IOMMU by hand, except for loading the
+/// vfio-pci module and handing the group device to the user. The other devices of the device's
+/// IOMMU group have to be bound to vfio-pci as well, see
+/// [`GroupInfo`](crate::vfio::GroupInfo). Requires root.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::pci::bind_to_vfio;
+/// use ixy::*;
+///
+/// let binding = bind_to_vfio("0000:01:00.0").unwrap();
+/// let dev = ixy_init("0000:01:00.0", 1, 1).unwrap();
+///
+/// // ...
+///
+/// // the device has to be closed before it can be handed back to its kernel driver
+/// drop(dev);
+/// binding.unbind().unwrap();
+/// ```
+pub fn bind_to_vfio(pci_addr: &str) -> Result<VfioBinding, IxyError> {
+    if !Path::new(&format!("/sys/bus/pci/devices/{}", pci_addr)).exists() {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "no pci device at {}",
+            pci_addr
+        )));
+    }
+
+    if !Path::new(&format!("/sys/bus/pci/drivers/{}", VFIO_PCI_DRIVER)).exists() {
+        return Err(IxyError::InvalidConfiguration(
+            "the vfio-pci driver is not loaded, run modprobe vfio-pci".to_string(),
+        ));
+    }
+
+    let previous_driver = pci_driver(pci_addr);
+    if previous_driver.as_deref() == Some(VFIO_PCI_DRIVER) {
+        return Ok(VfioBinding {
+            pci_addr: pci_addr.to_string(),
+            previous_driver,
+            restore: false,
+        });
+    }
+
+    if let Some(ref driver) = previous_driver {
+        info!("unbinding {} from {}", pci_addr, driver);
+    }
+    unbind_driver(pci_addr)?;
+
+    // from now on the device is handed back when the binding is dropped, even if binding fails
+    let binding = VfioBinding {
+        pci_addr: pci_addr.to_string(),
+        previous_driver,
+        restore: true,
+    };
+
+    // the override makes vfio-pci accept the device without adding its ids
+    write_sysfs(
+        &format!("/sys/bus/pci/devices/{}/driver_override", pci_addr),
+        VFIO_PCI_DRIVER,
+    )?;
+    write_sysfs(
+        &format!("/sys/bus/pci/drivers/{}/bind", VFIO_PCI_DRIVER),
+        pci_addr,
+    )?;
+
+    if pci_driver(pci_addr).as_deref() != Some(VFIO_PCI_DRIVER) {
+        return Err(IxyError::VfioNotBound(pci_addr.to_string()));
+    }
+
+    Ok(binding)
+}
+
+/// Returns the name of the driver the device at `pci_addr` is bound to, if any.
+pub(crate) fn pci_driver(pci_addr: &str) -> Option<String> {
+    let link = fs::read_link(format!("/sys/bus/pci/devices/{}/driver", pci_addr)).ok()?;
+
+    Some(link.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Writes `value` to the sysfs attribute at `path`.
+fn write_sysfs(path: &str, value: &str) -> Result<(), IxyError> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => IxyError::InsufficientPrivileges {
+                detail: format!("no write access to {}, run as root", path),
+            },
+            _ => IxyError::Io(e),
+        })?;
+
+    file.write_all(value.as_bytes())?;
+
+    Ok(())
 }
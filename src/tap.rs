@@ -0,0 +1,748 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice, Read, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::af_xdp::set_interface_flag;
+use crate::memory::*;
+
+use crate::pci::{MappedBar, PcieLink};
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::InterruptModeration;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-tap";
+
+/// Prefix of the names passed to `ixy_init` that select this backend, e.g. `tap:ixy0`.
+pub(crate) const TAP_PREFIX: &str = "tap:";
+
+const PKT_BUF_ENTRY_SIZE: usize = 2048;
+const MAX_RX_BUFFER_SIZE: usize = 16 * 1024;
+const MEMPOOL_SIZE: usize = 1024;
+
+// the kernel queues up to txqueuelen frames per queue, the limit of a single rx_batch is
+// modeled after the descriptor rings of the drivers
+const NUM_RX_QUEUE_ENTRIES: usize = 512;
+
+// taps have no physical link, they are reported as gigabit NICs while the interface is up
+const LINK_SPEED: u32 = 1000;
+
+/// Backend that exchanges packets with the kernel via a TAP interface, e.g. to develop and
+/// debug applications without a network card, hugepages or root privileges.
+///
+/// Frames the kernel sends on the interface are received, packets sent are received by the
+/// kernel's network stack as if they arrived on the interface. Every queue is a file
+/// descriptor of a multi-queue tap, the kernel spreads its frames across them by flow.
+///
+/// Creating the interface needs `CAP_NET_ADMIN`, a tap created beforehand for the user, e.g.
+/// with `ip tuntap add ixy0 mode tap multi_queue user $USER`, is opened without privileges.
+/// Packets are copied between the kernel and ordinary memory, so this backend is far slower
+/// than the drivers.
+pub struct TapDevice {
+    // the name passed to ixy_init, i.e. including the prefix
+    name: String,
+    ifname: String,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    // queue i receives if i < num_rx_queues and sends if i < num_tx_queues
+    queues: Vec<TapQueue>,
+    mac_addr: Cell<[u8; 6]>,
+    poll_strategy: PollStrategy,
+    // the kernel counts in the opposite direction, the driver counts the packets itself
+    totals: TapCounters,
+    counters: Cell<TapCounters>,
+    // set once the interface is gone, it is not accessed anymore
+    removed: Cell<bool>,
+}
+
+/// Values of the packet counters summed over all queues.
+#[derive(Clone, Copy, Default)]
+struct TapCounters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+/// Queue of the tap, i.e. a non-blocking file descriptor attached to the interface.
+struct TapQueue {
+    file: File,
+    pool: Arc<Mempool>,
+    num_posted: usize,
+    rx_enabled: Cell<bool>,
+}
+
+impl TapQueue {
+    /// Attaches a new queue to tap `ifname`, the tap is created if it does not exist yet.
+    fn open(ifname: &str, multi_queue: bool) -> Result<TapQueue, IxyError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/net/tun")?;
+
+        let mut ifreq: libc::ifreq = unsafe { mem::zeroed() };
+        for (dst, &src) in ifreq.ifr_name.iter_mut().zip(ifname.as_bytes()) {
+            *dst = src as libc::c_char;
+        }
+
+        let mut flags = libc::IFF_TAP | libc::IFF_NO_PI;
+        if multi_queue {
+            flags |= libc::IFF_MULTI_QUEUE;
+        }
+        ifreq.ifr_ifru.ifru_flags = flags as libc::c_short;
+
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF as _, &ifreq) } == -1 {
+            let err = io::Error::last_os_error();
+
+            return Err(match err.raw_os_error() {
+                Some(libc::EPERM) => IxyError::InsufficientPrivileges {
+                    detail: format!(
+                        "creating tap {} needs CAP_NET_ADMIN, create it for this user with `ip \
+                         tuntap add {} mode tap multi_queue user $USER`",
+                        ifname, ifname
+                    ),
+                },
+                // the interface exists, but is no tap or has the other queue mode
+                Some(libc::EINVAL) => IxyError::InvalidConfiguration(format!(
+                    "cannot attach to {}: not a{} tap",
+                    ifname,
+                    if multi_queue { " multi-queue" } else { "" }
+                )),
+                _ => IxyError::Io(err),
+            });
+        }
+
+        Ok(TapQueue {
+            file,
+            pool: Mempool::allocate_anonymous(MEMPOOL_SIZE, PKT_BUF_ENTRY_SIZE)?,
+            num_posted: NUM_RX_QUEUE_ENTRIES,
+            rx_enabled: Cell::new(true),
+        })
+    }
+
+    /// Reads up to `num_packets` frames into packets and pushes them onto `buffer`. Returns the
+    /// number of packets and their bytes.
+    fn receive(&mut self, buffer: &mut VecDeque<Packet>, num_packets: usize) -> (usize, u64) {
+        let buf_len = self.pool.entry_size() - self.pool.headroom();
+
+        let mut received = 0;
+        let mut bytes = 0;
+
+        while received < num_packets {
+            let mut p = match alloc_pkt(&self.pool, buf_len) {
+                Some(p) => p,
+                None => break,
+            };
+
+            // every read returns a single frame, which is truncated if it exceeds the buffer
+            let len = match self.file.read(&mut p) {
+                Ok(len) => len,
+                Err(_) => break,
+            };
+
+            if len > buf_len {
+                warn!(
+                    "dropping frame of {} bytes: rx buffers hold at most {} bytes",
+                    len, buf_len
+                );
+                continue;
+            }
+
+            p.len = len;
+            bytes += len as u64;
+            received += 1;
+
+            buffer.push_back(p);
+        }
+
+        (received, bytes)
+    }
+
+    /// Writes packets of `packets` to the tap until the kernel's queue is full. Returns the
+    /// number of packets and their bytes.
+    fn send(&mut self, packets: &mut VecDeque<Packet>) -> (usize, u64) {
+        let mut sent = 0;
+        let mut bytes = 0;
+
+        while let Some(p) = packets.pop_front() {
+            // every write is a single frame, so chained packets are written at once
+            let result = if p.next.is_none() {
+                self.file.write(&p)
+            } else {
+                let segments: Vec<IoSlice<'_>> = p.segments().map(|s| IoSlice::new(s)).collect();
+                self.file.write_vectored(&segments)
+            };
+
+            match result {
+                Ok(len) => {
+                    bytes += len as u64;
+                    sent += 1;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    packets.push_front(p);
+                    break;
+                }
+                // the kernel drops frames while the interface is down, like a nic without link
+                Err(_) => {}
+            }
+        }
+
+        (sent, bytes)
+    }
+
+    /// Reads and drops all frames the kernel queued for this queue.
+    fn discard(&mut self) {
+        let mut buf = vec![0; MAX_RX_BUFFER_SIZE];
+
+        while self.file.read(&mut buf).is_ok() {}
+    }
+}
+
+impl IxyDevice for TapDevice {
+    /// Returns an initialized `TapDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(name: &str, num_rx_queues: u16, num_tx_queues: u16) -> Result<TapDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        let ifname = name.strip_prefix(TAP_PREFIX).unwrap_or(name);
+
+        if ifname.is_empty() || ifname.len() >= libc::IFNAMSIZ {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "invalid interface name {}",
+                ifname
+            )));
+        }
+
+        let num_queues = num_rx_queues.max(num_tx_queues).max(1);
+
+        let mut queues = Vec::with_capacity(usize::from(num_queues));
+
+        // taps created without multi_queue only take a single queue
+        match TapQueue::open(ifname, true) {
+            Ok(queue) => queues.push(queue),
+            Err(IxyError::InvalidConfiguration(_)) if num_queues == 1 => {
+                queues.push(TapQueue::open(ifname, false)?)
+            }
+            Err(e) => return Err(e),
+        }
+
+        for _ in 1..num_queues {
+            queues.push(TapQueue::open(ifname, true)?);
+        }
+
+        // the interface has to be up for the kernel to exchange frames with it
+        if let Err(e) = set_interface_flag(ifname, libc::IFF_UP, true) {
+            warn!(
+                "cannot bring up {}, bring it up with `ip link set {} up`: {}",
+                ifname, ifname, e
+            );
+        }
+
+        // a locally administered address derived from the name, the kernel side has its own
+        let mut mac_addr = [0x02, 0x00, 0x00, 0x00, 0x00, 0x00];
+        for (i, byte) in ifname.bytes().enumerate() {
+            mac_addr[2 + i % 4] ^= byte;
+        }
+
+        Ok(TapDevice {
+            name: format!("{}{}", TAP_PREFIX, ifname),
+            ifname: ifname.to_string(),
+            num_rx_queues,
+            num_tx_queues,
+            queues,
+            mac_addr: Cell::new(mac_addr),
+            poll_strategy: PollStrategy::BusySpin,
+            totals: TapCounters::default(),
+            counters: Cell::new(TapCounters::default()),
+            removed: Cell::new(false),
+        })
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    fn is_card_iommu_capable(&self) -> bool {
+        false
+    }
+
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Returns the name of this device, i.e. the interface name with the `tap:` prefix.
+    fn get_pci_addr(&self) -> &str {
+        &self.name
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        Err(IxyError::InvalidConfiguration(format!(
+            "tap device has no BAR{} to map",
+            bar
+        )))
+    }
+
+    /// Returns the mac address of this side of the tap, the kernel's side has its own.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac_addr.get()
+    }
+
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        self.mac_addr.set(mac);
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let queue = &mut self.queues[queue_id as usize];
+
+        if !queue.rx_enabled.get() {
+            return 0;
+        }
+
+        let num_packets = num_packets.min(queue.num_posted);
+        let (received, bytes) = queue.receive(buffer, num_packets);
+
+        self.totals.rx_pkts += received as u64;
+        self.totals.rx_bytes += bytes;
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the interface was deleted.
+    fn is_removed(&self) -> bool {
+        if !self.removed.get() && !Path::new(&self.sysfs_path("")).exists() {
+            self.removed.set(true);
+        }
+
+        self.removed.get()
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns [`None`], frames stay in the kernel until they are read by `rx_batch`.
+    fn rx_peek(&self, _queue_id: u32) -> Option<&[u8]> {
+        None
+    }
+
+    /// Limits the number of packets a single `rx_batch` call returns to `num_posted`.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if num_posted > NUM_RX_QUEUE_ENTRIES {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} buffers: limit is {}",
+                num_posted, NUM_RX_QUEUE_ENTRIES
+            )));
+        }
+
+        self.queues[queue_id as usize].num_posted = num_posted;
+
+        Ok(())
+    }
+
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.queues[queue_id as usize].num_posted
+    }
+
+    /// Writes as many packets as possible from `packets` to the tap, they are finished right
+    /// away.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let (sent, bytes) = self.queues[queue_id as usize].send(packets);
+
+        self.totals.tx_pkts += sent as u64;
+        self.totals.tx_bytes += bytes;
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`, the packets are counted by the driver.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.totals;
+
+        stats.rx_pkts += current.rx_pkts - last.rx_pkts;
+        stats.tx_pkts += current.tx_pkts - last.tx_pkts;
+        stats.rx_bytes += current.rx_bytes - last.rx_bytes;
+        stats.tx_bytes += current.tx_bytes - last.tx_bytes;
+
+        self.counters.set(current);
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.counters.set(self.totals);
+    }
+
+    /// Taps have no error counters, the kernel's counters of the interface are in sysfs.
+    fn read_extended_stats(&self, _stats: &mut ExtendedStats) {}
+
+    /// Returns 0 while the interface is down and a gigabit otherwise.
+    fn get_link_speed(&self) -> u32 {
+        // taps without carrier detection report their state as unknown once they are up
+        match fs::read_to_string(self.sysfs_path("operstate")) {
+            Ok(operstate) if matches!(operstate.trim(), "up" | "unknown") => LINK_SPEED,
+            _ => 0,
+        }
+    }
+
+    /// Taps are no pci devices.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        Ok(None)
+    }
+
+    /// Changes the number of queues by attaching queues to or detaching them from the tap,
+    /// frames queued in the kernel for removed queues are lost.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        // the last queue keeps the interface from being deleted
+        let num_queues = usize::from(num_rx_queues.max(num_tx_queues).max(1));
+
+        self.queues.truncate(num_queues);
+        while self.queues.len() < num_queues {
+            self.queues.push(TapQueue::open(&self.ifname, true)?);
+        }
+
+        self.num_rx_queues = num_rx_queues;
+        self.num_tx_queues = num_tx_queues;
+
+        Ok(())
+    }
+
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.queues[queue_id as usize].rx_enabled.set(true);
+
+        Ok(())
+    }
+
+    /// Stops reading from the queue, the kernel drops frames once its queue is full.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+        self.queues[queue_id as usize].rx_enabled.set(false);
+
+        Ok(())
+    }
+
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues) && self.queues[queue_id as usize].rx_enabled.get()
+    }
+
+    /// The tx queues of a tap send as long as its file descriptor is open.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Single tx queues of a tap are not stopped.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable tx queues",
+            DRIVER_NAME
+        )))
+    }
+
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+    }
+
+    /// Returns true, packets are finished as soon as they are written.
+    fn tx_healthy(&mut self, queue_id: u32, _timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Ok(true)
+    }
+
+    /// Validates the queue, packets are finished as soon as they are written.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Disables rx queue `queue_id` and drops the frames the kernel queued for it.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let queue = &mut self.queues[queue_id as usize];
+        queue.rx_enabled.set(false);
+        queue.discard();
+
+        Ok(())
+    }
+
+    /// Validates the queue, packets are finished as soon as they are written.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// The kernel drops frames once the queue of the tap is full.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} always drops packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Taps have no pause frames.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "ignoring flow control mode {:?} of {}: taps have no pause frames",
+            mode, self.name
+        );
+    }
+
+    /// Returns [`FlowControl::None`], taps have no pause frames.
+    fn get_flow_control(&self) -> FlowControl {
+        FlowControl::None
+    }
+
+    /// Frames of a tap never carry a CRC.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot keep the crc on {}: tap frames have none",
+                self.name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, received frames never carry a CRC.
+    fn get_crc_strip(&self) -> bool {
+        true
+    }
+
+    /// Replaces the mempool of rx queue `queue_id` with one of `buffer_size` byte buffers.
+    /// Frames larger than a buffer are dropped.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        self.queues[queue_id as usize].pool =
+            Mempool::allocate_anonymous(MEMPOOL_SIZE, buffer_size)?;
+
+        Ok(())
+    }
+
+    /// Validates the queue, `wait_rx_interrupt` polls the file descriptor of the queue.
+    fn enable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Validates the queue, `wait_rx_interrupt` polls the file descriptor of the queue.
+    fn disable_rx_interrupt(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)
+    }
+
+    /// Returns no moderation, the kernel wakes up waiters for every frame.
+    fn get_interrupt_moderation(&self, _queue_id: u32) -> InterruptModeration {
+        InterruptModeration::Static(0)
+    }
+
+    /// Sleeps until the kernel queued a frame for rx queue `queue_id` or `timeout` elapsed.
+    /// Returns whether a frame is queued.
+    fn wait_rx_interrupt(
+        &mut self,
+        queue_id: u32,
+        timeout: Option<Duration>,
+    ) -> Result<bool, IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        let mut pollfd = libc::pollfd {
+            fd: self.queues[queue_id as usize].file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(false);
+                }
+                Err(IxyError::Io(err))
+            }
+            ready => Ok(ready > 0),
+        }
+    }
+
+    /// Taps have no EEPROM.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// Taps have no thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    /// Taps have no descriptor rings.
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            queue_id < u32::from(self.num_rx_queues),
+            "rx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// Taps have no descriptor rings.
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        assert!(
+            queue_id < u32::from(self.num_tx_queues),
+            "tx queue {} is not configured",
+            queue_id
+        );
+
+        (0, 0)
+    }
+
+    /// Taps have no registers.
+    fn dump_registers(&self) -> RegisterDump {
+        RegisterDump::default()
+    }
+
+    /// Drops the frames queued in the kernel, enables all queues and resets the stats. The
+    /// queues stay attached, so the interface and its configuration survive.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.name);
+
+        for queue in self.queues.iter_mut() {
+            queue.discard();
+            queue.pool = Mempool::allocate_anonymous(MEMPOOL_SIZE, PKT_BUF_ENTRY_SIZE)?;
+            queue.num_posted = NUM_RX_QUEUE_ENTRIES;
+            queue.rx_enabled.set(true);
+        }
+
+        self.totals = TapCounters::default();
+        self.counters.set(TapCounters::default());
+
+        Ok(())
+    }
+}
+
+impl TapDevice {
+    /// Returns the path of attribute `name` of the interface in sysfs.
+    fn sysfs_path(&self, name: &str) -> String {
+        format!("/sys/class/net/{}/{}", self.ifname, name)
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+}
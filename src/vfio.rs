@@ -1,31 +1,69 @@
-use std::error::Error;
+//! VFIO containers and diagnostics of the IOMMU groups of devices bound to vfio-pci.
+
+use std::fmt;
 use std::fs;
-use std::fs::{File, OpenOptions};
+use std::fs::OpenOptions;
+use std::io;
 use std::mem;
-use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
 use std::ptr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
-use crate::memory::{get_vfio_container, set_vfio_container};
-use crate::pci::{BUS_MASTER_ENABLE_BIT, COMMAND_REGISTER_OFFSET};
+use crate::pci::{pci_driver, BUS_MASTER_ENABLE_BIT, COMMAND_REGISTER_OFFSET};
+use crate::IxyError;
 
 // constants needed for IOMMU. Grabbed from linux/vfio.h
-pub const VFIO_GET_API_VERSION: u64 = 15204;
-pub const VFIO_CHECK_EXTENSION: u64 = 15205;
-pub const VFIO_SET_IOMMU: u64 = 15206;
-pub const VFIO_GROUP_GET_STATUS: u64 = 15207;
-pub const VFIO_GROUP_SET_CONTAINER: u64 = 15208;
-pub const VFIO_GROUP_GET_DEVICE_FD: u64 = 15210;
-pub const VFIO_DEVICE_GET_REGION_INFO: u64 = 15212;
-
-pub const VFIO_API_VERSION: i32 = 0;
-pub const VFIO_TYPE1_IOMMU: u64 = 1;
-pub const VFIO_GROUP_FLAGS_VIABLE: u32 = 1;
-pub const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
-pub const VFIO_PCI_BAR0_REGION_INDEX: u32 = 0;
+pub(crate) const VFIO_GET_API_VERSION: u64 = 15204;
+pub(crate) const VFIO_CHECK_EXTENSION: u64 = 15205;
+pub(crate) const VFIO_SET_IOMMU: u64 = 15206;
+pub(crate) const VFIO_GROUP_GET_STATUS: u64 = 15207;
+pub(crate) const VFIO_GROUP_SET_CONTAINER: u64 = 15208;
+pub(crate) const VFIO_GROUP_GET_DEVICE_FD: u64 = 15210;
+pub(crate) const VFIO_DEVICE_GET_INFO: u64 = 15211;
+pub(crate) const VFIO_DEVICE_GET_REGION_INFO: u64 = 15212;
+pub(crate) const VFIO_DEVICE_GET_IRQ_INFO: u64 = 15213;
+pub(crate) const VFIO_DEVICE_SET_IRQS: u64 = 15214;
+pub(crate) const VFIO_DEVICE_RESET: u64 = 15215;
+
+pub(crate) const VFIO_API_VERSION: i32 = 0;
+pub(crate) const VFIO_TYPE1_IOMMU: u64 = 1;
+pub(crate) const VFIO_NOIOMMU_IOMMU: u64 = 8;
+pub(crate) const VFIO_GROUP_FLAGS_VIABLE: u32 = 1;
+pub(crate) const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
+pub(crate) const VFIO_PCI_BAR0_REGION_INDEX: u32 = 0;
+pub(crate) const VFIO_PCI_MSIX_IRQ_INDEX: u32 = 2;
+
+const VFIO_DEVICE_FLAGS_RESET: u32 = 1;
+
+const VFIO_IRQ_SET_DATA_NONE: u32 = 1;
+const VFIO_IRQ_SET_DATA_EVENTFD: u32 = 1 << 2;
+const VFIO_IRQ_SET_ACTION_TRIGGER: u32 = 1 << 5;
 
 const VFIO_DMA_MAP_FLAG_READ: u32 = 1;
 const VFIO_DMA_MAP_FLAG_WRITE: u32 = 2;
+const VFIO_IOMMU_GET_INFO: u64 = 15216;
 const VFIO_IOMMU_MAP_DMA: u64 = 15217;
+const VFIO_IOMMU_UNMAP_DMA: u64 = 15218;
+const VFIO_IOMMU_INFO_PGSIZES: u32 = 1;
+const VFIO_IOMMU_INFO_CAPS: u32 = 1 << 1;
+const VFIO_IOMMU_TYPE1_INFO_CAP_IOVA_RANGE: u16 = 1;
+
+// iova range used if the kernel doesn't report the supported ranges, it stays above the x86 msi
+// window below 4 GiB and within the 39 bit address width every VT-d IOMMU supports
+const IOVA_DEFAULT_START: u64 = 1 << 32;
+const IOVA_DEFAULT_END: u64 = 1 << 39;
+// iovas are aligned to the mapping size up to this, so the IOMMU can use its large pages
+const IOVA_MAX_ALIGNMENT: u64 = 1 << 30;
+
+// drivers that don't prevent using a group via VFIO, see vfio_dev_driver_allowed in the kernel
+pub(crate) const VFIO_PCI_DRIVER: &str = "vfio-pci";
+const VIABLE_DRIVERS: [&str; 3] = [VFIO_PCI_DRIVER, "pci-stub", "pcieport"];
+
+// we want one VFIO Container for all NICs by default, so every NIC can read from every
+// other NICs memory, especially the mempool
+static SHARED_CONTAINER: Mutex<Option<Arc<VfioContainer>>> = Mutex::new(None);
 
 /// struct vfio_iommu_type1_dma_map, grabbed from linux/vfio.h
 #[allow(non_camel_case_types)]
@@ -38,6 +76,87 @@ struct vfio_iommu_type1_dma_map {
     size: usize,
 }
 
+/// struct vfio_iommu_type1_dma_unmap, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_iommu_type1_dma_unmap {
+    argsz: u32,
+    flags: u32,
+    iova: u64,
+    size: u64,
+}
+
+/// struct vfio_iommu_type1_info, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_iommu_type1_info {
+    argsz: u32,
+    flags: u32,
+    iova_pgsizes: u64,
+    cap_offset: u32,
+    pad: u32,
+}
+
+/// struct vfio_info_cap_header, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_info_cap_header {
+    id: u16,
+    version: u16,
+    next: u32,
+}
+
+/// struct vfio_iommu_type1_info_cap_iova_range without its variable-length ranges, grabbed
+/// from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_iommu_type1_info_cap_iova_range {
+    header: vfio_info_cap_header,
+    nr_iovas: u32,
+    reserved: u32,
+}
+
+/// struct vfio_iova_range, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_iova_range {
+    start: u64,
+    end: u64,
+}
+
+/// struct vfio_device_info, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_device_info {
+    argsz: u32,
+    flags: u32,
+    num_regions: u32,
+    num_irqs: u32,
+    cap_offset: u32,
+    pad: u32,
+}
+
+/// struct vfio_irq_info, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_irq_info {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    count: u32,
+}
+
+/// struct vfio_irq_set without its variable-length data, grabbed from linux/vfio.h
+#[allow(non_camel_case_types)]
+#[repr(C)]
+struct vfio_irq_set {
+    argsz: u32,
+    flags: u32,
+    index: u32,
+    start: u32,
+    count: u32,
+}
+
 /// struct vfio_group_status, grabbed from linux/vfio.h
 #[allow(non_camel_case_types)]
 #[repr(C)]
@@ -58,116 +177,583 @@ struct vfio_region_info {
     offset: u64,
 }
 
-/// Initializes the IOMMU for a given PCI device. The device must be bound to the VFIO driver.
-pub fn vfio_init(pci_addr: &str) -> Result<RawFd, Box<dyn Error>> {
-    let dfd: RawFd;
-    let group_file: File;
-    let gfd: RawFd;
+/// The IOMMU model of an IOMMU group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IommuType {
+    /// The group is isolated by an IOMMU, its devices access memory by IOVAs.
+    Type1,
+    /// `vfio-pci` in `enable_unsafe_noiommu_mode`, the devices access memory by its physical
+    /// address without any isolation.
+    NoIommu,
+}
 
-    // we also have to build this vfio struct...
-    let group_status: vfio_group_status = vfio_group_status {
-        argsz: mem::size_of::<vfio_group_status>() as u32,
-        flags: 0,
-    };
+impl fmt::Display for IommuType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IommuType::Type1 => write!(f, "Type1 IOMMU"),
+            IommuType::NoIommu => write!(f, "no-IOMMU"),
+        }
+    }
+}
 
-    // need to set up the container exactly once
-    let mut first_time_setup = false;
-    let mut cfd = get_vfio_container();
+/// A PCI device of an IOMMU group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupDevice {
+    /// The pci address of the device.
+    pub pci_addr: String,
+    /// The kernel driver the device is bound to, if any.
+    pub driver: Option<String>,
+}
 
-    if cfd == -1 {
-        first_time_setup = true;
+impl GroupDevice {
+    /// Returns whether the device is bound to vfio-pci.
+    pub fn is_vfio_bound(&self) -> bool {
+        self.driver.as_deref() == Some(VFIO_PCI_DRIVER)
+    }
+
+    /// Returns whether the device's driver allows using its group via VFIO, i.e. it is bound
+    /// to vfio-pci, pci-stub, the PCIe port driver of a bridge or no driver at all.
+    pub fn is_viable(&self) -> bool {
+        match self.driver.as_deref() {
+            None => true,
+            Some(driver) => VIABLE_DRIVERS.contains(&driver),
+        }
+    }
+}
+
+/// The state of the IOMMU group of a PCI device as far as VFIO is concerned, for diagnosing
+/// why a device can't be initialized with VFIO.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::vfio::GroupInfo;
+///
+/// let info = GroupInfo::query("0000:01:00.0").unwrap();
+/// if !info.viable {
+///     // e.g. "0000:01:00.1 is bound to ixgbe, bind it to vfio-pci or unbind it"
+///     eprintln!("{}", info);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    /// The number of the group, i.e. its device is `/dev/vfio/<group>`.
+    pub group: u32,
+    /// The IOMMU model of the group.
+    pub iommu_type: IommuType,
+    /// Whether the group can be used, i.e. the drivers of all its devices allow it.
+    pub viable: bool,
+    /// Whether the group's VFIO device exists, i.e. at least one of its devices is bound to
+    /// vfio-pci.
+    pub bound: bool,
+    /// All devices of the group, including the queried one.
+    pub devices: Vec<GroupDevice>,
+}
+
+impl GroupInfo {
+    /// Returns the state of the IOMMU group of the device at `pci_addr`.
+    ///
+    /// Fails if the device doesn't belong to a group, i.e. there is no IOMMU and no-IOMMU mode
+    /// is not enabled.
+    pub fn query(pci_addr: &str) -> Result<GroupInfo, IxyError> {
+        let group = iommu_group(pci_addr)?;
+
+        let iommu_type = if Path::new(&format!("/dev/vfio/noiommu-{}", group)).exists() {
+            IommuType::NoIommu
+        } else {
+            IommuType::Type1
+        };
+
+        let mut devices = Vec::new();
+        for entry in fs::read_dir(format!("/sys/kernel/iommu_groups/{}/devices", group))? {
+            let pci_addr = entry?.file_name().to_string_lossy().into_owned();
+            let driver = pci_driver(&pci_addr);
+
+            devices.push(GroupDevice { pci_addr, driver });
+        }
+        devices.sort_by(|a, b| a.pci_addr.cmp(&b.pci_addr));
+
+        let bound = match iommu_type {
+            IommuType::NoIommu => true,
+            IommuType::Type1 => Path::new(&format!("/dev/vfio/{}", group)).exists(),
+        };
+
+        Ok(GroupInfo {
+            group,
+            iommu_type,
+            viable: devices.iter().all(GroupDevice::is_viable),
+            bound,
+            devices,
+        })
+    }
+}
+
+impl fmt::Display for GroupInfo {
+    /// Describes the group and what prevents using it, one line each.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "iommu group {} ({}) is {}",
+            self.group,
+            self.iommu_type,
+            if self.viable { "viable" } else { "not viable" }
+        )?;
+
+        if !self.bound {
+            write!(f, "\nno device of the group is bound to vfio-pci")?;
+        }
+
+        for device in &self.devices {
+            match device.driver {
+                Some(ref driver) if !device.is_viable() => write!(
+                    f,
+                    "\n{} is bound to {}, bind it to vfio-pci or unbind it",
+                    device.pci_addr, driver
+                )?,
+                Some(ref driver) => write!(f, "\n{} is bound to {}", device.pci_addr, driver)?,
+                None => write!(f, "\n{} is not bound to a driver", device.pci_addr)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the IOMMU group of the device at `pci_addr`.
+fn iommu_group(pci_addr: &str) -> Result<u32, IxyError> {
+    let link = fs::read_link(format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr))
+        .map_err(IxyError::VfioSetup)?;
+
+    link.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.parse::<u32>().ok())
+        .ok_or_else(|| {
+            IxyError::VfioSetup(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid iommu group {}", link.display()),
+            ))
+        })
+}
+
+/// A VFIO container, i.e. an IOMMU address space shared by the devices of the groups added to
+/// it.
+///
+/// By default all devices share one container, so every device can access the memory of every
+/// other device, especially their mempools. Devices initialized with a container of their own
+/// via [`ixy_init_with_container`](crate::ixy_init_with_container) are isolated from the others
+/// and can only access memory allocated in their container. All devices of an IOMMU group have
+/// to use the same container.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use ixy::memory::Mempool;
+/// use ixy::*;
+///
+/// let container = VfioContainer::new().unwrap();
+/// let dev = ixy_init_with_container("0000:01:00.0", 1, 1, &container).unwrap();
+///
+/// // only devices in this container can access the pool
+/// let pool = Mempool::allocate_in(&*container, 2048, 0).unwrap();
+/// ```
+pub struct VfioContainer {
+    fd: OwnedFd,
+    // allows handing out dma memory that keeps the container alive
+    this: Weak<VfioContainer>,
+    // the IOMMU type, set when the first group is added
+    iommu_type: Mutex<Option<u64>>,
+    // initialized with the IOMMU's supported ranges on the first mapping
+    iova_allocator: Mutex<Option<IovaAllocator>>,
+}
+
+impl VfioContainer {
+    /// Opens a new VFIO container without any groups.
+    pub fn new() -> Result<Arc<VfioContainer>, IxyError> {
         // open vfio file to create new vfio container
         let container_file = OpenOptions::new()
             .read(true)
             .write(true)
             .open("/dev/vfio/vfio")
-            .unwrap();
-        cfd = container_file.into_raw_fd();
-        set_vfio_container(cfd);
+            .map_err(IxyError::VfioSetup)?;
 
         // check if the container's API version is the same as the VFIO API's
-        if unsafe { libc::ioctl(cfd, VFIO_GET_API_VERSION) } != VFIO_API_VERSION {
-            return Err("unknown VFIO API Version".into());
+        if unsafe { libc::ioctl(container_file.as_raw_fd(), VFIO_GET_API_VERSION) }
+            != VFIO_API_VERSION
+        {
+            return Err(IxyError::VfioSetup(io::Error::other(
+                "unknown VFIO API Version",
+            )));
         }
 
-        // check if type1 is supported
-        if unsafe { libc::ioctl(cfd, VFIO_CHECK_EXTENSION, VFIO_TYPE1_IOMMU) } != 1 {
-            return Err("container doesn't support Type1 IOMMU".into());
+        Ok(Arc::new_cyclic(|this| VfioContainer {
+            fd: container_file.into(),
+            this: this.clone(),
+            iommu_type: Mutex::new(None),
+            iova_allocator: Mutex::new(None),
+        }))
+    }
+
+    /// Returns the container shared by all devices that are not initialized with a container
+    /// of their own, opening it if necessary.
+    pub fn shared() -> Result<Arc<VfioContainer>, IxyError> {
+        let mut shared = SHARED_CONTAINER.lock().unwrap();
+
+        if let Some(ref container) = *shared {
+            return Ok(Arc::clone(container));
         }
+
+        let container = VfioContainer::new()?;
+        *shared = Some(Arc::clone(&container));
+
+        Ok(container)
     }
 
-    // find vfio group for device
-    let link = fs::read_link(format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).unwrap();
-    let group = link
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .parse::<i32>()
-        .unwrap();
-
-    // open the devices' group
-    group_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(format!("/dev/vfio/{}", group))
-        .unwrap();
-    gfd = group_file.as_raw_fd();
-
-    // Test the group is viable and available
-    if unsafe { libc::ioctl(gfd, VFIO_GROUP_GET_STATUS, &group_status) } == -1 {
-        return Err(
-            format!("failed to VFIO_GROUP_GET_STATUS. Errno: {}", unsafe {
-                *libc::__errno_location()
-            })
-            .into(),
-        );
+    /// Returns the shared container if a device uses it.
+    pub(crate) fn shared_if_open() -> Option<Arc<VfioContainer>> {
+        SHARED_CONTAINER.lock().unwrap().clone()
     }
-    if (group_status.flags & VFIO_GROUP_FLAGS_VIABLE) != 1 {
-        return Err(
-            "group is not viable (ie, not all devices in this group are bound to vfio)".into(),
-        );
+
+    /// Returns whether the devices of this container access memory through an IOMMU, i.e. by
+    /// IOVAs of this container.
+    ///
+    /// This is `false` until the first device is added and for containers without an IOMMU,
+    /// whose devices access memory by its physical address.
+    pub fn uses_iommu(&self) -> bool {
+        *self.iommu_type.lock().unwrap() == Some(VFIO_TYPE1_IOMMU)
     }
 
-    // Add the group to the container
-    if unsafe { libc::ioctl(gfd, VFIO_GROUP_SET_CONTAINER, &cfd) } == -1 {
-        return Err(
-            format!("failed to VFIO_GROUP_SET_CONTAINER. Errno: {}", unsafe {
-                *libc::__errno_location()
-            })
-            .into(),
-        );
+    /// Returns whether this container works without an IOMMU, i.e. its devices access memory
+    /// by its physical address.
+    pub fn is_noiommu(&self) -> bool {
+        *self.iommu_type.lock().unwrap() == Some(VFIO_NOIOMMU_IOMMU)
     }
 
-    if first_time_setup {
-        // Enable the IOMMU model we want
-        if unsafe { libc::ioctl(cfd, VFIO_SET_IOMMU, VFIO_TYPE1_IOMMU) } == -1 {
-            return Err(format!(
-                "failed to VFIO_SET_IOMMU to VFIO_TYPE1_IOMMU. Errno: {}",
-                unsafe { *libc::__errno_location() }
-            )
-            .into());
+    /// Adds the group of the PCI device to this container and returns the device's file
+    /// descriptor. The device must be bound to the VFIO driver.
+    ///
+    /// Groups of `vfio-pci` in `enable_unsafe_noiommu_mode` are supported as well, their
+    /// devices access memory by its physical address since there is no IOMMU.
+    pub(crate) fn add_device(&self, pci_addr: &str) -> Result<RawFd, IxyError> {
+        // we also have to build this vfio struct...
+        let group_status: vfio_group_status = vfio_group_status {
+            argsz: mem::size_of::<vfio_group_status>() as u32,
+            flags: 0,
+        };
+
+        // find vfio group for device
+        let group = iommu_group(pci_addr)?;
+
+        // groups without an IOMMU get a differently named group device
+        let noiommu_path = format!("/dev/vfio/noiommu-{}", group);
+        let noiommu = Path::new(&noiommu_path).exists();
+        let (iommu_type, iommu_name) = if noiommu {
+            warn!(
+                "{} uses VFIO without an IOMMU, its DMA is not isolated",
+                pci_addr
+            );
+            (VFIO_NOIOMMU_IOMMU, "no-IOMMU")
+        } else {
+            (VFIO_TYPE1_IOMMU, "Type1 IOMMU")
+        };
+
+        let cfd = self.fd.as_raw_fd();
+
+        // the IOMMU type is set up with the first group, held until then so that concurrent
+        // inits don't race for it
+        let mut container_type = self.iommu_type.lock().unwrap();
+
+        match *container_type {
+            None => {
+                // check if the iommu type is supported
+                if unsafe { libc::ioctl(cfd, VFIO_CHECK_EXTENSION, iommu_type) } != 1 {
+                    return Err(IxyError::VfioSetup(io::Error::other(format!(
+                        "container doesn't support {}",
+                        iommu_name
+                    ))));
+                }
+            }
+            Some(t) if t != iommu_type => {
+                return Err(IxyError::VfioSetup(io::Error::other(
+                    "devices with and without an IOMMU can't share a VFIO container",
+                )));
+            }
+            Some(_) => {}
+        }
+
+        // open the devices' group
+        let group_path = if noiommu {
+            noiommu_path
+        } else {
+            format!("/dev/vfio/{}", group)
+        };
+        let group_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(group_path)
+            .map_err(|e| match e.kind() {
+                // the group device only exists once a device of the group is bound to vfio-pci
+                io::ErrorKind::NotFound => IxyError::VfioNotBound(pci_addr.to_string()),
+                _ => IxyError::VfioSetup(e),
+            })?;
+        let gfd = group_file.as_raw_fd();
+
+        // Test the group is viable and available
+        if unsafe { libc::ioctl(gfd, VFIO_GROUP_GET_STATUS, &group_status) } == -1 {
+            return Err(vfio_error("VFIO_GROUP_GET_STATUS"));
+        }
+        if (group_status.flags & VFIO_GROUP_FLAGS_VIABLE) != 1 {
+            let detail = match GroupInfo::query(pci_addr) {
+                Ok(info) => info.to_string(),
+                Err(_) => "not all devices in this group are bound to vfio".to_string(),
+            };
+            return Err(IxyError::VfioSetup(io::Error::other(format!(
+                "group is not viable: {}",
+                detail
+            ))));
+        }
+
+        // Add the group to the container
+        if unsafe { libc::ioctl(gfd, VFIO_GROUP_SET_CONTAINER, &cfd) } == -1 {
+            return Err(vfio_error("VFIO_GROUP_SET_CONTAINER"));
+        }
+
+        if container_type.is_none() {
+            // Enable the IOMMU model we want
+            if unsafe { libc::ioctl(cfd, VFIO_SET_IOMMU, iommu_type) } == -1 {
+                return Err(vfio_error(&format!("VFIO_SET_IOMMU to {}", iommu_name)));
+            }
+            *container_type = Some(iommu_type);
+        }
+
+        // Get a file descriptor for the device
+        let dfd = unsafe { libc::ioctl(gfd, VFIO_GROUP_GET_DEVICE_FD, pci_addr) };
+        if dfd == -1 {
+            return Err(vfio_error("VFIO_GROUP_GET_DEVICE_FD"));
         }
+
+        vfio_enable_dma(dfd)?;
+
+        Ok(dfd)
+    }
+
+    /// Returns the container as an `Arc`, which dma memory of the container holds on to.
+    pub(crate) fn arc(&self) -> Arc<VfioContainer> {
+        // a container is only ever created inside an Arc, which outlives any &self
+        self.this.upgrade().unwrap()
     }
 
-    // Get a file descriptor for the device
-    dfd = unsafe { libc::ioctl(gfd, VFIO_GROUP_GET_DEVICE_FD, pci_addr) };
-    if dfd == -1 {
-        return Err(
-            format!("failed to VFIO_GROUP_GET_DEVICE_FD. Errno: {}", unsafe {
-                *libc::__errno_location()
+    /// Maps `size` bytes at `ptr` for DMA at a free IOVA of this container and returns it.
+    ///
+    /// The IOVA lies within the ranges the IOMMU supports and is aligned to `size`, up to
+    /// 1 GiB.
+    pub(crate) fn map_dma(&self, ptr: usize, size: usize) -> Result<usize, IxyError> {
+        let iova = self.with_iova_allocator(|allocator| {
+            allocator.allocate(size as u64).ok_or_else(|| {
+                IxyError::DmaMapFailed(io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    format!("no free iova range for {} bytes", size),
+                ))
             })
-            .into(),
-        );
+        })?;
+
+        if let Err(e) = self.map_dma_ioctl(ptr, size, iova as usize) {
+            self.with_iova_allocator(|allocator| {
+                allocator.release(iova, size as u64);
+                Ok(())
+            })?;
+            return Err(IxyError::DmaMapFailed(e));
+        }
+
+        Ok(iova as usize)
+    }
+
+    /// Maps `size` bytes at `ptr` for DMA at the caller-chosen `iova`.
+    ///
+    /// Fails if the range overlaps an existing mapping of this container or lies outside the
+    /// ranges the IOMMU supports.
+    pub(crate) fn map_dma_at(
+        &self,
+        ptr: usize,
+        size: usize,
+        iova: usize,
+    ) -> Result<usize, IxyError> {
+        let reserved =
+            self.with_iova_allocator(|allocator| Ok(allocator.reserve(iova as u64, size as u64)))?;
+
+        if !reserved {
+            return Err(IxyError::DmaMapFailed(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "iova range {:#x}..{:#x} conflicts with an existing mapping or is not \
+                     supported by the IOMMU",
+                    iova,
+                    iova + size
+                ),
+            )));
+        }
+
+        if let Err(e) = self.map_dma_ioctl(ptr, size, iova) {
+            self.with_iova_allocator(|allocator| {
+                allocator.release(iova as u64, size as u64);
+                Ok(())
+            })?;
+            return Err(IxyError::DmaMapFailed(e));
+        }
+
+        Ok(iova)
+    }
+
+    /// Unmaps `size` bytes at `iova` that were mapped for DMA with [`VfioContainer::map_dma`]
+    /// or [`VfioContainer::map_dma_at`], the IOVA range can be reused afterwards.
+    pub(crate) fn unmap_dma(&self, iova: usize, size: usize) -> Result<(), IxyError> {
+        let iommu_dma_unmap = vfio_iommu_type1_dma_unmap {
+            argsz: mem::size_of::<vfio_iommu_type1_dma_unmap>() as u32,
+            flags: 0,
+            iova: iova as u64,
+            size: size as u64,
+        };
+
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), VFIO_IOMMU_UNMAP_DMA, &iommu_dma_unmap) } == -1
+        {
+            return Err(IxyError::DmaMapFailed(io::Error::last_os_error()));
+        }
+
+        self.with_iova_allocator(|allocator| {
+            allocator.release(iova as u64, size as u64);
+            Ok(())
+        })
+    }
+
+    /// Maps `size` bytes at `ptr` into this container at `iova`.
+    fn map_dma_ioctl(&self, ptr: usize, size: usize, iova: usize) -> io::Result<()> {
+        let iommu_dma_map: vfio_iommu_type1_dma_map = vfio_iommu_type1_dma_map {
+            argsz: mem::size_of::<vfio_iommu_type1_dma_map>() as u32,
+            vaddr: ptr as *mut u8,
+            size,
+            iova: iova as *mut u8,
+            flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+        };
+
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), VFIO_IOMMU_MAP_DMA, &iommu_dma_map) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f` with the IOVA allocator of this container, querying the IOMMU's supported
+    /// ranges first if this is the first mapping.
+    fn with_iova_allocator<R>(
+        &self,
+        f: impl FnOnce(&mut IovaAllocator) -> Result<R, IxyError>,
+    ) -> Result<R, IxyError> {
+        let mut allocator = self.iova_allocator.lock().unwrap();
+
+        if allocator.is_none() {
+            let (ranges, min_alignment) = self.iova_ranges()?;
+            debug!("iova ranges of the IOMMU: {:x?}", ranges);
+            *allocator = Some(IovaAllocator::new(&ranges, min_alignment));
+        }
+
+        f(allocator.as_mut().unwrap())
     }
 
-    vfio_enable_dma(dfd)?;
+    /// Returns the IOVA ranges the IOMMU of this container supports as `start..end` pairs and
+    /// its smallest page size.
+    ///
+    /// Falls back to a conservative range for kernels that don't report them.
+    fn iova_ranges(&self) -> Result<(Vec<(u64, u64)>, u64), IxyError> {
+        let cfd = self.fd.as_raw_fd();
 
-    Ok(dfd)
+        let mut info = vfio_iommu_type1_info {
+            argsz: mem::size_of::<vfio_iommu_type1_info>() as u32,
+            flags: 0,
+            iova_pgsizes: 0,
+            cap_offset: 0,
+            pad: 0,
+        };
+        if unsafe { libc::ioctl(cfd, VFIO_IOMMU_GET_INFO, &mut info) } == -1 {
+            return Err(vfio_error("VFIO_IOMMU_GET_INFO"));
+        }
+
+        let min_alignment = if info.flags & VFIO_IOMMU_INFO_PGSIZES != 0 && info.iova_pgsizes != 0 {
+            1 << info.iova_pgsizes.trailing_zeros()
+        } else {
+            4096
+        };
+
+        let default = vec![(IOVA_DEFAULT_START, IOVA_DEFAULT_END)];
+
+        // the kernel raises argsz to the size including the capability chain if there is one
+        if info.flags & VFIO_IOMMU_INFO_CAPS == 0
+            || info.argsz as usize <= mem::size_of::<vfio_iommu_type1_info>()
+        {
+            return Ok((default, min_alignment));
+        }
+
+        // u64 words keep the buffer aligned for the structs within it
+        let len = info.argsz as usize;
+        let mut buffer = vec![0u64; len.div_ceil(8)];
+        let buf = buffer.as_mut_ptr() as *mut u8;
+        unsafe {
+            ptr::write(
+                buf as *mut vfio_iommu_type1_info,
+                vfio_iommu_type1_info {
+                    argsz: len as u32,
+                    flags: 0,
+                    iova_pgsizes: 0,
+                    cap_offset: 0,
+                    pad: 0,
+                },
+            );
+        }
+        if unsafe { libc::ioctl(cfd, VFIO_IOMMU_GET_INFO, buf) } == -1 {
+            return Err(vfio_error("VFIO_IOMMU_GET_INFO with capabilities"));
+        }
+
+        let info = unsafe { ptr::read(buf as *const vfio_iommu_type1_info) };
+        let mut offset = info.cap_offset as usize;
+
+        while offset != 0 && offset + mem::size_of::<vfio_info_cap_header>() <= len {
+            let header =
+                unsafe { ptr::read_unaligned(buf.add(offset) as *const vfio_info_cap_header) };
+
+            if header.id == VFIO_IOMMU_TYPE1_INFO_CAP_IOVA_RANGE
+                && offset + mem::size_of::<vfio_iommu_type1_info_cap_iova_range>() <= len
+            {
+                let cap = unsafe {
+                    ptr::read_unaligned(
+                        buf.add(offset) as *const vfio_iommu_type1_info_cap_iova_range
+                    )
+                };
+                let first = offset + mem::size_of::<vfio_iommu_type1_info_cap_iova_range>();
+
+                let ranges = (0..cap.nr_iovas as usize)
+                    .map(|i| first + i * mem::size_of::<vfio_iova_range>())
+                    .take_while(|&o| o + mem::size_of::<vfio_iova_range>() <= len)
+                    .map(|o| unsafe { ptr::read_unaligned(buf.add(o) as *const vfio_iova_range) })
+                    // the kernel's ends are inclusive
+                    .map(|r| (r.start, r.end.saturating_add(1)))
+                    .collect();
+
+                return Ok((ranges, min_alignment));
+            }
+
+            offset = header.next as usize;
+        }
+
+        Ok((default, min_alignment))
+    }
+}
+
+impl AsRawFd for VfioContainer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
 }
 
 /// Enables DMA Bit for VFIO devices
-pub fn vfio_enable_dma(device_file_descriptor: RawFd) -> Result<(), Box<dyn Error>> {
+pub(crate) fn vfio_enable_dma(device_file_descriptor: RawFd) -> Result<(), IxyError> {
     // Get region info for config region
     let conf_reg: vfio_region_info = vfio_region_info {
         argsz: mem::size_of::<vfio_region_info>() as u32,
@@ -185,10 +771,9 @@ pub fn vfio_enable_dma(device_file_descriptor: RawFd) -> Result<(), Box<dyn Erro
         )
     } == -1
     {
-        return Err(format!(
-            "failed to VFIO_DEVICE_GET_REGION_INFO for index VFIO_PCI_CONFIG_REGION_INDEX. Errno: {}",
-            unsafe { *libc::__errno_location() }
-        ).into());
+        return Err(vfio_error(
+            "VFIO_DEVICE_GET_REGION_INFO for index VFIO_PCI_CONFIG_REGION_INDEX",
+        ));
     }
 
     let mut dma: u16 = 0;
@@ -201,13 +786,11 @@ pub fn vfio_enable_dma(device_file_descriptor: RawFd) -> Result<(), Box<dyn Erro
         )
     } == -1
     {
-        return Err(format!("failed to pread DMA bit. Errno: {}", unsafe {
-            *libc::__errno_location()
-        })
-        .into());
+        return Err(vfio_error("pread DMA bit"));
     }
 
-    dma |= 1 << BUS_MASTER_ENABLE_BIT;
+    // the config space is little endian and dma holds its raw bytes
+    dma |= (1u16 << BUS_MASTER_ENABLE_BIT).to_le();
 
     if unsafe {
         libc::pwrite(
@@ -218,16 +801,44 @@ pub fn vfio_enable_dma(device_file_descriptor: RawFd) -> Result<(), Box<dyn Erro
         )
     } == -1
     {
-        return Err(format!("failed to pwrite DMA bit. Errno: {}", unsafe {
-            *libc::__errno_location()
-        })
-        .into());
+        return Err(vfio_error("pwrite DMA bit"));
     }
     Ok(())
 }
 
+/// Resets the device, e.g. by a PCI function-level reset. The kernel restores the device's
+/// config space afterwards, so DMA stays enabled.
+///
+/// Fails if the device supports no reset method.
+pub(crate) fn vfio_reset_device(device_fd: RawFd) -> Result<(), IxyError> {
+    let device_info = vfio_device_info {
+        argsz: mem::size_of::<vfio_device_info>() as u32,
+        flags: 0,
+        num_regions: 0,
+        num_irqs: 0,
+        cap_offset: 0,
+        pad: 0,
+    };
+    if unsafe { libc::ioctl(device_fd, VFIO_DEVICE_GET_INFO, &device_info) } == -1 {
+        return Err(vfio_error("VFIO_DEVICE_GET_INFO"));
+    }
+
+    if device_info.flags & VFIO_DEVICE_FLAGS_RESET == 0 {
+        return Err(IxyError::VfioSetup(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "device supports no reset method",
+        )));
+    }
+
+    if unsafe { libc::ioctl(device_fd, VFIO_DEVICE_RESET) } == -1 {
+        return Err(vfio_error("VFIO_DEVICE_RESET"));
+    }
+
+    Ok(())
+}
+
 /// Mmaps a VFIO resource and returns a pointer to the mapped memory.
-pub fn vfio_map_region(fd: RawFd, index: u32) -> Result<(*mut u8, usize), Box<dyn Error>> {
+pub(crate) fn vfio_map_region(fd: RawFd, index: u32) -> Result<(*mut u8, usize), IxyError> {
     let region_info: vfio_region_info = vfio_region_info {
         argsz: mem::size_of::<vfio_region_info>() as u32,
         flags: 0,
@@ -237,12 +848,7 @@ pub fn vfio_map_region(fd: RawFd, index: u32) -> Result<(*mut u8, usize), Box<dy
         offset: 0,
     };
     if unsafe { libc::ioctl(fd, VFIO_DEVICE_GET_REGION_INFO, &region_info) } == -1 {
-        return Err(
-            format!("failed to VFIO_DEVICE_GET_REGION_INFO. Errno: {}", unsafe {
-                *libc::__errno_location()
-            })
-            .into(),
-        );
+        return Err(vfio_error("VFIO_DEVICE_GET_REGION_INFO"));
     }
 
     let len = region_info.size as usize;
@@ -258,30 +864,223 @@ pub fn vfio_map_region(fd: RawFd, index: u32) -> Result<(*mut u8, usize), Box<dy
         )
     };
     if ptr == libc::MAP_FAILED {
-        return Err(format!("failed to mmap region. Errno: {}", unsafe {
-            *libc::__errno_location()
-        })
-        .into());
+        return Err(vfio_error("mmap region"));
     }
     let addr = ptr as *mut u8;
 
     Ok((addr, len))
 }
 
-pub fn vfio_map_dma(ptr: usize, size: usize) -> Result<usize, Box<dyn Error>> {
-    let iommu_dma_map: vfio_iommu_type1_dma_map = vfio_iommu_type1_dma_map {
-        argsz: mem::size_of::<vfio_iommu_type1_dma_map>() as u32,
-        vaddr: ptr as *mut u8,
-        size,
-        iova: ptr as *mut u8,
-        flags: VFIO_DMA_MAP_FLAG_READ | VFIO_DMA_MAP_FLAG_WRITE,
+/// Hands out non-overlapping IOVA ranges within the ranges the IOMMU supports and reuses them
+/// once they are released.
+struct IovaAllocator {
+    /// Free ranges as `start..end` pairs, sorted and with gaps between them.
+    free: Vec<(u64, u64)>,
+    min_alignment: u64,
+}
+
+impl IovaAllocator {
+    fn new(ranges: &[(u64, u64)], min_alignment: u64) -> IovaAllocator {
+        let mut allocator = IovaAllocator {
+            free: Vec::new(),
+            min_alignment,
+        };
+
+        for &(start, end) in ranges {
+            if start < end {
+                allocator.release(start, end - start);
+            }
+        }
+
+        allocator
+    }
+
+    /// Takes the first free range of `size` bytes aligned to `size`, up to 1 GiB.
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let alignment = size
+            .checked_next_power_of_two()
+            .unwrap_or(IOVA_MAX_ALIGNMENT)
+            .clamp(self.min_alignment, IOVA_MAX_ALIGNMENT);
+
+        let start = self.free.iter().find_map(|&(start, end)| {
+            let aligned = start.checked_next_multiple_of(alignment)?;
+            (aligned.checked_add(size)? <= end).then_some(aligned)
+        })?;
+
+        self.reserve(start, size).then_some(start)
+    }
+
+    /// Takes the range of `size` bytes at `start`, returns `false` if it is not entirely free.
+    fn reserve(&mut self, start: u64, size: u64) -> bool {
+        let end = match start.checked_add(size) {
+            Some(end) if size > 0 => end,
+            _ => return false,
+        };
+
+        let i = match self.free.iter().position(|&(s, e)| s <= start && end <= e) {
+            Some(i) => i,
+            None => return false,
+        };
+        let (free_start, free_end) = self.free[i];
+
+        // keep the free parts before and after the reserved range
+        self.free.splice(
+            i..=i,
+            [(free_start, start), (end, free_end)]
+                .iter()
+                .copied()
+                .filter(|&(s, e)| s < e),
+        );
+
+        true
+    }
+
+    /// Returns the range of `size` bytes at `start` to the free ranges.
+    fn release(&mut self, start: u64, size: u64) {
+        let mut start = start;
+        let mut end = start.saturating_add(size);
+
+        // merge with the free ranges adjacent to the released one
+        let i = self.free.partition_point(|&(_, e)| e < start);
+        let mut j = i;
+        while j < self.free.len() && self.free[j].0 <= end {
+            start = start.min(self.free[j].0);
+            end = end.max(self.free[j].1);
+            j += 1;
+        }
+
+        self.free.splice(i..j, [(start, end)]);
+    }
+}
+
+/// Creates an eventfd for each of the first `num_vectors` MSI-X vectors of the device and
+/// registers it as the vector's trigger, i.e. the eventfd is signaled when the vector fires.
+pub(crate) fn vfio_enable_msix(
+    device_fd: RawFd,
+    num_vectors: u32,
+) -> Result<Vec<OwnedFd>, IxyError> {
+    let irq_info = vfio_irq_info {
+        argsz: mem::size_of::<vfio_irq_info>() as u32,
+        flags: 0,
+        index: VFIO_PCI_MSIX_IRQ_INDEX,
+        count: 0,
+    };
+    if unsafe { libc::ioctl(device_fd, VFIO_DEVICE_GET_IRQ_INFO, &irq_info) } == -1 {
+        return Err(vfio_error("VFIO_DEVICE_GET_IRQ_INFO for MSI-X"));
+    }
+
+    if irq_info.count < num_vectors {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "device has {} MSI-X vectors, {} are needed",
+            irq_info.count, num_vectors
+        )));
+    }
+
+    let mut eventfds = Vec::with_capacity(num_vectors as usize);
+    for _ in 0..num_vectors {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd == -1 {
+            return Err(vfio_error("create eventfd"));
+        }
+        eventfds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+    }
+
+    let fds: Vec<RawFd> = eventfds.iter().map(|fd| fd.as_raw_fd()).collect();
+    vfio_set_irqs(
+        device_fd,
+        VFIO_IRQ_SET_DATA_EVENTFD | VFIO_IRQ_SET_ACTION_TRIGGER,
+        &fds,
+    )?;
+
+    Ok(eventfds)
+}
+
+/// Disables all MSI-X vectors of the device, their eventfds are no longer signaled.
+pub(crate) fn vfio_disable_msix(device_fd: RawFd) -> Result<(), IxyError> {
+    vfio_set_irqs(
+        device_fd,
+        VFIO_IRQ_SET_DATA_NONE | VFIO_IRQ_SET_ACTION_TRIGGER,
+        &[],
+    )
+}
+
+/// Issues `VFIO_DEVICE_SET_IRQS` for the MSI-X vectors with `flags` and the eventfds `fds`.
+fn vfio_set_irqs(device_fd: RawFd, flags: u32, fds: &[RawFd]) -> Result<(), IxyError> {
+    let header_len = mem::size_of::<vfio_irq_set>();
+    let argsz = header_len + mem::size_of_val(fds);
+
+    // the eventfds follow the header, u32 keeps the buffer aligned for it
+    let mut buffer = vec![0u32; argsz.div_ceil(mem::size_of::<u32>())];
+    let header = vfio_irq_set {
+        argsz: argsz as u32,
+        flags,
+        index: VFIO_PCI_MSIX_IRQ_INDEX,
+        start: 0,
+        count: fds.len() as u32,
     };
 
-    let ioctl_result =
-        unsafe { libc::ioctl(get_vfio_container(), VFIO_IOMMU_MAP_DMA, &iommu_dma_map) };
-    if ioctl_result != -1 {
-        Ok(iommu_dma_map.iova as usize)
-    } else {
-        Err("failed to map the DMA memory - ulimit set for this user?".into())
+    unsafe {
+        let ptr = buffer.as_mut_ptr() as *mut u8;
+        ptr::write(ptr as *mut vfio_irq_set, header);
+        ptr::copy_nonoverlapping(
+            fds.as_ptr() as *const u8,
+            ptr.add(header_len),
+            mem::size_of_val(fds),
+        );
+    }
+
+    if unsafe { libc::ioctl(device_fd, VFIO_DEVICE_SET_IRQS, buffer.as_ptr()) } == -1 {
+        return Err(vfio_error("VFIO_DEVICE_SET_IRQS for MSI-X"));
     }
+
+    Ok(())
+}
+
+/// Waits until `eventfd` is signaled or `timeout` passed and returns whether it was signaled.
+///
+/// Waits indefinitely if `timeout` is [`None`], an interrupted wait counts as timed out.
+pub(crate) fn vfio_wait_eventfd(
+    eventfd: RawFd,
+    timeout: Option<Duration>,
+) -> Result<bool, IxyError> {
+    let mut pollfd = libc::pollfd {
+        fd: eventfd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+    match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+        -1 => {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(IxyError::Io(err))
+            }
+        }
+        0 => Ok(false),
+        _ => {
+            // reset the counter, the eventfd is non-blocking so this can't hang
+            let mut counter = 0u64;
+            unsafe {
+                libc::read(
+                    eventfd,
+                    &mut counter as *mut u64 as *mut libc::c_void,
+                    mem::size_of::<u64>(),
+                )
+            };
+
+            Ok(true)
+        }
+    }
+}
+
+/// Returns a `VfioSetup` error for the failed `operation` including the current errno.
+fn vfio_error(operation: &str) -> IxyError {
+    let err = io::Error::last_os_error();
+    IxyError::VfioSetup(io::Error::new(
+        err.kind(),
+        format!("failed to {}: {}", operation, err),
+    ))
 }
@@ -0,0 +1,2929 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{self, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::checksum::{
+    fill_ipv4_checksum, fill_l4_checksum, pseudo_header_checksum, write_l4_checksum, HeaderOffsets,
+};
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES,
+    NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, enable_dma, pci_numa_node, unbind_driver, MappedBar, PciDevice, PcieLink,
+    RemovalLatch,
+};
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-virtio";
+
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+// the transitional device also offers the legacy interface, only the modern one is used
+const VIRTIO_NET_TRANSITIONAL_DEVICE_ID: u16 = 0x1000;
+const VIRTIO_NET_MODERN_DEVICE_ID: u16 = 0x1041;
+
+// vendor specific pci capabilities describe where the structures are, see section 4.1.4
+const PCI_CAPABILITY_ID_VENDOR: u8 = 0x09;
+const VIRTIO_PCI_CAP_CFG_TYPE: u16 = 3;
+const VIRTIO_PCI_CAP_BAR: u16 = 4;
+const VIRTIO_PCI_CAP_OFFSET: u16 = 8;
+const VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER: u16 = 16;
+
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// common configuration structure, see section 4.1.4.3
+const VIRTIO_PCI_COMMON_DFSELECT: usize = 0x00;
+const VIRTIO_PCI_COMMON_DF: usize = 0x04;
+const VIRTIO_PCI_COMMON_GFSELECT: usize = 0x08;
+const VIRTIO_PCI_COMMON_GF: usize = 0x0C;
+const VIRTIO_PCI_COMMON_MSIX: usize = 0x10;
+const VIRTIO_PCI_COMMON_NUMQ: usize = 0x12;
+const VIRTIO_PCI_COMMON_STATUS: usize = 0x14;
+const VIRTIO_PCI_COMMON_CFGGENERATION: usize = 0x15;
+const VIRTIO_PCI_COMMON_Q_SELECT: usize = 0x16;
+const VIRTIO_PCI_COMMON_Q_SIZE: usize = 0x18;
+const VIRTIO_PCI_COMMON_Q_MSIX: usize = 0x1A;
+const VIRTIO_PCI_COMMON_Q_ENABLE: usize = 0x1C;
+const VIRTIO_PCI_COMMON_Q_NOFF: usize = 0x1E;
+const VIRTIO_PCI_COMMON_Q_DESCLO: usize = 0x20;
+const VIRTIO_PCI_COMMON_Q_AVAILLO: usize = 0x28;
+const VIRTIO_PCI_COMMON_Q_USEDLO: usize = 0x30;
+
+const VIRTIO_MSI_NO_VECTOR: u16 = 0xFFFF;
+
+// device status bits, see section 2.1
+const VIRTIO_CONFIG_S_ACKNOWLEDGE: u8 = 1 << 0;
+const VIRTIO_CONFIG_S_DRIVER: u8 = 1 << 1;
+const VIRTIO_CONFIG_S_DRIVER_OK: u8 = 1 << 2;
+const VIRTIO_CONFIG_S_FEATURES_OK: u8 = 1 << 3;
+const VIRTIO_CONFIG_S_FAILED: u8 = 1 << 7;
+const VIRTIO_FAILED_READ_STATUS: u8 = 0xFF;
+
+// feature bits, see sections 5.1.3 and 6
+/// The device fills in the TCP and UDP checksums of sent packets.
+pub const VIRTIO_NET_F_CSUM: u64 = 1 << 0;
+/// The device reports its maximum mtu.
+pub const VIRTIO_NET_F_MTU: u64 = 1 << 3;
+/// The device has a mac address.
+pub const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+/// The device segments sent TCP packets over IPv4.
+pub const VIRTIO_NET_F_HOST_TSO4: u64 = 1 << 11;
+/// The device segments sent TCP packets over IPv6.
+pub const VIRTIO_NET_F_HOST_TSO6: u64 = 1 << 12;
+/// Received frames may span several rx buffers.
+pub const VIRTIO_NET_F_MRG_RXBUF: u64 = 1 << 15;
+/// The device reports its link status.
+pub const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+/// The device has a control virtqueue.
+pub const VIRTIO_NET_F_CTRL_VQ: u64 = 1 << 17;
+/// The control virtqueue sets the receive filters.
+pub const VIRTIO_NET_F_CTRL_RX: u64 = 1 << 18;
+/// The control virtqueue sets the mac address.
+pub const VIRTIO_NET_F_CTRL_MAC_ADDR: u64 = 1 << 23;
+/// The device and the driver suppress notifications with event indexes.
+pub const VIRTIO_RING_F_EVENT_IDX: u64 = 1 << 29;
+/// The device complies with virtio 1.0.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+/// The device accesses memory through the IOMMU.
+pub const VIRTIO_F_ACCESS_PLATFORM: u64 = 1 << 33;
+/// The device supports packed virtqueues.
+pub const VIRTIO_F_RING_PACKED: u64 = 1 << 34;
+/// The device reports its link speed and duplex.
+pub const VIRTIO_NET_F_SPEED_DUPLEX: u64 = 1 << 63;
+
+const DRIVER_FEATURES: u64 = VIRTIO_NET_F_CSUM
+    | VIRTIO_NET_F_MTU
+    | VIRTIO_NET_F_MAC
+    | VIRTIO_NET_F_HOST_TSO4
+    | VIRTIO_NET_F_HOST_TSO6
+    | VIRTIO_NET_F_MRG_RXBUF
+    | VIRTIO_NET_F_STATUS
+    | VIRTIO_NET_F_CTRL_VQ
+    | VIRTIO_NET_F_CTRL_RX
+    | VIRTIO_NET_F_CTRL_MAC_ADDR
+    | VIRTIO_RING_F_EVENT_IDX
+    | VIRTIO_F_VERSION_1
+    | VIRTIO_F_ACCESS_PLATFORM
+    | VIRTIO_F_RING_PACKED
+    | VIRTIO_NET_F_SPEED_DUPLEX;
+
+// network device configuration, see section 5.1.4
+const VIRTIO_NET_CFG_MAC: usize = 0;
+const VIRTIO_NET_CFG_STATUS: usize = 6;
+const VIRTIO_NET_CFG_MTU: usize = 10;
+const VIRTIO_NET_CFG_SPEED: usize = 12;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1 << 0;
+const VIRTIO_NET_SPEED_UNKNOWN: u32 = 0xFFFF_FFFF;
+// devices without a speed are reported as gigabit NICs
+const VIRTIO_NET_DEFAULT_SPEED: u32 = 1000;
+
+// every packet is preceded by a virtio_net_hdr, it includes num_buffers with VIRTIO_F_VERSION_1
+const VIRTIO_NET_HDR_SIZE: usize = 12;
+const VIRTIO_NET_HDR_NUM_BUFFERS: usize = 10;
+const VIRTIO_NET_HDR_FLAGS: usize = 0;
+const VIRTIO_NET_HDR_GSO_TYPE: usize = 1;
+const VIRTIO_NET_HDR_HDR_LEN: usize = 2;
+const VIRTIO_NET_HDR_GSO_SIZE: usize = 4;
+const VIRTIO_NET_HDR_CSUM_START: usize = 6;
+const VIRTIO_NET_HDR_CSUM_OFFSET: usize = 8;
+
+const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 1;
+const VIRTIO_NET_HDR_GSO_NONE: u8 = 0;
+const VIRTIO_NET_HDR_GSO_TCPV4: u8 = 1;
+const VIRTIO_NET_HDR_GSO_TCPV6: u8 = 4;
+
+// ethernet header and vlan tag on top of the mtu
+const FRAME_OVERHEAD: usize = 18;
+// devices without VIRTIO_NET_F_MTU take standard ethernet frames
+const DEFAULT_MTU: usize = 1500;
+
+// VLAN offloads are emulated in software, VIRTIO_NET_F_CTRL_VLAN makes QEMU drop all tagged
+// frames that are not in its filter table
+const VLAN_ID_MASK: u16 = 0xfff;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1 << 0;
+const VIRTQ_DESC_F_WRITE: u16 = 1 << 1;
+const VIRTQ_DESC_F_AVAIL: u16 = 1 << 7;
+const VIRTQ_DESC_F_USED: u16 = 1 << 15;
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1 << 0;
+const VIRTQ_USED_F_NO_NOTIFY: u16 = 1 << 0;
+const VIRTQ_RING_EVENT_FLAGS_DISABLE: u16 = 1 << 0;
+const VIRTQ_RING_EVENT_FLAGS_DESC: u16 = 1 << 1;
+const VIRTQ_RING_EVENT_WRAP_COUNTER: u16 = 1 << 15;
+
+// off_wrap and flags of the event suppression structures of a packed virtqueue
+const VIRTQ_EVENT_SUPPRESS_SIZE: usize = 4;
+
+// the used ring has to be aligned to 4 bytes, the descriptors to 16 bytes
+const VIRTQ_USED_ALIGN: usize = 4;
+const VIRTQ_DESC_ALIGN: usize = 16;
+
+// a single queue pair without VIRTIO_NET_F_MQ
+const MAX_VIRTIO_QUEUES: u16 = 1;
+
+// control virtqueue commands, see section 5.1.6.5
+const VIRTIO_NET_CTRL_RX: u8 = 0;
+const VIRTIO_NET_CTRL_RX_PROMISC: u8 = 0;
+const VIRTIO_NET_CTRL_RX_ALLMULTI: u8 = 1;
+const VIRTIO_NET_CTRL_MAC: u8 = 1;
+const VIRTIO_NET_CTRL_MAC_TABLE_SET: u8 = 0;
+const VIRTIO_NET_CTRL_MAC_ADDR_SET: u8 = 1;
+const VIRTIO_NET_OK: u8 = 0;
+
+// the control virtqueue follows the queue pairs
+const CTRL_QUEUE_INDEX: u16 = 2 * MAX_VIRTIO_QUEUES;
+const CTRL_QUEUE_ENTRIES: usize = 16;
+// header, ack and data of the single command in flight
+const CTRL_HDR_OFFSET: usize = 0;
+const CTRL_ACK_OFFSET: usize = 8;
+const CTRL_DATA_OFFSET: usize = 16;
+const CTRL_BUF_SIZE: usize = 1024;
+const CTRL_TIMEOUT: Duration = Duration::from_secs(1);
+
+// the mac table takes both lists in a single command
+const NUM_UNICAST_FILTERS: usize = 32;
+const NUM_MULTICAST_FILTERS: usize = 64;
+
+const TX_MAX_SEGMENTS: usize = 16;
+
+// the device acknowledges a reset by reading back a status of 0
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Driver for virtio network devices via the modern virtio-pci interface of the virtio 1.1
+/// specification, e.g. the virtio-net device of QEMU (`-device virtio-net-pci`).
+///
+/// The capabilities in the config space point to the common, notification and device
+/// configuration structures in the memory BARs. Legacy devices that only offer the I/O port
+/// interface are not supported. Interrupts are disabled, all queues are polled.
+///
+/// Packed virtqueues are used if the device offers them (e.g. QEMU with `packed=on`), split
+/// virtqueues otherwise. With mergeable rx buffers frames larger than a buffer are received as
+/// chains of segments, frames above the device's mtu are never sent. The mac address and the
+/// receive filters are set via the control virtqueue if the device has one.
+pub struct VirtioDevice {
+    pci_addr: String,
+    // every BAR one of the configuration structures lives in
+    bars: Vec<MappedBar>,
+    common: Region,
+    notify: Region,
+    notify_off_multiplier: u32,
+    device_cfg: Region,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<VirtioRxQueue>,
+    tx_queues: Vec<VirtioTxQueue>,
+    // features both the device and the driver support
+    features: u64,
+    mtu: usize,
+    // set up once VIRTIO_NET_F_CTRL_VQ was negotiated
+    ctrl_queue: RefCell<Option<VirtioCtrlQueue>>,
+    // receive filters set via the control queue, they are restored on every start of the device
+    promisc: Cell<Option<bool>>,
+    allmulti: Cell<Option<bool>>,
+    mac_addr: Cell<Option<[u8; 6]>>,
+    unicast_filters: RefCell<Vec<Option<[u8; 6]>>>,
+    multicast_filters: RefCell<Vec<[u8; 6]>>,
+    // VLAN offloads emulated by the driver, they are kept across resets
+    vlan_strip: bool,
+    vlan_filters: RefCell<Vec<u16>>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    // the driver counts the packets, the device has no counters
+    counters: Cell<VirtioCounters>,
+    // set once the status reads as all ones, the device is not accessed anymore
+    removal: RemovalLatch<usize>,
+}
+
+/// Location of a configuration structure, i.e. the index in `VirtioDevice::bars` and the
+/// offset in that BAR.
+#[derive(Clone, Copy)]
+struct Region {
+    bar: usize,
+    offset: usize,
+}
+
+/// Values of the packet counters summed over all queues at their last read.
+#[derive(Clone, Copy, Default)]
+struct VirtioCounters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+/// Descriptor of a split virtqueue, see section 2.6.5.
+#[repr(C)]
+#[allow(dead_code)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// Element of the used ring of a split virtqueue, see section 2.6.8.
+#[repr(C)]
+#[allow(dead_code)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// Descriptor of a packed virtqueue, see section 2.7.13.
+#[repr(C)]
+#[allow(dead_code)]
+struct VirtqPackedDesc {
+    addr: u64,
+    len: u32,
+    id: u16,
+    flags: u16,
+}
+
+/// Descriptor of a packed virtqueue as set up by the driver. It is only written to the ring
+/// once its chain is made available.
+#[derive(Clone, Copy, Default)]
+struct PackedShadow {
+    addr: usize,
+    len: usize,
+    flags: u16,
+    next: u16,
+}
+
+/// Split or packed virtqueue, i.e. its rings in one dma allocation, see sections 2.6 and 2.7.
+///
+/// Descriptors are identified by ids that stay the same in both layouts. In a split virtqueue
+/// the id is the index in the descriptor table. In a packed virtqueue descriptors are written
+/// to the ring in the order they are made available, the driver keeps them in `shadow` until
+/// then and the id of the first descriptor identifies the chain.
+struct Virtqueue {
+    index: u16,
+    size: u16,
+    // the layout is negotiated on every start of the device
+    packed: bool,
+    // whether the device tells which index it wants to be notified at, i.e.
+    // VIRTIO_RING_F_EVENT_IDX was negotiated
+    event_idx: bool,
+    // keeps the rings mapped
+    mem: Dma<u8>,
+    free_head: u16,
+    num_free: u16,
+    // next slot of the available ring, or the ring of a packed virtqueue
+    avail_idx: u16,
+    // next slot of the used ring, or the ring of a packed virtqueue
+    last_used_idx: u16,
+    // descriptors of a packed virtqueue by id
+    shadow: Vec<PackedShadow>,
+    // number of descriptors of every chain in a packed virtqueue by the id of its head
+    chain_len: Vec<u16>,
+    avail_wrap_counter: bool,
+    used_wrap_counter: bool,
+    // the first chain made available since the last publish, its flags are written last
+    pending_head: Option<(u16, u16)>,
+    // slots made available since the device was last notified
+    num_added: u16,
+    // offset of the queue's doorbell in the notification structure
+    notify_offset: usize,
+}
+
+impl Virtqueue {
+    /// Returns an empty split queue `index` of `size` descriptors in `mem`.
+    fn new(mem: Dma<u8>, index: u16, size: u16) -> Virtqueue {
+        let mut queue = Virtqueue {
+            index,
+            size,
+            packed: false,
+            event_idx: false,
+            mem,
+            free_head: 0,
+            num_free: 0,
+            avail_idx: 0,
+            last_used_idx: 0,
+            shadow: vec![PackedShadow::default(); usize::from(size)],
+            chain_len: vec![0; usize::from(size)],
+            avail_wrap_counter: true,
+            used_wrap_counter: true,
+            pending_head: None,
+            num_added: 0,
+            notify_offset: 0,
+        };
+
+        queue.reset();
+
+        queue
+    }
+
+    /// Puts all descriptors back on the free list and empties the rings, the device must not
+    /// access the queue anymore.
+    fn reset(&mut self) {
+        unsafe {
+            memset(self.mem.virt, ring_size(usize::from(self.size)), 0);
+        }
+
+        for i in 0..self.size {
+            self.set_desc_next(i, i + 1);
+        }
+
+        // the queue is polled, the device must not raise interrupts
+        unsafe {
+            if self.packed {
+                ptr::write_volatile(
+                    self.driver_event().add(1),
+                    VIRTQ_RING_EVENT_FLAGS_DISABLE.to_le(),
+                );
+            } else {
+                ptr::write_volatile(self.avail(), VIRTQ_AVAIL_F_NO_INTERRUPT.to_le());
+            }
+        }
+
+        self.free_head = 0;
+        self.num_free = self.size;
+        self.avail_idx = 0;
+        self.last_used_idx = 0;
+        self.avail_wrap_counter = true;
+        self.used_wrap_counter = true;
+        self.pending_head = None;
+        self.num_added = 0;
+    }
+
+    /// Takes a descriptor off the free list, points it to the `len` bytes at `addr` and returns
+    /// its id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no descriptor is free.
+    fn alloc_desc(&mut self, addr: usize, len: usize, flags: u16) -> u16 {
+        assert!(self.num_free > 0, "no free descriptor");
+
+        let id = self.free_head;
+        self.free_head = self.desc_next(id);
+
+        if self.packed {
+            let shadow = &mut self.shadow[usize::from(id)];
+            shadow.addr = addr;
+            shadow.len = len;
+            shadow.flags = flags;
+        } else {
+            unsafe {
+                let desc = self.desc().add(usize::from(id));
+                ptr::write_volatile(&mut (*desc).addr, (addr as u64).to_le());
+                ptr::write_volatile(&mut (*desc).len, (len as u32).to_le());
+                ptr::write_volatile(&mut (*desc).flags, flags.to_le());
+            }
+        }
+
+        self.num_free -= 1;
+
+        id
+    }
+
+    /// Appends the descriptor `next` to the descriptor `id`.
+    fn chain_desc(&mut self, id: u16, next: u16) {
+        let flags = self.desc_flags(id);
+        self.set_desc_flags(id, flags | VIRTQ_DESC_F_NEXT);
+        self.set_desc_next(id, next);
+    }
+
+    /// Makes the descriptor chain starting at `head` available, the device only sees it once
+    /// the queue is published.
+    fn push_avail(&mut self, head: u16) {
+        if self.packed {
+            self.push_avail_packed(head);
+            return;
+        }
+
+        let slot = usize::from(self.avail_idx % self.size);
+
+        unsafe {
+            ptr::write_volatile(self.avail().add(2 + slot), head.to_le());
+        }
+
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        self.num_added = self.num_added.wrapping_add(1);
+    }
+
+    /// Writes the descriptor chain starting at `head` to the next slots of a packed virtqueue.
+    fn push_avail_packed(&mut self, head: u16) {
+        let mut id = head;
+        let mut len = 0;
+
+        loop {
+            let shadow = self.shadow[usize::from(id)];
+            let desc = unsafe { self.packed_desc().add(usize::from(self.avail_idx)) };
+
+            // the avail and used flags of an available descriptor differ, see section 2.7.1
+            let flags = shadow.flags
+                | if self.avail_wrap_counter {
+                    VIRTQ_DESC_F_AVAIL
+                } else {
+                    VIRTQ_DESC_F_USED
+                };
+
+            unsafe {
+                ptr::write_volatile(&mut (*desc).addr, (shadow.addr as u64).to_le());
+                ptr::write_volatile(&mut (*desc).len, (shadow.len as u32).to_le());
+                ptr::write_volatile(&mut (*desc).id, head.to_le());
+            }
+
+            // the device stops at the first descriptor that isn't available, so the flags of
+            // the first head of a batch are written once all others are in place
+            if id == head && self.pending_head.is_none() {
+                self.pending_head = Some((self.avail_idx, flags));
+            } else {
+                unsafe {
+                    ptr::write_volatile(&mut (*desc).flags, flags.to_le());
+                }
+            }
+
+            len += 1;
+
+            self.avail_idx += 1;
+            if self.avail_idx == self.size {
+                self.avail_idx = 0;
+                self.avail_wrap_counter = !self.avail_wrap_counter;
+            }
+
+            if (shadow.flags & VIRTQ_DESC_F_NEXT) == 0 {
+                break;
+            }
+
+            id = shadow.next;
+        }
+
+        self.chain_len[usize::from(head)] = len;
+        self.num_added = self.num_added.wrapping_add(len);
+    }
+
+    /// Hands all descriptor chains made available to the device.
+    fn publish(&mut self) {
+        // the device must see the descriptors before the new index or head
+        atomic::fence(Ordering::Release);
+
+        if self.packed {
+            if let Some((slot, flags)) = self.pending_head.take() {
+                unsafe {
+                    let desc = self.packed_desc().add(usize::from(slot));
+                    ptr::write_volatile(&mut (*desc).flags, flags.to_le());
+                }
+            }
+        } else {
+            unsafe {
+                ptr::write_volatile(self.avail().add(1), self.avail_idx.to_le());
+            }
+        }
+    }
+
+    /// Returns whether the device wants to be notified about the chains published since the
+    /// last notification, see sections 2.6.10 and 2.7.10.
+    fn needs_notification(&mut self) -> bool {
+        // the device must see the published chains before its event is read
+        atomic::fence(Ordering::SeqCst);
+
+        let new = self.avail_idx;
+        let old = new.wrapping_sub(self.num_added);
+        self.num_added = 0;
+
+        if self.packed {
+            let (off_wrap, flags) = unsafe {
+                (
+                    u16::from_le(ptr::read_volatile(self.device_event())),
+                    u16::from_le(ptr::read_volatile(self.device_event().add(1))),
+                )
+            };
+
+            if !self.event_idx || flags != VIRTQ_RING_EVENT_FLAGS_DESC {
+                return flags != VIRTQ_RING_EVENT_FLAGS_DISABLE;
+            }
+
+            // the event is a slot of the ring and the wrap counter the slot belongs to
+            let mut event = off_wrap & !VIRTQ_RING_EVENT_WRAP_COUNTER;
+            if ((off_wrap & VIRTQ_RING_EVENT_WRAP_COUNTER) != 0) != self.avail_wrap_counter {
+                event = event.wrapping_sub(self.size);
+            }
+
+            return need_event(event, new, old);
+        }
+
+        if self.event_idx {
+            let avail_event = u16::from_le(unsafe {
+                ptr::read_volatile(self.used_ring().add(usize::from(self.size)) as *const u16)
+            });
+
+            need_event(avail_event, new, old)
+        } else {
+            let flags = u16::from_le(unsafe { ptr::read_volatile(self.used()) });
+
+            (flags & VIRTQ_USED_F_NO_NOTIFY) == 0
+        }
+    }
+
+    /// Returns the head and the written length of the next chain the device is done with
+    /// without taking it off the used ring.
+    fn peek_used(&self) -> Option<(u16, usize)> {
+        if self.packed {
+            return self.peek_used_packed();
+        }
+
+        let used_idx = u16::from_le(unsafe { ptr::read_volatile(self.used().add(1)) });
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+
+        // the element must not be read before the index
+        atomic::fence(Ordering::Acquire);
+
+        let elem = unsafe {
+            self.used_ring()
+                .add(usize::from(self.last_used_idx % self.size))
+        };
+        let (id, len) = unsafe {
+            (
+                u32::from_le(ptr::read_volatile(&(*elem).id)),
+                u32::from_le(ptr::read_volatile(&(*elem).len)),
+            )
+        };
+
+        Some((id as u16, len as usize))
+    }
+
+    /// Returns the head and the written length of the next used descriptor of a packed
+    /// virtqueue, see section 2.7.21.
+    fn peek_used_packed(&self) -> Option<(u16, usize)> {
+        let desc = unsafe { self.packed_desc().add(usize::from(self.last_used_idx)) };
+        let flags = u16::from_le(unsafe { ptr::read_volatile(&(*desc).flags) });
+
+        // the device sets both flags to its wrap counter
+        let avail = (flags & VIRTQ_DESC_F_AVAIL) != 0;
+        let used = (flags & VIRTQ_DESC_F_USED) != 0;
+        if avail != used || used != self.used_wrap_counter {
+            return None;
+        }
+
+        // the id and length must not be read before the flags
+        atomic::fence(Ordering::Acquire);
+
+        let (id, len) = unsafe {
+            (
+                u16::from_le(ptr::read_volatile(&(*desc).id)),
+                u32::from_le(ptr::read_volatile(&(*desc).len)),
+            )
+        };
+
+        Some((id, len as usize))
+    }
+
+    /// Takes the next chain the device is done with off the used ring and returns its head and
+    /// the written length.
+    fn next_used(&mut self) -> Option<(u16, usize)> {
+        let used = self.peek_used()?;
+
+        if self.packed {
+            // the device writes one used descriptor per chain and skips the rest of it
+            self.last_used_idx += self.chain_len[usize::from(used.0)];
+            if self.last_used_idx >= self.size {
+                self.last_used_idx -= self.size;
+                self.used_wrap_counter = !self.used_wrap_counter;
+            }
+        } else {
+            self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        }
+
+        Some(used)
+    }
+
+    /// Puts the descriptor chain starting at `head` back on the free list and calls `f` with the
+    /// id of every descriptor of the chain.
+    fn free_chain(&mut self, head: u16, mut f: impl FnMut(u16)) {
+        let mut id = head;
+
+        loop {
+            f(id);
+            self.num_free += 1;
+
+            if (self.desc_flags(id) & VIRTQ_DESC_F_NEXT) == 0 {
+                // the last descriptor of the chain points to the old head of the free list
+                self.set_desc_next(id, self.free_head);
+                break;
+            }
+
+            id = self.desc_next(id);
+        }
+
+        self.free_head = head;
+    }
+
+    /// Returns the number of descriptors the device owns.
+    fn num_used(&self) -> usize {
+        usize::from(self.size - self.num_free)
+    }
+
+    /// Returns the next slot of the available ring and the next slot of the used ring the
+    /// device writes, for a packed virtqueue the slots of the driver.
+    fn ring_indexes(&self) -> (u16, u16) {
+        if self.packed {
+            return (self.avail_idx, self.last_used_idx);
+        }
+
+        unsafe {
+            (
+                u16::from_le(ptr::read_volatile(self.avail().add(1))),
+                u16::from_le(ptr::read_volatile(self.used().add(1))),
+            )
+        }
+    }
+
+    /// Returns the physical address of the descriptor table or ring.
+    fn desc_phys(&self) -> usize {
+        self.mem.phys
+    }
+
+    /// Returns the physical address of the available ring, or the driver event suppression
+    /// structure of a packed virtqueue.
+    fn avail_phys(&self) -> usize {
+        self.mem.phys + avail_offset(usize::from(self.size))
+    }
+
+    /// Returns the physical address of the used ring, or the device event suppression
+    /// structure of a packed virtqueue.
+    fn used_phys(&self) -> usize {
+        let size = usize::from(self.size);
+
+        if self.packed {
+            self.mem.phys + avail_offset(size) + VIRTQ_EVENT_SUPPRESS_SIZE
+        } else {
+            self.mem.phys + used_offset(size)
+        }
+    }
+
+    fn desc(&self) -> *mut VirtqDesc {
+        self.mem.virt as *mut VirtqDesc
+    }
+
+    fn packed_desc(&self) -> *mut VirtqPackedDesc {
+        self.mem.virt as *mut VirtqPackedDesc
+    }
+
+    /// Returns the flags, idx and ring of the available ring.
+    fn avail(&self) -> *mut u16 {
+        (self.mem.virt as usize + avail_offset(usize::from(self.size))) as *mut u16
+    }
+
+    /// Returns the flags and idx of the used ring.
+    fn used(&self) -> *mut u16 {
+        (self.mem.virt as usize + used_offset(usize::from(self.size))) as *mut u16
+    }
+
+    fn used_ring(&self) -> *mut VirtqUsedElem {
+        (self.used() as usize + 4) as *mut VirtqUsedElem
+    }
+
+    /// Returns the off_wrap and flags of the driver event suppression structure.
+    fn driver_event(&self) -> *mut u16 {
+        self.avail()
+    }
+
+    /// Returns the off_wrap and flags of the device event suppression structure.
+    fn device_event(&self) -> *mut u16 {
+        (self.avail() as usize + VIRTQ_EVENT_SUPPRESS_SIZE) as *mut u16
+    }
+
+    fn desc_flags(&self, id: u16) -> u16 {
+        if self.packed {
+            self.shadow[usize::from(id)].flags
+        } else {
+            u16::from_le(unsafe { ptr::read_volatile(&(*self.desc().add(usize::from(id))).flags) })
+        }
+    }
+
+    fn set_desc_flags(&mut self, id: u16, flags: u16) {
+        if self.packed {
+            self.shadow[usize::from(id)].flags = flags;
+        } else {
+            unsafe {
+                ptr::write_volatile(
+                    &mut (*self.desc().add(usize::from(id))).flags,
+                    flags.to_le(),
+                );
+            }
+        }
+    }
+
+    fn desc_next(&self, id: u16) -> u16 {
+        if self.packed {
+            self.shadow[usize::from(id)].next
+        } else {
+            u16::from_le(unsafe { ptr::read_volatile(&(*self.desc().add(usize::from(id))).next) })
+        }
+    }
+
+    fn set_desc_next(&mut self, id: u16, next: u16) {
+        if self.packed {
+            self.shadow[usize::from(id)].next = next;
+        } else {
+            unsafe {
+                ptr::write_volatile(&mut (*self.desc().add(usize::from(id))).next, next.to_le());
+            }
+        }
+    }
+}
+
+/// Control virtqueue and the buffer of the command in flight, see section 5.1.6.5.
+struct VirtioCtrlQueue {
+    vq: Virtqueue,
+    buf: Dma<u8>,
+}
+
+struct VirtioRxQueue {
+    vq: Virtqueue,
+    pool: Arc<Mempool>,
+    // mempool entry of the buffer of every descriptor the device owns
+    bufs: Vec<Option<usize>>,
+    num_posted: usize,
+    drained: bool,
+    // whether frames may span several buffers, i.e. VIRTIO_NET_F_MRG_RXBUF was negotiated
+    mergeable: bool,
+    // frame whose remaining buffers the device hasn't returned yet and their number
+    pending: Option<(Packet, u16)>,
+    rx_pkts: u64,
+    rx_bytes: u64,
+}
+
+impl VirtioRxQueue {
+    /// Returns a queue on `vq` that receives into buffers of `pool`.
+    fn new(vq: Virtqueue, pool: Arc<Mempool>) -> VirtioRxQueue {
+        let size = usize::from(vq.size);
+
+        VirtioRxQueue {
+            vq,
+            pool,
+            bufs: (0..size).map(|_| None).collect(),
+            num_posted: size,
+            drained: false,
+            mergeable: false,
+            pending: None,
+            rx_pkts: 0,
+            rx_bytes: 0,
+        }
+    }
+
+    /// Empties the queue for a new start of the device and posts `num_posted` buffers again.
+    fn rewind(&mut self) -> Result<(), IxyError> {
+        for buf in self.bufs.iter_mut().filter_map(Option::take) {
+            self.pool.free_buf(buf);
+        }
+
+        self.pending = None;
+        self.vq.reset();
+
+        if !self.drained {
+            self.fill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Posts buffers of the mempool until the device owns `num_posted` of them, returns whether
+    /// buffers were posted.
+    fn fill(&mut self) -> Result<bool, IxyError> {
+        let mut posted = false;
+
+        while self.vq.num_used() < self.num_posted {
+            let buf = self.pool.alloc_buf().ok_or(IxyError::PoolExhausted)?;
+            self.post(buf);
+            posted = true;
+        }
+
+        if posted {
+            self.vq.publish();
+        }
+
+        Ok(posted)
+    }
+
+    /// Puts the mempool entry `buf` into the available ring.
+    fn post(&mut self, buf: usize) {
+        // the device writes the header into the headroom in front of the packet data
+        let addr = unsafe { self.pool.get_phys_addr(buf) } - VIRTIO_NET_HDR_SIZE;
+        let len = self.pool.entry_size() - self.pool.headroom() + VIRTIO_NET_HDR_SIZE;
+
+        let id = self.vq.alloc_desc(addr, len, VIRTQ_DESC_F_WRITE);
+        self.bufs[usize::from(id)] = Some(buf);
+        self.vq.push_avail(id);
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills the queue.
+    /// Returns the number of received packets and whether buffers were posted.
+    ///
+    /// A frame spanning several buffers is returned as a chain of segments once the device
+    /// returned all of its buffers.
+    fn receive(&mut self, buffer: &mut VecDeque<Packet>, num_packets: usize) -> (usize, bool) {
+        let mut received_packets = 0;
+        let mut used_buffers = 0;
+
+        {
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
+
+            while received_packets < num_packets {
+                let (id, len) = match self.vq.next_used() {
+                    Some(used) => used,
+                    None => break,
+                };
+
+                let bufs = &mut self.bufs;
+                let mut buf = None;
+                self.vq
+                    .free_chain(id, |i| buf = bufs[usize::from(i)].take());
+                let buf = buf.expect("descriptor without buffer");
+
+                used_buffers += 1;
+
+                let pool = &self.pool;
+
+                if let Some((mut head, remaining)) = self.pending.take() {
+                    // only the first buffer of a frame starts with a header, the data of all
+                    // others starts right at the descriptor
+                    let segment = unsafe {
+                        Packet {
+                            addr_virt: pool.get_virt_addr(buf).sub(VIRTIO_NET_HDR_SIZE),
+                            addr_phys: pool.get_phys_addr(buf) - VIRTIO_NET_HDR_SIZE,
+                            len,
+                            pool: pool.clone(),
+                            pool_entry: buf,
+                            next: None,
+                            meta: PacketMeta::default(),
+                        }
+                    };
+
+                    self.rx_bytes += len as u64;
+                    head.chain(segment);
+
+                    if remaining > 1 {
+                        self.pending = Some((head, remaining - 1));
+                    } else {
+                        self.rx_pkts += 1;
+                        buffer.push_back(head);
+                        received_packets += 1;
+                    }
+
+                    continue;
+                }
+
+                let num_buffers = if self.mergeable {
+                    u16::from_le(unsafe {
+                        ptr::read_volatile(
+                            pool.get_virt_addr(buf)
+                                .sub(VIRTIO_NET_HDR_SIZE - VIRTIO_NET_HDR_NUM_BUFFERS)
+                                as *const u16,
+                        )
+                    })
+                } else {
+                    1
+                };
+
+                #[allow(unused_mut)]
+                let mut p = unsafe {
+                    Packet {
+                        addr_virt: pool.get_virt_addr(buf),
+                        addr_phys: pool.get_phys_addr(buf),
+                        len: len.saturating_sub(VIRTIO_NET_HDR_SIZE),
+                        pool: pool.clone(),
+                        pool_entry: buf,
+                        next: None,
+                        meta: PacketMeta::default(),
+                    }
+                };
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                self.rx_bytes += p.len as u64;
+
+                if num_buffers > 1 {
+                    self.pending = Some((p, num_buffers - 1));
+                    continue;
+                }
+
+                self.rx_pkts += 1;
+
+                buffer.push_back(p);
+                received_packets += 1;
+            }
+
+            // hand new buffers to the device, at most num_posted at a time
+            while used_buffers > 0 && self.vq.num_used() < self.num_posted {
+                match free_stack.pop() {
+                    Some(buf) => {
+                        let addr = unsafe { self.pool.get_phys_addr(buf) } - VIRTIO_NET_HDR_SIZE;
+                        let len =
+                            self.pool.entry_size() - self.pool.headroom() + VIRTIO_NET_HDR_SIZE;
+
+                        let id = self.vq.alloc_desc(addr, len, VIRTQ_DESC_F_WRITE);
+                        self.bufs[usize::from(id)] = Some(buf);
+                        self.vq.push_avail(id);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        if used_buffers > 0 {
+            self.vq.publish();
+        }
+
+        (received_packets, used_buffers > 0)
+    }
+
+    /// Returns the content of the first buffer of the next received packet without taking it
+    /// off the queue.
+    fn peek(&self) -> Option<&[u8]> {
+        // the first buffer of a frame still waiting for the rest is already off the ring
+        if let Some((ref head, _)) = self.pending {
+            return Some(unsafe { slice::from_raw_parts(head.addr_virt, head.len) });
+        }
+
+        let (id, len) = self.vq.peek_used()?;
+        let buf = self.bufs[usize::from(id)]?;
+
+        unsafe {
+            Some(slice::from_raw_parts(
+                self.pool.get_virt_addr(buf),
+                len.saturating_sub(VIRTIO_NET_HDR_SIZE),
+            ))
+        }
+    }
+
+    /// Returns the number of bytes of a frame that fit into a single buffer.
+    fn buf_len(&self) -> usize {
+        self.pool.entry_size() - self.pool.headroom()
+    }
+
+    /// Limits the buffers the device may receive into to `num_posted`, returns whether more
+    /// buffers were posted right away.
+    fn set_posted(&mut self, num_posted: usize) -> Result<bool, IxyError> {
+        if num_posted > usize::from(self.vq.size) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: queue of {} descriptors holds at most {}",
+                num_posted, self.vq.size, self.vq.size
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // posted buffers can't be taken back, lowering the limit takes effect as packets arrive
+        if self.drained {
+            return Ok(false);
+        }
+
+        self.fill()
+    }
+
+    /// Returns all buffers of the queue to the mempool, the device must not access them anymore.
+    fn drain(&mut self) {
+        for buf in self.bufs.iter_mut().filter_map(Option::take) {
+            self.pool.free_buf(buf);
+        }
+
+        self.pending = None;
+        self.vq.reset();
+        self.drained = true;
+    }
+}
+
+/// Fields of the virtio_net_hdr of a packet whose checksum the device fills in, see section
+/// 5.1.6.
+#[derive(Clone, Copy)]
+struct TxOffloadHeader {
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+}
+
+struct VirtioTxQueue {
+    vq: Virtqueue,
+    // one header per descriptor, the header of a packet belongs to its first descriptor
+    headers: *mut u8,
+    pool: Option<Arc<Mempool>>,
+    // mempool entry of every descriptor pointing to packet data
+    bufs: Vec<Option<usize>>,
+    // entries of the packets finished by the device, returned to the mempool at once
+    cleaned: Vec<usize>,
+    // longest frame the device accepts
+    max_frame_len: usize,
+    // the device fills in TCP and UDP checksums, i.e. VIRTIO_NET_F_CSUM was negotiated
+    csum: bool,
+    // the device segments TCP packets, i.e. VIRTIO_NET_F_HOST_TSO4 resp. TSO6 was negotiated
+    tso4: bool,
+    tso6: bool,
+    watchdog_used: u16,
+    watchdog_time: Instant,
+    tx_pkts: u64,
+    tx_bytes: u64,
+}
+
+impl VirtioTxQueue {
+    /// Returns an empty queue on `vq`.
+    fn new(vq: Virtqueue) -> VirtioTxQueue {
+        let size = usize::from(vq.size);
+
+        VirtioTxQueue {
+            headers: (vq.mem.virt as usize + headers_offset(size)) as *mut u8,
+            vq,
+            pool: None,
+            bufs: (0..size).map(|_| None).collect(),
+            cleaned: Vec::with_capacity(size),
+            max_frame_len: DEFAULT_MTU + FRAME_OVERHEAD,
+            csum: false,
+            tso4: false,
+            tso6: false,
+            watchdog_used: 0,
+            watchdog_time: Instant::now(),
+            tx_pkts: 0,
+            tx_bytes: 0,
+        }
+    }
+
+    /// Pops as many packets as fit into the queue from `packets` and puts them into the
+    /// available ring, the device only sees them once the queue is published.
+    fn send(&mut self, packets: &mut VecDeque<Packet>) -> usize {
+        let mut sent = 0;
+
+        self.clean();
+
+        if self.pool.is_none() {
+            if let Some(packet) = packets.front() {
+                self.pool = Some(packet.pool.clone());
+            }
+        }
+
+        while let Some(mut packet) = packets.pop_front() {
+            // the tag is inserted in software, the device has no VLAN offloads
+            if let Some(tci) = packet.meta.vlan_tci.take() {
+                if !packet.insert_vlan_tag(tci) {
+                    warn!("dropping frame without headroom for its VLAN tag");
+                    continue;
+                }
+            }
+
+            let num_segments = packet.num_segments();
+
+            assert!(
+                packet
+                    .segments()
+                    .all(|s| Arc::ptr_eq(self.pool.as_ref().unwrap(), &s.pool)),
+                "distinct memory pools for a single tx queue are not supported yet"
+            );
+            assert!(
+                num_segments <= TX_MAX_SEGMENTS,
+                "packet of {} segments exceeds the limit of {}",
+                num_segments,
+                TX_MAX_SEGMENTS
+            );
+
+            // the header takes another descriptor
+            if usize::from(self.vq.num_free) < num_segments + 1 {
+                // tx queue of device is full, push packet back onto the
+                // queue of to-be-sent packets
+                packets.push_front(packet);
+                break;
+            }
+
+            let offload = self.prepare_offloads(&mut packet);
+            let segmented =
+                offload.is_some_and(|offload| offload.gso_type != VIRTIO_NET_HDR_GSO_NONE);
+
+            // the device must not be handed frames above its mtu unless it segments them, see
+            // section 5.1.6.2
+            if !segmented && packet.total_len() > self.max_frame_len {
+                warn!(
+                    "dropping frame of {} bytes, the device takes at most {} bytes",
+                    packet.total_len(),
+                    self.max_frame_len
+                );
+                continue;
+            }
+
+            let head = self.vq.free_head;
+            let header = self.header(head);
+
+            unsafe {
+                memset(header, VIRTIO_NET_HDR_SIZE, 0);
+
+                // section 5.1.6.2 - the device checksums everything from csum_start on
+                if let Some(offload) = offload {
+                    *header.add(VIRTIO_NET_HDR_FLAGS) = VIRTIO_NET_HDR_F_NEEDS_CSUM;
+                    *header.add(VIRTIO_NET_HDR_GSO_TYPE) = offload.gso_type;
+                    ptr::write_unaligned(
+                        header.add(VIRTIO_NET_HDR_HDR_LEN) as *mut u16,
+                        offload.hdr_len.to_le(),
+                    );
+                    ptr::write_unaligned(
+                        header.add(VIRTIO_NET_HDR_GSO_SIZE) as *mut u16,
+                        offload.gso_size.to_le(),
+                    );
+                    ptr::write_unaligned(
+                        header.add(VIRTIO_NET_HDR_CSUM_START) as *mut u16,
+                        offload.csum_start.to_le(),
+                    );
+                    ptr::write_unaligned(
+                        header.add(VIRTIO_NET_HDR_CSUM_OFFSET) as *mut u16,
+                        offload.csum_offset.to_le(),
+                    );
+                }
+            }
+
+            let head = self
+                .vq
+                .alloc_desc(self.header_phys(head), VIRTIO_NET_HDR_SIZE, 0);
+
+            let mut prev = head;
+            let mut segment = Some(packet);
+
+            while let Some(mut p) = segment {
+                segment = p.unchain();
+
+                let id = self.vq.alloc_desc(p.get_phys_addr(), p.len(), 0);
+                self.vq.chain_desc(prev, id);
+
+                self.tx_bytes += p.len() as u64;
+                self.bufs[usize::from(id)] = Some(p.pool_entry);
+                mem::forget(p);
+
+                prev = id;
+            }
+
+            self.vq.push_avail(head);
+
+            self.tx_pkts += 1;
+            sent += 1;
+        }
+
+        if sent > 0 {
+            self.vq.publish();
+        }
+
+        sent
+    }
+
+    /// Returns the buffers of all packets the device is done with to their mempool.
+    fn clean(&mut self) {
+        let pool = match self.pool {
+            Some(ref pool) => pool,
+            None => return,
+        };
+
+        let cleaned = &mut self.cleaned;
+
+        while let Some((head, _)) = self.vq.next_used() {
+            let bufs = &mut self.bufs;
+            self.vq.free_chain(head, |id| {
+                if let Some(buf) = bufs[usize::from(id)].take() {
+                    cleaned.push(buf);
+                }
+            });
+        }
+
+        pool.free_bufs(cleaned.drain(..));
+    }
+
+    /// Returns whether the queue is empty or the device finished packets within `timeout`.
+    fn healthy(&mut self, timeout: Duration) -> bool {
+        self.clean();
+
+        if self.vq.num_used() == 0 || self.vq.last_used_idx != self.watchdog_used {
+            self.watchdog_used = self.vq.last_used_idx;
+            self.watchdog_time = Instant::now();
+
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Drops all pending packets and empties the queue, the device must not access it anymore.
+    fn reset(&mut self) {
+        if let Some(ref pool) = self.pool {
+            for buf in self.bufs.iter_mut().filter_map(Option::take) {
+                pool.free_buf(buf);
+            }
+        }
+
+        self.vq.reset();
+
+        self.watchdog_used = 0;
+        self.watchdog_time = Instant::now();
+    }
+
+    /// Fills in the checksums `packet` asks for in software, except for a TCP or UDP checksum
+    /// the device fills in. Returns the offloads for the header of the packet in that case.
+    fn prepare_offloads(&self, packet: &mut Packet) -> Option<TxOffloadHeader> {
+        let offloads = packet.meta.tx_offloads;
+        let tso_mss = offloads.tso_mss.filter(|&mss| mss > 0);
+        if !offloads.ipv4_checksum && !offloads.l4_checksum && tso_mss.is_none() {
+            return None;
+        }
+
+        let offsets = HeaderOffsets::parse(packet)?;
+
+        // all checksums of segmented packets are filled in, the TCP checksum field holds the
+        // pseudo header of the whole packet
+        let tcp_header_len = tso_mss.and_then(|_| offsets.tcp_header_len(packet));
+        if tcp_header_len.is_some() {
+            let checksum = pseudo_header_checksum(&packet[offsets.l3..offsets.l4]);
+            write_l4_checksum(packet, &offsets, checksum);
+        }
+
+        // the device has no offload for IPv4 header checksums
+        if offsets.ipv4 && (offloads.ipv4_checksum || tcp_header_len.is_some()) {
+            fill_ipv4_checksum(packet, &offsets);
+        }
+
+        if !offloads.l4_checksum && tcp_header_len.is_none() {
+            return None;
+        }
+
+        let field = offsets.l4_checksum_offset()?;
+
+        if !self.csum {
+            // the checksum covers the whole packet, it has to be in a single buffer
+            if packet.num_segments() == 1 {
+                fill_l4_checksum(packet, &offsets);
+            }
+
+            return None;
+        }
+
+        let mut offload = TxOffloadHeader {
+            gso_type: VIRTIO_NET_HDR_GSO_NONE,
+            hdr_len: 0,
+            gso_size: 0,
+            csum_start: offsets.l4 as u16,
+            csum_offset: field as u16,
+        };
+
+        // section 5.1.6.2 - only packets with more than a segment of payload are split
+        if let (Some(mss), Some(l4len)) = (tso_mss, tcp_header_len) {
+            let hdr_len = offsets.l4 + l4len;
+            let supported = if offsets.ipv4 { self.tso4 } else { self.tso6 };
+
+            if supported && packet.total_len() > hdr_len + usize::from(mss) {
+                offload.gso_type = if offsets.ipv4 {
+                    VIRTIO_NET_HDR_GSO_TCPV4
+                } else {
+                    VIRTIO_NET_HDR_GSO_TCPV6
+                };
+                offload.hdr_len = hdr_len as u16;
+                offload.gso_size = mss;
+            }
+        }
+
+        Some(offload)
+    }
+
+    /// Returns the header slot of descriptor `id`.
+    fn header(&self, id: u16) -> *mut u8 {
+        (self.headers as usize + usize::from(id) * VIRTIO_NET_HDR_SIZE) as *mut u8
+    }
+
+    /// Returns the physical address of the header slot of descriptor `id`.
+    fn header_phys(&self, id: u16) -> usize {
+        self.vq.mem.phys
+            + headers_offset(usize::from(self.vq.size))
+            + usize::from(id) * VIRTIO_NET_HDR_SIZE
+    }
+}
+
+impl IxyDevice for VirtioDevice {
+    /// Returns an initialized `VirtioDevice` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<VirtioDevice, IxyError> {
+        VirtioDevice::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the features written to the device after negotiation.
+    fn get_negotiated_features(&self) -> Result<u64, IxyError> {
+        Ok(VirtioDevice::get_negotiated_features(self))
+    }
+
+    /// Returns the mac address of this device, all zeros if the device has none.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        let mut mac = [0; 6];
+
+        if !self.has_feature(VIRTIO_NET_F_MAC) {
+            return mac;
+        }
+
+        // the device may change its configuration while it is read
+        loop {
+            let generation = self.common_read_8(VIRTIO_PCI_COMMON_CFGGENERATION);
+
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = self.device_cfg_read_8(VIRTIO_NET_CFG_MAC + i);
+            }
+
+            if self.common_read_8(VIRTIO_PCI_COMMON_CFGGENERATION) == generation {
+                return mac;
+            }
+        }
+    }
+
+    /// Sets the mac address of this device via the control queue, the device needs
+    /// VIRTIO_NET_F_CTRL_MAC_ADDR for that.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        if !self.has_feature(VIRTIO_NET_F_CTRL_MAC_ADDR) {
+            warn!(
+                "cannot set mac address {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} of {}: the \
+                 device has no control over its mac address",
+                mac[0], mac[1], mac[2], mac[3], mac[4], mac[5], self.pci_addr
+            );
+            return;
+        }
+
+        match self.ctrl_command(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, &mac) {
+            Ok(()) => self.mac_addr.set(Some(mac)),
+            Err(e) => warn!("failed to set mac address of {}: {}", self.pci_addr, e),
+        }
+    }
+
+    /// Adds `mac` to the mac table of the device, the device needs VIRTIO_NET_F_CTRL_RX for
+    /// that.
+    fn add_unicast_filter(&self, mac: [u8; 6]) -> Result<usize, IxyError> {
+        self.check_ctrl_rx()?;
+
+        let index = self
+            .unicast_filters
+            .borrow()
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| {
+                IxyError::InvalidConfiguration("all unicast filters are in use".to_string())
+            })?;
+
+        self.unicast_filters.borrow_mut()[index] = Some(mac);
+
+        if let Err(e) = self.write_mac_table() {
+            self.unicast_filters.borrow_mut()[index] = None;
+            return Err(e);
+        }
+
+        Ok(index)
+    }
+
+    /// Removes the unicast filter `index` from the mac table of the device.
+    fn remove_unicast_filter(&self, index: usize) -> Result<(), IxyError> {
+        self.check_ctrl_rx()?;
+
+        let mac = match self.unicast_filters.borrow().get(index) {
+            Some(Some(mac)) => *mac,
+            _ => {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "unicast filter {} is not in use",
+                    index
+                )))
+            }
+        };
+
+        self.unicast_filters.borrow_mut()[index] = None;
+
+        if let Err(e) = self.write_mac_table() {
+            self.unicast_filters.borrow_mut()[index] = Some(mac);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Enables or disables promiscuous mode via the control queue, the device needs
+    /// VIRTIO_NET_F_CTRL_RX for that.
+    fn set_promisc(&self, enabled: bool) -> Result<(), IxyError> {
+        self.check_ctrl_rx()?;
+
+        self.ctrl_command(
+            VIRTIO_NET_CTRL_RX,
+            VIRTIO_NET_CTRL_RX_PROMISC,
+            &[u8::from(enabled)],
+        )?;
+        self.promisc.set(Some(enabled));
+
+        Ok(())
+    }
+
+    /// Enables or disables the reception of all multicast packets via the control queue, the
+    /// device needs VIRTIO_NET_F_CTRL_RX for that.
+    fn set_allmulti(&self, enabled: bool) -> Result<(), IxyError> {
+        self.check_ctrl_rx()?;
+
+        self.ctrl_command(
+            VIRTIO_NET_CTRL_RX,
+            VIRTIO_NET_CTRL_RX_ALLMULTI,
+            &[u8::from(enabled)],
+        )?;
+        self.allmulti.set(Some(enabled));
+
+        Ok(())
+    }
+
+    /// Replaces the multicast addresses of the mac table of the device.
+    fn set_multicast_filters(&self, macs: &[[u8; 6]]) -> Result<(), IxyError> {
+        self.check_ctrl_rx()?;
+
+        if macs.len() > NUM_MULTICAST_FILTERS {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot set {} multicast filters: limit is {}",
+                macs.len(),
+                NUM_MULTICAST_FILTERS
+            )));
+        }
+
+        let previous = self.multicast_filters.replace(macs.to_vec());
+
+        if let Err(e) = self.write_mac_table() {
+            self.multicast_filters.replace(previous);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Enables or disables VLAN stripping, it is done by the driver.
+    fn set_vlan_strip(&mut self, enabled: bool) -> Result<(), IxyError> {
+        self.vlan_strip = enabled;
+
+        Ok(())
+    }
+
+    /// Adds `vlan` to the VLAN filter table, tagged packets of other VLANs are dropped by the
+    /// driver.
+    fn add_vlan_filter(&self, vlan: u16) -> Result<(), IxyError> {
+        check_vlan_id(vlan)?;
+
+        let mut filters = self.vlan_filters.borrow_mut();
+        if !filters.contains(&vlan) {
+            filters.push(vlan);
+        }
+
+        Ok(())
+    }
+
+    /// Removes `vlan` from the VLAN filter table.
+    fn remove_vlan_filter(&self, vlan: u16) -> Result<(), IxyError> {
+        check_vlan_id(vlan)?;
+
+        self.vlan_filters.borrow_mut().retain(|&v| v != vlan);
+
+        Ok(())
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (mut received, posted) = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        if posted && self.rx_queues[queue_id as usize].vq.needs_notification() {
+            self.notify_queue(&self.rx_queues[queue_id as usize].vq);
+        }
+
+        if self.vlan_strip || !self.vlan_filters.borrow().is_empty() {
+            received = self.apply_vlan_offloads(buffer, received);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the device was removed, i.e. its status reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.common_read_8(VIRTIO_PCI_COMMON_STATUS) == VIRTIO_FAILED_READ_STATUS
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of buffers of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].set_posted(num_posted)?
+            && self.rx_queues[queue_id as usize].vq.needs_notification()
+        {
+            self.notify_queue(&self.rx_queues[queue_id as usize].vq);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of buffers of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all packets of the batch are available, notify the device once for the whole batch
+        // and only if it asks for it
+        if sent > 0 && self.tx_queues[queue_id as usize].vq.needs_notification() {
+            self.notify_queue(&self.tx_queues[queue_id as usize].vq);
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`, the packets are counted by the driver.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_pkts += current.rx_pkts - last.rx_pkts;
+        stats.tx_pkts += current.tx_pkts - last.tx_pkts;
+        stats.rx_bytes += current.rx_bytes - last.rx_bytes;
+        stats.tx_bytes += current.tx_bytes - last.tx_bytes;
+
+        self.counters.set(current);
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.counters.set(self.read_counters());
+    }
+
+    /// Virtio devices have no counters for extended stats.
+    fn read_extended_stats(&self, _stats: &mut ExtendedStats) {}
+
+    /// Returns the link speed the device reports, or a gigabit if it has no speed.
+    fn get_link_speed(&self) -> u32 {
+        if self.has_feature(VIRTIO_NET_F_STATUS)
+            && (self.device_cfg_read_16(VIRTIO_NET_CFG_STATUS) & VIRTIO_NET_S_LINK_UP) == 0
+        {
+            return 0;
+        }
+
+        if self.has_feature(VIRTIO_NET_F_SPEED_DUPLEX) {
+            let speed = self.device_cfg_read_32(VIRTIO_NET_CFG_SPEED);
+            if speed != 0 && speed != VIRTIO_NET_SPEED_UNKNOWN {
+                return speed;
+            }
+        }
+
+        VIRTIO_NET_DEFAULT_SPEED
+    }
+
+    /// Returns the PCIe link of this device as emulated by the hypervisor, if any.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device, the device is reset and drops
+    /// the packets pending in the tx queues.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        // queues can only be set up while the device is in reset
+        self.stop()?;
+
+        for mut queue in self.rx_queues.drain(num_rx_queues as usize..) {
+            queue.drain();
+        }
+
+        for mut queue in self.tx_queues.drain(num_tx_queues as usize..) {
+            queue.reset();
+        }
+
+        self.num_rx_queues = self.num_rx_queues.min(num_rx_queues);
+        self.num_tx_queues = self.num_tx_queues.min(num_tx_queues);
+
+        for queue_id in self.num_rx_queues..num_rx_queues {
+            self.init_rx_queue(queue_id)?;
+        }
+        self.num_rx_queues = num_rx_queues;
+
+        for queue_id in self.num_tx_queues..num_tx_queues {
+            self.init_tx_queue(queue_id)?;
+        }
+        self.num_tx_queues = num_tx_queues;
+
+        self.start()
+    }
+
+    /// The queues of a virtio device are enabled as long as the device is running.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].drained {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Virtio devices can only stop all queues at once by a reset.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether rx queue `queue_id` has buffers.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_rx_queues) && !self.rx_queues[queue_id as usize].drained
+    }
+
+    /// The queues of a virtio device are enabled as long as the device is running.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// Virtio devices can only stop all queues at once by a reset.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether tx queue `queue_id` is configured.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        queue_id < u32::from(self.num_tx_queues)
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or the device finished packets within
+    /// `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(timeout))
+    }
+
+    /// Drops all pending packets of tx queue `queue_id`. Only a reset of the device stops its
+    /// queues, so the pending packets of the other tx queues are dropped as well.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.stop()?;
+        self.start()
+    }
+
+    /// Returns all buffers of rx queue `queue_id` to the mempool, the device is reset and
+    /// started again without them.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.stop()?;
+        self.rx_queues[queue_id as usize].drain();
+
+        self.start()
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // the device puts every packet into the used ring once it is sent
+        let queue = &mut self.tx_queues[queue_id as usize];
+        queue.clean();
+        while queue.vq.num_used() > 0 {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+            queue.clean();
+        }
+
+        Ok(())
+    }
+
+    /// Virtio devices drop packets when an rx queue has no buffer.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} always drops packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Virtio devices have no pause frames.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "ignoring flow control mode {:?} of {}: virtio has no pause frames",
+            mode, self.pci_addr
+        );
+    }
+
+    /// Returns [`FlowControl::None`], virtio devices have no pause frames.
+    fn get_flow_control(&self) -> FlowControl {
+        FlowControl::None
+    }
+
+    /// Virtio devices never pass the CRC to the driver.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot keep the crc on {}: virtio frames have none",
+                self.pci_addr
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, received frames never carry a CRC.
+    fn get_crc_strip(&self) -> bool {
+        true
+    }
+
+    /// Drains rx queue `queue_id` and restarts the device with a new mempool of `buffer_size`
+    /// bytes for the queue. Buffers smaller than a frame of the device's mtu require mergeable
+    /// rx buffers.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        if !self.has_feature(VIRTIO_NET_F_MRG_RXBUF) && buffer_size < self.mtu + FRAME_OVERHEAD {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is too small for the mtu of {} bytes: device {} has no \
+                 mergeable rx buffers",
+                buffer_size, self.mtu, self.pci_addr
+            )));
+        }
+
+        let pool = self.allocate_rx_mempool(buffer_size)?;
+
+        self.stop()?;
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        queue.drain();
+        queue.pool = pool;
+        queue.drained = false;
+
+        self.start()
+    }
+
+    /// Virtio devices have no EEPROM.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// Virtio devices have no thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        let vq = &self.rx_queues[queue_id as usize].vq;
+
+        (vq.desc_phys(), ring_size(usize::from(vq.size)))
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        let vq = &self.tx_queues[queue_id as usize].vq;
+
+        (vq.desc_phys(), ring_size(usize::from(vq.size)))
+    }
+
+    /// Reads the common configuration of this device and the indexes of its queues.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        self.common_write_32(VIRTIO_PCI_COMMON_DFSELECT, 0);
+        dump.push(
+            "DEVICE_FEATURE[0]",
+            self.common_read_32(VIRTIO_PCI_COMMON_DF),
+        );
+        self.common_write_32(VIRTIO_PCI_COMMON_DFSELECT, 1);
+        dump.push(
+            "DEVICE_FEATURE[1]",
+            self.common_read_32(VIRTIO_PCI_COMMON_DF),
+        );
+        self.common_write_32(VIRTIO_PCI_COMMON_GFSELECT, 0);
+        dump.push(
+            "DRIVER_FEATURE[0]",
+            self.common_read_32(VIRTIO_PCI_COMMON_GF),
+        );
+        self.common_write_32(VIRTIO_PCI_COMMON_GFSELECT, 1);
+        dump.push(
+            "DRIVER_FEATURE[1]",
+            self.common_read_32(VIRTIO_PCI_COMMON_GF),
+        );
+
+        dump.push(
+            "NUM_QUEUES",
+            u32::from(self.common_read_16(VIRTIO_PCI_COMMON_NUMQ)),
+        );
+        dump.push(
+            "DEVICE_STATUS",
+            u32::from(self.common_read_8(VIRTIO_PCI_COMMON_STATUS)),
+        );
+        dump.push(
+            "CONFIG_GENERATION",
+            u32::from(self.common_read_8(VIRTIO_PCI_COMMON_CFGGENERATION)),
+        );
+
+        // the indexes of the rings live in dma memory
+        let ctrl_queue = self.ctrl_queue.borrow();
+        let queues = self
+            .rx_queues
+            .iter()
+            .map(|queue| &queue.vq)
+            .chain(self.tx_queues.iter().map(|queue| &queue.vq))
+            .chain(ctrl_queue.as_ref().map(|ctrl_queue| &ctrl_queue.vq));
+
+        for vq in queues {
+            self.common_write_16(VIRTIO_PCI_COMMON_Q_SELECT, vq.index);
+            dump.push(
+                format!("QUEUE_SIZE[{}]", vq.index),
+                u32::from(self.common_read_16(VIRTIO_PCI_COMMON_Q_SIZE)),
+            );
+            dump.push(
+                format!("QUEUE_ENABLE[{}]", vq.index),
+                u32::from(self.common_read_16(VIRTIO_PCI_COMMON_Q_ENABLE)),
+            );
+
+            let (avail_idx, used_idx) = vq.ring_indexes();
+            dump.push(format!("AVAIL_IDX[{}]", vq.index), u32::from(avail_idx));
+            dump.push(format!("USED_IDX[{}]", vq.index), u32::from(used_idx));
+        }
+
+        dump
+    }
+
+    /// Resets this device via its status register and initializes it again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        self.stop()?;
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        // the device forgets its receive filters
+        self.promisc.set(None);
+        self.allmulti.set(None);
+        self.mac_addr.set(None);
+        self.unicast_filters
+            .replace(vec![None; NUM_UNICAST_FILTERS]);
+        self.multicast_filters.replace(Vec::new());
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for VirtioDevice {
+    fn drop(&mut self) {
+        // stop all dma before the rings and mempools are unmapped
+        if let Err(e) = self.stop() {
+            warn!("failed to reset device {}: {}", self.pci_addr, e);
+        }
+    }
+}
+
+impl VirtioDevice {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == VIRTIO_VENDOR_ID
+            && (device_id == VIRTIO_NET_TRANSITIONAL_DEVICE_ID
+                || device_id == VIRTIO_NET_MODERN_DEVICE_ID)
+    }
+
+    /// Returns the features negotiated with the device, i.e. the features written to it after
+    /// masking the offered features with the ones supported by this driver.
+    pub fn get_negotiated_features(&self) -> u64 {
+        self.features
+    }
+
+    /// Returns whether the feature `bit`, e.g. `VIRTIO_NET_F_MRG_RXBUF`, was negotiated.
+    pub fn has_feature(&self, bit: u64) -> bool {
+        (self.features & bit) != 0
+    }
+
+    /// Returns an initialized `VirtioDevice` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<VirtioDevice, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory
+        check_privileges(pci_addr, vfio, dma_size(num_rx_queues, num_tx_queues))?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        // section 4.1.4 - the capabilities tell where the configuration structures are
+        let caps = find_virtio_caps(&PciDevice::open(pci_addr)?)?;
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+        } else {
+            unbind_driver(pci_addr)?;
+            enable_dma(pci_addr)?;
+        }
+
+        // map every BAR that holds one of the structures once
+        let mut bar_ids: Vec<u8> = Vec::new();
+        let mut region = |bar: u8, offset: u32| {
+            let index = bar_ids.iter().position(|&b| b == bar).unwrap_or_else(|| {
+                bar_ids.push(bar);
+                bar_ids.len() - 1
+            });
+            Region {
+                bar: index,
+                offset: offset as usize,
+            }
+        };
+
+        let common = region(caps.common.0, caps.common.1);
+        let notify = region(caps.notify.0, caps.notify.1);
+        let device_cfg = region(caps.device.0, caps.device.1);
+
+        let bars = bar_ids
+            .iter()
+            .map(|&bar| {
+                if vfio {
+                    MappedBar::map_vfio(device_fd, bar)
+                } else {
+                    MappedBar::map(pci_addr, bar)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut dev = VirtioDevice {
+            pci_addr: pci_addr.to_string(),
+            bars,
+            common,
+            notify,
+            notify_off_multiplier: caps.notify_off_multiplier,
+            device_cfg,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            features: 0,
+            mtu: DEFAULT_MTU,
+            ctrl_queue: RefCell::new(None),
+            promisc: Cell::new(None),
+            allmulti: Cell::new(None),
+            mac_addr: Cell::new(None),
+            unicast_filters: RefCell::new(vec![None; NUM_UNICAST_FILTERS]),
+            multicast_filters: RefCell::new(Vec::new()),
+            vlan_strip: false,
+            vlan_filters: RefCell::new(Vec::new()),
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            counters: Cell::new(VirtioCounters::default()),
+            removal: RemovalLatch::new(VIRTIO_PCI_COMMON_STATUS),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets the device, negotiates the features, sets up the queues and starts the device,
+    /// see section 3.1.1.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        self.stop()?;
+
+        info!("initializing device {}", self.pci_addr);
+
+        for queue_id in 0..self.num_rx_queues {
+            self.init_rx_queue(queue_id)?;
+        }
+
+        for queue_id in 0..self.num_tx_queues {
+            self.init_tx_queue(queue_id)?;
+        }
+
+        self.start()?;
+
+        let mac = self.get_mac_addr();
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        self.counters.set(VirtioCounters::default());
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Allocates the virtqueue and mempool of rx queue `queue_id`, rx queues have the even
+    /// virtqueue indexes.
+    fn init_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+
+        let vq = self.allocate_virtqueue(2 * queue_id, NUM_RX_QUEUE_ENTRIES)?;
+        let mempool = self.allocate_rx_mempool(PKT_BUF_ENTRY_SIZE)?;
+
+        self.rx_queues.push(VirtioRxQueue::new(vq, mempool));
+
+        Ok(())
+    }
+
+    /// Allocates the virtqueue of tx queue `queue_id`, tx queues have the odd virtqueue indexes.
+    fn init_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+
+        let vq = self.allocate_virtqueue(2 * queue_id + 1, NUM_TX_QUEUE_ENTRIES)?;
+
+        self.tx_queues.push(VirtioTxQueue::new(vq));
+
+        Ok(())
+    }
+
+    /// Allocates virtqueue `index` with at most `max_size` descriptors, the device may support
+    /// fewer.
+    fn allocate_virtqueue(&self, index: u16, max_size: usize) -> Result<Virtqueue, IxyError> {
+        self.common_write_16(VIRTIO_PCI_COMMON_Q_SELECT, index);
+        let device_size = usize::from(self.common_read_16(VIRTIO_PCI_COMMON_Q_SIZE));
+
+        if device_size == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} has no virtqueue {}",
+                self.pci_addr, index
+            )));
+        }
+
+        let size = device_size.min(max_size);
+        let mem: Dma<u8> = allocate_dma(self.allocator.as_ref(), self.numa_node, ring_size(size))?;
+
+        debug!("virtqueue {} phys addr: {:#x}", index, mem.phys);
+        debug!("virtqueue {} virt addr: {:p}", index, mem.virt);
+
+        Ok(Virtqueue::new(mem, index, size as u16))
+    }
+
+    /// Allocates an rx mempool of `buffer_size` byte buffers whose headroom fits the header the
+    /// device writes in front of every packet.
+    fn allocate_rx_mempool(&self, buffer_size: usize) -> Result<Arc<Mempool>, IxyError> {
+        let pool = allocate_mempool(
+            self.allocator.as_ref(),
+            self.numa_node,
+            buffer_size + VIRTIO_NET_HDR_SIZE,
+        )?;
+        pool.set_headroom(pool.headroom().max(VIRTIO_NET_HDR_SIZE));
+
+        Ok(pool)
+    }
+
+    /// Resets the device and waits until it stopped all dma.
+    fn stop(&self) -> Result<(), IxyError> {
+        debug!("resetting device {}", self.pci_addr);
+
+        self.common_write_8(VIRTIO_PCI_COMMON_STATUS, 0);
+
+        let start = Instant::now();
+        while self.common_read_8(VIRTIO_PCI_COMMON_STATUS) != 0 {
+            if self.removal.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "device {} did not finish its reset",
+                    self.pci_addr
+                )));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    /// Negotiates the features, rewinds and sets up all queues and tells the device that the
+    /// driver is ready, see section 3.1.1. The device has to be in reset.
+    fn start(&mut self) -> Result<(), IxyError> {
+        self.set_status(VIRTIO_CONFIG_S_ACKNOWLEDGE);
+        self.set_status(VIRTIO_CONFIG_S_DRIVER);
+
+        let mut negotiated = self.negotiate_features(DRIVER_FEATURES);
+
+        // fall back to split virtqueues if the device refuses packed ones
+        if negotiated.is_err() && self.has_feature(VIRTIO_F_RING_PACKED) {
+            warn!(
+                "device {} refused packed virtqueues, falling back to split virtqueues",
+                self.pci_addr
+            );
+
+            self.stop()?;
+            self.set_status(VIRTIO_CONFIG_S_ACKNOWLEDGE);
+            self.set_status(VIRTIO_CONFIG_S_DRIVER);
+
+            negotiated = self.negotiate_features(DRIVER_FEATURES & !VIRTIO_F_RING_PACKED);
+        }
+
+        negotiated?;
+
+        let packed = self.has_feature(VIRTIO_F_RING_PACKED);
+        debug!(
+            "using {} virtqueues",
+            if packed { "packed" } else { "split" }
+        );
+
+        self.mtu = if self.has_feature(VIRTIO_NET_F_MTU) {
+            usize::from(self.device_cfg_read_16(VIRTIO_NET_CFG_MTU))
+        } else {
+            DEFAULT_MTU
+        };
+        debug!("mtu is {} bytes", self.mtu);
+
+        let mergeable = self.has_feature(VIRTIO_NET_F_MRG_RXBUF);
+
+        // without mergeable buffers every frame has to fit into a single buffer
+        if !mergeable {
+            if let Some(queue_id) = self
+                .rx_queues
+                .iter()
+                .position(|queue| queue.buf_len() < self.mtu + FRAME_OVERHEAD)
+            {
+                self.set_status(VIRTIO_CONFIG_S_FAILED);
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "rx buffers of queue {} are too small for the mtu of {} bytes",
+                    queue_id, self.mtu
+                )));
+            }
+        }
+
+        let event_idx = self.has_feature(VIRTIO_RING_F_EVENT_IDX);
+
+        for queue in self.rx_queues.iter_mut() {
+            queue.vq.packed = packed;
+            queue.vq.event_idx = event_idx;
+            queue.mergeable = mergeable;
+            queue.rewind()?;
+        }
+
+        for queue in self.tx_queues.iter_mut() {
+            queue.vq.packed = packed;
+            queue.vq.event_idx = event_idx;
+            queue.max_frame_len = self.mtu + FRAME_OVERHEAD;
+            queue.csum = (self.features & VIRTIO_NET_F_CSUM) != 0;
+            queue.tso4 = (self.features & VIRTIO_NET_F_HOST_TSO4) != 0;
+            queue.tso6 = (self.features & VIRTIO_NET_F_HOST_TSO6) != 0;
+            queue.reset();
+        }
+
+        // no configuration change interrupts
+        self.common_write_16(VIRTIO_PCI_COMMON_MSIX, VIRTIO_MSI_NO_VECTOR);
+
+        for i in 0..self.rx_queues.len() {
+            let offset = self.setup_virtqueue(&self.rx_queues[i].vq);
+            self.rx_queues[i].vq.notify_offset = offset;
+        }
+
+        for i in 0..self.tx_queues.len() {
+            let offset = self.setup_virtqueue(&self.tx_queues[i].vq);
+            self.tx_queues[i].vq.notify_offset = offset;
+        }
+
+        if self.has_feature(VIRTIO_NET_F_CTRL_VQ) {
+            self.init_ctrl_queue(packed, event_idx)?;
+        }
+
+        self.set_status(VIRTIO_CONFIG_S_DRIVER_OK);
+
+        // the rx queues start out full, the device has no event to ask for the first notification
+        for i in 0..self.rx_queues.len() {
+            self.rx_queues[i].vq.num_added = 0;
+            self.notify_queue(&self.rx_queues[i].vq);
+        }
+
+        self.restore_rx_filters()
+    }
+
+    /// Allocates the control virtqueue if it doesn't exist yet and sets it up.
+    fn init_ctrl_queue(&mut self, packed: bool, event_idx: bool) -> Result<(), IxyError> {
+        if self.ctrl_queue.get_mut().is_none() {
+            debug!("initializing control queue");
+
+            let vq = self.allocate_virtqueue(CTRL_QUEUE_INDEX, CTRL_QUEUE_ENTRIES)?;
+            let buf = allocate_dma(self.allocator.as_ref(), self.numa_node, CTRL_BUF_SIZE)?;
+
+            *self.ctrl_queue.get_mut() = Some(VirtioCtrlQueue { vq, buf });
+        }
+
+        let mut ctrl_queue = self.ctrl_queue.take().unwrap();
+
+        ctrl_queue.vq.packed = packed;
+        ctrl_queue.vq.event_idx = event_idx;
+        ctrl_queue.vq.reset();
+        ctrl_queue.vq.notify_offset = self.setup_virtqueue(&ctrl_queue.vq);
+
+        *self.ctrl_queue.get_mut() = Some(ctrl_queue);
+
+        Ok(())
+    }
+
+    /// Sends the receive filters set by the user to the device again after a reset.
+    fn restore_rx_filters(&self) -> Result<(), IxyError> {
+        if let Some(mac) = self.mac_addr.get() {
+            self.ctrl_command(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET, &mac)?;
+        }
+
+        if let Some(enabled) = self.promisc.get() {
+            self.ctrl_command(
+                VIRTIO_NET_CTRL_RX,
+                VIRTIO_NET_CTRL_RX_PROMISC,
+                &[u8::from(enabled)],
+            )?;
+        }
+
+        if let Some(enabled) = self.allmulti.get() {
+            self.ctrl_command(
+                VIRTIO_NET_CTRL_RX,
+                VIRTIO_NET_CTRL_RX_ALLMULTI,
+                &[u8::from(enabled)],
+            )?;
+        }
+
+        if self.unicast_filters.borrow().iter().any(Option::is_some)
+            || !self.multicast_filters.borrow().is_empty()
+        {
+            self.write_mac_table()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if the device can't change its receive mode or mac table.
+    fn check_ctrl_rx(&self) -> Result<(), IxyError> {
+        if !self.has_feature(VIRTIO_NET_F_CTRL_RX) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} has no control over its receive filters",
+                self.pci_addr
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Drops tagged packets of VLANs that are not in the filter table and strips the tags of the
+    /// others if stripping is enabled. Only the last `received` packets of `buffer` are touched,
+    /// returns how many of them are left.
+    fn apply_vlan_offloads(&self, buffer: &mut VecDeque<Packet>, received: usize) -> usize {
+        let filters = self.vlan_filters.borrow();
+        let start = buffer.len() - received;
+        let mut index = 0;
+
+        buffer.retain_mut(|p| {
+            index += 1;
+            if index <= start {
+                return true;
+            }
+
+            let tci = match p.vlan_tag() {
+                Some(tci) => tci,
+                None => return true,
+            };
+
+            if !filters.is_empty() && !filters.contains(&(tci & VLAN_ID_MASK)) {
+                return false;
+            }
+
+            if self.vlan_strip {
+                p.strip_vlan_tag();
+                p.meta.vlan_tci = Some(tci);
+            }
+
+            true
+        });
+
+        buffer.len() - start
+    }
+
+    /// Writes the unicast and multicast filters to the mac table of the device, see section
+    /// 5.1.6.5.2.
+    fn write_mac_table(&self) -> Result<(), IxyError> {
+        let mut data = Vec::new();
+
+        let unicast_filters = self.unicast_filters.borrow();
+        let unicast = unicast_filters.iter().flatten().collect::<Vec<_>>();
+        data.extend_from_slice(&(unicast.len() as u32).to_le_bytes());
+        for mac in unicast {
+            data.extend_from_slice(mac);
+        }
+
+        let multicast = self.multicast_filters.borrow();
+        data.extend_from_slice(&(multicast.len() as u32).to_le_bytes());
+        for mac in multicast.iter() {
+            data.extend_from_slice(mac);
+        }
+
+        self.ctrl_command(VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_TABLE_SET, &data)
+    }
+
+    /// Sends the command `cmd` of `class` with `data` via the control queue and waits for the
+    /// device to acknowledge it.
+    fn ctrl_command(&self, class: u8, cmd: u8, data: &[u8]) -> Result<(), IxyError> {
+        let mut ctrl_queue = self.ctrl_queue.borrow_mut();
+        let ctrl_queue = ctrl_queue.as_mut().ok_or_else(|| {
+            IxyError::InvalidConfiguration(format!("device {} has no control queue", self.pci_addr))
+        })?;
+
+        assert!(
+            data.len() <= CTRL_BUF_SIZE - CTRL_DATA_OFFSET,
+            "control command of {} bytes exceeds the buffer",
+            data.len()
+        );
+
+        let buf = &ctrl_queue.buf;
+        unsafe {
+            ptr::write_volatile(buf.virt.add(CTRL_HDR_OFFSET), class);
+            ptr::write_volatile(buf.virt.add(CTRL_HDR_OFFSET + 1), cmd);
+            ptr::copy_nonoverlapping(data.as_ptr(), buf.virt.add(CTRL_DATA_OFFSET), data.len());
+            ptr::write_volatile(buf.virt.add(CTRL_ACK_OFFSET), !VIRTIO_NET_OK);
+        }
+
+        // the device reads the header and the data and writes the ack
+        let vq = &mut ctrl_queue.vq;
+        let head = vq.alloc_desc(buf.phys + CTRL_HDR_OFFSET, 2, 0);
+        let data_desc = vq.alloc_desc(buf.phys + CTRL_DATA_OFFSET, data.len(), 0);
+        let ack_desc = vq.alloc_desc(buf.phys + CTRL_ACK_OFFSET, 1, VIRTQ_DESC_F_WRITE);
+        vq.chain_desc(head, data_desc);
+        vq.chain_desc(data_desc, ack_desc);
+        vq.push_avail(head);
+        vq.publish();
+
+        if vq.needs_notification() {
+            self.notify_queue(vq);
+        }
+
+        let start = Instant::now();
+        loop {
+            if let Some((id, _)) = vq.next_used() {
+                vq.free_chain(id, |_| {});
+                break;
+            }
+
+            if self.is_removed() {
+                return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+            }
+            if start.elapsed() > CTRL_TIMEOUT {
+                return Err(IxyError::InvalidConfiguration(format!(
+                    "device {} did not answer control command {}.{}",
+                    self.pci_addr, class, cmd
+                )));
+            }
+            thread::sleep(Duration::from_micros(100));
+        }
+
+        let ack = unsafe { ptr::read_volatile(buf.virt.add(CTRL_ACK_OFFSET)) };
+        if ack != VIRTIO_NET_OK {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} refused control command {}.{}",
+                self.pci_addr, class, cmd
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Accepts the features of the device in `driver_features`, see section 3.1.1.
+    fn negotiate_features(&mut self, driver_features: u64) -> Result<(), IxyError> {
+        self.common_write_32(VIRTIO_PCI_COMMON_DFSELECT, 0);
+        let low = self.common_read_32(VIRTIO_PCI_COMMON_DF);
+        self.common_write_32(VIRTIO_PCI_COMMON_DFSELECT, 1);
+        let high = self.common_read_32(VIRTIO_PCI_COMMON_DF);
+
+        let device_features = u64::from(low) | (u64::from(high) << 32);
+
+        self.features = device_features & driver_features;
+
+        // segmentation needs the device to fill in checksums, see section 5.1.3.1
+        if !self.has_feature(VIRTIO_NET_F_CSUM) {
+            self.features &= !(VIRTIO_NET_F_HOST_TSO4 | VIRTIO_NET_F_HOST_TSO6);
+        }
+
+        if (device_features & VIRTIO_F_VERSION_1) == 0 {
+            self.set_status(VIRTIO_CONFIG_S_FAILED);
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} only offers the legacy virtio interface",
+                self.pci_addr
+            )));
+        }
+
+        debug!(
+            "device features {:#018x}, driver features {:#018x}",
+            device_features, self.features
+        );
+
+        self.common_write_32(VIRTIO_PCI_COMMON_GFSELECT, 0);
+        self.common_write_32(VIRTIO_PCI_COMMON_GF, self.features as u32);
+        self.common_write_32(VIRTIO_PCI_COMMON_GFSELECT, 1);
+        self.common_write_32(VIRTIO_PCI_COMMON_GF, (self.features >> 32) as u32);
+
+        // the device clears FEATURES_OK if it can't work with the features
+        self.set_status(VIRTIO_CONFIG_S_FEATURES_OK);
+        if (self.common_read_8(VIRTIO_PCI_COMMON_STATUS) & VIRTIO_CONFIG_S_FEATURES_OK) == 0 {
+            self.set_status(VIRTIO_CONFIG_S_FAILED);
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} refused features {:#018x}",
+                self.pci_addr, self.features
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Tells the device where the rings of `vq` are and enables it, returns the offset of its
+    /// doorbell in the notification structure, see section 4.1.5.1.3.
+    fn setup_virtqueue(&self, vq: &Virtqueue) -> usize {
+        self.common_write_16(VIRTIO_PCI_COMMON_Q_SELECT, vq.index);
+        self.common_write_16(VIRTIO_PCI_COMMON_Q_SIZE, vq.size);
+        self.common_write_16(VIRTIO_PCI_COMMON_Q_MSIX, VIRTIO_MSI_NO_VECTOR);
+        self.common_write_64(VIRTIO_PCI_COMMON_Q_DESCLO, vq.desc_phys() as u64);
+        self.common_write_64(VIRTIO_PCI_COMMON_Q_AVAILLO, vq.avail_phys() as u64);
+        self.common_write_64(VIRTIO_PCI_COMMON_Q_USEDLO, vq.used_phys() as u64);
+
+        let notify_off = u32::from(self.common_read_16(VIRTIO_PCI_COMMON_Q_NOFF));
+
+        self.common_write_16(VIRTIO_PCI_COMMON_Q_ENABLE, 1);
+
+        (notify_off * self.notify_off_multiplier) as usize
+    }
+
+    /// Tells the device that `vq` has new available buffers.
+    fn notify_queue(&self, vq: &Virtqueue) {
+        if self.removal.is_removed() {
+            return;
+        }
+
+        // the device must see the new index before the notification
+        atomic::fence(Ordering::SeqCst);
+
+        self.bars[self.notify.bar].write_16(self.notify.offset + vq.notify_offset, vq.index);
+    }
+
+    /// Adds `status` to the device status.
+    fn set_status(&self, status: u8) {
+        let current = self.common_read_8(VIRTIO_PCI_COMMON_STATUS);
+        self.common_write_8(VIRTIO_PCI_COMMON_STATUS, current | status);
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Returns the packet counters of the driver summed over all queues.
+    fn read_counters(&self) -> VirtioCounters {
+        let mut counters = VirtioCounters::default();
+
+        for queue in self.rx_queues.iter() {
+            counters.rx_pkts += queue.rx_pkts;
+            counters.rx_bytes += queue.rx_bytes;
+        }
+
+        for queue in self.tx_queues.iter() {
+            counters.tx_pkts += queue.tx_pkts;
+            counters.tx_bytes += queue.tx_bytes;
+        }
+
+        counters
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the byte at `offset` of the common configuration structure.
+    fn common_read_8(&self, offset: usize) -> u8 {
+        self.removal.read(&self.pci_addr, offset, |offset| {
+            self.bars[self.common.bar].read_8(self.common.offset + offset)
+        })
+    }
+
+    /// Returns the word at `offset` of the common configuration structure.
+    fn common_read_16(&self, offset: usize) -> u16 {
+        self.bars[self.common.bar].read_16(self.common.offset + offset)
+    }
+
+    /// Returns the double word at `offset` of the common configuration structure.
+    fn common_read_32(&self, offset: usize) -> u32 {
+        self.bars[self.common.bar].read_32(self.common.offset + offset)
+    }
+
+    /// Writes the byte `value` to `offset` of the common configuration structure.
+    fn common_write_8(&self, offset: usize, value: u8) {
+        if self.removal.is_removed() {
+            return;
+        }
+
+        self.bars[self.common.bar].write_8(self.common.offset + offset, value);
+    }
+
+    /// Writes the word `value` to `offset` of the common configuration structure.
+    fn common_write_16(&self, offset: usize, value: u16) {
+        if self.removal.is_removed() {
+            return;
+        }
+
+        self.bars[self.common.bar].write_16(self.common.offset + offset, value);
+    }
+
+    /// Writes the double word `value` to `offset` of the common configuration structure.
+    fn common_write_32(&self, offset: usize, value: u32) {
+        if self.removal.is_removed() {
+            return;
+        }
+
+        self.bars[self.common.bar].write_32(self.common.offset + offset, value);
+    }
+
+    /// Writes the 64 bit `value` as two double words to `offset` of the common configuration
+    /// structure, the low one first.
+    fn common_write_64(&self, offset: usize, value: u64) {
+        self.common_write_32(offset, value as u32);
+        self.common_write_32(offset + 4, (value >> 32) as u32);
+    }
+
+    /// Returns the byte at `offset` of the device configuration structure.
+    fn device_cfg_read_8(&self, offset: usize) -> u8 {
+        self.bars[self.device_cfg.bar].read_8(self.device_cfg.offset + offset)
+    }
+
+    /// Returns the word at `offset` of the device configuration structure.
+    fn device_cfg_read_16(&self, offset: usize) -> u16 {
+        self.bars[self.device_cfg.bar].read_16(self.device_cfg.offset + offset)
+    }
+
+    /// Returns the double word at `offset` of the device configuration structure.
+    fn device_cfg_read_32(&self, offset: usize) -> u32 {
+        self.bars[self.device_cfg.bar].read_32(self.device_cfg.offset + offset)
+    }
+}
+
+/// BARs and offsets of the configuration structures of a virtio device.
+struct VirtioCaps {
+    common: (u8, u32),
+    notify: (u8, u32),
+    notify_off_multiplier: u32,
+    device: (u8, u32),
+}
+
+/// Returns the locations of the common, notification and device configuration structures,
+/// the first capability of every type is used, see section 4.1.4.
+fn find_virtio_caps(config: &PciDevice) -> Result<VirtioCaps, IxyError> {
+    let mut common = None;
+    let mut notify = None;
+    let mut device = None;
+
+    for (id, cap) in config.capabilities()? {
+        if id != PCI_CAPABILITY_ID_VENDOR {
+            continue;
+        }
+
+        let location = (
+            config.read_config_8(cap + VIRTIO_PCI_CAP_BAR)?,
+            config.read_config_32(cap + VIRTIO_PCI_CAP_OFFSET)?,
+        );
+
+        match config.read_config_8(cap + VIRTIO_PCI_CAP_CFG_TYPE)? {
+            VIRTIO_PCI_CAP_COMMON_CFG if common.is_none() => common = Some(location),
+            VIRTIO_PCI_CAP_NOTIFY_CFG if notify.is_none() => {
+                let multiplier =
+                    config.read_config_32(cap + VIRTIO_PCI_CAP_NOTIFY_OFF_MULTIPLIER)?;
+                notify = Some((location, multiplier));
+            }
+            VIRTIO_PCI_CAP_DEVICE_CFG if device.is_none() => device = Some(location),
+            _ => {}
+        }
+    }
+
+    match (common, notify, device) {
+        (Some(common), Some((notify, notify_off_multiplier)), Some(device)) => Ok(VirtioCaps {
+            common,
+            notify,
+            notify_off_multiplier,
+            device,
+        }),
+        _ => Err(IxyError::InvalidConfiguration(format!(
+            "device {} has no modern virtio-pci capabilities",
+            config.pci_addr()
+        ))),
+    }
+}
+
+/// Returns an error if the device doesn't have the given number of queues.
+fn check_num_queues(num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+    if num_rx_queues > MAX_VIRTIO_QUEUES || num_tx_queues > MAX_VIRTIO_QUEUES {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "cannot configure {} rx and {} tx queues: {} supports a single rx and tx queue",
+            num_rx_queues, num_tx_queues, DRIVER_NAME
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns whether the device asked to be notified at `event` when the index moved from `old`
+/// to `new`, see section 2.6.10.2.
+fn need_event(event: u16, new: u16, old: u16) -> bool {
+    new.wrapping_sub(event).wrapping_sub(1) < new.wrapping_sub(old)
+}
+
+/// Returns the offset of the available ring of a virtqueue of `size` descriptors.
+fn avail_offset(size: usize) -> usize {
+    size * mem::size_of::<VirtqDesc>()
+}
+
+/// Returns the offset of the used ring of a virtqueue of `size` descriptors.
+fn used_offset(size: usize) -> usize {
+    // flags, idx, ring and used_event
+    (avail_offset(size) + 6 + 2 * size).next_multiple_of(VIRTQ_USED_ALIGN)
+}
+
+/// Returns the offset of the tx headers behind the rings of a virtqueue of `size`
+/// descriptors.
+fn headers_offset(size: usize) -> usize {
+    // flags, idx, ring and avail_event
+    (used_offset(size) + 6 + size * mem::size_of::<VirtqUsedElem>())
+        .next_multiple_of(VIRTQ_DESC_ALIGN)
+}
+
+/// Returns the size of the dma memory of a virtqueue of `size` descriptors including the tx
+/// headers. The rings of a packed virtqueue take less memory than those of a split one, so
+/// the memory fits both layouts.
+fn ring_size(size: usize) -> usize {
+    headers_offset(size) + size * VIRTIO_NET_HDR_SIZE
+}
+
+/// Returns an error if `vlan` is no valid VLAN id.
+fn check_vlan_id(vlan: u16) -> Result<(), IxyError> {
+    if vlan > VLAN_ID_MASK {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "invalid VLAN id {}",
+            vlan
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1941 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{self, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::*;
+use crate::vfio::*;
+
+use crate::ixgbe::{
+    allocate_dma, allocate_mempool, dma_size, MAX_RX_BUFFER_SIZE, NUM_RX_QUEUE_ENTRIES,
+    NUM_TX_QUEUE_ENTRIES, PKT_BUF_ENTRY_SIZE, TX_DRAIN_TIMEOUT,
+};
+use crate::pci::{
+    check_privileges, pci_map_resource, pci_numa_node, pci_reset_function, MappedBar, PciDevice,
+    PcieLink, RemovalLatch,
+};
+use crate::vfio::VFIO_PCI_BAR0_REGION_INDEX;
+use crate::DeviceStats;
+use crate::ExtendedStats;
+use crate::FlowControl;
+use crate::IxyDevice;
+use crate::IxyError;
+use crate::PollStrategy;
+use crate::RegisterDump;
+use crate::MAX_QUEUES;
+
+const DRIVER_NAME: &str = "ixy-vmxnet3";
+
+const VMWARE_VENDOR_ID: u16 = 0x15AD;
+const VMXNET3_DEVICE_ID: u16 = 0x07B0;
+
+// BAR0 holds the doorbells of the queues, every register is 8 bytes apart
+const VMXNET3_REG_IMR: u32 = 0x000;
+const VMXNET3_REG_TXPROD: u32 = 0x600;
+const VMXNET3_REG_RXPROD: u32 = 0x800;
+const VMXNET3_REG_RXPROD2: u32 = 0xA00;
+const VMXNET3_REG_ALIGN: u32 = 8;
+
+// BAR1 holds the registers of the virtual device
+const VMXNET3_REG_VRRS: u32 = 0x00;
+const VMXNET3_REG_UVRS: u32 = 0x08;
+const VMXNET3_REG_DSAL: u32 = 0x10;
+const VMXNET3_REG_DSAH: u32 = 0x18;
+const VMXNET3_REG_CMD: u32 = 0x20;
+const VMXNET3_REG_MACL: u32 = 0x28;
+const VMXNET3_REG_MACH: u32 = 0x30;
+const VMXNET3_REG_ECR: u32 = 0x40;
+
+// commands are written to the command register, the result is read back from it
+const VMXNET3_CMD_ACTIVATE_DEV: u32 = 0xCAFE_0000;
+const VMXNET3_CMD_QUIESCE_DEV: u32 = 0xCAFE_0001;
+const VMXNET3_CMD_RESET_DEV: u32 = 0xCAFE_0002;
+const VMXNET3_CMD_UPDATE_RX_MODE: u32 = 0xCAFE_0003;
+const VMXNET3_CMD_GET_QUEUE_STATUS: u32 = 0xF00D_0000;
+const VMXNET3_CMD_GET_STATS: u32 = 0xF00D_0001;
+const VMXNET3_CMD_GET_LINK: u32 = 0xF00D_0002;
+
+const VMXNET3_REV1: u32 = 1 << 0;
+const VMXNET3_UPT_VERSION1: u32 = 1 << 0;
+const VMXNET3_REV1_MAGIC: u32 = 0xBABE_FEE1;
+
+// the device only cares about the guest being a 64 bit linux
+const VMXNET3_GOS_BITS_64: u32 = 2;
+const VMXNET3_GOS_TYPE_LINUX: u32 = 1 << 2;
+const VMXNET3_DRIVER_VERSION: u32 = 1;
+
+const VMXNET3_IC_DISABLE_ALL: u32 = 1 << 0;
+const VMXNET3_IMM_AUTO: u8 = 0;
+const VMXNET3_IMR_MASKED: u32 = 1;
+
+const VMXNET3_RXM_UCAST: u32 = 1 << 0;
+const VMXNET3_RXM_BCAST: u32 = 1 << 2;
+const VMXNET3_RXM_ALL_MULTI: u32 = 1 << 3;
+const VMXNET3_RXM_PROMISC: u32 = 1 << 4;
+
+const VMXNET3_LINK_UP: u32 = 1 << 0;
+const VMXNET3_LINK_SPEED_SHIFT: u32 = 16;
+
+// descriptors are handed over by flipping their generation bit
+const VMXNET3_INIT_GEN: u32 = 1;
+
+const VMXNET3_TXD_LEN_MASK: u32 = 0x3fff;
+const VMXNET3_TXD_GEN_SHIFT: u32 = 14;
+const VMXNET3_TXD_EOP: u32 = 1 << 12;
+const VMXNET3_TXD_CQ: u32 = 1 << 13;
+const VMXNET3_TCD_IDX_MASK: u32 = 0xfff;
+const VMXNET3_TCD_GEN_SHIFT: u32 = 31;
+
+const VMXNET3_RXD_LEN_MASK: u32 = 0x3fff;
+const VMXNET3_RXD_GEN_SHIFT: u32 = 31;
+const VMXNET3_RCD_IDX_MASK: u32 = 0xfff;
+const VMXNET3_RCD_EOP: u32 = 1 << 14;
+const VMXNET3_RCD_SOP: u32 = 1 << 15;
+const VMXNET3_RCD_RSS_TYPE_SHIFT: u32 = 26;
+const VMXNET3_RCD_RSS_TYPE_MASK: u32 = 0xf;
+const VMXNET3_RCD_LEN_MASK: u32 = 0x3fff;
+const VMXNET3_RCD_ERR: u32 = 1 << 14;
+const VMXNET3_RCD_FLAGS_MASK: u32 = 0x00ff_0000;
+const VMXNET3_RCD_GEN_SHIFT: u32 = 31;
+
+const VMXNET3_FAILED_READ_REG: u32 = 0xFFFF_FFFF;
+
+// queues of a revision 1 device
+const MAX_VMXNET3_TX_QUEUES: u16 = 8;
+const MAX_VMXNET3_RX_QUEUES: u16 = 16;
+
+// every tx descriptor has an entry of 128 bytes in the data ring, it is not used by ixy
+const TX_DATA_DESC_SIZE: usize = 128;
+// the second rx ring only takes body buffers of frames spanning several buffers, it is never
+// filled as the mtu is chosen to fit every frame into a single buffer
+const RX_RING2_SIZE: usize = 32;
+// rings have to start at 512 byte boundaries
+const RING_BASE_ALIGN: usize = 512;
+
+// the queue descriptors follow the shared memory area
+const QUEUE_DESC_OFFSET: usize = 1024;
+const QUEUE_DESC_SIZE: usize = 256;
+const SHARED_SIZE: usize =
+    QUEUE_DESC_OFFSET + (MAX_VMXNET3_TX_QUEUES + MAX_VMXNET3_RX_QUEUES) as usize * QUEUE_DESC_SIZE;
+
+const MAX_RX_BUF_SIZE: usize = (1 << 14) - 1;
+// ethernet header and vlan tag on top of the mtu
+const FRAME_OVERHEAD: usize = 18;
+const MIN_MTU: usize = 60;
+const MAX_MTU: usize = 9000;
+const DEFAULT_MTU: usize = 1500;
+
+const TX_MAX_SEGMENTS: usize = 16;
+
+/// Driver for the vmxnet3 paravirtual network card of VMware hypervisors, see the
+/// `vmxnet3_defs.h` header of the Linux driver for the interface.
+///
+/// The driver and the device share their configuration in dma memory, every queue consists of a
+/// command ring and a completion ring whose descriptors are handed over via generation bits.
+/// Interrupts are disabled, all queues are polled.
+pub struct Vmxnet3Device {
+    pci_addr: String,
+    // BAR0 with the doorbells
+    addr: *mut u8,
+    len: usize,
+    // BAR1 with the registers of the virtual device
+    vd: MappedBar,
+    num_rx_queues: u16,
+    num_tx_queues: u16,
+    rx_queues: Vec<Vmxnet3RxQueue>,
+    tx_queues: Vec<Vmxnet3TxQueue>,
+    // the shared memory area followed by the queue descriptors
+    shared: Dma<u8>,
+    vfio: bool,
+    container: Option<Arc<VfioContainer>>,
+    device_fd: RawFd,
+    numa_node: Option<u32>,
+    allocator: Option<Arc<dyn DmaAllocator>>,
+    poll_strategy: PollStrategy,
+    // the counters of the device are not cleared on read
+    counters: Cell<Vmxnet3Counters>,
+    // set once the registers read as all ones, the device is not accessed anymore
+    removal: RemovalLatch<u32>,
+}
+
+/// Values of the statistic counters summed over all queues at their last read.
+#[derive(Clone, Copy, Default)]
+struct Vmxnet3Counters {
+    rx_pkts: u64,
+    tx_pkts: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_broadcast: u64,
+    rx_multicast: u64,
+    tx_broadcast: u64,
+    tx_multicast: u64,
+    rx_no_buffer: u64,
+}
+
+/// Shared memory area of the driver, `Vmxnet3_DriverShared` with the nested structures inlined.
+#[repr(C)]
+#[allow(dead_code)]
+struct DriverShared {
+    magic: u32,
+    pad: u32,
+    // Vmxnet3_MiscConf
+    driver_version: u32,
+    gos: u32,
+    vmxnet3_rev_spt: u32,
+    upt_ver_spt: u32,
+    upt_features: u64,
+    dd_pa: u64,
+    queue_desc_pa: u64,
+    dd_len: u32,
+    queue_desc_len: u32,
+    mtu: u32,
+    max_num_rx_sg: u16,
+    num_tx_queues: u8,
+    num_rx_queues: u8,
+    misc_reserved: [u32; 4],
+    // Vmxnet3_IntrConf
+    auto_mask: u8,
+    num_intrs: u8,
+    event_intr_idx: u8,
+    mod_levels: [u8; 25],
+    intr_ctrl: u32,
+    intr_reserved: [u32; 2],
+    // Vmxnet3_RxFilterConf
+    rx_mode: u32,
+    mf_table_len: u16,
+    pad1: u16,
+    mf_table_pa: u64,
+    vf_table: [u32; 128],
+    // rss, power management and plugin configuration, all unused
+    var_len_conf: [[u64; 2]; 3],
+    ecr: u32,
+    reserved: [u32; 5],
+}
+
+/// Per-queue statistics of a tx queue, `UPT1_TxStats`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct TxStats {
+    tso_pkts: u64,
+    tso_bytes: u64,
+    ucast_pkts: u64,
+    ucast_bytes: u64,
+    mcast_pkts: u64,
+    mcast_bytes: u64,
+    bcast_pkts: u64,
+    bcast_bytes: u64,
+    error_pkts: u64,
+    discard_pkts: u64,
+}
+
+/// Per-queue statistics of an rx queue, `UPT1_RxStats`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(dead_code)]
+struct RxStats {
+    lro_pkts: u64,
+    lro_bytes: u64,
+    ucast_pkts: u64,
+    ucast_bytes: u64,
+    mcast_pkts: u64,
+    mcast_bytes: u64,
+    bcast_pkts: u64,
+    bcast_bytes: u64,
+    out_of_buf_pkts: u64,
+    error_pkts: u64,
+}
+
+/// Control, configuration, status and statistics of a tx queue, `Vmxnet3_TxQueueDesc`.
+#[repr(C)]
+#[allow(dead_code)]
+struct TxQueueDesc {
+    tx_num_deferred: u32,
+    tx_threshold: u32,
+    ctrl_reserved: u64,
+    tx_ring_base_pa: u64,
+    data_ring_base_pa: u64,
+    comp_ring_base_pa: u64,
+    dd_pa: u64,
+    conf_reserved: u64,
+    tx_ring_size: u32,
+    data_ring_size: u32,
+    comp_ring_size: u32,
+    dd_len: u32,
+    intr_idx: u8,
+    pad1: u8,
+    tx_data_ring_desc_size: u16,
+    pad2: [u8; 4],
+    stopped: u8,
+    pad3: [u8; 3],
+    error: u32,
+    stats: TxStats,
+    pad4: [u8; 88],
+}
+
+/// Control, configuration, status and statistics of an rx queue, `Vmxnet3_RxQueueDesc`.
+#[repr(C)]
+#[allow(dead_code)]
+struct RxQueueDesc {
+    update_rx_prod: u8,
+    pad: [u8; 7],
+    ctrl_reserved: u64,
+    rx_ring_base_pa: [u64; 2],
+    comp_ring_base_pa: u64,
+    dd_pa: u64,
+    rx_data_ring_base_pa: u64,
+    rx_ring_size: [u32; 2],
+    comp_ring_size: u32,
+    dd_len: u32,
+    intr_idx: u8,
+    pad1: u8,
+    rx_data_ring_desc_size: u16,
+    pad2: [u8; 4],
+    stopped: u8,
+    pad3: [u8; 3],
+    error: u32,
+    stats: RxStats,
+    pad4: [u8; 88],
+}
+
+/// Tx descriptor, length and generation bit are in `val1`, end of packet and completion
+/// request in `val2`.
+#[repr(C)]
+#[allow(dead_code)]
+struct TxDesc {
+    addr: u64,
+    val1: u32,
+    val2: u32,
+}
+
+/// Tx completion descriptor, the index of the completed descriptor is in `val1`, the
+/// generation bit in `val2`.
+#[repr(C)]
+#[allow(dead_code)]
+struct TxCompDesc {
+    val1: u32,
+    ext2: u32,
+    ext3: u32,
+    val2: u32,
+}
+
+/// Rx descriptor, buffer length and generation bit are in `val1`.
+#[repr(C)]
+#[allow(dead_code)]
+struct RxDesc {
+    addr: u64,
+    val1: u32,
+    ext1: u32,
+}
+
+/// Rx completion descriptor with the index of the descriptor and the start and end of packet
+/// bits in `val1`, length and error bit in `val2`, offload flags and generation bit in `val3`.
+#[repr(C)]
+#[allow(dead_code)]
+struct RxCompDesc {
+    val1: u32,
+    rss_hash: u32,
+    val2: u32,
+    val3: u32,
+}
+
+struct Vmxnet3RxQueue {
+    descriptors: *mut RxDesc,
+    completions: *mut RxCompDesc,
+    // keeps both rings and the completion ring mapped
+    ring: Dma<u8>,
+    num_descriptors: usize,
+    pool: Arc<Mempool>,
+    bufs_in_use: Vec<usize>,
+    rx_index: usize,
+    rx_fill: usize,
+    fill_gen: u32,
+    comp_index: usize,
+    comp_gen: u32,
+    num_posted: usize,
+}
+
+impl Vmxnet3RxQueue {
+    /// Returns a queue on the rings in `ring` that receives into buffers of `pool`.
+    fn new(ring: Dma<u8>, pool: Arc<Mempool>) -> Vmxnet3RxQueue {
+        Vmxnet3RxQueue {
+            descriptors: ring.virt as *mut RxDesc,
+            completions: (ring.virt as usize + rx_comp_ring_offset()) as *mut RxCompDesc,
+            ring,
+            num_descriptors: NUM_RX_QUEUE_ENTRIES,
+            pool,
+            bufs_in_use: Vec::with_capacity(NUM_RX_QUEUE_ENTRIES),
+            rx_index: 0,
+            rx_fill: 0,
+            fill_gen: VMXNET3_INIT_GEN,
+            comp_index: 0,
+            comp_gen: VMXNET3_INIT_GEN,
+            num_posted: NUM_RX_QUEUE_ENTRIES - 1,
+        }
+    }
+
+    /// Attaches a buffer of the mempool to every descriptor, the descriptors are posted once the
+    /// ring is rewound.
+    fn fill(&mut self) -> Result<(), IxyError> {
+        for _ in 0..self.num_descriptors {
+            let buf = self.pool.alloc_buf().ok_or(IxyError::PoolExhausted)?;
+
+            // we need to remember which descriptor entry belongs to which mempool entry
+            self.bufs_in_use.push(buf);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the ring back to the start the device expects after activation and posts
+    /// `num_posted` descriptors.
+    fn rewind(&mut self) {
+        unsafe {
+            memset(self.ring.virt, rx_ring_size(), 0);
+        }
+
+        let buf_len = self.buf_len() as u32;
+        for (i, &buf) in self.bufs_in_use.iter().enumerate() {
+            unsafe {
+                let desc = self.descriptors.add(i);
+                ptr::write_volatile(
+                    &mut (*desc).addr,
+                    (self.pool.get_phys_addr(buf) as u64).to_le(),
+                );
+                ptr::write_volatile(&mut (*desc).val1, buf_len.to_le());
+            }
+        }
+
+        self.rx_index = 0;
+        self.rx_fill = 0;
+        self.fill_gen = VMXNET3_INIT_GEN;
+        self.comp_index = 0;
+        self.comp_gen = VMXNET3_INIT_GEN;
+
+        self.post_to(self.num_posted);
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer` and refills their
+    /// descriptors. Returns the number of received packets and the new producer index of the
+    /// ring if refilled descriptors were handed back to the device.
+    fn receive(
+        &mut self,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> (usize, Option<usize>) {
+        let mut rx_index = self.rx_index;
+        let mut received_packets = 0;
+
+        {
+            // lock the pool once for the whole batch
+            let mut free_stack = self.pool.free_stack();
+
+            while received_packets < num_packets {
+                let comp = unsafe { self.completions.add(self.comp_index) };
+                let val3 = u32::from_le(unsafe { ptr::read_volatile(&(*comp).val3) });
+
+                if (val3 >> VMXNET3_RCD_GEN_SHIFT) != self.comp_gen {
+                    break;
+                }
+
+                // the completion stays pending until its descriptor can be refilled without
+                // the buffers reserved for clones
+                if free_stack.available() < 1 {
+                    break;
+                }
+
+                let (val1, val2, rss_hash) = unsafe {
+                    (
+                        u32::from_le(ptr::read_volatile(&(*comp).val1)),
+                        u32::from_le(ptr::read_volatile(&(*comp).val2)),
+                        u32::from_le(ptr::read_volatile(&(*comp).rss_hash)),
+                    )
+                };
+
+                self.comp_index += 1;
+                if self.comp_index == rx_comp_ring_entries() {
+                    self.comp_index = 0;
+                    self.comp_gen ^= 1;
+                }
+
+                // only the first ring is posted, its descriptors complete in order
+                let index = (val1 & VMXNET3_RCD_IDX_MASK) as usize;
+                rx_index = (index + 1) % self.num_descriptors;
+
+                // frames never span several buffers, the device drops them with the error bit
+                // set, the buffer is posted again as is
+                if (val2 & VMXNET3_RCD_ERR) != 0
+                    || (val1 & (VMXNET3_RCD_SOP | VMXNET3_RCD_EOP))
+                        != (VMXNET3_RCD_SOP | VMXNET3_RCD_EOP)
+                {
+                    continue;
+                }
+
+                let pool = &self.pool;
+
+                // replace currently used buffer with a free buffer of the mempool
+                let new_buf = free_stack.pop().expect("no buffer available");
+                let buf = mem::replace(&mut self.bufs_in_use[index], new_buf);
+
+                let rss_type = (val1 >> VMXNET3_RCD_RSS_TYPE_SHIFT) & VMXNET3_RCD_RSS_TYPE_MASK;
+
+                #[allow(unused_mut)]
+                let mut p = unsafe {
+                    Packet {
+                        addr_virt: pool.get_virt_addr(buf),
+                        addr_phys: pool.get_phys_addr(buf),
+                        len: (val2 & VMXNET3_RCD_LEN_MASK) as usize,
+                        pool: pool.clone(),
+                        pool_entry: buf,
+                        next: None,
+                        meta: PacketMeta {
+                            rss_hash: if rss_type != 0 { Some(rss_hash) } else { None },
+                            packet_type: rss_type,
+                            offload_flags: val3 & VMXNET3_RCD_FLAGS_MASK,
+                            ..PacketMeta::default()
+                        },
+                    }
+                };
+
+                unsafe {
+                    ptr::write_volatile(
+                        &mut (*self.descriptors.add(index)).addr,
+                        (pool.get_phys_addr(new_buf) as u64).to_le(),
+                    );
+                }
+
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "sse"
+                ))]
+                p.prefetch(Prefetch::Time1);
+
+                buffer.push_back(p);
+                received_packets += 1;
+            }
+        }
+
+        let mut prod = None;
+
+        if rx_index != self.rx_index {
+            self.rx_index = rx_index;
+
+            // hand refilled descriptors back to the device, at most num_posted at a time
+            let posted = (self.rx_fill + self.num_descriptors - rx_index) % self.num_descriptors;
+            if posted < self.num_posted {
+                self.post_to((rx_index + self.num_posted) % self.num_descriptors);
+                prod = Some(self.rx_fill);
+            }
+        }
+
+        (received_packets, prod)
+    }
+
+    /// Returns the content of the next received packet without taking it off the ring.
+    fn peek(&self) -> Option<&[u8]> {
+        if self.bufs_in_use.is_empty() {
+            return None;
+        }
+
+        unsafe {
+            let comp = self.completions.add(self.comp_index);
+            let val3 = u32::from_le(ptr::read_volatile(&(*comp).val3));
+            if (val3 >> VMXNET3_RCD_GEN_SHIFT) != self.comp_gen {
+                return None;
+            }
+
+            let val1 = u32::from_le(ptr::read_volatile(&(*comp).val1));
+            let val2 = u32::from_le(ptr::read_volatile(&(*comp).val2));
+            if (val2 & VMXNET3_RCD_ERR) != 0 {
+                return None;
+            }
+
+            let index = (val1 & VMXNET3_RCD_IDX_MASK) as usize;
+            let len = (val2 & VMXNET3_RCD_LEN_MASK) as usize;
+            let addr = self.pool.get_virt_addr(self.bufs_in_use[index]);
+
+            Some(slice::from_raw_parts(addr, len))
+        }
+    }
+
+    /// Limits the descriptors the device may receive into to `num_posted`, returns the new
+    /// producer index if more descriptors were handed to the device right away.
+    fn set_posted(&mut self, num_posted: usize) -> Result<Option<usize>, IxyError> {
+        if num_posted >= self.num_descriptors {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot post {} descriptors: ring of {} descriptors holds at most {}",
+                num_posted,
+                self.num_descriptors,
+                self.num_descriptors - 1
+            )));
+        }
+
+        self.num_posted = num_posted;
+
+        // posted descriptors can't be taken back, lowering the limit takes effect as packets
+        // arrive
+        let posted = (self.rx_fill + self.num_descriptors - self.rx_index) % self.num_descriptors;
+        if posted >= num_posted || self.bufs_in_use.is_empty() {
+            return Ok(None);
+        }
+
+        self.post_to((self.rx_index + num_posted) % self.num_descriptors);
+
+        Ok(Some(self.rx_fill))
+    }
+
+    /// Hands the descriptors up to `index` to the device by flipping their generation bits.
+    fn post_to(&mut self, index: usize) {
+        let buf_len = self.buf_len() as u32;
+
+        while self.rx_fill != index {
+            unsafe {
+                ptr::write_volatile(
+                    &mut (*self.descriptors.add(self.rx_fill)).val1,
+                    ((buf_len & VMXNET3_RXD_LEN_MASK) | (self.fill_gen << VMXNET3_RXD_GEN_SHIFT))
+                        .to_le(),
+                );
+            }
+
+            self.rx_fill += 1;
+            if self.rx_fill == self.num_descriptors {
+                self.rx_fill = 0;
+                self.fill_gen ^= 1;
+            }
+        }
+    }
+
+    /// Returns all buffers of the ring to the mempool, the device must not access them anymore.
+    fn drain(&mut self) {
+        for buf in self.bufs_in_use.drain(..) {
+            self.pool.free_buf(buf);
+        }
+
+        // clear the generation bits so rx_batch doesn't pick up the freed buffers
+        unsafe {
+            memset(self.ring.virt, rx_ring_size(), 0);
+        }
+
+        self.rx_index = 0;
+        self.rx_fill = 0;
+        self.comp_index = 0;
+    }
+
+    /// Returns the size of the buffers handed to the device, i.e. the data area of the
+    /// mempool's entries without the headroom.
+    fn buf_len(&self) -> usize {
+        (self.pool.entry_size() - self.pool.headroom()).min(MAX_RX_BUF_SIZE)
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<RxDesc>(),
+        )
+    }
+}
+
+struct Vmxnet3TxQueue {
+    descriptors: *mut TxDesc,
+    completions: *mut TxCompDesc,
+    // keeps the ring, the completion ring and the data ring mapped
+    ring: Dma<u8>,
+    num_descriptors: usize,
+    pool: Option<Arc<Mempool>>,
+    bufs_in_use: VecDeque<usize>,
+    clean_index: usize,
+    tx_index: usize,
+    tx_gen: u32,
+    comp_index: usize,
+    comp_gen: u32,
+    watchdog_clean: usize,
+    watchdog_time: Instant,
+}
+
+impl Vmxnet3TxQueue {
+    /// Returns an empty queue on the rings in `ring`.
+    fn new(ring: Dma<u8>) -> Vmxnet3TxQueue {
+        Vmxnet3TxQueue {
+            descriptors: ring.virt as *mut TxDesc,
+            completions: (ring.virt as usize + tx_comp_ring_offset()) as *mut TxCompDesc,
+            ring,
+            num_descriptors: NUM_TX_QUEUE_ENTRIES,
+            pool: None,
+            bufs_in_use: VecDeque::with_capacity(NUM_TX_QUEUE_ENTRIES),
+            clean_index: 0,
+            tx_index: 0,
+            tx_gen: VMXNET3_INIT_GEN,
+            comp_index: 0,
+            comp_gen: VMXNET3_INIT_GEN,
+            watchdog_clean: 0,
+            watchdog_time: Instant::now(),
+        }
+    }
+
+    /// Pops as many packets as fit into the ring from `packets` and hands their descriptors to
+    /// the device, it only looks at them once the producer index is moved to `tail`.
+    fn send(&mut self, packets: &mut VecDeque<Packet>) -> usize {
+        let mut sent = 0;
+        let clean_index = self.clean();
+
+        if self.pool.is_none() {
+            if let Some(packet) = packets.front() {
+                self.pool = Some(packet.pool.clone());
+            }
+        }
+
+        while let Some(packet) = packets.pop_front() {
+            let num_segments = packet.num_segments();
+
+            assert!(
+                packet
+                    .segments()
+                    .all(|s| Arc::ptr_eq(self.pool.as_ref().unwrap(), &s.pool)),
+                "distinct memory pools for a single tx queue are not supported yet"
+            );
+            assert!(
+                num_segments <= TX_MAX_SEGMENTS,
+                "packet of {} segments exceeds the limit of {}",
+                num_segments,
+                TX_MAX_SEGMENTS
+            );
+
+            // one descriptor always stays empty to tell a full ring from an empty one
+            let free =
+                (clean_index + self.num_descriptors - self.tx_index - 1) % self.num_descriptors;
+
+            if free < num_segments {
+                // tx queue of device is full, push packet back onto the
+                // queue of to-be-sent packets
+                packets.push_front(packet);
+                break;
+            }
+
+            let sop = self.tx_index;
+            let mut segment = Some(packet);
+
+            while let Some(mut p) = segment {
+                segment = p.unchain();
+
+                // the first descriptor is handed over last, the device must not see a
+                // partially written packet
+                let gen = if self.tx_index == sop {
+                    self.tx_gen ^ 1
+                } else {
+                    self.tx_gen
+                };
+
+                // a completion for the last descriptor returns the whole packet
+                let val2 = if segment.is_none() {
+                    VMXNET3_TXD_EOP | VMXNET3_TXD_CQ
+                } else {
+                    0
+                };
+
+                unsafe {
+                    write_tx_desc(
+                        self.descriptors.add(self.tx_index),
+                        p.get_phys_addr(),
+                        p.len(),
+                        gen,
+                        val2,
+                    );
+                }
+
+                self.bufs_in_use.push_back(p.pool_entry);
+                mem::forget(p);
+
+                self.tx_index += 1;
+                if self.tx_index == self.num_descriptors {
+                    self.tx_index = 0;
+                    self.tx_gen ^= 1;
+                }
+            }
+
+            atomic::fence(Ordering::Release);
+
+            unsafe {
+                let desc = self.descriptors.add(sop);
+                let val1 = u32::from_le(ptr::read_volatile(&(*desc).val1));
+                ptr::write_volatile(
+                    &mut (*desc).val1,
+                    (val1 ^ (1 << VMXNET3_TXD_GEN_SHIFT)).to_le(),
+                );
+            }
+
+            sent += 1;
+        }
+
+        sent
+    }
+
+    /// Returns the buffers of all completed packets to their mempool and returns the new clean
+    /// index.
+    fn clean(&mut self) -> usize {
+        loop {
+            let comp = unsafe { self.completions.add(self.comp_index) };
+            let val2 = u32::from_le(unsafe { ptr::read_volatile(&(*comp).val2) });
+
+            if (val2 >> VMXNET3_TCD_GEN_SHIFT) != self.comp_gen {
+                break;
+            }
+
+            // the completion names the last descriptor of the packet
+            let eop = (u32::from_le(unsafe { ptr::read_volatile(&(*comp).val1) })
+                & VMXNET3_TCD_IDX_MASK) as usize;
+            let cleaned =
+                (eop + self.num_descriptors - self.clean_index) % self.num_descriptors + 1;
+
+            if let Some(ref pool) = self.pool {
+                pool.free_bufs(self.bufs_in_use.drain(..cleaned));
+            }
+
+            self.clean_index = (eop + 1) % self.num_descriptors;
+
+            self.comp_index += 1;
+            if self.comp_index == self.num_descriptors {
+                self.comp_index = 0;
+                self.comp_gen ^= 1;
+            }
+        }
+
+        self.clean_index
+    }
+
+    /// Returns the index after the last written descriptor, i.e. the producer index for the
+    /// device.
+    fn tail(&self) -> usize {
+        self.tx_index
+    }
+
+    /// Returns whether the ring is empty or the device completed packets within `timeout`.
+    fn healthy(&mut self, timeout: Duration) -> bool {
+        let clean_index = self.clean();
+
+        if clean_index == self.tx_index || clean_index != self.watchdog_clean {
+            self.watchdog_clean = clean_index;
+            self.watchdog_time = Instant::now();
+
+            return true;
+        }
+
+        self.watchdog_time.elapsed() < timeout
+    }
+
+    /// Drops all pending packets and rewinds the ring to the start the device expects after
+    /// activation, the device must not access it anymore.
+    fn reset(&mut self) {
+        if let Some(ref pool) = self.pool {
+            for buf in self.bufs_in_use.drain(..) {
+                pool.free_buf(buf);
+            }
+        }
+
+        unsafe {
+            memset(self.ring.virt, tx_ring_size(), 0);
+        }
+
+        self.clean_index = 0;
+        self.tx_index = 0;
+        self.tx_gen = VMXNET3_INIT_GEN;
+        self.comp_index = 0;
+        self.comp_gen = VMXNET3_INIT_GEN;
+        self.watchdog_clean = 0;
+        self.watchdog_time = Instant::now();
+    }
+
+    /// Returns the physical address and the size in bytes of the descriptor ring.
+    fn ring_phys(&self) -> (usize, usize) {
+        (
+            self.ring.phys,
+            self.num_descriptors * mem::size_of::<TxDesc>(),
+        )
+    }
+}
+
+impl IxyDevice for Vmxnet3Device {
+    /// Returns an initialized `Vmxnet3Device` on success.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn init(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<Vmxnet3Device, IxyError> {
+        Vmxnet3Device::init_with_allocator(pci_addr, num_rx_queues, num_tx_queues, None, None)
+    }
+
+    /// Returns the driver's name of this device.
+    fn get_driver_name(&self) -> &str {
+        DRIVER_NAME
+    }
+
+    /// Returns the card's iommu capability, VFIO without an IOMMU doesn't count.
+    fn is_card_iommu_capable(&self) -> bool {
+        self.container
+            .as_ref()
+            .is_some_and(|container| container.uses_iommu())
+    }
+
+    /// Returns VFIO container file descriptor or [`None`] if IOMMU is not available.
+    fn get_vfio_container(&self) -> Option<RawFd> {
+        self.container
+            .as_ref()
+            .map(|container| container.as_raw_fd())
+    }
+
+    /// Returns the pci address of this device.
+    fn get_pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    fn map_bar(&self, bar: u8) -> Result<MappedBar, IxyError> {
+        if self.vfio {
+            MappedBar::map_vfio(self.device_fd, bar)
+        } else {
+            MappedBar::map(&self.pci_addr, bar)
+        }
+    }
+
+    /// Returns the mac address of this device.
+    fn get_mac_addr(&self) -> [u8; 6] {
+        let low = self.get_reg32(VMXNET3_REG_MACL).to_le_bytes();
+        let high = self.get_reg32(VMXNET3_REG_MACH).to_le_bytes();
+
+        [low[0], low[1], low[2], low[3], high[0], high[1]]
+    }
+
+    /// Sets the mac address of this device.
+    fn set_mac_addr(&self, mac: [u8; 6]) {
+        let low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+        let high = u32::from_le_bytes([mac[4], mac[5], 0, 0]);
+
+        self.set_reg32(VMXNET3_REG_MACL, low);
+        self.set_reg32(VMXNET3_REG_MACH, high);
+    }
+
+    /// Pushes up to `num_packets` received `Packet`s onto `buffer`.
+    fn rx_batch(
+        &mut self,
+        queue_id: u32,
+        buffer: &mut VecDeque<Packet>,
+        num_packets: usize,
+    ) -> usize {
+        let (received, prod) = self.rx_queues[queue_id as usize].receive(buffer, num_packets);
+
+        // the device asks for producer updates only if it ran out of descriptors
+        if let Some(prod) = prod {
+            let desc = self.rx_queue_desc(queue_id as u16);
+            if unsafe { ptr::read_volatile(&(*desc).update_rx_prod) } != 0 {
+                self.set_rx_prod(queue_id as u16, prod);
+            }
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "rx queue {}: received {} of {} packets",
+            queue_id,
+            received,
+            num_packets
+        );
+
+        received
+    }
+
+    /// Returns whether the device was removed, i.e. its revision register reads as all ones.
+    fn is_removed(&self) -> bool {
+        self.get_reg32(VMXNET3_REG_VRRS) == VMXNET3_FAILED_READ_REG
+    }
+
+    fn set_poll_strategy(&mut self, strategy: PollStrategy) {
+        self.poll_strategy = strategy;
+    }
+
+    fn get_poll_strategy(&self) -> PollStrategy {
+        self.poll_strategy
+    }
+
+    /// Returns the content of the next received packet without removing it from the rx queue.
+    fn rx_peek(&self, queue_id: u32) -> Option<&[u8]> {
+        self.rx_queues[queue_id as usize].peek()
+    }
+
+    /// Sets the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn set_rx_posted(&mut self, queue_id: u32, num_posted: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if let Some(prod) = self.rx_queues[queue_id as usize].set_posted(num_posted)? {
+            self.set_rx_prod(queue_id as u16, prod);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of descriptors of rx queue `queue_id` the device may receive into.
+    fn get_rx_posted(&self, queue_id: u32) -> usize {
+        self.rx_queues[queue_id as usize].num_posted
+    }
+
+    /// Pops as many packets as possible from `packets` to put them into the device`s tx queue.
+    fn tx_batch(&mut self, queue_id: u32, packets: &mut VecDeque<Packet>) -> usize {
+        let sent = self.tx_queues[queue_id as usize].send(packets);
+
+        // all descriptors of the batch are written, ring the doorbell once for the whole batch
+        if sent > 0 {
+            self.set_pt_reg32(
+                VMXNET3_REG_TXPROD + VMXNET3_REG_ALIGN * queue_id,
+                self.tx_queues[queue_id as usize].tail() as u32,
+            );
+        }
+
+        #[cfg(feature = "trace-batches")]
+        trace!(
+            "tx queue {}: sent {} of {} packets",
+            queue_id,
+            sent,
+            sent + packets.len()
+        );
+
+        sent
+    }
+
+    /// Reads the stats of this device into `stats`.
+    fn read_stats(&self, stats: &mut DeviceStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_pkts += current.rx_pkts.wrapping_sub(last.rx_pkts);
+        stats.tx_pkts += current.tx_pkts.wrapping_sub(last.tx_pkts);
+        stats.rx_bytes += current.rx_bytes.wrapping_sub(last.rx_bytes);
+        stats.tx_bytes += current.tx_bytes.wrapping_sub(last.tx_bytes);
+
+        // the other counters belong to the extended stats
+        self.counters.set(Vmxnet3Counters {
+            rx_pkts: current.rx_pkts,
+            tx_pkts: current.tx_pkts,
+            rx_bytes: current.rx_bytes,
+            tx_bytes: current.tx_bytes,
+            ..last
+        });
+    }
+
+    /// Resets the stats of this device.
+    fn reset_stats(&self) {
+        self.counters.set(self.read_counters());
+    }
+
+    /// Reads the extended stats of this device into `stats`, the virtual device counts no
+    /// errors of the physical layer and no pause frames.
+    fn read_extended_stats(&self, stats: &mut ExtendedStats) {
+        let last = self.counters.get();
+        let current = self.read_counters();
+
+        stats.rx_broadcast += current.rx_broadcast.wrapping_sub(last.rx_broadcast);
+        stats.rx_multicast += current.rx_multicast.wrapping_sub(last.rx_multicast);
+        stats.tx_broadcast += current.tx_broadcast.wrapping_sub(last.tx_broadcast);
+        stats.tx_multicast += current.tx_multicast.wrapping_sub(last.tx_multicast);
+        stats.rx_no_buffer += current.rx_no_buffer.wrapping_sub(last.rx_no_buffer);
+
+        self.counters.set(Vmxnet3Counters {
+            rx_pkts: last.rx_pkts,
+            tx_pkts: last.tx_pkts,
+            rx_bytes: last.rx_bytes,
+            tx_bytes: last.tx_bytes,
+            ..current
+        });
+    }
+
+    /// Returns the link speed the hypervisor reports for this device.
+    fn get_link_speed(&self) -> u32 {
+        let link = self.command(VMXNET3_CMD_GET_LINK);
+        if (link & VMXNET3_LINK_UP) == 0 || link == VMXNET3_FAILED_READ_REG {
+            return 0;
+        }
+
+        link >> VMXNET3_LINK_SPEED_SHIFT
+    }
+
+    /// Returns the PCIe link of this device as emulated by the hypervisor.
+    fn get_pcie_link(&self) -> Result<Option<PcieLink>, IxyError> {
+        PciDevice::open(&self.pci_addr)?.pcie_link()
+    }
+
+    /// Changes the number of rx and tx queues of this device, the device is reactivated and
+    /// drops the packets pending in the tx queues.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    fn reconfigure_queues(
+        &mut self,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+    ) -> Result<(), IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        info!(
+            "reconfiguring device {} to {} rx and {} tx queues",
+            self.pci_addr, num_rx_queues, num_tx_queues
+        );
+
+        // the device only reads the number of queues on activation
+        self.quiesce();
+
+        if self.num_rx_queues > num_rx_queues {
+            self.num_rx_queues = num_rx_queues;
+            for mut queue in self.rx_queues.drain(num_rx_queues as usize..) {
+                queue.drain();
+            }
+        }
+
+        if self.num_tx_queues > num_tx_queues {
+            self.num_tx_queues = num_tx_queues;
+            for mut queue in self.tx_queues.drain(num_tx_queues as usize..) {
+                queue.reset();
+            }
+        }
+
+        for queue_id in self.num_rx_queues..num_rx_queues {
+            self.init_rx_queue(queue_id)?;
+        }
+        self.num_rx_queues = num_rx_queues;
+
+        for queue_id in self.num_tx_queues..num_tx_queues {
+            self.init_tx_queue(queue_id)?;
+        }
+        self.num_tx_queues = num_tx_queues;
+
+        self.start()
+    }
+
+    /// The queues of the vmxnet3 are enabled as long as the device is active.
+    fn enable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if self.rx_queues[queue_id as usize].bufs_in_use.is_empty() {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} has been drained",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// The vmxnet3 can only stop all queues at once.
+    fn disable_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether rx queue `queue_id` has buffers and was not stopped by the device.
+    fn is_rx_queue_enabled(&self, queue_id: u32) -> bool {
+        if queue_id >= u32::from(self.num_rx_queues)
+            || self.rx_queues[queue_id as usize].bufs_in_use.is_empty()
+        {
+            return false;
+        }
+
+        self.command(VMXNET3_CMD_GET_QUEUE_STATUS);
+        let desc = self.rx_queue_desc(queue_id as u16);
+
+        unsafe { ptr::read_volatile(&(*desc).stopped) == 0 }
+    }
+
+    /// The queues of the vmxnet3 are enabled as long as the device is active.
+    fn enable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)
+    }
+
+    /// The vmxnet3 can only stop all queues at once.
+    fn disable_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Err(IxyError::InvalidConfiguration(format!(
+            "{} cannot disable single queues",
+            DRIVER_NAME
+        )))
+    }
+
+    /// Returns whether tx queue `queue_id` was not stopped by the device.
+    fn is_tx_queue_enabled(&self, queue_id: u32) -> bool {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return false;
+        }
+
+        self.command(VMXNET3_CMD_GET_QUEUE_STATUS);
+        let desc = self.tx_queue_desc(queue_id as u16);
+
+        unsafe { ptr::read_volatile(&(*desc).stopped) == 0 }
+    }
+
+    /// Returns whether tx queue `queue_id` is empty or the device completed packets within
+    /// `timeout`.
+    fn tx_healthy(&mut self, queue_id: u32, timeout: Duration) -> Result<bool, IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        Ok(self.tx_queues[queue_id as usize].healthy(timeout))
+    }
+
+    /// Drops all pending packets of tx queue `queue_id`. The device can only restart all queues
+    /// at once, so the pending packets of the other tx queues are dropped as well.
+    fn reset_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        warn!(
+            "resetting tx queue {} of device {}",
+            queue_id, self.pci_addr
+        );
+
+        self.quiesce();
+        self.start()
+    }
+
+    /// Returns all buffers of rx queue `queue_id` to the mempool, the device is reactivated
+    /// without them.
+    fn drain_rx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        self.quiesce();
+        self.rx_queues[queue_id as usize].drain();
+
+        self.start()
+    }
+
+    /// Waits until tx queue `queue_id` is empty and returns all sent buffers to their mempool.
+    fn drain_tx_queue(&mut self, queue_id: u32) -> Result<(), IxyError> {
+        self.check_tx_queue(queue_id)?;
+
+        let start = Instant::now();
+
+        // every packet is completed once the device processed it
+        let queue = &mut self.tx_queues[queue_id as usize];
+        while queue.clean() != queue.tail() {
+            if start.elapsed() > TX_DRAIN_TIMEOUT {
+                return self.reset_tx_queue(queue_id);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        Ok(())
+    }
+
+    /// The vmxnet3 always drops packets when an rx queue has no free descriptor.
+    fn set_rx_drop_enable(&self, queue_id: u32, enable: bool) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "{} always drops packets of a full rx queue",
+                DRIVER_NAME
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Flow control is a setting of the physical port, only the hypervisor can change it.
+    fn set_flow_control(&self, mode: FlowControl) {
+        warn!(
+            "ignoring flow control mode {:?} of {}: it is set by the hypervisor",
+            mode, self.pci_addr
+        );
+    }
+
+    /// Returns [`FlowControl::None`], the virtual device has no pause frames.
+    fn get_flow_control(&self) -> FlowControl {
+        FlowControl::None
+    }
+
+    /// The vmxnet3 never passes the CRC to the driver.
+    fn set_crc_strip(&mut self, enable: bool) -> Result<(), IxyError> {
+        if !enable {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "cannot keep the crc on {}: the virtual device has none",
+                self.pci_addr
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns true, received frames never carry a CRC.
+    fn get_crc_strip(&self) -> bool {
+        true
+    }
+
+    /// Drains rx queue `queue_id` and restarts the device with a new mempool of `buffer_size`
+    /// bytes for the queue. The mtu is raised to fit frames into the smallest buffer of all rx
+    /// queues.
+    fn set_rx_buffer_size(&mut self, queue_id: u32, buffer_size: usize) -> Result<(), IxyError> {
+        self.check_rx_queue(queue_id)?;
+
+        if buffer_size == 0 || buffer_size > MAX_RX_BUFFER_SIZE {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx buffer size {} is not between 1 and {} bytes",
+                buffer_size, MAX_RX_BUFFER_SIZE
+            )));
+        }
+
+        let pool = allocate_mempool(self.allocator.as_ref(), self.numa_node, buffer_size)?;
+
+        self.quiesce();
+
+        let queue = &mut self.rx_queues[queue_id as usize];
+        queue.drain();
+        queue.pool = pool;
+        queue.fill()?;
+
+        self.start()
+    }
+
+    /// The virtual device has no EEPROM.
+    fn read_eeprom_word(&self, _offset: u16) -> u16 {
+        0
+    }
+
+    fn get_firmware_version(&self) -> u32 {
+        0
+    }
+
+    fn validate_eeprom_checksum(&self) -> bool {
+        true
+    }
+
+    /// The virtual device has no thermal sensor.
+    fn get_temperature(&self) -> Option<i16> {
+        None
+    }
+
+    fn rx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.rx_queues[queue_id as usize].ring_phys()
+    }
+
+    fn tx_ring_phys(&self, queue_id: u32) -> (usize, usize) {
+        self.tx_queues[queue_id as usize].ring_phys()
+    }
+
+    /// Reads the registers of the virtual device and the state of the queues.
+    fn dump_registers(&self) -> RegisterDump {
+        let mut dump = RegisterDump::default();
+
+        let global = [
+            ("VRRS", VMXNET3_REG_VRRS),
+            ("UVRS", VMXNET3_REG_UVRS),
+            ("DSAL", VMXNET3_REG_DSAL),
+            ("DSAH", VMXNET3_REG_DSAH),
+            ("MACL", VMXNET3_REG_MACL),
+            ("MACH", VMXNET3_REG_MACH),
+            ("ECR", VMXNET3_REG_ECR),
+        ];
+
+        for &(name, reg) in global.iter() {
+            dump.push(name, self.get_reg32(reg));
+        }
+
+        dump.push("LINK", self.command(VMXNET3_CMD_GET_LINK));
+
+        // the doorbells can't be read back, the queue status lives in the shared memory
+        self.command(VMXNET3_CMD_GET_QUEUE_STATUS);
+
+        for i in 0..self.num_tx_queues {
+            let desc = self.tx_queue_desc(i);
+            let (stopped, error) = unsafe {
+                (
+                    ptr::read_volatile(&(*desc).stopped),
+                    ptr::read_volatile(&(*desc).error),
+                )
+            };
+            dump.push(format!("TXQ_STOPPED[{}]", i), u32::from(stopped));
+            dump.push(format!("TXQ_ERROR[{}]", i), u32::from_le(error));
+        }
+
+        for i in 0..self.num_rx_queues {
+            let desc = self.rx_queue_desc(i);
+            let (stopped, error) = unsafe {
+                (
+                    ptr::read_volatile(&(*desc).stopped),
+                    ptr::read_volatile(&(*desc).error),
+                )
+            };
+            dump.push(format!("RXQ_STOPPED[{}]", i), u32::from(stopped));
+            dump.push(format!("RXQ_ERROR[{}]", i), u32::from_le(error));
+        }
+
+        dump
+    }
+
+    /// Resets this device with a function-level reset via VFIO or sysfs and initializes it
+    /// again.
+    fn reset(&mut self) -> Result<(), IxyError> {
+        if self.vfio {
+            vfio_reset_device(self.device_fd)?;
+        } else {
+            pci_reset_function(&self.pci_addr)?;
+        }
+
+        // the reset stopped all dma, so the rings and the buffers in them can be dropped
+        self.rx_queues.clear();
+        self.tx_queues.clear();
+
+        self.reset_and_init()
+    }
+}
+
+impl Drop for Vmxnet3Device {
+    fn drop(&mut self) {
+        // stop all dma before the rings, the shared memory and the mempools are unmapped
+        self.command(VMXNET3_CMD_QUIESCE_DEV);
+        self.command(VMXNET3_CMD_RESET_DEV);
+    }
+}
+
+impl Vmxnet3Device {
+    /// Returns whether this driver supports the device with the given ids.
+    pub(crate) fn supports(vendor_id: u16, device_id: u16) -> bool {
+        vendor_id == VMWARE_VENDOR_ID && device_id == VMXNET3_DEVICE_ID
+    }
+
+    /// Returns an initialized `Vmxnet3Device` whose dma memory is allocated by `allocator`, or
+    /// placed on the device's numa node if [`None`].
+    ///
+    /// A device bound to vfio-pci is added to `container`, or the shared container if
+    /// [`None`]. Without an `allocator` the memory of a device with its own container is
+    /// allocated in that container.
+    ///
+    /// # Panics
+    /// Panics if `num_rx_queues` or `num_tx_queues` exceeds `MAX_QUEUES`.
+    pub(crate) fn init_with_allocator(
+        pci_addr: &str,
+        num_rx_queues: u16,
+        num_tx_queues: u16,
+        allocator: Option<Arc<dyn DmaAllocator>>,
+        container: Option<Arc<VfioContainer>>,
+    ) -> Result<Vmxnet3Device, IxyError> {
+        assert!(
+            num_rx_queues <= MAX_QUEUES,
+            "cannot configure {} rx queues: limit is {}",
+            num_rx_queues,
+            MAX_QUEUES
+        );
+        assert!(
+            num_tx_queues <= MAX_QUEUES,
+            "cannot configure {} tx queues: limit is {}",
+            num_tx_queues,
+            MAX_QUEUES
+        );
+
+        check_num_queues(num_rx_queues, num_tx_queues)?;
+
+        let vfio = Path::new(&format!("/sys/bus/pci/devices/{}/iommu_group", pci_addr)).exists();
+
+        // fail early instead of with an opaque errno while allocating dma memory, the shared
+        // memory takes another huge page
+        check_privileges(
+            pci_addr,
+            vfio,
+            dma_size(num_rx_queues, num_tx_queues) + HUGE_PAGE_SIZE,
+        )?;
+
+        let numa_node = pci_numa_node(pci_addr);
+
+        let mut device_fd: RawFd = -1;
+        let mut allocator = allocator;
+        let mut vfio_container = None;
+        let (addr, len, vd) = if vfio {
+            let container = match container {
+                Some(container) => {
+                    if allocator.is_none() {
+                        allocator = Some(Arc::clone(&container) as Arc<dyn DmaAllocator>);
+                    }
+                    container
+                }
+                None => VfioContainer::shared()?,
+            };
+
+            device_fd = container.add_device(pci_addr)?;
+            vfio_container = Some(container);
+            let (addr, len) = vfio_map_region(device_fd, VFIO_PCI_BAR0_REGION_INDEX)?;
+            (addr, len, MappedBar::map_vfio(device_fd, 1)?)
+        } else {
+            let (addr, len) = pci_map_resource(pci_addr)?;
+            (addr, len, MappedBar::map(pci_addr, 1)?)
+        };
+
+        let shared = allocate_dma(allocator.as_ref(), numa_node, SHARED_SIZE)?;
+
+        let mut dev = Vmxnet3Device {
+            pci_addr: pci_addr.to_string(),
+            addr,
+            len,
+            vd,
+            num_rx_queues,
+            num_tx_queues,
+            rx_queues: Vec::with_capacity(num_rx_queues as usize),
+            tx_queues: Vec::with_capacity(num_tx_queues as usize),
+            shared,
+            vfio,
+            container: vfio_container,
+            device_fd,
+            numa_node,
+            allocator,
+            poll_strategy: PollStrategy::BusySpin,
+            counters: Cell::new(Vmxnet3Counters::default()),
+            removal: RemovalLatch::new(VMXNET3_REG_VRRS),
+        };
+
+        dev.reset_and_init()?;
+
+        Ok(dev)
+    }
+
+    /// Resets the device, agrees on revision 1 of the interface, sets up the queues and
+    /// activates the device.
+    fn reset_and_init(&mut self) -> Result<(), IxyError> {
+        info!("resetting device {}", self.pci_addr);
+
+        self.command(VMXNET3_CMD_RESET_DEV);
+
+        if self.is_removed() {
+            return Err(IxyError::DeviceRemoved(self.pci_addr.clone()));
+        }
+
+        // the revision registers report the supported revisions, writing one selects it
+        if self.get_reg32(VMXNET3_REG_VRRS) & VMXNET3_REV1 == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} does not support vmxnet3 revision 1",
+                self.pci_addr
+            )));
+        }
+        self.set_reg32(VMXNET3_REG_VRRS, VMXNET3_REV1);
+
+        if self.get_reg32(VMXNET3_REG_UVRS) & VMXNET3_UPT_VERSION1 == 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} does not support UPT version 1",
+                self.pci_addr
+            )));
+        }
+        self.set_reg32(VMXNET3_REG_UVRS, VMXNET3_UPT_VERSION1);
+
+        info!("initializing device {}", self.pci_addr);
+
+        let mac = self.get_mac_addr();
+        info!(
+            "mac address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+
+        // the device must not raise interrupts, all queues are polled
+        self.set_pt_reg32(VMXNET3_REG_IMR, VMXNET3_IMR_MASKED);
+
+        for queue_id in 0..self.num_rx_queues {
+            self.init_rx_queue(queue_id)?;
+        }
+
+        for queue_id in 0..self.num_tx_queues {
+            self.init_tx_queue(queue_id)?;
+        }
+
+        self.start()?;
+
+        // counters of the device start at zero after the reset
+        self.counters.set(Vmxnet3Counters::default());
+
+        // wait some time for the link to come up
+        self.wait_for_link();
+
+        Ok(())
+    }
+
+    /// Allocates the rings and mempool of rx queue `queue_id` and attaches buffers to its
+    /// descriptors.
+    fn init_rx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing rx queue {}", queue_id);
+
+        let dma: Dma<u8> = allocate_dma(self.allocator.as_ref(), self.numa_node, rx_ring_size())?;
+
+        debug!("rx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("rx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        let mempool =
+            allocate_mempool(self.allocator.as_ref(), self.numa_node, PKT_BUF_ENTRY_SIZE)?;
+
+        let mut queue = Vmxnet3RxQueue::new(dma, mempool);
+        queue.fill()?;
+
+        self.rx_queues.push(queue);
+
+        Ok(())
+    }
+
+    /// Allocates the rings of tx queue `queue_id`.
+    fn init_tx_queue(&mut self, queue_id: u16) -> Result<(), IxyError> {
+        debug!("initializing tx queue {}", queue_id);
+
+        let dma: Dma<u8> = allocate_dma(self.allocator.as_ref(), self.numa_node, tx_ring_size())?;
+
+        debug!("tx ring {} phys addr: {:#x}", queue_id, dma.phys);
+        debug!("tx ring {} virt addr: {:p}", queue_id, dma.virt);
+
+        self.tx_queues.push(Vmxnet3TxQueue::new(dma));
+
+        Ok(())
+    }
+
+    /// Stops all queues of the device, packets that have not been sent yet stay in the rings.
+    fn quiesce(&self) {
+        debug!("quiescing device {}", self.pci_addr);
+
+        self.command(VMXNET3_CMD_QUIESCE_DEV);
+    }
+
+    /// Rewinds all rings, writes the configuration to the shared memory and activates the
+    /// device, all queues start out at the beginning of their rings.
+    fn start(&mut self) -> Result<(), IxyError> {
+        for queue in self.rx_queues.iter_mut() {
+            if !queue.bufs_in_use.is_empty() {
+                queue.rewind();
+            }
+        }
+
+        for queue in self.tx_queues.iter_mut() {
+            queue.reset();
+        }
+
+        self.write_shared();
+
+        self.set_reg32(
+            VMXNET3_REG_DSAL,
+            (self.shared.phys as u64 & 0xffff_ffff) as u32,
+        );
+        self.set_reg32(VMXNET3_REG_DSAH, (self.shared.phys as u64 >> 32) as u32);
+
+        debug!("activating device {}", self.pci_addr);
+
+        let result = self.command(VMXNET3_CMD_ACTIVATE_DEV);
+        if result != 0 {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "device {} refused activation with {:#x}",
+                self.pci_addr, result
+            )));
+        }
+
+        // rx queues start out full, the second rings stay empty
+        for queue_id in 0..self.num_rx_queues {
+            let prod = self.rx_queues[queue_id as usize].rx_fill;
+            self.set_rx_prod(queue_id, prod);
+            self.set_pt_reg32(
+                VMXNET3_REG_RXPROD2 + VMXNET3_REG_ALIGN * u32::from(queue_id),
+                0,
+            );
+        }
+
+        // enable promisc mode by default to make testing easier
+        self.command(VMXNET3_CMD_UPDATE_RX_MODE);
+
+        Ok(())
+    }
+
+    /// Writes the configuration of the driver and all queues to the shared memory, the device
+    /// reads it on activation.
+    fn write_shared(&mut self) {
+        let num_queues = usize::from(self.num_tx_queues + self.num_rx_queues);
+
+        // frames have to fit into a single buffer of every rx queue
+        let mtu = self
+            .rx_queues
+            .iter()
+            .map(|queue| queue.buf_len().saturating_sub(FRAME_OVERHEAD))
+            .min()
+            .unwrap_or(DEFAULT_MTU)
+            .clamp(MIN_MTU, MAX_MTU);
+
+        unsafe {
+            memset(self.shared.virt, SHARED_SIZE, 0);
+
+            let shared = self.shared.virt as *mut DriverShared;
+
+            (*shared).magic = VMXNET3_REV1_MAGIC.to_le();
+            (*shared).driver_version = VMXNET3_DRIVER_VERSION.to_le();
+            (*shared).gos = (VMXNET3_GOS_BITS_64 | VMXNET3_GOS_TYPE_LINUX).to_le();
+            (*shared).vmxnet3_rev_spt = VMXNET3_REV1.to_le();
+            (*shared).upt_ver_spt = VMXNET3_UPT_VERSION1.to_le();
+            (*shared).queue_desc_pa = ((self.shared.phys + QUEUE_DESC_OFFSET) as u64).to_le();
+            (*shared).queue_desc_len = ((num_queues * QUEUE_DESC_SIZE) as u32).to_le();
+            (*shared).mtu = (mtu as u32).to_le();
+            (*shared).max_num_rx_sg = 1u16.to_le();
+            (*shared).num_tx_queues = self.num_tx_queues as u8;
+            (*shared).num_rx_queues = self.num_rx_queues as u8;
+
+            (*shared).auto_mask = 1;
+            (*shared).num_intrs = 1;
+            (*shared).event_intr_idx = 0;
+            (*shared).mod_levels[0] = VMXNET3_IMM_AUTO;
+            (*shared).intr_ctrl = VMXNET3_IC_DISABLE_ALL.to_le();
+
+            (*shared).rx_mode = (VMXNET3_RXM_UCAST
+                | VMXNET3_RXM_BCAST
+                | VMXNET3_RXM_ALL_MULTI
+                | VMXNET3_RXM_PROMISC)
+                .to_le();
+
+            for (i, queue) in self.tx_queues.iter().enumerate() {
+                let desc = self.tx_queue_desc(i as u16);
+                let base = queue.ring.phys;
+
+                (*desc).tx_ring_base_pa = (base as u64).to_le();
+                (*desc).comp_ring_base_pa = ((base + tx_comp_ring_offset()) as u64).to_le();
+                (*desc).data_ring_base_pa = ((base + tx_data_ring_offset()) as u64).to_le();
+                (*desc).tx_ring_size = (queue.num_descriptors as u32).to_le();
+                (*desc).comp_ring_size = (queue.num_descriptors as u32).to_le();
+                (*desc).data_ring_size = (queue.num_descriptors as u32).to_le();
+            }
+
+            for (i, queue) in self.rx_queues.iter().enumerate() {
+                let desc = self.rx_queue_desc(i as u16);
+                let base = queue.ring.phys;
+
+                (*desc).rx_ring_base_pa = [
+                    (base as u64).to_le(),
+                    ((base + rx_ring2_offset()) as u64).to_le(),
+                ];
+                (*desc).comp_ring_base_pa = ((base + rx_comp_ring_offset()) as u64).to_le();
+                (*desc).rx_ring_size = [
+                    (queue.num_descriptors as u32).to_le(),
+                    (RX_RING2_SIZE as u32).to_le(),
+                ];
+                (*desc).comp_ring_size = (rx_comp_ring_entries() as u32).to_le();
+            }
+        }
+    }
+
+    /// Returns the descriptor of tx queue `queue_id` in the shared memory.
+    fn tx_queue_desc(&self, queue_id: u16) -> *mut TxQueueDesc {
+        (self.shared.virt as usize + QUEUE_DESC_OFFSET + usize::from(queue_id) * QUEUE_DESC_SIZE)
+            as *mut TxQueueDesc
+    }
+
+    /// Returns the descriptor of rx queue `queue_id` in the shared memory, the rx queues follow
+    /// the tx queues.
+    fn rx_queue_desc(&self, queue_id: u16) -> *mut RxQueueDesc {
+        let index = usize::from(self.num_tx_queues + queue_id);
+
+        (self.shared.virt as usize + QUEUE_DESC_OFFSET + index * QUEUE_DESC_SIZE)
+            as *mut RxQueueDesc
+    }
+
+    /// Moves the producer index of the first ring of rx queue `queue_id` to `prod`.
+    fn set_rx_prod(&self, queue_id: u16, prod: usize) {
+        self.set_pt_reg32(
+            VMXNET3_REG_RXPROD + VMXNET3_REG_ALIGN * u32::from(queue_id),
+            prod as u32,
+        );
+    }
+
+    /// Waits for the link to come up.
+    fn wait_for_link(&self) {
+        info!("waiting for link");
+        let time = Instant::now();
+        let mut speed = self.get_link_speed();
+        while speed == 0 && time.elapsed().as_secs() < 10 {
+            thread::sleep(Duration::from_millis(100));
+            speed = self.get_link_speed();
+        }
+        info!("link speed is {} Mbit/s", self.get_link_speed());
+    }
+
+    /// Returns the current values of the statistic counters summed over all queues.
+    fn read_counters(&self) -> Vmxnet3Counters {
+        let mut counters = Vmxnet3Counters::default();
+
+        // the device copies its counters to the queue descriptors on request
+        self.command(VMXNET3_CMD_GET_STATS);
+
+        for i in 0..self.num_rx_queues {
+            let stats = unsafe { ptr::read_volatile(&(*self.rx_queue_desc(i)).stats) };
+
+            counters.rx_pkts += u64::from_le(stats.ucast_pkts)
+                + u64::from_le(stats.mcast_pkts)
+                + u64::from_le(stats.bcast_pkts);
+            counters.rx_bytes += u64::from_le(stats.ucast_bytes)
+                + u64::from_le(stats.mcast_bytes)
+                + u64::from_le(stats.bcast_bytes);
+            counters.rx_multicast += u64::from_le(stats.mcast_pkts);
+            counters.rx_broadcast += u64::from_le(stats.bcast_pkts);
+            counters.rx_no_buffer += u64::from_le(stats.out_of_buf_pkts);
+        }
+
+        for i in 0..self.num_tx_queues {
+            let stats = unsafe { ptr::read_volatile(&(*self.tx_queue_desc(i)).stats) };
+
+            counters.tx_pkts += u64::from_le(stats.ucast_pkts)
+                + u64::from_le(stats.mcast_pkts)
+                + u64::from_le(stats.bcast_pkts);
+            counters.tx_bytes += u64::from_le(stats.ucast_bytes)
+                + u64::from_le(stats.mcast_bytes)
+                + u64::from_le(stats.bcast_bytes);
+            counters.tx_multicast += u64::from_le(stats.mcast_pkts);
+            counters.tx_broadcast += u64::from_le(stats.bcast_pkts);
+        }
+
+        counters
+    }
+
+    /// Returns an error if rx queue `queue_id` is not configured.
+    fn check_rx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_rx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "rx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if tx queue `queue_id` is not configured.
+    fn check_tx_queue(&self, queue_id: u32) -> Result<(), IxyError> {
+        if queue_id >= u32::from(self.num_tx_queues) {
+            return Err(IxyError::InvalidConfiguration(format!(
+                "tx queue {} is not configured",
+                queue_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Executes the command `cmd` and returns its result.
+    fn command(&self, cmd: u32) -> u32 {
+        self.set_reg32(VMXNET3_REG_CMD, cmd);
+        self.get_reg32(VMXNET3_REG_CMD)
+    }
+
+    /// Returns the register `reg` of the virtual device in BAR1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reg` does not belong to the mapped memory of BAR1.
+    fn get_reg32(&self, reg: u32) -> u32 {
+        self.removal
+            .read(&self.pci_addr, reg, |reg| self.vd.read_32(reg as usize))
+    }
+
+    /// Sets the register `reg` of the virtual device in BAR1 to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reg` does not belong to the mapped memory of BAR1.
+    fn set_reg32(&self, reg: u32, value: u32) {
+        if self.removal.is_removed() {
+            return;
+        }
+
+        self.vd.write_32(reg as usize, value);
+    }
+
+    /// Sets the doorbell at `self.addr` + `reg` in BAR0 to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self.addr` + `reg` does not belong to the mapped memory of the pci device.
+    fn set_pt_reg32(&self, reg: u32, value: u32) {
+        assert!(reg as usize <= self.len - 4, "memory access out of bounds");
+
+        if self.removal.is_removed() {
+            return;
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                (self.addr as usize + reg as usize) as *mut u32,
+                value.to_le(),
+            );
+        }
+    }
+}
+
+/// Returns an error if the vmxnet3 doesn't have the given number of queues.
+fn check_num_queues(num_rx_queues: u16, num_tx_queues: u16) -> Result<(), IxyError> {
+    if num_rx_queues > MAX_VMXNET3_RX_QUEUES || num_tx_queues > MAX_VMXNET3_TX_QUEUES {
+        return Err(IxyError::InvalidConfiguration(format!(
+            "cannot configure {} rx and {} tx queues: {} supports up to {} rx and {} tx queues",
+            num_rx_queues, num_tx_queues, DRIVER_NAME, MAX_VMXNET3_RX_QUEUES, MAX_VMXNET3_TX_QUEUES
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the offset of the completion ring in the dma memory of a tx queue.
+fn tx_comp_ring_offset() -> usize {
+    (NUM_TX_QUEUE_ENTRIES * mem::size_of::<TxDesc>()).next_multiple_of(RING_BASE_ALIGN)
+}
+
+/// Returns the offset of the data ring in the dma memory of a tx queue.
+fn tx_data_ring_offset() -> usize {
+    tx_comp_ring_offset()
+        + (NUM_TX_QUEUE_ENTRIES * mem::size_of::<TxCompDesc>()).next_multiple_of(RING_BASE_ALIGN)
+}
+
+/// Returns the size of the dma memory of a tx queue, i.e. of its descriptor, completion and data
+/// rings.
+fn tx_ring_size() -> usize {
+    tx_data_ring_offset() + NUM_TX_QUEUE_ENTRIES * TX_DATA_DESC_SIZE
+}
+
+/// Returns the offset of the second ring in the dma memory of an rx queue.
+fn rx_ring2_offset() -> usize {
+    (NUM_RX_QUEUE_ENTRIES * mem::size_of::<RxDesc>()).next_multiple_of(RING_BASE_ALIGN)
+}
+
+/// Returns the offset of the completion ring in the dma memory of an rx queue.
+fn rx_comp_ring_offset() -> usize {
+    rx_ring2_offset() + (RX_RING2_SIZE * mem::size_of::<RxDesc>()).next_multiple_of(RING_BASE_ALIGN)
+}
+
+/// Returns the number of entries of the completion ring of an rx queue, it serves both rings.
+fn rx_comp_ring_entries() -> usize {
+    NUM_RX_QUEUE_ENTRIES + RX_RING2_SIZE
+}
+
+/// Returns the size of the dma memory of an rx queue, i.e. of both rings and the completion
+/// ring.
+fn rx_ring_size() -> usize {
+    rx_comp_ring_offset() + rx_comp_ring_entries() * mem::size_of::<RxCompDesc>()
+}
+
+/// Writes a descriptor for the `len` bytes at `phys_addr` with generation bit `gen` to `desc`.
+unsafe fn write_tx_desc(desc: *mut TxDesc, phys_addr: usize, len: usize, gen: u32, val2: u32) {
+    // a length of 0 stands for 16 KiB
+    let val1 = (len as u32 & VMXNET3_TXD_LEN_MASK) | (gen << VMXNET3_TXD_GEN_SHIFT);
+
+    ptr::write_volatile(&mut (*desc).addr, (phys_addr as u64).to_le());
+    ptr::write_volatile(&mut (*desc).val2, val2.to_le());
+    ptr::write_volatile(&mut (*desc).val1, val1.to_le());
+}